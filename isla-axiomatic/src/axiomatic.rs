@@ -389,6 +389,18 @@ pub mod relations {
         !is_translate(ev) && !is_ifetch(ev) && ev.base().filter(|b| b.is_memory_read()).is_some()
     }
 
+    pub fn is_address_announce<B: BV>(ev: &AxEvent<B>) -> bool {
+        ev.base().filter(|b| b.is_address_announce()).is_some()
+    }
+
+    pub fn is_sleep_request<B: BV>(ev: &AxEvent<B>) -> bool {
+        ev.base().filter(|b| b.is_sleep_request()).is_some()
+    }
+
+    pub fn is_wakeup_request<B: BV>(ev: &AxEvent<B>) -> bool {
+        ev.base().filter(|b| b.is_wakeup_request()).is_some()
+    }
+
     /// \[M\] aka R|W
     pub fn is_memory<B: BV>(ev: &AxEvent<B>) -> bool {
         is_read(ev) || is_write(ev)
@@ -401,6 +413,18 @@ pub mod relations {
         }
     }
 
+    /// True if the events are the read and write halves of an
+    /// atomic read-modify-write linked by a shared id in the trace
+    /// (see [isla_lib::config::MemoryKinds::annotate]). Returns
+    /// `None` if neither event has an id, in which case the
+    /// footprint analysis is used instead.
+    fn rmw_pair<B: BV>(ev1: &AxEvent<B>, ev2: &AxEvent<B>) -> Option<bool> {
+        match (ev1.base().and_then(|b| b.rmw_id()), ev2.base().and_then(|b| b.rmw_id())) {
+            (None, None) => None,
+            (id1, id2) => Some(id1 == id2 && intra_instruction_ordered(ev1, ev2)),
+        }
+    }
+
     pub fn amo<B: BV>(
         ev1: &AxEvent<B>,
         ev2: &AxEvent<B>,
@@ -410,7 +434,9 @@ pub mod relations {
         is_read(ev1)
             && is_write(ev2)
             && intra_instruction_ordered(ev1, ev2)
-            && rmw_dep(ev1.instruction_index, ev2.instruction_index, &thread_opcodes[ev1.thread_id], footprints)
+            && rmw_pair(ev1, ev2).unwrap_or_else(|| {
+                rmw_dep(ev1.instruction_index, ev2.instruction_index, &thread_opcodes[ev1.thread_id], footprints)
+            })
     }
 
     pub fn univ<B: BV>(_: &AxEvent<B>, _: &AxEvent<B>) -> bool {
@@ -494,7 +520,9 @@ pub mod relations {
         (po(ev1, ev2) || intra_instruction_ordered(ev1, ev2))
             && is_read(ev1)
             && is_write(ev2)
-            && rmw_dep(ev1.instruction_index, ev2.instruction_index, &thread_opcodes[ev1.thread_id], footprints)
+            && rmw_pair(ev1, ev2).unwrap_or_else(|| {
+                rmw_dep(ev1.instruction_index, ev2.instruction_index, &thread_opcodes[ev1.thread_id], footprints)
+            })
     }
 
     pub fn translation_walk_order<B: BV>(ev1: &AxEvent<B>, ev2: &AxEvent<B>) -> bool {
@@ -545,6 +573,9 @@ pub struct ExecutionInfo<'ev, B> {
     /// A mapping from SMT symbols to their types
     pub types: HashMap<Sym, Ty>,
     pub function_types: HashMap<Sym, (Vec<Ty>, Ty)>,
+    /// The thread, instruction cycle, and symbolic result of each
+    /// check a thread made to see if it was sleeping
+    pub sleeps: Vec<(ThreadId, usize, Sym)>,
 }
 
 /// An iterator over the base events in a candidate execution
@@ -891,6 +922,7 @@ impl<'ev, B: BV> ExecutionInfo<'ev, B> {
             final_writes: HashMap::new(),
             types: HashMap::new(),
             function_types: HashMap::new(),
+            sleeps: Vec::new(),
         };
 
         let read_event_registers = isa_config.read_event_registers();
@@ -968,6 +1000,16 @@ impl<'ev, B: BV> ExecutionInfo<'ev, B> {
 
                         Event::WriteMem { .. } => cycle_events.push(CycleEvent::new("W", po, eid, tid, event, None)),
 
+                        Event::AddressAnnounce { .. } => {
+                            cycle_events.push(CycleEvent::new("AA", po, eid, tid, event, translate))
+                        }
+
+                        Event::SleepRequest => cycle_events.push(CycleEvent::new("SLEEP", po, eid, tid, event, None)),
+
+                        Event::WakeupRequest => cycle_events.push(CycleEvent::new("WAKE", po, eid, tid, event, None)),
+
+                        Event::Sleeping(v) => exec.sleeps.push((tid, po, *v)),
+
                         Event::Function { name, call } => {
                             if *call {
                                 call_stack.push(*name);
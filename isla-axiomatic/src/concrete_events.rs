@@ -0,0 +1,869 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module decides candidate executions without invoking the SMT
+//! solver when every memory event has a concrete address. In that
+//! case the only freedom left to the solver is the choice of rf and
+//! co, so we enumerate them, evaluate the values flowing through the
+//! thread traces for each choice, and check the memory model with
+//! [isla_mml::eval]. The sets and relations mirror those generated
+//! by [crate::smt_events::smt_of_candidate]. Whenever something
+//! cannot be decided concretely the caller should fall back to SMT.
+
+use std::collections::{HashMap, HashSet};
+
+use isla_lib::bitvector::b64::B64;
+use isla_lib::bitvector::BV;
+use isla_lib::config::ISAConfig;
+use isla_lib::ir::{Name, Val};
+use isla_lib::memory::Memory;
+use isla_lib::smt::smtlib::{Def, Exp};
+use isla_lib::smt::{EnumMember, Event, Sym};
+
+use isla_mml::eval::{evaluate_memory_model, ConcreteExecution};
+use isla_mml::memory_model::{ExpArena, MemoryModel, Symtab};
+
+use crate::axiomatic::relations::*;
+use crate::axiomatic::{AxEvent, ExecutionInfo, ThreadId};
+use crate::footprint_analysis::Footprint;
+use crate::litmus::exp::{Exp as LitmusExp, Loc};
+
+/// The maximum number of rf and co combinations we will enumerate
+/// for a single candidate before leaving it to the solver
+const MAX_EXECUTIONS: usize = 4096;
+
+/// The maximum nesting of expressions and definitions we will follow
+/// when evaluating the thread traces
+const MAX_DEPTH: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Bool(bool),
+    Bits(B64),
+    Enum(EnumMember),
+}
+
+/// Where a read event reads from
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    Initial,
+    Write(usize),
+}
+
+fn to_b64<B: BV>(bv: B) -> Option<B64> {
+    if bv.len() <= 64 {
+        Some(B64::new(bv.lower_u64(), bv.len()))
+    } else {
+        None
+    }
+}
+
+fn mask(bits: u64, len: u32) -> B64 {
+    B64::new(bits & B64::ones(len).lower_u64(), len)
+}
+
+/// Compare two values, returning `None` if they have different
+/// sorts, in which case the SMT would be ill-typed.
+fn same(x: Value, y: Value) -> Option<bool> {
+    match (x, y) {
+        (Value::Bool(b1), Value::Bool(b2)) => Some(b1 == b2),
+        (Value::Bits(bv1), Value::Bits(bv2)) if bv1.len() == bv2.len() => Some(bv1 == bv2),
+        (Value::Enum(m1), Value::Enum(m2)) if m1.enum_id == m2.enum_id => Some(m1 == m2),
+        (_, _) => None,
+    }
+}
+
+/// Adapt the data written by a write of `wbytes` to a read of
+/// `rbytes`, as in `read_write_pair` in [crate::smt_events]
+fn resize(bv: B64, rbytes: u32, wbytes: u32) -> Option<B64> {
+    if rbytes == wbytes {
+        Some(bv)
+    } else if rbytes > wbytes {
+        if rbytes * 8 <= 64 {
+            Some(bv.zero_extend(rbytes * 8))
+        } else {
+            None
+        }
+    } else if rbytes > 0 {
+        bv.extract(rbytes * 8 - 1, 0)
+    } else {
+        None
+    }
+}
+
+fn litmus_value(exp: &LitmusExp<u64>) -> Option<B64> {
+    use LitmusExp::*;
+    match exp {
+        Loc(n) | Nat(n) => Some(B64::from_u64(*n)),
+        Bits64(bits, len) if *len <= 64 && mask(*bits, *len).lower_u64() == *bits => Some(B64::new(*bits, *len)),
+        Bin(bits) => B64::from_str(&format!("#b{}", bits)),
+        Hex(bits) => B64::from_str(&format!("#x{}", bits)),
+        _ => None,
+    }
+}
+
+/// The parts of a candidate needed to compute the values read and
+/// written by each event
+struct Trace<'a, B> {
+    events: &'a [AxEvent<'a, B>],
+    defs: HashMap<Sym, &'a Exp<Sym>>,
+    asserts: Vec<&'a Exp<Sym>>,
+    /// Maps the symbolic value returned by each read to that read
+    read_syms: HashMap<Sym, usize>,
+    write_widths: HashSet<u32>,
+    final_writes: &'a HashMap<(Name, ThreadId), &'a Val<B>>,
+    memory: &'a Memory<B>,
+    initial_addrs: &'a HashMap<u64, u64>,
+}
+
+impl<'a, B: BV> Trace<'a, B> {
+    /// The value read from the initial state, see `read_initial_symbolic`
+    fn initial_value(&self, address: &Val<B>, bytes: u32) -> Option<B64> {
+        let address = match address {
+            Val::Bits(bv) => bv.lower_u64(),
+            _ => return None,
+        };
+        if bytes > 8 {
+            return None;
+        }
+        if let Some(value) = self.initial_addrs.get(&address) {
+            return Some(mask(*value, bytes * 8));
+        }
+        match self.memory.in_custom_region(address).and_then(|region| region.initial_value(address, bytes)) {
+            Some(bv) => to_b64(bv),
+            None => Some(B64::zeros(bytes * 8)),
+        }
+    }
+
+    /// Whether a concrete value could be read from the initial state, see `read_initial_concrete`
+    fn concrete_initial(&self, bv: B, address: &Val<B>) -> Option<bool> {
+        let address = match address {
+            Val::Bits(bv) => bv.lower_u64(),
+            _ => return None,
+        };
+        if let Some(value) = self.initial_addrs.get(&address) {
+            return Some(bv.lower_u64() == *value);
+        }
+        match self.memory.in_custom_region(address) {
+            Some(region) => Some(Some(bv) == region.initial_value(address, bv.len() / 8)),
+            None => Some(bv.is_zero()),
+        }
+    }
+}
+
+/// Evaluates the thread traces for a specific choice of rf. Any
+/// function returning `None` could not decide the result.
+struct Evaluator<'t, 'a, B> {
+    trace: &'t Trace<'a, B>,
+    rf: &'t [Option<Source>],
+    values: HashMap<Sym, Option<Value>>,
+    lets: Vec<(Sym, Value)>,
+    depth: usize,
+}
+
+impl<'t, 'a, B: BV> Evaluator<'t, 'a, B> {
+    fn new(trace: &'t Trace<'a, B>, rf: &'t [Option<Source>]) -> Self {
+        Evaluator { trace, rf, values: HashMap::new(), lets: Vec::new(), depth: 0 }
+    }
+
+    fn var(&mut self, v: Sym) -> Option<Value> {
+        if let Some((_, value)) = self.lets.iter().rev().find(|(w, _)| *w == v) {
+            return Some(*value);
+        }
+        if let Some(value) = self.values.get(&v) {
+            return *value;
+        }
+
+        let trace = self.trace;
+        // Any cyclic definition will see this and be unknown
+        self.values.insert(v, None);
+        let value = if let Some(read) = trace.read_syms.get(&v) {
+            self.read_value(*read)
+        } else if let Some(exp) = trace.defs.get(&v) {
+            self.exp(exp)
+        } else {
+            None
+        };
+        self.values.insert(v, value);
+        value
+    }
+
+    fn val(&mut self, val: &Val<B>) -> Option<Value> {
+        match val {
+            Val::Symbolic(v) => self.var(*v),
+            Val::Bits(bv) => to_b64(*bv).map(Value::Bits),
+            Val::Bool(b) => Some(Value::Bool(*b)),
+            _ => None,
+        }
+    }
+
+    fn bits(&mut self, val: &Val<B>) -> Option<B64> {
+        match self.val(val)? {
+            Value::Bits(bv) => Some(bv),
+            _ => None,
+        }
+    }
+
+    fn read_value(&mut self, read: usize) -> Option<Value> {
+        let trace = self.trace;
+        let ev = &trace.events[read];
+        let (_, rbytes) = ev.read_value()?;
+        match self.rf[read]? {
+            Source::Initial => trace.initial_value(ev.address()?, rbytes).map(Value::Bits),
+            Source::Write(write) => {
+                let (data, wbytes) = trace.events[write].write_data()?;
+                if matches!(data, Val::Symbolic(_)) && rbytes != wbytes {
+                    return None;
+                }
+                resize(self.bits(data)?, rbytes, wbytes).map(Value::Bits)
+            }
+        }
+    }
+
+    fn read_initial(&mut self, read: usize) -> Option<bool> {
+        let trace = self.trace;
+        let ev = &trace.events[read];
+        match ev.read_value()? {
+            (Val::Bits(bv), _) => trace.concrete_initial(*bv, ev.address()?),
+            (value, bytes) => Some(self.bits(value)? == trace.initial_value(ev.address()?, bytes)?),
+        }
+    }
+
+    fn rw_pair(&mut self, write: usize, read: usize) -> Option<bool> {
+        let trace = self.trace;
+        let (rvalue, rbytes) = match trace.events[read].read_value() {
+            Some(read) => read,
+            None => return Some(false),
+        };
+        let (wdata, wbytes) = match trace.events[write].write_data() {
+            Some(write) => write,
+            None => return Some(false),
+        };
+        match (rvalue, wdata) {
+            (Val::Symbolic(sym1), Val::Symbolic(sym2)) if sym1 == sym2 => Some(true),
+            (Val::Symbolic(_), Val::Symbolic(_)) => {
+                same(Value::Bits(self.bits(rvalue)?), Value::Bits(self.bits(wdata)?))
+            }
+            (Val::Bits(bv1), Val::Bits(bv2)) => Some(bv1 == bv2),
+            (Val::Bits(_), Val::Symbolic(_)) | (Val::Symbolic(_), Val::Bits(_)) => {
+                let data = resize(self.bits(wdata)?, rbytes, wbytes)?;
+                Some(self.bits(rvalue)? == data)
+            }
+            (_, _) => Some(false),
+        }
+    }
+
+    /// Check that each read can read from its source under rf, and
+    /// that every assertion in the thread traces holds
+    fn consistent(&mut self, reads: &[usize]) -> Option<bool> {
+        let trace = self.trace;
+        let mut unknown = false;
+        for read in reads {
+            let check = match self.rf[*read]? {
+                Source::Initial => self.read_initial(*read),
+                Source::Write(write) => self.rw_pair(write, *read),
+            };
+            match check {
+                Some(false) => return Some(false),
+                Some(true) => (),
+                None => unknown = true,
+            }
+        }
+        for exp in &trace.asserts {
+            match self.boolean(exp) {
+                Some(false) => return Some(false),
+                Some(true) => (),
+                None => unknown = true,
+            }
+        }
+        if unknown {
+            None
+        } else {
+            Some(true)
+        }
+    }
+
+    fn boolean(&mut self, exp: &Exp<Sym>) -> Option<bool> {
+        match self.exp(exp)? {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn bv(&mut self, exp: &Exp<Sym>) -> Option<B64> {
+        match self.exp(exp)? {
+            Value::Bits(bv) => Some(bv),
+            _ => None,
+        }
+    }
+
+    fn bv2(&mut self, lhs: &Exp<Sym>, rhs: &Exp<Sym>) -> Option<(B64, B64)> {
+        let x = self.bv(lhs)?;
+        let y = self.bv(rhs)?;
+        if x.len() == y.len() {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    fn binary<F>(&mut self, lhs: &Exp<Sym>, rhs: &Exp<Sym>, op: F) -> Option<Value>
+    where
+        F: FnOnce(B64, B64) -> B64,
+    {
+        self.bv2(lhs, rhs).map(|(x, y)| Value::Bits(op(x, y)))
+    }
+
+    fn compare<F>(&mut self, lhs: &Exp<Sym>, rhs: &Exp<Sym>, op: F) -> Option<Value>
+    where
+        F: FnOnce(B64, B64) -> bool,
+    {
+        self.bv2(lhs, rhs).map(|(x, y)| Value::Bool(op(x, y)))
+    }
+
+    fn exp(&mut self, exp: &Exp<Sym>) -> Option<Value> {
+        if self.depth >= MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = self.exp_rec(exp);
+        self.depth -= 1;
+        value
+    }
+
+    fn exp_rec(&mut self, exp: &Exp<Sym>) -> Option<Value> {
+        use Exp::*;
+        match exp {
+            Var(v) => self.var(*v),
+            Bits(bits) if bits.len() <= 64 => {
+                let value = bits.iter().enumerate().fold(0, |value, (n, bit)| value | (u64::from(*bit) << n));
+                Some(Value::Bits(B64::new(value, bits.len() as u32)))
+            }
+            Bits64(bv) => Some(Value::Bits(*bv)),
+            Enum(member) => Some(Value::Enum(*member)),
+            Bool(b) => Some(Value::Bool(*b)),
+            Eq(lhs, rhs) => same(self.exp(lhs)?, self.exp(rhs)?).map(Value::Bool),
+            Neq(lhs, rhs) => same(self.exp(lhs)?, self.exp(rhs)?).map(|b| Value::Bool(!b)),
+            And(lhs, rhs) => match (self.boolean(lhs), self.boolean(rhs)) {
+                (Some(false), _) | (_, Some(false)) => Some(Value::Bool(false)),
+                (Some(true), Some(true)) => Some(Value::Bool(true)),
+                (_, _) => None,
+            },
+            Or(lhs, rhs) => match (self.boolean(lhs), self.boolean(rhs)) {
+                (Some(true), _) | (_, Some(true)) => Some(Value::Bool(true)),
+                (Some(false), Some(false)) => Some(Value::Bool(false)),
+                (_, _) => None,
+            },
+            Not(exp) => self.boolean(exp).map(|b| Value::Bool(!b)),
+            Bvnot(exp) => self.bv(exp).map(|bv| Value::Bits(!bv)),
+            Bvand(lhs, rhs) => self.binary(lhs, rhs, |x, y| x & y),
+            Bvor(lhs, rhs) => self.binary(lhs, rhs, |x, y| x | y),
+            Bvxor(lhs, rhs) => self.binary(lhs, rhs, |x, y| x ^ y),
+            Bvnand(lhs, rhs) => self.binary(lhs, rhs, |x, y| !(x & y)),
+            Bvnor(lhs, rhs) => self.binary(lhs, rhs, |x, y| !(x | y)),
+            Bvxnor(lhs, rhs) => self.binary(lhs, rhs, |x, y| !(x ^ y)),
+            Bvneg(exp) => self.bv(exp).map(|bv| Value::Bits(-bv)),
+            Bvadd(lhs, rhs) => self.binary(lhs, rhs, |x, y| x + y),
+            Bvsub(lhs, rhs) => self.binary(lhs, rhs, |x, y| x - y),
+            Bvmul(lhs, rhs) => self.binary(lhs, rhs, |x, y| mask(x.lower_u64().wrapping_mul(y.lower_u64()), x.len())),
+            Bvudiv(lhs, rhs) => self.binary(lhs, rhs, |x, y| {
+                if y.is_zero() {
+                    B64::ones(x.len())
+                } else {
+                    B64::new(x.lower_u64() / y.lower_u64(), x.len())
+                }
+            }),
+            Bvurem(lhs, rhs) => {
+                self.binary(
+                    lhs,
+                    rhs,
+                    |x, y| {
+                        if y.is_zero() {
+                            x
+                        } else {
+                            B64::new(x.lower_u64() % y.lower_u64(), x.len())
+                        }
+                    },
+                )
+            }
+            Bvult(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.lower_u64() < y.lower_u64()),
+            Bvule(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.lower_u64() <= y.lower_u64()),
+            Bvugt(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.lower_u64() > y.lower_u64()),
+            Bvuge(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.lower_u64() >= y.lower_u64()),
+            Bvslt(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.signed() < y.signed()),
+            Bvsle(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.signed() <= y.signed()),
+            Bvsgt(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.signed() > y.signed()),
+            Bvsge(lhs, rhs) => self.compare(lhs, rhs, |x, y| x.signed() >= y.signed()),
+            Extract(hi, lo, exp) if lo <= hi => self.bv(exp)?.extract(*hi, *lo).map(Value::Bits),
+            ZeroExtend(n, exp) => {
+                let bv = self.bv(exp)?;
+                if bv.len() + n <= 64 {
+                    Some(Value::Bits(bv.zero_extend(bv.len() + n)))
+                } else {
+                    None
+                }
+            }
+            SignExtend(n, exp) => {
+                let bv = self.bv(exp)?;
+                if bv.len() + n <= 64 {
+                    Some(Value::Bits(bv.sign_extend(bv.len() + n)))
+                } else {
+                    None
+                }
+            }
+            Bvshl(lhs, rhs) => self.binary(lhs, rhs, |x, y| x.shiftl(y.lower_u64() as i128)),
+            Bvlshr(lhs, rhs) => self.binary(lhs, rhs, |x, y| x.shiftr(y.lower_u64() as i128)),
+            Bvashr(lhs, rhs) => self.binary(lhs, rhs, |x, y| x.arith_shiftr(y.lower_u64() as i128)),
+            Concat(lhs, rhs) => {
+                let x = self.bv(lhs)?;
+                let y = self.bv(rhs)?;
+                x.append(y).map(Value::Bits)
+            }
+            Ite(cond, then_exp, else_exp) => {
+                if self.boolean(cond)? {
+                    self.exp(then_exp)
+                } else {
+                    self.exp(else_exp)
+                }
+            }
+            Distinct(exps) => {
+                let mut values = Vec::new();
+                for exp in exps {
+                    values.push(self.exp(exp)?)
+                }
+                let mut distinct = true;
+                for (i, x) in values.iter().enumerate() {
+                    for y in &values[i + 1..] {
+                        distinct &= !same(*x, *y)?
+                    }
+                }
+                Some(Value::Bool(distinct))
+            }
+            Let(bindings, body) => {
+                let mut values = Vec::new();
+                for (v, exp) in bindings {
+                    values.push((*v, self.exp(exp)?))
+                }
+                let scope = self.lets.len();
+                self.lets.extend(values);
+                let value = self.exp(body);
+                self.lets.truncate(scope);
+                value
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluate the final assertion of the litmus test, as
+    /// `exp_to_smt` in [crate::smt_events]. An event has a co
+    /// successor if `has_successor` is true at its index.
+    fn final_assertion(&mut self, exp: &LitmusExp<u64>, has_successor: &[bool]) -> Option<bool> {
+        use LitmusExp::*;
+        match exp {
+            EqLoc(loc, exp) => self.eq_loc(loc, exp, has_successor),
+            And(exps) => {
+                let mut result = true;
+                for exp in exps {
+                    result &= self.final_assertion(exp, has_successor)?
+                }
+                Some(result)
+            }
+            Or(exps) => {
+                let mut result = false;
+                for exp in exps {
+                    result |= self.final_assertion(exp, has_successor)?
+                }
+                Some(result)
+            }
+            Implies(exp1, exp2) => {
+                Some(!self.final_assertion(exp1, has_successor)? || self.final_assertion(exp2, has_successor)?)
+            }
+            Not(exp) => self.final_assertion(exp, has_successor).map(|b| !b),
+            True => Some(true),
+            False => Some(false),
+            _ => None,
+        }
+    }
+
+    fn eq_loc(&mut self, loc: &Loc<u64>, exp: &LitmusExp<u64>, has_successor: &[bool]) -> Option<bool> {
+        let trace = self.trace;
+        let value = litmus_value(exp)?;
+        match loc {
+            Loc::Register { reg, thread_id } => match trace.final_writes.get(&(*reg, *thread_id)) {
+                Some(val) => same(Value::Bits(self.bits(val)?), Value::Bits(value)),
+                None => Some(false),
+            },
+            Loc::LastWriteTo { address, bytes } => self.last_write_to(*address, *bytes, value, has_successor),
+        }
+    }
+
+    /// See `last_write_to.smt2`. Note that any event without a co
+    /// successor is considered, so reads and the initial write count
+    /// as writing zero.
+    fn last_write_to(&mut self, address: u64, bytes: u32, value: B64, has_successor: &[bool]) -> Option<bool> {
+        let trace = self.trace;
+        if !trace.write_widths.contains(&bytes) || bytes > 8 || value.len() != 64 {
+            return None;
+        }
+        let target = value.extract(bytes * 8 - 1, 0)?;
+
+        // The initial write is the last event in the execution
+        for (i, has_successor) in has_successor.iter().enumerate() {
+            if *has_successor {
+                continue;
+            }
+            let (ev_address, ev_value) = match trace.events.get(i).and_then(|ev| ev.base()) {
+                Some(Event::WriteMem { address: ev_address, data, bytes: wbytes, .. }) if *wbytes == bytes => {
+                    (self.bits(ev_address)?.lower_u64(), self.bits(data)?)
+                }
+                Some(Event::WriteMem { address: ev_address, .. })
+                | Some(Event::ReadMem { address: ev_address, .. }) => {
+                    (self.bits(ev_address)?.lower_u64(), B64::zeros(bytes * 8))
+                }
+                _ => (0, B64::zeros(bytes * 8)),
+            };
+            if ev_address == address && ev_value == target {
+                return Some(true);
+            }
+        }
+
+        let initial = trace.initial_addrs.get(&address).copied().unwrap_or(0);
+        let written = trace
+            .events
+            .iter()
+            .any(|ev| is_write(ev) && matches!(ev.address(), Some(Val::Bits(bv)) if bv.lower_u64() == address));
+        Some(value.lower_u64() == initial && !written)
+    }
+}
+
+fn same_address<B: BV>(ev1: &AxEvent<B>, ev2: &AxEvent<B>) -> bool {
+    matches!((ev1.address(), ev2.address()), (Some(Val::Bits(bv1)), Some(Val::Bits(bv2))) if bv1 == bv2)
+}
+
+fn overlap_address<B: BV>(ev1: &AxEvent<B>, ev2: &AxEvent<B>) -> bool {
+    ev1.addresses().any(|addr1| ev2.addresses().any(|addr2| addr1 == addr2))
+}
+
+fn add_set<B: BV, F>(exec: &mut ConcreteExecution, symtab: &mut Symtab, name: &str, events: &[AxEvent<B>], set: F)
+where
+    F: Fn(&AxEvent<B>) -> bool,
+{
+    exec.add_set(symtab.intern(name), (0..events.len()).filter(|i| set(&events[*i])))
+}
+
+fn add_rel<B: BV, F>(exec: &mut ConcreteExecution, symtab: &mut Symtab, name: &str, events: &[AxEvent<B>], rel: F)
+where
+    F: Fn(&AxEvent<B>, &AxEvent<B>) -> bool,
+{
+    let n = events.len();
+    exec.add_relation(
+        symtab.intern(name),
+        (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).filter(|(i, j)| rel(&events[*i], &events[*j])),
+    )
+}
+
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, *item);
+            result.push(permutation)
+        }
+    }
+    result
+}
+
+/// Step to the next combination of choices, returning false once
+/// every combination has been visited
+fn next_index(index: &mut [usize], sizes: &[usize]) -> bool {
+    for (i, size) in index.iter_mut().zip(sizes) {
+        *i += 1;
+        if *i < *size {
+            return true;
+        }
+        *i = 0
+    }
+    false
+}
+
+/// Decide whether a candidate execution satisfies both the memory
+/// model and the final assertion by enumerating rf and co. Returns
+/// `None` if the candidate is not concrete enough, in which case it
+/// should be checked with SMT instead.
+#[allow(clippy::too_many_arguments)]
+pub fn check_concrete_candidate<B: BV>(
+    candidate: &[&[Event<B>]],
+    exec: &ExecutionInfo<B>,
+    ignore_ifetch: bool,
+    footprints: &HashMap<B, Footprint>,
+    memory: &Memory<B>,
+    initial_physical_addrs: &HashMap<u64, u64>,
+    final_assertion: &LitmusExp<u64>,
+    isa_config: &ISAConfig<B>,
+    mm: &MemoryModel,
+    mm_arena: &ExpArena,
+    mm_symtab: &mut Symtab,
+) -> Option<bool> {
+    let events = &exec.smt_events;
+    let iw = events.len();
+
+    if !exec.sleeps.is_empty() || events.iter().any(|ev| is_translate(ev) || is_ifetch(ev)) {
+        return None;
+    }
+    if !events.iter().flat_map(|ev| ev.addresses()).all(|addr| matches!(addr, Val::Bits(bv) if bv.len() == 64)) {
+        return None;
+    }
+
+    let mut trace = Trace {
+        events,
+        defs: HashMap::new(),
+        asserts: Vec::new(),
+        read_syms: HashMap::new(),
+        write_widths: HashSet::new(),
+        final_writes: &exec.final_writes,
+        memory,
+        initial_addrs: initial_physical_addrs,
+    };
+
+    for thread in candidate {
+        for event in *thread {
+            match event {
+                Event::Smt(Def::DefineConst(v, exp), _, _) => {
+                    trace.defs.insert(*v, exp);
+                }
+                Event::Smt(def, _, _) => trace.asserts.extend(def.assertion()),
+                _ => (),
+            }
+        }
+    }
+
+    // As in smt_of_candidate, there is always an 8 byte width
+    trace.write_widths.insert(8);
+    for (_, _, ev) in exec.base_events() {
+        if let Event::WriteMem { bytes, .. } = ev {
+            trace.write_widths.insert(*bytes);
+        }
+    }
+
+    let reads: Vec<usize> = (0..iw).filter(|r| is_read(&events[*r])).collect();
+    for r in &reads {
+        if let Some((Val::Symbolic(v), _)) = events[*r].read_value() {
+            if trace.read_syms.insert(*v, *r).is_some() {
+                return None;
+            }
+        }
+    }
+
+    let sources: Vec<Vec<Source>> = reads
+        .iter()
+        .map(|r| {
+            let mut from = vec![Source::Initial];
+            for (w, ev) in events.iter().enumerate() {
+                if is_write(ev) && disjoint(ev, &events[*r]) && same_address(ev, &events[*r]) {
+                    from.push(Source::Write(w))
+                }
+            }
+            from
+        })
+        .collect();
+
+    // Writes grouped by address. Each group is totally ordered by co.
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for w in (0..iw).filter(|w| is_write(&events[*w])) {
+        match groups.iter_mut().find(|group| same_address(&events[group[0]], &events[w])) {
+            Some(group) => {
+                // Such writes are not related by loc, so co is not total over the group
+                if group.iter().any(|w2| !disjoint(&events[*w2], &events[w])) {
+                    return None;
+                }
+                group.push(w)
+            }
+            None => groups.push(vec![w]),
+        }
+    }
+
+    let mut total: usize = 1;
+    for sources in &sources {
+        total = total.saturating_mul(sources.len())
+    }
+    for group in &groups {
+        for k in 1..=group.len() {
+            total = total.saturating_mul(k)
+        }
+    }
+    if total > MAX_EXECUTIONS {
+        return None;
+    }
+
+    let mut concrete = ConcreteExecution::new(iw + 1);
+    let translations = exec.translations();
+    let thread_opcodes = &exec.thread_opcodes;
+
+    add_set(&mut concrete, mm_symtab, "R", events, is_read);
+    add_set(&mut concrete, mm_symtab, "W", events, is_write);
+    add_set(&mut concrete, mm_symtab, "AT", events, is_translate);
+    add_set(&mut concrete, mm_symtab, "T", events, |ev| is_translate(ev) && ev.base.iter().any(|b| b.is_memory_read()));
+    add_set(&mut concrete, mm_symtab, "Stage1", events, |ev| is_translate(ev) && is_in_s1_table(ev));
+    add_set(&mut concrete, mm_symtab, "Stage2", events, |ev| is_translate(ev) && is_in_s2_table(ev));
+    add_set(&mut concrete, mm_symtab, "M", events, is_memory);
+    add_set(&mut concrete, mm_symtab, "IF", events, is_ifetch);
+    add_set(&mut concrete, mm_symtab, "AA", events, is_address_announce);
+    add_set(&mut concrete, mm_symtab, "SLEEP", events, is_sleep_request);
+    add_set(&mut concrete, mm_symtab, "WAKE", events, is_wakeup_request);
+    concrete.add_set(mm_symtab.intern("IW"), [iw]);
+
+    for (set, kinds) in isa_config.register_event_sets.iter() {
+        add_set(&mut concrete, mm_symtab, &format!("read_{}", set), events, |ev| {
+            kinds.iter().any(|k| k.is_read() && ev.has_read_reg_of(k.name()))
+        });
+        // Matches the write_ sets generated by smt_of_candidate
+        add_set(&mut concrete, mm_symtab, &format!("write_{}", set), events, |ev| {
+            kinds.iter().any(|k| k.is_write() && ev.has_read_reg_of(k.name()))
+        });
+    }
+
+    add_rel(&mut concrete, mm_symtab, "amo", events, |ev1, ev2| amo(ev1, ev2, thread_opcodes, footprints));
+    add_rel(&mut concrete, mm_symtab, "instruction-order", events, instruction_order);
+    add_rel(&mut concrete, mm_symtab, "po", events, po);
+    if !ignore_ifetch {
+        add_rel(&mut concrete, mm_symtab, "fpo", events, |ev1, ev2| {
+            instruction_order(ev1, ev2) && is_ifetch(ev1) && is_ifetch(ev2)
+        });
+        add_rel(&mut concrete, mm_symtab, "fe", events, ifetch_to_execute);
+    }
+    add_rel(&mut concrete, mm_symtab, "iio", events, intra_instruction_ordered);
+    add_rel(&mut concrete, mm_symtab, "int", events, internal);
+    add_rel(&mut concrete, mm_symtab, "ext", events, external);
+    add_rel(&mut concrete, mm_symtab, "same-translation", events, same_translation);
+    add_rel(&mut concrete, mm_symtab, "loc", events, |ev1, ev2| disjoint(ev1, ev2) && same_address(ev1, ev2));
+    add_rel(&mut concrete, mm_symtab, "overlap-loc", events, |ev1, ev2| {
+        disjoint(ev1, ev2) && overlap_address(ev1, ev2)
+    });
+    add_rel(&mut concrete, mm_symtab, "po-loc", events, |ev1, ev2| po(ev1, ev2) && same_address(ev1, ev2));
+    add_rel(&mut concrete, mm_symtab, "addr", events, |ev1, ev2| {
+        addr(ev1, ev2, thread_opcodes, footprints) && is_memory(ev2)
+    });
+    add_rel(&mut concrete, mm_symtab, "data", events, |ev1, ev2| {
+        data(ev1, ev2, thread_opcodes, footprints) && is_memory(ev2)
+    });
+    add_rel(&mut concrete, mm_symtab, "ctrl", events, |ev1, ev2| ctrl(ev1, ev2, thread_opcodes, footprints));
+    add_rel(&mut concrete, mm_symtab, "rmw", events, |ev1, ev2| rmw(ev1, ev2, thread_opcodes, footprints));
+    add_rel(&mut concrete, mm_symtab, "translate-same-va-page", events, |ev1, ev2| {
+        same_va_page(ev1, ev2, &translations)
+    });
+    add_rel(&mut concrete, mm_symtab, "translate-same-ipa-page", events, |ev1, ev2| {
+        same_ipa_page(ev1, ev2, &translations)
+    });
+    concrete.add_relation(mm_symtab.intern("id"), (0..=iw).map(|i| (i, i)));
+
+    let rf_name = mm_symtab.intern("rf");
+    let co_name = mm_symtab.intern("co");
+    let r_initial_name = mm_symtab.intern("r-initial");
+    let rw_pair_name = mm_symtab.intern("rw-pair");
+
+    let rf_sizes: Vec<usize> = sources.iter().map(Vec::len).collect();
+    let co_orders: Vec<Vec<Vec<usize>>> = groups.iter().map(|group| permutations(group)).collect();
+    let co_sizes: Vec<usize> = co_orders.iter().map(Vec::len).collect();
+
+    let mut rf_index = vec![0; reads.len()];
+    loop {
+        let mut rf = vec![None; iw];
+        for (k, r) in reads.iter().enumerate() {
+            rf[*r] = Some(sources[k][rf_index[k]])
+        }
+
+        let mut evaluator = Evaluator::new(&trace, &rf);
+        if evaluator.consistent(&reads)? {
+            let mut r_initial = Vec::new();
+            for r in &reads {
+                if evaluator.read_initial(*r)? {
+                    r_initial.push(*r)
+                }
+            }
+            let mut rw_pairs = Vec::new();
+            for w in (0..iw).filter(|w| is_write(&events[*w])) {
+                for r in &reads {
+                    if evaluator.rw_pair(w, *r)? {
+                        rw_pairs.push((w, *r))
+                    }
+                }
+            }
+            concrete.add_set(r_initial_name, r_initial);
+            concrete.add_relation(rw_pair_name, rw_pairs);
+            concrete.add_relation(
+                rf_name,
+                reads.iter().map(|r| match rf[*r] {
+                    Some(Source::Write(w)) => (w, *r),
+                    _ => (iw, *r),
+                }),
+            );
+
+            let mut co_index = vec![0; groups.len()];
+            loop {
+                let mut co = Vec::new();
+                let mut has_successor = vec![false; iw + 1];
+                for (orders, i) in co_orders.iter().zip(&co_index) {
+                    let order = &orders[*i];
+                    for (j, w1) in order.iter().enumerate() {
+                        co.push((iw, *w1));
+                        has_successor[iw] = true;
+                        for w2 in &order[j + 1..] {
+                            co.push((*w1, *w2));
+                            has_successor[*w1] = true
+                        }
+                    }
+                }
+
+                if evaluator.final_assertion(final_assertion, &has_successor)? {
+                    concrete.add_relation(co_name, co);
+                    match evaluate_memory_model(mm, mm_arena, mm_symtab, &concrete) {
+                        Ok(result) if result.is_allowed() => return Some(true),
+                        Ok(_) => (),
+                        Err(_) => return None,
+                    }
+                }
+
+                if !next_index(&mut co_index, &co_sizes) {
+                    break;
+                }
+            }
+        }
+
+        if !next_index(&mut rf_index, &rf_sizes) {
+            break;
+        }
+    }
+
+    Some(false)
+}
@@ -39,7 +39,7 @@ use crossbeam::queue::SegQueue;
 use isla_lib::init::InitArchWithConfig;
 use serde::{Deserialize, Serialize};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::io::Write;
@@ -49,6 +49,7 @@ use std::time::Instant;
 
 use isla_lib::bitvector::BV;
 use isla_lib::cache::{Cacheable, Cachekey};
+use isla_lib::config::RegisterCoalescing;
 use isla_lib::executor;
 use isla_lib::executor::{LocalFrame, TaskState, TraceError};
 use isla_lib::ir::*;
@@ -89,13 +90,21 @@ pub struct Footprint {
     is_exclusive: bool,
 }
 
+/// Footprints are cached by opcode, and by a hash of the
+/// architecture and its configuration when one is available, so a
+/// cache directory can be shared between architectures without
+/// returning stale footprints.
 pub struct Footprintkey {
+    arch_hash: Option<String>,
     opcode: String,
 }
 
 impl Cachekey for Footprintkey {
     fn key(&self) -> String {
-        format!("opcode_{}", self.opcode)
+        match &self.arch_hash {
+            Some(arch_hash) => format!("footprint_{}_{}", arch_hash, self.opcode),
+            None => format!("opcode_{}", self.opcode),
+        }
     }
 }
 
@@ -120,6 +129,31 @@ impl Footprint {
         }
     }
 
+    /// Apply register coalescing rules from the architecture
+    /// configuration to a footprint for reporting. The dependency
+    /// analysis must always use the uncoalesced footprint, as
+    /// coalescing can introduce dependencies between accesses to
+    /// distinct registers or fields.
+    pub fn coalesced(&self, coalescing: &RegisterCoalescing) -> Self {
+        let coalesce = |taints: &Taints| -> Taints {
+            taints.iter().map(|(reg, accessor)| coalescing.coalesce(*reg, accessor)).collect()
+        };
+
+        Footprint {
+            write_data_taints: (coalesce(&self.write_data_taints.0), self.write_data_taints.1),
+            mem_addr_taints: (coalesce(&self.mem_addr_taints.0), self.mem_addr_taints.1),
+            branch_addr_taints: (coalesce(&self.branch_addr_taints.0), self.branch_addr_taints.1),
+            register_reads: coalesce(&self.register_reads),
+            register_writes: coalesce(&self.register_writes),
+            register_writes_tainted: coalesce(&self.register_writes_tainted),
+            register_writes_ignored: self.register_writes_ignored.clone(),
+            is_store: self.is_store,
+            is_load: self.is_load,
+            is_branch: self.is_branch,
+            is_exclusive: self.is_exclusive,
+        }
+    }
+
     /// This just prints the footprint information in a human-readable
     /// form for debugging.
     pub fn pretty(&self, buf: &mut dyn Write, symtab: &Symtab) -> Result<(), Box<dyn Error>> {
@@ -185,6 +219,104 @@ impl Footprint {
         writeln!(buf)?;
         Ok(())
     }
+
+    /// Render the footprint with registers named by their source
+    /// names, so it can be compared against a footprint computed
+    /// under a different architecture (and hence symbol table).
+    pub fn summary(&self, symtab: &Symtab) -> FootprintSummary {
+        let render = |taints: &Taints| -> BTreeSet<String> {
+            taints
+                .iter()
+                .map(|(reg, accessor)| {
+                    let mut s = zencode::decode(symtab.to_str(*reg));
+                    for component in accessor {
+                        match component {
+                            Accessor::Field(name) => {
+                                s.push('.');
+                                s.push_str(&zencode::decode(symtab.to_str(*name)))
+                            }
+                        }
+                    }
+                    s
+                })
+                .collect()
+        };
+
+        let mut kind = BTreeSet::new();
+        for (flag, name) in [
+            (self.is_store, "store"),
+            (self.is_load, "load"),
+            (self.is_exclusive, "exclusive"),
+            (self.is_branch, "branch"),
+        ] {
+            if flag {
+                kind.insert(name.to_string());
+            }
+        }
+
+        let mut sets = BTreeMap::new();
+        sets.insert("Kind", kind);
+        sets.insert("Memory write", render(&self.write_data_taints.0));
+        sets.insert("Memory read", render(&self.register_writes_tainted));
+        sets.insert("Memory address", render(&self.mem_addr_taints.0));
+        sets.insert("Branch address", render(&self.branch_addr_taints.0));
+        sets.insert("Register reads", render(&self.register_reads));
+        sets.insert("Register writes", render(&self.register_writes));
+        FootprintSummary { sets }
+    }
+}
+
+/// A symbol table independent form of a [Footprint], see
+/// [Footprint::summary]. Each component of the footprint is a sorted
+/// set of register names (or instruction kinds), keyed by a
+/// human-readable description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FootprintSummary {
+    pub sets: BTreeMap<&'static str, BTreeSet<String>>,
+}
+
+/// The difference in one component of two footprint summaries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FootprintChange {
+    pub component: &'static str,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl FootprintSummary {
+    /// Compute the components that changed going from `self` to
+    /// `other`. An empty result means both footprints agree.
+    pub fn diff(&self, other: &FootprintSummary) -> Vec<FootprintChange> {
+        let empty = BTreeSet::new();
+        let components: BTreeSet<&'static str> = self.sets.keys().chain(other.sets.keys()).copied().collect();
+        components
+            .into_iter()
+            .filter_map(|component| {
+                let before = self.sets.get(component).unwrap_or(&empty);
+                let after = other.sets.get(component).unwrap_or(&empty);
+                let removed: Vec<String> = before.difference(after).cloned().collect();
+                let added: Vec<String> = after.difference(before).cloned().collect();
+                if removed.is_empty() && added.is_empty() {
+                    None
+                } else {
+                    Some(FootprintChange { component, removed, added })
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for FootprintChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.component)?;
+        for r in &self.removed {
+            write!(f, " -{}", r)?
+        }
+        for a in &self.added {
+            write!(f, " +{}", a)?
+        }
+        Ok(())
+    }
 }
 
 // There is an rmw dependency from `from` to `to` if `from` is a
@@ -380,17 +512,21 @@ impl Error for FootprintError {
 /// * `thread_buckets` - A vector of paths (event vectors) for each thread in the litmus test
 /// * `arch` - The initial state and configuration of the architecture
 /// * `cache_dir` - A directory to cache footprint results
+/// * `arch_hash` - A hash of the architecture and configuration, used
+///   to key the cache along with each opcode
 pub fn footprint_analysis<'ir, B>(
     num_threads: usize,
     thread_buckets: &[Vec<EvPath<B>>],
     arch: &InitArchWithConfig<'ir, B>,
     cache: Option<&Path>,
+    arch_hash: Option<&str>,
 ) -> Result<HashMap<B, Footprint>, FootprintError>
 where
     B: BV,
 {
     let mut concrete_opcodes: HashSet<B> = HashSet::new();
     let mut footprints = HashMap::new();
+    let key = |opcode: &B| Footprintkey { arch_hash: arch_hash.map(String::from), opcode: opcode.to_string() };
 
     for thread in thread_buckets {
         for path in thread {
@@ -398,9 +534,7 @@ where
                 match event {
                     Event::Instr(Val::Bits(bv)) => {
                         if let Some(cache_dir) = &cache {
-                            if let Some(footprint) =
-                                Footprint::from_cache(Footprintkey { opcode: bv.to_string() }, cache_dir)
-                            {
+                            if let Some(footprint) = Footprint::from_cache(key(bv), cache_dir) {
                                 footprints.insert(*bv, footprint);
                             } else {
                                 concrete_opcodes.insert(*bv);
@@ -416,7 +550,14 @@ where
         }
     }
 
-    log!(log::VERBOSE, &format!("Got {} uncached concrete opcodes for footprint analysis", concrete_opcodes.len()));
+    log!(
+        log::VERBOSE,
+        &format!(
+            "Got {} cached and {} uncached concrete opcodes for footprint analysis",
+            footprints.len(),
+            concrete_opcodes.len()
+        )
+    );
 
     let function_id = match arch.shared_state.symtab.get("zisla_footprint") {
         Some(id) => id,
@@ -557,10 +698,109 @@ where
         }
 
         if let Some(cache_dir) = &cache {
-            footprint.cache(Footprintkey { opcode: opcode.to_string() }, cache_dir);
+            footprint.cache(key(&opcode), cache_dir);
         }
         footprints.insert(opcode, footprint);
     }
 
     Ok(footprints)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use isla_lib::bitvector::b64::B64;
+
+    #[test]
+    fn coalescing_does_not_create_dependencies() {
+        let mut symtab = Symtab::new();
+        let pstate = symtab.intern("zPSTATE");
+        let n = symtab.intern("zN");
+        let z = symtab.intern("zZ");
+
+        let mut coalescing = RegisterCoalescing::default();
+        coalescing.coalesce_fields(pstate, vec![n, z]);
+
+        // The first instruction writes a value read from memory to
+        // PSTATE.N, and the second stores PSTATE.Z
+        let mut load = Footprint::new();
+        load.is_load = true;
+        load.register_writes.insert((pstate, vec![Accessor::Field(n)]));
+        load.register_writes_tainted.insert((pstate, vec![Accessor::Field(n)]));
+
+        let mut store = Footprint::new();
+        store.is_store = true;
+        store.register_reads.insert((pstate, vec![Accessor::Field(z)]));
+        store.write_data_taints.0.insert((pstate, vec![Accessor::Field(z)]));
+
+        let instrs = [B64::from_u32(0), B64::from_u32(1)];
+        let mut footprints = HashMap::new();
+        footprints.insert(instrs[0], load);
+        footprints.insert(instrs[1], store);
+
+        assert!(!data_dep(0, 1, &instrs, &footprints));
+
+        let load = footprints[&instrs[0]].coalesced(&coalescing);
+        let store = footprints[&instrs[1]].coalesced(&coalescing);
+        assert!(load.register_writes_tainted.contains(&(pstate, vec![])));
+        assert!(store.write_data_taints.0.contains(&(pstate, vec![])));
+        assert_eq!(load.summary(&symtab).sets["Register writes"], vec!["PSTATE".to_string()].into_iter().collect());
+        assert_eq!(store.summary(&symtab).sets["Register reads"], vec!["PSTATE".to_string()].into_iter().collect());
+
+        // The footprints used for the dependency analysis are unchanged
+        assert!(footprints[&instrs[0]].register_writes.contains(&(pstate, vec![Accessor::Field(n)])));
+        assert!(!data_dep(0, 1, &instrs, &footprints));
+    }
+
+    #[test]
+    fn summary_diff_across_symbol_tables() {
+        // The two architectures intern the same registers in a
+        // different order, so their names differ
+        let mut symtab = Symtab::new();
+        let x0 = symtab.intern("zX0");
+        let x1 = symtab.intern("zX1");
+        let mut other_symtab = Symtab::new();
+        let other_x2 = other_symtab.intern("zX2");
+        let other_x1 = other_symtab.intern("zX1");
+        let other_x0 = other_symtab.intern("zX0");
+
+        let mut footprint = Footprint::new();
+        footprint.is_load = true;
+        footprint.register_reads.insert((x0, vec![]));
+        footprint.register_writes.insert((x1, vec![]));
+
+        let mut other_footprint = Footprint::new();
+        other_footprint.is_load = true;
+        other_footprint.register_reads.insert((other_x0, vec![]));
+        other_footprint.register_writes.insert((other_x1, vec![]));
+
+        let summary = footprint.summary(&symtab);
+        assert!(summary.diff(&other_footprint.summary(&other_symtab)).is_empty());
+
+        other_footprint.is_load = false;
+        other_footprint.is_store = true;
+        other_footprint.register_reads.insert((other_x2, vec![]));
+        let changes = summary.diff(&other_footprint.summary(&other_symtab));
+        assert_eq!(
+            changes,
+            vec![
+                FootprintChange {
+                    component: "Kind",
+                    removed: vec!["load".to_string()],
+                    added: vec!["store".to_string()]
+                },
+                FootprintChange { component: "Register reads", removed: vec![], added: vec!["X2".to_string()] },
+            ]
+        );
+        assert_eq!(changes[0].to_string(), "Kind: -load +store");
+
+        // Components missing from one summary are treated as empty
+        let mut partial = summary.clone();
+        partial.sets.remove("Register writes");
+        assert_eq!(
+            partial.diff(&summary),
+            vec![FootprintChange { component: "Register writes", removed: vec![], added: vec!["X1".to_string()] }]
+        )
+    }
+}
@@ -0,0 +1,487 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a diy-style generator for litmus tests. A
+//! test is described by a cycle of relations, such as `PodWW Rfe
+//! PodRR Fre`, and we synthesize the threads, addresses and final
+//! condition that would witness that cycle. The output is a litmus
+//! test in Isla's TOML format, so it can be passed directly to the
+//! litmus runner.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use isla_lib::ir::Symtab;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dir {
+    R,
+    W,
+}
+
+impl Dir {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'R' => Some(Dir::R),
+            'W' => Some(Dir::W),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dir::R => write!(f, "R"),
+            Dir::W => write!(f, "W"),
+        }
+    }
+}
+
+/// A single edge in a cycle. Program-order edges may optionally be
+/// separated by a full fence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// Program order, to the same (`Pos`) or a different (`Pod`) location
+    Po { same_loc: bool, fenced: bool, src: Dir, dst: Dir },
+    /// Reads-from, internal or external
+    Rf { external: bool },
+    /// From-reads, internal or external
+    Fr { external: bool },
+    /// Coherence order, internal or external
+    Co { external: bool },
+}
+
+impl Edge {
+    pub fn src(&self) -> Dir {
+        match self {
+            Edge::Po { src, .. } => *src,
+            Edge::Rf { .. } | Edge::Co { .. } => Dir::W,
+            Edge::Fr { .. } => Dir::R,
+        }
+    }
+
+    pub fn dst(&self) -> Dir {
+        match self {
+            Edge::Po { dst, .. } => *dst,
+            Edge::Rf { .. } => Dir::R,
+            Edge::Fr { .. } | Edge::Co { .. } => Dir::W,
+        }
+    }
+
+    /// External edges cross from one thread to another
+    pub fn is_external(&self) -> bool {
+        match self {
+            Edge::Po { .. } => false,
+            Edge::Rf { external } | Edge::Fr { external } | Edge::Co { external } => *external,
+        }
+    }
+
+    /// Does the edge change which location is being accessed?
+    pub fn changes_location(&self) -> bool {
+        matches!(self, Edge::Po { same_loc: false, .. })
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ie = |external: bool| if external { "e" } else { "i" };
+        match self {
+            Edge::Po { same_loc, fenced, src, dst } => {
+                let fence = if *fenced { "DMB.SY" } else { "Po" };
+                write!(f, "{}{}{}{}", fence, if *same_loc { "s" } else { "d" }, src, dst)
+            }
+            Edge::Rf { external } => write!(f, "Rf{}", ie(*external)),
+            Edge::Fr { external } => write!(f, "Fr{}", ie(*external)),
+            Edge::Co { external } => write!(f, "Co{}", ie(*external)),
+        }
+    }
+}
+
+impl FromStr for Edge {
+    type Err = GenerateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let communication = |external: char| match external {
+            'e' => Some(true),
+            'i' => Some(false),
+            _ => None,
+        };
+
+        let po = |rest: &str, fenced: bool| {
+            let mut chars = rest.chars();
+            let same_loc = match chars.next()? {
+                's' => true,
+                'd' => false,
+                _ => return None,
+            };
+            let src = Dir::from_char(chars.next()?)?;
+            let dst = Dir::from_char(chars.next()?)?;
+            if chars.next().is_some() {
+                return None;
+            }
+            Some(Edge::Po { same_loc, fenced, src, dst })
+        };
+
+        let edge = if let Some(rest) = s.strip_prefix("Po") {
+            po(rest, false)
+        } else if let Some(rest) = s.strip_prefix("DMB.SY") {
+            po(rest, true)
+        } else if s.len() == 3 {
+            let external = communication(s.chars().nth(2).unwrap());
+            match &s[0..2] {
+                "Rf" => external.map(|external| Edge::Rf { external }),
+                "Fr" => external.map(|external| Edge::Fr { external }),
+                "Co" | "Ws" => external.map(|external| Edge::Co { external }),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        edge.ok_or_else(|| GenerateError::UnknownEdge(s.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerateError {
+    UnknownEdge(String),
+    EmptyCycle,
+    NoExternalEdge,
+    DirectionMismatch(Edge, Edge),
+    SingleLocationChange,
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use GenerateError::*;
+        match self {
+            UnknownEdge(edge) => write!(f, "Unknown relation {} in cycle", edge),
+            EmptyCycle => write!(f, "Cycle must contain at least one relation"),
+            NoExternalEdge => write!(f, "Cycle must contain at least one external relation"),
+            DirectionMismatch(e1, e2) => {
+                write!(f, "Relation {} ends with a {} event, but {} starts with a {} event", e1, e1.dst(), e2, e2.src())
+            }
+            SingleLocationChange => {
+                write!(f, "Cycle cannot change location exactly once, as it could not return to the first location")
+            }
+        }
+    }
+}
+
+impl Error for GenerateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// The architecture we generate assembly for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GenerateArch {
+    AArch64,
+    RiscV64,
+}
+
+impl FromStr for GenerateArch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aarch64" | "arm" | "armv8" => Ok(GenerateArch::AArch64),
+            "riscv64" | "riscv" | "rv64" => Ok(GenerateArch::RiscV64),
+            _ => Err(format!("Unknown architecture {} for litmus test generation", s)),
+        }
+    }
+}
+
+impl GenerateArch {
+    /// Determine which architecture a model is for from the general
+    /// purpose registers it declares, if it is one we can generate
+    /// tests for.
+    pub fn from_symtab(symtab: &Symtab) -> Option<Self> {
+        if symtab.get("zR0").is_some() && symtab.get("zR30").is_some() {
+            Some(GenerateArch::AArch64)
+        } else if symtab.get("zx1").is_some() && symtab.get("zx31").is_some() {
+            Some(GenerateArch::RiscV64)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            GenerateArch::AArch64 => "AArch64",
+            GenerateArch::RiscV64 => "RISCV",
+        }
+    }
+
+    /// The name of the n-th general purpose register (in its 64-bit form)
+    fn reg(self, n: usize) -> String {
+        match self {
+            GenerateArch::AArch64 => format!("X{}", n),
+            // Start from x5 to avoid the zero register, ra, sp, gp, and tp
+            GenerateArch::RiscV64 => format!("x{}", n + 5),
+        }
+    }
+
+    fn store(self, code: &mut String, value: u64, data: usize, addr: usize) {
+        match self {
+            GenerateArch::AArch64 => {
+                writeln!(code, "\tmov W{},#{}", data, value).unwrap();
+                writeln!(code, "\tstr W{},[X{}]", data, addr).unwrap()
+            }
+            GenerateArch::RiscV64 => {
+                writeln!(code, "\tli {},{}", self.reg(data), value).unwrap();
+                writeln!(code, "\tsw {},0({})", self.reg(data), self.reg(addr)).unwrap()
+            }
+        }
+    }
+
+    fn load(self, code: &mut String, dest: usize, addr: usize) {
+        match self {
+            GenerateArch::AArch64 => writeln!(code, "\tldr W{},[X{}]", dest, addr).unwrap(),
+            GenerateArch::RiscV64 => writeln!(code, "\tlw {},0({})", self.reg(dest), self.reg(addr)).unwrap(),
+        }
+    }
+
+    fn fence(self, code: &mut String) {
+        match self {
+            GenerateArch::AArch64 => writeln!(code, "\tdmb sy").unwrap(),
+            GenerateArch::RiscV64 => writeln!(code, "\tfence rw,rw").unwrap(),
+        }
+    }
+}
+
+/// Parse a whitespace or `+` separated cycle of relations
+pub fn parse_cycle(cycle: &str) -> Result<Vec<Edge>, GenerateError> {
+    cycle.split(|c: char| c.is_whitespace() || c == '+').filter(|s| !s.is_empty()).map(Edge::from_str).collect()
+}
+
+/// The canonical diy-style name for a cycle, e.g. `PodWW+Rfe+PodRR+Fre`
+pub fn cycle_name(cycle: &[Edge]) -> String {
+    cycle.iter().map(|edge| edge.to_string()).collect::<Vec<_>>().join("+")
+}
+
+struct GenEvent {
+    dir: Dir,
+    thread: usize,
+    loc: usize,
+    // Only meaningful for writes, values are numbered from 1 in
+    // coherence order for each location
+    value: u64,
+}
+
+fn location_name(loc: usize) -> String {
+    const NAMES: [&str; 6] = ["x", "y", "z", "a", "b", "c"];
+    NAMES.get(loc).map(|s| s.to_string()).unwrap_or_else(|| format!("l{}", loc))
+}
+
+/// Generate a litmus test witnessing the given cycle of relations,
+/// returning the test name and its TOML source.
+pub fn generate_litmus(cycle: &[Edge], arch: GenerateArch) -> Result<(String, String), GenerateError> {
+    use GenerateError::*;
+
+    if cycle.is_empty() {
+        return Err(EmptyCycle);
+    }
+
+    let n = cycle.len();
+    for i in 0..n {
+        let (e1, e2) = (&cycle[i], &cycle[(i + 1) % n]);
+        if e1.dst() != e2.src() {
+            return Err(DirectionMismatch(*e1, *e2));
+        }
+    }
+
+    // Rotate the cycle so that the edge leading into the first event
+    // is external, meaning the first event starts a thread. We pick
+    // the last such edge so cycles like `PodWW Rfe PodRR Fre` keep
+    // their written order.
+    let start = cycle.iter().rposition(Edge::is_external).ok_or(NoExternalEdge)?;
+    let edges: Vec<Edge> = cycle[start + 1..].iter().chain(cycle[..=start].iter()).copied().collect();
+
+    let location_changes = edges.iter().filter(|e| e.changes_location()).count();
+    if location_changes == 1 {
+        return Err(SingleLocationChange);
+    }
+
+    // Event i is the source of edges[i], and the target of edges[i - 1]
+    let mut events = Vec::with_capacity(n);
+    let mut thread = 0;
+    let mut changes = 0;
+    for i in 0..n {
+        if i > 0 && edges[i - 1].is_external() {
+            thread += 1
+        }
+        let loc = if location_changes == 0 { 0 } else { changes % location_changes };
+        events.push(GenEvent { dir: edges[i].src(), thread, loc, value: 0 });
+        if edges[i].changes_location() {
+            changes += 1
+        }
+    }
+    let num_threads = thread + 1;
+
+    // Number the writes to each location in coherence order. We walk
+    // the cycle starting from the first event of each location's
+    // segment, so writes are ordered following the cycle.
+    let mut write_counts: BTreeMap<usize, u64> = BTreeMap::new();
+    let segment_start = (0..n).find(|i| *i > 0 && edges[i - 1].changes_location()).unwrap_or(0);
+    for k in 0..n {
+        let i = (segment_start + k) % n;
+        if events[i].dir == Dir::W {
+            let count = write_counts.entry(events[i].loc).or_insert(0);
+            *count += 1;
+            events[i].value = *count
+        }
+    }
+
+    // Allocate registers as diy does, with each access using an even
+    // register for its data, and the following odd register holding
+    // the address the first time a location is accessed by a thread.
+    let mut codes = vec![String::new(); num_threads];
+    let mut inits: Vec<BTreeMap<String, String>> = vec![BTreeMap::new(); num_threads];
+    let mut addr_regs: Vec<HashMap<usize, usize>> = vec![HashMap::new(); num_threads];
+    let mut next_reg = vec![0; num_threads];
+    let mut conditions = Vec::new();
+
+    for (i, ev) in events.iter().enumerate() {
+        let t = ev.thread;
+        let data = next_reg[t];
+        next_reg[t] += 2;
+        let addr = *addr_regs[t].entry(ev.loc).or_insert_with(|| {
+            inits[t].insert(arch.reg(data + 1), location_name(ev.loc));
+            data + 1
+        });
+
+        if i > 0 && matches!(edges[i - 1], Edge::Po { fenced: true, .. }) {
+            arch.fence(&mut codes[t])
+        }
+
+        match ev.dir {
+            Dir::W => arch.store(&mut codes[t], ev.value, data, addr),
+            Dir::R => {
+                arch.load(&mut codes[t], data, addr);
+                let prev = (i + n - 1) % n;
+                let expected = match edges[prev] {
+                    Edge::Rf { .. } => Some(events[prev].value),
+                    _ => match edges[i] {
+                        // We read the write that is coherence-before the fr target
+                        Edge::Fr { .. } => Some(events[(i + 1) % n].value - 1),
+                        _ => None,
+                    },
+                };
+                if let Some(value) = expected {
+                    conditions.push(format!("{}:{} = {}", t, arch.reg(data), value))
+                }
+            }
+        }
+    }
+
+    // With more than one write to a location, check the final value
+    // to observe the coherence order.
+    for (loc, count) in &write_counts {
+        if *count > 1 {
+            conditions.push(format!("*{} = {}", location_name(*loc), count))
+        }
+    }
+
+    let name = cycle_name(cycle);
+    let num_locs = usize::max(location_changes, 1);
+
+    let mut toml = String::new();
+    writeln!(toml, "arch = \"{}\"", arch.name()).unwrap();
+    writeln!(toml, "name = \"{}\"", name).unwrap();
+    writeln!(toml, "cycle = \"{}\"", cycle.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" ")).unwrap();
+    writeln!(
+        toml,
+        "symbolic = [{}]",
+        (0..num_locs).map(|loc| format!("\"{}\"", location_name(loc))).collect::<Vec<_>>().join(", ")
+    )
+    .unwrap();
+
+    for t in 0..num_threads {
+        writeln!(toml, "\n[thread.{}]", t).unwrap();
+        writeln!(
+            toml,
+            "init = {{ {} }}",
+            inits[t].iter().map(|(reg, loc)| format!("{} = \"{}\"", reg, loc)).collect::<Vec<_>>().join(", ")
+        )
+        .unwrap();
+        writeln!(toml, "code = \"\"\"\n{}\"\"\"", codes[t]).unwrap();
+    }
+
+    writeln!(toml, "\n[final]").unwrap();
+    if conditions.is_empty() {
+        writeln!(toml, "assertion = \"true\"").unwrap();
+    } else {
+        writeln!(toml, "assertion = \"{}\"", conditions.join(" and ")).unwrap();
+    }
+
+    Ok((name, toml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cycle() {
+        let cycle = parse_cycle("PodWW Rfe PodRR Fre").unwrap();
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle_name(&cycle), "PodWW+Rfe+PodRR+Fre");
+        assert_eq!(
+            parse_cycle("DMB.SYdWW+Coe").unwrap()[0],
+            Edge::Po { same_loc: false, fenced: true, src: Dir::W, dst: Dir::W }
+        );
+        assert!(parse_cycle("PodXW").is_err());
+    }
+
+    #[test]
+    fn test_generate_mp() {
+        let cycle = parse_cycle("PodWW Rfe PodRR Fre").unwrap();
+        let (name, toml) = generate_litmus(&cycle, GenerateArch::AArch64).unwrap();
+        assert_eq!(name, "PodWW+Rfe+PodRR+Fre");
+        assert!(toml.contains("symbolic = [\"x\", \"y\"]"));
+        assert!(toml.contains("[thread.0]"));
+        assert!(toml.contains("[thread.1]"));
+        assert!(!toml.contains("[thread.2]"));
+        assert!(toml.contains("assertion = \"1:X0 = 1 and 1:X2 = 0\""));
+    }
+
+    #[test]
+    fn test_generate_errors() {
+        assert!(generate_litmus(&parse_cycle("PodWR PodRW").unwrap(), GenerateArch::AArch64).is_err());
+        assert!(generate_litmus(&parse_cycle("PodWW Rfe").unwrap(), GenerateArch::AArch64).is_err());
+        assert!(generate_litmus(&parse_cycle("Rfe PodRR").unwrap(), GenerateArch::AArch64).is_err());
+        assert!(generate_litmus(&parse_cycle("Rfe PodRW Coe").unwrap(), GenerateArch::AArch64).is_err());
+    }
+}
@@ -2164,6 +2164,11 @@ fn concrete_graph_from_candidate<'ir, B: BV>(
                     );
                 };
             }
+            Some(Event::AddressAnnounce { .. }) => {
+                if opts.debug {
+                    events.insert(event.name.clone(), GraphEvent::from_axiomatic(event, &litmus.objdump, None));
+                }
+            }
             _ => {
                 if opts.debug {
                     events.insert(event.name.clone(), GraphEvent::from_axiomatic(event, &litmus.objdump, None));
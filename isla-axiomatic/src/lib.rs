@@ -44,7 +44,9 @@ lalrpop_mod!(
 );
 
 pub mod axiomatic;
+pub mod concrete_events;
 pub mod footprint_analysis;
+pub mod generate;
 pub mod graph;
 pub mod litmus;
 pub mod page_table;
@@ -575,7 +575,7 @@ fn parse_sizeof_types(litmus_toml: &Value) -> Result<HashMap<String, u32>, Strin
     Ok(sym_sizeof)
 }
 
-fn parse_init<B>(
+fn parse_init<B: BV>(
     reg: &str,
     value: &Value,
     symbolic_addrs: &HashMap<String, u64>,
@@ -593,10 +593,10 @@ fn parse_init<B>(
     match symbolic_addrs.get(value) {
         Some(addr) => Ok((reg, *addr)),
         None => {
-            if let Some(hex) = value.strip_prefix("0x") {
-                match u64::from_str_radix(hex, 16) {
-                    Ok(n) => Ok((reg, n)),
-                    Err(_) => Err(format!("Cannot parse hexadecimal initial value in litmus: {}", value)),
+            if ["0x", "0b", "#x", "#b", "(_"].iter().any(|prefix| value.starts_with(prefix)) {
+                match B::from_str_sized(value) {
+                    Some(bv) if bv.len() <= 64 => Ok((reg, bv.lower_u64())),
+                    _ => Err(format!("Cannot parse bitvector initial value in litmus: {}", value)),
                 }
             } else if value.ends_with(':') {
                 match label_from_objdump(&value[0..value.len() - 1], objdump) {
@@ -694,7 +694,7 @@ fn parse_self_modify_region<B: BV>(toml_region: &Value, objdump: &str) -> Result
         table.get("values").and_then(Value::as_array).ok_or("self_modify element must have a `values` field")?;
     let values = values
         .iter()
-        .map(|v| v.as_str().and_then(B::from_str).map(|bv| (bv.lower_u64(), bv.len())))
+        .map(|v| v.as_str().and_then(B::from_str_sized).map(|bv| (bv.lower_u64(), bv.len())))
         .collect::<Option<Vec<_>>>()
         .ok_or("Could not parse `values` field")?;
 
@@ -846,6 +846,10 @@ pub struct Litmus<B> {
     pub self_modify_regions: Vec<Region<B>>,
     pub objdump: String,
     pub final_assertion: exp::Exp<String>,
+    /// True if the final assertion is the negation of a herd7
+    /// `forall` condition, which isla-litmus translates into
+    /// `~exists ~X` by negating the assertion and expecting unsat
+    pub final_forall: bool,
     pub graph_opts: LitmusGraphOpts,
 }
 
@@ -1000,6 +1004,9 @@ impl<B: BV> Litmus<B> {
             None => Err("No final.assertion found in litmus file".to_string()),
         })?;
 
+        let final_forall =
+            fin.get("expect").and_then(Value::as_str) == Some("unsat") && matches!(final_assertion, exp::Exp::Not(_));
+
         let meta = litmus_toml.get("meta");
 
         let graph_opts_force_show_events = meta
@@ -1030,6 +1037,7 @@ impl<B: BV> Litmus<B> {
             self_modify_regions,
             objdump,
             final_assertion,
+            final_forall,
             graph_opts,
         })
     }
@@ -1039,6 +1047,33 @@ impl<B: BV> Litmus<B> {
         format::litmus_latex(output, self, &id, true, symtab)
     }
 
+    /// The kind of the final condition, as printed by herd7 after
+    /// the test name: `Required` for a `forall` condition and
+    /// `Allowed` for an `exists` condition.
+    pub fn herd_kind(&self) -> &'static str {
+        if self.final_forall {
+            "Required"
+        } else {
+            "Allowed"
+        }
+    }
+
+    /// The final assertion, formatted as a herd7 `Condition` line.
+    pub fn herd_condition(&self, symtab: &Symtab, isa: &ISAConfig<B>) -> String {
+        match &self.final_assertion {
+            exp::Exp::Not(exp) if self.final_forall => {
+                format!("forall ({})", format::herd_condition::<B>(exp, &isa.register_renames, symtab, false))
+            }
+            exp => format!("exists ({})", format::herd_condition::<B>(exp, &isa.register_renames, symtab, false)),
+        }
+    }
+
+    /// The final state described by the final assertion, formatted
+    /// as a herd7 `States` line, if it describes a single state.
+    pub fn herd_state(&self, symtab: &Symtab, isa: &ISAConfig<B>) -> Option<String> {
+        format::herd_state::<B>(&self.final_assertion, &isa.register_renames, symtab)
+    }
+
     pub fn from_file<P>(path: P, symtab: &Symtab, isa: &ISAConfig<B>) -> Result<Self, String>
     where
         P: AsRef<Path>,
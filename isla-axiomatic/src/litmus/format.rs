@@ -27,6 +27,7 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
 use std::str::Lines;
@@ -168,6 +169,113 @@ fn exp_latex<B: BV>(exp: &Exp<String>, symtab: &Symtab, bracket: bool) -> String
     }
 }
 
+// Register renames are many-to-one (e.g. both W0 and X0 map to R0
+// on AArch64), so we pick the greatest name to be deterministic,
+// which prefers the full-width X registers that herd7 prints.
+fn herd_register(reg: Name, renames: &HashMap<String, Name>, symtab: &Symtab) -> String {
+    renames
+        .iter()
+        .filter(|(_, to)| **to == reg)
+        .map(|(from, _)| from)
+        .max()
+        .cloned()
+        .unwrap_or_else(|| zencode::decode(symtab.to_str(reg)))
+}
+
+fn herd_loc(loc: &exp::Loc<String>, renames: &HashMap<String, Name>, symtab: &Symtab) -> String {
+    match loc {
+        exp::Loc::Register { reg, thread_id } => format!("{}:{}", thread_id, herd_register(*reg, renames, symtab)),
+        exp::Loc::LastWriteTo { address, .. } => address.clone(),
+    }
+}
+
+/// Format a final assertion using the concrete syntax herd7 uses
+/// for its `Condition` lines, with values printed in decimal.
+pub(crate) fn herd_condition<B: BV>(
+    exp: &Exp<String>,
+    renames: &HashMap<String, Name>,
+    symtab: &Symtab,
+    bracket: bool,
+) -> String {
+    let join = |exps: &[Exp<String>], op: &str| {
+        let exps = exps.iter().map(|exp| herd_condition::<B>(exp, renames, symtab, true)).collect::<Vec<_>>().join(op);
+        if bracket {
+            format!("({})", exps)
+        } else {
+            exps
+        }
+    };
+
+    match exp {
+        Exp::Loc(id) => id.to_string(),
+        Exp::Label(label) => label.clone(),
+        Exp::True => "true".to_string(),
+        Exp::False => "false".to_string(),
+        Exp::Bin(bin) => u64::from_str_radix(bin, 2).map(|n| n.to_string()).unwrap_or_else(|_| format!("0b{}", bin)),
+        Exp::Hex(hex) => u64::from_str_radix(hex, 16).map(|n| n.to_string()).unwrap_or_else(|_| format!("0x{}", hex)),
+        Exp::Bits64(bits, _) => bits.to_string(),
+        Exp::Nat(n) => n.to_string(),
+        Exp::App(f, args, _) if f == "extz" && args.len() == 2 => {
+            herd_condition::<B>(&args[0], renames, symtab, bracket)
+        }
+        Exp::App(f, args, _) => {
+            let args =
+                args.iter().map(|arg| herd_condition::<B>(arg, renames, symtab, false)).collect::<Vec<_>>().join(",");
+            format!("{}({})", f, args)
+        }
+        Exp::And(exps) => join(exps, " /\\ "),
+        Exp::Or(exps) => join(exps, " \\/ "),
+        Exp::Not(exp) => format!("~{}", herd_condition::<B>(exp, renames, symtab, true)),
+        Exp::Implies(lhs, rhs) => {
+            let exps = format!(
+                "{} => {}",
+                herd_condition::<B>(lhs, renames, symtab, true),
+                herd_condition::<B>(rhs, renames, symtab, true)
+            );
+            if bracket {
+                format!("({})", exps)
+            } else {
+                exps
+            }
+        }
+        Exp::EqLoc(loc, exp) => {
+            format!("{}={}", herd_loc(loc, renames, symtab), herd_condition::<B>(exp, renames, symtab, true))
+        }
+    }
+}
+
+/// If the final assertion is a conjunction of equalities, it
+/// describes a single final state which we can print as a herd7
+/// `States` line, e.g. `0:X0=1; 1:X2=0;`.
+pub(crate) fn herd_state<B: BV>(exp: &Exp<String>, renames: &HashMap<String, Name>, symtab: &Symtab) -> Option<String> {
+    fn collect_eqs<'a>(exp: &'a Exp<String>, eqs: &mut Vec<(&'a exp::Loc<String>, &'a Exp<String>)>) -> bool {
+        match exp {
+            Exp::EqLoc(loc, exp) => {
+                eqs.push((loc, exp));
+                true
+            }
+            Exp::And(exps) => exps.iter().all(|exp| collect_eqs(exp, eqs)),
+            Exp::True => true,
+            _ => false,
+        }
+    }
+
+    let mut eqs = Vec::new();
+    if !collect_eqs(exp, &mut eqs) {
+        return None;
+    }
+
+    let mut state = String::new();
+    for (loc, exp) in eqs {
+        state.push_str(&format!(
+            "{}={}; ",
+            herd_loc(loc, renames, symtab),
+            herd_condition::<B>(exp, renames, symtab, false)
+        ))
+    }
+    Some(state.trim_end().to_string())
+}
+
 pub(crate) fn litmus_latex<B: BV>(
     output: &mut dyn Write,
     litmus: &Litmus<B>,
@@ -40,7 +40,7 @@ use isla_lib::log;
 use isla_lib::memory::{CustomRegion, Memory};
 use isla_lib::primop_util::{length_bits, smt_sbits};
 use isla_lib::smt::{
-    smtlib::{bits64, Exp, Ty},
+    smtlib::{bits64, Def, Exp, Ty},
     Event, ReadOpts, SmtResult, Solver, Sym, WriteOpts,
 };
 use isla_lib::source_loc::SourceLoc;
@@ -561,6 +561,27 @@ impl<B: BV> Desc<B> {
         }
     }
 
+    /// A descriptor where only the bits set in `mask` are known, and
+    /// are equal to the corresponding bits of `known`. The remaining
+    /// bits are unconstrained, so a table walk reading this
+    /// descriptor sees a symbolic value constrained to agree with the
+    /// known bits.
+    pub fn partial(known: u64, mask: u64) -> Self {
+        use Exp::*;
+        let known = known & mask;
+        Desc::Symbolic(
+            known,
+            Arc::new(move |solver| {
+                let desc = solver.declare_const(Ty::BitVec(64), SourceLoc::unknown());
+                solver.add(Def::Assert(Eq(
+                    Box::new(Bvand(Box::new(Var(desc)), Box::new(bits64(mask, 64)))),
+                    Box::new(bits64(known, 64)),
+                )));
+                desc
+            }),
+        )
+    }
+
     /// Make a level 3 descriptor potentially be invalid
     pub fn or_invalid(self) -> Self {
         self.or_desc(0)
@@ -867,6 +888,45 @@ impl<B: BV> PageTables<B> {
         self.map(level0, VirtualAddress::from_u64(page), page, false, attrs, level)
     }
 
+    /// Identity map every page (or block, at levels 1 and 2) which
+    /// overlaps the given range of addresses, so a test can map a
+    /// whole region without listing each page.
+    pub fn identity_map_range<P: PageAttrs>(
+        &mut self,
+        level0: Index,
+        range: Range<u64>,
+        attrs: P,
+        level: u64,
+    ) -> Option<Vec<UpdateWalk>> {
+        let size: u64 = match level {
+            1 => 1 << 30,
+            2 => 1 << 21,
+            3 => 1 << 12,
+            _ => return None,
+        };
+
+        let mut walks = Vec::new();
+        let mut page = range.start & !(size - 1);
+        while page < range.end {
+            walks.push(self.identity_map(level0, page, attrs.clone(), level)?);
+            page += size
+        }
+        Some(walks)
+    }
+
+    /// Make the descriptor for a virtual address partially symbolic
+    /// (see [Desc::partial]), rather than requiring a concrete value
+    pub fn partial(
+        &mut self,
+        level0: Index,
+        va: VirtualAddress,
+        level: u64,
+        known: u64,
+        mask: u64,
+    ) -> Option<UpdateWalk> {
+        self.update(level0, va, |_| Some(Desc::partial(known, mask)), level)
+    }
+
     pub fn freeze(&self) -> ImmutablePageTables<B> {
         ImmutablePageTables { base_addr: self.base_addr, tables: self.tables.clone().into(), region: self.region }
     }
@@ -1272,4 +1332,47 @@ mod tests {
             panic!("simple_translation_table_walk failed")
         }
     }
+
+    #[test]
+    fn test_identity_map_range() {
+        use Exp::*;
+        use SmtResult::*;
+
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+
+        let mut tables = PageTables::<B64>::new("test", 0x5000_0000);
+        let l0 = tables.alloc();
+
+        let walks = tables.identity_map_range(l0, 0x8000_0800..0x8000_2001, S1PageAttrs::default(), 3).unwrap();
+        assert_eq!(walks.len(), 3);
+
+        for va in [0x8000_0123, 0x8000_1FFF, 0x8000_2000] {
+            let pa = simple_translation_table_walk(&tables, l0, VirtualAddress::from_u64(va), &mut solver).unwrap();
+            assert_eq!(Unsat, solver.check_sat_with(&Neq(Box::new(Var(pa)), Box::new(bits64(va, 64)))))
+        }
+    }
+
+    #[test]
+    fn test_partial_descriptor() {
+        use Exp::*;
+        use SmtResult::*;
+
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+
+        let mut tables = PageTables::<B64>::new("test", 0x5000_0000);
+        let l0 = tables.alloc();
+        let va = VirtualAddress::from_u64(0xDEAD_BEEF);
+
+        // Fix the output address bits, but leave the attributes unknown
+        let oa_mask = bzhi_u64(u64::MAX ^ 0xFFF, 48);
+        tables.partial(l0, va, 3, 0x8000_0000, oa_mask).unwrap();
+
+        let pa = simple_translation_table_walk(&tables, l0, va, &mut solver).unwrap();
+        assert_eq!(Sat, solver.check_sat_with(&Eq(Box::new(Var(pa)), Box::new(bits64(0x8000_0EEF, 64)))));
+        assert_eq!(Unsat, solver.check_sat_with(&Neq(Box::new(Var(pa)), Box::new(bits64(0x8000_0EEF, 64)))));
+    }
 }
@@ -59,12 +59,13 @@ use isla_mml::smt::{write_sexps, SexpArena, SexpId};
 
 use crate::axiomatic::model::Model;
 use crate::axiomatic::{Candidates, ExecutionInfo, ThreadId};
+use crate::concrete_events::check_concrete_candidate;
 use crate::footprint_analysis::{footprint_analysis, Footprint, FootprintError};
 use crate::graph::GraphOpts;
 use crate::litmus::exp::{partial_eval, reset_eval, Exp, Partial};
 use crate::litmus::{Litmus, Thread};
 use crate::page_table::setup::{armv8_litmus_page_tables, PageTableSetup, SetupError};
-use crate::smt_events::smt_of_candidate;
+use crate::smt_events::{relation_report, smt_of_candidate};
 
 #[derive(Debug)]
 pub enum LitmusRunError<E> {
@@ -121,6 +122,16 @@ pub struct LitmusRunOpts {
     pub armv8_page_tables: bool,
     pub merge_translations: Option<bool>,
     pub remove_uninteresting_translates: Option<bool>,
+    /// A hash of the architecture and its configuration, used to key
+    /// cached footprints
+    pub arch_hash: Option<String>,
+    /// Print the sizes of the relations generated for each candidate
+    /// execution, and any obvious problems with them
+    pub relation_report: bool,
+    /// Leave whether a thread sleeps after a sleep request (e.g. WFE)
+    /// symbolic, so the candidate executions decide which threads
+    /// can be woken. Otherwise threads never sleep.
+    pub symbolic_sleep: bool,
 }
 
 pub struct LitmusRunInfo {
@@ -156,6 +167,7 @@ where
     let isa_config = arch.isa_config;
     let shared_state = arch.shared_state;
     let mut memory = Memory::new();
+    memory.set_ifetch_sees_writes(isa_config.self_modifying_code);
 
     for region in &litmus.self_modify_regions {
         memory.add_region(region.clone())
@@ -237,7 +249,9 @@ where
                 .iter()
                 .map(|(loc, exp)| (loc.clone(), reset_eval(exp, all_addrs, &litmus.objdump)))
                 .collect();
-            TaskState::with_reset_registers(reset)
+            let mut task_state = TaskState::with_reset_registers(reset);
+            task_state.set_symbolic_sleep(opts.symbolic_sleep);
+            task_state
         })
         .collect();
     let mut lets = arch.lets.clone();
@@ -295,6 +309,7 @@ where
             Some(Ok((task_id, mut events))) => {
                 let mut events: EvPath<B> = events.drain(..).rev().filter(&event_filter).collect();
                 simplify::remove_unused(&mut events);
+                isa_config.memory_kinds.annotate(&mut events, shared_state);
                 for event in events.iter_mut() {
                     let total = threads.len();
                     assert!(task_id < total);
@@ -351,8 +366,9 @@ where
                 || ev.is_abstract()
         })?;
 
-    let footprints = footprint_analysis(opts.num_threads, &thread_buckets, farch, Some(cache.as_ref()))
-        .map_err(LitmusRunError::Footprint)?;
+    let footprints =
+        footprint_analysis(opts.num_threads, &thread_buckets, farch, Some(cache.as_ref()), opts.arch_hash.as_deref())
+            .map_err(LitmusRunError::Footprint)?;
 
     let candidates = Candidates::new(&thread_buckets);
     let num_candidates = candidates.total();
@@ -443,7 +459,10 @@ impl<E: Error> Error for CallbackError<E> {
 }
 
 /// This function runs a callback on the output of the SMT solver for
-/// each candidate execution combined with a cat model.
+/// each candidate execution combined with a cat model. If
+/// `concrete_memory_model` is provided, candidates where every memory
+/// event has a concrete address are checked directly against it, and
+/// the callback receives just `sat` or `unsat` with no model.
 #[allow(clippy::too_many_arguments)]
 pub fn smt_output_per_candidate<B, P, F, E>(
     uid: &str,
@@ -456,6 +475,7 @@ pub fn smt_output_per_candidate<B, P, F, E>(
     memory_model: &[SexpId],
     memory_model_symtab: &memory_model::Symtab,
     memory_model_accessors: &HashMap<memory_model::Name, memory_model::AccessorInfo>,
+    concrete_memory_model: Option<(&memory_model::MemoryModel, &memory_model::ExpArena)>,
     extra_smt: &[(String, String)],
     check_sat_using: Option<&str>,
     get_model: bool,
@@ -513,6 +533,33 @@ where
                 if let Some(split_stages) = opts.merge_translations {
                     exec.merge_translations(split_stages, &mut memory_model_symtab)
                 }
+                if opts.relation_report && first_run {
+                    let report = relation_report(&exec, footprints, memory, initial_physical_addrs);
+                    eprint!("Relations for candidate of {}:\n{}", litmus.name, report)
+                }
+
+                if let Some((mm, mm_arena)) = concrete_memory_model {
+                    if !opts.exhaustive && !opts.armv8_page_tables && extra_smt.is_empty() {
+                        if let Some(allowed) = check_concrete_candidate(
+                            candidate,
+                            &exec,
+                            opts.ignore_ifetch,
+                            footprints,
+                            memory,
+                            initial_physical_addrs,
+                            final_assertion,
+                            arch.isa_config,
+                            mm,
+                            mm_arena,
+                            &mut memory_model_symtab,
+                        ) {
+                            log!(log::VERBOSE, &format!("concrete evaluation took: {}ms", now.elapsed().as_millis()));
+                            let output = if allowed { "sat" } else { "unsat" };
+                            break callback(exec, memory, all_addrs, translation_tables, footprints, output)
+                                .map_err(CallbackError::User);
+                        }
+                    }
+                }
 
                 let mut path = cache.as_ref().to_owned();
                 path.push(format!("isla_candidate_{}_{}_{}.smt2", uid, std::process::id(), tid));
@@ -605,7 +652,8 @@ where
                             arch.shared_state,
                             &memory_model_symtab,
                             &mut sexps,
-                        );
+                        )
+                        .map_err(|err| CallbackError::Internal(memory_model::format_error(&err)))?;
                         accessor_sexps.push(f);
                     }
                     let index_bitwidths = index_bitwidths(&exec.smt_events);
@@ -29,6 +29,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::io::Write;
 
 use isla_lib::bitvector::BV;
@@ -41,7 +42,7 @@ use isla_lib::smt::{Event, Sym};
 use isla_cat::smt::Sexp;
 
 use crate::axiomatic::relations::*;
-use crate::axiomatic::{AxEvent, ExecutionInfo, Pairs};
+use crate::axiomatic::{AxEvent, ExecutionInfo, Pairs, ThreadId};
 use crate::footprint_analysis::Footprint;
 use crate::litmus::{exp::Exp, exp::Loc, opcode_from_objdump, Litmus};
 
@@ -502,6 +503,89 @@ static IFETCH_SMTLIB: &str = include_str!("ifetch.smt2");
 
 static LAST_WRITE_TO: &str = include_str!("last_write_to.smt2");
 
+// The sets, relations, and functions defined for every candidate by
+// `smt_of_candidate`, the SMTLIB files above, and the translation
+// relations in `smt_relations`
+static PROVIDED_NAMES: [&str; 53] = [
+    "R",
+    "W",
+    "M",
+    "IW",
+    "AT",
+    "T",
+    "T_f",
+    "Stage1",
+    "Stage2",
+    "AA",
+    "IF",
+    "SLEEP",
+    "WAKE",
+    "r-initial",
+    "ifetch-match",
+    "ifetch-initial",
+    "amo",
+    "instruction-order",
+    "po",
+    "fpo",
+    "fe",
+    "iio",
+    "int",
+    "ext",
+    "same-translation",
+    "loc",
+    "overlap-loc",
+    "po-loc",
+    "rw-pair",
+    "addr",
+    "data",
+    "ctrl",
+    "rmw",
+    "translate-same-va-page",
+    "translate-same-ipa-page",
+    "id",
+    "co",
+    "rf",
+    "irf",
+    "wco",
+    "scl",
+    "addr_of",
+    "read_addr_of",
+    "translated_before",
+    "translate_va",
+    "translate_ipa",
+    "trf1-internal",
+    "trf2-internal",
+    "trf",
+    "tt_init",
+    "tt_write",
+    "tt-init",
+    "tt-write",
+];
+
+// Functions generated for each access width, like `val_of_64`
+static PROVIDED_WIDTH_PREFIXES: [&str; 4] = ["val_of_", "write_data_of_", "write_addr_data_of_", "last_write_to_"];
+
+/// Returns true if `name` is defined by isla for each candidate
+/// execution, so a memory model may refer to it without declaring it.
+pub fn is_provided_name<B: BV>(name: &str, isa_config: &ISAConfig<B>) -> bool {
+    if PROVIDED_NAMES.contains(&name) {
+        return true;
+    }
+
+    if PROVIDED_WIDTH_PREFIXES
+        .iter()
+        .any(|prefix| name.strip_prefix(prefix).map_or(false, |width| width.parse::<u32>().is_ok()))
+    {
+        return true;
+    }
+
+    // Each register event set `s` from the ISA config defines
+    // `read_s`, `write_s`, and `val_of_read_s`
+    isa_config.register_event_sets.keys().any(|set| {
+        ["read_", "write_", "val_of_read_"].iter().any(|prefix| name.strip_prefix(prefix) == Some(set.as_str()))
+    })
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn smt_of_candidate<B: BV>(
     output: &mut dyn Write,
@@ -756,6 +840,9 @@ pub fn smt_of_candidate<B: BV>(
 
     smt_set(|ev| is_read(ev) || is_write(ev), events).write_set(output, "M")?;
     smt_set(is_ifetch, events).write_set(output, "IF")?;
+    smt_set(is_address_announce, events).write_set(output, "AA")?;
+    smt_set(is_sleep_request, events).write_set(output, "SLEEP")?;
+    smt_set(is_wakeup_request, events).write_set(output, "WAKE")?;
 
     for (set, kinds) in isa_config.register_event_sets.iter() {
         smt_set(|ev| kinds.iter().any(|k| k.is_read() && ev.has_read_reg_of(k.name())), events)
@@ -853,9 +940,128 @@ pub fn smt_of_candidate<B: BV>(
         writeln!(output, "{}", lwt)?;
     }
 
+    writeln!(output, "; === SLEEPING ===\n")?;
+    log!(log::LITMUS, "generating smt sleep constraints");
+    // The last instruction (by po) in each thread that requests a wakeup
+    let mut last_wake: HashMap<ThreadId, usize> = HashMap::new();
+    for ev in events.iter().filter(|ev| is_wakeup_request(ev)) {
+        let po = last_wake.entry(ev.thread_id).or_default();
+        *po = (*po).max(ev.instruction_index + 1)
+    }
+    for (tid, po, sleeping) in &exec.sleeps {
+        // A thread can only sleep if it can be woken, either by
+        // another thread or by an interrupt injected into the same
+        // thread after the cycle in which it checks whether it is
+        // sleeping.
+        let can_wake = last_wake.iter().any(|(wake_tid, wake_po)| wake_tid != tid || wake_po > po);
+        if !can_wake {
+            writeln!(output, "(assert (not v{}))", sleeping)?
+        }
+    }
+    writeln!(output)?;
+
     writeln!(output, "; === FINAL ASSERTION ===\n")?;
     log!(log::LITMUS, "generating smt final assertion");
     writeln!(output, "(assert {})\n", exp_to_smt(final_assertion, &exec.final_writes))?;
 
     Ok(())
 }
+
+/// Sizes of the sets and relations generated for a candidate
+/// execution, along with any obvious problems with them. Relations
+/// that are only determined by the solver (rf, co, loc) are reported
+/// as the number of pairs that could possibly be related. This is
+/// useful for catching mistakes in how events are set up before the
+/// SMT check produces an unexplained unsat.
+pub struct RelationReport {
+    pub sizes: Vec<(&'static str, usize)>,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for RelationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, size) in &self.sizes {
+            writeln!(f, "  {:<16} {}", name, size)?
+        }
+        for problem in &self.problems {
+            writeln!(f, "  warning: {}", problem)?
+        }
+        Ok(())
+    }
+}
+
+/// Returns false if two events definitely access different
+/// locations, using only their concrete addresses.
+fn may_alias<B: BV>(ev1: &AxEvent<B>, ev2: &AxEvent<B>) -> bool {
+    match (ev1.address(), ev2.address()) {
+        (Some(Val::Bits(bv1)), Some(Val::Bits(bv2))) => bv1 == bv2,
+        (Some(_), Some(_)) => true,
+        (_, _) => false,
+    }
+}
+
+fn is_possible(mut sexp: Sexp) -> bool {
+    sexp.simplify(&HashSet::new());
+    !matches!(sexp, Sexp::False)
+}
+
+pub fn relation_report<B: BV>(
+    exec: &ExecutionInfo<B>,
+    footprints: &HashMap<B, Footprint>,
+    memory: &Memory<B>,
+    initial_physical_addrs: &HashMap<u64, u64>,
+) -> RelationReport {
+    let events = &exec.smt_events;
+    let thread_opcodes = &exec.thread_opcodes;
+    let set_size = |set: fn(&AxEvent<B>) -> bool| events.iter().filter(|ev| set(ev)).count();
+    let rel_size = |rel: &dyn Fn(&AxEvent<B>, &AxEvent<B>) -> bool| {
+        Pairs::from_slice(events).filter(|(ev1, ev2)| rel(ev1, ev2)).count()
+    };
+    let dep_size = |rel: DepRel<B>| rel_size(&|ev1, ev2| rel(ev1, ev2, thread_opcodes, footprints));
+
+    let mut problems = Vec::new();
+
+    let mut rf = 0;
+    for read in events.iter().filter(|ev| is_read(ev)) {
+        let sources = events
+            .iter()
+            .filter(|write| {
+                is_write(write)
+                    && disjoint(write, read)
+                    && may_alias(write, read)
+                    && is_possible(read_write_pair(write, read))
+            })
+            .count()
+            + is_possible(read_initial(read, memory, initial_physical_addrs)) as usize;
+        if sources == 0 {
+            problems.push(format!("read {} cannot read from any write or the initial state", read.name))
+        }
+        rf += sources
+    }
+
+    let sizes = vec![
+        ("R", set_size(is_read)),
+        ("W", set_size(is_write)),
+        ("M", set_size(is_memory)),
+        ("IF", set_size(is_ifetch)),
+        ("AT", set_size(is_translate)),
+        ("AA", set_size(is_address_announce)),
+        ("po", rel_size(&po)),
+        ("iio", rel_size(&intra_instruction_ordered)),
+        ("int", rel_size(&internal)),
+        ("ext", rel_size(&external)),
+        ("addr", dep_size(addr)),
+        ("data", dep_size(data)),
+        ("ctrl", dep_size(ctrl)),
+        ("rmw", dep_size(rmw)),
+        ("amo", dep_size(amo)),
+        ("loc (possible)", rel_size(&|ev1, ev2| disjoint(ev1, ev2) && may_alias(ev1, ev2))),
+        ("rf (possible)", rf),
+        (
+            "co (possible)",
+            rel_size(&|ev1, ev2| is_write(ev1) && is_write(ev2) && disjoint(ev1, ev2) && may_alias(ev1, ev2)),
+        ),
+    ];
+
+    RelationReport { sizes, problems }
+}
@@ -0,0 +1,432 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a cheap abstract interpretation of each
+//! function's IR, tracking the known bits of bitvector variables and
+//! the values of boolean variables. When the executor reaches a
+//! branch on a symbolic condition, the facts computed for that point
+//! are combined with any concrete values in the current frame, and if
+//! that is enough to decide the condition we can skip querying the
+//! solver for the infeasible direction.
+//!
+//! The analysis for each function is computed the first time one of
+//! its branches is reached, and cached.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::bitvector::BV;
+use crate::ir::*;
+
+/// An abstract value. Variables without an abstract value may have
+/// any value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Abstract {
+    Bool(bool),
+    /// A bitvector of length at most 64, for which the bits set in
+    /// `known` are equal to those in `value`.
+    Bits {
+        len: u32,
+        known: u64,
+        value: u64,
+    },
+}
+
+fn mask(len: u32) -> u64 {
+    if len >= 64 {
+        u64::MAX
+    } else {
+        (1 << len) - 1
+    }
+}
+
+fn bits(len: u32, known: u64, value: u64) -> Option<Abstract> {
+    if len > 64 || known == 0 {
+        None
+    } else {
+        Some(Abstract::Bits { len, known, value: value & known })
+    }
+}
+
+impl Abstract {
+    pub fn from_val<B: BV>(val: &Val<B>) -> Option<Self> {
+        match val {
+            Val::Bool(b) => Some(Abstract::Bool(*b)),
+            Val::Bits(bv) if bv.len() <= 64 => bits(bv.len(), mask(bv.len()), bv.lower_u64()),
+            _ => None,
+        }
+    }
+
+    fn join(self, other: Self) -> Option<Self> {
+        use Abstract::*;
+        match (self, other) {
+            (Bool(x), Bool(y)) if x == y => Some(Bool(x)),
+            (Bits { len: l1, known: k1, value: v1 }, Bits { len: l2, known: k2, value: v2 }) if l1 == l2 => {
+                bits(l1, k1 & k2 & !(v1 ^ v2), v1)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Evaluate an expression abstractly, using `lookup` to find the
+/// abstract values of variables.
+pub fn eval<F>(exp: &Exp<Name>, lookup: &F) -> Option<Abstract>
+where
+    F: Fn(Name) -> Option<Abstract>,
+{
+    use Abstract::*;
+    match exp {
+        Exp::Id(id) => lookup(*id),
+        Exp::Bool(b) => Some(Bool(*b)),
+        Exp::Bits(bv) => bits(bv.len(), mask(bv.len()), bv.lower_u64()),
+        Exp::Call(op, args) => {
+            let args: Vec<Option<Abstract>> = args.iter().map(|arg| eval(arg, lookup)).collect();
+            eval_op(*op, &args)
+        }
+        _ => None,
+    }
+}
+
+fn eval_op(op: Op, args: &[Option<Abstract>]) -> Option<Abstract> {
+    use Abstract::*;
+    match (op, args) {
+        (Op::Not, [Some(Bool(b))]) => Some(Bool(!b)),
+        (Op::And, args) => {
+            if args.iter().any(|arg| *arg == Some(Bool(false))) {
+                Some(Bool(false))
+            } else if args.iter().all(|arg| *arg == Some(Bool(true))) {
+                Some(Bool(true))
+            } else {
+                None
+            }
+        }
+        (Op::Or, args) => {
+            if args.iter().any(|arg| *arg == Some(Bool(true))) {
+                Some(Bool(true))
+            } else if args.iter().all(|arg| *arg == Some(Bool(false))) {
+                Some(Bool(false))
+            } else {
+                None
+            }
+        }
+        (Op::Eq, [Some(lhs), Some(rhs)]) => equal(*lhs, *rhs).map(Bool),
+        (Op::Neq, [Some(lhs), Some(rhs)]) => equal(*lhs, *rhs).map(|eq| Bool(!eq)),
+        (Op::Bvnot, [Some(Bits { len, known, value })]) => bits(*len, *known, !value),
+        (Op::Bvand, [Some(Bits { len, known: k1, value: v1 }), Some(Bits { len: l2, known: k2, value: v2 })])
+            if len == l2 =>
+        {
+            // A bit is known if it is known in both, or known to be zero in either
+            bits(*len, (k1 & k2) | (k1 & !v1) | (k2 & !v2), v1 & v2)
+        }
+        (Op::Bvor, [Some(Bits { len, known: k1, value: v1 }), Some(Bits { len: l2, known: k2, value: v2 })])
+            if len == l2 =>
+        {
+            bits(*len, (k1 & k2) | (k1 & v1) | (k2 & v2), v1 | v2)
+        }
+        (Op::Bvxor, [Some(Bits { len, known: k1, value: v1 }), Some(Bits { len: l2, known: k2, value: v2 })])
+            if len == l2 =>
+        {
+            bits(*len, k1 & k2, v1 ^ v2)
+        }
+        (Op::ZeroExtend(to), [Some(Bits { len, known, value })]) if *to <= 64 && *to >= *len => {
+            bits(*to, known | (mask(*to) & !mask(*len)), *value)
+        }
+        (Op::Concat, [Some(Bits { len: l1, known: k1, value: v1 }), Some(Bits { len: l2, known: k2, value: v2 })])
+            if l1 + l2 <= 64 =>
+        {
+            bits(l1 + l2, (k1 << l2) | k2, (v1 << l2) | v2)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether two abstract values are definitely equal or
+/// definitely different, if that can be determined.
+fn equal(lhs: Abstract, rhs: Abstract) -> Option<bool> {
+    use Abstract::*;
+    match (lhs, rhs) {
+        (Bool(x), Bool(y)) => Some(x == y),
+        (Bits { len: l1, known: k1, value: v1 }, Bits { len: l2, known: k2, value: v2 }) if l1 == l2 => {
+            let both = k1 & k2;
+            if (v1 ^ v2) & both != 0 {
+                Some(false)
+            } else if both == mask(l1) {
+                Some(true)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+type State = HashMap<Name, Abstract>;
+
+fn join_states(lhs: &State, rhs: &State) -> State {
+    lhs.iter().filter_map(|(id, x)| Some((*id, x.join(*rhs.get(id)?)?))).collect()
+}
+
+fn assign(state: &mut State, locals: &HashSet<Name>, id: Name, value: Option<Abstract>) {
+    if !locals.contains(&id) {
+        return;
+    }
+    match value {
+        Some(value) => state.insert(id, value),
+        None => state.remove(&id),
+    };
+}
+
+/// The facts for a single function, for each branch on a symbolic
+/// condition.
+struct FunctionFacts {
+    branches: HashMap<usize, State>,
+}
+
+fn analyze<B: BV>(args: &[Name], instrs: &[Instr<Name, B>]) -> FunctionFacts {
+    let mut locals: HashSet<Name> = args.iter().copied().collect();
+    for instr in instrs {
+        match instr {
+            Instr::Decl(id, _, _) | Instr::Init(id, _, _, _) => {
+                locals.insert(*id);
+            }
+            _ => (),
+        }
+    }
+
+    let mut states: Vec<Option<State>> = vec![None; instrs.len()];
+    let mut worklist = vec![0];
+    if !instrs.is_empty() {
+        states[0] = Some(State::new())
+    }
+
+    let propagate = |states: &mut Vec<Option<State>>, worklist: &mut Vec<usize>, target: usize, state: State| {
+        if target >= states.len() {
+            return;
+        }
+        let new_state = match &states[target] {
+            None => state,
+            Some(old) => {
+                let joined = join_states(old, &state);
+                if joined == *old {
+                    return;
+                }
+                joined
+            }
+        };
+        states[target] = Some(new_state);
+        worklist.push(target)
+    };
+
+    while let Some(pc) = worklist.pop() {
+        let mut state = match &states[pc] {
+            Some(state) => state.clone(),
+            None => continue,
+        };
+        match &instrs[pc] {
+            Instr::Decl(id, _, _) => {
+                state.remove(id);
+                propagate(&mut states, &mut worklist, pc + 1, state)
+            }
+            Instr::Init(id, _, exp, _) | Instr::Copy(Loc::Id(id), exp, _) => {
+                let value = eval(exp, &|id| state.get(&id).copied());
+                assign(&mut state, &locals, *id, value);
+                propagate(&mut states, &mut worklist, pc + 1, state)
+            }
+            Instr::Copy(Loc::Addr(_), _, _) => propagate(&mut states, &mut worklist, pc + 1, State::new()),
+            Instr::Copy(loc, _, _)
+            | Instr::Call(loc, _, _, _, _)
+            | Instr::PrimopUnary(loc, _, _, _)
+            | Instr::PrimopBinary(loc, _, _, _, _)
+            | Instr::PrimopVariadic(loc, _, _, _) => {
+                state.remove(&loc.id());
+                propagate(&mut states, &mut worklist, pc + 1, state)
+            }
+            Instr::Monomorphize(_, _) => propagate(&mut states, &mut worklist, pc + 1, state),
+            Instr::Jump(exp, target, _) => {
+                let mut taken = state.clone();
+                match exp {
+                    Exp::Id(id) => {
+                        assign(&mut taken, &locals, *id, Some(Abstract::Bool(true)));
+                        assign(&mut state, &locals, *id, Some(Abstract::Bool(false)))
+                    }
+                    Exp::Call(Op::Not, args) => {
+                        if let [Exp::Id(id)] = args.as_slice() {
+                            assign(&mut taken, &locals, *id, Some(Abstract::Bool(false)));
+                            assign(&mut state, &locals, *id, Some(Abstract::Bool(true)))
+                        }
+                    }
+                    _ => (),
+                }
+                propagate(&mut states, &mut worklist, *target, taken);
+                propagate(&mut states, &mut worklist, pc + 1, state)
+            }
+            Instr::Goto(target) => propagate(&mut states, &mut worklist, *target, state),
+            Instr::Exit(_, _) | Instr::Arbitrary | Instr::End => (),
+        }
+    }
+
+    let branches = instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instr)| match (instr, &states[pc]) {
+            (Instr::Jump(_, _, _), Some(state)) if !state.is_empty() => Some((pc, state.clone())),
+            _ => None,
+        })
+        .collect();
+
+    FunctionFacts { branches }
+}
+
+/// Find the branches in a function body with `args` whose conditions
+/// are always true or always false, given the facts that hold at each
+/// branch. Returns whether each such branch is always taken.
+pub fn constant_branches<B: BV>(args: &[Name], instrs: &[Instr<Name, B>]) -> HashMap<usize, bool> {
+    let facts = analyze(args, instrs);
+    instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instr)| match instr {
+            Instr::Jump(exp, _, _) => {
+                let state = facts.branches.get(&pc);
+                match eval(exp, &|id| state.and_then(|state| state.get(&id).copied())) {
+                    Some(Abstract::Bool(b)) => Some((pc, b)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// A cache of the facts computed for each function, which can be
+/// shared between threads.
+#[derive(Default)]
+pub struct BranchFacts {
+    functions: RwLock<HashMap<Name, Arc<FunctionFacts>>>,
+    decided: AtomicU64,
+}
+
+impl BranchFacts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Try to decide the condition of the branch at `pc` in
+    /// `function`, using `concrete` to find the values of variables
+    /// in the current frame.
+    pub fn decide<B: BV, F>(
+        &self,
+        shared_state: &SharedState<B>,
+        function: Name,
+        pc: usize,
+        exp: &Exp<Name>,
+        concrete: F,
+    ) -> Option<bool>
+    where
+        F: Fn(Name) -> Option<Abstract>,
+    {
+        let facts = self.function_facts(shared_state, function)?;
+        let state = facts.branches.get(&pc);
+        let result = eval(exp, &|id| concrete(id).or_else(|| state.and_then(|state| state.get(&id).copied())));
+        match result {
+            Some(Abstract::Bool(b)) => {
+                self.decided.fetch_add(1, Ordering::Relaxed);
+                Some(b)
+            }
+            _ => None,
+        }
+    }
+
+    fn function_facts<B: BV>(&self, shared_state: &SharedState<B>, function: Name) -> Option<Arc<FunctionFacts>> {
+        if let Some(facts) = self.functions.read().unwrap().get(&function) {
+            return Some(facts.clone());
+        }
+        let (args, _, instrs) = shared_state.functions.get(&function)?;
+        let args: Vec<Name> = args.iter().map(|(id, _)| *id).collect();
+        let facts = Arc::new(analyze(&args, instrs));
+        self.functions.write().unwrap().insert(function, facts.clone());
+        Some(facts)
+    }
+
+    /// The number of branches decided without querying the solver.
+    pub fn decided(&self) -> u64 {
+        self.decided.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::source_loc::SourceLoc;
+
+    fn known(len: u32, known: u64, value: u64) -> Option<Abstract> {
+        bits(len, known, value)
+    }
+
+    #[test]
+    fn known_bits_ops() {
+        let top_byte_zero = known(16, 0xFF00, 0);
+        let one = Abstract::from_val(&Val::Bits(B64::new(0x100, 16)));
+        assert_eq!(eval_op(Op::Eq, &[top_byte_zero, one]), Some(Abstract::Bool(false)));
+        assert_eq!(eval_op(Op::Eq, &[top_byte_zero, known(16, 0x00FF, 0x1)]), None);
+        assert_eq!(eval_op(Op::Bvand, &[None, known(8, 0xFF, 0)]), None);
+        assert_eq!(eval_op(Op::Bvand, &[known(8, 0x0F, 0), known(8, 0xF0, 0)]), known(8, 0xFF, 0));
+        assert_eq!(eval_op(Op::ZeroExtend(16), &[known(8, 0x1, 1)]), known(16, 0xFF01, 1));
+        assert_eq!(eval_op(Op::Concat, &[known(4, 0xF, 0xA), known(4, 0, 0)]), known(8, 0xF0, 0xA0));
+        assert_eq!(eval_op(Op::And, &[None, Some(Abstract::Bool(false))]), Some(Abstract::Bool(false)));
+    }
+
+    #[test]
+    fn branch_facts_after_assignment() {
+        let mut symtab = Symtab::new();
+        let x = symtab.intern("x");
+        let b = symtab.intern("b");
+        let instrs: Vec<Instr<Name, B64>> = vec![
+            Instr::Decl(x, Ty::Bits(8), SourceLoc::unknown()),
+            Instr::Copy(
+                Loc::Id(x),
+                Exp::Call(Op::Bvand, vec![Exp::Id(b), Exp::Bits(B64::new(0x0F, 8))]),
+                SourceLoc::unknown(),
+            ),
+            Instr::Jump(Exp::Call(Op::Eq, vec![Exp::Id(x), Exp::Bits(B64::new(0x10, 8))]), 3, SourceLoc::unknown()),
+            Instr::End,
+        ];
+        let facts = analyze(&[b], &instrs);
+        let state = facts.branches.get(&2).unwrap();
+        assert_eq!(state.get(&x), known(8, 0xF0, 0).as_ref());
+        let test = match &instrs[2] {
+            Instr::Jump(exp, _, _) => exp,
+            _ => unreachable!(),
+        };
+        assert_eq!(eval(test, &|id| state.get(&id).copied()), Some(Abstract::Bool(false)))
+    }
+}
@@ -0,0 +1,140 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module handles pinning worker threads to cores. When enabled
+//! with [set_pin_workers], each worker started by
+//! [crate::executor::start_multi] is pinned to a core, with cores
+//! ordered by NUMA node so consecutive workers share a node. Only the
+//! threads are placed; no memory policy is set, so where each
+//! worker's allocations end up is left to the kernel and the system
+//! allocator.
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PIN_WORKERS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_pin_workers(pin: bool) {
+    PIN_WORKERS.store(pin, Ordering::SeqCst)
+}
+
+pub fn pin_workers() -> bool {
+    PIN_WORKERS.load(Ordering::SeqCst)
+}
+
+/// Parse a Linux cpu list, e.g. `0-3,8-11`
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((lo, hi)) => {
+                if let (Ok(lo), Ok(hi)) = (lo.parse::<usize>(), hi.parse::<usize>()) {
+                    cpus.extend(lo..=hi)
+                }
+            }
+            None => {
+                if let Ok(cpu) = range.parse() {
+                    cpus.push(cpu)
+                }
+            }
+        }
+    }
+    cpus
+}
+
+/// The cores this process may run on, grouped by NUMA node. If the
+/// node topology is unavailable the cores are in numerical order.
+#[cfg(target_os = "linux")]
+pub fn available_cores() -> Vec<usize> {
+    let allowed: Vec<usize> = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Vec::new();
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|cpu| libc::CPU_ISSET(*cpu, &set)).collect()
+    };
+
+    let mut nodes: Vec<(usize, Vec<usize>)> = fs::read_dir("/sys/devices/system/node")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let node = entry.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+                    let cpus = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+                    Some((node, parse_cpu_list(&cpus)))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    nodes.sort();
+
+    let mut cores: Vec<usize> =
+        nodes.iter().flat_map(|(_, cpus)| cpus.iter().copied()).filter(|cpu| allowed.contains(cpu)).collect();
+    for cpu in allowed {
+        if !cores.contains(&cpu) {
+            cores.push(cpu)
+        }
+    }
+    cores
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn available_cores() -> Vec<usize> {
+    Vec::new()
+}
+
+/// Pin the current thread to a single core
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> std::io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_core: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "thread pinning is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8-9\n"), vec![0, 1, 2, 3, 8, 9]);
+        assert_eq!(parse_cpu_list("5"), vec![5]);
+        assert_eq!(parse_cpu_list(""), Vec::<usize>::new())
+    }
+}
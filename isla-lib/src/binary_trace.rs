@@ -0,0 +1,1300 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a compact binary encoding for traces, as
+//! an alternative to the SMTLIB-style s-expressions produced by
+//! [crate::simplify::write_events]. Each event is written as a
+//! single tag byte followed by its fields, with symbols, names, and
+//! lengths encoded as LEB128 varints and bitvectors stored as raw
+//! little-endian bytes. SMT definitions are encoded via bincode.
+//!
+//! A binary trace file starts with [MAGIC], and contains zero or more
+//! traces, each terminated by an end-of-trace marker. This means that
+//! tools can stream traces to a file as they are collected, and can
+//! tell the binary format apart from textual traces using
+//! [is_binary_trace].
+//!
+//! A trace can also be written in chunks while it is still being
+//! generated (see [crate::smt::spill]). Each chunk is a separate
+//! record with a numeric id, which continues an optional parent
+//! chunk, so paths which fork after writing a chunk share it. A
+//! trace may then start with a reference to a chunk, standing for
+//! the events of that chunk and all its ancestors. If the writer is
+//! killed before a path finishes, a reader created with
+//! [BinaryTraceReader::recovering] will return the events of each
+//! chain of chunks which no trace refers to.
+
+use bincode::Options;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use crate::bitvector::BV;
+use crate::ir::{BitsSegment, Name, Val};
+use crate::smt::{Accessor, EnumId, EnumMember, Event, ReadOpts, Sym};
+
+/// The magic bytes at the start of every binary trace file. The last
+/// byte is the format version.
+pub const MAGIC: &[u8; 8] = b"ISLATRC\x03";
+
+const TAG_END: u8 = 0;
+const TAG_SMT: u8 = 1;
+const TAG_FORK: u8 = 2;
+const TAG_FUNCTION: u8 = 3;
+const TAG_ABSTRACT: u8 = 4;
+const TAG_READ_REG: u8 = 5;
+const TAG_WRITE_REG: u8 = 6;
+const TAG_ASSUME_REG: u8 = 7;
+const TAG_READ_MEM: u8 = 8;
+const TAG_WRITE_MEM: u8 = 9;
+const TAG_MARK_REG: u8 = 10;
+const TAG_BRANCH: u8 = 11;
+const TAG_ADDRESS_ANNOUNCE: u8 = 12;
+const TAG_CYCLE: u8 = 13;
+const TAG_INSTR: u8 = 14;
+const TAG_ASSUME: u8 = 15;
+const TAG_ASSUME_FUN: u8 = 16;
+const TAG_USE_FUN_ASSUMPTION: u8 = 17;
+const TAG_PROVENANCE: u8 = 18;
+const TAG_SLEEP_REQUEST: u8 = 19;
+const TAG_WAKEUP_REQUEST: u8 = 20;
+const TAG_SLEEPING: u8 = 21;
+const TAG_CHUNK: u8 = 22;
+const TAG_CHUNK_REF: u8 = 23;
+const TAG_INTERRUPT: u8 = 24;
+
+const VAL_SYMBOLIC: u8 = 0;
+const VAL_I64: u8 = 1;
+const VAL_I128: u8 = 2;
+const VAL_BOOL: u8 = 3;
+const VAL_BITS: u8 = 4;
+const VAL_MIXED_BITS: u8 = 5;
+const VAL_STRING: u8 = 6;
+const VAL_UNIT: u8 = 7;
+const VAL_VECTOR: u8 = 8;
+const VAL_LIST: u8 = 9;
+const VAL_ENUM: u8 = 10;
+const VAL_STRUCT: u8 = 11;
+const VAL_CTOR: u8 = 12;
+const VAL_SYMBOLIC_CTOR: u8 = 13;
+const VAL_REF: u8 = 14;
+const VAL_POISON: u8 = 15;
+
+/// Lengths are read from the trace before the items they count, so
+/// we never pre-allocate more than this many items for them, lest a
+/// corrupt trace make us try to allocate an arbitrary amount of
+/// memory.
+const MAX_PREALLOCATE: usize = 1024;
+
+const RESULT_OK: u8 = 0;
+const RESULT_ERR: u8 = 1;
+
+#[derive(Debug)]
+pub enum BinaryTraceError {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    BadMagic,
+    BadTag(&'static str, u8),
+    BadBits(u32),
+    BadString,
+    VarintOverflow,
+    UnexpectedEnd,
+    UnknownChunk(u64),
+    UnknownRegion(String),
+}
+
+impl fmt::Display for BinaryTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BinaryTraceError::*;
+        match self {
+            Io(err) => write!(f, "IO error in binary trace: {}", err),
+            Bincode(err) => write!(f, "Failed to decode SMT definition in binary trace: {}", err),
+            BadMagic => write!(f, "Not a binary trace (bad magic number)"),
+            BadTag(what, tag) => write!(f, "Invalid {} tag {} in binary trace", what, tag),
+            BadBits(len) => write!(f, "Bitvector of length {} is too long for this bitvector type", len),
+            BadString => write!(f, "Invalid UTF-8 string in binary trace"),
+            VarintOverflow => write!(f, "Varint too large in binary trace"),
+            UnexpectedEnd => write!(f, "Binary trace ended in the middle of a trace"),
+            UnknownChunk(id) => write!(f, "Reference to unknown chunk {} in binary trace", id),
+            UnknownRegion(name) => write!(f, "Unknown memory region {:?} in binary trace", name),
+        }
+    }
+}
+
+impl Error for BinaryTraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl From<io::Error> for BinaryTraceError {
+    fn from(err: io::Error) -> Self {
+        BinaryTraceError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for BinaryTraceError {
+    fn from(err: bincode::Error) -> Self {
+        BinaryTraceError::Bincode(err)
+    }
+}
+
+/// Returns true if the given prefix of a file is the start of a
+/// binary trace.
+pub fn is_binary_trace(prefix: &[u8]) -> bool {
+    prefix.starts_with(MAGIC)
+}
+
+pub(crate) fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+pub(crate) fn write_varint(buf: &mut dyn Write, mut n: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut i = 0;
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes[i] = byte;
+            i += 1;
+            break;
+        }
+        bytes[i] = byte | 0x80;
+        i += 1
+    }
+    buf.write_all(&bytes[..i])
+}
+
+pub(crate) fn write_u8(buf: &mut dyn Write, n: u8) -> io::Result<()> {
+    buf.write_all(&[n])
+}
+
+pub(crate) fn write_bool(buf: &mut dyn Write, b: bool) -> io::Result<()> {
+    write_u8(buf, b as u8)
+}
+
+pub(crate) fn write_u32(buf: &mut dyn Write, n: u32) -> io::Result<()> {
+    write_varint(buf, u64::from(n))
+}
+
+pub(crate) fn write_usize(buf: &mut dyn Write, n: usize) -> io::Result<()> {
+    write_varint(buf, n as u64)
+}
+
+fn write_i128(buf: &mut dyn Write, n: i128) -> io::Result<()> {
+    // Zigzag encode so small negative numbers stay small
+    let mut z = ((n << 1) ^ (n >> 127)) as u128;
+    loop {
+        let byte = (z & 0x7F) as u8;
+        z >>= 7;
+        if z == 0 {
+            return write_u8(buf, byte);
+        }
+        write_u8(buf, byte | 0x80)?
+    }
+}
+
+pub(crate) fn write_str(buf: &mut dyn Write, s: &str) -> io::Result<()> {
+    write_usize(buf, s.len())?;
+    buf.write_all(s.as_bytes())
+}
+
+fn write_sym(buf: &mut dyn Write, v: Sym) -> io::Result<()> {
+    write_u32(buf, v.id)
+}
+
+pub(crate) fn write_name(buf: &mut dyn Write, n: Name) -> io::Result<()> {
+    write_u32(buf, n.to_u32())
+}
+
+/// Bitvectors are written as their length, followed by the bits in
+/// 64-bit chunks from least to most significant, with each chunk
+/// taking only as many little-endian bytes as it needs.
+fn write_bits<B: BV>(buf: &mut dyn Write, bv: B) -> io::Result<()> {
+    let len = bv.len();
+    write_u32(buf, len)?;
+    let mut from = 0;
+    while from < len {
+        let chunk_len = std::cmp::min(64, len - from);
+        let chunk = bv.slice(from, chunk_len).unwrap().lower_u64();
+        buf.write_all(&chunk.to_le_bytes()[..(chunk_len as usize + 7) / 8])?;
+        from += chunk_len
+    }
+    Ok(())
+}
+
+fn write_val<B: BV>(buf: &mut dyn Write, v: &Val<B>) -> io::Result<()> {
+    match v {
+        Val::Symbolic(v) => {
+            write_u8(buf, VAL_SYMBOLIC)?;
+            write_sym(buf, *v)
+        }
+        Val::I64(n) => {
+            write_u8(buf, VAL_I64)?;
+            write_i128(buf, i128::from(*n))
+        }
+        Val::I128(n) => {
+            write_u8(buf, VAL_I128)?;
+            write_i128(buf, *n)
+        }
+        Val::Bool(b) => {
+            write_u8(buf, VAL_BOOL)?;
+            write_bool(buf, *b)
+        }
+        Val::Bits(bv) => {
+            write_u8(buf, VAL_BITS)?;
+            write_bits(buf, *bv)
+        }
+        Val::MixedBits(segments) => {
+            write_u8(buf, VAL_MIXED_BITS)?;
+            write_usize(buf, segments.len())?;
+            for segment in segments {
+                match segment {
+                    BitsSegment::Symbolic(v) => {
+                        write_bool(buf, false)?;
+                        write_sym(buf, *v)?
+                    }
+                    BitsSegment::Concrete(bv) => {
+                        write_bool(buf, true)?;
+                        write_bits(buf, *bv)?
+                    }
+                }
+            }
+            Ok(())
+        }
+        Val::String(s) => {
+            write_u8(buf, VAL_STRING)?;
+            write_str(buf, s)
+        }
+        Val::Unit => write_u8(buf, VAL_UNIT),
+        Val::Vector(vs) => {
+            write_u8(buf, VAL_VECTOR)?;
+            write_vals(buf, vs)
+        }
+        Val::List(vs) => {
+            write_u8(buf, VAL_LIST)?;
+            write_vals(buf, vs)
+        }
+        Val::Enum(member) => {
+            write_u8(buf, VAL_ENUM)?;
+            write_usize(buf, member.enum_id.to_usize())?;
+            write_usize(buf, member.member)
+        }
+        Val::Struct(fields) => {
+            write_u8(buf, VAL_STRUCT)?;
+            write_fields(buf, fields.iter())
+        }
+        Val::Ctor(ctor, v) => {
+            write_u8(buf, VAL_CTOR)?;
+            write_name(buf, *ctor)?;
+            write_val(buf, v)
+        }
+        Val::SymbolicCtor(v, possibilities) => {
+            write_u8(buf, VAL_SYMBOLIC_CTOR)?;
+            write_sym(buf, *v)?;
+            write_fields(buf, possibilities.iter())
+        }
+        Val::Ref(reg) => {
+            write_u8(buf, VAL_REF)?;
+            write_name(buf, *reg)
+        }
+        Val::Poison => write_u8(buf, VAL_POISON),
+    }
+}
+
+pub(crate) fn write_vals<B: BV>(buf: &mut dyn Write, vs: &[Val<B>]) -> io::Result<()> {
+    write_usize(buf, vs.len())?;
+    for v in vs {
+        write_val(buf, v)?
+    }
+    Ok(())
+}
+
+fn write_fields<'a, B: BV + 'a, I>(buf: &mut dyn Write, fields: I) -> io::Result<()>
+where
+    I: ExactSizeIterator<Item = (&'a Name, &'a Val<B>)>,
+{
+    write_usize(buf, fields.len())?;
+    for (field, v) in fields {
+        write_name(buf, *field)?;
+        write_val(buf, v)?
+    }
+    Ok(())
+}
+
+pub(crate) fn write_opt_val<B: BV>(buf: &mut dyn Write, v: &Option<Val<B>>) -> io::Result<()> {
+    match v {
+        None => write_bool(buf, false),
+        Some(v) => {
+            write_bool(buf, true)?;
+            write_val(buf, v)
+        }
+    }
+}
+
+fn write_accessors(buf: &mut dyn Write, accessors: &[Accessor]) -> io::Result<()> {
+    write_usize(buf, accessors.len())?;
+    for Accessor::Field(field) in accessors {
+        write_name(buf, *field)?
+    }
+    Ok(())
+}
+
+fn write_abstract<B: BV>(
+    buf: &mut dyn Write,
+    tag: u8,
+    name: Name,
+    args: &[Val<B>],
+    return_value: &Val<B>,
+) -> io::Result<()> {
+    write_u8(buf, tag)?;
+    write_name(buf, name)?;
+    write_vals(buf, args)?;
+    write_val(buf, return_value)
+}
+
+/// Write a single event in the binary trace format. This does not
+/// write the [MAGIC] header or any end-of-trace marker, for that use
+/// [BinaryTraceWriter].
+pub fn write_event_binary<B: BV>(buf: &mut dyn Write, event: &Event<B>) -> Result<(), BinaryTraceError> {
+    use Event::*;
+    match event {
+        Smt(def, attrs, info) => {
+            write_u8(buf, TAG_SMT)?;
+            bincode_options().serialize_into(&mut *buf, &(def, attrs, info))?
+        }
+        Fork(n, v, branch, info) => {
+            write_u8(buf, TAG_FORK)?;
+            write_u32(buf, *n)?;
+            write_sym(buf, *v)?;
+            write_u32(buf, *branch)?;
+            bincode_options().serialize_into(&mut *buf, info)?
+        }
+        Function { name, call } => {
+            write_u8(buf, TAG_FUNCTION)?;
+            write_name(buf, *name)?;
+            write_bool(buf, *call)?
+        }
+        Abstract { name, primitive, args, return_value } => {
+            write_abstract(buf, TAG_ABSTRACT, *name, args, return_value)?;
+            write_bool(buf, *primitive)?
+        }
+        ReadReg(name, accessors, v) | WriteReg(name, accessors, v) | AssumeReg(name, accessors, v) => {
+            let tag = match event {
+                ReadReg(..) => TAG_READ_REG,
+                WriteReg(..) => TAG_WRITE_REG,
+                _ => TAG_ASSUME_REG,
+            };
+            write_u8(buf, tag)?;
+            write_name(buf, *name)?;
+            write_accessors(buf, accessors)?;
+            write_val(buf, v)?
+        }
+        ReadMem { value, read_kind, address, bytes, tag_value, opts, region } => {
+            write_u8(buf, TAG_READ_MEM)?;
+            write_val(buf, value)?;
+            write_val(buf, read_kind)?;
+            write_val(buf, address)?;
+            write_u32(buf, *bytes)?;
+            write_opt_val(buf, tag_value)?;
+            write_bool(buf, opts.is_exclusive)?;
+            write_bool(buf, opts.is_ifetch)?;
+            bincode_options().serialize_into(&mut *buf, &opts.kind)?;
+            bincode_options().serialize_into(&mut *buf, &opts.rmw_id)?;
+            write_str(buf, region)?
+        }
+        WriteMem { value, write_kind, address, data, bytes, tag_value, opts, region } => {
+            write_u8(buf, TAG_WRITE_MEM)?;
+            write_sym(buf, *value)?;
+            write_val(buf, write_kind)?;
+            write_val(buf, address)?;
+            write_val(buf, data)?;
+            write_u32(buf, *bytes)?;
+            write_opt_val(buf, tag_value)?;
+            bincode_options().serialize_into(&mut *buf, opts)?;
+            write_str(buf, region)?
+        }
+        MarkReg { regs, mark } => {
+            write_u8(buf, TAG_MARK_REG)?;
+            write_usize(buf, regs.len())?;
+            for reg in regs {
+                write_name(buf, *reg)?
+            }
+            write_str(buf, mark)?
+        }
+        Branch { address } => {
+            write_u8(buf, TAG_BRANCH)?;
+            write_val(buf, address)?
+        }
+        AddressAnnounce { address } => {
+            write_u8(buf, TAG_ADDRESS_ANNOUNCE)?;
+            write_val(buf, address)?
+        }
+        Cycle(n) => {
+            write_u8(buf, TAG_CYCLE)?;
+            write_varint(buf, *n)?
+        }
+        Instr(opcode) => {
+            write_u8(buf, TAG_INSTR)?;
+            write_val(buf, opcode)?
+        }
+        SleepRequest => write_u8(buf, TAG_SLEEP_REQUEST)?,
+        WakeupRequest => write_u8(buf, TAG_WAKEUP_REQUEST)?,
+        Sleeping(v) => {
+            write_u8(buf, TAG_SLEEPING)?;
+            write_sym(buf, *v)?
+        }
+        Interrupt { index, arrival } => {
+            write_u8(buf, TAG_INTERRUPT)?;
+            write_u32(buf, *index)?;
+            write_sym(buf, *arrival)?
+        }
+        Assume(exp) => {
+            write_u8(buf, TAG_ASSUME)?;
+            bincode_options().serialize_into(&mut *buf, exp)?
+        }
+        AssumeFun { name, args, return_value } => write_abstract(buf, TAG_ASSUME_FUN, *name, args, return_value)?,
+        UseFunAssumption { name, args, return_value } => {
+            write_abstract(buf, TAG_USE_FUN_ASSUMPTION, *name, args, return_value)?
+        }
+        Provenance { function, pc } => {
+            write_u8(buf, TAG_PROVENANCE)?;
+            write_name(buf, *function)?;
+            write_u32(buf, *pc)?
+        }
+    }
+    Ok(())
+}
+
+/// Write a single value in the same encoding used for values within
+/// events.
+pub fn write_val_binary<B: BV>(buf: &mut dyn Write, v: &Val<B>) -> Result<(), BinaryTraceError> {
+    write_val(buf, v)?;
+    Ok(())
+}
+
+/// Write a complete trace (without the [MAGIC] header), terminated
+/// by an end-of-trace marker.
+pub fn write_events_binary<B: BV>(buf: &mut dyn Write, events: &[Event<B>]) -> Result<(), BinaryTraceError> {
+    for event in events {
+        write_event_binary(buf, event)?
+    }
+    write_u8(buf, TAG_END)?;
+    Ok(())
+}
+
+/// A streaming writer for binary trace files. The header is written
+/// when the writer is created, after which any number of traces can
+/// be appended.
+pub struct BinaryTraceWriter<W> {
+    out: W,
+}
+
+impl<W: Write> BinaryTraceWriter<W> {
+    pub fn new(mut out: W) -> Result<Self, BinaryTraceError> {
+        out.write_all(MAGIC)?;
+        Ok(BinaryTraceWriter { out })
+    }
+
+    /// Append a single event to the current trace.
+    pub fn write_event<B: BV>(&mut self, event: &Event<B>) -> Result<(), BinaryTraceError> {
+        write_event_binary(&mut self.out, event)
+    }
+
+    /// Finish the current trace, any further events will be part of
+    /// a new trace.
+    pub fn end_trace(&mut self) -> Result<(), BinaryTraceError> {
+        write_u8(&mut self.out, TAG_END)?;
+        Ok(())
+    }
+
+    /// Write a chunk of events continuing the chunk `parent` (if
+    /// any), and flush the underlying writer so the chunk can be
+    /// recovered even if the path it belongs to never finishes. This
+    /// should not be called in the middle of a trace.
+    pub fn write_chunk<B: BV>(
+        &mut self,
+        id: u64,
+        parent: Option<u64>,
+        events: &[&Event<B>],
+    ) -> Result<(), BinaryTraceError> {
+        write_u8(&mut self.out, TAG_CHUNK)?;
+        write_varint(&mut self.out, id)?;
+        write_varint(&mut self.out, parent.map_or(0, |parent| parent + 1))?;
+        for event in events {
+            write_event_binary(&mut self.out, event)?
+        }
+        write_u8(&mut self.out, TAG_END)?;
+        self.flush()
+    }
+
+    /// Start a trace with the events of chunk `id` and its ancestors,
+    /// see [BinaryTraceWriter::write_chunk]. The rest of the trace
+    /// should follow.
+    pub fn write_chunk_ref(&mut self, id: u64) -> Result<(), BinaryTraceError> {
+        write_u8(&mut self.out, TAG_CHUNK_REF)?;
+        write_varint(&mut self.out, id)?;
+        Ok(())
+    }
+
+    /// Append a complete trace.
+    pub fn write_trace<B: BV>(&mut self, events: &[Event<B>]) -> Result<(), BinaryTraceError> {
+        write_events_binary(&mut self.out, events)
+    }
+
+    pub fn flush(&mut self) -> Result<(), BinaryTraceError> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// The names of the memory regions defined in [crate::memory], and
+/// of the page table regions created by isla-axiomatic. Memory events
+/// refer to their region by a `&'static str`, so when decoding we can
+/// only accept these names, rather than leaking a new string for each
+/// unknown name in a (possibly corrupt) trace.
+const REGION_NAMES: &[&str] =
+    &["default", "constrained", "symbolic", "symbolic code", "concrete", "device", "stage 1", "stage 2"];
+
+fn intern_region(name: String) -> Result<&'static str, BinaryTraceError> {
+    REGION_NAMES.iter().find(|known| **known == name).copied().ok_or(BinaryTraceError::UnknownRegion(name))
+}
+
+pub(crate) fn read_u8<R: Read>(input: &mut R) -> Result<u8, BinaryTraceError> {
+    let mut byte = [0; 1];
+    input.read_exact(&mut byte).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            BinaryTraceError::UnexpectedEnd
+        } else {
+            BinaryTraceError::Io(err)
+        }
+    })?;
+    Ok(byte[0])
+}
+
+pub(crate) fn read_bool<R: Read>(input: &mut R) -> Result<bool, BinaryTraceError> {
+    match read_u8(input)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        b => Err(BinaryTraceError::BadTag("boolean", b)),
+    }
+}
+
+pub(crate) fn read_varint<R: Read>(input: &mut R) -> Result<u64, BinaryTraceError> {
+    let mut n: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(input)?;
+        if shift > 63 || (shift == 63 && byte & 0x7E != 0) {
+            return Err(BinaryTraceError::VarintOverflow);
+        }
+        n |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        shift += 7
+    }
+}
+
+pub(crate) fn read_u32<R: Read>(input: &mut R) -> Result<u32, BinaryTraceError> {
+    let n = read_varint(input)?;
+    if n > u64::from(u32::MAX) {
+        Err(BinaryTraceError::VarintOverflow)
+    } else {
+        Ok(n as u32)
+    }
+}
+
+pub(crate) fn read_usize<R: Read>(input: &mut R) -> Result<usize, BinaryTraceError> {
+    let n = read_varint(input)?;
+    if n > usize::MAX as u64 {
+        Err(BinaryTraceError::VarintOverflow)
+    } else {
+        Ok(n as usize)
+    }
+}
+
+fn read_i128<R: Read>(input: &mut R) -> Result<i128, BinaryTraceError> {
+    let mut z: u128 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(input)?;
+        if shift > 127 || (shift == 126 && byte & 0x7C != 0) {
+            return Err(BinaryTraceError::VarintOverflow);
+        }
+        z |= u128::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7
+    }
+    Ok((z >> 1) as i128 ^ -((z & 1) as i128))
+}
+
+pub(crate) fn read_string<R: Read>(input: &mut R) -> Result<String, BinaryTraceError> {
+    let len = read_usize(input)?;
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(BinaryTraceError::UnexpectedEnd);
+    }
+    String::from_utf8(bytes).map_err(|_| BinaryTraceError::BadString)
+}
+
+fn read_sym<R: Read>(input: &mut R) -> Result<Sym, BinaryTraceError> {
+    Ok(Sym::from_u32(read_u32(input)?))
+}
+
+pub(crate) fn read_name<R: Read>(input: &mut R) -> Result<Name, BinaryTraceError> {
+    Ok(Name::from_u32(read_u32(input)?))
+}
+
+fn read_bits<B: BV, R: Read>(input: &mut R) -> Result<B, BinaryTraceError> {
+    let len = read_u32(input)?;
+    if len > B::MAX_WIDTH {
+        return Err(BinaryTraceError::BadBits(len));
+    }
+    if len == 0 {
+        return Ok(B::zeros(0));
+    }
+    let mut chunks = Vec::new();
+    let mut from = 0;
+    while from < len {
+        let chunk_len = std::cmp::min(64, len - from);
+        let mut bytes = [0; 8];
+        input.read_exact(&mut bytes[..(chunk_len as usize + 7) / 8]).map_err(|_| BinaryTraceError::UnexpectedEnd)?;
+        chunks.push(B::new(u64::from_le_bytes(bytes), chunk_len));
+        from += chunk_len
+    }
+    let mut chunks = chunks.into_iter().rev();
+    let mut bv = chunks.next().unwrap();
+    for chunk in chunks {
+        bv = bv.append(chunk).ok_or(BinaryTraceError::BadBits(len))?
+    }
+    Ok(bv)
+}
+
+fn read_val<B: BV, R: Read>(input: &mut R) -> Result<Val<B>, BinaryTraceError> {
+    Ok(match read_u8(input)? {
+        VAL_SYMBOLIC => Val::Symbolic(read_sym(input)?),
+        VAL_I64 => Val::I64(read_i128(input)? as i64),
+        VAL_I128 => Val::I128(read_i128(input)?),
+        VAL_BOOL => Val::Bool(read_bool(input)?),
+        VAL_BITS => Val::Bits(read_bits(input)?),
+        VAL_MIXED_BITS => {
+            let len = read_usize(input)?;
+            let mut segments = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+            for _ in 0..len {
+                if read_bool(input)? {
+                    segments.push(BitsSegment::Concrete(read_bits(input)?))
+                } else {
+                    segments.push(BitsSegment::Symbolic(read_sym(input)?))
+                }
+            }
+            Val::MixedBits(segments)
+        }
+        VAL_STRING => Val::String(read_string(input)?),
+        VAL_UNIT => Val::Unit,
+        VAL_VECTOR => Val::Vector(read_vals(input)?),
+        VAL_LIST => Val::List(read_vals(input)?),
+        VAL_ENUM => {
+            let enum_id = EnumId::from_usize(read_usize(input)?);
+            Val::Enum(EnumMember { enum_id, member: read_usize(input)? })
+        }
+        VAL_STRUCT => Val::Struct(read_fields(input)?.into_iter().collect()),
+        VAL_CTOR => {
+            let ctor = read_name(input)?;
+            Val::Ctor(ctor, Box::new(read_val(input)?))
+        }
+        VAL_SYMBOLIC_CTOR => {
+            let v = read_sym(input)?;
+            Val::SymbolicCtor(v, read_fields(input)?.into_iter().collect())
+        }
+        VAL_REF => Val::Ref(read_name(input)?),
+        VAL_POISON => Val::Poison,
+        tag => return Err(BinaryTraceError::BadTag("value", tag)),
+    })
+}
+
+pub(crate) fn read_vals<B: BV, R: Read>(input: &mut R) -> Result<Vec<Val<B>>, BinaryTraceError> {
+    let len = read_usize(input)?;
+    let mut vs = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+    for _ in 0..len {
+        vs.push(read_val(input)?)
+    }
+    Ok(vs)
+}
+
+fn read_fields<B: BV, R: Read>(input: &mut R) -> Result<Vec<(Name, Val<B>)>, BinaryTraceError> {
+    let len = read_usize(input)?;
+    let mut fields = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+    for _ in 0..len {
+        let field = read_name(input)?;
+        fields.push((field, read_val(input)?))
+    }
+    Ok(fields)
+}
+
+pub(crate) fn read_opt_val<B: BV, R: Read>(input: &mut R) -> Result<Option<Val<B>>, BinaryTraceError> {
+    if read_bool(input)? {
+        Ok(Some(read_val(input)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_accessors<R: Read>(input: &mut R) -> Result<Vec<Accessor>, BinaryTraceError> {
+    let len = read_usize(input)?;
+    let mut accessors = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+    for _ in 0..len {
+        accessors.push(Accessor::Field(read_name(input)?))
+    }
+    Ok(accessors)
+}
+
+/// Read a single value written by [write_val_binary].
+pub fn read_val_binary<B: BV, R: Read>(input: &mut R) -> Result<Val<B>, BinaryTraceError> {
+    read_val(input)
+}
+
+enum Item<B> {
+    Event(Event<B>),
+    ChunkRef(u64),
+    End,
+}
+
+/// Read a single event, returning `None` if we reach the end of the
+/// current trace. References to chunks are not resolved, and are
+/// reported as an error.
+pub fn read_event_binary<B: BV, R: Read>(input: &mut R) -> Result<Option<Event<B>>, BinaryTraceError> {
+    match read_item(input)? {
+        Item::Event(event) => Ok(Some(event)),
+        Item::ChunkRef(_) => Err(BinaryTraceError::BadTag("event", TAG_CHUNK_REF)),
+        Item::End => Ok(None),
+    }
+}
+
+fn read_item<B: BV, R: Read>(input: &mut R) -> Result<Item<B>, BinaryTraceError> {
+    use Event::*;
+    Ok(Item::Event(match read_u8(input)? {
+        TAG_END => return Ok(Item::End),
+        TAG_CHUNK_REF => return Ok(Item::ChunkRef(read_varint(input)?)),
+        TAG_SMT => {
+            let (def, attrs, info) = bincode_options().deserialize_from(&mut *input)?;
+            Smt(def, attrs, info)
+        }
+        TAG_FORK => {
+            let n = read_u32(input)?;
+            let v = read_sym(input)?;
+            let branch = read_u32(input)?;
+            Fork(n, v, branch, bincode_options().deserialize_from(&mut *input)?)
+        }
+        TAG_FUNCTION => Function { name: read_name(input)?, call: read_bool(input)? },
+        TAG_ABSTRACT => {
+            let name = read_name(input)?;
+            let args = read_vals(input)?;
+            let return_value = read_val(input)?;
+            Abstract { name, primitive: read_bool(input)?, args, return_value }
+        }
+        tag @ (TAG_READ_REG | TAG_WRITE_REG | TAG_ASSUME_REG) => {
+            let name = read_name(input)?;
+            let accessors = read_accessors(input)?;
+            let v = read_val(input)?;
+            match tag {
+                TAG_READ_REG => ReadReg(name, accessors, v),
+                TAG_WRITE_REG => WriteReg(name, accessors, v),
+                _ => AssumeReg(name, accessors, v),
+            }
+        }
+        TAG_READ_MEM => {
+            let value = read_val(input)?;
+            let read_kind = read_val(input)?;
+            let address = read_val(input)?;
+            let bytes = read_u32(input)?;
+            let tag_value = read_opt_val(input)?;
+            let is_exclusive = read_bool(input)?;
+            let is_ifetch = read_bool(input)?;
+            let kind = bincode_options().deserialize_from(&mut *input)?;
+            let rmw_id = bincode_options().deserialize_from(&mut *input)?;
+            let opts = ReadOpts { is_exclusive, is_ifetch, kind, rmw_id };
+            let region = intern_region(read_string(input)?)?;
+            ReadMem { value, read_kind, address, bytes, tag_value, opts, region }
+        }
+        TAG_WRITE_MEM => {
+            let value = read_sym(input)?;
+            let write_kind = read_val(input)?;
+            let address = read_val(input)?;
+            let data = read_val(input)?;
+            let bytes = read_u32(input)?;
+            let tag_value = read_opt_val(input)?;
+            let opts = bincode_options().deserialize_from(&mut *input)?;
+            let region = intern_region(read_string(input)?)?;
+            WriteMem { value, write_kind, address, data, bytes, tag_value, opts, region }
+        }
+        TAG_MARK_REG => {
+            let len = read_usize(input)?;
+            let mut regs = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+            for _ in 0..len {
+                regs.push(read_name(input)?)
+            }
+            MarkReg { regs, mark: read_string(input)? }
+        }
+        TAG_BRANCH => Branch { address: read_val(input)? },
+        TAG_ADDRESS_ANNOUNCE => AddressAnnounce { address: read_val(input)? },
+        TAG_CYCLE => Cycle(read_varint(input)?),
+        TAG_INSTR => Instr(read_val(input)?),
+        TAG_SLEEP_REQUEST => SleepRequest,
+        TAG_WAKEUP_REQUEST => WakeupRequest,
+        TAG_SLEEPING => Sleeping(read_sym(input)?),
+        TAG_INTERRUPT => Interrupt { index: read_u32(input)?, arrival: read_sym(input)? },
+        TAG_ASSUME => Assume(bincode_options().deserialize_from(&mut *input)?),
+        tag @ (TAG_ASSUME_FUN | TAG_USE_FUN_ASSUMPTION) => {
+            let name = read_name(input)?;
+            let args = read_vals(input)?;
+            let return_value = read_val(input)?;
+            if tag == TAG_ASSUME_FUN {
+                AssumeFun { name, args, return_value }
+            } else {
+                UseFunAssumption { name, args, return_value }
+            }
+        }
+        TAG_PROVENANCE => Provenance { function: read_name(input)?, pc: read_u32(input)? },
+        tag => return Err(BinaryTraceError::BadTag("event", tag)),
+    }))
+}
+
+/// True if an error was caused by the input ending unexpectedly, as
+/// it will if the process writing a trace was killed.
+fn is_truncation(err: &BinaryTraceError) -> bool {
+    match err {
+        BinaryTraceError::UnexpectedEnd => true,
+        BinaryTraceError::Io(err) => err.kind() == io::ErrorKind::UnexpectedEof,
+        BinaryTraceError::Bincode(err) => {
+            matches!(&**err, bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof)
+        }
+        _ => false,
+    }
+}
+
+struct Chunk<B> {
+    parent: Option<u64>,
+    events: Vec<Event<B>>,
+    /// True if a trace or another chunk continues this one
+    continued: bool,
+}
+
+/// The events of chunk `id` and all its ancestors, oldest first
+fn chunk_events<B: Clone>(chunks: &HashMap<u64, Chunk<B>>, id: u64) -> Result<Vec<Event<B>>, BinaryTraceError> {
+    let mut chain = Vec::new();
+    let mut next = Some(id);
+    while let Some(id) = next {
+        let chunk = chunks.get(&id).ok_or(BinaryTraceError::UnknownChunk(id))?;
+        chain.push(&chunk.events);
+        next = chunk.parent
+    }
+    Ok(chain.into_iter().rev().flatten().cloned().collect())
+}
+
+/// A streaming reader for binary trace files, which yields each
+/// trace in the file in turn. Chunks written while paths were
+/// running are kept in memory, as any later trace may refer to them.
+pub struct BinaryTraceReader<R, B> {
+    input: R,
+    recover: bool,
+    chunks: HashMap<u64, Chunk<B>>,
+    /// Chains of chunks which no trace refers to, returned at the end
+    /// of the input when recovering
+    unfinished: Option<Vec<u64>>,
+    _bv: PhantomData<B>,
+}
+
+impl<R: Read, B: BV> BinaryTraceReader<R, B> {
+    pub fn new(mut input: R) -> Result<Self, BinaryTraceError> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic).map_err(|_| BinaryTraceError::BadMagic)?;
+        if !is_binary_trace(&magic) {
+            return Err(BinaryTraceError::BadMagic);
+        }
+        Ok(BinaryTraceReader { input, recover: false, chunks: HashMap::new(), unfinished: None, _bv: PhantomData })
+    }
+
+    /// If the file ends part way through a record, ignore that record
+    /// rather than returning an error. At the end of the file, return
+    /// the events written for each path which never finished, from
+    /// the chunks which no trace refers to.
+    pub fn recovering(self) -> Self {
+        BinaryTraceReader { recover: true, ..self }
+    }
+
+    /// Read the next event in the current trace, returning `None` at
+    /// the end of each trace.
+    pub fn read_event(&mut self) -> Result<Option<Event<B>>, BinaryTraceError> {
+        read_event_binary(&mut self.input)
+    }
+
+    fn read_chunk(&mut self) -> Result<(), BinaryTraceError> {
+        let id = read_varint(&mut self.input)?;
+        let parent = read_varint(&mut self.input)?.checked_sub(1);
+        let mut events = Vec::new();
+        while let Some(event) = read_event_binary(&mut self.input)? {
+            events.push(event)
+        }
+        if let Some(parent) = parent {
+            self.chunks.get_mut(&parent).ok_or(BinaryTraceError::UnknownChunk(parent))?.continued = true
+        }
+        self.chunks.insert(id, Chunk { parent, events, continued: false });
+        Ok(())
+    }
+
+    fn read_record(&mut self, tag: u8) -> Result<Option<Vec<Event<B>>>, BinaryTraceError> {
+        if tag == TAG_CHUNK {
+            self.read_chunk()?;
+            return Ok(None);
+        }
+        let mut events = Vec::new();
+        let mut rest = (&[tag][..]).chain(&mut self.input);
+        loop {
+            match read_item(&mut rest)? {
+                Item::Event(event) => events.push(event),
+                Item::ChunkRef(id) if events.is_empty() => {
+                    events = chunk_events(&self.chunks, id)?;
+                    self.chunks.get_mut(&id).unwrap().continued = true
+                }
+                Item::ChunkRef(_) => return Err(BinaryTraceError::BadTag("event", TAG_CHUNK_REF)),
+                Item::End => return Ok(Some(events)),
+            }
+        }
+    }
+
+    /// Returns the next complete trace, or `None` if there are no
+    /// more traces in the file.
+    pub fn read_trace(&mut self) -> Result<Option<Vec<Event<B>>>, BinaryTraceError> {
+        while self.unfinished.is_none() {
+            let mut byte = [0; 1];
+            if self.input.read(&mut byte)? == 0 {
+                break;
+            }
+            match self.read_record(byte[0]) {
+                Ok(Some(events)) => return Ok(Some(events)),
+                Ok(None) => (),
+                Err(err) if self.recover && is_truncation(&err) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !self.recover {
+            return Ok(None);
+        }
+        let unfinished = self.unfinished.get_or_insert_with(|| {
+            let mut ids: Vec<u64> =
+                self.chunks.iter().filter_map(|(id, chunk)| if chunk.continued { None } else { Some(*id) }).collect();
+            ids.sort_unstable_by(|a, b| b.cmp(a));
+            ids
+        });
+        match unfinished.pop() {
+            Some(id) => chunk_events(&self.chunks, id).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: Read, B: BV> Iterator for BinaryTraceReader<R, B> {
+    type Item = Result<Vec<Event<B>>, BinaryTraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_trace().transpose()
+    }
+}
+
+/// The result of a single path, either a value or an error message,
+/// along with the trace for that path. This is the subset of a
+/// [crate::executor::path_result::PathResult] which can be encoded,
+/// used to send results between processes and to save them in
+/// checkpoints (see [crate::checkpoint]).
+pub type TracedResult<B> = Result<(Val<B>, Vec<Event<B>>), (String, Vec<Event<B>>)>;
+
+pub(crate) fn write_result<B: BV>(out: &mut dyn Write, result: &TracedResult<B>) -> Result<(), BinaryTraceError> {
+    match result {
+        Ok((val, events)) => {
+            write_u8(out, RESULT_OK)?;
+            write_val_binary(out, val)?;
+            write_events_binary(out, events)
+        }
+        Err((msg, events)) => {
+            write_u8(out, RESULT_ERR)?;
+            write_str(out, msg)?;
+            write_events_binary(out, events)
+        }
+    }
+}
+
+fn read_events<B: BV, R: Read>(input: &mut R) -> Result<Vec<Event<B>>, BinaryTraceError> {
+    let mut events = Vec::new();
+    while let Some(event) = read_event_binary(input)? {
+        events.push(event)
+    }
+    Ok(events)
+}
+
+/// Read the next result, returning `None` at the end of the input.
+pub(crate) fn read_result<B: BV, R: Read>(input: &mut R) -> Result<Option<TracedResult<B>>, BinaryTraceError> {
+    let mut byte = [0; 1];
+    if input.read(&mut byte)? == 0 {
+        return Ok(None);
+    }
+    let mut rest = (&byte[..]).chain(input);
+    match read_u8(&mut rest)? {
+        RESULT_OK => {
+            let val = read_val_binary(&mut rest)?;
+            Ok(Some(Ok((val, read_events(&mut rest)?))))
+        }
+        RESULT_ERR => {
+            let msg = read_string(&mut rest)?;
+            Ok(Some(Err((msg, read_events(&mut rest)?))))
+        }
+        _ => Err(BinaryTraceError::UnexpectedEnd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b129::B129;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::{Def, Exp, Ty};
+    use crate::smt::{DefAttrs, MemKind, WriteOpts};
+    use crate::source_loc::SourceLoc;
+
+    fn roundtrip<B: BV>(events: &[Event<B>]) -> Vec<Event<B>> {
+        let mut writer = BinaryTraceWriter::new(Vec::new()).unwrap();
+        writer.write_trace(events).unwrap();
+        writer.write_trace(events).unwrap();
+        let bytes = writer.into_inner();
+        assert!(is_binary_trace(&bytes));
+        let traces: Vec<_> = BinaryTraceReader::new(&bytes[..]).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(traces.len(), 2);
+        traces.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn varints() {
+        for n in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, n).unwrap();
+            assert_eq!(read_varint(&mut &buf[..]).unwrap(), n)
+        }
+        for n in [0, -1, 1, -64, 64, i128::MIN, i128::MAX] {
+            let mut buf = Vec::new();
+            write_i128(&mut buf, n).unwrap();
+            assert_eq!(read_i128(&mut &buf[..]).unwrap(), n)
+        }
+    }
+
+    #[test]
+    fn huge_lengths() {
+        for tag in [VAL_MIXED_BITS, VAL_VECTOR, VAL_LIST, VAL_STRUCT] {
+            let mut buf = vec![tag];
+            write_varint(&mut buf, u64::MAX).unwrap();
+            assert!(matches!(read_val::<B64, _>(&mut &buf[..]), Err(BinaryTraceError::UnexpectedEnd)))
+        }
+        // The accessors of a register read, and the registers of a
+        // register mark
+        for prefix in [&[TAG_READ_REG, 0][..], &[TAG_MARK_REG][..]] {
+            let mut buf = prefix.to_vec();
+            write_varint(&mut buf, u64::MAX).unwrap();
+            assert!(matches!(read_event_binary::<B64, _>(&mut &buf[..]), Err(BinaryTraceError::UnexpectedEnd)))
+        }
+    }
+
+    #[test]
+    fn bits() {
+        for bv in [B129::zeros(0), B129::new(0xAB, 5), B129::new(u64::MAX, 64), B129::ones(129), B129::new(7, 100)] {
+            let mut buf = Vec::new();
+            write_bits(&mut buf, bv).unwrap();
+            assert_eq!(read_bits::<B129, _>(&mut &buf[..]).unwrap(), bv)
+        }
+        let mut buf = Vec::new();
+        write_bits(&mut buf, B64::new(0xDEAD_BEEF, 64)).unwrap();
+        assert_eq!(read_bits::<B64, _>(&mut &buf[..]).unwrap(), B64::new(0xDEAD_BEEF, 64))
+    }
+
+    #[test]
+    fn events() {
+        let v = Sym::from_u32(3);
+        let events: Vec<Event<B64>> = vec![
+            Event::Smt(Def::DeclareConst(v, Ty::BitVec(64)), DefAttrs::default(), SourceLoc::unknown()),
+            Event::Smt(
+                Def::Assert(Exp::Eq(Box::new(Exp::Var(v)), Box::new(Exp::Bits64(B64::new(1, 64))))),
+                DefAttrs::uninteresting(),
+                SourceLoc::unknown(),
+            ),
+            Event::ReadReg(Name::from_u32(12), vec![Accessor::Field(Name::from_u32(2))], Val::Symbolic(v)),
+            Event::ReadMem {
+                value: Val::Bits(B64::new(0x1234, 32)),
+                read_kind: Val::Enum(EnumMember { enum_id: EnumId::from_usize(1), member: 4 }),
+                address: Val::MixedBits(vec![BitsSegment::Symbolic(v), BitsSegment::Concrete(B64::new(0, 12))]),
+                bytes: 4,
+                tag_value: None,
+                opts: ReadOpts {
+                    kind: MemKind { acquire: true, rmw: true, size_class: Some(4), ..MemKind::default() },
+                    rmw_id: Some(3),
+                    ..ReadOpts::ifetch()
+                },
+                region: "stage 1",
+            },
+            Event::WriteMem {
+                value: v,
+                write_kind: Val::Unit,
+                address: Val::I128(-4),
+                data: Val::Vector(vec![Val::Bool(true), Val::String("x".to_string())]),
+                bytes: 8,
+                tag_value: Some(Val::Bool(false)),
+                opts: WriteOpts::exclusive(),
+                region: "default",
+            },
+            Event::Cycle(3),
+            Event::SleepRequest,
+            Event::Sleeping(v),
+            Event::WakeupRequest,
+            Event::Interrupt { index: 2, arrival: v },
+            Event::Function { name: Name::from_u32(7), call: false },
+            Event::Provenance { function: Name::from_u32(9), pc: 42 },
+        ];
+        let decoded = roundtrip(&events);
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", events))
+    }
+
+    #[test]
+    fn unknown_region() {
+        let event: Event<B64> = Event::WriteMem {
+            value: Sym::from_u32(0),
+            write_kind: Val::Unit,
+            address: Val::I128(0),
+            data: Val::Unit,
+            bytes: 1,
+            tag_value: None,
+            opts: WriteOpts::default(),
+            region: "unknown",
+        };
+        let mut buf = Vec::new();
+        write_event_binary(&mut buf, &event).unwrap();
+        assert!(matches!(
+            read_event_binary::<B64, _>(&mut &buf[..]),
+            Err(BinaryTraceError::UnknownRegion(name)) if name == "unknown"
+        ))
+    }
+
+    #[test]
+    fn forked_chunks() {
+        let events: Vec<Event<B64>> = vec![Event::Cycle(0), Event::Instr(Val::Bits(B64::new(0xd503201f, 32)))];
+        let refs: Vec<&Event<B64>> = events.iter().collect();
+        let mut writer = BinaryTraceWriter::new(Vec::new()).unwrap();
+        writer.write_chunk(0, None, &refs).unwrap();
+        // Two paths fork after the first chunk, and each writes another
+        writer.write_chunk(1, Some(0), &refs[..1]).unwrap();
+        writer.write_chunk(2, Some(0), &refs[1..]).unwrap();
+        writer.write_chunk_ref(2).unwrap();
+        writer.write_trace(&[Event::<B64>::Cycle(1)]).unwrap();
+        writer.write_chunk_ref(1).unwrap();
+        writer.write_trace(&[Event::<B64>::Cycle(2)]).unwrap();
+        writer.write_trace(&events).unwrap();
+        let bytes = writer.into_inner();
+
+        let traces: Vec<_> = BinaryTraceReader::<_, B64>::new(&bytes[..]).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(traces.len(), 3);
+        assert!(matches!(traces[0].as_slice(), [Event::Cycle(0), Event::Instr(_), Event::Instr(_), Event::Cycle(1)]));
+        assert!(matches!(traces[1].as_slice(), [Event::Cycle(0), Event::Instr(_), Event::Cycle(0), Event::Cycle(2)]));
+        assert_eq!(traces[2].len(), 2)
+    }
+
+    #[test]
+    fn recover_truncated_trace() {
+        let events: Vec<Event<B64>> = vec![Event::Cycle(0), Event::Instr(Val::Bits(B64::new(0xd503201f, 32)))];
+        let refs: Vec<&Event<B64>> = events.iter().collect();
+        let mut writer = BinaryTraceWriter::new(Vec::new()).unwrap();
+        writer.write_chunk(0, None, &refs).unwrap();
+        writer.write_chunk(1, Some(0), &refs).unwrap();
+        writer.write_chunk(2, Some(0), &refs).unwrap();
+        writer.write_chunk_ref(1).unwrap();
+        writer.write_trace(&events).unwrap();
+        writer.write_chunk(3, Some(2), &refs).unwrap();
+        let mut bytes = writer.into_inner();
+        bytes.pop();
+
+        assert!(BinaryTraceReader::<_, B64>::new(&bytes[..]).unwrap().collect::<Result<Vec<_>, _>>().is_err());
+
+        // The path which wrote chunk 1 finished, but the one which
+        // wrote chunk 2 did not, and its last chunk was cut short
+        let traces: Vec<_> =
+            BinaryTraceReader::<_, B64>::new(&bytes[..]).unwrap().recovering().collect::<Result<_, _>>().unwrap();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].len(), 6);
+        assert_eq!(traces[1].len(), 4)
+    }
+
+    #[test]
+    fn result_round_trip() {
+        let results: Vec<TracedResult<B64>> = vec![
+            Ok((Val::Bits(B64::new(0xAB, 8)), vec![Event::Instr(Val::Symbolic(Sym::from_u32(3)))])),
+            Err(("Error Unreachable".to_string(), vec![])),
+        ];
+        let mut buf = Vec::new();
+        for result in &results {
+            write_result(&mut buf, result).unwrap()
+        }
+        let mut input = &buf[..];
+        match read_result::<B64, _>(&mut input).unwrap() {
+            Some(Ok((Val::Bits(bv), events))) => {
+                assert_eq!(bv, B64::new(0xAB, 8));
+                assert_eq!(events.len(), 1)
+            }
+            _ => panic!("expected a value"),
+        }
+        match read_result::<B64, _>(&mut input).unwrap() {
+            Some(Err((msg, events))) => {
+                assert_eq!(msg, "Error Unreachable");
+                assert!(events.is_empty())
+            }
+            _ => panic!("expected an error"),
+        }
+        assert!(read_result::<B64, _>(&mut input).unwrap().is_none())
+    }
+}
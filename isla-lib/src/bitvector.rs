@@ -75,6 +75,54 @@ macro_rules! write_bits {
 pub mod b129;
 pub mod b64;
 
+/// Proptest strategies for generating concrete bitvectors of any type
+/// implementing [BV]. Enabled for the tests of this crate, and for
+/// other crates via the `proptest` feature.
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy {
+    use proptest::prelude::*;
+    use std::ops::RangeInclusive;
+
+    use super::BV;
+
+    fn from_bools<B: BV>(bools: Vec<bool>) -> B {
+        let mut bv = B::zeros(bools.len() as u32);
+        for (n, b) in bools.iter().enumerate() {
+            if *b {
+                bv = bv.set_slice(n as u32, B::BIT_ONE)
+            }
+        }
+        bv
+    }
+
+    /// Generate a bitvector with exactly `len` bits.
+    pub fn bits_of_len<B: BV>(len: u32) -> impl Strategy<Value = B> {
+        prop::collection::vec(any::<bool>(), len as usize).prop_map(from_bools)
+    }
+
+    /// Generate a bitvector with a length drawn from `lens`.
+    pub fn bits<B: BV>(lens: RangeInclusive<u32>) -> impl Strategy<Value = B> {
+        lens.prop_flat_map(bits_of_len)
+    }
+
+    /// Generate a pair of bitvectors that have the same length, as
+    /// required by most binary bitvector operations.
+    pub fn bits_pair<B: BV>(lens: RangeInclusive<u32>) -> impl Strategy<Value = (B, B)> {
+        lens.prop_flat_map(|len| (bits_of_len(len), bits_of_len(len)))
+    }
+
+    /// Generate a pair of bitvectors whose lengths are drawn
+    /// independently from `lhs_lens` and `rhs_lens`, for operations
+    /// such as `append` and shifts whose operands can have different
+    /// lengths.
+    pub fn bits_pair_of_lens<B: BV>(
+        lhs_lens: RangeInclusive<u32>,
+        rhs_lens: RangeInclusive<u32>,
+    ) -> impl Strategy<Value = (B, B)> {
+        (bits(lhs_lens), bits(rhs_lens))
+    }
+}
+
 /// If we are indexing a vector of length `n`, we require this many
 /// bits to represent all possible indices.
 pub fn required_index_bits(n: usize) -> u32 {
@@ -141,6 +189,87 @@ pub fn bit_vector_from_str(s: &str) -> Option<Vec<bool>> {
     }
 }
 
+/// Read a vector of bits from a string as [bit_vector_from_str], but
+/// additionally allowing an explicit width. The width can either be
+/// given as a suffix, e.g. `0x1:64` or `#b101:8`, or using the SMTLIB
+/// indexed form `(_ bv5 8)` where the value is a decimal number. The
+/// literal is zero-extended to the given width, and parsing fails if
+/// it has any set bits that do not fit within the width.
+pub fn bit_vector_from_str_sized(s: &str) -> Option<Vec<bool>> {
+    let s = s.trim();
+
+    let (mut bits, width) = if let Some(indexed) = s.strip_prefix("(_").and_then(|s| s.strip_suffix(')')) {
+        let mut words = indexed.split_whitespace();
+        let value = words.next()?.strip_prefix("bv")?.parse::<u128>().ok()?;
+        let width = words.next()?.parse::<usize>().ok()?;
+        if words.next().is_some() {
+            return None;
+        }
+        ((0..128).map(|i| (value >> i) & 1 == 1).collect::<Vec<_>>(), width)
+    } else if let Some((literal, width)) = s.rsplit_once(':') {
+        (bit_vector_from_str(literal.trim())?, width.trim().parse::<usize>().ok()?)
+    } else {
+        return bit_vector_from_str(s);
+    };
+
+    if bits.iter().skip(width).any(|bit| *bit) {
+        return None;
+    }
+    bits.resize(width, false);
+    Some(bits)
+}
+
+/// The available styles for displaying a concrete bitvector, see [BV::display_as]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitsFormat {
+    /// SMTLIB style, `#x` if the length is a multiple of four,
+    /// otherwise `#b`. This is the same as the `Display` instance.
+    Smt,
+    /// Hexadecimal with a `0x` prefix. If the length is not a
+    /// multiple of four then the leading digit is padded, and the
+    /// width is given explicitly as a `:<width>` suffix, so the
+    /// output can always be read by [bit_vector_from_str_sized].
+    Hex,
+    /// Binary with a `0b` prefix
+    Bin,
+}
+
+/// A wrapper for displaying a bitvector with a specified [BitsFormat]
+pub struct DisplayBits<B> {
+    bits: B,
+    format: BitsFormat,
+}
+
+impl<B: BV> fmt::Display for DisplayBits<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = self.bits.to_vec();
+        match self.format {
+            BitsFormat::Smt => write!(f, "{}", self.bits),
+            BitsFormat::Hex => {
+                write!(f, "0x")?;
+                for i in (0..(bits.len() + 3) / 4).rev() {
+                    let mut digit = 0;
+                    for j in (0..4).rev() {
+                        digit = (digit << 1) | (*bits.get(i * 4 + j).unwrap_or(&false) as u32)
+                    }
+                    write!(f, "{:x}", digit)?
+                }
+                if bits.len() % 4 != 0 {
+                    write!(f, ":{}", bits.len())?
+                }
+                Ok(())
+            }
+            BitsFormat::Bin => {
+                write!(f, "0b")?;
+                for bit in bits.iter().rev() {
+                    write!(f, "{}", if *bit { '1' } else { '0' })?
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 /// Each concrete bitvector type has a maximum length, so when we read
 /// a bitvector from a string we may not be able to fit it inside our
 /// type. This enumeration allows for a fallback using a vector of
@@ -238,6 +367,29 @@ where
     /// `None` if the string is not parseable for any reason
     fn from_str(s: &str) -> Option<Self>;
 
+    /// Parses a bitvector like [BV::from_str], but additionally
+    /// allowing an explicit width as described in
+    /// [bit_vector_from_str_sized]. Returns `None` if the width is
+    /// greater than `MAX_WIDTH`.
+    fn from_str_sized(s: &str) -> Option<Self> {
+        let bits = bit_vector_from_str_sized(s)?;
+        if bits.len() > Self::MAX_WIDTH as usize {
+            return None;
+        }
+        let mut bv = Self::zeros(bits.len() as u32);
+        for (n, bit) in bits.iter().enumerate() {
+            if *bit {
+                bv = bv.set_slice(n as u32, Self::BIT_ONE)
+            }
+        }
+        Some(bv)
+    }
+
+    /// Display a bitvector using the given format
+    fn display_as(self, format: BitsFormat) -> DisplayBits<Self> {
+        DisplayBits { bits: self, format }
+    }
+
     fn from_str_long(s: &str) -> Option<ParsedBits<Self>> {
         if let Some(bv) = Self::from_str(s) {
             Some(ParsedBits::Short(bv))
@@ -465,4 +617,37 @@ mod tests {
         assert_eq!(bit_vector_from_str("0xABG"), None);
         assert_eq!(bit_vector_from_str(""), None);
     }
+
+    #[test]
+    fn test_bit_vector_from_str_sized() {
+        assert_eq!(bit_vector_from_str_sized("0x2"), Some(vec![false, true, false, false]));
+        assert_eq!(bit_vector_from_str_sized("0x2:6"), Some(vec![false, true, false, false, false, false]));
+        assert_eq!(bit_vector_from_str_sized("#b101:3"), Some(vec![true, false, true]));
+        assert_eq!(bit_vector_from_str_sized("0x02:3"), Some(vec![false, true, false]));
+        assert_eq!(bit_vector_from_str_sized("0xF:3"), None);
+        assert_eq!(bit_vector_from_str_sized("(_ bv5 4)"), Some(vec![true, false, true, false]));
+        assert_eq!(bit_vector_from_str_sized("(_ bv16 4)"), None);
+        assert_eq!(bit_vector_from_str_sized("(_ bv5)"), None);
+        assert_eq!(bit_vector_from_str_sized("0x1:"), None);
+    }
+
+    #[test]
+    fn test_from_str_sized() {
+        use b64::B64;
+        assert_eq!(B64::from_str_sized("0x1:64"), Some(B64::new(1, 64)));
+        assert_eq!(B64::from_str_sized("(_ bv255 8)"), Some(B64::new(0xFF, 8)));
+        assert_eq!(B64::from_str_sized("#b1"), Some(B64::new(1, 1)));
+        assert_eq!(B64::from_str_sized("0x1:65"), None);
+    }
+
+    #[test]
+    fn test_display_as() {
+        use b64::B64;
+        assert_eq!(format!("{}", B64::new(0b101, 3).display_as(BitsFormat::Smt)), "#b101");
+        assert_eq!(format!("{}", B64::new(0b101, 3).display_as(BitsFormat::Hex)), "0x5:3");
+        assert_eq!(format!("{}", B64::new(0xAB, 8).display_as(BitsFormat::Hex)), "0xab");
+        assert_eq!(format!("{}", B64::new(0xAB, 8).display_as(BitsFormat::Bin)), "0b10101011");
+        let bv = B64::new(0x1F, 7);
+        assert_eq!(B64::from_str_sized(&format!("{}", bv.display_as(BitsFormat::Hex))), Some(bv));
+    }
 }
@@ -403,6 +403,19 @@ impl BV for B129 {
     }
 }
 
+#[cfg(any(test, feature = "proptest"))]
+impl proptest::arbitrary::Arbitrary for B129 {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        use proptest::prelude::*;
+        (0..=129u32, any::<u128>(), any::<bool>())
+            .prop_map(|(len, bits, tag)| B129 { len, tag: tag && len == 129, bits: bzhi_u128(bits, len) })
+            .boxed()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
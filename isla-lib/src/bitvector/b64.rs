@@ -312,6 +312,17 @@ impl BV for B64 {
     }
 }
 
+#[cfg(any(test, feature = "proptest"))]
+impl proptest::arbitrary::Arbitrary for B64 {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        use proptest::prelude::*;
+        (0..=64u32, any::<u64>()).prop_map(|(len, bits)| B64 { len, bits: bzhi_u64(bits, len) }).boxed()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
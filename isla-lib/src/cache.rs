@@ -30,8 +30,9 @@
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub trait Cachekey {
     fn key(&self) -> String;
@@ -63,3 +64,99 @@ pub trait Cacheable: Serialize + DeserializeOwned {
         }
     }
 }
+
+/// A cache with two tiers: a bounded in-memory cache which evicts the
+/// least recently used entry when full, in front of an optional
+/// directory on disk (see [Cacheable]) which persists between runs.
+/// Values found on disk are promoted into memory.
+pub struct TieredCache<V> {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<String, (u64, V)>,
+    dir: Option<PathBuf>,
+}
+
+impl<V: Cacheable + Clone> TieredCache<V> {
+    /// Create a cache holding at most `capacity` values in memory
+    /// (so a capacity of zero disables the memory tier), and using
+    /// `dir` as the disk tier if given.
+    pub fn new(capacity: usize, dir: Option<PathBuf>) -> Self {
+        TieredCache { capacity, clock: 0, entries: HashMap::new(), dir }
+    }
+
+    pub fn get(&mut self, key: V::Key) -> Option<V> {
+        let name = key.key();
+        self.clock += 1;
+        if let Some((used, value)) = self.entries.get_mut(&name) {
+            *used = self.clock;
+            return Some(value.clone());
+        }
+        let value = V::from_cache(key, self.dir.as_ref()?)?;
+        self.insert_memory(name, value.clone());
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: V::Key, value: V) {
+        let name = key.key();
+        if let Some(dir) = &self.dir {
+            value.cache(key, dir)
+        }
+        self.clock += 1;
+        self.insert_memory(name, value)
+    }
+
+    fn insert_memory(&mut self, name: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&name) {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, (used, _))| *used).map(|(k, _)| k.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(name, (self.clock, value));
+    }
+
+    /// The number of values held in memory
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Key(u32);
+
+    impl Cachekey for Key {
+        fn key(&self) -> String {
+            format!("test_{}", self.0)
+        }
+    }
+
+    impl Cacheable for String {
+        type Key = Key;
+    }
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let mut cache: TieredCache<String> = TieredCache::new(2, None);
+        cache.insert(Key(1), "one".to_string());
+        cache.insert(Key(2), "two".to_string());
+        assert_eq!(cache.get(Key(1)), Some("one".to_string()));
+        cache.insert(Key(3), "three".to_string());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(Key(2)), None);
+        assert_eq!(cache.get(Key(1)), Some("one".to_string()));
+        assert_eq!(cache.get(Key(3)), Some("three".to_string()));
+
+        let mut disabled: TieredCache<String> = TieredCache::new(0, None);
+        disabled.insert(Key(1), "one".to_string());
+        assert!(disabled.get(Key(1)).is_none())
+    }
+}
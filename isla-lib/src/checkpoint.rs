@@ -0,0 +1,111 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module saves and loads checkpoints of a long running
+//! exploration, consisting of the state of a
+//! [crate::executor::ForkLog] and the results collected so far.
+//! Checkpoints are written to a temporary file which is then renamed,
+//! so an interrupted write never replaces the previous checkpoint.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::binary_trace::{read_result, write_result, BinaryTraceError, TracedResult};
+use crate::bitvector::BV;
+use crate::executor::ForkLogState;
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"ISLACKP\x01";
+
+fn checkpoint_file(dir: &Path) -> PathBuf {
+    dir.join("checkpoint")
+}
+
+/// Returns true if the directory contains a checkpoint.
+pub fn exists(dir: &Path) -> bool {
+    checkpoint_file(dir).is_file()
+}
+
+pub fn save<B: BV>(dir: &Path, state: &ForkLogState, results: &[TracedResult<B>]) -> Result<(), BinaryTraceError> {
+    fs::create_dir_all(dir)?;
+    let tmp = dir.join("checkpoint.tmp");
+    {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(CHECKPOINT_MAGIC)?;
+        bincode::serialize_into(&mut out, state)?;
+        bincode::serialize_into(&mut out, &(results.len() as u64))?;
+        for result in results {
+            write_result(&mut out, result)?
+        }
+        out.into_inner().map_err(|err| err.into_error())?.sync_all()?
+    }
+    fs::rename(&tmp, checkpoint_file(dir))?;
+    Ok(())
+}
+
+pub fn load<B: BV>(dir: &Path) -> Result<(ForkLogState, Vec<TracedResult<B>>), BinaryTraceError> {
+    let mut input = BufReader::new(File::open(checkpoint_file(dir))?);
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic).map_err(|_| BinaryTraceError::BadMagic)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(BinaryTraceError::BadMagic);
+    }
+    let state: ForkLogState = bincode::deserialize_from(&mut input)?;
+    let len: u64 = bincode::deserialize_from(&mut input)?;
+    let mut results = Vec::new();
+    for _ in 0..len {
+        results.push(read_result(&mut input)?.ok_or(BinaryTraceError::UnexpectedEnd)?)
+    }
+    Ok((state, results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Val;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let dir = std::env::temp_dir().join(format!("isla-checkpoint-test-{}", std::process::id()));
+        let state = ForkLogState { pending: vec![vec![0, 1], vec![1]], completed: vec![vec![0, 0]] };
+        let results: Vec<TracedResult<B64>> = vec![Ok((Val::Bool(true), vec![])), Err(("error".to_string(), vec![]))];
+
+        save(&dir, &state, &results).unwrap();
+        assert!(exists(&dir));
+        let (loaded_state, loaded_results) = load::<B64>(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded_state.pending, state.pending);
+        assert_eq!(loaded_state.completed, state.completed);
+        assert_eq!(loaded_results.len(), 2);
+        assert!(matches!(loaded_results[0], Ok((Val::Bool(true), _))));
+        assert!(matches!(&loaded_results[1], Err((msg, _)) if msg == "error"))
+    }
+}
@@ -35,16 +35,18 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Arc;
 use toml::Value;
 
 use crate::bitvector::BV;
-use crate::ir::{Loc, Name, Reset, Symtab, URVal, Val};
+use crate::ir::{Loc, Name, Reset, SharedState, Symtab, Ty, URVal, Val, Widening};
 use crate::ir_lexer::new_ir_lexer;
 use crate::primop_util::symbolic_from_typedefs;
 use crate::smt::smtlib::Exp;
+use crate::smt::{Accessor, EnumMember, Event, MemKind};
 use crate::smt_parser;
 use crate::source_loc::SourceLoc;
 use crate::value_parser::{LocParser, URValParser, ValParser};
@@ -414,6 +416,38 @@ fn get_reset_constraints(config: &Value) -> Result<Vec<Exp<Loc<String>>>, String
     }
 }
 
+fn get_constraint_presets(config: &Value) -> Result<HashMap<String, Vec<Exp<Loc<String>>>>, String> {
+    let presets_toml =
+        config.get("constraints").and_then(|section| section.as_table()).and_then(|section| section.get("presets"));
+
+    let Some(presets_toml) = presets_toml else {
+        return Ok(HashMap::new())
+    };
+
+    let Some(presets_toml) = presets_toml.as_table() else {
+        return Err("constraints.presets should be a table of <name> = [<constraint>, ...] pairs".to_string())
+    };
+
+    let mut presets = HashMap::new();
+    for (name, constraints) in presets_toml {
+        let constraints = constraints
+            .as_array()
+            .and_then(|vec| vec.iter().map(|item| item.as_str()).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| format!("constraints.presets.\"{}\" should be an array of constraint strings", name))?;
+        let constraints = constraints
+            .iter()
+            .map(|constraint| {
+                smt_parser::ExpParser::new()
+                    .parse(constraint)
+                    .map_err(|err| format!("Error parsing constraint in preset \"{}\": {}", name, err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        presets.insert(name.to_string(), constraints);
+    }
+
+    Ok(presets)
+}
+
 fn get_register_renames(config: &Value, symtab: &Symtab) -> Result<HashMap<String, Name>, String> {
     let defaults = config
         .get("registers")
@@ -443,6 +477,182 @@ fn get_register_renames(config: &Value, symtab: &Symtab) -> Result<HashMap<Strin
     }
 }
 
+/// A named bitfield within a register, spanning bits `hi` down to
+/// `lo` inclusive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterField {
+    pub name: String,
+    pub hi: u32,
+    pub lo: u32,
+}
+
+fn parse_field_range(range: &str) -> Option<(u32, u32)> {
+    match range.split_once(':') {
+        Some((hi, lo)) => {
+            let (hi, lo) = (hi.trim().parse().ok()?, lo.trim().parse().ok()?);
+            if hi >= lo {
+                Some((hi, lo))
+            } else {
+                None
+            }
+        }
+        None => {
+            let bit = range.trim().parse().ok()?;
+            Some((bit, bit))
+        }
+    }
+}
+
+fn get_register_fields(config: &Value, symtab: &Symtab) -> Result<HashMap<Name, Vec<RegisterField>>, String> {
+    let fields = config
+        .get("registers")
+        .and_then(|registers| registers.as_table())
+        .and_then(|registers| registers.get("fields"));
+
+    let Some(fields) = fields else {
+        return Ok(HashMap::new())
+    };
+
+    let Some(fields) = fields.as_table() else {
+        return Err("registers.fields should be a table of <register> = { <field> = \"<hi>:<lo>\" } pairs".to_string())
+    };
+
+    let mut register_fields = HashMap::new();
+    for (register, layout) in fields {
+        let Some(name) = symtab.get(&zencode::encode(register)) else {
+            return Err(format!("Could not find register {} when parsing registers.fields in configuration", register))
+        };
+        let Some(layout) = layout.as_table() else {
+            return Err(format!("registers.fields.{} should be a table of <field> = \"<hi>:<lo>\" pairs", register))
+        };
+        let mut layout = layout
+            .iter()
+            .map(|(field, range)| match range.as_str().and_then(parse_field_range) {
+                Some((hi, lo)) => Ok(RegisterField { name: field.to_string(), hi, lo }),
+                None => Err(format!("Could not parse bit range for registers.fields.{}.{}", register, field)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        layout.sort_by(|f1, f2| f2.lo.cmp(&f1.lo));
+        register_fields.insert(name, layout);
+    }
+
+    Ok(register_fields)
+}
+
+/// A mapping from the constructors, enumeration members, and boolean
+/// struct fields used by the model for read and write kinds to
+/// structured memory access attributes, read from the
+/// `[memory_kinds]` table in the configuration.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKinds {
+    kinds: HashMap<Name, MemKind>,
+}
+
+impl MemoryKinds {
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    /// Compute the attributes for a read or write kind value. All
+    /// constructors, enumeration members, and true boolean fields
+    /// within the value which appear in the mapping contribute to the
+    /// result.
+    pub fn classify<B: BV>(&self, kind: &Val<B>, shared_state: &SharedState<B>) -> MemKind {
+        match kind {
+            Val::Enum(EnumMember { enum_id, member }) => {
+                let mut attrs = MemKind::default();
+                for (name, kind) in &self.kinds {
+                    if shared_state.enum_members.get(name) == Some(&(*member, enum_id.to_usize())) {
+                        attrs = attrs.union(*kind)
+                    }
+                }
+                attrs
+            }
+            Val::Ctor(ctor, value) => {
+                let attrs = self.kinds.get(ctor).copied().unwrap_or_default();
+                attrs.union(self.classify(value, shared_state))
+            }
+            Val::Struct(fields) => fields.iter().fold(MemKind::default(), |attrs, (field, value)| match value {
+                Val::Bool(true) => attrs.union(self.kinds.get(field).copied().unwrap_or_default()),
+                _ => attrs.union(self.classify(value, shared_state)),
+            }),
+            _ => MemKind::default(),
+        }
+    }
+
+    /// Attach the attributes for each memory event's kind to the
+    /// event. The read and write halves of each atomic
+    /// read-modify-write are also linked by giving them the same
+    /// `rmw_id`: an rmw read is paired with the next rmw write in the
+    /// same instruction, and the id is the position of the read in
+    /// the trace. A read with no matching write (e.g. a failed
+    /// compare-and-swap) keeps its id, but nothing shares it.
+    pub fn annotate<B: BV>(&self, events: &mut [Event<B>], shared_state: &SharedState<B>) {
+        if self.is_empty() {
+            return;
+        }
+        let mut pending_read = None;
+        for (i, event) in events.iter_mut().enumerate() {
+            match event {
+                Event::ReadMem { read_kind, opts, .. } => {
+                    opts.kind = self.classify(read_kind, shared_state);
+                    if opts.kind.rmw {
+                        opts.rmw_id = Some(i as u32);
+                        pending_read = opts.rmw_id
+                    }
+                }
+                Event::WriteMem { write_kind, opts, .. } => {
+                    opts.kind = self.classify(write_kind, shared_state);
+                    if opts.kind.rmw {
+                        opts.rmw_id = pending_read.take()
+                    }
+                }
+                Event::Instr(_) | Event::Cycle(_) => pending_read = None,
+                _ => (),
+            }
+        }
+    }
+}
+
+fn get_memory_kind(name: &str, attrs: &Value) -> Result<MemKind, String> {
+    let Some(attrs) = attrs.as_table() else {
+        return Err(format!("memory_kinds.{} should be a table of attributes", name))
+    };
+
+    let mut kind = MemKind::default();
+    for (attr, value) in attrs {
+        match (attr.as_str(), value) {
+            ("acquire", Value::Boolean(b)) => kind.acquire = *b,
+            ("release", Value::Boolean(b)) => kind.release = *b,
+            ("exclusive", Value::Boolean(b)) => kind.exclusive = *b,
+            ("rmw", Value::Boolean(b)) => kind.rmw = *b,
+            ("size_class", Value::Integer(n)) if u32::try_from(*n).is_ok() => kind.size_class = Some(*n as u32),
+            _ => return Err(format!("Invalid attribute {} = {} in memory_kinds.{}", attr, value, name)),
+        }
+    }
+    Ok(kind)
+}
+
+fn get_memory_kinds(config: &Value, symtab: &Symtab) -> Result<MemoryKinds, String> {
+    let Some(kinds) = config.get("memory_kinds") else {
+        return Ok(MemoryKinds::default())
+    };
+
+    let Some(kinds) = kinds.as_table() else {
+        return Err("memory_kinds should be a table of <constructor> = { <attribute> = <value> } pairs".to_string())
+    };
+
+    let mut memory_kinds = MemoryKinds::default();
+    for (ctor, attrs) in kinds {
+        let Some(name) = symtab.get(&zencode::encode(ctor)) else {
+            return Err(format!("Could not find {} when parsing memory_kinds in configuration", ctor))
+        };
+        memory_kinds.kinds.insert(name, get_memory_kind(ctor, attrs)?);
+    }
+
+    Ok(memory_kinds)
+}
+
 fn get_translation_function(config: &Value, symtab: &Symtab) -> Result<Option<Name>, String> {
     if let Some(value) = config.get("translation_function") {
         if let Some(string) = value.as_str() {
@@ -459,29 +669,276 @@ fn get_translation_function(config: &Value, symtab: &Symtab) -> Result<Option<Na
     }
 }
 
-fn get_trace_functions(config: &Value, symtab: &Symtab) -> Result<HashSet<Name>, String> {
-    let trace = config.get("trace");
+fn get_function_set(config: &Value, key: &str, symtab: &Symtab) -> Result<HashSet<Name>, String> {
+    let functions = config.get(key);
 
-    if let Some(trace) = trace {
-        if let Some(trace) = trace.as_array() {
-            trace
+    if let Some(functions) = functions {
+        if let Some(functions) = functions.as_array() {
+            functions
                 .iter()
                 .map(|function| {
                     if let Some(function) = function.as_str().and_then(|f| symtab.get(&zencode::encode(f))) {
                         Ok(function)
                     } else {
-                        Err(format!("Could not find function {} when parsing trace in configuration", function))
+                        Err(format!("Could not find function {} when parsing {} in configuration", function, key))
                     }
                 })
                 .collect()
         } else {
-            Err("trace should be a list of function names".to_string())
+            Err(format!("{} should be a list of function names", key))
         }
     } else {
         Ok(HashSet::new())
     }
 }
 
+fn get_trace_functions(config: &Value, symtab: &Symtab) -> Result<HashSet<Name>, String> {
+    get_function_set(config, "trace", symtab)
+}
+
+/// Functions listed under `untraced` have their internal register
+/// accesses omitted from traces, e.g. for utility accessors of
+/// PSTATE that would otherwise clutter every instruction.
+fn get_untraced_functions(config: &Value, symtab: &Symtab) -> Result<HashSet<Name>, String> {
+    get_function_set(config, "untraced", symtab)
+}
+
+/// Functions listed as `uninterpreted` are never executed. Calls to
+/// them return a fresh symbolic value of the function's declared
+/// return type, and are recorded as abstract events.
+fn get_uninterpreted_functions(config: &Value, symtab: &Symtab) -> Result<HashSet<Name>, String> {
+    get_function_set(config, "uninterpreted", symtab)
+}
+
+fn parse_abstract_ty(ty: &str) -> Option<Ty<Name>> {
+    match ty {
+        "unit" => Some(Ty::Unit),
+        "bool" => Some(Ty::Bool),
+        "bit" => Some(Ty::Bit),
+        "int" => Some(Ty::I128),
+        _ => {
+            let width = ty.strip_prefix("bits(")?.strip_suffix(')')?;
+            width.trim().parse::<u32>().ok().filter(|w| *w > 0).map(Ty::Bits)
+        }
+    }
+}
+
+/// Abstract primitives are declared as an array of tables, e.g.
+/// ```toml
+/// [[abstract]]
+/// name = "read_tag"
+/// arity = 1
+/// returns = "bits(1)"
+/// ```
+/// Calls to these functions produce an abstract event with a fresh
+/// symbolic return value rather than being executed.
+fn get_abstract_primitives(config: &Value, symtab: &Symtab) -> Result<HashMap<Name, (usize, Ty<Name>)>, String> {
+    let mut primitives = HashMap::new();
+
+    let Some(decls) = config.get("abstract") else {
+        return Ok(primitives)
+    };
+
+    let Some(decls) = decls.as_array() else {
+        return Err("abstract should be an array of tables in configuration".to_string())
+    };
+
+    for decl in decls {
+        let Some(name) = decl.get("name").and_then(Value::as_str) else {
+            return Err("abstract primitive must have a name".to_string())
+        };
+
+        let Some(f) = symtab.get(&zencode::encode(name)) else {
+            return Err(format!("Could not find function {} when parsing abstract in configuration", name))
+        };
+
+        let arity = match decl.get("arity").and_then(Value::as_integer) {
+            Some(n) if n >= 0 => n as usize,
+            _ => return Err(format!("abstract primitive {} must have a non-negative integer arity", name)),
+        };
+
+        let ty = decl.get("returns").and_then(Value::as_str).unwrap_or("unit");
+        let Some(ty) = parse_abstract_ty(ty) else {
+            return Err(format!("Unsupported return type {} for abstract primitive {}", ty, name))
+        };
+
+        if primitives.insert(f, (arity, ty)).is_some() {
+            return Err(format!("abstract primitive {} declared more than once", name));
+        }
+    }
+
+    Ok(primitives)
+}
+
+/// Widening is declared as an array of tables, e.g.
+/// ```toml
+/// [[widen]]
+/// name = "MemLoadLoop"
+/// unroll = 4
+/// modifies = ["R0", "R1"]
+/// ```
+/// After the function has been entered `unroll` times on a path,
+/// further calls return a fresh symbolic value and give fresh
+/// symbolic values to the registers in `modifies`.
+fn get_widening(config: &Value, symtab: &Symtab) -> Result<HashMap<Name, Widening>, String> {
+    let mut widening = HashMap::new();
+
+    let Some(decls) = config.get("widen") else {
+        return Ok(widening)
+    };
+
+    let Some(decls) = decls.as_array() else {
+        return Err("widen should be an array of tables in configuration".to_string())
+    };
+
+    for decl in decls {
+        let Some(name) = decl.get("name").and_then(Value::as_str) else {
+            return Err("widened function must have a name".to_string())
+        };
+
+        let Some(f) = symtab.get(&zencode::encode(name)) else {
+            return Err(format!("Could not find function {} when parsing widen in configuration", name))
+        };
+
+        let unroll = match decl.get("unroll").and_then(Value::as_integer) {
+            Some(n) if n >= 0 => n as usize,
+            _ => return Err(format!("widened function {} must have a non-negative integer unroll bound", name)),
+        };
+
+        let mut modifies = Vec::new();
+        if let Some(registers) = decl.get("modifies") {
+            let Some(registers) = registers.as_array() else {
+                return Err(format!("modifies for widened function {} should be a list of register names", name))
+            };
+            for register in registers {
+                let Some(register) = register.as_str().and_then(|r| symtab.get(&zencode::encode(r))) else {
+                    return Err(format!(
+                        "Could not find register {} when parsing widen for {} in configuration",
+                        register, name
+                    ))
+                };
+                modifies.push(register)
+            }
+        }
+
+        if widening.insert(f, Widening { unroll, modifies }).is_some() {
+            return Err(format!("widening for function {} declared more than once", name));
+        }
+    }
+
+    Ok(widening)
+}
+
+fn get_optional_registers_set(config: &Value, set_name: &str, symtab: &Symtab) -> Result<Option<HashSet<Name>>, String> {
+    match config.get("registers").and_then(|registers| registers.as_table()) {
+        Some(registers) if registers.contains_key(set_name) => get_registers_set(config, set_name, symtab).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Rules for coalescing register accesses in footprints, so that
+/// footprints stay comparable when a model splits or renames state,
+/// read from `[[registers.coalesce]]` in the configuration.
+#[derive(Clone, Debug, Default)]
+pub struct RegisterCoalescing {
+    /// Registers recorded as accesses to another register
+    registers: HashMap<Name, Name>,
+    /// Fields of a register whose accesses are recorded as accesses
+    /// to the whole register
+    fields: HashMap<Name, HashSet<Name>>,
+}
+
+impl RegisterCoalescing {
+    /// Record accesses to `register` as accesses to `into`. Returns
+    /// false if `register` was already coalesced.
+    pub fn coalesce_register(&mut self, register: Name, into: Name) -> bool {
+        self.registers.insert(register, into).is_none()
+    }
+
+    /// Record accesses to `fields` of `into` as accesses to the whole register
+    pub fn coalesce_fields<I: IntoIterator<Item = Name>>(&mut self, into: Name, fields: I) {
+        self.fields.entry(into).or_default().extend(fields)
+    }
+
+    /// The register and accessors to record for an access
+    pub fn coalesce(&self, reg: Name, accessor: &[Accessor]) -> (Name, Vec<Accessor>) {
+        if let Some(into) = self.registers.get(&reg) {
+            return (*into, Vec::new());
+        }
+        if let (Some(fields), Some(Accessor::Field(field))) = (self.fields.get(&reg), accessor.first()) {
+            if fields.contains(field) {
+                return (reg, Vec::new());
+            }
+        }
+        (reg, accessor.to_vec())
+    }
+}
+
+/// Coalescing rules are declared as an array of tables, e.g.
+/// ```toml
+/// [[registers.coalesce]]
+/// into = "PSTATE"
+/// fields = ["N", "Z", "C", "V"]
+/// ```
+/// where `fields` lists fields of the `into` register, and
+/// `registers` (not shown) lists other registers to be recorded as
+/// accesses to it.
+fn get_register_coalescing(config: &Value, symtab: &Symtab) -> Result<RegisterCoalescing, String> {
+    let mut coalescing = RegisterCoalescing::default();
+
+    let Some(rules) = config.get("registers").and_then(|registers| registers.get("coalesce")) else {
+        return Ok(coalescing)
+    };
+
+    let Some(rules) = rules.as_array() else {
+        return Err("registers.coalesce should be an array of tables in configuration".to_string())
+    };
+
+    let names = |rule: &Value, key: &str, what: &str| -> Result<Vec<Name>, String> {
+        let Some(names) = rule.get(key) else {
+            return Ok(Vec::new())
+        };
+        let Some(names) = names.as_array() else {
+            return Err(format!("registers.coalesce {} should be a list of {} names", key, what))
+        };
+        names
+            .iter()
+            .map(|name| {
+                name.as_str().and_then(|n| symtab.get(&zencode::encode(n))).ok_or_else(|| {
+                    format!("Could not find {} {} when parsing registers.coalesce in configuration", what, name)
+                })
+            })
+            .collect()
+    };
+
+    for rule in rules {
+        allowed_keys(rule, "[[registers.coalesce]]", &["into", "registers", "fields"])?;
+
+        let Some(into) = rule.get("into").and_then(Value::as_str) else {
+            return Err("registers.coalesce rule must have an into register".to_string())
+        };
+        let Some(into) = symtab.get(&zencode::encode(into)) else {
+            return Err(format!("Could not find register {} when parsing registers.coalesce in configuration", into))
+        };
+
+        for register in names(rule, "registers", "register")? {
+            if !coalescing.coalesce_register(register, into) {
+                return Err(format!(
+                    "register {} coalesced more than once in configuration",
+                    zencode::decode(symtab.to_str(register))
+                ));
+            }
+        }
+
+        let fields = names(rule, "fields", "field")?;
+        if !fields.is_empty() {
+            coalescing.coalesce_fields(into, fields)
+        }
+    }
+
+    Ok(coalescing)
+}
+
 fn get_registers_set<C>(config: &Value, set_name: &str, symtab: &Symtab) -> Result<C, String>
 where
     C: FromIterator<Name> + Default,
@@ -553,6 +1010,96 @@ fn get_default_sizeof(config: &Value) -> Result<u32, String> {
     }
 }
 
+fn get_self_modifying_code(config: &Value) -> Result<bool, String> {
+    match config.get("self_modifying_code") {
+        None => Ok(false),
+        Some(Value::Boolean(b)) => Ok(*b),
+        Some(_) => Err("self_modifying_code should be a boolean".to_string()),
+    }
+}
+
+/// A memory-mapped device region. Reads return fresh symbolic
+/// values, and both reads and writes are recorded as abstract device
+/// events rather than memory events.
+#[derive(Clone, Debug)]
+pub struct MmioRegion {
+    pub name: String,
+    pub range: Range<u64>,
+    /// If non-empty, reads are constrained to return one of these
+    /// values
+    pub values: Vec<u64>,
+}
+
+#[allow(clippy::from_str_radix_10)]
+fn parse_address(value: &Value) -> Option<u64> {
+    match value {
+        Value::Integer(i) => u64::try_from(*i).ok(),
+        Value::String(s) if s.len() >= 2 && &s[0..2] == "0x" => u64::from_str_radix(&s[2..], 16).ok(),
+        Value::String(s) => u64::from_str_radix(s, 10).ok(),
+        _ => None,
+    }
+}
+
+/// Device regions are declared as an array of tables, e.g.
+/// ```toml
+/// [[mmio]]
+/// name = "uart"
+/// base = "0x09000000"
+/// top = "0x09001000"
+/// values = ["0x0", "0x20"]
+/// ```
+/// where `values` is optional.
+fn get_mmio_regions(config: &Value) -> Result<Vec<MmioRegion>, String> {
+    let mut regions: Vec<MmioRegion> = Vec::new();
+
+    let Some(decls) = config.get("mmio") else {
+        return Ok(regions)
+    };
+
+    let Some(decls) = decls.as_array() else {
+        return Err("mmio should be an array of tables in configuration".to_string())
+    };
+
+    for decl in decls {
+        allowed_keys(decl, "[[mmio]]", &["name", "base", "top", "values"])?;
+
+        let Some(name) = decl.get("name").and_then(Value::as_str) else {
+            return Err("mmio region must have a name".to_string())
+        };
+
+        let (Some(base), Some(top)) =
+            (decl.get("base").and_then(parse_address), decl.get("top").and_then(parse_address))
+        else {
+            return Err(format!("mmio region {} must have a base and top address", name))
+        };
+
+        if base >= top {
+            return Err(format!("mmio region {} has base 0x{:x} not below top 0x{:x}", name, base, top));
+        }
+
+        if let Some(other) = regions.iter().find(|r| r.range.start < top && base < r.range.end) {
+            return Err(format!("mmio region {} overlaps mmio region {}", name, other.name));
+        }
+
+        let mut values = Vec::new();
+        if let Some(vs) = decl.get("values") {
+            let Some(vs) = vs.as_array() else {
+                return Err(format!("values for mmio region {} should be a list of integers", name))
+            };
+            for v in vs {
+                let Some(v) = parse_address(v) else {
+                    return Err(format!("Could not parse {} as a value for mmio region {}", v, name))
+                };
+                values.push(v)
+            }
+        }
+
+        regions.push(MmioRegion { name: name.to_string(), range: base..top, values })
+    }
+
+    Ok(regions)
+}
+
 pub struct ISAConfig<B> {
     /// The identifier for the program counter register
     pub pc: Name,
@@ -593,24 +1140,53 @@ pub struct ISAConfig<B> {
     pub reset_registers: Vec<(Loc<Name>, Reset<B>)>,
     /// Constraints that should hold at reset_registers
     pub reset_constraints: Vec<Exp<Loc<String>>>,
+    /// Named sets of reset constraints, e.g. for a particular
+    /// exception level or privilege mode
+    pub constraint_presets: HashMap<String, Vec<Exp<Loc<String>>>>,
     /// Assumptions to use about function behaviour
     pub function_assumptions: Vec<(String, Vec<Exp<Loc<String>>>, Exp<Loc<String>>)>,
     /// Register synonyms to rename
     pub register_renames: HashMap<String, Name>,
+    /// Field layouts for registers, used when writing traces
+    pub register_fields: HashMap<Name, Vec<RegisterField>>,
+    /// Structured attributes for the model's read and write kinds
+    pub memory_kinds: MemoryKinds,
     /// Registers to ignore during footprint analysis
     pub ignored_registers: HashSet<Name>,
+    /// Rules for coalescing registers when reporting footprints
+    pub coalesced_registers: RegisterCoalescing,
     /// Relaxed registers
     pub relaxed_registers: HashSet<Name>,
+    /// If set, only these registers (and those with default or reset
+    /// values) may start with a symbolic value
+    pub symbolic_registers: Option<HashSet<Name>>,
+    /// Registers which must be written before they are read
+    pub write_before_read_registers: HashSet<Name>,
     /// Print debug information for any function calls in this set during symbolic execution
     pub probes: HashSet<Name>,
     /// Trace calls to functions in this set
     pub trace_functions: HashSet<Name>,
+    /// Don't trace register accesses made within functions in this set
+    pub untraced_functions: HashSet<Name>,
+    /// Functions which are left uninterpreted, returning fresh
+    /// symbolic values of their declared return type
+    pub uninterpreted_functions: HashSet<Name>,
+    /// Functions treated as abstract primitives, with their arity and return type
+    pub abstract_primitives: HashMap<Name, (usize, Ty<Name>)>,
+    /// Recursive functions to summarize after a bounded number of
+    /// unrollings
+    pub widening: HashMap<Name, Widening>,
+    /// Memory-mapped device regions
+    pub mmio_regions: Vec<MmioRegion>,
     /// Address translation function
     pub translation_function: Option<Name>,
     /// The abstract events that should be included in program order
     pub in_program_order: HashSet<Name>,
     /// The default size (in bytes) for memory accesses in litmus tests
     pub default_sizeof: u32,
+    /// Whether instruction fetches should observe earlier writes to
+    /// the same address, to support self-modifying code
+    pub self_modifying_code: bool,
 }
 
 impl<B: BV> ISAConfig<B> {
@@ -651,18 +1227,62 @@ impl<B: BV> ISAConfig<B> {
             default_registers: get_default_registers(&config, symtab)?,
             reset_registers: get_reset_registers(&config, symtab)?,
             reset_constraints: get_reset_constraints(&config)?,
+            constraint_presets: get_constraint_presets(&config)?,
             function_assumptions: Vec::new(),
             register_renames: get_register_renames(&config, symtab)?,
+            register_fields: get_register_fields(&config, symtab)?,
+            memory_kinds: get_memory_kinds(&config, symtab)?,
             ignored_registers: get_registers_set(&config, "ignore", symtab)?,
+            coalesced_registers: get_register_coalescing(&config, symtab)?,
             relaxed_registers: get_registers_set(&config, "relaxed", symtab)?,
+            symbolic_registers: get_optional_registers_set(&config, "symbolic", symtab)?,
+            write_before_read_registers: get_registers_set(&config, "write_before_read", symtab)?,
             probes: HashSet::new(),
             trace_functions,
+            untraced_functions: get_untraced_functions(&config, symtab)?,
+            uninterpreted_functions: get_uninterpreted_functions(&config, symtab)?,
+            abstract_primitives: get_abstract_primitives(&config, symtab)?,
+            widening: get_widening(&config, symtab)?,
+            mmio_regions: get_mmio_regions(&config)?,
             translation_function,
             in_program_order: get_in_program_order(&config, symtab)?,
             default_sizeof: get_default_sizeof(&config)?,
+            self_modifying_code: get_self_modifying_code(&config)?,
         })
     }
 
+    /// Add the constraints from a named preset to the reset
+    /// constraints
+    pub fn use_constraint_preset(&mut self, name: &str) -> Result<(), String> {
+        let Some(constraints) = self.constraint_presets.get(name) else {
+            let mut available: Vec<&str> = self.constraint_presets.keys().map(String::as_str).collect();
+            available.sort_unstable();
+            return Err(format!("No constraint preset \"{}\" in configuration (available: {})", name, available.join(", ")))
+        };
+        self.reset_constraints.extend(constraints.iter().cloned());
+        Ok(())
+    }
+
+    /// Returns true if reading the register before it has been
+    /// written should be an error, rather than giving it a symbolic
+    /// initial value. This is the case for registers listed in
+    /// `registers.write_before_read`, and when `registers.symbolic` is
+    /// given, for any register not listed there that lacks a default
+    /// or reset value.
+    pub fn requires_write_before_read(&self, reg: Name) -> bool {
+        if self.write_before_read_registers.contains(&reg) {
+            return true;
+        }
+        match &self.symbolic_registers {
+            Some(symbolic) => {
+                !(symbolic.contains(&reg)
+                    || self.default_registers.contains_key(&reg)
+                    || self.reset_registers.iter().any(|(loc, _)| loc.id() == reg))
+            }
+            None => false,
+        }
+    }
+
     pub fn read_event_registers(&self) -> HashSet<Name> {
         let mut registers = HashSet::new();
         for (_, regs) in self.register_event_sets.iter() {
@@ -719,3 +1339,57 @@ impl<B: BV> ISAConfig<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_coalescing() {
+        let mut symtab = Symtab::new();
+        let pstate = symtab.intern("zPSTATE");
+        let n = symtab.intern("zN");
+        let z = symtab.intern("zZ");
+        let el = symtab.intern("zEL");
+        let nzcv = symtab.intern("zNZCV");
+
+        let config: Value = toml::from_str(
+            r#"
+            [[registers.coalesce]]
+            into = "PSTATE"
+            registers = ["NZCV"]
+            fields = ["N", "Z"]
+            "#,
+        )
+        .unwrap();
+        let coalescing = get_register_coalescing(&config, &symtab).unwrap();
+
+        assert_eq!(coalescing.coalesce(pstate, &[Accessor::Field(n)]), (pstate, vec![]));
+        assert_eq!(coalescing.coalesce(pstate, &[Accessor::Field(z)]), (pstate, vec![]));
+        assert_eq!(coalescing.coalesce(pstate, &[Accessor::Field(el)]), (pstate, vec![Accessor::Field(el)]));
+        assert_eq!(coalescing.coalesce(nzcv, &[]), (pstate, vec![]));
+        assert_eq!(coalescing.coalesce(el, &[]), (el, vec![]));
+    }
+
+    #[test]
+    fn register_coalescing_errors() {
+        let mut symtab = Symtab::new();
+        symtab.intern("zPSTATE");
+        let nzcv = symtab.intern("zNZCV");
+
+        let unknown: Value =
+            toml::from_str("[[registers.coalesce]]\ninto = \"PSTATE\"\nregisters = [\"FOO\"]").unwrap();
+        assert!(get_register_coalescing(&unknown, &symtab).is_err());
+
+        let twice: Value = toml::from_str(
+            "[[registers.coalesce]]\ninto = \"PSTATE\"\nregisters = [\"NZCV\"]\n\
+             [[registers.coalesce]]\ninto = \"PSTATE\"\nregisters = [\"NZCV\"]",
+        )
+        .unwrap();
+        assert!(get_register_coalescing(&twice, &symtab).is_err());
+
+        let empty: Value = toml::from_str("").unwrap();
+        let coalescing = get_register_coalescing(&empty, &symtab).unwrap();
+        assert_eq!(coalescing.coalesce(nzcv, &[]), (nzcv, vec![]));
+    }
+}
@@ -0,0 +1,113 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module tracks which IR instructions have been executed,
+//! shared between all the threads exploring an architecture. It is
+//! used both to report coverage and to guide the scheduler towards
+//! tasks that are about to execute code no other task has reached,
+//! see [crate::executor::TaskState::set_coverage_guided].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::ir::{Name, SharedState, Symtab};
+use crate::zencode;
+
+pub struct Coverage {
+    functions: HashMap<Name, Box<[AtomicBool]>>,
+}
+
+impl Coverage {
+    pub fn new<B>(shared_state: &SharedState<'_, B>) -> Self {
+        let functions = shared_state
+            .functions
+            .iter()
+            .map(|(f, (_, _, instrs))| (*f, instrs.iter().map(|_| AtomicBool::new(false)).collect()))
+            .collect();
+        Coverage { functions }
+    }
+
+    pub fn visit(&self, function: Name, pc: usize) {
+        if let Some(flag) = self.functions.get(&function).and_then(|instrs| instrs.get(pc)) {
+            // Avoid writing to the shared cache line in the common case
+            if !flag.load(Ordering::Relaxed) {
+                flag.store(true, Ordering::Relaxed)
+            }
+        }
+    }
+
+    pub fn is_covered(&self, function: Name, pc: usize) -> bool {
+        self.functions
+            .get(&function)
+            .and_then(|instrs| instrs.get(pc))
+            .map_or(false, |flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of covered instructions in a function,
+    /// along with its total number of instructions.
+    pub fn function_summary(&self, function: Name) -> Option<(usize, usize)> {
+        self.functions
+            .get(&function)
+            .map(|instrs| (instrs.iter().filter(|flag| flag.load(Ordering::Relaxed)).count(), instrs.len()))
+    }
+
+    /// Returns the number of covered instructions, along with the
+    /// total number of instructions, in all functions that were
+    /// reached at all.
+    pub fn summary(&self) -> (usize, usize) {
+        self.functions
+            .keys()
+            .filter_map(|f| self.function_summary(*f))
+            .filter(|(covered, _)| *covered > 0)
+            .fold((0, 0), |(covered, total), (c, t)| (covered + c, total + t))
+    }
+
+    /// A report listing the coverage of each function that was
+    /// reached, sorted by name.
+    pub fn report(&self, symtab: &Symtab) -> String {
+        let mut lines: Vec<(String, usize, usize)> = self
+            .functions
+            .keys()
+            .filter_map(|f| {
+                let (covered, total) = self.function_summary(*f)?;
+                if covered > 0 {
+                    Some((zencode::decode(symtab.to_str(*f)), covered, total))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        lines.sort();
+        let mut report = String::new();
+        for (name, covered, total) in lines {
+            report.push_str(&format!("{} {}/{}\n", name, covered, total))
+        }
+        report
+    }
+}
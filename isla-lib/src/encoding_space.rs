@@ -0,0 +1,538 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module partitions a space of instruction encodings by the
+//! behavior of the decoder. The decoder is run on an opcode with
+//! symbolic bits, and [partition_collector] covers the opcodes taking
+//! each path with [Pattern]s, i.e. bits where some positions are
+//! don't-cares. A [Partition] groups the patterns by the [Behavior]
+//! of each path (the function it was stopped at, the value it
+//! returned, or the error it ended with), and also reports the
+//! encodings which no path reached, e.g.
+//!
+//! ```text
+//! execute_add_immediate: 8388608 encodings
+//!   100100010xxxxxxxxxxxxxxxxxxxxxxx bits 0x91000000 mask 0xff800000
+//! error: Unallocated: ...
+//! ```
+//!
+//! The patterns for a path are found by fixing every bit which can
+//! only take one value on the path, then splitting on the remaining
+//! bits until each pattern lies entirely within the path condition.
+//! Path conditions which depend on anything other than the opcode,
+//! such as symbolic register values, cannot be covered exactly, so
+//! once [MAX_PATH_PATTERNS] patterns have been found for a path any
+//! remaining ones are reported as only partially covered.
+
+use crossbeam::queue::SegQueue;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+use crate::bitvector::BV;
+use crate::error::ExecError;
+use crate::executor::{Backtrace, LocalFrame};
+use crate::ir::{BitsSegment, SharedState, Val};
+use crate::primop_util::mixed_bits_to_smt;
+use crate::smt::smtlib::{bits64, Def, Exp};
+use crate::smt::{Config, Context, Event, Model, SmtResult, Solver, Sym};
+use crate::source_loc::SourceLoc;
+use crate::summary::model_bits;
+use crate::zencode;
+
+/// The number of patterns found for a path before any remaining ones
+/// are reported as partially covered
+pub const MAX_PATH_PATTERNS: usize = 256;
+
+/// A set of encodings, given by the values of the bits set in `mask`.
+/// The other bits can take any value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pattern {
+    pub width: u32,
+    pub mask: u64,
+    pub bits: u64,
+}
+
+impl Pattern {
+    /// Every encoding of `width` bits, which must be at most 64
+    pub fn any(width: u32) -> Self {
+        Pattern { width, mask: 0, bits: 0 }
+    }
+
+    /// The encodings of an opcode with symbolic bits, where the bits
+    /// of its concrete segments are fixed. Returns `None` if the
+    /// opcode is not a bitvector of at most 64 bits.
+    pub fn from_opcode<B: BV>(opcode: &Val<B>, solver: &mut Solver<B>) -> Option<Self> {
+        let segments = match opcode {
+            Val::Bits(bv) => vec![BitsSegment::Concrete(*bv)],
+            Val::Symbolic(v) => vec![BitsSegment::Symbolic(*v)],
+            Val::MixedBits(segments) => segments.clone(),
+            _ => return None,
+        };
+        let mut pattern = Pattern::any(0);
+        for segment in &segments {
+            let (len, mask, bits) = match segment {
+                BitsSegment::Concrete(bv) => (bv.len(), u64::MAX, bv.lower_u64()),
+                BitsSegment::Symbolic(v) => (solver.length(*v)?, 0, 0),
+            };
+            if pattern.width + len > 64 {
+                return None;
+            }
+            pattern.width += len;
+            pattern.mask = (pattern.mask.checked_shl(len).unwrap_or(0) | (mask & ones(len))) & ones(pattern.width);
+            pattern.bits = (pattern.bits.checked_shl(len).unwrap_or(0) | (bits & ones(len))) & ones(pattern.width);
+        }
+        Some(pattern)
+    }
+
+    /// The number of encodings matching the pattern
+    pub fn count(&self) -> u128 {
+        1 << (self.width - self.mask.count_ones())
+    }
+
+    pub fn matches(&self, opcode: u64) -> bool {
+        opcode & self.mask == self.bits
+    }
+
+    fn intersects(&self, other: &Pattern) -> bool {
+        let common = self.mask & other.mask;
+        self.bits & common == other.bits & common
+    }
+
+    /// The don't-care bits, from most to least significant
+    fn free_bits(&self) -> Vec<u32> {
+        (0..self.width).rev().filter(|bit| (self.mask >> bit) & 1 == 0).collect()
+    }
+
+    fn fix(&self, bit: u32, value: bool) -> Pattern {
+        Pattern {
+            width: self.width,
+            mask: self.mask | (1 << bit),
+            bits: (self.bits & !(1 << bit)) | (u64::from(value) << bit),
+        }
+    }
+
+    /// The encodings which match `self` but not `other`, as disjoint
+    /// patterns
+    pub fn subtract(&self, other: &Pattern) -> Vec<Pattern> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+        let mut patterns = Vec::new();
+        let mut rest = *self;
+        for bit in self.free_bits() {
+            if (other.mask >> bit) & 1 == 1 {
+                let value = (other.bits >> bit) & 1 == 1;
+                patterns.push(rest.fix(bit, !value));
+                rest = rest.fix(bit, value)
+            }
+        }
+        patterns
+    }
+
+    /// Combine two patterns which differ only in the value of a single
+    /// fixed bit
+    fn merge(&self, other: &Pattern) -> Option<Pattern> {
+        let diff = self.bits ^ other.bits;
+        if self.width == other.width && self.mask == other.mask && diff.count_ones() == 1 {
+            Some(Pattern { width: self.width, mask: self.mask & !diff, bits: self.bits & !diff })
+        } else {
+            None
+        }
+    }
+
+    /// An expression which is true when `opcode` matches the pattern
+    fn exp(&self, opcode: &Exp<Sym>) -> Exp<Sym> {
+        if self.mask == 0 {
+            return Exp::Bool(true);
+        }
+        Exp::Eq(
+            Box::new(Exp::Bvand(Box::new(opcode.clone()), Box::new(bits64(self.mask, self.width)))),
+            Box::new(bits64(self.bits, self.width)),
+        )
+    }
+}
+
+fn ones(len: u32) -> u64 {
+    u64::MAX.checked_shr(64 - len).unwrap_or(0)
+}
+
+/// Shown from the most significant bit, with `x` for don't-cares
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bit in (0..self.width).rev() {
+            match ((self.mask >> bit) & 1, (self.bits >> bit) & 1) {
+                (0, _) => write!(f, "x")?,
+                (_, b) => write!(f, "{}", b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Repeatedly combine patterns which differ in a single bit, and sort
+/// the result
+fn merge_patterns(mut patterns: Vec<Pattern>) -> Vec<Pattern> {
+    loop {
+        patterns.sort_by_key(|pattern| (pattern.bits, !pattern.mask));
+        patterns.dedup();
+        let mut merged = None;
+        'search: for (i, p) in patterns.iter().enumerate() {
+            for (j, q) in patterns.iter().enumerate().skip(i + 1) {
+                if let Some(pq) = p.merge(q) {
+                    merged = Some((i, j, pq));
+                    break 'search;
+                }
+            }
+        }
+        match merged {
+            Some((i, j, pq)) => {
+                patterns.remove(j);
+                patterns[i] = pq
+            }
+            None => return patterns,
+        }
+    }
+}
+
+/// Narrow a pattern by fixing each don't-care bit which can only take
+/// one value on the path. Returns `None` if no opcode matching the
+/// pattern is consistent with the path.
+fn fix_bits<B: BV>(pattern: Pattern, opcode: Sym, solver: &mut Solver<B>) -> Result<Option<Pattern>, ExecError> {
+    let opcode_exp = Exp::Var(opcode);
+    match solver.check_sat_with(&pattern.exp(&opcode_exp)) {
+        SmtResult::Sat => (),
+        SmtResult::Unsat => return Ok(None),
+        SmtResult::Unknown => return Err(ExecError::Z3Unknown),
+    }
+    let witness: B = model_bits(&mut Model::new(solver), opcode, pattern.width)?;
+    let witness = witness.lower_u64();
+
+    let mut fixed = pattern;
+    for bit in pattern.free_bits() {
+        let value = (witness >> bit) & 1 == 1;
+        if solver.check_sat_with(&pattern.fix(bit, !value).exp(&opcode_exp)) == SmtResult::Unsat {
+            fixed = fixed.fix(bit, value)
+        }
+    }
+    Ok(Some(fixed))
+}
+
+/// Find patterns covering the opcodes which are consistent with the
+/// path condition of `solver`. Returns the patterns which lie
+/// entirely within the path condition, and those which are only
+/// partially covered because the path condition depends on other
+/// variables, or because `limit` patterns were found.
+pub fn path_patterns<B: BV>(
+    solver: &mut Solver<B>,
+    opcode: &Val<B>,
+    limit: usize,
+) -> Result<(Vec<Pattern>, Vec<Pattern>), ExecError> {
+    let info = SourceLoc::unknown();
+    let Some(start) = Pattern::from_opcode(opcode, solver) else {
+        return Err(ExecError::Type(format!("Cannot find encodings for opcode {:?}", opcode), info));
+    };
+    let opcode_exp = mixed_bits_to_smt(opcode.clone(), solver, info)?;
+    let opcode = solver.define_const(opcode_exp, info);
+
+    // A second solver with the same variables, but without the path
+    // condition, checks whether each pattern lies within it
+    let mut events = solver.trace().to_vec();
+    events.reverse();
+    let ctx = Context::new(Config::new());
+    let mut checker = Solver::<B>::new(&ctx);
+    let mut condition = Exp::Bool(true);
+    for event in events {
+        match event {
+            Event::Smt(Def::Assert(exp) | Def::NamedAssert(_, _, exp), _, _) => {
+                condition = Exp::And(Box::new(condition), Box::new(exp.clone()))
+            }
+            Event::Smt(def, _, _) => checker.add(def.clone()),
+            _ => (),
+        }
+    }
+    let outside = Exp::Not(Box::new(condition));
+
+    let mut exact = Vec::new();
+    let mut partial = Vec::new();
+    let mut worklist = vec![start];
+    while let Some(pattern) = worklist.pop() {
+        let Some(pattern) = fix_bits(pattern, opcode, solver)? else { continue };
+        let escapes = Exp::And(Box::new(pattern.exp(&Exp::Var(opcode))), Box::new(outside.clone()));
+        let split = pattern.free_bits().first().copied();
+        match (checker.check_sat_with(&escapes), split) {
+            (SmtResult::Unsat, _) => exact.push(pattern),
+            (SmtResult::Sat, Some(bit)) if exact.len() + partial.len() + worklist.len() + 2 <= limit => {
+                worklist.push(pattern.fix(bit, false));
+                worklist.push(pattern.fix(bit, true))
+            }
+            _ => partial.push(pattern),
+        }
+    }
+    Ok((merge_patterns(exact), merge_patterns(partial)))
+}
+
+/// What the decoder did on a path
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Behavior {
+    /// The path reached a function it was stopped at, such as an
+    /// execute function
+    Calls(String),
+    /// The path returned a value, shown as just its constructor for a
+    /// union such as an AST
+    Returns(String),
+    Exits,
+    Error(String),
+}
+
+impl fmt::Display for Behavior {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Behavior::Calls(function) => write!(f, "{}", function),
+            Behavior::Returns(value) => write!(f, "returns {}", value),
+            Behavior::Exits => write!(f, "exits"),
+            Behavior::Error(msg) => write!(f, "error: {}", msg),
+        }
+    }
+}
+
+/// The behavior of a path as passed to a collector, or `None` for dead
+/// paths. Both functions abstracted by a stop-at condition and
+/// functions killed by a kill-at condition count as being called.
+fn path_behavior<'ir, B: BV>(
+    result: &Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &Solver<B>,
+) -> Option<Behavior> {
+    let symtab = &shared_state.symtab;
+    let mut events = solver.trace().to_vec();
+    events.reverse();
+    let abstracted = events.iter().find_map(|event| match event {
+        Event::Abstract { name, primitive: false, .. } => Some(*name),
+        _ => None,
+    });
+    if let Some(function) = abstracted {
+        return Some(Behavior::Calls(zencode::decode(symtab.to_str(function))));
+    }
+    match result {
+        Ok((Val::Ctor(ctor, _), _)) => Some(Behavior::Returns(zencode::decode(symtab.to_str(*ctor)))),
+        Ok((val, _)) => Some(Behavior::Returns(val.to_string(symtab))),
+        Err((ExecError::Stopped(function), _)) => Some(Behavior::Calls(function.clone())),
+        Err((ExecError::Exit, _)) => Some(Behavior::Exits),
+        Err((ExecError::Dead, _)) => None,
+        Err((err, _)) => Some(Behavior::Error(format!("{}", err))),
+    }
+}
+
+type PathPatterns = Result<(Vec<Pattern>, Vec<Pattern>), String>;
+
+/// The behavior and patterns of each path, for an opcode whose
+/// encodings are given by `universe`
+pub struct PartitionQueue<B> {
+    opcode: Val<B>,
+    universe: Pattern,
+    paths: SegQueue<(Behavior, PathPatterns)>,
+}
+
+impl<B: BV> PartitionQueue<B> {
+    pub fn new(opcode: Val<B>, universe: Pattern) -> Self {
+        PartitionQueue { opcode, universe, paths: SegQueue::new() }
+    }
+}
+
+/// A collector which finds the patterns covering the opcodes taking
+/// each path, for the opcode given to [PartitionQueue::new]. Results
+/// can be grouped using [Partition::from_queue].
+pub fn partition_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &PartitionQueue<B>,
+) {
+    if let Some(behavior) = path_behavior(&result, shared_state, &solver) {
+        let patterns = path_patterns(&mut solver, &collected.opcode, MAX_PATH_PATTERNS).map_err(|err| err.to_string());
+        collected.paths.push((behavior, patterns))
+    }
+}
+
+/// Encodings grouped by behavior
+pub struct Partition {
+    /// The patterns wholly and partially covered by each behavior
+    behaviors: BTreeMap<Behavior, (Vec<Pattern>, Vec<Pattern>)>,
+    /// The encodings which were not covered by any path, e.g. because
+    /// paths were killed or timed out
+    unexplored: Vec<Pattern>,
+    /// Paths for which patterns could not be found
+    failures: Vec<(Behavior, String)>,
+}
+
+impl Partition {
+    pub fn from_queue<B: BV>(queue: &PartitionQueue<B>) -> Self {
+        let mut behaviors: BTreeMap<Behavior, (Vec<Pattern>, Vec<Pattern>)> = BTreeMap::new();
+        let mut failures = Vec::new();
+        while let Some((behavior, patterns)) = queue.paths.pop() {
+            match patterns {
+                Ok((exact, partial)) => {
+                    let (all_exact, all_partial) = behaviors.entry(behavior).or_default();
+                    all_exact.extend(exact);
+                    all_partial.extend(partial)
+                }
+                Err(msg) => failures.push((behavior, msg)),
+            }
+        }
+        failures.sort();
+
+        let mut unexplored = vec![queue.universe];
+        for (exact, partial) in behaviors.values_mut() {
+            *exact = merge_patterns(mem::take(exact));
+            *partial = merge_patterns(mem::take(partial));
+            for pattern in exact.iter().chain(partial.iter()) {
+                unexplored = unexplored.iter().flat_map(|u| u.subtract(pattern)).collect()
+            }
+        }
+
+        Partition { behaviors, unexplored: merge_patterns(unexplored), failures }
+    }
+
+    /// The patterns wholly covered by a behavior
+    pub fn patterns(&self, behavior: &Behavior) -> &[Pattern] {
+        self.behaviors.get(behavior).map(|(exact, _)| exact.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn unexplored(&self) -> &[Pattern] {
+        &self.unexplored
+    }
+}
+
+fn write_patterns(f: &mut fmt::Formatter, patterns: &[Pattern], suffix: &str) -> fmt::Result {
+    for pattern in patterns {
+        writeln!(f, "  {} bits {:#x} mask {:#x}{}", pattern, pattern.bits, pattern.mask, suffix)?
+    }
+    Ok(())
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (behavior, (exact, partial)) in &self.behaviors {
+            writeln!(f, "{}: {} encodings", behavior, exact.iter().map(Pattern::count).sum::<u128>())?;
+            write_patterns(f, exact, "")?;
+            write_patterns(f, partial, " (partially)")?
+        }
+        if !self.unexplored.is_empty() {
+            writeln!(f, "unexplored: {} encodings", self.unexplored.iter().map(Pattern::count).sum::<u128>())?;
+            write_patterns(f, &self.unexplored, "")?
+        }
+        for (behavior, msg) in &self.failures {
+            writeln!(f, "{}: could not find encodings: {}", behavior, msg)?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::Ty;
+
+    fn pattern(s: &str) -> Pattern {
+        let width = s.len() as u32;
+        s.chars().fold(Pattern::any(width), |p, c| match c {
+            'x' => Pattern { mask: p.mask << 1, bits: p.bits << 1, ..p },
+            _ => Pattern { mask: (p.mask << 1) | 1, bits: (p.bits << 1) | u64::from(c == '1'), ..p },
+        })
+    }
+
+    #[test]
+    fn pattern_operations() {
+        let p = pattern("10xx");
+        assert_eq!(p.to_string(), "10xx");
+        assert_eq!(p.count(), 4);
+        assert!(p.matches(0b1011) && !p.matches(0b1111));
+
+        let rest: Vec<String> = pattern("xxxx").subtract(&p).iter().map(Pattern::to_string).collect();
+        assert_eq!(rest, vec!["0xxx", "11xx"]);
+        assert_eq!(p.subtract(&pattern("0xxx")), vec![p]);
+
+        let merged = merge_patterns(vec![pattern("0011"), pattern("0111"), pattern("0x10"), pattern("1111")]);
+        let merged: Vec<String> = merged.iter().map(Pattern::to_string).collect();
+        assert_eq!(merged, vec!["0x1x", "1111"])
+    }
+
+    #[test]
+    fn opcode_patterns() {
+        let info = SourceLoc::unknown();
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(Ty::BitVec(6), info);
+        let opcode = Val::MixedBits(vec![BitsSegment::Concrete(B64::new(0b00, 2)), BitsSegment::Symbolic(v)]);
+        assert_eq!(Pattern::from_opcode(&opcode, &mut solver), Some(pattern("00xxxxxx")));
+
+        // Bits 5 and 4 are not both set
+        let high = Exp::Extract(5, 4, Box::new(Exp::Var(v)));
+        solver.add(Def::Assert(Exp::Neq(Box::new(high), Box::new(bits64(0b11, 2)))));
+        let (exact, partial) = path_patterns(&mut solver, &opcode, MAX_PATH_PATTERNS).unwrap();
+        let exact: Vec<String> = exact.iter().map(Pattern::to_string).collect();
+        assert_eq!(exact, vec!["000xxxxx", "0010xxxx"]);
+        assert!(partial.is_empty());
+
+        // A path condition depending on another variable cannot be
+        // covered exactly
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(Ty::BitVec(4), info);
+        let w = solver.declare_const(Ty::BitVec(4), info);
+        solver.add(Def::Assert(Exp::Bvult(Box::new(Exp::Var(v)), Box::new(Exp::Var(w)))));
+        let (exact, partial) = path_patterns(&mut solver, &Val::Symbolic(v), 4).unwrap();
+        assert!(exact.is_empty());
+        assert!(!partial.is_empty() && partial.iter().all(|p| !p.matches(0b1111)))
+    }
+
+    #[test]
+    fn partition_by_behavior() {
+        let queue = PartitionQueue::<B64>::new(Val::Unit, pattern("xxxx"));
+        let add = Behavior::Calls("execute_add".to_string());
+        queue.paths.push((add.clone(), Ok((vec![pattern("00xx")], vec![]))));
+        queue.paths.push((add.clone(), Ok((vec![pattern("01xx")], vec![]))));
+        queue.paths.push((Behavior::Error("Unallocated".to_string()), Ok((vec![pattern("10xx")], vec![]))));
+
+        let partition = Partition::from_queue(&queue);
+        assert_eq!(partition.patterns(&add), &[pattern("0xxx")]);
+        assert_eq!(partition.unexplored(), &[pattern("11xx")]);
+        assert_eq!(
+            partition.to_string(),
+            "execute_add: 8 encodings\n  0xxx bits 0x0 mask 0x8\n\
+             error: Unallocated: 4 encodings\n  10xx bits 0x8 mask 0xc\n\
+             unexplored: 4 encodings\n  11xx bits 0xc mask 0xc\n"
+        )
+    }
+}
@@ -51,11 +51,13 @@ impl IslaError for VoidError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum ExecError {
     Type(String, SourceLoc),
     VariableNotFound(String),
     Unimplemented,
+    /// A call reached a Sail primitive which has no implementation
+    UnimplementedPrimop(String, SourceLoc),
     AssertionFailure(Option<String>, SourceLoc),
     NoFunction(String, SourceLoc),
     Overflow,
@@ -78,6 +80,24 @@ pub enum ExecError {
     OutOfBounds(&'static str),
     MatchFailure(SourceLoc),
     Timeout,
+    /// The number of instructions executed on a single path exceeded
+    /// the limit set for the task.
+    InstructionLimit,
+    /// The number of instruction cycles on a single path exceeded the
+    /// limit set for the task.
+    CycleLimit,
+    /// The number of forks on a single path exceeded the limit set
+    /// for the task.
+    ForkLimit,
+    /// The time spent in the SMT solver on a single path exceeded the
+    /// limit set for the task.
+    SolverTimeLimit,
+    /// The number of events in the trace of a single path exceeded
+    /// the limit set for the task.
+    EventLimit,
+    /// A path returned to an identical state at a backwards jump, so
+    /// it can never terminate.
+    NonTermination(String),
     Dead,
     Exit,
     NoModel,
@@ -85,6 +105,15 @@ pub enum ExecError {
     Z3Unknown,
     /// Execution stopped because this function is in the stop_functions set
     Stopped(String),
+    /// An event observer asked for the path to be aborted, for the
+    /// given reason
+    Aborted(String),
+    /// A register that is configured to be written before it is read
+    /// was read while still uninitialised.
+    ReadBeforeWrite(String),
+    /// A task which was written to disk while memory usage was over
+    /// its cap could not be read back.
+    TaskLost(String),
 }
 
 impl IslaError for ExecError {
@@ -95,7 +124,8 @@ impl IslaError for ExecError {
             | AssertionFailure(_, info)
             | NoFunction(_, info)
             | SymbolicLength(_, info)
-            | MatchFailure(info) => *info,
+            | MatchFailure(info)
+            | UnimplementedPrimop(_, info) => *info,
             _ => SourceLoc::unknown(),
         }
     }
@@ -108,6 +138,7 @@ impl fmt::Display for ExecError {
             Type(msg, _) => write!(f, "Type error: {}", msg),
             VariableNotFound(v) => write!(f, "Variable {} not found", v),
             Unimplemented => write!(f, "Unimplemented"),
+            UnimplementedPrimop(primop, _) => write!(f, "Primop {} is not implemented", primop),
             AssertionFailure(None, _) => write!(f, "Assertion failure"),
             AssertionFailure(Some(msg), _) => write!(f, "Assertion failure: {}", msg),
             NoFunction(func, _) => write!(f, "Function {} does not exist", func),
@@ -122,12 +153,21 @@ impl fmt::Display for ExecError {
             OutOfBounds(func) => write!(f, "Out of bounds error in {}", func),
             MatchFailure(_) => write!(f, "Pattern match failure"),
             Timeout => write!(f, "Timeout"),
+            InstructionLimit => write!(f, "Instruction limit exceeded"),
+            CycleLimit => write!(f, "Cycle limit exceeded"),
+            ForkLimit => write!(f, "Fork limit exceeded"),
+            SolverTimeLimit => write!(f, "Solver time limit exceeded"),
+            EventLimit => write!(f, "Event limit exceeded"),
+            NonTermination(loc) => write!(f, "Non-termination: state repeated at {}", loc),
             Dead => write!(f, "Dead code found"),
             Exit => write!(f, "Exit called"),
             NoModel => write!(f, "No SMT model found"),
             Z3Error(msg) => write!(f, "SMT solver error: {}", msg),
             Z3Unknown => write!(f, "SMT solver returned unknown"),
             Stopped(func) => write!(f, "Execution stopped at {}", func),
+            Aborted(reason) => write!(f, "Execution aborted: {}", reason),
+            ReadBeforeWrite(reg) => write!(f, "Register {} was read before it was written", reg),
+            TaskLost(msg) => write!(f, "Task could not be restored from disk: {}", msg),
         }
     }
 }
@@ -33,36 +33,71 @@
 
 use crossbeam::deque::{Injector, Steal, Stealer, Worker};
 use crossbeam::queue::SegQueue;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
 use std::mem;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::absint::{Abstract, BranchFacts};
+use crate::affinity;
 use crate::bitvector::{b64::B64, required_index_bits, BV};
+use crate::coverage::Coverage;
 use crate::error::{ExecError, IslaError};
 use crate::ir::*;
 use crate::log;
 use crate::memory::Memory;
 use crate::primop;
-use crate::primop_util::{build_ite, ite_phi, smt_value, symbolic};
+use crate::primop::unimplemented::UnimplementedStats;
+use crate::primop_util::{build_ite, ite_phi, length_bits, smt_value, symbolic};
 use crate::probe;
 use crate::register::*;
+use crate::smt::observer::EventObserver;
+use crate::smt::query_log::QueryLog;
 use crate::smt::smtlib::Def;
+use crate::smt::spill::TraceSink;
+use crate::smt::unknown::{QuerySite, UnknownPolicies, UnknownStats};
 use crate::smt::*;
 use crate::source_loc::SourceLoc;
 use crate::zencode;
 
+pub mod compare;
+pub mod counterexample;
+pub mod interrupt;
+pub mod path_result;
+pub mod prefix;
+pub mod reach;
+pub mod shed;
+pub mod simplify_pool;
+pub mod snapshot;
+
+use interrupt::{Arrival, Interrupt};
+use path_result::{path_results, PathError, PathResult};
+use shed::{MemoryUsage, ShedQueue};
+
 #[derive(Clone)]
 struct LocalState<'ir, B> {
     vars: Bindings<'ir, B>,
     regs: RegisterBindings<'ir, B>,
     lets: Bindings<'ir, B>,
+    /// The number of active calls to functions in
+    /// [SharedState::untraced_functions]. Register accesses are only
+    /// traced when this is zero.
+    untraced_depth: u32,
+}
+
+impl<'ir, B> LocalState<'ir, B> {
+    fn trace_registers(&self) -> bool {
+        self.untraced_depth == 0
+    }
 }
 
 /// Gets a value from a variable `Bindings` map. Note that this function is set up to handle the
@@ -118,7 +153,7 @@ fn get_id_and_initialize<'state, 'ir, B: BV>(
             Some(value) => {
                 let symbol = zencode::decode(shared_state.symtab.to_str(id));
                 // HACK: Don't store the entire TLB in the trace
-                if !for_write && symbol != "_TLB" {
+                if !for_write && symbol != "_TLB" && local_state.trace_registers() {
                     solver.add_event(Event::ReadReg(id, accessor.to_vec(), value.clone()));
                 }
                 Borrowed(value)
@@ -137,6 +172,39 @@ fn get_id_and_initialize<'state, 'ir, B: BV>(
     })
 }
 
+/// Find the bits `hi` down to `lo` occupied by a field of a packed
+/// register, as described by the `[registers.fields]` section of the
+/// ISA config.
+fn packed_field<B: BV>(reg: Name, field: Name, shared_state: &SharedState<B>) -> Option<(u32, u32)> {
+    let fields = shared_state.register_fields.get(&reg)?;
+    let field = zencode::decode(shared_state.symtab.to_str(field));
+    fields.iter().find(|f| f.name == field).map(|f| (f.hi, f.lo))
+}
+
+fn packed_loc_field<B: BV>(loc: &Loc<Name>, field: Name, shared_state: &SharedState<B>) -> Option<(u32, u32)> {
+    match loc {
+        Loc::Id(reg) => packed_field(*reg, field, shared_state),
+        _ => None,
+    }
+}
+
+fn write_packed_field<B: BV>(
+    bits: Val<B>,
+    (hi, lo): (u32, u32),
+    update: Val<B>,
+    solver: &mut Solver<B>,
+    info: SourceLoc,
+) -> Result<Val<B>, ExecError> {
+    let update_length = length_bits(&update, solver, info)?;
+    if update_length != hi - lo + 1 {
+        return Err(ExecError::Type(
+            format!("Cannot write {} bits to packed register field {}:{}", update_length, hi, lo),
+            info,
+        ));
+    }
+    primop::set_slice_internal(bits, Val::I128(lo as i128), update, solver, info)
+}
+
 fn get_loc_and_initialize<'ir, B: BV>(
     loc: &Loc<Name>,
     local_state: &mut LocalState<'ir, B>,
@@ -152,15 +220,17 @@ fn get_loc_and_initialize<'ir, B: BV>(
         }
         Loc::Field(loc, field) => {
             accessor.push(Accessor::Field(*field));
-            if let Val::Struct(members) =
-                get_loc_and_initialize(loc, local_state, shared_state, solver, accessor, info, for_write)?
-            {
-                match members.get(field) {
+            match get_loc_and_initialize(loc, local_state, shared_state, solver, accessor, info, for_write)? {
+                Val::Struct(members) => match members.get(field) {
                     Some(field_value) => field_value.clone(),
                     None => panic!("No field {:?}", shared_state.symtab.to_str(*field)),
-                }
-            } else {
-                panic!("Struct expression did not evaluate to a struct")
+                },
+                bits => match packed_loc_field(loc, *field, shared_state) {
+                    Some((hi, lo)) => {
+                        primop::subrange_internal(bits, Val::I128(hi as i128), Val::I128(lo as i128), solver, info)?
+                    }
+                    None => panic!("Struct expression did not evaluate to a struct"),
+                },
             }
         }
         _ => panic!("Cannot get_loc_and_initialize"),
@@ -237,8 +307,11 @@ fn read_register_from_vector<'state, 'ir, B: BV>(
     match index {
         ConcreteIndex(i) => {
             // This unwrap should be same as all register references must point to value registers
+            let trace = local_state.trace_registers();
             let value = local_state.regs.get(regs[i], shared_state, solver, info)?.unwrap();
-            solver.add_event(Event::ReadReg(regs[i], Vec::new(), value.clone()));
+            if trace {
+                solver.add_event(Event::ReadReg(regs[i], Vec::new(), value.clone()));
+            }
             Ok(value.clone())
         }
         SymbolicIndex(i) => {
@@ -322,7 +395,9 @@ fn write_register_from_vector<'state, 'ir, B: BV>(
         ConcreteIndex(i) => {
             // This unwrap should be same as all register references must point to value registers
             local_state.regs.assign(regs[i], value.clone(), shared_state);
-            solver.add_event(Event::WriteReg(regs[i], Vec::new(), value))
+            if local_state.trace_registers() {
+                solver.add_event(Event::WriteReg(regs[i], Vec::new(), value))
+            }
         }
         SymbolicIndex(i) => {
             let mut reg_values = Vec::new(); 
@@ -369,7 +444,7 @@ fn eval_exp_with_accessor<'state, 'ir, B: BV>(
         Bits(bv) => Owned(Val::Bits(B::new(bv.lower_u64(), bv.len()))),
         String(s) => Owned(Val::String(s.clone())),
 
-        Undefined(ty) => Owned(symbolic(ty, shared_state, solver, info)?),
+        Undefined(ty) => Owned(solver.with_undefined(|solver| symbolic(ty, shared_state, solver, info))?),
 
         Call(op, unevaluated_args) => {
             let mut args: Vec<Val<B>> = Vec::new();
@@ -459,15 +534,27 @@ fn eval_exp_with_accessor<'state, 'ir, B: BV>(
                 },
 
                 non_struct => {
-                    return Err(ExecError::Type(
-                        format!(
-                            "When accessing field {} struct expression {:?} did not evaluate to a struct, instead {}",
-                            shared_state.symtab.to_str(*field),
-                            exp,
-                            non_struct.as_ref().to_string(&shared_state.symtab)
-                        ),
-                        info,
-                    ))
+                    let packed = if let Id(reg) = **exp { packed_field(reg, *field, shared_state) } else { None };
+                    match packed {
+                        Some((hi, lo)) => Owned(primop::subrange_internal(
+                            non_struct.into_owned(),
+                            Val::I128(hi as i128),
+                            Val::I128(lo as i128),
+                            solver,
+                            info,
+                        )?),
+                        None => {
+                            return Err(ExecError::Type(
+                                format!(
+                                    "When accessing field {} struct expression {:?} did not evaluate to a struct, instead {}",
+                                    shared_state.symtab.to_str(*field),
+                                    exp,
+                                    non_struct.as_ref().to_string(&shared_state.symtab)
+                                ),
+                                info,
+                            ))
+                        }
+                    }
                 }
             }
         }
@@ -513,7 +600,7 @@ fn assign_with_accessor<'ir, B: BV>(
             } else {
                 let symbol = zencode::decode(shared_state.symtab.to_str(*id));
                 // HACK: Don't store the entire TLB in the trace
-                if symbol != "_TLB" {
+                if symbol != "_TLB" && local_state.trace_registers() {
                     solver.add_event(Event::WriteReg(*id, accessor.to_vec(), v.clone()))
                 }
                 local_state.regs.assign(*id, v, shared_state);
@@ -521,34 +608,33 @@ fn assign_with_accessor<'ir, B: BV>(
         }
 
         Loc::Field(loc, field) => {
-            if let Val::Struct(field_values) =
-                get_loc_and_initialize(loc, local_state, shared_state, solver, &mut accessor.clone(), info, true)?
-            {
-                accessor.push(Accessor::Field(*field));
-                // As a sanity test, check that the field exists.
-                match field_values.get(field) {
-                    Some(_) => {
-                        let mut field_values = field_values.clone();
-                        field_values.insert(*field, v);
-                        assign_with_accessor(
-                            loc,
-                            Val::Struct(field_values),
-                            local_state,
-                            shared_state,
-                            solver,
-                            accessor,
-                            info,
-                        )?;
+            // Structs take priority over packed register fields, as
+            // when reading a field in get_loc_and_initialize.
+            match get_loc_and_initialize(loc, local_state, shared_state, solver, &mut accessor.clone(), info, true)? {
+                Val::Struct(mut field_values) => {
+                    accessor.push(Accessor::Field(*field));
+                    // As a sanity test, check that the field exists.
+                    if field_values.insert(*field, v).is_none() {
+                        panic!("Invalid field assignment")
                     }
-                    None => panic!("Invalid field assignment"),
+                    assign_with_accessor(
+                        loc,
+                        Val::Struct(field_values),
+                        local_state,
+                        shared_state,
+                        solver,
+                        accessor,
+                        info,
+                    )?
                 }
-            } else {
-                panic!(
-                    "Cannot assign struct to non-struct {:?}.{:?} ({:?})",
-                    loc,
-                    field,
-                    get_loc_and_initialize(loc, local_state, shared_state, solver, &mut accessor.clone(), info, true)
-                )
+                bits => match packed_loc_field(loc, *field, shared_state) {
+                    Some(range) => {
+                        accessor.push(Accessor::Field(*field));
+                        let bits = write_packed_field(bits, range, v, solver, info)?;
+                        assign_with_accessor(loc, bits, local_state, shared_state, solver, accessor, info)?
+                    }
+                    None => panic!("Cannot assign struct to non-struct {:?}.{:?} ({:?})", loc, field, bits),
+                },
             }
         }
 
@@ -599,6 +685,33 @@ type Stack<'ir, B> = Option<
 
 pub type Backtrace = Vec<(Name, usize)>;
 
+/// Set up a closure to restore the caller's state when the function
+/// it calls returns.
+fn return_to<'ir, B: BV>(
+    tid: usize,
+    caller_pc: usize,
+    caller_instrs: &'ir [Instr<Name, B>],
+    loc: &'ir Loc<Name>,
+    info: SourceLoc,
+    untraced: bool,
+    caller_stack_call: Stack<'ir, B>,
+) -> Stack<'ir, B> {
+    Some(Arc::new(move |ret, frame, shared_state, solver| {
+        pop_call_stack(frame);
+        if untraced {
+            frame.local_state.untraced_depth -= 1
+        }
+        // could avoid putting caller_pc into the stack?
+        if let Some((name, _)) = frame.backtrace.pop() {
+            frame.function_name = name;
+        }
+        frame.pc = caller_pc + 1;
+        frame.instrs = caller_instrs;
+        frame.stack_call = caller_stack_call.clone();
+        assign(tid, &loc.clone(), ret, &mut frame.local_state, shared_state, solver, info)
+    }))
+}
+
 /// A `Frame` is an immutable snapshot of the program state while it
 /// is being symbolically executed.
 #[derive(Clone)]
@@ -607,6 +720,10 @@ pub struct Frame<'ir, B> {
     pc: usize,
     forks: u32,
     backjumps: u32,
+    instructions: u64,
+    fork_path: Arc<Vec<u32>>,
+    replay: Arc<Vec<u32>>,
+    visited_states: Arc<HashSet<u64>>,
     local_state: Arc<LocalState<'ir, B>>,
     memory: Arc<Memory<B>>,
     instrs: &'ir [Instr<Name, B>],
@@ -614,6 +731,9 @@ pub struct Frame<'ir, B> {
     stack_call: Stack<'ir, B>,
     backtrace: Arc<Backtrace>,
     function_assumptions: Arc<HashMap<Name, Vec<(Vec<Val<B>>, Val<B>)>>>,
+    suspended: bool,
+    resuming_watchpoint: bool,
+    fork_choice: Option<(Sym, u64)>,
 }
 
 /// A `LocalFrame` is a mutable frame which is used by a currently
@@ -624,6 +744,14 @@ pub struct LocalFrame<'ir, B> {
     pc: usize,
     forks: u32,
     backjumps: u32,
+    instructions: u64,
+    /// Shared with frozen copies of the frame, so it is only copied
+    /// when a path which has forked takes another branch
+    fork_path: Arc<Vec<u32>>,
+    replay: Arc<Vec<u32>>,
+    /// Shared with frozen copies of the frame, and only copied when
+    /// loop detection adds a new state
+    visited_states: Arc<HashSet<u64>>,
     local_state: LocalState<'ir, B>,
     memory: Memory<B>,
     instrs: &'ir [Instr<Name, B>],
@@ -631,6 +759,9 @@ pub struct LocalFrame<'ir, B> {
     stack_call: Stack<'ir, B>,
     backtrace: Backtrace,
     function_assumptions: HashMap<Name, Vec<(Vec<Val<B>>, Val<B>)>>,
+    suspended: bool,
+    resuming_watchpoint: bool,
+    fork_choice: Option<(Sym, u64)>,
 }
 
 pub fn unfreeze_frame<'ir, B: BV>(frame: &Frame<'ir, B>) -> LocalFrame<'ir, B> {
@@ -639,6 +770,10 @@ pub fn unfreeze_frame<'ir, B: BV>(frame: &Frame<'ir, B>) -> LocalFrame<'ir, B> {
         pc: frame.pc,
         forks: frame.forks,
         backjumps: frame.backjumps,
+        instructions: frame.instructions,
+        fork_path: frame.fork_path.clone(),
+        replay: frame.replay.clone(),
+        visited_states: frame.visited_states.clone(),
         local_state: (*frame.local_state).clone(),
         memory: (*frame.memory).clone(),
         instrs: frame.instrs,
@@ -646,6 +781,9 @@ pub fn unfreeze_frame<'ir, B: BV>(frame: &Frame<'ir, B>) -> LocalFrame<'ir, B> {
         stack_call: frame.stack_call.clone(),
         backtrace: (*frame.backtrace).clone(),
         function_assumptions: (*frame.function_assumptions).clone(),
+        suspended: frame.suspended,
+        resuming_watchpoint: frame.resuming_watchpoint,
+        fork_choice: frame.fork_choice,
     }
 }
 
@@ -655,6 +793,10 @@ pub fn freeze_frame<'ir, B: BV>(frame: &LocalFrame<'ir, B>) -> Frame<'ir, B> {
         pc: frame.pc,
         forks: frame.forks,
         backjumps: frame.backjumps,
+        instructions: frame.instructions,
+        fork_path: frame.fork_path.clone(),
+        replay: frame.replay.clone(),
+        visited_states: frame.visited_states.clone(),
         local_state: Arc::new(frame.local_state.clone()),
         memory: Arc::new(frame.memory.clone()),
         instrs: frame.instrs,
@@ -662,10 +804,152 @@ pub fn freeze_frame<'ir, B: BV>(frame: &LocalFrame<'ir, B>) -> Frame<'ir, B> {
         stack_call: frame.stack_call.clone(),
         backtrace: Arc::new(frame.backtrace.clone()),
         function_assumptions: Arc::new(frame.function_assumptions.clone()),
+        suspended: frame.suspended,
+        resuming_watchpoint: frame.resuming_watchpoint,
+        fork_choice: frame.fork_choice,
+    }
+}
+
+impl<'ir, B: BV> Frame<'ir, B> {
+    /// The function currently being executed
+    pub fn function_name(&self) -> Name {
+        self.function_name
+    }
+
+    /// The index of the next instruction to execute in the current
+    /// function
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
     }
 }
 
 impl<'ir, B: BV> LocalFrame<'ir, B> {
+    /// The function currently being executed
+    pub fn function_name(&self) -> Name {
+        self.function_name
+    }
+
+    /// Set the function to execute, starting from its first
+    /// instruction
+    pub fn set_function(&mut self, name: Name, instrs: &'ir [Instr<Name, B>]) -> &mut Self {
+        self.function_name = name;
+        self.instrs = instrs;
+        self.pc = 0;
+        self
+    }
+
+    /// The index of the next instruction to execute in the current
+    /// function
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Start execution from a specific instruction in the current
+    /// function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pc` is not an instruction of the current function.
+    pub fn set_pc(&mut self, pc: usize) -> &mut Self {
+        assert!(pc < self.instrs.len(), "pc {} out of range for function with {} instructions", pc, self.instrs.len());
+        self.pc = pc;
+        self
+    }
+
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+
+    /// True if the path was stopped before a call by
+    /// [StopAction::Suspend], or before a memory access by a
+    /// suspending watchpoint, rather than returning from its top-level
+    /// function. A suspended frame resumes with that call or access
+    /// when it is turned back into a task.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Suspend the path at the current instruction, which will be
+    /// re-executed when the frame is resumed. Used by primops that
+    /// stop a path before a memory access hits a watchpoint, so the
+    /// re-executed access is not checked against the watchpoints
+    /// again.
+    pub(crate) fn suspend(&mut self) {
+        self.suspended = true;
+        self.resuming_watchpoint = true
+    }
+
+    /// True if the frame is resuming from a watchpoint suspension and
+    /// has not yet re-executed the access, which is then allowed to
+    /// proceed.
+    pub(crate) fn take_resuming_watchpoint(&mut self) -> bool {
+        mem::take(&mut self.resuming_watchpoint)
+    }
+
+    /// Bind a local variable to a value
+    pub fn insert_local(&mut self, name: Name, val: Val<B>) -> &mut Self {
+        self.local_state.vars.insert(name, UVal::Init(val));
+        self
+    }
+
+    /// Get the value of a local variable, if it has been initialised
+    pub fn get_local(&self, name: Name) -> Option<&Val<B>> {
+        match self.local_state.vars.get(&name) {
+            Some(UVal::Init(val)) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a register, if it has been initialised. Unlike
+    /// [RegisterBindings::get] this never creates a symbolic value.
+    pub fn get_register(&self, name: Name) -> Option<&Val<B>> {
+        self.local_state.regs.get_last_if_initialized(name)
+    }
+
+    /// The value returned by the current function, if it has been
+    /// set
+    pub fn return_value(&self) -> Option<&Val<B>> {
+        self.get_local(RETURN)
+    }
+
+    /// Print the current function and position, along with the
+    /// initialised local variables and registers, sorted by name.
+    pub fn pretty(&self, buf: &mut dyn Write, symtab: &Symtab) -> std::io::Result<()> {
+        let name = |id: Name| zencode::decode(symtab.to_str(id));
+
+        writeln!(buf, "{} @ {}", name(self.function_name), self.pc)?;
+
+        let mut vars: Vec<(String, &Val<B>)> = self
+            .local_state
+            .vars
+            .iter()
+            .filter_map(|(id, uval)| match uval {
+                UVal::Init(val) => Some((name(*id), val)),
+                UVal::Uninit(_) => None,
+            })
+            .collect();
+        vars.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+        for (var, val) in vars {
+            writeln!(buf, "  {} = {}", var, val.to_string(symtab))?
+        }
+
+        let mut regs: Vec<(String, &Val<B>)> = self
+            .local_state
+            .regs
+            .iter()
+            .filter_map(|(id, _)| self.get_register(*id).map(|val| (name(*id), val)))
+            .collect();
+        regs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+        for (reg, val) in regs {
+            writeln!(buf, "  register {} = {}", reg, val.to_string(symtab))?
+        }
+        Ok(())
+    }
+
     pub fn vars_mut(&mut self) -> &mut Bindings<'ir, B> {
         &mut self.local_state.vars
     }
@@ -674,10 +958,37 @@ impl<'ir, B: BV> LocalFrame<'ir, B> {
         &self.local_state.vars
     }
 
+    /// The number of instructions executed on the current path
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
     pub fn regs_mut(&mut self) -> &mut RegisterBindings<'ir, B> {
         &mut self.local_state.regs
     }
 
+    /// Give every register its initial value now, rather than when it
+    /// is first read, so that tasks which run different functions from
+    /// a checkpoint taken afterwards start with the same registers.
+    /// Registers which must be written before they are read are left
+    /// uninitialized.
+    pub fn initialize_registers(
+        &mut self,
+        shared_state: &SharedState<'ir, B>,
+        solver: &mut Solver<B>,
+    ) -> Result<&mut Self, ExecError> {
+        let info = SourceLoc::unknown();
+        let mut names: Vec<Name> = self.local_state.regs.iter().map(|(name, _)| *name).collect();
+        names.sort();
+        for name in names {
+            match self.local_state.regs.get(name, shared_state, solver, info) {
+                Ok(_) | Err(ExecError::ReadBeforeWrite(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self)
+    }
+
     pub fn regs(&self) -> &RegisterBindings<'ir, B> {
         &self.local_state.regs
     }
@@ -769,13 +1080,20 @@ impl<'ir, B: BV> LocalFrame<'ir, B> {
             pc: 0,
             forks: 0,
             backjumps: 0,
-            local_state: LocalState { vars, regs, lets },
+            instructions: 0,
+            fork_path: Arc::new(Vec::new()),
+            replay: Arc::new(Vec::new()),
+            visited_states: Arc::new(HashSet::new()),
+            local_state: LocalState { vars, regs, lets, untraced_depth: 0 },
             memory: Memory::new(),
             instrs,
             stack_vars: Vec::new(),
             stack_call: None,
             backtrace: Vec::new(),
             function_assumptions: HashMap::new(),
+            suspended: false,
+            resuming_watchpoint: false,
+            fork_choice: None,
         }
     }
 
@@ -789,6 +1107,7 @@ impl<'ir, B: BV> LocalFrame<'ir, B> {
     ) -> Self {
         let mut new_frame = LocalFrame::new(name, args, ret_ty, vals, instrs);
         new_frame.forks = self.forks;
+        new_frame.instructions = self.instructions;
         new_frame.local_state.regs = self.local_state.regs.clone();
         new_frame.local_state.lets = self.local_state.lets.clone();
         new_frame.memory = self.memory.clone();
@@ -801,7 +1120,9 @@ impl<'ir, B: BV> LocalFrame<'ir, B> {
         state: &'task TaskState<B>,
         checkpoint: Checkpoint<B>,
     ) -> Task<'ir, 'task, B> {
-        Task { id: task_id, frame: freeze_frame(self), checkpoint, fork_cond: None, state, stop_conditions: None }
+        let mut frame = freeze_frame(self);
+        frame.suspended = false;
+        Task { id: task_id, frame, checkpoint, fork_cond: None, state, stop_conditions: None }
     }
 
     pub fn task<'task>(&self, task_id: usize, state: &'task TaskState<B>) -> Task<'ir, 'task, B> {
@@ -854,7 +1175,7 @@ fn smt_exp_to_value<B: BV>(exp: smtlib::Exp<Sym>, solver: &mut Solver<B>) -> Res
 }
 
 pub fn reset_registers<'ir, 'task, B: BV>(
-    _tid: usize,
+    tid: usize,
     frame: &mut LocalFrame<'ir, B>,
     task_state: &'task TaskState<B>,
     shared_state: &SharedState<'ir, B>,
@@ -882,14 +1203,18 @@ pub fn reset_registers<'ir, 'task, B: BV>(
             solver.add_event(Event::AssumeReg(reg_id, accessor, value));
         }
     }
-    for (loc, reset) in &task_state.reset_registers {
+    // Registers are reset in a fixed order so the variables they
+    // declare are numbered the same way in every run
+    let mut task_resets: Vec<_> = task_state.reset_registers.iter().collect();
+    task_resets.sort_by(|(loc1, _), (loc2, _)| loc1.cmp(loc2));
+    for (loc, reset) in task_resets {
         let value = reset(&frame.memory, shared_state.typedefs(), solver)?;
         let mut accessor = Vec::new();
         assign_with_accessor(loc, value.clone(), &mut frame.local_state, shared_state, solver, &mut accessor, info)?;
         solver.add_event(Event::AssumeReg(loc.id(), accessor, value));
     }
     if !shared_state.reset_constraints.is_empty() {
-        for constraint in &shared_state.reset_constraints {
+        for (i, constraint) in shared_state.reset_constraints.iter().enumerate() {
             let mut lookup = |s| match shared_state.symtab.get_loc(s) {
                 Some(loc) => {
                     let value = get_loc_and_initialize(
@@ -908,9 +1233,11 @@ pub fn reset_registers<'ir, 'task, B: BV>(
             };
             let assertion_exp = constraint.map_var(&mut lookup).map_err(ExecError::Unreachable)?;
             solver.add_event(Event::Assume(constraint.clone()));
-            solver.add(Def::Assert(assertion_exp));
+            // The traces of every thread in a litmus test end up in
+            // the same SMT file, so the names must include the thread
+            solver.assume(format!("thread{}_reset_constraint_{}", tid, i), assertion_exp);
         }
-        if solver.check_sat().is_unsat()? {
+        if !solver.is_possible(QuerySite::Feasibility, None)? {
             return Err(ExecError::Dead);
         }
     }
@@ -965,6 +1292,7 @@ pub struct StopConditions {
 pub enum StopAction {
     Kill,     // Remove entire trace
     Abstract, // Keep trace, put abstract call at end
+    Suspend,  // Keep trace, end the path just before the call so it can be resumed
 }
 
 impl StopConditions {
@@ -1048,14 +1376,24 @@ fn run<'ir, 'task, B: BV>(
     task_id: usize,
     timeout: Timeout,
     stop_conditions: Option<&'task StopConditions>,
-    queue: &Worker<Task<'ir, 'task, B>>,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
     frame: &Frame<'ir, B>,
     task_state: &'task TaskState<B>,
     shared_state: &SharedState<'ir, B>,
     solver: &mut Solver<B>,
 ) -> Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)> {
     let mut frame = unfreeze_frame(frame);
-    match run_loop(tid, task_id, timeout, stop_conditions, queue, &mut frame, task_state, shared_state, solver) {
+    if let Some(usage) = &task_state.memory_usage {
+        usage.set_worker(tid, shed::frame_size(&frame), solver.approximate_size())
+    }
+    let result = run_loop(tid, task_id, timeout, stop_conditions, queue, &mut frame, task_state, shared_state, solver);
+    if let Some(usage) = &task_state.memory_usage {
+        usage.set_worker(tid, 0, 0)
+    }
+    if let (Err(ExecError::UnimplementedPrimop(primop, _)), Some(stats)) = (&result, &task_state.unimplemented_stats) {
+        stats.record(primop)
+    }
+    match result {
         Ok(v) => Ok((v, frame)),
         Err(err) => {
             frame.backtrace.push((frame.function_name, frame.pc));
@@ -1064,6 +1402,154 @@ fn run<'ir, 'task, B: BV>(
     }
 }
 
+/// Summarize a call to a recursive function which has reached its
+/// unrolling bound (see [Widening]). The call is replaced by a fresh
+/// symbolic return value, and the registers in its frame condition
+/// are given fresh symbolic values. This is sound as long as the
+/// function really only writes those registers, and properties do
+/// not depend on the precise value it returns.
+#[allow(clippy::too_many_arguments)]
+fn widen_call<'ir, B: BV>(
+    tid: usize,
+    loc: &Loc<Name>,
+    f: Name,
+    args: Vec<Val<B>>,
+    ret_ty: &Ty<Name>,
+    widening: &Widening,
+    info: SourceLoc,
+    frame: &mut LocalFrame<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<(), ExecError> {
+    log_from!(
+        tid,
+        log::VERBOSE,
+        format!(
+            "Widening call to {} after {} unrollings",
+            zencode::decode(shared_state.symtab.to_str(f)),
+            widening.unroll
+        )
+    );
+    for reg in &widening.modifies {
+        let Some(ty) = shared_state.registers.get(reg) else {
+            return Err(ExecError::Unreachable(format!(
+                "widened function {} modifies unknown register {}",
+                zencode::decode(shared_state.symtab.to_str(f)),
+                zencode::decode(shared_state.symtab.to_str(*reg))
+            )))
+        };
+        let value = symbolic(ty, shared_state, solver, info)?;
+        assign(tid, &Loc::Id(*reg), value, &mut frame.local_state, shared_state, solver, info)?
+    }
+    let return_value = symbolic(ret_ty, shared_state, solver, info)?;
+    solver.add_event(Event::Abstract { name: f, primitive: false, args, return_value: return_value.clone() });
+    assign(tid, loc, return_value, &mut frame.local_state, shared_state, solver, info)?;
+    frame.pc += 1;
+    Ok(())
+}
+
+/// Run a call to a function declared as an abstract primitive in the
+/// ISA config. The arguments are evaluated, but the function itself
+/// is not executed; instead we create a fresh symbolic return value
+/// of the declared type and record an abstract event.
+#[allow(clippy::too_many_arguments)]
+fn run_abstract_primitive<'ir, B: BV>(
+    loc: &Loc<Name>,
+    f: Name,
+    args: &[Exp<Name>],
+    info: SourceLoc,
+    tid: usize,
+    frame: &mut LocalFrame<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<(), ExecError> {
+    let (arity, return_ty) = &shared_state.abstract_primitives[&f];
+    if args.len() != *arity {
+        return Err(ExecError::Type(
+            format!(
+                "abstract primitive {} called with {} arguments, expected {}",
+                zencode::decode(shared_state.symtab.to_str(f)),
+                args.len(),
+                arity
+            ),
+            info,
+        ));
+    }
+    let args = args
+        .iter()
+        .map(|arg| eval_exp(arg, &mut frame.local_state, shared_state, solver, info).map(Cow::into_owned))
+        .collect::<Result<Vec<Val<B>>, _>>()?;
+    let return_value = symbolic(return_ty, shared_state, solver, info)?;
+    solver.add_event(Event::Abstract { name: f, primitive: true, args, return_value: return_value.clone() });
+    assign(tid, loc, return_value, &mut frame.local_state, shared_state, solver, info)?;
+    frame.pc += 1;
+    Ok(())
+}
+
+/// Deliver any interrupts that may arrive at the instruction boundary
+/// the frame has just reached, see [interrupt].
+fn inject_interrupts<'ir, B: BV>(
+    tid: usize,
+    frame: &mut LocalFrame<'ir, B>,
+    interrupts: &[Interrupt<B>],
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+    info: SourceLoc,
+) -> Result<(), ExecError> {
+    let boundary = solver.get_cycle_count() as u64 - 1;
+
+    for (index, interrupt) in interrupts.iter().enumerate() {
+        if !interrupt.arrival.may_arrive_at(boundary) {
+            continue;
+        }
+        let arrives = match interrupt.arrival {
+            Arrival::At(_) => None,
+            Arrival::Symbolic { from, to } => {
+                use smtlib::Exp::*;
+                let index = index as u32;
+                // The arrival point is chosen once per path when the
+                // window is entered, so the interrupt arrives at most
+                // once.
+                let arrival = match solver.interrupt_arrival(index) {
+                    Some(arrival) => arrival,
+                    None => {
+                        let arrival = solver.declare_const(smtlib::Ty::BitVec(64), info);
+                        solver.assert(And(
+                            Box::new(Bvule(Box::new(Bits64(B64::new(from, 64))), Box::new(Var(arrival)))),
+                            Box::new(Bvule(Box::new(Var(arrival)), Box::new(Bits64(B64::new(to, 64))))),
+                        ));
+                        solver.add_event(Event::Interrupt { index, arrival });
+                        arrival
+                    }
+                };
+                Some(solver.define_const(Eq(Box::new(Var(arrival)), Box::new(Bits64(B64::new(boundary, 64)))), info))
+            }
+        };
+        for (loc, value) in &interrupt.assignments {
+            let value = match arrives {
+                Some(arrives) => {
+                    let current = get_loc_and_initialize(
+                        loc,
+                        &mut frame.local_state,
+                        shared_state,
+                        solver,
+                        &mut Vec::new(),
+                        info,
+                        true,
+                    )?;
+                    build_ite(arrives, value, &current, solver, info)?
+                }
+                None => value.clone(),
+            };
+            assign(tid, loc, value, &mut frame.local_state, shared_state, solver, info)?
+        }
+        if interrupt.wakeup {
+            solver.add_event(Event::WakeupRequest)
+        }
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 fn run_special_primop<'ir, 'task, B: BV>(
     loc: &Loc<Name>,
@@ -1143,9 +1629,12 @@ fn run_special_primop<'ir, 'task, B: BV>(
         frame.pc += 1
     } else if f == REG_DEREF && args.len() == 1 {
         if let Val::Ref(reg) = eval_exp(&args[0], &mut frame.local_state, shared_state, solver, info)?.into_owned() {
+            let trace = frame.local_state.trace_registers();
             match frame.regs_mut().get(reg, shared_state, solver, info)? {
                 Some(value) => {
-                    solver.add_event(Event::ReadReg(reg, Vec::new(), value.clone()));
+                    if trace {
+                        solver.add_event(Event::ReadReg(reg, Vec::new(), value.clone()));
+                    }
                     assign(tid, loc, value.clone(), &mut frame.local_state, shared_state, solver, info)?
                 }
                 None => return Err(ExecError::Type(format!("reg_deref {:?}", &reg), info)),
@@ -1197,6 +1686,8 @@ fn run_special_primop<'ir, 'task, B: BV>(
         let arg = eval_exp(&args[0], &mut frame.local_state, shared_state, solver, info)?.into_owned();
         assign(tid, loc, Val::Ctor(f, Box::new(arg)), &mut frame.local_state, shared_state, solver, info)?;
         frame.pc += 1
+    } else if let Some((_, _, primop)) = shared_state.externs.get(&f) {
+        return Err(ExecError::UnimplementedPrimop(primop.to_string(), info));
     } else {
         let symbol = zencode::decode(shared_state.symtab.to_str(f));
         return Err(ExecError::NoFunction(symbol, info));
@@ -1204,76 +1695,491 @@ fn run_special_primop<'ir, 'task, B: BV>(
     Ok(())
 }
 
+fn hash_val<B: BV, H: Hasher>(v: &Val<B>, state: &mut H) {
+    use Val::*;
+    mem::discriminant(v).hash(state);
+    match v {
+        Symbolic(v) => v.hash(state),
+        I64(n) => n.hash(state),
+        I128(n) => n.hash(state),
+        Bool(b) => b.hash(state),
+        Bits(bv) => bv.hash(state),
+        MixedBits(segments) => {
+            for segment in segments {
+                match segment {
+                    BitsSegment::Symbolic(v) => v.hash(state),
+                    BitsSegment::Concrete(bv) => bv.hash(state),
+                }
+            }
+        }
+        String(s) => s.hash(state),
+        Unit | Poison => (),
+        Vector(vals) | List(vals) => vals.iter().for_each(|v| hash_val(v, state)),
+        Enum(EnumMember { enum_id, member }) => {
+            enum_id.to_usize().hash(state);
+            member.hash(state)
+        }
+        Struct(fields) => {
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_by_key(|(k, _)| **k);
+            for (k, v) in fields {
+                k.hash(state);
+                hash_val(v, state)
+            }
+        }
+        Ctor(ctor, v) => {
+            ctor.hash(state);
+            hash_val(v, state)
+        }
+        SymbolicCtor(v, possibilities) => {
+            v.hash(state);
+            let mut possibilities: Vec<_> = possibilities.iter().collect();
+            possibilities.sort_by_key(|(k, _)| **k);
+            for (k, v) in possibilities {
+                k.hash(state);
+                hash_val(v, state)
+            }
+        }
+        Ref(reg) => reg.hash(state),
+    }
+}
+
+fn hash_bindings<B: BV, H: Hasher>(bindings: &Bindings<'_, B>, state: &mut H) {
+    let mut bindings: Vec<_> = bindings.iter().collect();
+    bindings.sort_by_key(|(k, _)| **k);
+    for (k, uval) in bindings {
+        k.hash(state);
+        match uval {
+            UVal::Uninit(_) => false.hash(state),
+            UVal::Init(v) => {
+                true.hash(state);
+                hash_val(v, state)
+            }
+        }
+    }
+}
+
+/// Hash the parts of a frame that determine how it will continue
+/// executing, used for loop detection.
+fn hash_frame_state<B: BV>(frame: &LocalFrame<'_, B>) -> u64 {
+    let mut state = DefaultHasher::new();
+    frame.function_name.hash(&mut state);
+    frame.pc.hash(&mut state);
+    frame.backtrace.hash(&mut state);
+    hash_bindings(&frame.local_state.vars, &mut state);
+    for vars in &frame.stack_vars {
+        hash_bindings(vars, &mut state)
+    }
+    let mut regs: Vec<Name> = frame.local_state.regs.iter().map(|(id, _)| *id).collect();
+    regs.sort();
+    for id in regs {
+        id.hash(&mut state);
+        if let Some(v) = frame.local_state.regs.get_last_if_initialized(id) {
+            hash_val(v, &mut state)
+        }
+    }
+    state.finish()
+}
+
+/// Find a local variable containing a symbolic enumeration that is
+/// compared against an enum member in a jump condition.
+fn symbolic_enum_comparison<B: BV>(
+    exp: &Exp<Name>,
+    frame: &LocalFrame<'_, B>,
+    shared_state: &SharedState<'_, B>,
+    solver: &mut Solver<B>,
+) -> Option<(Name, Sym, EnumId)> {
+    match exp {
+        Exp::Call(Op::Not, args) if args.len() == 1 => symbolic_enum_comparison(&args[0], frame, shared_state, solver),
+        Exp::Call(Op::Eq | Op::Neq, args) if args.len() == 2 => {
+            for (x, member) in [(&args[0], &args[1]), (&args[1], &args[0])] {
+                if let (Exp::Id(id), Exp::Id(member)) = (x, member) {
+                    if let (Some(UVal::Init(Val::Symbolic(v))), Some((_, enum_size))) =
+                        (frame.vars().get(id), shared_state.enum_members.get(member))
+                    {
+                        return Some((*id, *v, solver.get_enum(*enum_size)));
+                    }
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Fork the execution once for each value in `choices` that the
+/// symbolic variable `v` could take, where `choice_is` gives the
+/// condition that `v` has a particular value, returning the value for
+/// the current path. The other paths are pushed onto the queue to
+/// resume at the current instruction with their value already known,
+/// so they do not query the solver again.
 #[allow(clippy::too_many_arguments)]
-fn run_loop<'ir, 'task, B: BV>(
+fn fork_choices<'ir, 'task, B: BV, F>(
+    v: Sym,
+    choices: impl Iterator<Item = u64>,
+    choice_is: F,
+    description: &str,
+    info: SourceLoc,
     tid: usize,
     task_id: usize,
-    timeout: Timeout,
-    stop_conditions: Option<&'task StopConditions>,
-    queue: &Worker<Task<'ir, 'task, B>>,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
     frame: &mut LocalFrame<'ir, B>,
     task_state: &'task TaskState<B>,
+    stop_conditions: Option<&'task StopConditions>,
     shared_state: &SharedState<'ir, B>,
     solver: &mut Solver<B>,
-) -> Result<Val<B>, ExecError> {
-    'main_loop: loop {
-        if frame.pc >= frame.instrs.len() {
-            // Currently this happens when evaluating letbindings.
-            return Ok(Val::Unit);
-        }
+) -> Result<u64, ExecError>
+where
+    F: Fn(u64) -> smtlib::Exp<Sym>,
+{
+    use smtlib::Def::*;
 
-        if timeout.timed_out() {
-            return Err(ExecError::Timeout);
+    // A path forked from this choice already has its condition asserted
+    if let Some((fork_v, choice)) = frame.fork_choice.take() {
+        if fork_v == v {
+            return Ok(choice);
         }
+    }
 
-        match &frame.instrs[frame.pc] {
-            Instr::Decl(v, ty, _) => {
-                frame.vars_mut().insert(*v, UVal::Uninit(ty));
-                frame.pc += 1;
-            }
+    let mut possible = Vec::new();
+    for choice in choices {
+        if solver.is_possible(QuerySite::Branch, Some(&choice_is(choice)))? {
+            possible.push(choice)
+        }
+    }
 
-            Instr::Init(var, _, exp, info) => {
-                let value = eval_exp(exp, &mut frame.local_state, shared_state, solver, *info)?.into_owned();
-                frame.vars_mut().insert(*var, UVal::Init(value));
-                frame.pc += 1;
-            }
+    let (first, rest) = possible.split_first().ok_or(ExecError::Dead)?;
 
-            Instr::Jump(exp, target, info) => {
-                let value = eval_exp(exp, &mut frame.local_state, shared_state, solver, *info)?;
-                match *value.as_ref() {
-                    Val::Symbolic(v) => {
-                        use smtlib::Def::*;
-                        use smtlib::Exp::*;
+    // When replaying a path, just follow the recorded choice
+    if let Some(n) = frame.replay.get(frame.fork_path.len()).copied().filter(|_| !rest.is_empty()) {
+        let choice = *possible.get(n as usize).ok_or(ExecError::Dead)?;
+        Arc::make_mut(&mut frame.fork_path).push(n);
+        solver.add(Assert(choice_is(choice)));
+        return Ok(choice);
+    }
 
-                        let test_true = Var(v);
-                        let test_false = Not(Box::new(Var(v)));
-                        let can_be_true = solver.check_sat_with(&test_true).is_sat()?;
-                        let can_be_false = solver.check_sat_with(&test_false).is_sat()?;
+    if !rest.is_empty() {
+        if_logging!(log::FORK, {
+            log_from!(
+                tid,
+                log::FORK,
+                &format!("{} {}", description, info.location_string(shared_state.symtab.files()))
+            );
+            probe::taint_info(log::FORK, v, Some(shared_state), solver)
+        });
 
-                        if can_be_true && can_be_false {
-                            if_logging!(log::FORK, {
-                                log_from!(tid, log::FORK, &format!("{}", info.location_string(shared_state.symtab.files())));
-                                probe::taint_info(log::FORK, v, Some(shared_state), solver)
-                            });
+        let point = checkpoint(solver);
+        let frozen = freeze_frame(frame);
+        frame.forks += 1;
+        for (n, choice) in rest.iter().enumerate() {
+            let mut child = frozen.clone();
+            if task_state.tracks_fork_paths() || !frozen.replay.is_empty() {
+                let mut child_path = frozen.fork_path.to_vec();
+                child_path.push(n as u32 + 1);
+                if let Some(fork_log) = &task_state.fork_log {
+                    if !fork_log.spawn(&child_path) {
+                        continue;
+                    }
+                }
+                if let Some(donor) = &task_state.fork_donor {
+                    if donor.donate(&child_path) {
+                        continue;
+                    }
+                }
+                child.fork_path = Arc::new(child_path)
+            }
+            child.fork_choice = Some((v, *choice));
+            queue.push(Task {
+                id: task_id,
+                frame: child,
+                checkpoint: point.clone(),
+                fork_cond: Some((Assert(choice_is(*choice)), Event::Fork(frame.forks - 1, v, n as u32 + 1, info))),
+                state: task_state,
+                stop_conditions,
+            })
+        }
 
-                            let point = checkpoint(solver);
-                            let frozen = Frame { pc: frame.pc + 1, ..freeze_frame(frame) };
-                            frame.forks += 1;
-                            queue.push(Task {
-                                id: task_id,
-                                frame: frozen,
-                                checkpoint: point,
-                                fork_cond: Some((Assert(test_false), Event::Fork(frame.forks - 1, v, 1, *info))),
-                                state: task_state,
-                                stop_conditions,
-                            });
+        // Drop our reference to the path first, so it is not copied
+        drop(frozen);
+        solver.add_event(Event::Fork(frame.forks - 1, v, 0, info));
+        solver.add(Assert(choice_is(*first)));
+        if task_state.tracks_fork_paths() || !frame.replay.is_empty() {
+            Arc::make_mut(&mut frame.fork_path).push(0)
+        }
+    }
 
-                            // Track which asserts are assocated with each fork in the trace, so we
-                            // can turn a set of traces into a tree later
-                            solver.add_event(Event::Fork(frame.forks - 1, v, 0, *info));
+    Ok(*first)
+}
 
-                            solver.add(Assert(test_true));
-                            frame.pc = *target
+/// Fork the execution once for each possible value of the symbolic
+/// enumeration `v` held in local variable `id`. The current path
+/// continues with the first possible member, and the other paths are
+/// pushed onto the queue to resume at the current instruction with
+/// their member.
+#[allow(clippy::too_many_arguments)]
+fn split_symbolic_enum<'ir, 'task, B: BV>(
+    id: Name,
+    v: Sym,
+    enum_id: EnumId,
+    info: SourceLoc,
+    tid: usize,
+    task_id: usize,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
+    frame: &mut LocalFrame<'ir, B>,
+    task_state: &'task TaskState<B>,
+    stop_conditions: Option<&'task StopConditions>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<(), ExecError> {
+    use smtlib::Exp::*;
+
+    let member_is = |member: u64| Eq(Box::new(Var(v)), Box::new(Enum(EnumMember { enum_id, member: member as usize })));
+    let member = fork_choices(
+        v,
+        0..enum_id.to_usize() as u64,
+        member_is,
+        "enum split",
+        info,
+        tid,
+        task_id,
+        queue,
+        frame,
+        task_state,
+        stop_conditions,
+        shared_state,
+        solver,
+    )?;
+
+    frame.vars_mut().insert(id, UVal::Init(Val::Enum(EnumMember { enum_id, member: member as usize })));
+    Ok(())
+}
+
+/// Fork the execution once for each register in a register vector of
+/// length `num_regs` that the symbolic index `v` could select,
+/// returning the concrete index for the current path. The other paths
+/// are pushed onto the queue to resume at the current instruction
+/// with their index.
+#[allow(clippy::too_many_arguments)]
+fn split_register_index<'ir, 'task, B: BV>(
+    v: Sym,
+    num_regs: usize,
+    info: SourceLoc,
+    tid: usize,
+    task_id: usize,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
+    frame: &mut LocalFrame<'ir, B>,
+    task_state: &'task TaskState<B>,
+    stop_conditions: Option<&'task StopConditions>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<Val<B>, ExecError> {
+    use smtlib::bits64;
+    use smtlib::Exp::*;
+
+    let len = solver
+        .length(v)
+        .ok_or_else(|| ExecError::Type("register vector index must be a bitvector".to_string(), info))?;
+    // Integers are 128-bit bitvectors, so wider indices are zero extended constants
+    let index_is = |i: u64| {
+        let i = if len <= 64 { bits64(i, len) } else { ZeroExtend(len - 64, Box::new(bits64(i, 64))) };
+        Eq(Box::new(Var(v)), Box::new(i))
+    };
+    let index = fork_choices(
+        v,
+        (0..num_regs as u64).filter(|i| len >= 64 || i >> len == 0),
+        index_is,
+        "register index split",
+        info,
+        tid,
+        task_id,
+        queue,
+        frame,
+        task_state,
+        stop_conditions,
+        shared_state,
+        solver,
+    )?;
+
+    Ok(Val::I128(index as i128))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_loop<'ir, 'task, B: BV>(
+    tid: usize,
+    task_id: usize,
+    timeout: Timeout,
+    stop_conditions: Option<&'task StopConditions>,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
+    frame: &mut LocalFrame<'ir, B>,
+    task_state: &'task TaskState<B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<Val<B>, ExecError> {
+    let mut last_position = (frame.function_name, frame.pc);
+
+    'main_loop: loop {
+        if frame.pc >= frame.instrs.len() {
+            // Currently this happens when evaluating letbindings.
+            return Ok(Val::Unit);
+        }
+
+        if timeout.timed_out() {
+            return Err(ExecError::Timeout);
+        }
+
+        if let Some(reason) = solver.take_abort() {
+            return Err(ExecError::Aborted(reason));
+        }
+
+        if task_state.loop_detection {
+            if frame.function_name == last_position.0 && frame.pc <= last_position.1 {
+                let state = hash_frame_state(frame);
+                if frame.visited_states.contains(&state) {
+                    return Err(ExecError::NonTermination(format!(
+                        "{} instruction {}",
+                        zencode::decode(shared_state.symtab.to_str(frame.function_name)),
+                        frame.pc
+                    )));
+                }
+                Arc::make_mut(&mut frame.visited_states).insert(state);
+            }
+            last_position = (frame.function_name, frame.pc)
+        }
+
+        if let Some(coverage) = &task_state.coverage {
+            coverage.visit(frame.function_name, frame.pc)
+        }
+
+        if task_state.provenance {
+            solver.set_position(frame.function_name, frame.pc)
+        }
+
+        frame.instructions += 1;
+        if let Some(limit) = task_state.instruction_limit {
+            if frame.instructions > limit {
+                return Err(ExecError::InstructionLimit);
+            }
+        }
+
+        if let Some(usage) = &task_state.memory_usage {
+            if frame.instructions % shed::SAMPLE_INTERVAL == 0 {
+                usage.set_worker(tid, shed::frame_size(frame), solver.approximate_size())
+            }
+        }
+
+        if let Some(limit) = task_state.fork_limit {
+            if frame.forks > limit {
+                return Err(ExecError::ForkLimit);
+            }
+        }
+
+        if let Some(limit) = task_state.solver_time_limit {
+            if solver.solver_time() > limit {
+                return Err(ExecError::SolverTimeLimit);
+            }
+        }
+
+        if let Some(limit) = task_state.event_limit {
+            if solver.trace().len() > limit {
+                return Err(ExecError::EventLimit);
+            }
+        }
+
+        match &frame.instrs[frame.pc] {
+            Instr::Decl(v, ty, _) => {
+                frame.vars_mut().insert(*v, UVal::Uninit(ty));
+                frame.pc += 1;
+            }
+
+            Instr::Init(var, _, exp, info) => {
+                let value = eval_exp(exp, &mut frame.local_state, shared_state, solver, *info)?.into_owned();
+                frame.vars_mut().insert(*var, UVal::Init(value));
+                frame.pc += 1;
+            }
+
+            Instr::Jump(exp, target, info) => {
+                if task_state.eager_enum_splits {
+                    if let Some((id, v, enum_id)) = symbolic_enum_comparison(exp, frame, shared_state, solver) {
+                        split_symbolic_enum(
+                            id,
+                            v,
+                            enum_id,
+                            *info,
+                            tid,
+                            task_id,
+                            queue,
+                            frame,
+                            task_state,
+                            stop_conditions,
+                            shared_state,
+                            solver,
+                        )?
+                    }
+                }
+
+                let value = eval_exp(exp, &mut frame.local_state, shared_state, solver, *info)?;
+                match *value.as_ref() {
+                    Val::Symbolic(v) => {
+                        use smtlib::Def::*;
+                        use smtlib::Exp::*;
+
+                        let test_true = Var(v);
+                        let test_false = Not(Box::new(Var(v)));
+                        let decided = task_state.branch_facts.as_ref().and_then(|facts| {
+                            facts.decide(shared_state, frame.function_name, frame.pc, exp, |id| {
+                                match frame.local_state.vars.get(&id) {
+                                    Some(UVal::Init(val)) => Abstract::from_val(val),
+                                    _ => None,
+                                }
+                            })
+                        });
+                        let (can_be_true, can_be_false) = match decided {
+                            Some(b) => (b, !b),
+                            None => (
+                                solver.is_possible(QuerySite::Branch, Some(&test_true))?,
+                                solver.is_possible(QuerySite::Branch, Some(&test_false))?,
+                            ),
+                        };
+
+                        if can_be_true && can_be_false {
+                            if_logging!(log::FORK, {
+                                log_from!(tid, log::FORK, &format!("{}", info.location_string(shared_state.symtab.files())));
+                                probe::taint_info(log::FORK, v, Some(shared_state), solver)
+                            });
+
+                            match choose_branches(frame, task_state) {
+                                Branches::Both(child) => {
+                                    let point = checkpoint(solver);
+                                    let frozen = child.apply(Frame { pc: frame.pc + 1, ..freeze_frame(frame) });
+                                    frame.forks += 1;
+                                    queue.push(Task {
+                                        id: task_id,
+                                        frame: frozen,
+                                        checkpoint: point,
+                                        fork_cond: Some((
+                                            Assert(test_false),
+                                            Event::Fork(frame.forks - 1, v, 1, *info),
+                                        )),
+                                        state: task_state,
+                                        stop_conditions,
+                                    });
+
+                                    // Track which asserts are assocated with each fork in the trace, so we
+                                    // can turn a set of traces into a tree later
+                                    solver.add_event(Event::Fork(frame.forks - 1, v, 0, *info));
+
+                                    solver.add(Assert(test_true));
+                                    frame.pc = *target
+                                }
+                                Branches::First => {
+                                    solver.add(Assert(test_true));
+                                    frame.pc = *target
+                                }
+                                Branches::Second => {
+                                    solver.add(Assert(test_false));
+                                    frame.pc += 1
+                                }
+                            }
                         } else if can_be_true {
                             solver.add(Assert(test_true));
                             frame.pc = *target
@@ -1307,8 +2213,20 @@ fn run_loop<'ir, 'task, B: BV>(
 
             Instr::PrimopUnary(loc, f, arg, info) => {
                 let arg = eval_exp(arg, &mut frame.local_state, shared_state, solver, *info)?.into_owned();
+                let cycles = solver.get_cycle_count();
                 let value = f(arg, solver, *info)?;
                 assign(tid, loc, value, &mut frame.local_state, shared_state, solver, *info)?;
+                // The cycle_count builtin marks an instruction boundary
+                if solver.get_cycle_count() != cycles {
+                    if let Some(limit) = task_state.cycle_limit {
+                        if solver.get_cycle_count() as u64 > limit {
+                            return Err(ExecError::CycleLimit);
+                        }
+                    }
+                    if !task_state.interrupts.is_empty() {
+                        inject_interrupts(tid, frame, &task_state.interrupts, shared_state, solver, *info)?
+                    }
+                }
                 frame.pc += 1;
             }
 
@@ -1326,10 +2244,57 @@ fn run_loop<'ir, 'task, B: BV>(
                     .map(|arg| eval_exp(arg, &mut frame.local_state, shared_state, solver, *info).map(Cow::into_owned))
                     .collect::<Result<_, _>>()?;
                 let value = f(args, solver, frame, *info)?;
+                if frame.suspended {
+                    return Ok(Val::Poison);
+                }
                 assign(tid, loc, value, &mut frame.local_state, shared_state, solver, *info)?;
                 frame.pc += 1;
             }
 
+            Instr::Call(loc, _, f, args, info) if shared_state.abstract_primitives.contains_key(f) => {
+                run_abstract_primitive(loc, *f, args, *info, tid, frame, shared_state, solver)?
+            }
+
+            Instr::Call(loc, _, f, args, info)
+                if (*f == READ_REGISTER_FROM_VECTOR || *f == WRITE_REGISTER_FROM_VECTOR)
+                    && task_state.register_index_strategy == RegisterIndexStrategy::CaseSplit =>
+            {
+                let mut args = args
+                    .iter()
+                    .map(|arg| eval_exp(arg, &mut frame.local_state, shared_state, solver, *info).map(Cow::into_owned))
+                    .collect::<Result<Vec<Val<B>>, _>>()?;
+                let regs = args.pop().unwrap();
+                let mut n = args.remove(0);
+                let split = match (&n, &regs) {
+                    (Val::Symbolic(v), Val::Vector(regs)) => Some((*v, regs.len())),
+                    _ => None,
+                };
+                if let Some((v, num_regs)) = split {
+                    n = split_register_index(
+                        v,
+                        num_regs,
+                        *info,
+                        tid,
+                        task_id,
+                        queue,
+                        frame,
+                        task_state,
+                        stop_conditions,
+                        shared_state,
+                        solver,
+                    )?
+                }
+                if let Some(value) = args.pop() {
+                    write_register_from_vector(n, value, regs, &mut frame.local_state, shared_state, solver, *info)?;
+                    assign(tid, loc, Val::Unit, &mut frame.local_state, shared_state, solver, *info)?
+                } else {
+                    let value =
+                        read_register_from_vector(n, regs, &mut frame.local_state, shared_state, solver, *info)?;
+                    assign(tid, loc, value, &mut frame.local_state, shared_state, solver, *info)?
+                }
+                frame.pc += 1
+            }
+
             Instr::Call(loc, _, f, args, info) => {
                 match shared_state.functions.get(f) {
                     None => run_special_primop(loc, *f, args, *info, tid, frame, task_state, shared_state, solver)?,
@@ -1366,6 +2331,10 @@ fn run_loop<'ir, 'task, B: BV>(
                                     });
                                     return Ok(Val::Poison);
                                 }
+                                Some(StopAction::Suspend) => {
+                                    frame.suspended = true;
+                                    return Ok(Val::Poison);
+                                }
                                 None => (),
                             }
                         }
@@ -1394,27 +2363,29 @@ fn run_loop<'ir, 'task, B: BV>(
                             }
                         }
 
+                        if let Some(widening) = shared_state.widening.get(f) {
+                            let depth = frame.backtrace.iter().filter(|(g, _)| g == f).count()
+                                + usize::from(frame.function_name == *f);
+                            if depth >= widening.unroll {
+                                widen_call(tid, loc, *f, args, ret_ty, widening, *info, frame, shared_state, solver)?;
+                                continue 'main_loop;
+                            }
+                        }
+
                         let caller_pc = frame.pc;
                         let caller_instrs = frame.instrs;
                         let caller_stack_call = frame.stack_call.clone();
+                        let untraced = shared_state.untraced_functions.contains(f);
+                        if untraced {
+                            frame.local_state.untraced_depth += 1
+                        }
                         push_call_stack(frame);
                         frame.backtrace.push((frame.function_name, caller_pc));
                         frame.function_name = *f;
                         frame.vars_mut().insert(RETURN, UVal::Uninit(ret_ty));
 
-                        // Set up a closure to restore our state when
-                        // the function we call returns
-                        frame.stack_call = Some(Arc::new(move |ret, frame, shared_state, solver| {
-                            pop_call_stack(frame);
-                            // could avoid putting caller_pc into the stack?
-                            if let Some((name, _)) = frame.backtrace.pop() {
-                                frame.function_name = name;
-                            }
-                            frame.pc = caller_pc + 1;
-                            frame.instrs = caller_instrs;
-                            frame.stack_call = caller_stack_call.clone();
-                            assign(tid, &loc.clone(), ret, &mut frame.local_state, shared_state, solver, *info)
-                        }));
+                        frame.stack_call =
+                            return_to(tid, caller_pc, caller_instrs, loc, *info, untraced, caller_stack_call);
 
                         for (i, arg) in args.drain(..).enumerate() {
                             frame.vars_mut().insert(params[i].0, UVal::Init(arg));
@@ -1507,21 +2478,31 @@ fn run_loop<'ir, 'task, B: BV>(
 
                     log_from!(tid, log::FORK, format!("Fork @ monomorphizing v{}", v));
 
-                    frame.forks += 1;
-
-                    queue.push(Task {
-                        id: task_id,
-                        frame: freeze_frame(frame),
-                        checkpoint: point,
-                        fork_cond: Some((
-                            Assert(Neq(Box::new(Var(v)), Box::new(bits64(result, size)))),
-                            Event::Fork(frame.forks - 1, v, 1, *info),
-                        )),
-                        state: task_state,
-                        stop_conditions,
-                    });
+                    match choose_branches(frame, task_state) {
+                        Branches::Both(child) => {
+                            frame.forks += 1;
+
+                            queue.push(Task {
+                                id: task_id,
+                                frame: child.apply(freeze_frame(frame)),
+                                checkpoint: point,
+                                fork_cond: Some((
+                                    Assert(Neq(Box::new(Var(v)), Box::new(bits64(result, size)))),
+                                    Event::Fork(frame.forks - 1, v, 1, *info),
+                                )),
+                                state: task_state,
+                                stop_conditions,
+                            });
 
-                    solver.add_event(Event::Fork(frame.forks - 1, v, 0, *info));
+                            solver.add_event(Event::Fork(frame.forks - 1, v, 0, *info));
+                        }
+                        Branches::First => (),
+                        Branches::Second => {
+                            // Monomorphize again with the chosen value excluded
+                            solver.add(Assert(Neq(Box::new(Var(v)), Box::new(bits64(result, size)))));
+                            continue 'main_loop;
+                        }
+                    }
 
                     solver.assert_eq(Var(v), bits64(result, size));
 
@@ -1584,127 +2565,925 @@ pub type Collector<'ir, B, R> = dyn 'ir
     + Sync
     + Fn(usize, usize, Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>, &SharedState<'ir, B>, Solver<B>, &R);
 
+/// How reads and writes of a vector of registers, such as a general
+/// purpose register file, are handled when the index is symbolic,
+/// e.g. because the register fields of a symbolic opcode are unknown.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterIndexStrategy {
+    /// Encode the access as a chain of if-then-else expressions over
+    /// every register in the vector, staying on a single path
+    Ite,
+    /// Fork once for each register the index could select, so each
+    /// path accesses a single concrete register
+    CaseSplit,
+}
+
+impl FromStr for RegisterIndexStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ite" => Ok(RegisterIndexStrategy::Ite),
+            "split" => Ok(RegisterIndexStrategy::CaseSplit),
+            _ => Err(format!("Unknown register index strategy {} (expected ite or split)", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct TaskState<B> {
     reset_registers: HashMap<Loc<Name>, Reset<B>>,
+    eager_enum_splits: bool,
+    register_index_strategy: RegisterIndexStrategy,
+    instruction_limit: Option<u64>,
+    cycle_limit: Option<u64>,
+    fork_limit: Option<u32>,
+    solver_time_limit: Option<Duration>,
+    event_limit: Option<usize>,
+    loop_detection: bool,
+    fork_log: Option<Arc<ForkLog>>,
+    fork_donor: Option<Arc<dyn ForkDonor>>,
+    coverage: Option<Arc<Coverage>>,
+    coverage_guided: bool,
+    branch_facts: Option<Arc<BranchFacts>>,
+    query_log: Option<Arc<QueryLog>>,
+    provenance: bool,
+    symbolic_sleep: bool,
+    unknown_policies: UnknownPolicies,
+    unknown_stats: Option<Arc<UnknownStats>>,
+    unimplemented_stats: Option<Arc<UnimplementedStats>>,
+    observer: Option<Arc<dyn EventObserver<B>>>,
+    trace_sink: Option<(Arc<dyn TraceSink<B>>, usize)>,
+    memory_usage: Option<Arc<MemoryUsage>>,
+    interrupts: Vec<Interrupt<B>>,
 }
 
 impl<B> TaskState<B> {
     pub fn new() -> Self {
-        TaskState { reset_registers: HashMap::new() }
+        TaskState {
+            reset_registers: HashMap::new(),
+            eager_enum_splits: false,
+            register_index_strategy: RegisterIndexStrategy::Ite,
+            instruction_limit: None,
+            cycle_limit: None,
+            fork_limit: None,
+            solver_time_limit: None,
+            event_limit: None,
+            loop_detection: false,
+            fork_log: None,
+            fork_donor: None,
+            coverage: None,
+            coverage_guided: false,
+            branch_facts: None,
+            query_log: None,
+            provenance: false,
+            symbolic_sleep: false,
+            unknown_policies: UnknownPolicies::default(),
+            unknown_stats: None,
+            unimplemented_stats: None,
+            observer: None,
+            trace_sink: None,
+            memory_usage: None,
+            interrupts: Vec::new(),
+        }
     }
 
     pub fn with_reset_registers(reset_registers: HashMap<Loc<Name>, Reset<B>>) -> Self {
-        TaskState { reset_registers }
+        TaskState {
+            reset_registers,
+            eager_enum_splits: false,
+            register_index_strategy: RegisterIndexStrategy::Ite,
+            instruction_limit: None,
+            cycle_limit: None,
+            fork_limit: None,
+            solver_time_limit: None,
+            event_limit: None,
+            loop_detection: false,
+            fork_log: None,
+            fork_donor: None,
+            coverage: None,
+            coverage_guided: false,
+            branch_facts: None,
+            query_log: None,
+            provenance: false,
+            symbolic_sleep: false,
+            unknown_policies: UnknownPolicies::default(),
+            unknown_stats: None,
+            unimplemented_stats: None,
+            observer: None,
+            trace_sink: None,
+            memory_usage: None,
+            interrupts: Vec::new(),
+        }
     }
-}
 
-impl<B> Default for TaskState<B> {
-    fn default() -> Self {
-        Self::new()
+    /// Set the registers which are reset at the start of the task,
+    /// overriding any reset for the same register in the shared state.
+    pub fn set_reset_registers(&mut self, reset_registers: HashMap<Loc<Name>, Reset<B>>) {
+        self.reset_registers = reset_registers
     }
-}
 
-/// A `Task` is a suspended point in the symbolic execution of a
-/// program. It consists of a frame, which is a snapshot of the
-/// program variables, a checkpoint which allows us to reconstruct the
-/// SMT solver state, and finally an option SMTLIB definiton which is
-/// added to the solver state when the task is resumed.
-pub struct Task<'ir, 'task, B> {
-    id: usize,
-    frame: Frame<'ir, B>,
-    checkpoint: Checkpoint<B>,
-    fork_cond: Option<(smtlib::Def, Event<B>)>,
-    state: &'task TaskState<B>,
-    stop_conditions: Option<&'task StopConditions>,
-}
+    /// Record the outstanding tasks in the given log, see [ForkLog].
+    pub fn set_fork_log(&mut self, fork_log: Option<Arc<ForkLog>>) {
+        self.fork_log = fork_log
+    }
 
-impl<'ir, 'task, B> Task<'ir, 'task, B> {
-    pub fn set_stop_conditions(&mut self, new_fns: &'task StopConditions) {
-        self.stop_conditions = Some(new_fns);
+    /// Offer each task created at a fork to the given donor, which
+    /// can take it to be explored elsewhere, see [ForkDonor].
+    pub fn set_fork_donor(&mut self, donor: Option<Arc<dyn ForkDonor>>) {
+        self.fork_donor = donor
     }
-}
 
-/// Start symbolically executing a Task using just the current thread, collecting the results using
-/// the given collector.
-pub fn start_single<'ir, 'task, B: BV, R>(
-    task: Task<'ir, 'task, B>,
-    shared_state: &SharedState<'ir, B>,
-    collected: &R,
-    collector: &Collector<'ir, B, R>,
-) {
-    let queue = Worker::new_lifo();
-    queue.push(task);
-    while let Some(task) = queue.pop() {
-        let mut cfg = Config::new();
-        cfg.set_param_value("model", "true");
-        let ctx = Context::new(cfg);
-        let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
-        if let Some((def, event)) = task.fork_cond {
-            solver.add_event(event);
+    /// True if each path must keep track of the branches it has
+    /// taken at forks, so the tasks it creates can be identified.
+    fn tracks_fork_paths(&self) -> bool {
+        self.fork_log.is_some() || self.fork_donor.is_some()
+    }
 
-            solver.add(def)
-        };
-        let result = run(
-            0,
-            task.id,
-            Timeout::unlimited(),
-            task.stop_conditions,
-            &queue,
-            &task.frame,
-            task.state,
-            shared_state,
-            &mut solver,
-        );
-        collector(0, task.id, result, shared_state, solver, collected)
+    /// Record which instructions are executed.
+    pub fn set_coverage(&mut self, coverage: Option<Arc<Coverage>>) {
+        self.coverage = coverage
     }
-}
 
-fn find_task<T>(local: &Worker<T>, global: &Injector<T>, stealers: &RwLock<Vec<Stealer<T>>>) -> Option<T> {
-    let stealers = stealers.read().unwrap();
-    local.pop().or_else(|| {
-        std::iter::repeat_with(|| {
-            let stolen: Steal<T> = stealers.iter().map(|s| s.steal()).collect();
-            stolen.or_else(|| global.steal_batch_and_pop(local))
-        })
-        .find(|s| !s.is_retry())
-        .and_then(|s| s.success())
-    })
-}
+    /// When coverage is being recorded, run tasks which start at an
+    /// instruction that has already been covered only once there are
+    /// no other tasks available.
+    pub fn set_coverage_guided(&mut self, guided: bool) {
+        self.coverage_guided = guided
+    }
 
-fn do_work<'ir, 'task, B: BV, R>(
-    tid: usize,
-    timeout: Timeout,
-    queue: &Worker<Task<'ir, 'task, B>>,
-    task: Task<'ir, 'task, B>,
-    shared_state: &SharedState<'ir, B>,
-    collected: &R,
-    collector: &Collector<'ir, B, R>,
-) {
-    let cfg = Config::new();
-    let ctx = Context::new(cfg);
-    let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
-    if let Some((def, event)) = task.fork_cond {
-        solver.add_event(event);
-        solver.add(def)
-    };
-    let result =
-        run(tid, task.id, timeout, task.stop_conditions, queue, &task.frame, task.state, shared_state, &mut solver);
-    collector(tid, task.id, result, shared_state, solver, collected)
+    /// Use a static analysis to decide symbolic branches without
+    /// querying the solver where possible, see [crate::absint].
+    pub fn set_branch_facts(&mut self, facts: Option<Arc<BranchFacts>>) {
+        self.branch_facts = facts
+    }
+
+    /// Log the solver queries made by each task, see
+    /// [crate::smt::query_log].
+    pub fn set_query_log(&mut self, log: Option<Arc<QueryLog>>) {
+        self.query_log = log
+    }
+
+    /// Record the function and instruction that generated each
+    /// event, see [Event::Provenance].
+    pub fn set_provenance(&mut self, provenance: bool) {
+        self.provenance = provenance
+    }
+
+    /// Leave whether the hart sleeps after a sleep request symbolic,
+    /// see [Solver::symbolic_sleep]. Only useful when the traces are
+    /// combined by something that can decide which harts could be
+    /// woken, like isla-axiomatic.
+    pub fn set_symbolic_sleep(&mut self, symbolic_sleep: bool) {
+        self.symbolic_sleep = symbolic_sleep
+    }
+
+    /// Choose what happens when the solver cannot decide a query,
+    /// see [crate::smt::unknown].
+    pub fn set_unknown_policies(&mut self, policies: UnknownPolicies) {
+        self.unknown_policies = policies
+    }
+
+    /// Count the queries the solver could not decide.
+    pub fn set_unknown_stats(&mut self, stats: Option<Arc<UnknownStats>>) {
+        self.unknown_stats = stats
+    }
+
+    /// Count the paths which fail by reaching each unimplemented
+    /// primop, so they can be reported together at the end of a run.
+    pub fn set_unimplemented_stats(&mut self, stats: Option<Arc<UnimplementedStats>>) {
+        self.unimplemented_stats = stats
+    }
+
+    /// Pass each event to an observer as it is generated, see
+    /// [crate::smt::observer].
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn EventObserver<B>>>) {
+        self.observer = observer
+    }
+
+    /// Write the events of each path to a sink in chunks of the given
+    /// size while it executes, rather than keeping them all in
+    /// memory, see [crate::smt::spill].
+    pub fn set_trace_sink(&mut self, sink: Option<(Arc<dyn TraceSink<B>>, usize)>) {
+        self.trace_sink = sink
+    }
+
+    /// Record an estimate of the memory used by each worker, and
+    /// write tasks to disk when it exceeds the cap (if any), see
+    /// [shed].
+    pub fn set_memory_usage(&mut self, usage: Option<Arc<MemoryUsage>>) {
+        self.memory_usage = usage
+    }
+
+    /// Inject interrupts at instruction boundaries, see [interrupt].
+    pub fn set_interrupts(&mut self, interrupts: Vec<Interrupt<B>>) {
+        self.interrupts = interrupts
+    }
+
+    /// If set, hash the state of each path whenever it jumps
+    /// backwards, and fail with `ExecError::NonTermination` if a path
+    /// revisits an identical state. Memory is not included in the
+    /// state, only the position, call stack, local variables, and
+    /// registers.
+    pub fn set_loop_detection(&mut self, detect: bool) {
+        self.loop_detection = detect
+    }
+
+    /// Limit the number of instructions that can be executed on any
+    /// single path, after which the path fails with
+    /// `ExecError::InstructionLimit`.
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit
+    }
+
+    /// Limit the number of instruction cycles (calls to the
+    /// `cycle_count` builtin) on any single path, after which the
+    /// path fails with `ExecError::CycleLimit`. Unlike the
+    /// instruction limit this counts architectural instructions, so
+    /// it stops a runaway fetch loop at the same point regardless of
+    /// how the model implements each instruction.
+    pub fn set_cycle_limit(&mut self, limit: Option<u64>) {
+        self.cycle_limit = limit
+    }
+
+    /// Limit the number of times any single path can fork, after
+    /// which the path fails with `ExecError::ForkLimit`.
+    pub fn set_fork_limit(&mut self, limit: Option<u32>) {
+        self.fork_limit = limit
+    }
+
+    /// Limit the total time spent in the SMT solver by any single
+    /// path (including the part of the path before it forked), after
+    /// which the path fails with `ExecError::SolverTimeLimit`. This
+    /// is only checked between queries, so a single slow query can
+    /// still exceed it.
+    pub fn set_solver_time_limit(&mut self, limit: Option<Duration>) {
+        self.solver_time_limit = limit
+    }
+
+    /// Limit the number of events in the trace of any single path,
+    /// after which the path fails with `ExecError::EventLimit`.
+    pub fn set_event_limit(&mut self, limit: Option<usize>) {
+        self.event_limit = limit
+    }
+
+    /// If set, when a jump compares a symbolic enumeration held in a
+    /// local variable against an enum member, fork once for each
+    /// member it could be and continue each path with the variable
+    /// made concrete, rather than accumulating constraints over the
+    /// symbolic value.
+    pub fn set_eager_enum_splits(&mut self, eager: bool) {
+        self.eager_enum_splits = eager
+    }
+
+    /// Set how register vector accesses with a symbolic index are
+    /// handled, see [RegisterIndexStrategy]. The default is
+    /// [RegisterIndexStrategy::Ite].
+    pub fn set_register_index_strategy(&mut self, strategy: RegisterIndexStrategy) {
+        self.register_index_strategy = strategy
+    }
 }
 
-enum Response {
-    Poke,
-    Kill,
+/// Takes tasks created at forks so they can be explored elsewhere,
+/// for example by another process (see [crate::multiprocess]). As for
+/// a [ForkLog], each task is identified by the branches taken at every
+/// fork between the initial task and the start of the task, so it can
+/// be recreated by replaying the initial task (see [Task::set_replay]).
+pub trait ForkDonor: Send + Sync {
+    /// Called with the path of each new task, returning true if the
+    /// task has been taken and should not be explored here.
+    fn donate(&self, path: &[u32]) -> bool;
 }
 
-#[derive(Clone)]
-enum Activity {
-    Idle(usize, Sender<Response>),
-    Busy(usize),
+/// The state of a [ForkLog] which can be saved to disk.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ForkLogState {
+    pub pending: Vec<Vec<u32>>,
+    pub completed: Vec<Vec<u32>>,
+}
+
+/// Records which tasks are outstanding, so a long running exploration
+/// can be saved and resumed later. Each task is identified by the
+/// branches taken at every fork between the initial task and the
+/// start of the task, which is enough to recreate it by replaying the
+/// initial task (see [Task::set_replay]). This relies on the solver
+/// making the same choices when a path is replayed.
+pub struct ForkLog {
+    pending: Mutex<HashSet<Vec<u32>>>,
+    completed: Mutex<HashSet<Vec<u32>>>,
+    finishing: RwLock<()>,
+}
+
+impl ForkLog {
+    pub fn new() -> Self {
+        Self::from_state(ForkLogState::default())
+    }
+
+    /// Resume from a saved state. Tasks which are pending or
+    /// completed in the saved state are not created again when their
+    /// parent is replayed.
+    pub fn from_state(state: ForkLogState) -> Self {
+        ForkLog {
+            pending: Mutex::new(state.pending.into_iter().collect()),
+            completed: Mutex::new(state.completed.into_iter().collect()),
+            finishing: RwLock::new(()),
+        }
+    }
+
+    /// Record a new task, returning false if it already exists.
+    fn spawn(&self, path: &[u32]) -> bool {
+        if self.completed.lock().unwrap().contains(path) {
+            return false;
+        }
+        self.pending.lock().unwrap().insert(path.to_vec())
+    }
+
+    fn complete(&self, path: Vec<u32>) {
+        self.pending.lock().unwrap().remove(&path);
+        self.completed.lock().unwrap().insert(path);
+    }
+
+    /// Held while a task is passing its result to the collector and
+    /// being marked as complete.
+    fn finishing(&self) -> RwLockReadGuard<'_, ()> {
+        self.finishing.read().unwrap()
+    }
+
+    /// Call `f` with the current state while no task is finishing, so
+    /// `f` can also save the collected results consistently with the
+    /// returned state.
+    pub fn snapshot<A, F>(&self, f: F) -> A
+    where
+        F: FnOnce(&ForkLogState) -> A,
+    {
+        let _finishing = self.finishing.write().unwrap();
+        let pending = self.pending.lock().unwrap();
+        let mut completed = self.completed.lock().unwrap();
+        // Completed tasks only need to be remembered while the task
+        // that created them could be replayed
+        completed.retain(|path| (0..path.len()).any(|n| pending.contains(&path[..n])));
+        let state =
+            ForkLogState { pending: pending.iter().cloned().collect(), completed: completed.iter().cloned().collect() };
+        drop(completed);
+        drop(pending);
+        f(&state)
+    }
+}
+
+impl Default for ForkLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Modifications to the frame of a task created at a binary fork
+struct ForkChild {
+    fork_path: Arc<Vec<u32>>,
+}
+
+impl ForkChild {
+    fn apply<'ir, B>(self, frame: Frame<'ir, B>) -> Frame<'ir, B> {
+        Frame { fork_path: self.fork_path, ..frame }
+    }
+}
+
+enum Branches {
+    /// Explore both branches, with a new task for the second
+    Both(ForkChild),
+    First,
+    Second,
+}
+
+/// Decide which branches of a binary fork should be explored here,
+/// either because we are replaying a path, or because the task for
+/// the second branch has been taken by a [ForkLog] or [ForkDonor].
+fn choose_branches<B: BV>(frame: &mut LocalFrame<'_, B>, task_state: &TaskState<B>) -> Branches {
+    let replayed = frame.replay.get(frame.fork_path.len()).copied();
+
+    if !task_state.tracks_fork_paths() && frame.replay.is_empty() {
+        return Branches::Both(ForkChild { fork_path: frame.fork_path.clone() });
+    }
+    match replayed {
+        Some(0) => {
+            Arc::make_mut(&mut frame.fork_path).push(0);
+            Branches::First
+        }
+        Some(_) => {
+            Arc::make_mut(&mut frame.fork_path).push(1);
+            Branches::Second
+        }
+        None => {
+            let mut child_path = frame.fork_path.to_vec();
+            child_path.push(1);
+            Arc::make_mut(&mut frame.fork_path).push(0);
+            match (&task_state.fork_log, &task_state.fork_donor) {
+                (Some(fork_log), _) if !fork_log.spawn(&child_path) => Branches::First,
+                (_, Some(donor)) if donor.donate(&child_path) => Branches::First,
+                _ => Branches::Both(ForkChild { fork_path: Arc::new(child_path) }),
+            }
+        }
+    }
+}
+
+impl<B> Default for TaskState<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `Task` is a suspended point in the symbolic execution of a
+/// program. It consists of a frame, which is a snapshot of the
+/// program variables, a checkpoint which allows us to reconstruct the
+/// SMT solver state, and finally an option SMTLIB definiton which is
+/// added to the solver state when the task is resumed.
+pub struct Task<'ir, 'task, B> {
+    id: usize,
+    frame: Frame<'ir, B>,
+    checkpoint: Checkpoint<B>,
+    fork_cond: Option<(smtlib::Def, Event<B>)>,
+    state: &'task TaskState<B>,
+    stop_conditions: Option<&'task StopConditions>,
+}
+
+impl<'ir, 'task, B> Task<'ir, 'task, B> {
+    pub fn set_stop_conditions(&mut self, new_fns: &'task StopConditions) {
+        self.stop_conditions = Some(new_fns);
+    }
+
+    /// Follow the given branches at the first forks this task reaches,
+    /// recreating a task recorded by a [ForkLog].
+    pub fn set_replay(&mut self, path: Vec<u32>) {
+        self.frame.replay = Arc::new(path)
+    }
+
+    fn path(&self) -> Vec<u32> {
+        if self.frame.replay.len() > self.frame.fork_path.len() {
+            self.frame.replay.to_vec()
+        } else {
+            self.frame.fork_path.to_vec()
+        }
+    }
+
+    /// Should this task be put aside in favour of tasks which will
+    /// execute uncovered code.
+    fn deprioritized(&self) -> bool {
+        match &self.state.coverage {
+            Some(coverage) if self.state.coverage_guided => {
+                coverage.is_covered(self.frame.function_name, self.frame.pc)
+            }
+            _ => false,
+        }
+    }
+
+    fn register(&self) {
+        if let Some(fork_log) = &self.state.fork_log {
+            fork_log.spawn(&self.path());
+        }
+    }
+}
+
+impl<'ir, 'task, B: BV> Task<'ir, 'task, B> {
+    fn queued(&self) {
+        if let Some(usage) = &self.state.memory_usage {
+            usage.queued(self)
+        }
+    }
+
+    fn dequeued(&self) {
+        if let Some(usage) = &self.state.memory_usage {
+            usage.dequeued(self)
+        }
+    }
+}
+
+type Precondition<'a, B> = Box<dyn Fn(&[Val<B>]) -> smtlib::Exp<Sym> + 'a>;
+
+/// Builds the initial frame and solver checkpoint for executing a
+/// function. Arguments which are not given a value are symbolic, and
+/// preconditions can constrain the symbolic arguments, e.g.
+///
+/// ```ignore
+/// let (frame, checkpoint) = TaskBuilder::new(function_id)
+///     .arg(Val::Bits(B64::new(0xd503201f, 32)))
+///     .symbolic_arg()
+///     .registers(&regs)
+///     .lets(&lets)
+///     .precondition(|args| match &args[1] {
+///         Val::Symbolic(v) => Exp::Bvult(Box::new(Exp::Var(*v)), Box::new(bits64(16, 64))),
+///         _ => Exp::Bool(true),
+///     })
+///     .build(&shared_state)?;
+/// let task = frame.task_with_checkpoint(0, &task_state, checkpoint);
+/// ```
+pub struct TaskBuilder<'a, 'ir, B> {
+    function: Name,
+    args: Vec<Option<Val<B>>>,
+    regs: Option<&'a RegisterBindings<'ir, B>>,
+    lets: Option<&'a Bindings<'ir, B>>,
+    initial_registers: Vec<(Name, Val<B>)>,
+    memory: Option<Memory<B>>,
+    preconditions: Vec<Precondition<'a, B>>,
+    checkpoint: Checkpoint<B>,
+    initialize_registers: bool,
+}
+
+impl<'a, 'ir, B: BV> TaskBuilder<'a, 'ir, B> {
+    pub fn new(function: Name) -> Self {
+        TaskBuilder {
+            function,
+            args: Vec::new(),
+            regs: None,
+            lets: None,
+            initial_registers: Vec::new(),
+            memory: None,
+            preconditions: Vec::new(),
+            checkpoint: Checkpoint::new(),
+            initialize_registers: false,
+        }
+    }
+
+    /// Add a concrete argument
+    pub fn arg(mut self, val: Val<B>) -> Self {
+        self.args.push(Some(val));
+        self
+    }
+
+    /// Add a symbolic argument. Any arguments not supplied when the
+    /// task is built are also symbolic.
+    pub fn symbolic_arg(mut self) -> Self {
+        self.args.push(None);
+        self
+    }
+
+    /// The registers for the architecture, usually from
+    /// [crate::init::Initialized]
+    pub fn registers(mut self, regs: &'a RegisterBindings<'ir, B>) -> Self {
+        self.regs = Some(regs);
+        self
+    }
+
+    /// The top-level let bindings for the architecture, usually from
+    /// [crate::init::Initialized]
+    pub fn lets(mut self, lets: &'a Bindings<'ir, B>) -> Self {
+        self.lets = Some(lets);
+        self
+    }
+
+    /// Set the initial value of a register
+    pub fn register(mut self, name: Name, val: Val<B>) -> Self {
+        self.initial_registers.push((name, val));
+        self
+    }
+
+    pub fn memory(mut self, memory: Memory<B>) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Add an SMT precondition, which is given the values of all the
+    /// function's arguments
+    pub fn precondition<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&[Val<B>]) -> smtlib::Exp<Sym> + 'a,
+    {
+        self.preconditions.push(Box::new(f));
+        self
+    }
+
+    /// Start from an existing solver checkpoint rather than an empty
+    /// one, e.g. one containing the initial state of memory
+    pub fn checkpoint(mut self, checkpoint: Checkpoint<B>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Give every register its initial value in the checkpoint, see
+    /// [LocalFrame::initialize_registers]
+    pub fn initialize_registers(mut self) -> Self {
+        self.initialize_registers = true;
+        self
+    }
+
+    /// Build the initial frame and checkpoint. Returns
+    /// [ExecError::Dead] if the preconditions are unsatisfiable.
+    pub fn build(self, shared_state: &SharedState<'ir, B>) -> Result<(LocalFrame<'ir, B>, Checkpoint<B>), ExecError> {
+        let info = SourceLoc::unknown();
+        let (args, ret_ty, instrs) = shared_state
+            .functions
+            .get(&self.function)
+            .ok_or_else(|| ExecError::NoFunction(zencode::decode(shared_state.symtab.to_str(self.function)), info))?;
+        if self.args.len() > args.len() {
+            return Err(ExecError::Type(
+                format!("{} arguments given for function with {} arguments", self.args.len(), args.len()),
+                info,
+            ));
+        }
+
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::from_checkpoint(&ctx, self.checkpoint);
+
+        let mut vals = Vec::with_capacity(args.len());
+        for (i, (_, ty)) in args.iter().enumerate() {
+            match self.args.get(i) {
+                Some(Some(val)) => vals.push(val.clone()),
+                _ => vals.push(symbolic(ty, shared_state, &mut solver, info)?),
+            }
+        }
+
+        let mut frame = LocalFrame::new(self.function, args, ret_ty, Some(&vals), instrs);
+        if let Some(lets) = self.lets {
+            frame.add_lets(lets);
+        }
+        if let Some(regs) = self.regs {
+            frame.add_regs(regs);
+        }
+        for (name, val) in self.initial_registers {
+            if !frame.regs().contains_key(name) {
+                return Err(ExecError::VariableNotFound(zencode::decode(shared_state.symtab.to_str(name))));
+            }
+            frame.regs_mut().assign(name, val, shared_state)
+        }
+        if let Some(memory) = self.memory {
+            frame.set_memory(memory);
+        }
+        if self.initialize_registers {
+            frame.initialize_registers(shared_state, &mut solver)?;
+        }
+
+        for precondition in &self.preconditions {
+            solver.add(smtlib::Def::Assert(precondition(&vals)))
+        }
+        if !self.preconditions.is_empty() && !solver.is_possible(QuerySite::Feasibility, None)? {
+            return Err(ExecError::Dead);
+        }
+
+        Ok((frame, checkpoint(&mut solver)))
+    }
+}
+
+/// Counts the tasks which have been created but not yet completed, so
+/// worker threads can tell when all the work is done. Idle workers
+/// wait on a condition variable. The generation number is bumped
+/// whenever a task is pushed or the last outstanding task completes,
+/// and is used to avoid missing a signal sent between a worker failing
+/// to find a task and starting to wait. Only when a worker is actually
+/// waiting do we take the lock and notify the condition variable, so
+/// forking is cheap while every worker is busy.
+struct Outstanding {
+    count: AtomicUsize,
+    generation: AtomicU64,
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+
+impl Outstanding {
+    fn new(count: usize) -> Self {
+        Outstanding {
+            count: AtomicUsize::new(count),
+            generation: AtomicU64::new(0),
+            waiters: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn signal(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        // Either a waiter sees the new generation before it sleeps, or
+        // we see it registered here. Taking the lock means it has
+        // started waiting before we notify.
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            drop(self.lock.lock().unwrap());
+            self.changed.notify_all()
+        }
+    }
+
+    /// Count a task as outstanding. This must happen before the task
+    /// is made available to other workers, so it cannot complete
+    /// before it has been counted.
+    fn add(&self) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn completed(&self) {
+        if self.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.signal()
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    fn is_done(&self) -> bool {
+        self.count() == 0
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Wait until a task is pushed or completed after `generation`
+    fn wait(&self, generation: u64) {
+        let mut guard = self.lock.lock().unwrap();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        while self.generation() == generation && !self.is_done() {
+            guard = self.changed.wait(guard).unwrap()
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A worker thread's local queue, which counts each task pushed to it
+/// as outstanding. Tasks pushed while memory usage is over its cap
+/// are written to disk instead.
+struct TaskQueue<'a, 'ir, 'task, B> {
+    tid: usize,
+    local: Worker<Task<'ir, 'task, B>>,
+    outstanding: &'a Outstanding,
+    shed: &'a ShedQueue<'task, B>,
+}
+
+impl<'a, 'ir, 'task, B: BV> TaskQueue<'a, 'ir, 'task, B> {
+    fn push(&self, task: Task<'ir, 'task, B>) {
+        self.outstanding.add();
+        if let Some(task) = self.shed.shed(self.tid, task) {
+            task.queued();
+            self.local.push(task)
+        }
+        self.outstanding.signal()
+    }
+}
+
+/// Start symbolically executing a Task using just the current thread, collecting the results using
+/// the given collector.
+pub fn start_single<'ir, 'task, B: BV, R>(
+    task: Task<'ir, 'task, B>,
+    shared_state: &SharedState<'ir, B>,
+    collected: &R,
+    collector: &Collector<'ir, B, R>,
+) {
+    let outstanding = Outstanding::new(0);
+    let shed = ShedQueue::new();
+    let queue = TaskQueue { tid: 0, local: Worker::new_lifo(), outstanding: &outstanding, shed: &shed };
+    let mut deferred = Vec::new();
+    task.register();
+    queue.push(task);
+    while let Some(task) =
+        queue.local.pop().map(Ok).or_else(|| shed.restore(shared_state)).or_else(|| deferred.pop().map(Ok))
+    {
+        let task = match task {
+            Ok(task) => task,
+            Err((task_id, error)) => {
+                collect_lost_task(0, task_id, error, shared_state, collected, collector);
+                continue;
+            }
+        };
+        if task.deprioritized() && !queue.local.is_empty() {
+            deferred.push(task);
+            continue;
+        }
+        task.dequeued();
+        let fork_log = task.state.fork_log.as_deref();
+        let path = fork_log.map(|_| task.path());
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
+        if let Some(log) = &task.state.query_log {
+            solver.log_queries(log.clone())
+        }
+        if task.state.provenance {
+            solver.track_provenance()
+        }
+        if task.state.symbolic_sleep {
+            solver.symbolic_sleep()
+        }
+        solver.set_unknown_policies(task.state.unknown_policies, task.state.unknown_stats.clone());
+        if let Some(observer) = &task.state.observer {
+            solver.set_observer(observer.clone(), task.id)
+        }
+        if let Some((sink, threshold)) = &task.state.trace_sink {
+            solver.set_trace_sink(sink.clone(), *threshold, task.id)
+        }
+        if let Some((def, event)) = task.fork_cond {
+            solver.add_event(event);
+
+            solver.add(def)
+        };
+        let result = run(
+            0,
+            task.id,
+            Timeout::unlimited(),
+            task.stop_conditions,
+            &queue,
+            &task.frame,
+            task.state,
+            shared_state,
+            &mut solver,
+        );
+        let _finishing = fork_log.map(ForkLog::finishing);
+        collector(0, task.id, result, shared_state, solver, collected);
+        if let (Some(fork_log), Some(path)) = (fork_log, path) {
+            fork_log.complete(path)
+        }
+    }
+}
+
+fn find_task<T>(local: &Worker<T>, global: &Injector<T>, stealers: &RwLock<Vec<Stealer<T>>>) -> Option<T> {
+    let stealers = stealers.read().unwrap();
+    local.pop().or_else(|| {
+        std::iter::repeat_with(|| {
+            let stolen: Steal<T> = stealers.iter().map(|s| s.steal()).collect();
+            stolen.or_else(|| global.steal_batch_and_pop(local))
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+/// Like [find_task], but tasks which are deprioritized are put aside
+/// in `deferred` and only run when no other task can be found.
+fn find_prioritized_task<'ir, 'task, B>(
+    local: &Worker<Task<'ir, 'task, B>>,
+    global: &Injector<Task<'ir, 'task, B>>,
+    deferred: &Injector<Task<'ir, 'task, B>>,
+    stealers: &RwLock<Vec<Stealer<Task<'ir, 'task, B>>>>,
+) -> Option<Task<'ir, 'task, B>> {
+    while let Some(task) = find_task(local, global, stealers) {
+        if task.deprioritized() {
+            deferred.push(task)
+        } else {
+            return Some(task);
+        }
+    }
+    std::iter::repeat_with(|| deferred.steal()).find(|s| !s.is_retry()).and_then(|s| s.success())
+}
+
+/// Report a task which was written to disk, but could not be read
+/// back, to the collector as a failed path.
+fn collect_lost_task<'ir, B: BV, R>(
+    tid: usize,
+    task_id: usize,
+    error: ExecError,
+    shared_state: &SharedState<'ir, B>,
+    collected: &R,
+    collector: &Collector<'ir, B, R>,
+) {
+    let ctx = Context::new(Config::new());
+    let solver = Solver::new(&ctx);
+    collector(tid, task_id, Err((error, Vec::new())), shared_state, solver, collected)
+}
+
+fn do_work<'ir, 'task, B: BV, R>(
+    tid: usize,
+    timeout: Timeout,
+    queue: &TaskQueue<'_, 'ir, 'task, B>,
+    task: Task<'ir, 'task, B>,
+    shared_state: &SharedState<'ir, B>,
+    collected: &R,
+    collector: &Collector<'ir, B, R>,
+) {
+    task.dequeued();
+    let fork_log = task.state.fork_log.as_deref();
+    let path = fork_log.map(|_| task.path());
+    let cfg = Config::new();
+    let ctx = Context::new(cfg);
+    let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
+    if let Some(log) = &task.state.query_log {
+        solver.log_queries(log.clone())
+    }
+    if task.state.provenance {
+        solver.track_provenance()
+    }
+    if task.state.symbolic_sleep {
+        solver.symbolic_sleep()
+    }
+    solver.set_unknown_policies(task.state.unknown_policies, task.state.unknown_stats.clone());
+    if let Some(observer) = &task.state.observer {
+        solver.set_observer(observer.clone(), task.id)
+    }
+    if let Some((sink, threshold)) = &task.state.trace_sink {
+        solver.set_trace_sink(sink.clone(), *threshold, task.id)
+    }
+    if let Some((def, event)) = task.fork_cond {
+        solver.add_event(event);
+        solver.add(def)
+    };
+    let result =
+        run(tid, task.id, timeout, task.stop_conditions, queue, &task.frame, task.state, shared_state, &mut solver);
+    let _finishing = fork_log.map(ForkLog::finishing);
+    collector(tid, task.id, result, shared_state, solver, collected);
+    if let (Some(fork_log), Some(path)) = (fork_log, path) {
+        fork_log.complete(path)
+    }
 }
 
-/// Start symbolically executing a Task across `num_threads` new threads, collecting the results
-/// using the given collector.
+/// Start symbolically executing a Task across up to `num_threads` new threads, collecting the
+/// results using the given collector. Threads are started as the number of outstanding tasks grows,
+/// and wait without spinning whenever there is no work for them.
 pub fn start_multi<'ir, 'task, B: BV, R>(
     num_threads: usize,
     timeout: Option<u64>,
@@ -1717,95 +3496,99 @@ pub fn start_multi<'ir, 'task, B: BV, R>(
 {
     let timeout = Timeout { start_time: Instant::now(), duration: timeout.map(Duration::from_secs) };
 
-    let (tx, rx): (Sender<Activity>, Receiver<Activity>) = mpsc::channel();
     let global: Arc<Injector<Task<B>>> = Arc::new(Injector::<Task<B>>::new());
     let stealers: Arc<RwLock<Vec<Stealer<Task<B>>>>> = Arc::new(RwLock::new(Vec::new()));
+    let deferred: Arc<Injector<Task<B>>> = Arc::new(Injector::<Task<B>>::new());
+    let outstanding = Arc::new(Outstanding::new(tasks.len()));
+    let cores = if affinity::pin_workers() { affinity::available_cores() } else { Vec::new() };
+    // Per-worker counts of completed tasks and time spent on them
+    let throughput: Vec<(AtomicUsize, AtomicU64)> =
+        (0..num_threads).map(|_| (AtomicUsize::new(0), AtomicU64::new(0))).collect();
+
+    let shed = &ShedQueue::new();
 
     for task in tasks {
+        task.register();
+        task.queued();
         global.push(task);
     }
 
-    thread::scope(|scope| {
-        for tid in 0..num_threads {
-            // When a worker is idle, it reports that to the main orchestrating thread, which can
-            // then 'poke' it to wake it up via a channel, which will cause the worker to try to
-            // steal some work, or the main thread can kill the worker.
-            let (poke_tx, poke_rx): (Sender<Response>, Receiver<Response>) = mpsc::channel();
-            let thread_tx = tx.clone();
+    let spawned = thread::scope(|scope| {
+        let spawn_worker = |tid: usize| {
             let global = global.clone();
+            let deferred = deferred.clone();
             let stealers = stealers.clone();
             let collected = collected.clone();
-
+            let outstanding = outstanding.clone();
+            let core = if cores.is_empty() { None } else { Some(cores[tid % cores.len()]) };
+            let (completed, busy) = &throughput[tid];
+
+            // Each worker runs until every task that has been created
+            // has completed. When it cannot find any work it waits to
+            // be signalled that new tasks have been pushed, or that
+            // the last task has completed.
             scope.spawn(move || {
-                let q = Worker::new_lifo();
+                if let Some(core) = core {
+                    if let Err(e) = affinity::pin_current_thread(core) {
+                        log_from!(tid, log::VERBOSE, format!("Failed to pin to core {}: {}", core, e))
+                    }
+                }
+                let q = TaskQueue { tid, local: Worker::new_lifo(), outstanding: &outstanding, shed };
                 {
                     let mut stealers = stealers.write().unwrap();
-                    stealers.push(q.stealer());
+                    stealers.push(q.local.stealer());
                 }
                 loop {
-                    if let Some(task) = find_task(&q, &global, &stealers) {
-                        thread_tx.send(Activity::Busy(tid)).unwrap();
-                        do_work(tid, timeout, &q, task, shared_state, collected.as_ref(), collector);
-                        while let Some(task) = find_task(&q, &global, &stealers) {
-                            do_work(tid, timeout, &q, task, shared_state, collected.as_ref(), collector)
+                    let generation = outstanding.generation();
+                    if let Some(task) = find_prioritized_task(&q.local, &global, &deferred, &stealers)
+                        .map(Ok)
+                        .or_else(|| shed.restore(shared_state))
+                    {
+                        let start = Instant::now();
+                        match task {
+                            Ok(task) => do_work(tid, timeout, &q, task, shared_state, collected.as_ref(), collector),
+                            Err((task_id, error)) => {
+                                collect_lost_task(tid, task_id, error, shared_state, collected.as_ref(), collector)
+                            }
                         }
-                    };
-                    thread_tx.send(Activity::Idle(tid, poke_tx.clone())).unwrap();
-                    match poke_rx.recv().unwrap() {
-                        Response::Poke => (),
-                        Response::Kill => break,
+                        completed.fetch_add(1, Ordering::Relaxed);
+                        busy.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                        outstanding.completed()
+                    } else if outstanding.is_done() {
+                        break;
+                    } else {
+                        outstanding.wait(generation)
                     }
                 }
             });
-        }
+        };
 
-        // Figuring out when to exit is a little complex. We start with only a few threads able to
-        // work because we haven't actually explored any of the state space, so all the other
-        // workers start idle and repeatedly try to steal work. There may be points when workers
-        // have no work, but we want them to become active again if more work becomes available. We
-        // therefore want to exit only when 1) all threads are idle, 2) we've told all the threads
-        // to steal some work, and 3) all the threads fail to do so and remain idle.
-        let mut current_activity = vec![0; num_threads];
-        let mut last_messages = vec![Activity::Busy(0); num_threads];
+        // Workers are started as the number of outstanding tasks
+        // grows, up to `num_threads`, so small queries don't start
+        // threads which would only sit idle.
+        let mut spawned = 0;
         loop {
-            loop {
-                match rx.try_recv() {
-                    Ok(Activity::Busy(tid)) => {
-                        last_messages[tid] = Activity::Busy(tid);
-                        current_activity[tid] = 0;
-                    }
-                    Ok(Activity::Idle(tid, poke)) => {
-                        last_messages[tid] = Activity::Idle(tid, poke);
-                        current_activity[tid] += 1;
-                    }
-                    Err(_) => break,
-                }
+            let generation = outstanding.generation();
+            while spawned < num_threads && spawned < outstanding.count() {
+                spawn_worker(spawned);
+                spawned += 1
             }
-            let mut quiescent = true;
-            for idleness in &current_activity {
-                if *idleness < 2 {
-                    quiescent = false
-                }
+            if spawned == num_threads || outstanding.is_done() {
+                break spawned;
             }
-            if quiescent {
-                for message in &last_messages {
-                    match message {
-                        Activity::Idle(_tid, poke) => poke.send(Response::Kill).unwrap(),
-                        Activity::Busy(tid) => panic!("Found busy thread {} when quiescent", tid),
-                    }
-                }
-                break;
-            }
-            for message in &last_messages {
-                match message {
-                    Activity::Idle(tid, poke) => {
-                        poke.send(Response::Poke).unwrap();
-                        current_activity[*tid] = 1;
-                    }
-                    Activity::Busy(_) => (),
-                }
-            }
-            thread::sleep(Duration::from_millis(1))
+            outstanding.wait(generation)
+        }
+    });
+
+    if_logging!(log::VERBOSE, {
+        for (tid, (completed, busy)) in throughput.iter().take(spawned).enumerate() {
+            let (completed, busy) = (completed.load(Ordering::Relaxed), busy.load(Ordering::Relaxed));
+            let rate = if busy > 0 { completed as f64 / (busy as f64 / 1_000_000.0) } else { 0.0 };
+            log_from!(
+                tid,
+                log::VERBOSE,
+                format!("Completed {} tasks in {}ms ({:.1} tasks/s)", completed, busy / 1000, rate)
+            )
         }
     })
 }
@@ -1816,47 +3599,50 @@ pub fn start_multi<'ir, 'task, B: BV, R>(
 /// true.
 pub fn all_unsat_collector<'ir, B: BV>(
     tid: usize,
-    _: usize,
+    task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     shared_state: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    solver: Solver<B>,
     collected: &AtomicBool,
 ) {
-    match result {
-        Ok(value) => match value {
-            (Val::Symbolic(v), _) => {
-                use smtlib::Def::*;
-                use smtlib::Exp::*;
-                solver.add(Assert(Not(Box::new(Var(v)))));
-                if solver.check_sat() != SmtResult::Unsat {
-                    log_from!(tid, log::VERBOSE, "Got sat");
+    path_results(
+        |tid,
+         path: PathResult<B>,
+         shared_state: &SharedState<'ir, B>,
+         solver: &mut Solver<B>,
+         collected: &AtomicBool| {
+            match (path.value, path.error) {
+                (Some(Val::Symbolic(v)), _) => {
+                    use smtlib::Def::*;
+                    use smtlib::Exp::*;
+                    solver.add(Assert(Not(Box::new(Var(v)))));
+                    if solver.check_sat() != SmtResult::Unsat {
+                        log_from!(tid, log::VERBOSE, "Got sat");
+                        collected.store(false, Ordering::Release)
+                    } else {
+                        log_from!(tid, log::VERBOSE, "Got unsat")
+                    }
+                }
+                (Some(Val::Bool(true)), _) => log_from!(tid, log::VERBOSE, "Got true"),
+                (Some(Val::Bool(false)), _) => {
+                    log_from!(tid, log::VERBOSE, "Got false");
                     collected.store(false, Ordering::Release)
-                } else {
-                    log_from!(tid, log::VERBOSE, "Got unsat")
                 }
-            }
-            (Val::Bool(true), _) => log_from!(tid, log::VERBOSE, "Got true"),
-            (Val::Bool(false), _) => {
-                log_from!(tid, log::VERBOSE, "Got false");
-                collected.store(false, Ordering::Release)
-            }
-            (value, _) => log_from!(tid, log::VERBOSE, &format!("Got value {:?}", value)),
-        },
-        Err((err, backtrace)) => match err {
-            ExecError::Dead => log_from!(tid, log::VERBOSE, "Dead"),
-            _ => {
-                if_logging!(log::VERBOSE, {
-                    log_from!(tid, log::VERBOSE, &format!("Got error, {:?}", err));
-                    for (f, pc) in backtrace.iter().rev() {
-                        log_from!(tid, log::VERBOSE, format!("  {} @ {}", shared_state.symtab.to_str(*f), pc));
-                    }
-                });
-                collected.store(false, Ordering::Release)
+                (_, Some(PathError { error: ExecError::Dead, .. })) => log_from!(tid, log::VERBOSE, "Dead"),
+                (_, Some(PathError { error, backtrace })) => {
+                    if_logging!(log::VERBOSE, {
+                        log_from!(tid, log::VERBOSE, &format!("Got error, {:?}", error));
+                        for (f, pc) in backtrace.iter().rev() {
+                            log_from!(tid, log::VERBOSE, format!("  {} @ {}", shared_state.symtab.to_str(*f), pc));
+                        }
+                    });
+                    collected.store(false, Ordering::Release)
+                }
+                (value, None) => log_from!(tid, log::VERBOSE, &format!("Got value {:?}", value)),
             }
         },
-    }
+    )(tid, task_id, result, shared_state, solver, collected)
 }
-
 #[derive(Debug)]
 pub enum TraceError {
     /// This is returned when we get an unexpected value at the end of
@@ -1911,94 +3697,511 @@ pub fn trace_collector<'ir, B: BV>(
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     shared_state: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    solver: Solver<B>,
     collected: &TraceQueue<B>,
 ) {
-    match result {
-        Ok(_) | Err((ExecError::Exit, _)) => {
-            let mut events = solver.trace().to_vec();
-            collected.push(Ok((task_id, events.drain(..).cloned().collect())))
-        }
-        Err((ExecError::Dead, _)) => (),
-        Err((err, backtrace)) => {
-            log_from!(tid, log::VERBOSE, format!("Error {:?}", err));
-            for (f, pc) in backtrace.iter().rev() {
-                log_from!(tid, log::VERBOSE, format!("  {} @ {}", shared_state.symtab.to_str(*f), pc));
-            }
-            if solver.check_sat() == SmtResult::Sat {
-                let model = Model::new(&solver);
-                collected.push(Err(TraceError::exec_model(err, model)))
-            } else {
-                collected.push(Err(TraceError::exec(err)))
+    path_results(
+        |tid,
+         path: PathResult<B>,
+         shared_state: &SharedState<'ir, B>,
+         solver: &mut Solver<B>,
+         collected: &TraceQueue<B>| {
+            match path.error {
+                None | Some(PathError { error: ExecError::Exit, .. }) => {
+                    collected.push(Ok((path.task_id, path.events)))
+                }
+                Some(PathError { error: ExecError::Dead, .. }) => (),
+                Some(PathError { error, backtrace }) => {
+                    log_from!(tid, log::VERBOSE, format!("Error {:?}", error));
+                    for (f, pc) in backtrace.iter().rev() {
+                        log_from!(tid, log::VERBOSE, format!("  {} @ {}", shared_state.symtab.to_str(*f), pc));
+                    }
+                    if solver.check_sat() == SmtResult::Sat {
+                        let model = Model::new(solver);
+                        collected.push(Err(TraceError::exec_model(error, model)))
+                    } else {
+                        collected.push(Err(TraceError::exec(error)))
+                    }
+                }
             }
-        }
-    }
+        },
+    )(tid, task_id, result, shared_state, solver, collected)
 }
 
 pub fn trace_value_collector<'ir, B: BV>(
-    _: usize,
+    tid: usize,
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
-    _: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
     collected: &TraceValueQueue<B>,
 ) {
-    match result {
-        Ok((val, _)) => {
-            let mut events = solver.trace().to_vec();
-            collected.push(Ok((task_id, val, events.drain(..).cloned().collect())))
-        }
-        Err((ExecError::Dead, _)) => (),
-        Err((err, _)) => {
-            if solver.check_sat() == SmtResult::Sat {
-                let model = Model::new(&solver);
-                collected.push(Err(TraceError::exec_model(err, model)))
-            } else {
-                collected.push(Err(TraceError::exec(err)))
+    path_results(
+        |_, path: PathResult<B>, _: &SharedState<'ir, B>, solver: &mut Solver<B>, collected: &TraceValueQueue<B>| {
+            match path.error {
+                // Suspended paths have no return value
+                None => collected.push(Ok((path.task_id, path.value.unwrap_or(Val::Poison), path.events))),
+                Some(PathError { error: ExecError::Dead, .. }) => (),
+                Some(PathError { error, .. }) => {
+                    if solver.check_sat() == SmtResult::Sat {
+                        let model = Model::new(solver);
+                        collected.push(Err(TraceError::exec_model(error, model)))
+                    } else {
+                        collected.push(Err(TraceError::exec(error)))
+                    }
+                }
             }
-        }
-    }
+        },
+    )(tid, task_id, result, shared_state, solver, collected)
 }
 
 pub fn trace_result_collector<'ir, B: BV>(
-    _: usize,
+    tid: usize,
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
-    _: &SharedState<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
     solver: Solver<B>,
     collected: &TraceResultQueue<B>,
 ) {
-    match result {
-        Ok((Val::Bool(result), _)) => {
-            let mut events = solver.trace().to_vec();
-            collected.push(Ok((task_id, result, events.drain(..).cloned().collect())))
-        }
-        Ok((val, _)) => collected.push(Err(TraceError::unexpected_value(val))),
-        Err((ExecError::Dead, _)) => (),
-        Err((err, _)) => collected.push(Err(TraceError::exec(err))),
-    }
+    path_results(
+        |_, path: PathResult<B>, _: &SharedState<'ir, B>, _: &mut Solver<B>, collected: &TraceResultQueue<B>| match (
+            path.value, path.error,
+        ) {
+            (Some(Val::Bool(result)), _) => collected.push(Ok((path.task_id, result, path.events))),
+            (_, Some(PathError { error: ExecError::Dead, .. })) => (),
+            (_, Some(PathError { error, .. })) => collected.push(Err(TraceError::exec(error))),
+            (value, None) => collected.push(Err(TraceError::unexpected_value(value.unwrap_or(Val::Poison)))),
+        },
+    )(tid, task_id, result, shared_state, solver, collected)
 }
 
 pub fn footprint_collector<'ir, B: BV>(
-    _: usize,
+    tid: usize,
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
-    _: &SharedState<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
     solver: Solver<B>,
     collected: &TraceQueue<B>,
 ) {
+    path_results(|_, path: PathResult<B>, _: &SharedState<'ir, B>, _: &mut Solver<B>, collected: &TraceQueue<B>| {
+        match (path.value, path.error) {
+            // Footprint function returns true on traces we need to consider as part of the footprint
+            (Some(Val::Bool(true)), _) => collected.push(Ok((path.task_id, path.events))),
+            // If it returns false or unit, we ignore that trace
+            (Some(Val::Bool(false)), _) => (),
+            (_, Some(PathError { error: ExecError::Dead, .. })) => (),
+            (_, Some(PathError { error, .. })) => collected.push(Err(TraceError::exec(error))),
+            // Anything else is an error!
+            (value, None) => collected.push(Err(TraceError::unexpected_value(value.unwrap_or(Val::Poison)))),
+        }
+    })(tid, task_id, result, shared_state, solver, collected)
+}
+/// The result of a single path, as passed to a [Collector]
+pub type CollectorResult<'ir, B> = Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>;
+
+fn duplicate_result<'ir, B: BV>(result: &CollectorResult<'ir, B>) -> CollectorResult<'ir, B> {
     match result {
-        // Footprint function returns true on traces we need to consider as part of the footprint
-        Ok((Val::Bool(true), _)) => {
-            let mut events = solver.trace().to_vec();
-            collected.push(Ok((task_id, events.drain(..).cloned().collect())))
-        }
-        // If it returns false or unit, we ignore that trace
-        Ok((Val::Bool(false), _)) => (),
-        // Anything else is an error!
-        Ok((val, _)) => collected.push(Err(TraceError::unexpected_value(val))),
-
-        Err((ExecError::Dead, _)) => (),
-        Err((err, _)) => collected.push(Err(TraceError::exec(err))),
+        Ok((val, frame)) => Ok((val.clone(), unfreeze_frame(&freeze_frame(frame)))),
+        Err((err, backtrace)) => Err((err.clone(), backtrace.clone())),
+    }
+}
+
+/// Combine two collectors, running both on every path and collecting
+/// into a pair. The first collector is given a copy of the solver
+/// replayed from the trace of the original, so this should be avoided
+/// for collectors which expect the solver's query log or provenance
+/// tracking to be enabled.
+pub fn both<'ir, B, R1, R2, C1, C2>(
+    first: C1,
+    second: C2,
+) -> impl Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &(R1, R2))
+where
+    B: BV,
+    C1: Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R1),
+    C2: Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R2),
+{
+    move |tid: usize,
+          task_id: usize,
+          result: CollectorResult<'ir, B>,
+          shared_state: &SharedState<'ir, B>,
+          mut solver: Solver<B>,
+          (collected1, collected2): &(R1, R2)| {
+        let point = checkpoint(&mut solver);
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        first(tid, task_id, duplicate_result(&result), shared_state, Solver::from_checkpoint(&ctx, point), collected1);
+        second(tid, task_id, result, shared_state, solver, collected2)
+    }
+}
+
+/// Only pass paths to a collector if they succeed or fail with an
+/// error accepted by `keep`, e.g. to ignore paths which reach an
+/// instruction limit.
+pub fn filter_errors<'ir, B, R, C, P>(
+    keep: P,
+    collector: C,
+) -> impl Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R)
+where
+    B: BV,
+    C: Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R),
+    P: Sync + Fn(&ExecError) -> bool,
+{
+    move |tid: usize,
+          task_id: usize,
+          result: CollectorResult<'ir, B>,
+          shared_state: &SharedState<'ir, B>,
+          solver: Solver<B>,
+          collected: &R| {
+        if let Err((err, _)) = &result {
+            if !keep(err) {
+                return;
+            }
+        }
+        collector(tid, task_id, result, shared_state, solver, collected)
+    }
+}
+
+/// Transform the result of each path before passing it to a
+/// collector, for example to turn specific errors into successful
+/// return values.
+pub fn map_results<'ir, B, R, C, F>(
+    f: F,
+    collector: C,
+) -> impl Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R)
+where
+    B: BV,
+    C: Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R),
+    F: Sync + Fn(CollectorResult<'ir, B>) -> CollectorResult<'ir, B>,
+{
+    move |tid: usize,
+          task_id: usize,
+          result: CollectorResult<'ir, B>,
+          shared_state: &SharedState<'ir, B>,
+          solver: Solver<B>,
+          collected: &R| { collector(tid, task_id, f(result), shared_state, solver, collected) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Def;
+
+    #[test]
+    fn eager_enum_splits() {
+        let mut symtab = Symtab::new();
+        let e = symtab.intern("zE");
+        let a = symtab.intern("zA");
+        let b = symtab.intern("zB");
+        let c = symtab.intern("zC");
+        let f = symtab.intern("zf");
+        let x = symtab.intern("zx");
+        let info = SourceLoc::unknown();
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Enum(e, vec![a, b, c]),
+            Def::Val(f, vec![Ty::Enum(e)], Ty::Bool),
+            Def::Fn(
+                f,
+                vec![x],
+                vec![
+                    Instr::Jump(Exp::Call(Op::Eq, vec![Exp::Id(x), Exp::Id(a)]), 3, info),
+                    Instr::Copy(Loc::Id(RETURN), Exp::Bool(false), info),
+                    Instr::Goto(4),
+                    Instr::Copy(Loc::Id(RETURN), Exp::Bool(true), info),
+                    Instr::End,
+                ],
+            ),
+        ];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let run = |task_state: &TaskState<B64>| {
+            let (frame, checkpoint) = TaskBuilder::new(f).symbolic_arg().build(&shared_state).unwrap();
+            let task = frame.task_with_checkpoint(0, task_state, checkpoint);
+            let queue = Arc::new(SegQueue::new());
+            start_multi(1, None, vec![task], &shared_state, queue.clone(), &trace_result_collector);
+            let mut results = Vec::new();
+            while let Some(result) = queue.pop() {
+                let (_, result, events) = result.unwrap();
+                results.push((result, events))
+            }
+            results
+        };
+
+        // Without eager splitting the jump forks once on the symbolic comparison
+        let results = run(&TaskState::new());
+        assert_eq!(results.len(), 2);
+
+        // With eager splitting we get one path per enum member, each
+        // having made the variable concrete before the jump
+        let mut task_state = TaskState::new();
+        task_state.set_eager_enum_splits(true);
+        let results = run(&task_state);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results.iter().filter(|(result, _)| *result).count(), 1);
+        for (_, events) in &results {
+            assert_eq!(events.iter().filter(|event| matches!(event, Event::Fork(..))).count(), 1)
+        }
+
+        // Each path took a different member of the enumeration
+        let mut choices: Vec<u32> = results
+            .iter()
+            .map(|(_, events)| {
+                events
+                    .iter()
+                    .find_map(|event| if let Event::Fork(_, _, n, _) = event { Some(*n) } else { None })
+                    .unwrap()
+            })
+            .collect();
+        choices.sort_unstable();
+        assert_eq!(choices, vec![0, 1, 2])
+    }
+
+    #[test]
+    fn register_index_splits() {
+        let mut symtab = Symtab::new();
+        let regs: Vec<Name> = (0..3).map(|i| symtab.intern(&format!("zR{}", i))).collect();
+        let f = symtab.intern("zf");
+        let n = symtab.intern("zn");
+        let rs = symtab.intern("zrs");
+        let info = SourceLoc::unknown();
+
+        let mut defs: Vec<Def<Name, B64>> = regs.iter().map(|reg| Def::Register(*reg, Ty::Bits(8))).collect();
+        defs.push(Def::Val(f, vec![Ty::Bits(2), Ty::Vector(Box::new(Ty::Ref(Box::new(Ty::Bits(8)))))], Ty::Bits(8)));
+        defs.push(Def::Fn(
+            f,
+            vec![n, rs],
+            vec![
+                Instr::Call(Loc::Id(RETURN), false, READ_REGISTER_FROM_VECTOR, vec![Exp::Id(n), Exp::Id(rs)], info),
+                Instr::End,
+            ],
+        ));
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let mut task_state = TaskState::new();
+        task_state.set_register_index_strategy(RegisterIndexStrategy::CaseSplit);
+
+        let mut builder =
+            TaskBuilder::new(f).symbolic_arg().arg(Val::Vector(regs.iter().map(|reg| Val::Ref(*reg)).collect()));
+        for (i, reg) in regs.iter().enumerate() {
+            builder = builder.register(*reg, Val::Bits(B64::new(i as u64, 8)))
+        }
+        let (frame, checkpoint) = builder.build(&shared_state).unwrap();
+        let task = frame.task_with_checkpoint(0, &task_state, checkpoint);
+        let queue = Arc::new(SegQueue::new());
+        start_multi(1, None, vec![task], &shared_state, queue.clone(), &trace_value_collector);
+
+        // The 2-bit index could also be 3, but there are only three
+        // registers, so we get one path per register, each reading a
+        // concrete register after a single fork
+        let mut values = Vec::new();
+        while let Some(result) = queue.pop() {
+            let (_, value, events) = result.unwrap();
+            assert_eq!(events.iter().filter(|event| matches!(event, Event::Fork(..))).count(), 1);
+            values.push(value)
+        }
+        values.sort_by_key(|value| if let Val::Bits(bv) = value { bv.lower_u64() } else { u64::MAX });
+        assert_eq!(values, (0..3).map(|i| Val::Bits(B64::new(i, 8))).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn reset_constraint_names_per_thread() {
+        use crate::simplify::{write_events_with_opts, WriteOpts};
+
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("zf");
+        let info = SourceLoc::unknown();
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Val(f, vec![], Ty::Bool),
+            Def::Fn(f, vec![], vec![Instr::Copy(Loc::Id(RETURN), Exp::Bool(true), info), Instr::End]),
+        ];
+        let reset_constraints = vec![smtlib::Exp::Bool(true), smtlib::Exp::Bool(true)];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), reset_constraints, Vec::new());
+        let task_state = TaskState::new();
+
+        // Write the traces of two threads into one file, as
+        // isla-axiomatic does for each candidate execution
+        let mut buf = Vec::new();
+        for tid in 0..2 {
+            let (mut frame, checkpoint) = TaskBuilder::new(f).build(&shared_state).unwrap();
+            let ctx = Context::new(Config::new());
+            let mut solver = Solver::from_checkpoint(&ctx, checkpoint);
+            reset_registers(tid, &mut frame, &task_state, &shared_state, &mut solver, info).unwrap();
+            let events: Vec<Event<B64>> = solver.trace().to_vec().into_iter().cloned().collect();
+            write_events_with_opts(&mut buf, &events, &shared_state.symtab, &WriteOpts::smtlib()).unwrap();
+        }
+        let smt = String::from_utf8(buf).unwrap();
+
+        let names: Vec<&str> = smt.split(":named |").skip(1).map(|s| s.split('|').next().unwrap()).collect();
+        assert_eq!(names.len(), 4);
+        let unique: HashSet<&str> = names.iter().copied().collect();
+        assert_eq!(unique.len(), 4, "duplicate assertion names in {}", smt)
+    }
+
+    #[test]
+    fn field_write_read_round_trip() {
+        use crate::config::RegisterField;
+
+        let mut symtab = Symtab::new();
+        let s = symtab.intern("zS");
+        let fld = symtab.intern("zfld");
+        let other = symtab.intern("zother");
+        let r = symtab.intern("zR");
+        let p = symtab.intern("zP");
+        let f_struct = symtab.intern("zf_struct");
+        let f_packed = symtab.intern("zf_packed");
+        let x = symtab.intern("zx");
+        let info = SourceLoc::unknown();
+
+        // Write a field of a register, then read it back
+        let body = |reg| {
+            vec![
+                Instr::Copy(Loc::Field(Box::new(Loc::Id(reg)), fld), Exp::Id(x), info),
+                Instr::Copy(Loc::Id(RETURN), Exp::Field(Box::new(Exp::Id(reg)), fld), info),
+                Instr::End,
+            ]
+        };
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Struct(s, vec![(fld, Ty::Bits(8)), (other, Ty::Bits(8))]),
+            Def::Register(r, Ty::Struct(s)),
+            Def::Register(p, Ty::Bits(16)),
+            Def::Val(f_struct, vec![Ty::Bits(8)], Ty::Bits(8)),
+            Def::Fn(f_struct, vec![x], body(r)),
+            Def::Val(f_packed, vec![Ty::Bits(8)], Ty::Bits(8)),
+            Def::Fn(f_packed, vec![x], body(p)),
+        ];
+        let mut shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        // The struct register also has a packed layout with the same
+        // field name, in which case the struct field is used for both
+        // reads and writes
+        let mut register_fields = HashMap::new();
+        for reg in [r, p] {
+            register_fields.insert(reg, vec![RegisterField { name: "fld".to_string(), hi: 15, lo: 8 }]);
+        }
+        shared_state.set_register_fields(register_fields);
+
+        let struct_value: HashMap<Name, Val<B64>, HashState> =
+            [(fld, Val::Bits(B64::new(0, 8))), (other, Val::Bits(B64::new(0x12, 8)))].into_iter().collect();
+
+        let run = |f: Name| {
+            let (frame, checkpoint) = TaskBuilder::new(f)
+                .arg(Val::Bits(B64::new(0xAB, 8)))
+                .register(r, Val::Struct(struct_value.clone()))
+                .register(p, Val::Bits(B64::new(0x00CD, 16)))
+                .build(&shared_state)
+                .unwrap();
+            let task = frame.task_with_checkpoint(0, &TaskState::new(), checkpoint);
+            let queue = Arc::new(SegQueue::new());
+            start_multi(1, None, vec![task], &shared_state, queue.clone(), &trace_value_collector);
+            let (_, value, events) = queue.pop().unwrap().unwrap();
+            assert!(queue.pop().is_none());
+            (value, events)
+        };
+
+        let (value, events) = run(f_struct);
+        assert_eq!(value, Val::Bits(B64::new(0xAB, 8)));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::WriteReg(reg, _, Val::Struct(fields))
+                if *reg == r && fields[&fld] == Val::Bits(B64::new(0xAB, 8))
+                    && fields[&other] == Val::Bits(B64::new(0x12, 8))
+        )));
+
+        let (value, events) = run(f_packed);
+        assert_eq!(value, Val::Bits(B64::new(0xAB, 8)));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::WriteReg(reg, accessor, Val::Bits(bits))
+                if *reg == p && *accessor == vec![Accessor::Field(fld)] && *bits == B64::new(0xABCD, 16)
+        )));
+    }
+
+    #[test]
+    fn symbolic_interrupt_arrives_at_most_once() {
+        use crate::primop_util::smt_value;
+
+        let mut symtab = Symtab::new();
+        let irq = symtab.intern("zirq");
+        let count = symtab.intern("zcount");
+        let f = symtab.intern("zf");
+        let info = SourceLoc::unknown();
+        let cycle_count = primop::unary_primops::<B64>()["cycle_count"];
+
+        // After each instruction boundary, acknowledge any pending
+        // interrupt and count it
+        let mut body = Vec::new();
+        for _ in 0..3 {
+            body.push(Instr::PrimopUnary(Loc::Id(RETURN), cycle_count, Exp::Unit, info));
+            body.push(Instr::Copy(Loc::Id(count), Exp::Call(Op::Bvadd, vec![Exp::Id(count), Exp::Id(irq)]), info));
+            body.push(Instr::Copy(Loc::Id(irq), Exp::Bits(B64::new(0, 8)), info));
+        }
+        body.push(Instr::Copy(Loc::Id(RETURN), Exp::Id(count), info));
+        body.push(Instr::End);
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Register(irq, Ty::Bits(8)),
+            Def::Register(count, Ty::Bits(8)),
+            Def::Val(f, vec![], Ty::Bits(8)),
+            Def::Fn(f, vec![], body),
+        ];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let mut task_state = TaskState::new();
+        task_state.set_interrupts(vec![
+            Interrupt::new(Arrival::Symbolic { from: 0, to: 3 }).assign(Loc::Id(irq), Val::Bits(B64::new(1, 8)))
+        ]);
+
+        let (frame, checkpoint) = TaskBuilder::new(f)
+            .register(irq, Val::Bits(B64::new(0, 8)))
+            .register(count, Val::Bits(B64::new(0, 8)))
+            .build(&shared_state)
+            .unwrap();
+        let task = frame.task_with_checkpoint(0, &task_state, checkpoint);
+        let queue = Arc::new(SegQueue::new());
+        start_multi(
+            1,
+            None,
+            vec![task],
+            &shared_state,
+            queue.clone(),
+            &|_: usize,
+              _: usize,
+              result: CollectorResult<B64>,
+              _: &SharedState<B64>,
+              mut solver: Solver<B64>,
+              collected: &SegQueue<(Vec<bool>, usize)>| {
+                let (value, _) = result.unwrap();
+                let value = smt_value(&value, info).unwrap();
+                let possible = (0..3)
+                    .map(|n| {
+                        let exp =
+                            smtlib::Exp::Eq(Box::new(value.clone()), Box::new(smtlib::Exp::Bits64(B64::new(n, 8))));
+                        solver.check_sat_with(&exp).is_sat().unwrap()
+                    })
+                    .collect();
+                let interrupts = solver
+                    .trace()
+                    .to_vec()
+                    .into_iter()
+                    .filter(|event| matches!(event, Event::Interrupt { .. }))
+                    .count();
+                collected.push((possible, interrupts))
+            },
+        );
+
+        let (possible, interrupts) = queue.pop().unwrap();
+        assert!(queue.pop().is_none());
+        // The interrupt may arrive once, or not at all, but not twice
+        assert_eq!(possible, vec![true, true, false]);
+        // The arrival point is declared once, at the start of the window
+        assert_eq!(interrupts, 1)
     }
 }
@@ -0,0 +1,716 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module compares the final states of two paths. Given the
+//! [FinalState] of each, [compare_states] asks the solver whether
+//! the final registers or memory can differ for some input which
+//! is consistent with both path conditions, and if so reports a
+//! model which distinguishes them.
+//!
+//! The inputs of the two paths are the variables they share, so both
+//! must have been executed from the same checkpoint, e.g. as paths of
+//! the same execution, or as tasks started from a common checkpoint
+//! which run two instruction sequences (to check they are
+//! equivalent), or one sequence and a specification function. The
+//! variables each path declares after they diverge are kept
+//! separate.
+//!
+//! Memory is compared at a single symbolic address. The memory
+//! accesses each path makes after they diverge are replayed against
+//! the same unknown initial memory, so reads of the same location
+//! return the same value on both sides.
+//!
+//! This is also used to check an implementation against a
+//! specification. A Sail specification function is run from the same
+//! checkpoint as the implementation (see [spec_frame] and
+//! [LocalFrame::initialize_registers]), and [check_against_spec]
+//! gives a [Verdict] for each implementation path. Alternatively
+//! [check_property] checks the final state of a path against an SMT
+//! property of its registers.
+
+use crossbeam::queue::SegQueue;
+
+use super::counterexample::{model_exp, model_value, Counterexample};
+use super::path_result::{path_results, PathError, PathResult, PathStatus};
+use super::*;
+use crate::primop_util::{mixed_bits_to_smt, symbolic};
+use crate::smt::smtlib::{bits64, Exp, Ty};
+
+/// The final state of a path which returned
+#[derive(Clone)]
+pub struct FinalState<B> {
+    pub task_id: usize,
+    /// The final value of every initialized register
+    pub registers: HashMap<Name, Val<B>>,
+    checkpoint: Checkpoint<B>,
+}
+
+impl<B: BV> FinalState<B> {
+    /// The final state of a path, given the solver passed to the
+    /// collector with it. Returns `None` if the path did not return.
+    pub fn new(path: &PathResult<B>, solver: &mut Solver<B>) -> Option<Self> {
+        if path.status != PathStatus::Returned {
+            return None;
+        }
+        Some(FinalState { task_id: path.task_id, registers: path.registers.clone(), checkpoint: checkpoint(solver) })
+    }
+}
+
+/// The final state of a path, or the task id and error for a path
+/// which exited or failed
+pub type PathOutcome<B> = Result<FinalState<B>, (usize, PathError)>;
+
+pub type FinalStateQueue<B> = SegQueue<PathOutcome<B>>;
+
+/// Collect the final state of every path which returned, and the
+/// error for every path which exited or failed. Dead and suspended
+/// paths are ignored.
+pub fn final_state_collector<'ir, B: BV>(
+    tid: usize,
+    task_id: usize,
+    result: CollectorResult<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
+    collected: &FinalStateQueue<B>,
+) {
+    path_results(
+        |_, path: PathResult<B>, _: &SharedState<'ir, B>, solver: &mut Solver<B>, collected: &FinalStateQueue<B>| {
+            if let Some(state) = FinalState::new(&path, solver) {
+                collected.push(Ok(state))
+            } else if let Some(error) = path.error {
+                if path.status != PathStatus::Dead {
+                    collected.push(Err((path.task_id, error)))
+                }
+            }
+        },
+    )(tid, task_id, result, shared_state, solver, collected)
+}
+
+/// A part of the final state which differs between two paths, with
+/// its value on each
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference<B> {
+    Register { name: Name, left: Val<B>, right: Val<B> },
+    Memory { address: Val<B>, left: Val<B>, right: Val<B> },
+}
+
+/// A model distinguishing the final states of two paths
+#[derive(Clone, Debug)]
+pub struct Distinction<B> {
+    /// Model values for the undefined variables shared by both paths
+    pub inputs: Vec<(Sym, Val<B>)>,
+    pub differences: Vec<Difference<B>>,
+}
+
+impl<B: BV> Distinction<B> {
+    pub fn to_string(&self, symtab: &Symtab) -> String {
+        let mut lines = vec!["final states differ".to_string()];
+        for difference in &self.differences {
+            match difference {
+                Difference::Register { name, left, right } => lines.push(format!(
+                    "  {}: {} != {}",
+                    zencode::decode(symtab.to_str(*name)),
+                    left.to_string(symtab),
+                    right.to_string(symtab)
+                )),
+                Difference::Memory { address, left, right } => lines.push(format!(
+                    "  memory at {}: {} != {}",
+                    address.to_string(symtab),
+                    left.to_string(symtab),
+                    right.to_string(symtab)
+                )),
+            }
+        }
+        if !self.inputs.is_empty() {
+            lines.push("when".to_string())
+        }
+        for (v, value) in &self.inputs {
+            lines.push(format!("  {} = {}", v, value.to_string(symtab)))
+        }
+        lines.join("\n")
+    }
+}
+
+/// The result of [compare_states]
+#[derive(Clone, Debug)]
+pub enum Comparison<B> {
+    /// The final states are the same for every input consistent with
+    /// both path conditions (including when there is no such input)
+    Equivalent,
+    Distinguished(Distinction<B>),
+    /// The solver could not decide whether the final states can differ
+    Unknown,
+}
+
+enum MemAccess<B> {
+    Read { address: Val<B>, value: Val<B>, bytes: u32 },
+    Write { address: Val<B>, data: Val<B>, bytes: u32 },
+}
+
+/// A write to memory, as the address, data, and number of bytes
+type MemWrite = (Exp<Sym>, Exp<Sym>, u32);
+
+fn rename(renaming: &HashMap<Sym, Sym>, v: Sym) -> Sym {
+    renaming.get(&v).copied().unwrap_or(v)
+}
+
+fn rename_exp(exp: &mut Exp<Sym>, renaming: &HashMap<Sym, Sym>) {
+    exp.modify(&mut |exp| match exp {
+        Exp::Var(v) | Exp::App(v, _) => *v = rename(renaming, *v),
+        Exp::Forall(vars, _) | Exp::Exists(vars, _) => vars.iter_mut().for_each(|(v, _)| *v = rename(renaming, *v)),
+        Exp::Let(bindings, _) => bindings.iter_mut().for_each(|(v, _)| *v = rename(renaming, *v)),
+        _ => (),
+    })
+}
+
+fn rename_def(def: &Def, renaming: &HashMap<Sym, Sym>) -> Def {
+    let mut def = def.clone();
+    match &mut def {
+        Def::DeclareConst(v, _) | Def::DeclareFun(v, _, _) => *v = rename(renaming, *v),
+        Def::DefineConst(v, exp) => {
+            *v = rename(renaming, *v);
+            rename_exp(exp, renaming)
+        }
+        Def::Assert(exp) | Def::NamedAssert(_, _, exp) => rename_exp(exp, renaming),
+        Def::DefineEnum(_) => (),
+    }
+    def
+}
+
+fn rename_val<B: BV>(val: &Val<B>, renaming: &HashMap<Sym, Sym>) -> Val<B> {
+    match val {
+        Val::Symbolic(v) => Val::Symbolic(rename(renaming, *v)),
+        Val::MixedBits(segments) => Val::MixedBits(
+            segments
+                .iter()
+                .map(|segment| match segment {
+                    BitsSegment::Symbolic(v) => BitsSegment::Symbolic(rename(renaming, *v)),
+                    BitsSegment::Concrete(bv) => BitsSegment::Concrete(*bv),
+                })
+                .collect(),
+        ),
+        Val::Vector(vals) => Val::Vector(vals.iter().map(|v| rename_val(v, renaming)).collect()),
+        Val::List(vals) => Val::List(vals.iter().map(|v| rename_val(v, renaming)).collect()),
+        Val::Struct(fields) => Val::Struct(fields.iter().map(|(k, v)| (*k, rename_val(v, renaming))).collect()),
+        Val::Ctor(ctor, v) => Val::Ctor(*ctor, Box::new(rename_val(v, renaming))),
+        Val::SymbolicCtor(tag, possibilities) => Val::SymbolicCtor(
+            rename(renaming, *tag),
+            possibilities.iter().map(|(k, v)| (*k, rename_val(v, renaming))).collect(),
+        ),
+        _ => val.clone(),
+    }
+}
+
+fn value_exp<B: BV>(val: &Val<B>, solver: &mut Solver<B>) -> Option<Exp<Sym>> {
+    match val {
+        Val::MixedBits(_) => mixed_bits_to_smt(val.clone(), solver, SourceLoc::unknown()).ok(),
+        _ => smt_value(val, SourceLoc::unknown()).ok(),
+    }
+}
+
+/// A boolean expression which is true when the two values differ.
+/// Values which cannot be represented in SMT are compared literally.
+fn differs<B: BV>(left: &Val<B>, right: &Val<B>, solver: &mut Solver<B>) -> Exp<Sym> {
+    match (left, right) {
+        (Val::Struct(l_fields), Val::Struct(r_fields)) => {
+            let mut fields: Vec<_> = l_fields.keys().filter(|k| r_fields.contains_key(k)).collect();
+            fields.sort();
+            disjunction(fields.into_iter().map(|k| differs(&l_fields[k], &r_fields[k], solver)).collect())
+        }
+        (Val::Vector(l_vals), Val::Vector(r_vals)) if l_vals.len() == r_vals.len() => {
+            disjunction(l_vals.iter().zip(r_vals).map(|(l, r)| differs(l, r, solver)).collect())
+        }
+        _ => match (value_exp(left, solver), value_exp(right, solver)) {
+            (Some(l), Some(r)) => Exp::Neq(Box::new(l), Box::new(r)),
+            _ => Exp::Bool(left != right),
+        },
+    }
+}
+
+fn disjunction(exps: Vec<Exp<Sym>>) -> Exp<Sym> {
+    exps.into_iter().reduce(|lhs, rhs| Exp::Or(Box::new(lhs), Box::new(rhs))).unwrap_or(Exp::Bool(false))
+}
+
+fn address_width<B: BV>(address: &Val<B>, solver: &mut Solver<B>) -> Option<u32> {
+    match address {
+        Val::Bits(bv) => Some(bv.len()),
+        Val::Symbolic(v) => solver.length(*v),
+        _ => None,
+    }
+}
+
+/// The byte at `address` after `writes`, in a memory which
+/// initially contains `memory`
+fn byte_at(address: Exp<Sym>, width: u32, memory: Sym, writes: &[MemWrite]) -> Exp<Sym> {
+    let mut byte = Exp::Select(Box::new(Exp::Var(memory)), Box::new(address.clone()));
+    for (write_address, data, bytes) in writes {
+        for i in 0..*bytes {
+            let offset = Exp::Bvadd(Box::new(write_address.clone()), Box::new(bits64(i as u64, width)));
+            byte = Exp::Ite(
+                Box::new(Exp::Eq(Box::new(address.clone()), Box::new(offset))),
+                Box::new(Exp::Extract(i * 8 + 7, i * 8, Box::new(data.clone()))),
+                Box::new(byte),
+            )
+        }
+    }
+    byte
+}
+
+fn mem_accesses<B: BV>(events: &[Event<B>], renaming: &HashMap<Sym, Sym>) -> Vec<MemAccess<B>> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::ReadMem { address, value, bytes, .. } => Some(MemAccess::Read {
+                address: rename_val(address, renaming),
+                value: rename_val(value, renaming),
+                bytes: *bytes,
+            }),
+            Event::WriteMem { address, data, bytes, .. } => Some(MemAccess::Write {
+                address: rename_val(address, renaming),
+                data: rename_val(data, renaming),
+                bytes: *bytes,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Constrain each read to return the bytes of the initial `memory`,
+/// as overwritten by any preceding writes (taking memory to be
+/// little-endian), and return all the writes.
+fn replay_accesses<B: BV>(
+    accesses: &[MemAccess<B>],
+    width: u32,
+    memory: Sym,
+    solver: &mut Solver<B>,
+) -> Result<Vec<MemWrite>, ExecError> {
+    let info = SourceLoc::unknown();
+    let mut writes = Vec::new();
+    for access in accesses {
+        let (MemAccess::Read { address, value: data, bytes } | MemAccess::Write { address, data, bytes }) = access;
+        if address_width(address, solver) != Some(width) {
+            return Err(ExecError::Type(format!("Cannot compare memory access at {:?}", address), info));
+        }
+        let (Some(address), Some(data)) = (value_exp(address, solver), value_exp(data, solver)) else {
+            return Err(ExecError::Type("Cannot compare memory access".to_string(), info));
+        };
+        match access {
+            MemAccess::Read { .. } => {
+                for i in 0..*bytes {
+                    let offset = Exp::Bvadd(Box::new(address.clone()), Box::new(bits64(i as u64, width)));
+                    let byte = Exp::Extract(i * 8 + 7, i * 8, Box::new(data.clone()));
+                    solver.add(Def::Assert(Exp::Eq(Box::new(byte), Box::new(byte_at(offset, width, memory, &writes)))))
+                }
+            }
+            MemAccess::Write { .. } => writes.push((address, data, *bytes)),
+        }
+    }
+    Ok(writes)
+}
+
+/// Check whether the final states of two paths can differ for some
+/// input consistent with both path conditions. Registers which are
+/// not initialized in both states, or which are in `ignore`, are not
+/// compared.
+pub fn compare_states<B: BV>(
+    left: &FinalState<B>,
+    right: &FinalState<B>,
+    ignore: &HashSet<Name>,
+) -> Result<Comparison<B>, ExecError> {
+    let info = SourceLoc::unknown();
+    let shared = left.checkpoint.shared_events(&right.checkpoint);
+    let left_events: Vec<Event<B>> = left.checkpoint.segments().into_iter().flatten().collect();
+    let right_events: Vec<Event<B>> = right.checkpoint.segments().into_iter().flatten().skip(shared).collect();
+
+    let mut cfg = Config::new();
+    cfg.set_param_value("model", "true");
+    let ctx = Context::new(cfg);
+    let mut solver = Solver::from_checkpoint(&ctx, left.checkpoint.clone());
+
+    // Every variable the right path declares after the paths diverge
+    // is given a fresh name, so it is distinct from the left path's
+    let mut renaming = HashMap::new();
+    for event in &right_events {
+        if let Event::Smt(def, _, _) = event {
+            if let Some(v) = event.defines() {
+                let fresh = solver.fresh();
+                renaming.insert(v, fresh);
+            }
+            solver.add(rename_def(def, &renaming))
+        }
+    }
+
+    let mut names: Vec<Name> = left
+        .registers
+        .keys()
+        .filter(|name| right.registers.contains_key(name) && !ignore.contains(name))
+        .copied()
+        .collect();
+    names.sort();
+    let registers: Vec<(Name, &Val<B>, Val<B>)> = names
+        .into_iter()
+        .map(|name| (name, &left.registers[&name], rename_val(&right.registers[&name], &renaming)))
+        .collect();
+
+    let mut conditions: Vec<Exp<Sym>> = registers.iter().map(|(_, l, r)| differs(l, r, &mut solver)).collect();
+
+    // Memory reads and writes after the paths diverge are replayed
+    // against the same initial memory
+    let left_accesses = mem_accesses(&left_events[shared..], &HashMap::new());
+    let right_accesses = mem_accesses(&right_events, &renaming);
+    let memory = match left_accesses.first().or_else(|| right_accesses.first()) {
+        Some(MemAccess::Read { address, .. } | MemAccess::Write { address, .. }) => {
+            let Some(width) = address_width(address, &mut solver) else {
+                return Err(ExecError::Type(format!("Cannot compare memory access at {:?}", address), info));
+            };
+            let memory = solver.declare_const(Ty::Array(Box::new(Ty::BitVec(width)), Box::new(Ty::BitVec(8))), info);
+            let left_writes = replay_accesses(&left_accesses, width, memory, &mut solver)?;
+            let right_writes = replay_accesses(&right_accesses, width, memory, &mut solver)?;
+            let address = solver.declare_const(Ty::BitVec(width), info);
+            let left_byte = solver.define_const(byte_at(Exp::Var(address), width, memory, &left_writes), info);
+            let right_byte = solver.define_const(byte_at(Exp::Var(address), width, memory, &right_writes), info);
+            conditions.push(Exp::Neq(Box::new(Exp::Var(left_byte)), Box::new(Exp::Var(right_byte))));
+            Some((address, left_byte, right_byte))
+        }
+        None => None,
+    };
+
+    solver.add(Def::Assert(disjunction(conditions)));
+    match solver.check_sat() {
+        SmtResult::Unsat => return Ok(Comparison::Equivalent),
+        SmtResult::Unknown => return Ok(Comparison::Unknown),
+        SmtResult::Sat => (),
+    }
+
+    let mut model = Model::new(&solver);
+    let inputs = left_events[..shared]
+        .iter()
+        .filter_map(|event| match event {
+            Event::Smt(Def::DeclareConst(v, _), _, _) => match model.get_var(*v) {
+                Ok(Some(exp)) => Some((*v, model_exp(exp)?)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let mut differences = Vec::new();
+    for (name, l, r) in &registers {
+        let (left, right) = (model_value(&mut model, l), model_value(&mut model, r));
+        if left != right {
+            differences.push(Difference::Register { name: *name, left, right })
+        }
+    }
+    if let Some((address, left_byte, right_byte)) = memory {
+        let [address, left, right] =
+            [address, left_byte, right_byte].map(|v| model_value(&mut model, &Val::Symbolic(v)));
+        if left != right {
+            differences.push(Difference::Memory { address, left, right })
+        }
+    }
+
+    Ok(Comparison::Distinguished(Distinction { inputs, differences }))
+}
+
+/// A frame which runs the specification function `spec` in the
+/// same initial state as `frame`, with the same arguments. Any
+/// arguments of `frame` which are uninitialized are first given
+/// symbolic values in `solver`, so both frames share them.
+pub fn spec_frame<'ir, B: BV>(
+    frame: &mut LocalFrame<'ir, B>,
+    spec: Name,
+    shared_state: &SharedState<'ir, B>,
+    solver: &mut Solver<B>,
+) -> Result<LocalFrame<'ir, B>, ExecError> {
+    let info = SourceLoc::unknown();
+    let no_function = |f: Name| ExecError::NoFunction(zencode::decode(shared_state.symtab.to_str(f)), info);
+    let function = frame.function_name();
+    let (args, _, _) = shared_state.functions.get(&function).ok_or_else(|| no_function(function))?;
+    let (spec_args, spec_ret_ty, spec_instrs) = shared_state.functions.get(&spec).ok_or_else(|| no_function(spec))?;
+    if args.len() != spec_args.len() {
+        return Err(ExecError::Type(
+            format!("Specification has {} arguments, but function has {}", spec_args.len(), args.len()),
+            info,
+        ));
+    }
+
+    let mut vals = Vec::with_capacity(args.len());
+    for (id, _) in args {
+        let val = match frame.vars().get(id) {
+            Some(UVal::Init(val)) => val.clone(),
+            Some(UVal::Uninit(ty)) => {
+                let ty = *ty;
+                let val = symbolic(ty, shared_state, solver, info)?;
+                frame.insert_local(*id, val.clone());
+                val
+            }
+            None => return Err(ExecError::VariableNotFound(zencode::decode(shared_state.symtab.to_str(*id)))),
+        };
+        vals.push(val)
+    }
+
+    let mut spec_frame = LocalFrame::new(spec, spec_args, spec_ret_ty, Some(&vals), spec_instrs);
+    spec_frame.add_lets(frame.lets()).add_regs(frame.regs()).set_memory(frame.memory().clone());
+    Ok(spec_frame)
+}
+
+/// Whether the final state of a path meets its specification
+#[derive(Clone, Debug)]
+pub enum Verdict<B> {
+    Satisfies,
+    /// The final state differs from that of a specification path
+    Differs(Distinction<B>),
+    /// The final state violates the specification property
+    Violates(Counterexample<B>),
+    /// The solver could not decide whether the specification is met
+    Unknown,
+}
+
+/// Check the final state of a path against the final states of every
+/// path of a specification started from the same checkpoint. The
+/// path satisfies the specification if, for each input where both
+/// it and a specification path return, their final states are the
+/// same. Inputs for which the specification fails are not checked,
+/// so if no specification path returns the verdict is unknown.
+pub fn check_against_spec<B: BV>(
+    state: &FinalState<B>,
+    spec: &[FinalState<B>],
+    ignore: &HashSet<Name>,
+) -> Result<Verdict<B>, ExecError> {
+    if spec.is_empty() {
+        return Ok(Verdict::Unknown);
+    }
+
+    let mut unknown = false;
+    for spec_state in spec {
+        match compare_states(state, spec_state, ignore)? {
+            Comparison::Equivalent => (),
+            Comparison::Distinguished(distinction) => return Ok(Verdict::Differs(distinction)),
+            Comparison::Unknown => unknown = true,
+        }
+    }
+    Ok(if unknown { Verdict::Unknown } else { Verdict::Satisfies })
+}
+
+fn loc_value<'a, B: BV>(loc: &Loc<Name>, registers: &'a HashMap<Name, Val<B>>) -> Option<&'a Val<B>> {
+    match loc {
+        Loc::Id(name) => registers.get(name),
+        Loc::Field(loc, field) => match loc_value(loc, registers)? {
+            Val::Struct(fields) => fields.get(field),
+            _ => None,
+        },
+        Loc::Addr(_) => None,
+    }
+}
+
+/// Check that an SMT property, whose variables are register
+/// locations, holds in the final state of a path for every input
+/// consistent with its path condition
+pub fn check_property<B: BV>(
+    state: &FinalState<B>,
+    property: &Exp<Loc<String>>,
+    symtab: &Symtab,
+) -> Result<Verdict<B>, ExecError> {
+    let info = SourceLoc::unknown();
+    let mut cfg = Config::new();
+    cfg.set_param_value("model", "true");
+    let ctx = Context::new(cfg);
+    let mut solver = Solver::from_checkpoint(&ctx, state.checkpoint.clone());
+
+    let mut lookup = |loc: &Loc<String>| {
+        let value = symtab
+            .get_loc(loc)
+            .and_then(|loc| loc_value(&loc, &state.registers))
+            .ok_or_else(|| format!("Location {} not found", loc))?;
+        value_exp(value, &mut solver).ok_or_else(|| format!("Cannot use the value of {} in a property", loc))
+    };
+    let property = property.map_var(&mut lookup).map_err(|err| ExecError::Type(err, info))?;
+
+    let p = solver.define_const(property, info);
+    solver.add(Def::Assert(Exp::Not(Box::new(Exp::Var(p)))));
+    match solver.check_sat() {
+        SmtResult::Unsat => Ok(Verdict::Satisfies),
+        SmtResult::Unknown => Ok(Verdict::Unknown),
+        SmtResult::Sat => Ok(Verdict::Violates(Counterexample::from_model(&solver, Some(p), None))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+
+    fn add(solver: &mut Solver<B64>, lhs: Exp<Sym>, rhs: Exp<Sym>) -> Val<B64> {
+        Val::Symbolic(solver.define_const(Exp::Bvadd(Box::new(lhs), Box::new(rhs)), SourceLoc::unknown()))
+    }
+
+    fn write(solver: &mut Solver<B64>, address: u64, byte: u64) {
+        let value = solver.fresh();
+        solver.add_event(Event::WriteMem {
+            value,
+            write_kind: Val::Unit,
+            address: Val::Bits(B64::new(address, 64)),
+            data: Val::Bits(B64::new(byte, 8)),
+            bytes: 1,
+            tag_value: None,
+            opts: WriteOpts::default(),
+            region: "",
+        })
+    }
+
+    fn read(solver: &mut Solver<B64>, address: u64) -> Val<B64> {
+        let value = Val::Symbolic(solver.declare_const(Ty::BitVec(8), SourceLoc::unknown()));
+        solver.add_event(Event::ReadMem {
+            value: value.clone(),
+            read_kind: Val::Unit,
+            address: Val::Bits(B64::new(address, 64)),
+            bytes: 1,
+            tag_value: None,
+            opts: ReadOpts::default(),
+            region: "",
+        });
+        value
+    }
+
+    /// Run `f` from `start`, and return the final state with `reg` set
+    /// to the value it returns
+    fn path<F>(ctx: &Context, start: &Checkpoint<B64>, reg: Name, f: F) -> FinalState<B64>
+    where
+        F: FnOnce(&mut Solver<B64>) -> Val<B64>,
+    {
+        let mut solver = Solver::from_checkpoint(ctx, start.clone());
+        let value = f(&mut solver);
+        let registers = [(reg, value)].into_iter().collect();
+        FinalState { task_id: 0, registers, checkpoint: checkpoint(&mut solver) }
+    }
+
+    #[test]
+    fn compare_final_states() {
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zR");
+        let info = SourceLoc::unknown();
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(8), info);
+        let start = checkpoint(&mut solver);
+
+        let incr = path(&ctx, &start, reg, |solver| add(solver, Exp::Var(x), bits64(1, 8)));
+        let incr_commuted = path(&ctx, &start, reg, |solver| add(solver, bits64(1, 8), Exp::Var(x)));
+        let double = path(&ctx, &start, reg, |solver| add(solver, Exp::Var(x), Exp::Var(x)));
+
+        let no_ignore = HashSet::new();
+        assert!(matches!(compare_states(&incr, &incr_commuted, &no_ignore), Ok(Comparison::Equivalent)));
+
+        let Ok(Comparison::Distinguished(distinction)) = compare_states(&incr, &double, &no_ignore) else {
+            panic!("expected the final states to differ")
+        };
+        let [(input, Val::Bits(x_value))] = distinction.inputs.as_slice() else {
+            panic!("expected a value for x")
+        };
+        assert_eq!(*input, x);
+        assert_ne!(x_value.lower_u64(), 1);
+        assert!(matches!(distinction.differences.as_slice(), [Difference::Register { name, .. }] if *name == reg));
+        assert!(distinction.to_string(&symtab).starts_with("final states differ\n  R: "));
+
+        let ignore = [reg].into_iter().collect();
+        assert!(matches!(compare_states(&incr, &double, &ignore), Ok(Comparison::Equivalent)));
+
+        // A write after the paths diverge makes the memory differ
+        let write_incr = path(&ctx, &start, reg, |solver| {
+            write(solver, 0x1000, 0xAB);
+            add(solver, Exp::Var(x), bits64(1, 8))
+        });
+        let Ok(Comparison::Distinguished(distinction)) = compare_states(&incr, &write_incr, &no_ignore) else {
+            panic!("expected the memory to differ")
+        };
+        let [Difference::Memory { address, right, .. }] = distinction.differences.as_slice() else {
+            panic!("expected a memory difference")
+        };
+        assert_eq!(*address, Val::Bits(B64::new(0x1000, 64)));
+        assert_eq!(*right, Val::Bits(B64::new(0xAB, 8)));
+
+        // Reads after the paths diverge see the same initial memory,
+        // and any preceding writes
+        let read_left = path(&ctx, &start, reg, |solver| read(solver, 0x2000));
+        let read_right = path(&ctx, &start, reg, |solver| read(solver, 0x2000));
+        assert!(matches!(compare_states(&read_left, &read_right, &no_ignore), Ok(Comparison::Equivalent)));
+
+        let write_read = path(&ctx, &start, reg, |solver| {
+            write(solver, 0x2000, 0xAB);
+            read(solver, 0x2000)
+        });
+        let write_const = path(&ctx, &start, reg, |solver| {
+            write(solver, 0x2000, 0xAB);
+            Val::Bits(B64::new(0xAB, 8))
+        });
+        assert!(matches!(compare_states(&write_read, &write_const, &no_ignore), Ok(Comparison::Equivalent)));
+        assert!(matches!(compare_states(&read_left, &write_read, &no_ignore), Ok(Comparison::Distinguished(_))))
+    }
+
+    #[test]
+    fn spec_verdicts() {
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zR");
+        let info = SourceLoc::unknown();
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(8), info);
+        let start = checkpoint(&mut solver);
+
+        let incr = path(&ctx, &start, reg, |solver| add(solver, Exp::Var(x), bits64(1, 8)));
+        let double = path(&ctx, &start, reg, |solver| add(solver, Exp::Var(x), Exp::Var(x)));
+        let spec = [path(&ctx, &start, reg, |solver| add(solver, bits64(1, 8), Exp::Var(x)))];
+
+        let no_ignore = HashSet::new();
+        assert!(matches!(check_against_spec(&incr, &spec, &no_ignore), Ok(Verdict::Satisfies)));
+        assert!(matches!(check_against_spec(&double, &spec, &no_ignore), Ok(Verdict::Differs(_))));
+        // Nothing can be concluded if the specification never returns
+        assert!(matches!(check_against_spec(&incr, &[], &no_ignore), Ok(Verdict::Unknown)));
+
+        // The lowest bit of R is zero
+        let even = Exp::Eq(
+            Box::new(Exp::Extract(0, 0, Box::new(Exp::Var(Loc::Id("zR".to_string()))))),
+            Box::new(bits64(0, 1)),
+        );
+        assert!(matches!(check_property(&double, &even, &symtab), Ok(Verdict::Satisfies)));
+        let Ok(Verdict::Violates(counterexample)) = check_property(&incr, &even, &symtab) else {
+            panic!("expected x + 1 to be odd for some x")
+        };
+        let [(input, Val::Bits(x_value))] = counterexample.variables.as_slice() else {
+            panic!("expected a value for x")
+        };
+        assert_eq!(*input, x);
+        assert_eq!(x_value.lower_u64() % 2, 0);
+
+        let missing = Exp::Var(Loc::Id("zS".to_string()));
+        assert!(check_property(&incr, &missing, &symtab).is_err())
+    }
+}
@@ -0,0 +1,280 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module reports counterexamples for properties checked with
+//! [all_unsat_collector]-style collectors. Rather than just reporting
+//! that a property can fail, [counterexample_collector] extracts
+//! model values for the variables the violated assertion depends on,
+//! and for the most recently read registers, so failures can be
+//! reported as e.g. `R2 = 0x8000000000000000`.
+
+use crossbeam::queue::SegQueue;
+
+use super::*;
+use crate::smt::smtlib::Exp;
+
+/// The number of distinct recently read registers to report
+const RECENT_REGISTERS: usize = 8;
+
+/// A path on which a property fails
+#[derive(Clone, Debug)]
+pub struct Counterexample<B> {
+    /// Model values for the undefined variables the violated
+    /// assertion depends on
+    pub variables: Vec<(Sym, Val<B>)>,
+    /// Model values for the most recently read registers, most recent
+    /// first
+    pub registers: Vec<(Name, Val<B>)>,
+    /// The error the path failed with, if it did not return false
+    pub error: Option<String>,
+}
+
+fn free_variables(exp: &Exp<Sym>, vars: &mut Vec<Sym>) {
+    let _ = exp.map_var(&mut |v: &Sym| -> Result<Exp<Sym>, String> {
+        vars.push(*v);
+        Ok(Exp::Var(*v))
+    });
+}
+
+/// Find the variables without a definition that `root` depends on
+fn assertion_variables<B>(events: &[&Event<B>], root: Sym) -> Vec<Sym> {
+    let mut definitions: HashMap<Sym, &Exp<Sym>> = HashMap::new();
+    for event in events {
+        if let Event::Smt(Def::DefineConst(v, exp), _, _) = event {
+            definitions.insert(*v, exp);
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut roots = Vec::new();
+    let mut worklist = vec![root];
+    while let Some(v) = worklist.pop() {
+        if !visited.insert(v) {
+            continue;
+        }
+        match definitions.get(&v) {
+            Some(exp) => free_variables(exp, &mut worklist),
+            None => roots.push(v),
+        }
+    }
+    roots.sort_by_key(|v| v.to_u32());
+    roots
+}
+
+pub(super) fn model_exp<B: BV>(exp: Exp<Sym>) -> Option<Val<B>> {
+    match exp {
+        Exp::Bool(b) => Some(Val::Bool(b)),
+        Exp::Bits64(bv) if bv.len() <= B::MAX_WIDTH => Some(Val::Bits(B::new(bv.lower_u64(), bv.len()))),
+        Exp::Enum(member) => Some(Val::Enum(member)),
+        _ => None,
+    }
+}
+
+/// Replace the symbolic parts of a value by their values in the
+/// model, where possible
+pub(super) fn model_value<B: BV>(model: &mut Model<B>, val: &Val<B>) -> Val<B> {
+    match val {
+        Val::Symbolic(v) => match model.get_var(*v) {
+            Ok(Some(exp)) => model_exp(exp).unwrap_or_else(|| val.clone()),
+            _ => val.clone(),
+        },
+        Val::MixedBits(segments) => {
+            let mut bits = B::zero_width();
+            for segment in segments {
+                let segment = match segment {
+                    BitsSegment::Concrete(bv) => Val::Bits(*bv),
+                    BitsSegment::Symbolic(v) => model_value(model, &Val::Symbolic(*v)),
+                };
+                match segment {
+                    Val::Bits(bv) => match bits.append(bv) {
+                        Some(appended) => bits = appended,
+                        None => return val.clone(),
+                    },
+                    _ => return val.clone(),
+                }
+            }
+            Val::Bits(bits)
+        }
+        Val::Vector(vals) => Val::Vector(vals.iter().map(|v| model_value(model, v)).collect()),
+        Val::List(vals) => Val::List(vals.iter().map(|v| model_value(model, v)).collect()),
+        Val::Struct(fields) => Val::Struct(fields.iter().map(|(k, v)| (*k, model_value(model, v))).collect()),
+        Val::Ctor(ctor, v) => Val::Ctor(*ctor, Box::new(model_value(model, v))),
+        _ => val.clone(),
+    }
+}
+
+impl<B: BV> Counterexample<B> {
+    /// Build a counterexample from a solver whose last call to
+    /// [Solver::check_sat] returned [SmtResult::Sat]. The variables
+    /// are found by following the definitions of `assertion` in the
+    /// trace.
+    pub fn from_model(solver: &Solver<B>, assertion: Option<Sym>, error: Option<String>) -> Self {
+        let events = solver.trace().to_vec();
+        let mut model = Model::new(solver);
+
+        let variables = match assertion {
+            Some(root) => assertion_variables(&events, root)
+                .into_iter()
+                .filter_map(|v| match model.get_var(v) {
+                    Ok(Some(exp)) => Some((v, model_exp(exp)?)),
+                    _ => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut registers: Vec<(Name, Val<B>)> = Vec::new();
+        for event in &events {
+            if registers.len() >= RECENT_REGISTERS {
+                break;
+            }
+            if let Event::ReadReg(name, accessor, value) = event {
+                if accessor.is_empty() && !registers.iter().any(|(r, _)| r == name) {
+                    registers.push((*name, model_value(&mut model, value)))
+                }
+            }
+        }
+
+        Counterexample { variables, registers, error }
+    }
+
+    /// A counterexample with no model, for when the solver could not
+    /// decide whether the property holds
+    pub fn without_model(error: String) -> Self {
+        Counterexample { variables: Vec::new(), registers: Vec::new(), error: Some(error) }
+    }
+
+    pub fn to_string(&self, symtab: &Symtab) -> String {
+        let mut lines = Vec::new();
+        match &self.error {
+            Some(err) => lines.push(format!("fails with {}", err)),
+            None => lines.push("fails".to_string()),
+        }
+        if !self.variables.is_empty() || !self.registers.is_empty() {
+            lines.push("when".to_string())
+        }
+        for (name, value) in &self.registers {
+            lines.push(format!("  {} = {}", zencode::decode(symtab.to_str(*name)), value.to_string(symtab)))
+        }
+        for (v, value) in &self.variables {
+            lines.push(format!("  {} = {}", v, value.to_string(symtab)))
+        }
+        lines.join("\n")
+    }
+}
+
+pub type CounterexampleQueue<B> = SegQueue<Counterexample<B>>;
+
+/// Like [all_unsat_collector], but records a [Counterexample] for
+/// every path on which the function can return false or fails with
+/// an error. The property holds if the queue is empty after
+/// execution.
+pub fn counterexample_collector<'ir, B: BV>(
+    tid: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &CounterexampleQueue<B>,
+) {
+    match result {
+        Ok((Val::Symbolic(v), _)) => {
+            solver.add(Def::Assert(Exp::Not(Box::new(Exp::Var(v)))));
+            match solver.check_sat() {
+                SmtResult::Unsat => log_from!(tid, log::VERBOSE, "Got unsat"),
+                SmtResult::Sat => {
+                    log_from!(tid, log::VERBOSE, "Got sat");
+                    collected.push(Counterexample::from_model(&solver, Some(v), None))
+                }
+                SmtResult::Unknown => {
+                    log_from!(tid, log::VERBOSE, "Got unknown");
+                    collected.push(Counterexample::without_model("solver returned unknown".to_string()))
+                }
+            }
+        }
+        Ok((Val::Bool(true), _)) => log_from!(tid, log::VERBOSE, "Got true"),
+        Ok((Val::Bool(false), _)) => {
+            log_from!(tid, log::VERBOSE, "Got false");
+            if solver.check_sat() == SmtResult::Sat {
+                collected.push(Counterexample::from_model(&solver, None, None))
+            } else {
+                collected.push(Counterexample::without_model("false".to_string()))
+            }
+        }
+        Ok((value, _)) => log_from!(tid, log::VERBOSE, &format!("Got value {:?}", value)),
+        Err((ExecError::Dead, _)) => log_from!(tid, log::VERBOSE, "Dead"),
+        Err((err, backtrace)) => {
+            if_logging!(log::VERBOSE, {
+                log_from!(tid, log::VERBOSE, &format!("Got error, {:?}", err));
+                for (f, pc) in backtrace.iter().rev() {
+                    log_from!(tid, log::VERBOSE, format!("  {} @ {}", shared_state.symtab.to_str(*f), pc));
+                }
+            });
+            let message = format!("{}", err);
+            if solver.check_sat() == SmtResult::Sat {
+                collected.push(Counterexample::from_model(&solver, None, Some(message)))
+            } else {
+                collected.push(Counterexample::without_model(message))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::Ty;
+
+    #[test]
+    fn counterexample_values() {
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zR2");
+        let info = SourceLoc::unknown();
+
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+
+        let x = solver.declare_const(Ty::BitVec(64), info);
+        solver.add_event(Event::ReadReg(reg, vec![], Val::Symbolic(x)));
+        let top = Exp::Bits64(B64::new(0x8000_0000_0000_0000, 64));
+        let property = solver.define_const(Exp::Neq(Box::new(Exp::Var(x)), Box::new(top)), info);
+
+        solver.add(Def::Assert(Exp::Not(Box::new(Exp::Var(property)))));
+        assert_eq!(solver.check_sat(), SmtResult::Sat);
+
+        let counterexample = Counterexample::from_model(&solver, Some(property), None);
+        let expected = Val::Bits(B64::new(0x8000_0000_0000_0000, 64));
+        assert_eq!(counterexample.variables, vec![(x, expected.clone())]);
+        assert_eq!(counterexample.registers, vec![(reg, expected)]);
+        assert!(counterexample.to_string(&symtab).contains("R2 = #x8000000000000000"))
+    }
+}
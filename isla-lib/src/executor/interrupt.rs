@@ -0,0 +1,124 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module allows asynchronous exceptions and interrupts to be
+//! injected into a path at instruction boundaries, i.e. each time the
+//! model calls the `cycle_count` builtin. An interrupt is delivered
+//! by assigning registers, typically setting a pending bit that the
+//! model checks between instructions, so the model's own exception
+//! entry code is used to take it.
+//!
+//! An interrupt can arrive either at a fixed boundary, or at a
+//! symbolic point within a range of boundaries. In the latter case a
+//! fresh bitvector giving the arrival point is declared when the
+//! range is entered, and recorded in the trace with an
+//! [crate::smt::Event::Interrupt] event. The registers are updated at
+//! each boundary in the range only if it is the arrival point, so the
+//! solver can explore every arrival point on a single path, and the
+//! interrupt arrives at most once. An arrival point at the end of the
+//! range means the interrupt did not arrive.
+//!
+//! An interrupt can also wake a sleeping hart, in which case a
+//! [crate::smt::Event::WakeupRequest] event is recorded at each
+//! boundary it may arrive at.
+
+use crate::ir::{Loc, Name, Val};
+
+/// The instruction boundary at which an interrupt arrives. Boundaries
+/// are counted from zero along each path, so boundary `n` is the end
+/// of the cycle reported as `Event::Cycle(n)`.
+#[derive(Clone, Debug)]
+pub enum Arrival {
+    /// The interrupt arrives at exactly this boundary
+    At(u64),
+    /// The interrupt may arrive at most once, at any boundary in the
+    /// range `from` up to but not including `to`
+    Symbolic { from: u64, to: u64 },
+}
+
+impl Arrival {
+    /// Returns true if the interrupt could arrive at the given boundary
+    pub fn may_arrive_at(&self, boundary: u64) -> bool {
+        match self {
+            Arrival::At(n) => *n == boundary,
+            Arrival::Symbolic { from, to } => *from <= boundary && boundary < *to,
+        }
+    }
+
+    pub fn is_symbolic(&self) -> bool {
+        matches!(self, Arrival::Symbolic { .. })
+    }
+}
+
+/// An interrupt to be injected, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct Interrupt<B> {
+    pub arrival: Arrival,
+    /// The register assignments that deliver the interrupt
+    pub assignments: Vec<(Loc<Name>, Val<B>)>,
+    /// If true, the interrupt wakes the hart if it is sleeping
+    pub wakeup: bool,
+}
+
+impl<B> Interrupt<B> {
+    pub fn new(arrival: Arrival) -> Self {
+        Interrupt { arrival, assignments: Vec::new(), wakeup: false }
+    }
+
+    /// Make the interrupt wake the hart if it is sleeping
+    pub fn wakes(mut self) -> Self {
+        self.wakeup = true;
+        self
+    }
+
+    /// Add a register assignment to be made when the interrupt arrives
+    pub fn assign(mut self, loc: Loc<Name>, value: Val<B>) -> Self {
+        self.assignments.push((loc, value));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrival_window() {
+        let fixed = Arrival::At(2);
+        assert!(!fixed.may_arrive_at(1));
+        assert!(fixed.may_arrive_at(2));
+        assert!(!fixed.may_arrive_at(3));
+
+        let window = Arrival::Symbolic { from: 1, to: 3 };
+        assert!(!window.may_arrive_at(0));
+        assert!(window.may_arrive_at(1));
+        assert!(window.may_arrive_at(2));
+        assert!(!window.may_arrive_at(3));
+    }
+}
@@ -0,0 +1,236 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module defines [PathResult], a structured summary of a
+//! single path built once from the raw result the executor passes to
+//! its collector. Rather than re-deriving the trace, return value,
+//! and error from `(Val, LocalFrame)` and the solver themselves,
+//! collectors can be wrapped with [path_results] and consume the
+//! summary directly, as the built-in collectors such as
+//! [trace_collector] do.
+
+use crossbeam::queue::SegQueue;
+
+use super::*;
+
+/// How a single path ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathStatus {
+    /// The function returned normally
+    Returned,
+    /// The path was suspended by a [StopAction::Suspend] stop condition
+    Suspended,
+    /// The path ended via an explicit call to `exit`
+    Exited,
+    /// The path was found to be unreachable
+    Dead,
+    /// The path ended with any other error
+    Failed,
+}
+
+/// Statistics gathered while executing a path
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathStats {
+    pub instructions: u64,
+    pub forks: u32,
+    pub backjumps: u32,
+}
+
+/// The error a path ended with, and where it occurred
+#[derive(Clone, Debug)]
+pub struct PathError {
+    pub error: ExecError,
+    pub backtrace: Backtrace,
+}
+
+/// The result of a single path
+#[derive(Clone, Debug)]
+pub struct PathResult<B> {
+    pub task_id: usize,
+    pub status: PathStatus,
+    /// The value returned by the function, if it returned
+    pub value: Option<Val<B>>,
+    /// The final value of every initialized register. Empty if the
+    /// path ended with an error.
+    pub registers: HashMap<Name, Val<B>>,
+    /// The trace of the path, with the most recent event first as in
+    /// [Trace::to_vec]
+    pub events: Vec<Event<B>>,
+    /// Execution statistics, unavailable if the path ended with an
+    /// error
+    pub stats: Option<PathStats>,
+    pub error: Option<PathError>,
+}
+
+impl<B: BV> PathResult<B> {
+    pub fn new<'ir>(task_id: usize, result: CollectorResult<'ir, B>, solver: &Solver<B>) -> Self {
+        let events = solver.trace().to_vec().into_iter().cloned().collect();
+        match result {
+            Ok((val, frame)) => {
+                let status = if frame.suspended { PathStatus::Suspended } else { PathStatus::Returned };
+                let registers = frame
+                    .local_state
+                    .regs
+                    .iter()
+                    .filter_map(|(name, reg)| Some((*name, reg.read_last_if_initialized()?.clone())))
+                    .collect();
+                let stats =
+                    PathStats { instructions: frame.instructions, forks: frame.forks, backjumps: frame.backjumps };
+                PathResult {
+                    task_id,
+                    status,
+                    value: if frame.suspended { None } else { Some(val) },
+                    registers,
+                    events,
+                    stats: Some(stats),
+                    error: None,
+                }
+            }
+            Err((error, backtrace)) => {
+                let status = match error {
+                    ExecError::Exit => PathStatus::Exited,
+                    ExecError::Dead => PathStatus::Dead,
+                    _ => PathStatus::Failed,
+                };
+                PathResult {
+                    task_id,
+                    status,
+                    value: None,
+                    registers: HashMap::new(),
+                    events,
+                    stats: None,
+                    error: Some(PathError { error, backtrace }),
+                }
+            }
+        }
+    }
+
+    /// Returns true if the path either returned or exited explicitly,
+    /// which is how most collectors treat successful paths
+    pub fn is_success(&self) -> bool {
+        matches!(self.status, PathStatus::Returned | PathStatus::Exited)
+    }
+}
+
+/// Adapt a collector which consumes [PathResult] into one which can
+/// be passed to the executor. The solver is still passed to the
+/// collector for queries about the final state of the path, such as
+/// building a model.
+pub fn path_results<'ir, B, R, C>(
+    collector: C,
+) -> impl Sync + Fn(usize, usize, CollectorResult<'ir, B>, &SharedState<'ir, B>, Solver<B>, &R)
+where
+    B: BV,
+    C: Sync + Fn(usize, PathResult<B>, &SharedState<'ir, B>, &mut Solver<B>, &R),
+{
+    move |tid: usize,
+          task_id: usize,
+          result: CollectorResult<'ir, B>,
+          shared_state: &SharedState<'ir, B>,
+          mut solver: Solver<B>,
+          collected: &R| {
+        let path = PathResult::new(task_id, result, &solver);
+        collector(tid, path, shared_state, &mut solver, collected)
+    }
+}
+
+pub type PathResultQueue<B> = SegQueue<PathResult<B>>;
+
+/// Collect the result of every path which was not found to be
+/// unreachable
+pub fn path_result_collector<'ir, B: BV>(
+    tid: usize,
+    task_id: usize,
+    result: CollectorResult<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
+    collected: &PathResultQueue<B>,
+) {
+    path_results(
+        |_, path: PathResult<B>, _: &SharedState<'ir, B>, _: &mut Solver<B>, collected: &PathResultQueue<B>| {
+            if path.status != PathStatus::Dead {
+                collected.push(path)
+            }
+        },
+    )(tid, task_id, result, shared_state, solver, collected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::{Def, ExitCause};
+    use std::sync::Arc;
+
+    #[test]
+    fn path_result_status() {
+        let mut symtab = Symtab::new();
+        let ret = symtab.intern("zret");
+        let exit = symtab.intern("zexit");
+        let info = SourceLoc::unknown();
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Val(ret, vec![], Ty::Bool),
+            Def::Fn(ret, vec![], vec![Instr::Copy(Loc::Id(RETURN), crate::ir::Exp::Bool(true), info), Instr::End]),
+            Def::Val(exit, vec![], Ty::Bool),
+            Def::Fn(exit, vec![], vec![Instr::Exit(ExitCause::Explicit, info)]),
+        ];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let task_state = TaskState::new();
+        let tasks: Vec<_> = [ret, exit]
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let (args, ret_ty, instrs) = shared_state.functions.get(f).unwrap();
+                LocalFrame::new(*f, args, ret_ty, None, instrs).task(i, &task_state)
+            })
+            .collect();
+
+        let queue = Arc::new(SegQueue::new());
+        start_multi(1, None, tasks, &shared_state, queue.clone(), &path_result_collector);
+
+        let mut results = Vec::new();
+        while let Some(path) = queue.pop() {
+            results.push(path)
+        }
+        results.sort_by_key(|path| path.task_id);
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0].status, PathStatus::Returned);
+        assert_eq!(results[0].value, Some(Val::Bool(true)));
+        assert!(results[0].stats.unwrap().instructions > 0);
+        assert!(results[0].error.is_none());
+
+        assert_eq!(results[1].status, PathStatus::Exited);
+        assert!(results[1].is_success());
+        assert!(matches!(results[1].error, Some(PathError { error: ExecError::Exit, .. })));
+    }
+}
@@ -0,0 +1,238 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module runs a prefix shared by many executions only once.
+//! For example, when exploring a fixed opcode under many different
+//! preconditions, fetch and decode are the same every time. The
+//! prefix is run with stop conditions that suspend each path at the
+//! start of the next phase (see [StopAction::Suspend]), such as the
+//! call to `execute`, and every suspended path is then used as the
+//! root of as many tasks as needed via [super::snapshot].
+
+use crossbeam::queue::SegQueue;
+use std::sync::Arc;
+
+use super::snapshot::{Snapshot, SnapshotError};
+use super::*;
+use crate::smt::smtlib::{Def, Exp};
+
+/// The outcome of a single path through a prefix
+pub enum PrefixPath<B> {
+    /// The path was suspended at the start of the next phase
+    Suspended(Snapshot<B>, Checkpoint<B>),
+    /// The path ended without reaching the next phase
+    Finished,
+    Failed(String),
+}
+
+pub type PrefixQueue<B> = SegQueue<PrefixPath<B>>;
+
+pub fn prefix_collector<'ir, B: BV>(
+    tid: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    _: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &PrefixQueue<B>,
+) {
+    match result {
+        Ok((_, frame)) if frame.is_suspended() => {
+            let point = checkpoint(&mut solver);
+            match Snapshot::new(tid, &frame, &point) {
+                Ok(snapshot) => collected.push(PrefixPath::Suspended(snapshot, point)),
+                Err(err) => collected.push(PrefixPath::Failed(err.to_string())),
+            }
+        }
+        Ok(_) | Err((ExecError::Exit, _)) => collected.push(PrefixPath::Finished),
+        Err((ExecError::Dead, _)) => (),
+        Err((err, _)) => collected.push(PrefixPath::Failed(err.to_string())),
+    }
+}
+
+/// The suspended paths of a prefix which has been run once, along
+/// with a count of the paths which ended or failed before reaching
+/// the next phase.
+pub struct Prefix<B> {
+    suspended: Vec<(Snapshot<B>, Checkpoint<B>)>,
+    finished: usize,
+    failed: Vec<String>,
+}
+
+impl<B: BV> Prefix<B> {
+    /// Run the prefix. The tasks should have stop conditions which
+    /// suspend their paths at the start of the next phase. The order
+    /// of the suspended paths is unspecified when using more than one
+    /// thread.
+    pub fn run<'ir>(num_threads: usize, tasks: Vec<Task<'ir, '_, B>>, shared_state: &SharedState<'ir, B>) -> Self {
+        let queue = Arc::new(SegQueue::new());
+        start_multi(num_threads, None, tasks, shared_state, queue.clone(), &prefix_collector);
+
+        let mut prefix = Prefix { suspended: Vec::new(), finished: 0, failed: Vec::new() };
+        while let Some(path) = queue.pop() {
+            match path {
+                PrefixPath::Suspended(snapshot, point) => prefix.suspended.push((snapshot, point)),
+                PrefixPath::Finished => prefix.finished += 1,
+                PrefixPath::Failed(msg) => prefix.failed.push(msg),
+            }
+        }
+        prefix
+    }
+
+    /// Use snapshots saved from an earlier run as the prefix.
+    pub fn from_snapshots(snapshots: Vec<Snapshot<B>>) -> Self {
+        let suspended = snapshots
+            .into_iter()
+            .map(|snapshot| {
+                let point = snapshot.checkpoint();
+                (snapshot, point)
+            })
+            .collect();
+        Prefix { suspended, finished: 0, failed: Vec::new() }
+    }
+
+    /// The snapshot of each suspended path, for example to save them
+    /// with [Snapshot::write].
+    pub fn snapshots(&self) -> impl Iterator<Item = &Snapshot<B>> {
+        self.suspended.iter().map(|(snapshot, _)| snapshot)
+    }
+
+    /// The number of suspended paths
+    pub fn len(&self) -> usize {
+        self.suspended.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.suspended.is_empty()
+    }
+
+    /// The number of paths which ended before the next phase
+    pub fn finished(&self) -> usize {
+        self.finished
+    }
+
+    /// The errors from paths which failed before the next phase
+    pub fn failed(&self) -> &[String] {
+        &self.failed
+    }
+
+    /// Create a task resuming each suspended path, with the id of the
+    /// task being the index of the path. Before each task is created
+    /// the precondition is applied to its frame, which it may modify
+    /// (for example to set register values), and it can return an
+    /// assertion which is added to the path. The assertion can only
+    /// refer to symbolic variables which already exist on the path.
+    /// The solver state for the prefix is shared between all the
+    /// tasks created from the same path.
+    pub fn tasks<'ir, 'task, F>(
+        &'ir self,
+        shared_state: &SharedState<'ir, B>,
+        state: &'task TaskState<B>,
+        mut precondition: F,
+    ) -> Result<Vec<Task<'ir, 'task, B>>, SnapshotError>
+    where
+        F: FnMut(&mut LocalFrame<'ir, B>) -> Option<Exp<Sym>>,
+    {
+        let mut tasks = Vec::with_capacity(self.suspended.len());
+        for (task_id, (snapshot, point)) in self.suspended.iter().enumerate() {
+            let mut frame = snapshot.restore_frame(shared_state)?;
+            let point = match precondition(&mut frame) {
+                Some(exp) => {
+                    point.extend(vec![Event::Smt(Def::Assert(exp), DefAttrs::default(), SourceLoc::unknown())])
+                }
+                None => point.clone(),
+            };
+            tasks.push(frame.task_with_checkpoint(task_id, state, point))
+        }
+        Ok(tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Def;
+
+    #[test]
+    fn shared_prefix() {
+        let mut symtab = Symtab::new();
+        let outer = symtab.intern("zouter");
+        let inner = symtab.intern("zinner");
+        let x = symtab.intern("zx");
+        let y = symtab.intern("zy");
+        let a = symtab.intern("za");
+        let info = SourceLoc::unknown();
+
+        let defs: Vec<Def<Name, B64>> = vec![
+            Def::Val(outer, vec![Ty::Bool], Ty::Bool),
+            Def::Fn(
+                outer,
+                vec![x],
+                vec![
+                    Instr::Decl(y, Ty::Bool, info),
+                    Instr::Call(Loc::Id(y), false, inner, vec![crate::ir::Exp::Id(x)], info),
+                    Instr::Copy(Loc::Id(RETURN), crate::ir::Exp::Id(y), info),
+                    Instr::End,
+                ],
+            ),
+            Def::Val(inner, vec![Ty::Bool], Ty::Bool),
+            Def::Fn(inner, vec![a], vec![Instr::Copy(Loc::Id(RETURN), crate::ir::Exp::Id(a), info), Instr::End]),
+        ];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let mut stops = StopConditions::new();
+        stops.add(inner, None, StopAction::Suspend);
+        let task_state = TaskState::new();
+        let (args, ret_ty, instrs) = shared_state.functions.get(&outer).unwrap();
+        let mut task = LocalFrame::new(outer, args, ret_ty, None, instrs).task(0, &task_state);
+        task.set_stop_conditions(&stops);
+
+        let prefix = Prefix::run(1, vec![task], &shared_state);
+        assert_eq!(prefix.len(), 1);
+        assert_eq!(prefix.finished(), 0);
+        assert!(prefix.failed().is_empty());
+
+        for b in [true, false] {
+            let tasks = prefix
+                .tasks(&shared_state, &task_state, |frame| {
+                    frame.insert_local(x, Val::Bool(b));
+                    None
+                })
+                .unwrap();
+            let queue = Arc::new(SegQueue::new());
+            start_multi(1, None, tasks, &shared_state, queue.clone(), &trace_result_collector);
+            match queue.pop() {
+                Some(Ok((0, result, _))) => assert_eq!(result, b),
+                _ => panic!("expected a single result from the resumed path"),
+            }
+            assert!(queue.pop().is_none())
+        }
+    }
+}
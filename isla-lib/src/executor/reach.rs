@@ -0,0 +1,234 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module searches for paths which reach a target, which can be
+//! a function being called (or stopped at), an abstract event, or an
+//! exception being thrown with a particular constructor. For each
+//! such path [reach_collector] records a [Reached] witness, with a
+//! concrete opcode and model values for the registers the path read,
+//! answering questions such as "can this instruction ever raise an
+//! alignment fault?".
+//!
+//! Function calls are only seen when the function is traced, so the
+//! target function should usually be added as a kill-at stop
+//! condition, which also ends each path as soon as it is reached.
+//!
+//! Paths which fail with an error (other than being dead, exiting, or
+//! being stopped) without reaching the target are recorded too, as
+//! they might have reached it had they continued. The target is only
+//! known to be unreachable if there are neither witnesses nor errors.
+
+use crossbeam::queue::SegQueue;
+
+use super::counterexample::model_value;
+use super::*;
+use crate::summary::opcode_witness;
+
+/// A path which reaches the target
+#[derive(Clone, Debug)]
+pub struct Reached<B> {
+    /// How the target was reached
+    pub how: String,
+    /// A concrete opcode which reaches the target, if one could be
+    /// found
+    pub opcode: Option<B>,
+    /// Model values for the registers read on the path, in the order
+    /// they were first read
+    pub registers: Vec<(Name, Val<B>)>,
+}
+
+impl<B: BV> Reached<B> {
+    pub fn to_string(&self, symtab: &Symtab) -> String {
+        let mut lines = vec![format!("reached: {}", self.how)];
+        if let Some(opcode) = self.opcode {
+            lines.push(format!("opcode: {}", opcode))
+        }
+        if !self.registers.is_empty() {
+            lines.push("when".to_string())
+        }
+        for (name, value) in &self.registers {
+            lines.push(format!("  {} = {}", zencode::decode(symtab.to_str(*name)), value.to_string(symtab)))
+        }
+        lines.join("\n")
+    }
+}
+
+/// The target to search for, the opcode being executed, and the
+/// witnesses and errors found so far
+pub struct ReachQueue<B> {
+    target: Name,
+    opcode: Val<B>,
+    reached: SegQueue<Reached<B>>,
+    errors: SegQueue<String>,
+}
+
+impl<B: BV> ReachQueue<B> {
+    pub fn new(target: Name, opcode: Val<B>) -> Self {
+        ReachQueue { target, opcode, reached: SegQueue::new(), errors: SegQueue::new() }
+    }
+
+    /// Take the errors of the paths which failed before reaching the
+    /// target, in sorted order
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors: Vec<String> = std::iter::from_fn(|| self.errors.pop()).collect();
+        errors.sort();
+        errors
+    }
+
+    /// Take the witnesses, ordered by opcode so the result does not
+    /// depend on the order paths were explored
+    pub fn witnesses(&self) -> Vec<Reached<B>> {
+        let mut witnesses: Vec<Reached<B>> = std::iter::from_fn(|| self.reached.pop()).collect();
+        witnesses.sort_by_key(|reached| reached.opcode.map(|bv| bv.lower_u64()));
+        witnesses
+    }
+}
+
+/// How a path reached the target, if it did
+fn reaches<'ir, B: BV>(
+    target: Name,
+    result: &CollectorResult<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &Solver<B>,
+) -> Option<String> {
+    let target_name = zencode::decode(shared_state.symtab.to_str(target));
+    let event = solver.trace().to_vec().into_iter().rev().find_map(|event| match event {
+        Event::Abstract { name, .. } if *name == target => Some(format!("abstract event {}", target_name)),
+        Event::Function { name, call: true } if *name == target => Some(format!("call to {}", target_name)),
+        _ => None,
+    });
+    if event.is_some() {
+        return event;
+    }
+    match result {
+        Ok((_, frame)) => match frame.get_exception() {
+            Some((Val::Ctor(ctor, _), location)) if *ctor == target => {
+                Some(format!("exception {} thrown at {}", target_name, location))
+            }
+            _ => None,
+        },
+        Err((ExecError::Stopped(function), _)) if *function == target_name => Some(format!("call to {}", target_name)),
+        Err(_) => None,
+    }
+}
+
+/// A collector which records a [Reached] witness for every path on
+/// which the target given to [ReachQueue::new] is reached. The target
+/// is unreachable if there are no witnesses or errors after
+/// execution.
+pub fn reach_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: CollectorResult<'ir, B>,
+    shared_state: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &ReachQueue<B>,
+) {
+    if let Err((ExecError::Dead, _)) = result {
+        return;
+    }
+    let Some(how) = reaches(collected.target, &result, shared_state, &solver) else {
+        match &result {
+            Ok(_) | Err((ExecError::Exit | ExecError::Stopped(_), _)) => (),
+            Err((error, _)) => collected.errors.push(error.to_string()),
+        }
+        return;
+    };
+    let opcode = opcode_witness(&mut solver, &collected.opcode).unwrap_or(None);
+
+    let mut registers: Vec<(Name, Val<B>)> = Vec::new();
+    if solver.check_sat() == SmtResult::Sat {
+        let events = solver.trace().to_vec();
+        let mut model = Model::new(&solver);
+        for event in events.iter().rev() {
+            if let Event::ReadReg(name, accessor, value) = event {
+                if accessor.is_empty() && !registers.iter().any(|(r, _)| r == name) {
+                    registers.push((*name, model_value(&mut model, value)))
+                }
+            }
+        }
+    }
+
+    collected.reached.push(Reached { how, opcode, registers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::{bits64, Exp, Ty};
+
+    #[test]
+    fn reach_abstract_event() {
+        let mut symtab = Symtab::new();
+        let fault = symtab.intern("zAlignmentFault");
+        let other = symtab.intern("zOther");
+        let reg = symtab.intern("zR");
+        let shared_state =
+            SharedState::<B64>::new(symtab, &[], HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+        let info = SourceLoc::unknown();
+
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let queue = ReachQueue::new(fault, Val::Unit);
+
+        // A path which raises the fault when R is 3
+        let mut solver = Solver::<B64>::new(&ctx);
+        let r = solver.declare_const(Ty::BitVec(8), info);
+        solver.add_event(Event::ReadReg(reg, vec![], Val::Symbolic(r)));
+        solver.add(Def::Assert(Exp::Eq(Box::new(Exp::Var(r)), Box::new(bits64(3, 8)))));
+        solver.add_event(Event::Abstract { name: fault, primitive: false, args: vec![], return_value: Val::Unit });
+        reach_collector(0, 0, Err((ExecError::Exit, Vec::new())), &shared_state, solver, &queue);
+
+        // A path which does not
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.add_event(Event::Abstract { name: other, primitive: false, args: vec![], return_value: Val::Unit });
+        reach_collector(0, 0, Err((ExecError::Exit, Vec::new())), &shared_state, solver, &queue);
+
+        // Dead paths never reach anything
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.add_event(Event::Abstract { name: fault, primitive: false, args: vec![], return_value: Val::Unit });
+        reach_collector(0, 0, Err((ExecError::Dead, Vec::new())), &shared_state, solver, &queue);
+        assert!(queue.errors().is_empty());
+
+        // A path which fails before reaching the target makes the
+        // result inconclusive
+        let solver = Solver::<B64>::new(&ctx);
+        reach_collector(0, 0, Err((ExecError::Timeout, Vec::new())), &shared_state, solver, &queue);
+        assert_eq!(queue.errors(), vec!["Timeout".to_string()]);
+
+        let witnesses = queue.witnesses();
+        assert_eq!(witnesses.len(), 1);
+        assert_eq!(witnesses[0].opcode, None);
+        assert_eq!(witnesses[0].registers, vec![(reg, Val::Bits(B64::new(3, 8)))]);
+        let witness = witnesses[0].to_string(&shared_state.symtab);
+        assert!(witness.starts_with("reached: abstract event AlignmentFault\nwhen\n  R = "))
+    }
+}
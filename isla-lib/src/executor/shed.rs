@@ -0,0 +1,385 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module tracks an approximation of the memory used by each
+//! worker during an execution, counting the frame and solver of the
+//! task each worker is running, and the frames of the tasks waiting
+//! in the queues. Optionally, a soft cap can be placed on the total.
+//! While the estimate exceeds the cap, newly forked tasks (which are
+//! the deepest in the execution tree) are written to disk as
+//! snapshots (see [super::snapshot]) rather than queued in memory,
+//! and are only read back once no queued tasks remain.
+//!
+//! The estimates only count the size of the data structures used by
+//! the executor, not the values they contain or Z3's own
+//! allocations, which are reported separately.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::snapshot::{Snapshot, SnapshotError};
+use super::*;
+use crate::memory::Region;
+use crate::smt::{z3_allocated, DefAttrs};
+
+/// How often, in IR instructions, a worker updates the estimate for
+/// the task it is running
+pub(super) const SAMPLE_INTERVAL: u64 = 4096;
+
+#[derive(Default)]
+struct WorkerUsage {
+    frame: AtomicUsize,
+    solver: AtomicUsize,
+}
+
+/// The approximate memory used by each worker, which can be shared
+/// between threads
+pub struct MemoryUsage {
+    workers: Vec<WorkerUsage>,
+    queued: AtomicUsize,
+    queued_tasks: AtomicUsize,
+    peak: AtomicUsize,
+    cap: Option<(usize, PathBuf)>,
+    next_file: AtomicUsize,
+    shed: AtomicUsize,
+    restored: AtomicUsize,
+}
+
+impl MemoryUsage {
+    /// Track the memory used by up to `num_threads` workers.
+    pub fn new(num_threads: usize) -> Self {
+        MemoryUsage {
+            workers: (0..num_threads).map(|_| WorkerUsage::default()).collect(),
+            queued: AtomicUsize::new(0),
+            queued_tasks: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            cap: None,
+            next_file: AtomicUsize::new(0),
+            shed: AtomicUsize::new(0),
+            restored: AtomicUsize::new(0),
+        }
+    }
+
+    /// As [MemoryUsage::new], but while the estimate exceeds `cap`
+    /// bytes newly forked tasks are written to files in `dir`, which
+    /// is created if it does not exist.
+    pub fn with_cap(num_threads: usize, cap: usize, dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(MemoryUsage { cap: Some((cap, dir)), ..Self::new(num_threads) })
+    }
+
+    /// The current estimate, in bytes
+    pub fn total(&self) -> usize {
+        let running: usize = self
+            .workers
+            .iter()
+            .map(|worker| worker.frame.load(Ordering::Relaxed) + worker.solver.load(Ordering::Relaxed))
+            .sum();
+        running + self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The highest estimate seen so far, in bytes
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// The number of tasks that were written to disk, and the number
+    /// that have been read back
+    pub fn shed_tasks(&self) -> (usize, usize) {
+        (self.shed.load(Ordering::Relaxed), self.restored.load(Ordering::Relaxed))
+    }
+
+    fn record_peak(&self) {
+        self.peak.fetch_max(self.total(), Ordering::Relaxed);
+    }
+
+    fn shed_dir(&self) -> Option<&Path> {
+        match &self.cap {
+            Some((cap, dir)) if self.total() > *cap => Some(dir),
+            _ => None,
+        }
+    }
+
+    pub(super) fn set_worker(&self, tid: usize, frame: usize, solver: usize) {
+        if let Some(worker) = self.workers.get(tid) {
+            worker.frame.store(frame, Ordering::Relaxed);
+            worker.solver.store(solver, Ordering::Relaxed);
+            self.record_peak()
+        }
+    }
+
+    pub(super) fn queued<B: BV>(&self, task: &Task<'_, '_, B>) {
+        self.queued.fetch_add(frozen_frame_size(&task.frame), Ordering::Relaxed);
+        self.queued_tasks.fetch_add(1, Ordering::Relaxed);
+        self.record_peak()
+    }
+
+    pub(super) fn dequeued<B: BV>(&self, task: &Task<'_, '_, B>) {
+        self.queued.fetch_sub(frozen_frame_size(&task.frame), Ordering::Relaxed);
+        self.queued_tasks.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// A table of the current estimate for each worker, followed by
+    /// the queued tasks and totals
+    pub fn report(&self) -> String {
+        let mut report = format!("{:<8} {:>12} {:>12}\n", "worker", "frame", "solver");
+        for (tid, worker) in self.workers.iter().enumerate() {
+            report.push_str(&format!(
+                "{:<8} {:>12} {:>12}\n",
+                tid,
+                mib(worker.frame.load(Ordering::Relaxed)),
+                mib(worker.solver.load(Ordering::Relaxed))
+            ))
+        }
+        report.push_str(&format!(
+            "queued: {} in {} tasks\n",
+            mib(self.queued.load(Ordering::Relaxed)),
+            self.queued_tasks.load(Ordering::Relaxed)
+        ));
+        report.push_str(&format!("total: {} (peak {})\n", mib(self.total()), mib(self.peak())));
+        if let Some((cap, _)) = &self.cap {
+            let (shed, restored) = self.shed_tasks();
+            report.push_str(&format!("cap: {}, shed {} tasks to disk, restored {}\n", mib(*cap), shed, restored))
+        }
+        report.push_str(&format!("z3: {}\n", mib(z3_allocated() as usize)));
+        report
+    }
+}
+
+fn mib(bytes: usize) -> String {
+    format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn bindings_size<B>(bindings: &Bindings<'_, B>) -> usize {
+    bindings.len() * mem::size_of::<(Name, UVal<'_, B>)>()
+}
+
+fn local_state_size<B: BV>(state: &LocalState<'_, B>) -> usize {
+    bindings_size(&state.vars)
+        + bindings_size(&state.lets)
+        + state.regs.iter().count() * mem::size_of::<(Name, Register<'_, B>)>()
+}
+
+fn memory_size<B>(memory: &Memory<B>) -> usize {
+    memory
+        .regions()
+        .iter()
+        .map(|region| match region {
+            Region::Concrete(_, contents) => contents.len() * mem::size_of::<(Address, u8)>(),
+            _ => mem::size_of::<Region<B>>(),
+        })
+        .sum()
+}
+
+pub(super) fn frame_size<B: BV>(frame: &LocalFrame<'_, B>) -> usize {
+    mem::size_of::<LocalFrame<'_, B>>()
+        + local_state_size(&frame.local_state)
+        + memory_size(&frame.memory)
+        + frame.stack_vars.iter().map(bindings_size).sum::<usize>()
+        + frame.visited_states.len() * mem::size_of::<u64>()
+}
+
+fn frozen_frame_size<B: BV>(frame: &Frame<'_, B>) -> usize {
+    mem::size_of::<Task<'_, '_, B>>()
+        + local_state_size(&frame.local_state)
+        + memory_size(&frame.memory)
+        + frame.stack_vars.iter().map(bindings_size).sum::<usize>()
+        + frame.visited_states.len() * mem::size_of::<u64>()
+}
+
+struct ShedTask<'task, B> {
+    id: usize,
+    file: PathBuf,
+    state: &'task TaskState<B>,
+    stop_conditions: Option<&'task StopConditions>,
+}
+
+/// The tasks of an execution which have been written to disk
+pub(super) struct ShedQueue<'task, B> {
+    tasks: Mutex<Vec<ShedTask<'task, B>>>,
+}
+
+fn write_snapshot<B: BV>(file: &Path, snapshot: &Snapshot<B>) -> Result<(), SnapshotError> {
+    let mut out = BufWriter::new(File::create(file)?);
+    snapshot.write(&mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn read_snapshot<B: BV>(file: &Path) -> Result<Snapshot<B>, SnapshotError> {
+    Snapshot::read(&mut BufReader::new(File::open(file)?))
+}
+
+impl<'task, B: BV> ShedQueue<'task, B> {
+    pub(super) fn new() -> Self {
+        ShedQueue { tasks: Mutex::new(Vec::new()) }
+    }
+
+    /// Write the task to disk if its memory usage is over the cap,
+    /// otherwise (or if the task cannot be written) return it.
+    pub(super) fn shed<'ir>(&self, tid: usize, task: Task<'ir, 'task, B>) -> Option<Task<'ir, 'task, B>> {
+        let Some(usage) = &task.state.memory_usage else {
+            return Some(task);
+        };
+        let Some(dir) = usage.shed_dir() else {
+            return Some(task);
+        };
+
+        // Memory callbacks are not preserved by snapshots
+        let frame = unfreeze_frame(&task.frame);
+        if frame.memory.has_callbacks() {
+            return Some(task);
+        }
+
+        // The fork condition is added to the checkpoint, where it will
+        // be replayed by the solver restoring the task
+        let checkpoint = match &task.fork_cond {
+            Some((def, event)) => {
+                let def = Event::Smt(def.clone(), DefAttrs::default(), SourceLoc::unknown());
+                task.checkpoint.extend(vec![event.clone(), def])
+            }
+            None => task.checkpoint.clone(),
+        };
+
+        let file = dir.join(format!("task{}.snapshot", usage.next_file.fetch_add(1, Ordering::Relaxed)));
+        if let Err(e) = Snapshot::new(tid, &frame, &checkpoint).and_then(|snapshot| write_snapshot(&file, &snapshot)) {
+            log!(log::VERBOSE, format!("Could not write task to {}: {}", file.display(), e));
+            return Some(task);
+        }
+        usage.shed.fetch_add(1, Ordering::Relaxed);
+
+        let shed = ShedTask { id: task.id, file, state: task.state, stop_conditions: task.stop_conditions };
+        self.tasks.lock().unwrap().push(shed);
+        None
+    }
+
+    /// Read back the most recently written task, if there is one. If
+    /// the task cannot be read, its id is returned with the error so
+    /// the lost path can be reported.
+    #[allow(clippy::type_complexity)]
+    pub(super) fn restore<'ir>(
+        &self,
+        shared_state: &SharedState<'ir, B>,
+    ) -> Option<Result<Task<'ir, 'task, B>, (usize, ExecError)>> {
+        let shed = self.tasks.lock().unwrap().pop()?;
+        let (frame, checkpoint) =
+            match read_snapshot(&shed.file).and_then(|snapshot| snapshot.restore_interned(shared_state)) {
+                Ok(restored) => restored,
+                Err(e) => {
+                    let message = format!("{}: {}", shed.file.display(), e);
+                    return Some(Err((shed.id, ExecError::TaskLost(message))));
+                }
+            };
+        let _ = fs::remove_file(&shed.file);
+        if let Some(usage) = &shed.state.memory_usage {
+            usage.restored.fetch_add(1, Ordering::Relaxed);
+        }
+        let task = Task {
+            id: shed.id,
+            frame: freeze_frame(&frame),
+            checkpoint,
+            fork_cond: None,
+            state: shed.state,
+            stop_conditions: shed.stop_conditions,
+        };
+        task.queued();
+        Some(Ok(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_report() {
+        let usage = MemoryUsage::new(2);
+        usage.set_worker(0, 1024 * 1024, 2 * 1024 * 1024);
+        usage.set_worker(1, 512 * 1024, 0);
+        assert_eq!(usage.total(), 3584 * 1024);
+        usage.set_worker(0, 0, 0);
+        assert_eq!(usage.total(), 512 * 1024);
+        assert_eq!(usage.peak(), 3584 * 1024);
+        // Workers beyond the number being tracked are ignored
+        usage.set_worker(2, 1024, 1024);
+        assert_eq!(usage.total(), 512 * 1024);
+
+        let report = usage.report();
+        assert!(report.starts_with("worker          frame       solver\n0              0.0MiB       0.0MiB\n"));
+        assert!(report.contains("total: 0.5MiB (peak 3.5MiB)\n"))
+    }
+
+    #[test]
+    fn shed_and_restore() {
+        use crate::bitvector::b64::B64;
+        use crate::ir::Def;
+
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("zf");
+        let x = symtab.intern("zx");
+        let body = vec![Instr::Copy(Loc::Id(RETURN), Exp::Id(x), SourceLoc::unknown()), Instr::End];
+        let defs: Vec<Def<Name, B64>> = vec![Def::Val(f, vec![Ty::Bits(8)], Ty::Bits(8)), Def::Fn(f, vec![x], body)];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        // With a cap of zero bytes every task is shed
+        let dir = std::env::temp_dir().join(format!("isla-shed-test-{}", std::process::id()));
+        let usage = Arc::new(MemoryUsage::with_cap(1, 0, dir.clone()).unwrap());
+        usage.set_worker(0, 1, 0);
+        let mut state = TaskState::new();
+        state.set_memory_usage(Some(usage.clone()));
+
+        let task = |id| {
+            let (frame, checkpoint) =
+                TaskBuilder::new(f).arg(Val::Bits(B64::new(0xAB, 8))).build(&shared_state).unwrap();
+            frame.task_with_checkpoint(id, &state, checkpoint)
+        };
+
+        let queue = ShedQueue::new();
+        assert!(queue.shed(0, task(7)).is_none());
+        let restored = queue.restore(&shared_state).unwrap().unwrap();
+        assert_eq!(restored.id, 7);
+        assert!(matches!(
+            restored.frame.local_state.vars.get(&x),
+            Some(UVal::Init(Val::Bits(bv))) if *bv == B64::new(0xAB, 8)
+        ));
+        assert!(queue.restore(&shared_state).is_none());
+        assert_eq!(usage.shed_tasks(), (1, 1));
+
+        // A task whose file has gone is reported as lost, rather than panicking
+        assert!(queue.shed(0, task(8)).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(queue.restore(&shared_state), Some(Err((8, ExecError::TaskLost(_))))));
+        assert!(queue.restore(&shared_state).is_none())
+    }
+}
@@ -0,0 +1,181 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module provides a collector which simplifies traces as they
+//! are collected, rather than keeping every unsimplified trace in
+//! memory until execution has finished. Traces are handed to a
+//! [SimplifyPool], whose background threads run a pipeline of passes
+//! (such as [crate::simplify::propagate_constants] and
+//! [crate::simplify::remove_unused]) on each trace while execution
+//! continues, pushing the results onto an ordinary [TraceQueue].
+//!
+//! The number of traces waiting to be simplified is bounded, so when
+//! the pool falls behind the workers collecting paths block until it
+//! catches up, which keeps peak memory proportional to the size of
+//! the simplified traces.
+
+use crossbeam::channel::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::{trace_collector, Backtrace, LocalFrame, TraceQueue};
+use crate::bitvector::BV;
+use crate::error::ExecError;
+use crate::ir::{SharedState, Val};
+use crate::smt::{EvPath, Solver};
+
+/// How many traces can be waiting for each thread in the pool before
+/// collectors block
+const PENDING_PER_THREAD: usize = 4;
+
+/// The passes run on each trace, which receive the events with the
+/// most recent first, as returned by the solver
+pub type TracePipeline<B> = dyn Fn(&mut EvPath<B>) + Send + Sync;
+
+pub struct SimplifyPool<B> {
+    sender: Mutex<Option<Sender<(usize, EvPath<B>)>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    results: Arc<TraceQueue<B>>,
+}
+
+fn simplify_worker<B: BV>(
+    receiver: Receiver<(usize, EvPath<B>)>,
+    pipeline: Arc<TracePipeline<B>>,
+    results: Arc<TraceQueue<B>>,
+) {
+    for (task_id, mut events) in receiver.iter() {
+        pipeline(&mut events);
+        results.push(Ok((task_id, events)))
+    }
+}
+
+impl<B: BV + 'static> SimplifyPool<B> {
+    /// Create a pool of `num_threads` threads running `pipeline`,
+    /// which push each simplified trace onto `results`.
+    pub fn new(num_threads: usize, pipeline: Arc<TracePipeline<B>>, results: Arc<TraceQueue<B>>) -> Self {
+        let num_threads = num_threads.max(1);
+        let (sender, receiver) = channel::bounded(num_threads * PENDING_PER_THREAD);
+        let workers = (0..num_threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let pipeline = pipeline.clone();
+                let results = results.clone();
+                thread::spawn(move || simplify_worker(receiver, pipeline, results))
+            })
+            .collect();
+        SimplifyPool { sender: Mutex::new(Some(sender)), workers: Mutex::new(workers), results }
+    }
+
+    /// Hand a trace to the pool, blocking while too many traces are
+    /// already waiting. Traces submitted after [SimplifyPool::finish]
+    /// are passed through unsimplified.
+    pub fn submit(&self, task_id: usize, events: EvPath<B>) {
+        let sender = self.sender.lock().unwrap().clone();
+        let unsent = match sender {
+            Some(sender) => sender.send((task_id, events)).err().map(|err| err.into_inner()),
+            None => Some((task_id, events)),
+        };
+        if let Some(trace) = unsent {
+            self.results.push(Ok(trace))
+        }
+    }
+
+    /// Wait for every submitted trace to be simplified and stop the
+    /// threads in the pool.
+    pub fn finish(&self) {
+        self.sender.lock().unwrap().take();
+        for worker in self.workers.lock().unwrap().drain(..) {
+            worker.join().unwrap()
+        }
+    }
+}
+
+impl<B> Drop for SimplifyPool<B> {
+    fn drop(&mut self) {
+        // Closing the channel lets any remaining workers exit once it is empty
+        self.sender.lock().unwrap().take();
+    }
+}
+
+/// Like [trace_collector], but each successful trace is simplified by
+/// the [SimplifyPool] before being pushed onto its results queue.
+/// Errors are pushed onto the results queue directly.
+pub fn simplifying_trace_collector<'ir, B: BV + 'static>(
+    tid: usize,
+    task_id: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
+    collected: &SimplifyPool<B>,
+) {
+    match result {
+        Ok(_) | Err((ExecError::Exit, _)) => {
+            let events = solver.trace().to_vec().into_iter().cloned().collect();
+            collected.submit(task_id, events)
+        }
+        result => trace_collector(tid, task_id, result, shared_state, solver, &collected.results),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Name;
+    use crate::smt::Event;
+    use crossbeam::queue::SegQueue;
+
+    #[test]
+    fn simplify_in_background() {
+        let results = Arc::new(SegQueue::new());
+        let pipeline: Arc<TracePipeline<B64>> =
+            Arc::new(|events: &mut EvPath<B64>| events.retain(|event| !event.is_cycle()));
+        let pool = SimplifyPool::new(2, pipeline, results.clone());
+
+        let reg = Name::from_u32(0);
+        for task_id in 0..20 {
+            let read = Event::ReadReg(reg, vec![], Val::Bits(B64::new(1, 8)));
+            pool.submit(task_id, vec![Event::Cycle(0), read, Event::Cycle(1)])
+        }
+        pool.finish();
+
+        let mut task_ids = Vec::new();
+        while let Some(trace) = results.pop() {
+            let (task_id, events) = trace.unwrap();
+            assert_eq!(events.len(), 1);
+            task_ids.push(task_id)
+        }
+        task_ids.sort_unstable();
+        assert_eq!(task_ids, (0..20).collect::<Vec<_>>());
+
+        // The pool has stopped, so later traces are kept as they are
+        pool.submit(20, vec![Event::Cycle(2)]);
+        assert_eq!(results.pop().unwrap().unwrap().1.len(), 1)
+    }
+}
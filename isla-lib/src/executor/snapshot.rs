@@ -0,0 +1,798 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module saves a suspended path (see [StopAction::Suspend]) as
+//! a serializable snapshot, consisting of its frame, the solver
+//! checkpoint, and its memory. A snapshot can be restored against
+//! the same architecture and executed further as many times as
+//! needed, so a shared prefix such as instruction decode only has to
+//! be explored once.
+//!
+//! The call stack of a frame is a chain of closures, which cannot be
+//! serialized. Instead it is rebuilt when restoring from the
+//! backtrace, as every entry in the backtrace is a call instruction
+//! in the calling function. Memory regions defined by arbitrary Rust
+//! code (constrained and custom regions) are not supported, and any
+//! [crate::memory::MemoryCallbacks] or
+//! [crate::memory::WatchpointHandler] must be installed again on the
+//! restored frame. Watchpoints themselves are preserved.
+
+use bincode::Options;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::*;
+use crate::binary_trace::*;
+use crate::memory::{Address, Region, WatchAction, Watchpoint};
+
+/// The magic bytes at the start of every snapshot. The last byte is
+/// the format version.
+pub const MAGIC: &[u8; 8] = b"ISLASNP\x02";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Encoding(BinaryTraceError),
+    BadMagic,
+    UnsupportedRegion(&'static str),
+    UnknownFunction(Name),
+    InvalidPosition(Name, usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SnapshotError::*;
+        match self {
+            Encoding(err) => write!(f, "{}", err),
+            BadMagic => write!(f, "Not a snapshot (bad magic number)"),
+            UnsupportedRegion(name) => write!(f, "Cannot snapshot memory containing a {} region", name),
+            UnknownFunction(name) => {
+                write!(f, "Function with identifier {} in snapshot does not exist in the architecture", name.to_u32())
+            }
+            InvalidPosition(name, pc) => write!(
+                f,
+                "Instruction {} of function with identifier {} in snapshot does not match the architecture",
+                pc,
+                name.to_u32()
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl From<BinaryTraceError> for SnapshotError {
+    fn from(err: BinaryTraceError) -> Self {
+        SnapshotError::Encoding(err)
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Encoding(BinaryTraceError::Io(err))
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        SnapshotError::Encoding(BinaryTraceError::Bincode(err))
+    }
+}
+
+/// A [UVal] where the type of an uninitialized value is owned by the
+/// snapshot.
+enum SnapshotUVal<B> {
+    Init(Val<B>),
+    Uninit(Ty<Name>),
+}
+
+type SnapshotBindings<B> = Vec<(Name, SnapshotUVal<B>)>;
+
+enum SnapshotRegisterValue<B> {
+    Uninit(Ty<Name>),
+    Init { last_write: Val<B>, last_read: Option<Val<B>>, old_writes: Vec<Val<B>> },
+}
+
+struct SnapshotRegister<B> {
+    name: Name,
+    relaxed: bool,
+    write_before_read: bool,
+    value: SnapshotRegisterValue<B>,
+}
+
+/// A suspended path which owns all its state, so it can outlive the
+/// execution that created it and be written to or read from a file.
+pub struct Snapshot<B> {
+    next_var: u32,
+    segments: Vec<Vec<Event<B>>>,
+    tid: usize,
+    suspended: bool,
+    resuming_watchpoint: bool,
+    function_name: Name,
+    pc: usize,
+    forks: u32,
+    backjumps: u32,
+    instructions: u64,
+    fork_path: Vec<u32>,
+    replay: Vec<u32>,
+    visited_states: Vec<u64>,
+    vars: SnapshotBindings<B>,
+    regs: Vec<SnapshotRegister<B>>,
+    lets: SnapshotBindings<B>,
+    untraced_depth: u32,
+    memory: Memory<B>,
+    stack_vars: Vec<SnapshotBindings<B>>,
+    backtrace: Backtrace,
+    function_assumptions: Vec<(Name, Vec<(Vec<Val<B>>, Val<B>)>)>,
+}
+
+fn snapshot_bindings<B: BV>(bindings: &Bindings<'_, B>) -> SnapshotBindings<B> {
+    let mut snapshot: SnapshotBindings<B> = bindings
+        .iter()
+        .map(|(id, uval)| {
+            let uval = match uval {
+                UVal::Init(val) => SnapshotUVal::Init(val.clone()),
+                UVal::Uninit(ty) => SnapshotUVal::Uninit((*ty).clone()),
+            };
+            (*id, uval)
+        })
+        .collect();
+    snapshot.sort_by_key(|(id, _)| *id);
+    snapshot
+}
+
+fn restore_bindings<'s, 'ir, B: BV, F>(bindings: &'s SnapshotBindings<B>, restore_ty: &F) -> Bindings<'ir, B>
+where
+    F: Fn(&'s Ty<Name>) -> &'ir Ty<Name>,
+{
+    bindings
+        .iter()
+        .map(|(id, uval)| {
+            let uval = match uval {
+                SnapshotUVal::Init(val) => UVal::Init(val.clone()),
+                SnapshotUVal::Uninit(ty) => UVal::Uninit(restore_ty(ty)),
+            };
+            (*id, uval)
+        })
+        .collect()
+}
+
+lazy_static! {
+    /// Frames restored by [Snapshot::restore_interned] borrow the
+    /// types of uninitialized variables from here, keyed by their
+    /// encoding, so each distinct type is only leaked once.
+    static ref INTERNED_TYS: Mutex<HashMap<Vec<u8>, &'static Ty<Name>>> = Mutex::new(HashMap::new());
+}
+
+fn intern_ty(ty: &Ty<Name>) -> &'static Ty<Name> {
+    let key = bincode_options().serialize(ty).expect("Failed to encode type");
+    let mut interned = INTERNED_TYS.lock().unwrap();
+    interned.entry(key).or_insert_with(|| Box::leak(Box::new(ty.clone())))
+}
+
+fn snapshot_memory<B: BV>(memory: &Memory<B>) -> Result<Memory<B>, SnapshotError> {
+    let mut snapshot = Memory::new();
+    for region in memory.regions() {
+        match region {
+            Region::Symbolic(range) => snapshot.add_symbolic_region(range.clone()),
+            Region::SymbolicCode(range) => snapshot.add_symbolic_code_region(range.clone()),
+            Region::Concrete(range, contents) => snapshot.add_concrete_region(range.clone(), contents.clone()),
+            Region::Constrained(_, _) | Region::Custom(_, _) => {
+                return Err(SnapshotError::UnsupportedRegion(region.region_name()))
+            }
+        }
+    }
+    snapshot.set_ifetch_sees_writes(memory.ifetch_sees_writes());
+    for watchpoint in memory.watchpoints() {
+        snapshot.add_watchpoint(watchpoint.clone())
+    }
+    Ok(snapshot)
+}
+
+fn function_instrs<'ir, B>(
+    name: Name,
+    shared_state: &SharedState<'ir, B>,
+) -> Result<&'ir [Instr<Name, B>], SnapshotError> {
+    match shared_state.functions.get(&name) {
+        Some((_, _, instrs)) => Ok(*instrs),
+        None => Err(SnapshotError::UnknownFunction(name)),
+    }
+}
+
+impl<B: BV> Snapshot<B> {
+    /// Take a snapshot of a frame, which will usually be a suspended
+    /// frame returned to a collector, along with a checkpoint of the
+    /// solver for the same path. The thread id `tid` is used when
+    /// logging from the restored call stack.
+    pub fn new(tid: usize, frame: &LocalFrame<'_, B>, checkpoint: &Checkpoint<B>) -> Result<Self, SnapshotError> {
+        let mut regs: Vec<SnapshotRegister<B>> = frame
+            .local_state
+            .regs
+            .iter()
+            .map(|(name, reg)| {
+                let value = match &reg.value {
+                    RelaxedVal::Uninit(ty) => SnapshotRegisterValue::Uninit((*ty).clone()),
+                    RelaxedVal::Init { last_write, last_read, old_writes } => SnapshotRegisterValue::Init {
+                        last_write: last_write.clone(),
+                        last_read: last_read.clone(),
+                        old_writes: old_writes.clone(),
+                    },
+                };
+                SnapshotRegister { name: *name, relaxed: reg.relaxed, write_before_read: reg.write_before_read, value }
+            })
+            .collect();
+        regs.sort_by_key(|reg| reg.name);
+
+        let mut visited_states: Vec<u64> = frame.visited_states.iter().copied().collect();
+        visited_states.sort_unstable();
+
+        let mut function_assumptions: Vec<_> =
+            frame.function_assumptions.iter().map(|(name, cases)| (*name, cases.clone())).collect();
+        function_assumptions.sort_by_key(|(name, _)| *name);
+
+        Ok(Snapshot {
+            next_var: checkpoint.next_var(),
+            segments: checkpoint.segments(),
+            tid,
+            suspended: frame.suspended,
+            resuming_watchpoint: frame.resuming_watchpoint,
+            function_name: frame.function_name,
+            pc: frame.pc,
+            forks: frame.forks,
+            backjumps: frame.backjumps,
+            instructions: frame.instructions,
+            fork_path: frame.fork_path.to_vec(),
+            replay: frame.replay.to_vec(),
+            visited_states,
+            vars: snapshot_bindings(&frame.local_state.vars),
+            regs,
+            lets: snapshot_bindings(&frame.local_state.lets),
+            untraced_depth: frame.local_state.untraced_depth,
+            memory: snapshot_memory(&frame.memory)?,
+            stack_vars: frame.stack_vars.iter().map(snapshot_bindings).collect(),
+            backtrace: frame.backtrace.clone(),
+            function_assumptions,
+        })
+    }
+
+    /// Recreate the frame and solver checkpoint of the snapshot, which
+    /// can be turned into a task with
+    /// [LocalFrame::task_with_checkpoint], which resumes the frame if
+    /// it was suspended. The types of any uninitialized variables are
+    /// borrowed from the snapshot.
+    pub fn restore<'ir>(
+        &'ir self,
+        shared_state: &SharedState<'ir, B>,
+    ) -> Result<(LocalFrame<'ir, B>, Checkpoint<B>), SnapshotError> {
+        Ok((self.restore_frame(shared_state)?, self.checkpoint()))
+    }
+
+    /// Recreate the solver checkpoint of the snapshot. The checkpoint
+    /// can be shared by any number of frames restored from the
+    /// snapshot.
+    pub fn checkpoint(&self) -> Checkpoint<B> {
+        Checkpoint::from_segments(self.next_var, self.segments.clone())
+    }
+
+    /// Recreate just the frame of the snapshot, see [Snapshot::restore].
+    pub fn restore_frame<'ir>(
+        &'ir self,
+        shared_state: &SharedState<'ir, B>,
+    ) -> Result<LocalFrame<'ir, B>, SnapshotError> {
+        self.build_frame(shared_state, &|ty| ty)
+    }
+
+    /// Like [Snapshot::restore], but the restored frame does not
+    /// borrow from the snapshot, so the snapshot can be dropped. The
+    /// types of uninitialized variables are interned for the rest of
+    /// the execution instead.
+    pub(crate) fn restore_interned<'ir>(
+        &self,
+        shared_state: &SharedState<'ir, B>,
+    ) -> Result<(LocalFrame<'ir, B>, Checkpoint<B>), SnapshotError> {
+        Ok((self.build_frame(shared_state, &intern_ty)?, self.checkpoint()))
+    }
+
+    fn build_frame<'s, 'ir, F>(
+        &'s self,
+        shared_state: &SharedState<'ir, B>,
+        restore_ty: &F,
+    ) -> Result<LocalFrame<'ir, B>, SnapshotError>
+    where
+        F: Fn(&'s Ty<Name>) -> &'ir Ty<Name>,
+    {
+        let instrs = function_instrs(self.function_name, shared_state)?;
+        if self.pc >= instrs.len() {
+            return Err(SnapshotError::InvalidPosition(self.function_name, self.pc));
+        }
+
+        let mut stack_call = None;
+        for (caller, caller_pc) in &self.backtrace {
+            let caller_instrs = function_instrs(*caller, shared_state)?;
+            match caller_instrs.get(*caller_pc) {
+                Some(Instr::Call(loc, _, f, _, info)) if shared_state.functions.contains_key(f) => {
+                    let untraced = shared_state.untraced_functions.contains(f);
+                    stack_call = return_to(self.tid, *caller_pc, caller_instrs, loc, *info, untraced, stack_call)
+                }
+                _ => return Err(SnapshotError::InvalidPosition(*caller, *caller_pc)),
+            }
+        }
+
+        let mut regs = RegisterBindings::new();
+        for reg in &self.regs {
+            let value = match &reg.value {
+                SnapshotRegisterValue::Uninit(ty) => RelaxedVal::Uninit(restore_ty(ty)),
+                SnapshotRegisterValue::Init { last_write, last_read, old_writes } => RelaxedVal::Init {
+                    last_write: last_write.clone(),
+                    last_read: last_read.clone(),
+                    old_writes: old_writes.clone(),
+                },
+            };
+            regs.insert_register(
+                reg.name,
+                Register { relaxed: reg.relaxed, write_before_read: reg.write_before_read, value },
+            )
+        }
+
+        Ok(LocalFrame {
+            function_name: self.function_name,
+            pc: self.pc,
+            forks: self.forks,
+            backjumps: self.backjumps,
+            instructions: self.instructions,
+            fork_path: Arc::new(self.fork_path.clone()),
+            replay: Arc::new(self.replay.clone()),
+            visited_states: Arc::new(self.visited_states.iter().copied().collect()),
+            local_state: LocalState {
+                vars: restore_bindings(&self.vars, restore_ty),
+                regs,
+                lets: restore_bindings(&self.lets, restore_ty),
+                untraced_depth: self.untraced_depth,
+            },
+            memory: self.memory.clone(),
+            instrs,
+            stack_vars: self.stack_vars.iter().map(|bindings| restore_bindings(bindings, restore_ty)).collect(),
+            stack_call,
+            backtrace: self.backtrace.clone(),
+            function_assumptions: self.function_assumptions.iter().cloned().collect(),
+            suspended: self.suspended,
+            resuming_watchpoint: self.resuming_watchpoint,
+            // A restored path queries the solver again for any choice it was forked with
+            fork_choice: None,
+        })
+    }
+
+    pub fn write(&self, buf: &mut dyn Write) -> Result<(), SnapshotError> {
+        buf.write_all(MAGIC)?;
+
+        write_u32(buf, self.next_var)?;
+        write_usize(buf, self.segments.len())?;
+        for segment in &self.segments {
+            write_events_binary(buf, segment)?
+        }
+
+        write_usize(buf, self.tid)?;
+        write_bool(buf, self.suspended)?;
+        write_bool(buf, self.resuming_watchpoint)?;
+        write_name(buf, self.function_name)?;
+        write_usize(buf, self.pc)?;
+        write_u32(buf, self.forks)?;
+        write_u32(buf, self.backjumps)?;
+        write_varint(buf, self.instructions)?;
+        write_u32s(buf, &self.fork_path)?;
+        write_u32s(buf, &self.replay)?;
+        write_usize(buf, self.visited_states.len())?;
+        for state in &self.visited_states {
+            write_varint(buf, *state)?
+        }
+
+        write_bindings(buf, &self.vars)?;
+        write_usize(buf, self.regs.len())?;
+        for reg in &self.regs {
+            write_name(buf, reg.name)?;
+            write_bool(buf, reg.relaxed)?;
+            write_bool(buf, reg.write_before_read)?;
+            match &reg.value {
+                SnapshotRegisterValue::Uninit(ty) => {
+                    write_u8(buf, 0)?;
+                    bincode_options().serialize_into(&mut *buf, ty)?
+                }
+                SnapshotRegisterValue::Init { last_write, last_read, old_writes } => {
+                    write_u8(buf, 1)?;
+                    write_val_binary(buf, last_write)?;
+                    write_opt_val(buf, last_read)?;
+                    write_vals(buf, old_writes)?
+                }
+            }
+        }
+        write_bindings(buf, &self.lets)?;
+        write_u32(buf, self.untraced_depth)?;
+
+        write_memory(buf, &self.memory)?;
+
+        write_usize(buf, self.stack_vars.len())?;
+        for bindings in &self.stack_vars {
+            write_bindings(buf, bindings)?
+        }
+        write_usize(buf, self.backtrace.len())?;
+        for (name, pc) in &self.backtrace {
+            write_name(buf, *name)?;
+            write_usize(buf, *pc)?
+        }
+        write_usize(buf, self.function_assumptions.len())?;
+        for (name, cases) in &self.function_assumptions {
+            write_name(buf, *name)?;
+            write_usize(buf, cases.len())?;
+            for (args, result) in cases {
+                write_vals(buf, args)?;
+                write_val_binary(buf, result)?
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic).map_err(|_| SnapshotError::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let next_var = read_u32(input)?;
+        let mut segments = Vec::new();
+        for _ in 0..read_usize(input)? {
+            let mut segment = Vec::new();
+            while let Some(event) = read_event_binary(input)? {
+                segment.push(event)
+            }
+            segments.push(segment)
+        }
+
+        let tid = read_usize(input)?;
+        let suspended = read_bool(input)?;
+        let resuming_watchpoint = read_bool(input)?;
+        let function_name = read_name(input)?;
+        let pc = read_usize(input)?;
+        let forks = read_u32(input)?;
+        let backjumps = read_u32(input)?;
+        let instructions = read_varint(input)?;
+        let fork_path = read_u32s(input)?;
+        let replay = read_u32s(input)?;
+        let mut visited_states = Vec::new();
+        for _ in 0..read_usize(input)? {
+            visited_states.push(read_varint(input)?)
+        }
+
+        let vars = read_bindings(input)?;
+        let mut regs = Vec::new();
+        for _ in 0..read_usize(input)? {
+            let name = read_name(input)?;
+            let relaxed = read_bool(input)?;
+            let write_before_read = read_bool(input)?;
+            let value = match read_u8(input)? {
+                0 => SnapshotRegisterValue::Uninit(bincode_options().deserialize_from(&mut *input)?),
+                1 => SnapshotRegisterValue::Init {
+                    last_write: read_val_binary(input)?,
+                    last_read: read_opt_val(input)?,
+                    old_writes: read_vals(input)?,
+                },
+                tag => return Err(BinaryTraceError::BadTag("register", tag).into()),
+            };
+            regs.push(SnapshotRegister { name, relaxed, write_before_read, value })
+        }
+        let lets = read_bindings(input)?;
+        let untraced_depth = read_u32(input)?;
+
+        let memory = read_memory(input)?;
+
+        let mut stack_vars = Vec::new();
+        for _ in 0..read_usize(input)? {
+            stack_vars.push(read_bindings(input)?)
+        }
+        let mut backtrace = Vec::new();
+        for _ in 0..read_usize(input)? {
+            backtrace.push((read_name(input)?, read_usize(input)?))
+        }
+        let mut function_assumptions = Vec::new();
+        for _ in 0..read_usize(input)? {
+            let name = read_name(input)?;
+            let mut cases = Vec::new();
+            for _ in 0..read_usize(input)? {
+                cases.push((read_vals(input)?, read_val_binary(input)?))
+            }
+            function_assumptions.push((name, cases))
+        }
+
+        Ok(Snapshot {
+            next_var,
+            segments,
+            tid,
+            suspended,
+            resuming_watchpoint,
+            function_name,
+            pc,
+            forks,
+            backjumps,
+            instructions,
+            fork_path,
+            replay,
+            visited_states,
+            vars,
+            regs,
+            lets,
+            untraced_depth,
+            memory,
+            stack_vars,
+            backtrace,
+            function_assumptions,
+        })
+    }
+}
+
+fn write_u32s(buf: &mut dyn Write, ns: &[u32]) -> io::Result<()> {
+    write_usize(buf, ns.len())?;
+    for n in ns {
+        write_u32(buf, *n)?
+    }
+    Ok(())
+}
+
+fn read_u32s<R: Read>(input: &mut R) -> Result<Vec<u32>, BinaryTraceError> {
+    let mut ns = Vec::new();
+    for _ in 0..read_usize(input)? {
+        ns.push(read_u32(input)?)
+    }
+    Ok(ns)
+}
+
+fn write_bindings<B: BV>(buf: &mut dyn Write, bindings: &SnapshotBindings<B>) -> Result<(), SnapshotError> {
+    write_usize(buf, bindings.len())?;
+    for (id, uval) in bindings {
+        write_name(buf, *id)?;
+        match uval {
+            SnapshotUVal::Init(val) => {
+                write_u8(buf, 0)?;
+                write_val_binary(buf, val)?
+            }
+            SnapshotUVal::Uninit(ty) => {
+                write_u8(buf, 1)?;
+                bincode_options().serialize_into(&mut *buf, ty)?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_bindings<B: BV, R: Read>(input: &mut R) -> Result<SnapshotBindings<B>, SnapshotError> {
+    let mut bindings = Vec::new();
+    for _ in 0..read_usize(input)? {
+        let id = read_name(input)?;
+        let uval = match read_u8(input)? {
+            0 => SnapshotUVal::Init(read_val_binary(input)?),
+            1 => SnapshotUVal::Uninit(bincode_options().deserialize_from(&mut *input)?),
+            tag => return Err(BinaryTraceError::BadTag("binding", tag).into()),
+        };
+        bindings.push((id, uval))
+    }
+    Ok(bindings)
+}
+
+const REGION_SYMBOLIC: u8 = 0;
+const REGION_SYMBOLIC_CODE: u8 = 1;
+const REGION_CONCRETE: u8 = 2;
+
+fn write_range(buf: &mut dyn Write, range: &Range<Address>) -> io::Result<()> {
+    write_varint(buf, range.start)?;
+    write_varint(buf, range.end)
+}
+
+fn read_range<R: Read>(input: &mut R) -> Result<Range<Address>, BinaryTraceError> {
+    Ok(read_varint(input)?..read_varint(input)?)
+}
+
+/// Memory is always written after [snapshot_memory], so only
+/// contains regions which can be serialized.
+fn write_memory<B: BV>(buf: &mut dyn Write, memory: &Memory<B>) -> Result<(), SnapshotError> {
+    write_bool(buf, memory.ifetch_sees_writes())?;
+    write_usize(buf, memory.regions().len())?;
+    for region in memory.regions() {
+        match region {
+            Region::Symbolic(range) => {
+                write_u8(buf, REGION_SYMBOLIC)?;
+                write_range(buf, range)?
+            }
+            Region::SymbolicCode(range) => {
+                write_u8(buf, REGION_SYMBOLIC_CODE)?;
+                write_range(buf, range)?
+            }
+            Region::Concrete(range, contents) => {
+                write_u8(buf, REGION_CONCRETE)?;
+                write_range(buf, range)?;
+                let mut contents: Vec<(Address, u8)> = contents.iter().map(|(addr, byte)| (*addr, *byte)).collect();
+                contents.sort_unstable();
+                write_usize(buf, contents.len())?;
+                for (addr, byte) in contents {
+                    write_varint(buf, addr)?;
+                    write_u8(buf, byte)?
+                }
+            }
+            Region::Constrained(_, _) | Region::Custom(_, _) => {
+                return Err(SnapshotError::UnsupportedRegion(region.region_name()))
+            }
+        }
+    }
+    write_usize(buf, memory.watchpoints().len())?;
+    for watchpoint in memory.watchpoints() {
+        write_range(buf, &watchpoint.range)?;
+        write_bool(buf, watchpoint.reads)?;
+        write_bool(buf, watchpoint.writes)?;
+        write_bool(buf, watchpoint.action == WatchAction::Suspend)?
+    }
+    Ok(())
+}
+
+fn read_memory<B: BV, R: Read>(input: &mut R) -> Result<Memory<B>, SnapshotError> {
+    let mut memory = Memory::new();
+    memory.set_ifetch_sees_writes(read_bool(input)?);
+    for _ in 0..read_usize(input)? {
+        match read_u8(input)? {
+            REGION_SYMBOLIC => memory.add_symbolic_region(read_range(input)?),
+            REGION_SYMBOLIC_CODE => memory.add_symbolic_code_region(read_range(input)?),
+            REGION_CONCRETE => {
+                let range = read_range(input)?;
+                let mut contents = HashMap::new();
+                for _ in 0..read_usize(input)? {
+                    contents.insert(read_varint(input)?, read_u8(input)?);
+                }
+                memory.add_concrete_region(range, contents)
+            }
+            tag => return Err(BinaryTraceError::BadTag("memory region", tag).into()),
+        }
+    }
+    for _ in 0..read_usize(input)? {
+        let range = read_range(input)?;
+        let reads = read_bool(input)?;
+        let writes = read_bool(input)?;
+        let action = if read_bool(input)? { WatchAction::Suspend } else { WatchAction::Report };
+        memory.add_watchpoint(Watchpoint { range, reads, writes, action })
+    }
+    Ok(memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib;
+
+    fn encode<B: BV>(snapshot: &Snapshot<B>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn roundtrip() {
+        let x = Name::from_u32(100);
+        let y = Name::from_u32(101);
+        let r = Name::from_u32(102);
+        let f = Name::from_u32(103);
+
+        let mut frame: LocalFrame<B64> = LocalFrame::new(f, &[(x, &Ty::I64), (y, &Ty::Bool)], &Ty::Unit, None, &[]);
+        frame.insert_local(x, Val::I64(-3));
+        frame.regs_mut().insert(r, false, UVal::Init(Val::Bits(B64::new(0xAB, 8))));
+        let mut contents = HashMap::new();
+        contents.insert(0x1000, 0x13);
+        contents.insert(0x1001, 0x37);
+        frame.memory_mut().add_concrete_region(0x1000..0x2000, contents);
+        frame.memory_mut().add_symbolic_region(0x3000..0x4000);
+
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(smtlib::Ty::BitVec(8), SourceLoc::unknown());
+        checkpoint(&mut solver);
+        solver.add(smtlib::Def::Assert(smtlib::Exp::Eq(
+            Box::new(smtlib::Exp::Var(v)),
+            Box::new(smtlib::Exp::Bits64(B64::new(0xAB, 8))),
+        )));
+        let point = checkpoint(&mut solver);
+        frame.suspend();
+
+        let snapshot = Snapshot::new(3, &frame, &point).unwrap();
+        assert_eq!(snapshot.segments.len(), 2);
+
+        let bytes = encode(&snapshot);
+        let decoded: Snapshot<B64> = Snapshot::read(&mut &bytes[..]).unwrap();
+        assert_eq!(encode(&decoded), bytes);
+        assert_eq!(decoded.next_var, point.next_var());
+        assert_eq!(decoded.tid, 3);
+        assert!(decoded.suspended);
+        assert!(matches!(decoded.vars.iter().find(|(id, _)| *id == y), Some((_, SnapshotUVal::Uninit(Ty::Bool)))));
+        assert!(matches!(decoded.memory.read_initial(0x1000, 2), Ok(Val::Bits(bv)) if bv == B64::new(0x3713, 16)));
+    }
+
+    #[test]
+    fn watchpoint_resume() {
+        use crate::memory::WatchAction;
+        use crate::primop::watch_access;
+
+        let f = Name::from_u32(0);
+        let mut frame: LocalFrame<B64> = LocalFrame::new(f, &[], &Ty::Unit, None, &[]);
+        frame.memory_mut().add_watchpoint(Watchpoint::new(0x1000..0x1010, WatchAction::Suspend));
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let (address, bytes) = (Val::Bits(B64::new(0x1000, 64)), Val::I128(8));
+
+        assert!(watch_access(&address, &bytes, None, &mut solver, &mut frame).unwrap());
+        assert!(frame.is_suspended());
+
+        // The watchpoint and the pending resume survive a snapshot
+        let snapshot = Snapshot::new(0, &frame, &Checkpoint::new()).unwrap();
+        let decoded: Snapshot<B64> = Snapshot::read(&mut &encode(&snapshot)[..]).unwrap();
+        assert_eq!(decoded.memory.watchpoints().len(), 1);
+        assert!(decoded.suspended && decoded.resuming_watchpoint);
+
+        // Resuming re-executes the suspended access without stopping,
+        // but later accesses are still watched
+        let state = TaskState::new();
+        let task = frame.task(0, &state);
+        let mut resumed = unfreeze_frame(&task.frame);
+        assert!(!resumed.is_suspended());
+        assert!(!watch_access(&address, &bytes, None, &mut solver, &mut resumed).unwrap());
+        assert!(!resumed.is_suspended());
+        assert!(watch_access(&address, &bytes, None, &mut solver, &mut resumed).unwrap())
+    }
+
+    #[test]
+    fn unsupported_region() {
+        let f = Name::from_u32(0);
+        let mut frame: LocalFrame<B64> = LocalFrame::new(f, &[], &Ty::Unit, None, &[]);
+        frame.memory_mut().add_region(Region::Constrained(0..8, Arc::new(|solver: &mut Solver<B64>| solver.fresh())));
+        assert!(matches!(
+            Snapshot::new(0, &frame, &Checkpoint::new()),
+            Err(SnapshotError::UnsupportedRegion("constrained"))
+        ))
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert!(matches!(Snapshot::<B64>::read(&mut &b"ISLATRC\x01"[..]), Err(SnapshotError::BadMagic)))
+    }
+}
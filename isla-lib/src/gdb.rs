@@ -0,0 +1,572 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a stub for the GDB remote serial protocol,
+//! so gdb can be attached to a model while it runs concretely. The
+//! model itself is provided by a [Target], which gives access to
+//! registers and memory and can execute a single instruction, while
+//! breakpoints, continuing, and the protocol are handled here.
+//!
+//! Only the subset of the protocol needed for interactive debugging
+//! is supported: reading and writing registers and memory,
+//! single-stepping, continuing, software and hardware breakpoints,
+//! and an XML target description generated from
+//! [Target::registers]. Registers are sent little-endian.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// A register as presented to gdb
+pub struct RegisterInfo {
+    pub name: String,
+    pub bitsize: u32,
+}
+
+/// The model being debugged
+pub trait Target {
+    /// The architecture name used in the target description, e.g.
+    /// `aarch64` or `riscv:rv64`
+    fn architecture(&self) -> &str;
+
+    /// The gdb feature the registers belong to, e.g.
+    /// `org.gnu.gdb.aarch64.core`
+    fn feature(&self) -> &str;
+
+    /// The registers in the order gdb numbers them. Registers must be
+    /// at most 64 bits.
+    fn registers(&self) -> &[RegisterInfo];
+
+    /// Returns `None` if the register has no concrete value
+    fn read_register(&mut self, n: usize) -> Option<u64>;
+
+    fn write_register(&mut self, n: usize, value: u64) -> Result<(), String>;
+
+    fn read_memory(&mut self, address: u64, len: usize) -> Result<Vec<u8>, String>;
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), String>;
+
+    fn pc(&mut self) -> Option<u64>;
+
+    /// Execute a single instruction
+    fn step(&mut self) -> Result<(), String>;
+}
+
+/// A connection to gdb, which must be able to check for an interrupt
+/// (a `0x03` byte) without blocking while the target is running
+pub trait Connection: Read + Write {
+    fn poll_interrupt(&mut self) -> io::Result<bool>;
+}
+
+impl Connection for TcpStream {
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = match self.read(&mut byte) {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap()
+    }
+    hex
+}
+
+/// Parse `<addr>,<len>` as used by the memory and breakpoint packets
+fn parse_addr_len(s: &str) -> Option<(u64, u64)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((u64::from_str_radix(addr, 16).ok()?, u64::from_str_radix(len, 16).ok()?))
+}
+
+/// Parse a little-endian register value of `bytes` bytes
+fn parse_register(hex: &str, bytes: usize) -> Option<u64> {
+    let value = hex_to_bytes(hex)?;
+    if value.len() != bytes {
+        return None;
+    }
+    Some(value.iter().rev().fold(0, |n, byte| (n << 8) | u64::from(*byte)))
+}
+
+enum Action {
+    Reply(String),
+    /// Reply, then stop serving
+    Finish(String),
+}
+
+pub struct GdbStub<T> {
+    target: T,
+    breakpoints: BTreeSet<u64>,
+}
+
+impl<T: Target> GdbStub<T> {
+    pub fn new(target: T) -> Self {
+        GdbStub { target, breakpoints: BTreeSet::new() }
+    }
+
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    pub fn into_target(self) -> T {
+        self.target
+    }
+
+    fn target_xml(&self) -> String {
+        let mut xml =
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\">".to_string();
+        write!(
+            xml,
+            "<architecture>{}</architecture><feature name=\"{}\">",
+            self.target.architecture(),
+            self.target.feature()
+        )
+        .unwrap();
+        for (n, reg) in self.target.registers().iter().enumerate() {
+            write!(xml, "<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\"/>", reg.name, reg.bitsize, n).unwrap()
+        }
+        xml.push_str("</feature></target>");
+        xml
+    }
+
+    fn register_hex(&mut self, n: usize) -> String {
+        let bytes = (self.target.registers()[n].bitsize as usize + 7) / 8;
+        match self.target.read_register(n) {
+            Some(value) => bytes_to_hex(&value.to_le_bytes()[..bytes.min(8)]),
+            None => "xx".repeat(bytes),
+        }
+    }
+
+    fn stop_reply(&self, result: Result<u8, String>) -> String {
+        match result {
+            Ok(signal) => format!("S{:02x}", signal),
+            Err(msg) => {
+                eprintln!("Stopped: {}", msg);
+                format!("S{:02x}", SIGILL)
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<u8, String> {
+        self.target.step()?;
+        Ok(SIGTRAP)
+    }
+
+    fn resume<C: Connection>(&mut self, conn: &mut C) -> Result<u8, String> {
+        loop {
+            self.target.step()?;
+            if self.target.pc().map_or(false, |pc| self.breakpoints.contains(&pc)) {
+                return Ok(SIGTRAP);
+            }
+            if conn.poll_interrupt().map_err(|e| e.to_string())? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    fn handle<C: Connection>(&mut self, packet: &str, conn: &mut C) -> Action {
+        use Action::*;
+
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+
+            "g" => (0..self.target.registers().len()).map(|n| self.register_hex(n)).collect(),
+
+            "G" => {
+                let mut rest = args;
+                for n in 0..self.target.registers().len() {
+                    let bytes = (self.target.registers()[n].bitsize as usize + 7) / 8;
+                    let Some((hex, tail)) = rest.get(..bytes * 2).map(|hex| (hex, &rest[bytes * 2..])) else {
+                        break;
+                    };
+                    rest = tail;
+                    if let Some(value) = parse_register(hex, bytes) {
+                        if let Err(msg) = self.target.write_register(n, value) {
+                            eprintln!("{}", msg);
+                            return Reply("E01".to_string());
+                        }
+                    }
+                }
+                "OK".to_string()
+            }
+
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < self.target.registers().len() => self.register_hex(n),
+                _ => "E01".to_string(),
+            },
+
+            "P" => {
+                let write = args.split_once('=').and_then(|(n, hex)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let bytes = (self.target.registers().get(n)?.bitsize as usize + 7) / 8;
+                    Some((n, parse_register(hex, bytes)?))
+                });
+                match write.map(|(n, value)| self.target.write_register(n, value)) {
+                    Some(Ok(())) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+
+            "m" => match parse_addr_len(args).map(|(addr, len)| self.target.read_memory(addr, len as usize)) {
+                Some(Ok(bytes)) => bytes_to_hex(&bytes),
+                _ => "E01".to_string(),
+            },
+
+            "M" => {
+                let write = args.split_once(':').and_then(|(addr_len, hex)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    let data = hex_to_bytes(hex)?;
+                    if data.len() as u64 == len {
+                        Some((addr, data))
+                    } else {
+                        None
+                    }
+                });
+                match write.map(|(addr, data)| self.target.write_memory(addr, &data)) {
+                    Some(Ok(())) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+
+            "s" => {
+                let result = self.step();
+                self.stop_reply(result)
+            }
+
+            "c" => {
+                let result = self.resume(conn);
+                self.stop_reply(result)
+            }
+
+            // Software and hardware breakpoints are treated the same,
+            // and watchpoints are unsupported
+            "Z" | "z" => match args.split_once(',').filter(|(kind, _)| *kind == "0" || *kind == "1") {
+                Some((_, rest)) => {
+                    match rest.split_once(',').and_then(|(addr, _)| u64::from_str_radix(addr, 16).ok()) {
+                        Some(addr) => {
+                            if command == "Z" {
+                                self.breakpoints.insert(addr);
+                            } else {
+                                self.breakpoints.remove(&addr);
+                            }
+                            "OK".to_string()
+                        }
+                        None => "E01".to_string(),
+                    }
+                }
+                None => String::new(),
+            },
+
+            "q" => {
+                if args.starts_with("Supported") {
+                    "PacketSize=4000;qXfer:features:read+".to_string()
+                } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+                    match parse_addr_len(range) {
+                        Some((offset, len)) => {
+                            let xml = self.target_xml();
+                            let start = (offset as usize).min(xml.len());
+                            let end = (start + len as usize).min(xml.len());
+                            format!("{}{}", if end == xml.len() { "l" } else { "m" }, &xml[start..end])
+                        }
+                        None => "E01".to_string(),
+                    }
+                } else if args == "Attached" {
+                    "1".to_string()
+                } else if args == "C" {
+                    "QC1".to_string()
+                } else if args == "fThreadInfo" {
+                    "m1".to_string()
+                } else if args == "sThreadInfo" {
+                    "l".to_string()
+                } else {
+                    String::new()
+                }
+            }
+
+            // There is only a single thread
+            "H" | "T" => "OK".to_string(),
+
+            "D" => return Finish("OK".to_string()),
+
+            _ => String::new(),
+        };
+        Reply(reply)
+    }
+
+    fn send<C: Connection>(conn: &mut C, data: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20)
+            } else {
+                escaped.push(byte)
+            }
+        }
+        write!(conn, "$")?;
+        conn.write_all(&escaped)?;
+        write!(conn, "#{:02x}", checksum(&escaped))?;
+        conn.flush()
+    }
+
+    /// Read the next packet, acknowledging it. Returns `None` when
+    /// the connection is closed.
+    fn receive<C: Connection>(conn: &mut C) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // Skip acknowledgements and interrupts sent while stopped
+            loop {
+                if conn.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if conn.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0])
+            }
+
+            let mut sum = [0; 2];
+            conn.read_exact(&mut sum)?;
+            let expected = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            if expected == Some(checksum(&data)) {
+                conn.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            } else {
+                conn.write_all(b"-")?
+            }
+        }
+    }
+
+    /// Serve requests from gdb until it detaches, kills the target,
+    /// or closes the connection
+    pub fn serve<C: Connection>(&mut self, conn: &mut C) -> io::Result<()> {
+        while let Some(packet) = Self::receive(conn)? {
+            if packet == "k" {
+                break;
+            }
+            match self.handle(&packet, conn) {
+                Action::Reply(reply) => Self::send(conn, &reply)?,
+                Action::Finish(reply) => {
+                    Self::send(conn, &reply)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    /// A target with a pc and a single general purpose register,
+    /// where each step increments the register and advances the pc
+    struct Counter {
+        regs: [RegisterInfo; 2],
+        values: [u64; 2],
+        memory: HashMap<u64, u8>,
+    }
+
+    impl Counter {
+        fn new() -> Self {
+            Counter {
+                regs: [
+                    RegisterInfo { name: "x0".to_string(), bitsize: 64 },
+                    RegisterInfo { name: "pc".to_string(), bitsize: 32 },
+                ],
+                values: [0, 0x1000],
+                memory: HashMap::new(),
+            }
+        }
+    }
+
+    impl Target for Counter {
+        fn architecture(&self) -> &str {
+            "test"
+        }
+
+        fn feature(&self) -> &str {
+            "org.gnu.gdb.test.core"
+        }
+
+        fn registers(&self) -> &[RegisterInfo] {
+            &self.regs
+        }
+
+        fn read_register(&mut self, n: usize) -> Option<u64> {
+            self.values.get(n).copied()
+        }
+
+        fn write_register(&mut self, n: usize, value: u64) -> Result<(), String> {
+            self.values[n] = value;
+            Ok(())
+        }
+
+        fn read_memory(&mut self, address: u64, len: usize) -> Result<Vec<u8>, String> {
+            Ok((address..address + len as u64).map(|addr| self.memory.get(&addr).copied().unwrap_or(0)).collect())
+        }
+
+        fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), String> {
+            for (addr, byte) in (address..).zip(data) {
+                self.memory.insert(addr, *byte);
+            }
+            Ok(())
+        }
+
+        fn pc(&mut self) -> Option<u64> {
+            Some(self.values[1])
+        }
+
+        fn step(&mut self) -> Result<(), String> {
+            self.values[0] += 1;
+            self.values[1] += 4;
+            Ok(())
+        }
+    }
+
+    /// Requests from gdb are read from `input`, and replies written to
+    /// `output`
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Script {
+        fn poll_interrupt(&mut self) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    fn run(packets: &[&str]) -> (String, Counter) {
+        let input: String = packets.iter().map(|data| packet(data)).collect();
+        let mut script = Script { input: Cursor::new(input.into_bytes()), output: Vec::new() };
+        let mut stub = GdbStub::new(Counter::new());
+        stub.serve(&mut script).unwrap();
+        (String::from_utf8(script.output).unwrap(), stub.into_target())
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (output, target) = run(&["g", "P0=2a00000000000000", "p0", "M2000,2:beef", "m1fff,4"]);
+        assert_eq!(
+            output,
+            [
+                format!("+{}", packet("000000000000000000100000")),
+                format!("+{}", packet("OK")),
+                format!("+{}", packet("2a00000000000000")),
+                format!("+{}", packet("OK")),
+                format!("+{}", packet("00beef00")),
+            ]
+            .concat()
+        );
+        assert_eq!(target.values[0], 42)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (output, target) = run(&["Z0,100c,4", "c", "s", "z0,100c,4", "D"]);
+        assert_eq!(
+            output,
+            [
+                format!("+{}", packet("OK")),
+                format!("+{}", packet("S05")),
+                format!("+{}", packet("S05")),
+                format!("+{}", packet("OK")),
+                format!("+{}", packet("OK")),
+            ]
+            .concat()
+        );
+        assert_eq!(target.values, [4, 0x1010])
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut script = Script { input: Cursor::new(b"$g#00".to_vec()), output: Vec::new() };
+        GdbStub::new(Counter::new()).serve(&mut script).unwrap();
+        assert_eq!(script.output, b"-")
+    }
+}
@@ -51,7 +51,7 @@
 //! * Finally use the [initialize_architecture] function in this
 //! module to set up everything ready for symbolic execution.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::Mutex;
 
 use crate::bitvector::BV;
@@ -59,9 +59,17 @@ use crate::config::ISAConfig;
 use crate::executor::{start_single, LocalFrame, TaskState};
 use crate::ir::*;
 use crate::log;
+use crate::primop::Primops;
 use crate::register::RegisterBindings;
 use crate::zencode;
 
+/// Evaluate each top-level letbinding once at load time, using a
+/// throwaway solver. Bindings which evaluate to a single concrete
+/// value on every path are baked into the initial letbindings. As
+/// symbolic values from the throwaway solver mean nothing in any
+/// other solver, bindings which are symbolic, depend on a choice, or
+/// fail to evaluate are left uninitialized, so they become fresh
+/// symbolic values when first used.
 fn initialize_letbindings<'ir, B: BV>(
     arch: &'ir [Def<Name, B>],
     shared_state: &SharedState<'ir, B>,
@@ -80,48 +88,61 @@ fn initialize_letbindings<'ir, B: BV>(
                     .task(0, &task_state)
             };
 
-            start_single(
-                task,
-                shared_state,
-                &letbindings,
-                &move |_tid, _task_id, result, shared_state, _solver, letbindings| match result {
-                    Ok((_, frame)) => {
-                        for (id, _) in bindings.iter() {
-                            match frame.vars().get(id) {
-                                Some(value) => {
-                                    let mut state = letbindings.lock().unwrap();
-                                    state.insert(*id, value.clone());
-                                }
-                                None => {
-                                    let symbol = zencode::decode(shared_state.symtab.to_str(*id));
-                                    log!(log::VERBOSE, &format!("No value for symbol {}", symbol))
-                                }
-                            }
-                        }
+            let paths: Mutex<Vec<Option<Bindings<'ir, B>>>> = Mutex::new(Vec::new());
+            start_single(task, shared_state, &paths, &move |_tid, _task_id, result, _shared_state, _solver, paths| {
+                match result {
+                    Ok((_, frame)) => paths.lock().unwrap().push(Some(frame.vars().clone())),
+                    Err(err) => {
+                        log!(log::VERBOSE, &format!("Failed to evaluate letbinding: {:?}", err));
+                        paths.lock().unwrap().push(None)
                     }
-                    Err(err) => log!(log::VERBOSE, &format!("Failed to evaluate letbinding: {:?}", err)),
-                },
-            );
+                }
+            });
+            let paths = paths.into_inner().unwrap();
+
+            let mut lets = letbindings.lock().unwrap();
+            for (id, ty) in bindings.iter() {
+                let value = match paths.as_slice() {
+                    [Some(vars)] => match vars.get(id) {
+                        Some(UVal::Init(value)) if !value.is_symbolic() => Some(value.clone()),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match value {
+                    Some(value) => {
+                        lets.insert(*id, UVal::Init(value));
+                    }
+                    None => {
+                        let symbol = zencode::decode(shared_state.symtab.to_str(*id));
+                        log!(log::VERBOSE, &format!("Letbinding {} is not constant", symbol));
+                        lets.insert(*id, UVal::Uninit(ty));
+                    }
+                }
+            }
         }
     }
 }
 
 fn initialize_register_state<'ir, B: BV>(
     defs: &'ir [Def<Name, B>],
-    initial_registers: &HashMap<Name, Val<B>>,
-    relaxed_registers: &HashSet<Name>,
+    isa_config: &ISAConfig<B>,
     symtab: &Symtab,
 ) -> RegisterBindings<'ir, B> {
+    let relaxed_registers = &isa_config.relaxed_registers;
     let mut registers = RegisterBindings::new();
     for def in defs.iter() {
         if let Def::Register(id, ty) = def {
-            if let Some(value) = initial_registers.get(id) {
+            if let Some(value) = isa_config.default_registers.get(id) {
                 value
                     .plausible(ty, symtab)
                     .unwrap_or_else(|err_msg| panic!("Bad initial value for {}: {}", symtab.to_str(*id), err_msg));
                 registers.insert(*id, relaxed_registers.contains(id), UVal::Init(value.clone()));
             } else {
                 registers.insert(*id, relaxed_registers.contains(id), UVal::Uninit(ty));
+                if isa_config.requires_write_before_read(*id) {
+                    registers.require_write_before_read(*id)
+                }
             }
         }
     }
@@ -139,13 +160,27 @@ pub fn initialize_architecture<'ir, B: BV>(
     symtab: Symtab<'ir>,
     isa_config: &ISAConfig<B>,
     mode: AssertionMode,
+) -> Initialized<'ir, B> {
+    initialize_architecture_with_primops(arch, symtab, isa_config, mode, &Primops::default())
+}
+
+/// Like [initialize_architecture], but using the given primop
+/// registry to implement Sail `extern` functions, allowing client
+/// crates to provide platform-specific intrinsics.
+pub fn initialize_architecture_with_primops<'ir, B: BV>(
+    arch: &'ir mut [Def<Name, B>],
+    symtab: Symtab<'ir>,
+    isa_config: &ISAConfig<B>,
+    mode: AssertionMode,
+    primops: &Primops<B>,
 ) -> Initialized<'ir, B> {
     insert_monomorphize(arch);
-    insert_primops(arch, mode);
+    uninterpret_functions(arch, &isa_config.uninterpreted_functions);
+    insert_primops(arch, mode, primops);
 
-    let regs = initialize_register_state(arch, &isa_config.default_registers, &isa_config.relaxed_registers, &symtab);
+    let regs = initialize_register_state(arch, isa_config, &symtab);
     let lets = Mutex::new(HashMap::default());
-    let shared_state = SharedState::new(
+    let mut shared_state = SharedState::new(
         symtab,
         arch,
         isa_config.probes.clone(),
@@ -154,6 +189,10 @@ pub fn initialize_architecture<'ir, B: BV>(
         isa_config.reset_constraints.clone(),
         isa_config.function_assumptions.clone(),
     );
+    shared_state.set_untraced_functions(isa_config.untraced_functions.clone());
+    shared_state.set_abstract_primitives(isa_config.abstract_primitives.clone());
+    shared_state.set_widening(isa_config.widening.clone());
+    shared_state.set_register_fields(isa_config.register_fields.clone());
 
     initialize_letbindings(arch, &shared_state, &regs, &lets);
 
@@ -0,0 +1,488 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module loads instruction metadata, either from ARM's
+//! machine-readable XML specification or from the tables in the
+//! riscv-opcodes repository, so opcodes in traces and reports can be
+//! labelled with a mnemonic and the values of their fields rather
+//! than as bare hexadecimal constants.
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::bitvector::BV;
+
+/// A single instruction encoding, with the bits that must be fixed
+/// for an opcode to match and the positions of its fields
+struct Encoding {
+    name: String,
+    width: u32,
+    mask: u64,
+    bits: u64,
+    /// Field names with their high and low bit positions
+    fields: Vec<(String, u32, u32)>,
+}
+
+impl Encoding {
+    fn matches(&self, opcode: u64, width: u32) -> bool {
+        self.width == width && opcode & self.mask == self.bits
+    }
+}
+
+/// An opcode decoded using [InstructionNames::decode]. Displays as the
+/// mnemonic followed by each field, e.g. `add rd=1 rs1=2 rs2=3`.
+pub struct DecodedInstruction<'a> {
+    pub name: &'a str,
+    pub fields: Vec<(&'a str, u64)>,
+}
+
+impl<'a> fmt::Display for DecodedInstruction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (field, value) in &self.fields {
+            write!(f, " {}={}", field, value)?
+        }
+        Ok(())
+    }
+}
+
+fn bit_range(hi: u32, lo: u32) -> u64 {
+    (u64::MAX >> (63 - hi)) & !((1 << lo) - 1)
+}
+
+fn parse_int(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// The operand positions from riscv-opcodes' `arg_lut.csv` for the
+/// common extensions, so tables can be loaded without it
+const RISCV_ARGS: &[(&str, u32, u32)] = &[
+    ("rd", 11, 7),
+    ("rs1", 19, 15),
+    ("rs2", 24, 20),
+    ("rs3", 31, 27),
+    ("aqrl", 26, 25),
+    ("aq", 26, 26),
+    ("rl", 25, 25),
+    ("fm", 31, 28),
+    ("pred", 27, 24),
+    ("succ", 23, 20),
+    ("rm", 14, 12),
+    ("imm20", 31, 12),
+    ("jimm20", 31, 12),
+    ("imm12", 31, 20),
+    ("csr", 31, 20),
+    ("imm12hi", 31, 25),
+    ("bimm12hi", 31, 25),
+    ("imm12lo", 11, 7),
+    ("bimm12lo", 11, 7),
+    ("zimm", 19, 15),
+    ("shamtq", 26, 20),
+    ("shamtd", 25, 20),
+    ("shamtw", 24, 20),
+    ("vd", 11, 7),
+    ("vs1", 19, 15),
+    ("vs2", 24, 20),
+    ("vs3", 11, 7),
+    ("vm", 25, 25),
+    ("nf", 31, 29),
+    ("simm5", 19, 15),
+    ("rd_p", 4, 2),
+    ("rs1_p", 9, 7),
+    ("rs2_p", 4, 2),
+    ("rd_rs1_p", 9, 7),
+    ("rd_rs1_n0", 11, 7),
+    ("rd_n0", 11, 7),
+    ("rs1_n0", 11, 7),
+    ("c_rs1_n0", 11, 7),
+    ("c_rs2", 6, 2),
+    ("c_rs2_n0", 6, 2),
+];
+
+/// A table of instruction encodings, used to name opcodes
+pub struct InstructionNames {
+    encodings: Vec<Encoding>,
+    riscv_args: HashMap<String, (u32, u32)>,
+}
+
+impl Default for InstructionNames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstructionNames {
+    pub fn new() -> Self {
+        InstructionNames {
+            encodings: Vec::new(),
+            riscv_args: RISCV_ARGS.iter().map(|(arg, hi, lo)| (arg.to_string(), (*hi, *lo))).collect(),
+        }
+    }
+
+    /// Load instruction metadata from a file or directory. Files
+    /// ending in `.xml` are read as ARM XML, `arg_lut.csv` gives
+    /// riscv-opcodes operand positions, and anything else is read as
+    /// a riscv-opcodes table. For directories, each file is loaded
+    /// (operand positions first).
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut files = fs::read_dir(path)
+                .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("Could not read directory {}: {}", path.display(), e))?;
+            files.retain(|file| file.is_file());
+            files.sort_by_key(|file| (!file.ends_with("arg_lut.csv"), file.clone()));
+            for file in files {
+                let extension = file.extension().and_then(|ext| ext.to_str());
+                if matches!(extension, Some("xml") | Some("csv"))
+                    || file.file_name().and_then(|name| name.to_str()).map_or(false, |name| name.starts_with("rv"))
+                {
+                    self.load(&file)?
+                }
+            }
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read instruction metadata {}: {}", path.display(), e))?;
+        let result = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("xml") => self.load_arm_xml(&contents),
+            Some("csv") => self.load_riscv_args(&contents),
+            _ => self.load_riscv_opcodes(&contents),
+        };
+        result.map_err(|msg| format!("{}: {}", path.display(), msg))
+    }
+
+    pub fn len(&self) -> usize {
+        self.encodings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.encodings.is_empty()
+    }
+
+    /// Load operand positions in the format of riscv-opcodes'
+    /// `arg_lut.csv`, e.g. `"rd", 11, 7`
+    pub fn load_riscv_args(&mut self, contents: &str) -> Result<(), String> {
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let parts: Vec<&str> = line.split(',').map(|part| part.trim().trim_matches('"')).collect();
+            match parts.as_slice() {
+                [arg, hi, lo] => {
+                    let (Some(hi), Some(lo)) = (parse_int(hi), parse_int(lo)) else {
+                        return Err(format!("Invalid operand position in '{}'", line));
+                    };
+                    self.riscv_args.insert(arg.to_string(), (hi as u32, lo as u32));
+                }
+                _ => return Err(format!("Expected 'name, hi, lo' in '{}'", line)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a riscv-opcodes table, where each line gives a mnemonic,
+    /// its operands, and its fixed bits, e.g.
+    /// `add rd rs1 rs2 31..25=0 14..12=0 6..2=0x0C 1..0=3`. Lines
+    /// starting with `$` (pseudo-ops and imports) are skipped.
+    pub fn load_riscv_opcodes(&mut self, contents: &str) -> Result<(), String> {
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() || line.starts_with('$') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap().to_string();
+            let (mut mask, mut bits, mut fields, mut top) = (0, 0, Vec::new(), 0);
+            for word in words {
+                if let Some((range, value)) = word.split_once('=') {
+                    let (hi, lo) = match range.split_once("..") {
+                        Some((hi, lo)) => (parse_int(hi), parse_int(lo)),
+                        None => (parse_int(range), parse_int(range)),
+                    };
+                    let (Some(hi), Some(lo)) = (hi, lo) else {
+                        return Err(format!("Invalid bit range {} for {}", range, name));
+                    };
+                    let (hi, lo) = (hi as u32, lo as u32);
+                    if hi > 63 || lo > hi {
+                        return Err(format!("Invalid bit range {} for {}", range, name));
+                    }
+                    top = top.max(hi);
+                    if value == "ignore" {
+                        continue;
+                    }
+                    let Some(value) = parse_int(value) else {
+                        return Err(format!("Invalid value {} for {}", value, name));
+                    };
+                    mask |= bit_range(hi, lo);
+                    bits |= (value << lo) & bit_range(hi, lo)
+                } else if let Some((hi, lo)) = self.riscv_args.get(word) {
+                    top = top.max(*hi);
+                    fields.push((word.to_string(), *hi, *lo))
+                }
+            }
+            // Instructions are a multiple of 16 bits, so compressed
+            // instructions are distinguished from the rest
+            let width = (top / 16 + 1) * 16;
+            self.encodings.push(Encoding { name, width, mask, bits, fields })
+        }
+        Ok(())
+    }
+
+    /// Load an instruction file from ARM's machine-readable XML
+    /// specification. Each `<encoding>` is named by its mnemonic, and
+    /// its fields are the named boxes of the register diagram which
+    /// are not fixed by the encoding. Attributes may appear in any
+    /// order and be quoted with either `"` or `'`.
+    pub fn load_arm_xml(&mut self, contents: &str) -> Result<(), String> {
+        lazy_static! {
+            static ref ICLASS: Regex = Regex::new(r"(?s)<iclass\b[^>]*>(.*?)</iclass>").unwrap();
+            static ref REGDIAGRAM: Regex = Regex::new(r"(?s)<regdiagram\b([^>]*)>(.*?)</regdiagram>").unwrap();
+            static ref ENCODING: Regex = Regex::new(r"(?s)<encoding\b([^>]*)>(.*?)</encoding>").unwrap();
+            static ref BOX: Regex = Regex::new(r"(?s)<box\b([^>]*)>(.*?)</box>").unwrap();
+            static ref CELL: Regex = Regex::new(r"(?s)<c\b([^>/]*)(?:/>|>(.*?)</c>)").unwrap();
+            static ref ATTR: Regex = Regex::new(r#"([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+            static ref DOCVAR: Regex = Regex::new(r"<docvar\b([^>]*)>").unwrap();
+        }
+
+        fn attrs(s: &str) -> HashMap<&str, &str> {
+            ATTR.captures_iter(s)
+                .map(|cap| (cap.get(1).unwrap().as_str(), cap.get(2).or_else(|| cap.get(3)).unwrap().as_str()))
+                .collect()
+        }
+
+        // The value of the first mnemonic docvar, if there is one
+        fn mnemonic(s: &str) -> Option<&str> {
+            DOCVAR.captures_iter(s).find_map(|docvar| {
+                let docvar_attrs = attrs(docvar.get(1).unwrap().as_str());
+                if docvar_attrs.get("key") == Some(&"mnemonic") {
+                    docvar_attrs.get("value").copied()
+                } else {
+                    None
+                }
+            })
+        }
+
+        // Apply each box to the bits of the diagram, returning the
+        // named boxes with their high and low bits
+        fn apply_boxes<'a>(diagram: &'a str, fixed: &mut [Option<bool>]) -> Result<Vec<(&'a str, u32, u32)>, String> {
+            let mut named = Vec::new();
+            for bx in BOX.captures_iter(diagram) {
+                let box_attrs = attrs(bx.get(1).unwrap().as_str());
+                let Some(hi) = box_attrs.get("hibit").and_then(|hi| hi.parse::<u32>().ok()) else {
+                    return Err("Box without a valid hibit attribute".to_string());
+                };
+                let width = box_attrs.get("width").and_then(|w| w.parse::<u32>().ok()).unwrap_or(1);
+                if width == 0 || width > hi + 1 || hi as usize >= fixed.len() {
+                    return Err(format!("Box at bit {} with width {} does not fit", hi, width));
+                }
+                let mut bit = hi as i64;
+                for cell in CELL.captures_iter(bx.get(2).unwrap().as_str()) {
+                    let colspan = attrs(cell.get(1).unwrap().as_str())
+                        .get("colspan")
+                        .and_then(|n| n.parse::<i64>().ok())
+                        .unwrap_or(1);
+                    let value = match cell.get(2).map(|c| c.as_str().trim()) {
+                        Some("0") => Some(false),
+                        Some("1") => Some(true),
+                        _ => None,
+                    };
+                    for _ in 0..colspan {
+                        if bit >= 0 {
+                            fixed[bit as usize] = value
+                        }
+                        bit -= 1
+                    }
+                }
+                if let Some(name) = box_attrs.get("name") {
+                    named.push((*name, hi, hi + 1 - width))
+                }
+            }
+            Ok(named)
+        }
+
+        let default_mnemonic = mnemonic(contents);
+
+        for iclass in ICLASS.captures_iter(contents) {
+            let iclass = iclass.get(1).unwrap().as_str();
+            let Some(diagram) = REGDIAGRAM.captures(iclass) else {
+                continue;
+            };
+            let Some(form) = attrs(diagram.get(1).unwrap().as_str()).get("form").copied() else {
+                continue;
+            };
+            let width: u32 = form.parse().map_err(|_| format!("Invalid register diagram form {:?}", form))?;
+            if width == 0 || width > 64 {
+                return Err(format!("Unsupported instruction width {}", width));
+            }
+            let mut class_fixed = vec![None; width as usize];
+            let named = apply_boxes(diagram.get(2).unwrap().as_str(), &mut class_fixed)?;
+
+            for encoding in ENCODING.captures_iter(iclass) {
+                let encoding_attrs = attrs(encoding.get(1).unwrap().as_str());
+                let body = encoding.get(2).unwrap().as_str();
+                let mut fixed = class_fixed.clone();
+                apply_boxes(body, &mut fixed)?;
+
+                let name = mnemonic(body)
+                    .or(default_mnemonic)
+                    .or_else(|| encoding_attrs.get("name").copied())
+                    .unwrap_or("?")
+                    .to_string();
+
+                let (mut mask, mut bits) = (0, 0);
+                for (i, bit) in fixed.iter().enumerate() {
+                    if let Some(bit) = bit {
+                        mask |= 1 << i;
+                        bits |= u64::from(*bit) << i
+                    }
+                }
+                let fields = named
+                    .iter()
+                    .filter(|(_, hi, lo)| fixed[*lo as usize..=*hi as usize].iter().any(Option::is_none))
+                    .map(|(name, hi, lo)| (name.to_string(), *hi, *lo))
+                    .collect();
+                self.encodings.push(Encoding { name, width, mask, bits, fields })
+            }
+        }
+        Ok(())
+    }
+
+    /// Name an opcode using the matching encoding with the most fixed
+    /// bits, as more specific encodings (such as aliases with a fixed
+    /// operand) take priority.
+    pub fn decode<B: BV>(&self, opcode: B) -> Option<DecodedInstruction<'_>> {
+        if opcode.len() > 64 {
+            return None;
+        }
+        let value = opcode.lower_u64();
+        let encoding = self
+            .encodings
+            .iter()
+            .filter(|encoding| encoding.matches(value, opcode.len()))
+            .max_by_key(|encoding| encoding.mask.count_ones())?;
+        Some(DecodedInstruction {
+            name: &encoding.name,
+            fields: encoding
+                .fields
+                .iter()
+                .map(|(field, hi, lo)| (field.as_str(), (value & bit_range(*hi, *lo)) >> lo))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+
+    #[test]
+    fn riscv_opcodes() {
+        let mut names = InstructionNames::new();
+        names
+            .load_riscv_opcodes(
+                "# comment\n\
+                 add rd rs1 rs2 31..25=0 14..12=0 6..2=0x0C 1..0=3\n\
+                 $pseudo_op rv64_i::slli slli rd rs1 shamtd 31..26=0 14..12=1 6..2=0x04 1..0=3\n\
+                 c.mv rd c_rs2_n0 1..0=2 15..13=4 12=0\n",
+            )
+            .unwrap();
+        assert_eq!(names.len(), 2);
+        // add x1, x2, x3
+        let add = names.decode(B64::new(0x003100b3, 32)).unwrap();
+        assert_eq!(add.to_string(), "add rd=1 rs1=2 rs2=3");
+        // c.mv x8, x9
+        let mv = names.decode(B64::new(0x8426, 16)).unwrap();
+        assert_eq!(mv.to_string(), "c.mv rd=8 c_rs2_n0=9");
+        assert!(names.decode(B64::new(0x0001, 16)).is_none())
+    }
+
+    #[test]
+    fn arm_xml() {
+        let xml = r#"
+<instructionsection id="ADD_addsub_imm" title="ADD (immediate)" type="instruction">
+  <docs><docvars><docvar key="mnemonic" value="ADD" /></docvars></docs>
+  <classes>
+    <iclass name="Not setting the condition flags" oneof="1" id="iclass_add" no_encodings="2" isa="A64">
+      <regdiagram form="32" psname="ADD_32_addsub_imm" tworows="1">
+        <box hibit="31" name="sf" usename="1"><c></c></box>
+        <box hibit="30" name="op" settings="1"><c>0</c></box>
+        <box hibit="29" name="S" settings="1"><c>0</c></box>
+        <box hibit="28" width="6" settings="6"><c>1</c><c>0</c><c>0</c><c>0</c><c>1</c><c>0</c></box>
+        <box hibit="22" name="sh" usename="1"><c></c></box>
+        <box hibit="21" width="12" name="imm12" usename="1"><c colspan="12"></c></box>
+        <box hibit="9" width="5" name="Rn" usename="1"><c colspan="5"></c></box>
+        <box hibit="4" width="5" name="Rd" usename="1"><c colspan="5"></c></box>
+      </regdiagram>
+      <encoding name="ADD_32_addsub_imm" oneofinclass="2" oneof="2" label="32-bit">
+        <box hibit="31" width="1" name="sf"><c>0</c></box>
+      </encoding>
+      <encoding name="ADD_64_addsub_imm" oneofinclass="2" oneof="2" label="64-bit">
+        <box hibit="31" width="1" name="sf"><c>1</c></box>
+      </encoding>
+    </iclass>
+  </classes>
+</instructionsection>"#;
+        let mut names = InstructionNames::new();
+        names.load_arm_xml(xml).unwrap();
+        assert_eq!(names.len(), 2);
+        // add x1, x2, #4
+        let add = names.decode(B64::new(0x91001041, 32)).unwrap();
+        assert_eq!(add.to_string(), "ADD sh=0 imm12=4 Rn=2 Rd=1");
+        assert!(names.decode(B64::new(0xd503201f, 32)).is_none())
+    }
+
+    #[test]
+    fn arm_xml_attributes() {
+        // Attributes reordered and quoted with single quotes
+        let xml = r#"
+<instructionsection type='instruction' id='NOP'>
+  <docs><docvars><docvar value='NOP' key='mnemonic'/></docvars></docs>
+  <iclass isa="A64" name='System'>
+    <regdiagram psname='NOP_HI_hints' form = '32'>
+      <box width='8' hibit='31' settings='8'><c>1</c><c>1</c><c>0</c><c>1</c><c>0</c><c>1</c><c>0</c><c>1</c></box>
+      <box name="Rt" usename="1" width="5" hibit="4"><c colspan="5"></c></box>
+    </regdiagram>
+    <encoding label='' name='NOP_HI_hints'></encoding>
+  </iclass>
+</instructionsection>"#;
+        let mut names = InstructionNames::new();
+        names.load_arm_xml(xml).unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names.decode(B64::new(0xd503201f, 32)).unwrap().to_string(), "NOP Rt=31");
+
+        let bad_form = r#"<iclass><regdiagram form="wide"></regdiagram><encoding></encoding></iclass>"#;
+        assert!(InstructionNames::new().load_arm_xml(bad_form).is_err())
+    }
+}
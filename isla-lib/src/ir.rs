@@ -45,21 +45,27 @@ use ahash;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use crate::bitvector::{b64::B64, BV};
+use crate::config::RegisterField;
 use crate::error::ExecError;
 use crate::memory::Memory;
 use crate::primop::{self, Binary, Primops, Unary, Variadic};
-use crate::smt::{smtlib, EnumMember, Solver, Sym};
+use crate::primop_util::{mixed_bits_to_smt, smt_value};
+use crate::smt::{smtlib, EnumId, EnumMember, Solver, Sym};
 use crate::source_loc::SourceLoc;
 use crate::zencode;
 
+pub mod enum_index;
 pub mod linearize;
 pub mod partial_linearize;
 pub mod serialize;
+pub mod specialize;
 pub mod ssa;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
@@ -72,6 +78,10 @@ impl Name {
         Name { id }
     }
 
+    pub fn to_u32(self) -> u32 {
+        self.id
+    }
+
     pub fn to_smt<V>(self) -> smtlib::Exp<V> {
         smtlib::Exp::Bits64(B64::from_u32(self.id))
     }
@@ -140,7 +150,7 @@ pub enum Ty<A> {
 }
 
 /// A [Loc] is a location that can be assigned to.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Loc<A> {
     Id(A),
     Field(Box<Loc<A>>, A),
@@ -209,6 +219,47 @@ pub enum BitsSegment<B> {
     Concrete(B),
 }
 
+static DETERMINISTIC_HASHING: AtomicBool = AtomicBool::new(false);
+
+/// Use fixed keys for every [HashState] created from now on, so maps
+/// using it are iterated in the same order in every run. This should
+/// be called before any such maps are created.
+pub fn set_deterministic_hashing() {
+    DETERMINISTIC_HASHING.store(true, Ordering::Relaxed)
+}
+
+/// The hasher for the maps used when executing, such as struct
+/// values and [Bindings]. Like [ahash::RandomState] it is seeded
+/// randomly, unless [set_deterministic_hashing] has been called.
+#[derive(Clone)]
+pub struct HashState(ahash::RandomState);
+
+impl HashState {
+    /// Create a hasher with fixed keys, independently of
+    /// [set_deterministic_hashing].
+    pub fn with_seeds(k0: u64, k1: u64, k2: u64, k3: u64) -> Self {
+        HashState(ahash::RandomState::with_seeds(k0, k1, k2, k3))
+    }
+}
+
+impl Default for HashState {
+    fn default() -> Self {
+        if DETERMINISTIC_HASHING.load(Ordering::Relaxed) {
+            HashState::with_seeds(0, 0, 0, 0)
+        } else {
+            HashState(ahash::RandomState::new())
+        }
+    }
+}
+
+impl BuildHasher for HashState {
+    type Hasher = ahash::AHasher;
+
+    fn build_hasher(&self) -> ahash::AHasher {
+        self.0.build_hasher()
+    }
+}
+
 /// A value is either a symbolic value, represented as `Symbolic(n)`
 /// for where n is the identifier of the variable in the SMT solver,
 /// or one of the concrete values in this enum.
@@ -233,13 +284,68 @@ pub enum Val<B> {
     Vector(Vec<Val<B>>),
     List(Vec<Val<B>>),
     Enum(EnumMember),
-    Struct(HashMap<Name, Val<B>, ahash::RandomState>),
+    Struct(HashMap<Name, Val<B>, HashState>),
     Ctor(Name, Box<Val<B>>),
     SymbolicCtor(Sym, HashMap<Name, Val<B>>),
     Ref(Name),
     Poison,
 }
 
+/// How concrete bitvectors and integers are written by [Val::write_as]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValBitsFormat {
+    /// Bitvectors use `#x` if their length is a multiple of four,
+    /// otherwise `#b`, and integers are written in decimal as
+    /// `(_ bvN 64)`
+    Default,
+    /// As `Default`, but integers are also written with `#x`
+    Hex,
+    /// Bitvectors and integers are always written with `#b`
+    Bin,
+}
+
+impl ValBitsFormat {
+    fn write_bits<B: BV>(self, buf: &mut dyn Write, bv: B) -> std::io::Result<()> {
+        match self {
+            ValBitsFormat::Default | ValBitsFormat::Hex => write!(buf, "{}", bv),
+            ValBitsFormat::Bin => {
+                write!(buf, "#b")?;
+                for bit in bv.to_vec().iter().rev() {
+                    write!(buf, "{}", if *bit { '1' } else { '0' })?
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn smt_scalar<B: BV>(v: &Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<smtlib::Exp<Sym>, ExecError> {
+    match v {
+        Val::MixedBits(_) => mixed_bits_to_smt(v.clone(), solver, info),
+        _ => smt_value(v, info),
+    }
+}
+
+/// Conjoin a list of boolean expressions, simplifying away any
+/// concrete conjuncts.
+fn smt_conjunction(exps: Vec<smtlib::Exp<Sym>>) -> smtlib::Exp<Sym> {
+    use smtlib::Exp;
+    let mut result: Option<Exp<Sym>> = None;
+    for exp in exps {
+        match exp {
+            Exp::Bool(true) => (),
+            Exp::Bool(false) => return Exp::Bool(false),
+            exp => {
+                result = Some(match result {
+                    None => exp,
+                    Some(acc) => Exp::And(Box::new(acc), Box::new(exp)),
+                })
+            }
+        }
+    }
+    result.unwrap_or(Exp::Bool(true))
+}
+
 impl<B: BV> From<&BitsSegment<B>> for Val<B> {
     fn from(segment: &BitsSegment<B>) -> Self {
         match segment {
@@ -292,19 +398,33 @@ impl<B: BV> Val<B> {
     }
 
     pub fn write(&self, buf: &mut dyn Write, symtab: &Symtab) -> std::io::Result<()> {
+        self.write_as(buf, symtab, ValBitsFormat::Default)
+    }
+
+    /// Write a value, using the given format for any concrete
+    /// bitvectors and integers it contains
+    pub fn write_as(&self, buf: &mut dyn Write, symtab: &Symtab, format: ValBitsFormat) -> std::io::Result<()> {
         use Val::*;
         match self {
             Symbolic(v) => write!(buf, "v{}", v),
-            I64(n) => write!(buf, "(_ bv{} 64)", n),
-            I128(n) => write!(buf, "(_ bv{} 128)", n),
+            I64(n) => match format {
+                ValBitsFormat::Default => write!(buf, "(_ bv{} 64)", n),
+                ValBitsFormat::Hex => write!(buf, "#x{:016x}", n),
+                ValBitsFormat::Bin => write!(buf, "#b{:064b}", n),
+            },
+            I128(n) => match format {
+                ValBitsFormat::Default => write!(buf, "(_ bv{} 128)", n),
+                ValBitsFormat::Hex => write!(buf, "#x{:032x}", n),
+                ValBitsFormat::Bin => write!(buf, "#b{:0128b}", n),
+            },
             Bool(b) => write!(buf, "{}", b),
-            Bits(bv) => write!(buf, "{}", bv),
+            Bits(bv) => format.write_bits(buf, *bv),
             MixedBits(bs) => {
                 write!(buf, "(")?;
                 for (i, segment) in bs.iter().enumerate() {
                     match segment {
                         BitsSegment::Symbolic(v) => write!(buf, "v{}", v)?,
-                        BitsSegment::Concrete(b) => write!(buf, "{}", b)?,
+                        BitsSegment::Concrete(b) => format.write_bits(buf, *b)?,
                     };
                     if i < bs.len() - 1 {
                         write!(buf, " @ ")?
@@ -319,10 +439,10 @@ impl<B: BV> Val<B> {
                 write!(buf, "(_ list ")?;
                 if let Some((last, elems)) = vec.split_last() {
                     for elem in elems {
-                        elem.write(buf, symtab)?;
+                        elem.write_as(buf, symtab, format)?;
                         write!(buf, " ")?
                     }
-                    last.write(buf, symtab)?;
+                    last.write_as(buf, symtab, format)?;
                 } else {
                     write!(buf, "nil")?
                 }
@@ -332,10 +452,10 @@ impl<B: BV> Val<B> {
                 write!(buf, "(_ vec ")?;
                 if let Some((last, elems)) = vec.split_last() {
                     for elem in elems {
-                        elem.write(buf, symtab)?;
+                        elem.write_as(buf, symtab, format)?;
                         write!(buf, " ")?
                     }
-                    last.write(buf, symtab)?;
+                    last.write_as(buf, symtab, format)?;
                 } else {
                     write!(buf, "nil")?
                 }
@@ -348,7 +468,7 @@ impl<B: BV> Val<B> {
                 } else {
                     for (i, (k, v)) in fields.iter().enumerate() {
                         write!(buf, "(|{}| ", zencode::decode(symtab.to_str(*k)))?;
-                        v.write(buf, symtab)?;
+                        v.write_as(buf, symtab, format)?;
                         write!(buf, ")")?;
                         if i < fields.len() - 1 {
                             write!(buf, " ")?
@@ -359,7 +479,7 @@ impl<B: BV> Val<B> {
             }
             Ctor(ctor, v) => {
                 write!(buf, "(|{}| ", zencode::decode(symtab.to_str_demangled(*ctor)))?;
-                v.write(buf, symtab)?;
+                v.write_as(buf, symtab, format)?;
                 write!(buf, ")")
             }
             SymbolicCtor(v, possibilities) => {
@@ -369,7 +489,7 @@ impl<B: BV> Val<B> {
                 } else {
                     for (i, (k, v)) in possibilities.iter().enumerate() {
                         write!(buf, "(|{}| ", zencode::decode(symtab.to_str_demangled(*k)))?;
-                        v.write(buf, symtab)?;
+                        v.write_as(buf, symtab, format)?;
                         write!(buf, ")")?;
                         if i < possibilities.len() - 1 {
                             write!(buf, " ")?
@@ -389,6 +509,270 @@ impl<B: BV> Val<B> {
         String::from_utf8(buf).unwrap()
     }
 
+    /// Convert a value into JSON. Struct fields, constructors,
+    /// registers, and enumeration members are rendered using their
+    /// (demangled) names from the symbol table. Symbolic values are
+    /// rendered as `{"symbolic": n}` references to SMT variable `vn`.
+    /// Bitvectors are rendered as strings in the same SMTLIB format as
+    /// [Val::write], and 128-bit integers are rendered as decimal
+    /// strings to avoid losing precision.
+    ///
+    /// An enumeration value only records its position and the size
+    /// of its enumeration, so when several enumerations have the same
+    /// size the member is named from the enumeration whose member was
+    /// interned first. Any such name is read back as the same value.
+    pub fn to_json(&self, shared_state: &SharedState<B>) -> serde_json::Value {
+        use serde_json::{json, Map, Value};
+        use Val::*;
+        let symtab = &shared_state.symtab;
+        let name = |n: &Name| zencode::decode(symtab.to_str_demangled(*n));
+        match self {
+            Symbolic(v) => json!({ "symbolic": v.to_u32() }),
+            I64(n) => json!({ "i64": n }),
+            I128(n) => json!({ "i128": n.to_string() }),
+            Bool(b) => Value::Bool(*b),
+            Bits(bv) => json!({ "bits": bv.to_string() }),
+            MixedBits(segments) => {
+                let segments: Vec<_> = segments
+                    .iter()
+                    .map(|segment| match segment {
+                        BitsSegment::Symbolic(v) => json!({ "symbolic": v.to_u32() }),
+                        BitsSegment::Concrete(bv) => json!({ "bits": bv.to_string() }),
+                    })
+                    .collect();
+                json!({ "mixed_bits": segments })
+            }
+            String(s) => Value::String(s.clone()),
+            Unit => Value::Null,
+            Vector(vals) => json!({ "vector": vals.iter().map(|v| v.to_json(shared_state)).collect::<Vec<_>>() }),
+            List(vals) => json!({ "list": vals.iter().map(|v| v.to_json(shared_state)).collect::<Vec<_>>() }),
+            Enum(EnumMember { enum_id, member }) => {
+                match shared_state
+                    .enum_members
+                    .iter()
+                    .filter(|(_, (pos, size))| *pos == *member && *size == enum_id.to_usize())
+                    .map(|(member_name, _)| *member_name)
+                    .min()
+                {
+                    Some(member_name) => json!({ "enum": name(&member_name) }),
+                    // Enumerations created by the solver rather than
+                    // defined in the IR have no member names
+                    None => json!({ "enum": enum_id.to_usize(), "member": member }),
+                }
+            }
+            Struct(fields) => {
+                let fields: Map<_, _> = fields.iter().map(|(k, v)| (name(k), v.to_json(shared_state))).collect();
+                json!({ "struct": fields })
+            }
+            Ctor(ctor, v) => json!({ "ctor": name(ctor), "value": v.to_json(shared_state) }),
+            SymbolicCtor(v, possibilities) => {
+                let possibilities: Map<_, _> =
+                    possibilities.iter().map(|(k, v)| (name(k), v.to_json(shared_state))).collect();
+                json!({ "symbolic_ctor": v.to_u32(), "possibilities": possibilities })
+            }
+            Ref(reg) => json!({ "register": name(reg) }),
+            Poison => json!({ "poison": true }),
+        }
+    }
+
+    /// The inverse of [Val::to_json]. Fails if the JSON is not in
+    /// the format produced by [Val::to_json], or if it refers to
+    /// names which are not in the symbol table.
+    pub fn from_json(json: &serde_json::Value, shared_state: &SharedState<B>) -> Result<Self, std::string::String> {
+        use serde_json::Value;
+
+        let symtab = &shared_state.symtab;
+        let lookup = |s: &str| {
+            let encoded = zencode::encode(s);
+            symtab
+                .get(&encoded)
+                .or_else(|| {
+                    symtab.mangled_names.iter().find(|(_, original)| **original == encoded).map(|(mangled, _)| *mangled)
+                })
+                .ok_or_else(|| format!("Unknown name {} in JSON value", s))
+        };
+        let sym = |v: &Value| {
+            v.as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .map(Sym::from_u32)
+                .ok_or_else(|| format!("Invalid symbolic variable {} in JSON value", v))
+        };
+        let bits = |v: &Value| {
+            v.as_str().and_then(B::from_str).ok_or_else(|| format!("Invalid bitvector {} in JSON value", v))
+        };
+        let vals = |v: &Value| match v.as_array() {
+            Some(vals) => vals.iter().map(|v| Val::from_json(v, shared_state)).collect::<Result<Vec<_>, _>>(),
+            None => Err(format!("Expected array, found {} in JSON value", v)),
+        };
+        let named_vals = |v: &Value| match v.as_object() {
+            Some(obj) => obj
+                .iter()
+                .map(|(k, v)| Ok((lookup(k)?, Val::from_json(v, shared_state)?)))
+                .collect::<Result<Vec<_>, std::string::String>>(),
+            None => Err(format!("Expected object, found {} in JSON value", v)),
+        };
+
+        match json {
+            Value::Null => Ok(Val::Unit),
+            Value::Bool(b) => Ok(Val::Bool(*b)),
+            Value::String(s) => Ok(Val::String(s.clone())),
+            Value::Object(obj) => {
+                if let Some(v) = obj.get("symbolic") {
+                    Ok(Val::Symbolic(sym(v)?))
+                } else if let Some(n) = obj.get("i64") {
+                    n.as_i64().map(Val::I64).ok_or_else(|| format!("Invalid i64 {} in JSON value", n))
+                } else if let Some(n) = obj.get("i128") {
+                    n.as_str()
+                        .and_then(|n| n.parse::<i128>().ok())
+                        .map(Val::I128)
+                        .ok_or_else(|| format!("Invalid i128 {} in JSON value", n))
+                } else if let Some(bv) = obj.get("bits") {
+                    Ok(Val::Bits(bits(bv)?))
+                } else if let Some(segments) = obj.get("mixed_bits") {
+                    let segments = match segments.as_array() {
+                        Some(segments) => segments
+                            .iter()
+                            .map(|segment| match (segment.get("symbolic"), segment.get("bits")) {
+                                (Some(v), _) => Ok(BitsSegment::Symbolic(sym(v)?)),
+                                (_, Some(bv)) => Ok(BitsSegment::Concrete(bits(bv)?)),
+                                _ => Err(format!("Invalid bitvector segment {} in JSON value", segment)),
+                            })
+                            .collect::<Result<_, _>>()?,
+                        None => return Err(format!("Expected array, found {} in JSON value", segments)),
+                    };
+                    Ok(Val::MixedBits(segments))
+                } else if let Some(v) = obj.get("vector") {
+                    Ok(Val::Vector(vals(v)?))
+                } else if let Some(v) = obj.get("list") {
+                    Ok(Val::List(vals(v)?))
+                } else if let Some(Value::String(member)) = obj.get("enum") {
+                    match shared_state.enum_members.get(&lookup(member)?) {
+                        Some((pos, size)) => {
+                            Ok(Val::Enum(EnumMember { enum_id: EnumId::from_usize(*size), member: *pos }))
+                        }
+                        None => Err(format!("{} is not an enumeration member in JSON value", member)),
+                    }
+                } else if let (Some(enum_id), Some(member)) = (obj.get("enum"), obj.get("member")) {
+                    match (enum_id.as_u64(), member.as_u64()) {
+                        (Some(enum_id), Some(member)) => Ok(Val::Enum(EnumMember {
+                            enum_id: EnumId::from_usize(enum_id as usize),
+                            member: member as usize,
+                        })),
+                        _ => Err(format!("Invalid enum member {} in JSON value", json)),
+                    }
+                } else if let Some(fields) = obj.get("struct") {
+                    Ok(Val::Struct(named_vals(fields)?.into_iter().collect()))
+                } else if let (Some(ctor), Some(v)) = (obj.get("ctor"), obj.get("value")) {
+                    let ctor = ctor.as_str().ok_or_else(|| format!("Invalid constructor {} in JSON value", ctor))?;
+                    Ok(Val::Ctor(lookup(ctor)?, Box::new(Val::from_json(v, shared_state)?)))
+                } else if let (Some(v), Some(possibilities)) = (obj.get("symbolic_ctor"), obj.get("possibilities")) {
+                    Ok(Val::SymbolicCtor(sym(v)?, named_vals(possibilities)?.into_iter().collect()))
+                } else if let Some(reg) = obj.get("register") {
+                    let reg = reg.as_str().ok_or_else(|| format!("Invalid register {} in JSON value", reg))?;
+                    Ok(Val::Ref(lookup(reg)?))
+                } else if obj.contains_key("poison") {
+                    Ok(Val::Poison)
+                } else {
+                    Err(format!("Unrecognised JSON value {}", json))
+                }
+            }
+            _ => Err(format!("Unrecognised JSON value {}", json)),
+        }
+    }
+
+    /// Structural equality between two values, which may be any mix
+    /// of concrete and symbolic. Returns `Exp::Bool` when the result
+    /// can be decided without the solver, otherwise an SMT
+    /// expression which is true exactly when the values are
+    /// equal. Unlike the derived `PartialEq`, comparing bitvectors of
+    /// different lengths is a type error rather than inequality.
+    pub fn eq_smt(
+        &self,
+        other: &Val<B>,
+        solver: &mut Solver<B>,
+        info: SourceLoc,
+    ) -> Result<smtlib::Exp<Sym>, ExecError> {
+        use smtlib::Exp;
+        use Val::*;
+        match (self, other) {
+            (MixedBits(_), _) => {
+                let lhs = mixed_bits_to_smt(self.clone(), solver, info)?;
+                Ok(Exp::Eq(Box::new(lhs), Box::new(smt_scalar(other, solver, info)?)))
+            }
+            (_, MixedBits(_)) => {
+                let rhs = mixed_bits_to_smt(other.clone(), solver, info)?;
+                Ok(Exp::Eq(Box::new(smt_scalar(self, solver, info)?), Box::new(rhs)))
+            }
+            (Symbolic(_), _) | (_, Symbolic(_)) => {
+                Ok(Exp::Eq(Box::new(smt_value(self, info)?), Box::new(smt_value(other, info)?)))
+            }
+
+            (Bits(lhs), Bits(rhs)) if lhs.len() == rhs.len() => Ok(Exp::Bool(lhs == rhs)),
+            (I64(lhs), I64(rhs)) => Ok(Exp::Bool(lhs == rhs)),
+            (I128(lhs), I128(rhs)) => Ok(Exp::Bool(lhs == rhs)),
+            (I64(lhs), I128(rhs)) | (I128(rhs), I64(lhs)) => Ok(Exp::Bool(*lhs as i128 == *rhs)),
+            (Bool(lhs), Bool(rhs)) => Ok(Exp::Bool(lhs == rhs)),
+            (Enum(lhs), Enum(rhs)) => Ok(Exp::Bool(lhs == rhs)),
+            (String(lhs), String(rhs)) => Ok(Exp::Bool(lhs == rhs)),
+            (Unit, Unit) => Ok(Exp::Bool(true)),
+
+            (Vector(lhs), Vector(rhs)) | (List(lhs), List(rhs)) => {
+                if lhs.len() != rhs.len() {
+                    return Ok(Exp::Bool(false));
+                }
+                let mut conjuncts = Vec::with_capacity(lhs.len());
+                for (l, r) in lhs.iter().zip(rhs.iter()) {
+                    conjuncts.push(l.eq_smt(r, solver, info)?)
+                }
+                Ok(smt_conjunction(conjuncts))
+            }
+            (Struct(lhs), Struct(rhs)) => {
+                if lhs.len() != rhs.len() {
+                    return Err(ExecError::Type(format!("eq_smt {:?} {:?}", self, other), info));
+                }
+                let mut conjuncts = Vec::with_capacity(lhs.len());
+                for (field, l) in lhs {
+                    let r = rhs
+                        .get(field)
+                        .ok_or_else(|| ExecError::Type(format!("eq_smt {:?} {:?}", self, other), info))?;
+                    conjuncts.push(l.eq_smt(r, solver, info)?)
+                }
+                Ok(smt_conjunction(conjuncts))
+            }
+            (Ctor(lhs_name, lhs), Ctor(rhs_name, rhs)) => {
+                if lhs_name == rhs_name {
+                    lhs.eq_smt(rhs, solver, info)
+                } else {
+                    Ok(Exp::Bool(false))
+                }
+            }
+            (Ctor(ctor, payload), SymbolicCtor(tag, possibilities))
+            | (SymbolicCtor(tag, possibilities), Ctor(ctor, payload)) => match possibilities.get(ctor) {
+                Some(possible) => Ok(smt_conjunction(vec![
+                    Exp::Eq(Box::new(Exp::Var(*tag)), Box::new(ctor.to_smt())),
+                    payload.eq_smt(possible, solver, info)?,
+                ])),
+                None => Ok(Exp::Bool(false)),
+            },
+            (SymbolicCtor(lhs_tag, lhs), SymbolicCtor(rhs_tag, rhs)) => {
+                let mut conjuncts = vec![Exp::Eq(Box::new(Exp::Var(*lhs_tag)), Box::new(Exp::Var(*rhs_tag)))];
+                for (ctor, l) in lhs {
+                    if let Some(r) = rhs.get(ctor) {
+                        let is_ctor = Exp::Eq(Box::new(Exp::Var(*lhs_tag)), Box::new(ctor.to_smt()));
+                        match l.eq_smt(r, solver, info)? {
+                            Exp::Bool(true) => (),
+                            Exp::Bool(false) => conjuncts.push(Exp::Not(Box::new(is_ctor))),
+                            eq => conjuncts.push(Exp::Or(Box::new(Exp::Not(Box::new(is_ctor))), Box::new(eq))),
+                        }
+                    }
+                }
+                Ok(smt_conjunction(conjuncts))
+            }
+
+            (_, _) => Err(ExecError::Type(format!("eq_smt {:?} {:?}", self, other), info)),
+        }
+    }
+
     /// Just enough of a type check to pick up bad default registers
     pub fn plausible<N: std::fmt::Debug>(&self, ty: &Ty<N>, symtab: &Symtab) -> Result<(), String> {
         match (self, ty) {
@@ -413,6 +797,7 @@ impl<B: BV> Val<B> {
             (Val::Struct(_), Ty::Struct(_)) => Ok(()), // TODO: element type
             (Val::Ctor(_, _), _) => Ok(()),            // TODO
             (Val::Ref(_), _) => Ok(()),                // TODO
+            (Val::SymbolicCtor(_, _), Ty::Union(_)) => Ok(()),
             (Val::Poison, _) => Ok(()),
             (_, _) => Err(format!("value {} doesn't appear to match type {:?}", self.to_string(symtab), ty)),
         }
@@ -433,7 +818,7 @@ pub enum URVal<B> {
 }
 
 /// A map from identifers to potentially uninitialized values.
-pub type Bindings<'ir, B> = HashMap<Name, UVal<'ir, B>, ahash::RandomState>;
+pub type Bindings<'ir, B> = HashMap<Name, UVal<'ir, B>, HashState>;
 
 /// A reference to either the declaration of a variable or a usage
 /// location.
@@ -723,6 +1108,10 @@ pub const READ_REGISTER_FROM_VECTOR: Name = Name { id: 20 };
 /// Primitive to write a register from a vector of register references
 pub const WRITE_REGISTER_FROM_VECTOR: Name = Name { id: 21 };
 
+/// Accesses to device (MMIO) memory regions are recorded as abstract
+/// events with this name
+pub const DEVICE_ACCESS: Name = Name { id: 22 };
+
 static GENSYM: &str = "zzUGENSYMzU";
 
 impl<'ir> Symtab<'ir> {
@@ -816,6 +1205,7 @@ impl<'ir> Symtab<'ir> {
         symtab.intern("zzUprimitivezU");
         symtab.intern("zread_register_from_vector");
         symtab.intern("zwrite_register_from_vector");
+        symtab.intern("zzUdevicezU");
         symtab
     }
 
@@ -886,6 +1276,19 @@ type ExternDecl<'ir> = (&'ir [Ty<Name>], &'ir Ty<Name>, &'ir str);
 pub type Reset<B> =
     Arc<dyn 'static + Send + Sync + Fn(&Memory<B>, Typedefs, &mut Solver<B>) -> Result<Val<B>, ExecError>>;
 
+/// Bounds the unrolling of a recursive function, see
+/// [SharedState::widening].
+#[derive(Clone, Debug)]
+pub struct Widening {
+    /// The number of nested calls to the function to execute before
+    /// summarizing the rest of the recursion
+    pub unroll: usize,
+    /// The frame condition for the summary: the only registers the
+    /// summarized calls may write. Any other state is assumed to be
+    /// unchanged.
+    pub modifies: Vec<Name>,
+}
+
 /// All symbolic evaluation happens over some (immutable) IR. The
 /// [SharedState] provides each worker that is performing symbolic
 /// evaluation with a convenient view into that IR.
@@ -917,6 +1320,18 @@ pub struct SharedState<'ir, B> {
     /// `trace_functions` defines a set of functions which we include
     /// in the traces as function call and return events
     pub trace_functions: HashSet<Name>,
+    /// `untraced_functions` is a set of functions within which
+    /// register reads and writes are not recorded in the trace
+    pub untraced_functions: HashSet<Name>,
+    /// `abstract_primitives` maps functions declared abstract in the
+    /// ISA config to their arity and return type. Calls to these
+    /// produce abstract events rather than being executed.
+    pub abstract_primitives: HashMap<Name, (usize, Ty<Name>)>,
+    /// `widening` bounds the recursion depth of functions in the ISA
+    /// config. Once a function has been entered `unroll` times on a
+    /// path, the next call returns a fresh symbolic value and havocs
+    /// the registers in `modifies` rather than being executed.
+    pub widening: HashMap<Name, Widening>,
     /// `reset_registers` are reset values for each register
     /// derived from the ISA config
     pub reset_registers: Vec<(Loc<Name>, Reset<B>)>,
@@ -927,6 +1342,10 @@ pub struct SharedState<'ir, B> {
     /// given arguments has the given result, skipping execution
     /// derived from the ISA config
     pub function_assumptions: Vec<(String, Vec<smtlib::Exp<Loc<String>>>, smtlib::Exp<Loc<String>>)>,
+    /// `register_fields` gives the bit layout of packed registers,
+    /// allowing their fields to be read and written as slices of the
+    /// underlying bitvector
+    pub register_fields: HashMap<Name, Vec<RegisterField>>,
 }
 
 #[derive(Copy, Clone)]
@@ -1015,12 +1434,39 @@ impl<'ir, B: BV> SharedState<'ir, B> {
             registers,
             probes,
             trace_functions,
+            untraced_functions: HashSet::new(),
+            abstract_primitives: HashMap::new(),
+            widening: HashMap::new(),
             reset_registers,
             reset_constraints,
             function_assumptions,
+            register_fields: HashMap::new(),
         }
     }
 
+    /// Set the functions within which register reads and writes are
+    /// not recorded in the trace. None by default.
+    pub fn set_untraced_functions(&mut self, untraced_functions: HashSet<Name>) {
+        self.untraced_functions = untraced_functions
+    }
+
+    /// Set the functions which produce abstract events rather than
+    /// being executed. None by default.
+    pub fn set_abstract_primitives(&mut self, abstract_primitives: HashMap<Name, (usize, Ty<Name>)>) {
+        self.abstract_primitives = abstract_primitives
+    }
+
+    /// Set the recursion bounds for functions. By default functions
+    /// are unrolled without any bound.
+    pub fn set_widening(&mut self, widening: HashMap<Name, Widening>) {
+        self.widening = widening
+    }
+
+    /// Set the field layouts of packed registers. None by default.
+    pub fn set_register_fields(&mut self, register_fields: HashMap<Name, Vec<RegisterField>>) {
+        self.register_fields = register_fields
+    }
+
     pub fn typedefs(&self) -> Typedefs {
         Typedefs { structs: &self.structs, enums: &self.enums, unions: &self.unions }
     }
@@ -1063,12 +1509,15 @@ fn insert_instr_primops<B: BV>(
                     Instr::Call(loc.clone(), false, READ_REGISTER_FROM_VECTOR, args.clone(), *info)
                 } else if name == "write_register_from_vector" {
                     Instr::Call(loc.clone(), false, WRITE_REGISTER_FROM_VECTOR, args.clone(), *info)
+                } else if primops.unimplemented.contains(name) {
+                    Instr::Call(loc.clone(), false, *f, args.clone(), *info)
                 } else {
                     // Currently we just warn when we don't have a
                     // primop. As long as we never actually try to
-                    // call it things will be fine. This happens for
-                    // softfloat based floating point in RISC-V right
-                    // now.
+                    // call it things will be fine, otherwise the path
+                    // fails with ExecError::UnimplementedPrimop. This
+                    // happens for softfloat based floating point in
+                    // RISC-V right now.
                     eprintln!("No primop {} ({:?})", name, f);
                     Instr::Call(loc.clone(), false, *f, args.clone(), *info)
                 }
@@ -1079,19 +1528,35 @@ fn insert_instr_primops<B: BV>(
     }
 }
 
-/// There are two ways to handle assertions in the Sail code, the
-/// first being to assume that they succeed (essentially treating them
-/// like assumptions in the SMT) - this is the optimistic mode. The
-/// other way is to assume that they might fail, and check each
+/// There are two main ways to handle assertions in the Sail code,
+/// the first being to assume that they succeed (essentially treating
+/// them like assumptions in the SMT) - this is the optimistic mode.
+/// The other way is to assume that they might fail, and check each
 /// assertion to ensure that it can never fail - this is the
-/// pessimistic mode.
+/// pessimistic mode. Assertions can also be ignored entirely, which
+/// neither constrains the path nor reports any violation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum AssertionMode {
     Pessimistic,
     Optimistic,
+    Ignore,
+}
+
+impl FromStr for AssertionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "assume" | "optimistic" => Ok(AssertionMode::Optimistic),
+            "check" | "pessimistic" => Ok(AssertionMode::Pessimistic),
+            "ignore" => Ok(AssertionMode::Ignore),
+            _ => Err(format!("Unknown assertion mode {} (expected assume, check, or ignore)", s)),
+        }
+    }
 }
 
 /// Change Calls without implementations into Primops
-pub(crate) fn insert_primops<B: BV>(defs: &mut [Def<Name, B>], mode: AssertionMode) {
+pub(crate) fn insert_primops<B: BV>(defs: &mut [Def<Name, B>], mode: AssertionMode, primops: &Primops<B>) {
     let mut externs: HashMap<Name, (String, bool)> = HashMap::new();
     for def in defs.iter() {
         if let Def::Extern(f, is_abstract, ext, _, _) = def {
@@ -1106,25 +1571,26 @@ pub(crate) fn insert_primops<B: BV>(defs: &mut [Def<Name, B>], mode: AssertionMo
         AssertionMode::Pessimistic => {
             externs.insert(SAIL_ASSERT, ("pessimistic_assert".to_string(), false));
         }
+        AssertionMode::Ignore => {
+            externs.insert(SAIL_ASSERT, ("ignore_assert".to_string(), false));
+        }
     };
     externs.insert(SAIL_ASSUME, ("assume".to_string(), false));
     externs.insert(BITVECTOR_UPDATE, ("bitvector_update".to_string(), false));
 
-    let primops = Primops::default();
-
     for def in defs.iter_mut() {
         match def {
             Def::Fn(f, args, body) => {
                 *def = Def::Fn(
                     *f,
                     args.to_vec(),
-                    body.iter().cloned().map(|instr| insert_instr_primops(instr, &externs, &primops)).collect(),
+                    body.iter().cloned().map(|instr| insert_instr_primops(instr, &externs, primops)).collect(),
                 )
             }
             Def::Let(bindings, setup) => {
                 *def = Def::Let(
                     bindings.clone(),
-                    setup.iter().cloned().map(|instr| insert_instr_primops(instr, &externs, &primops)).collect(),
+                    setup.iter().cloned().map(|instr| insert_instr_primops(instr, &externs, primops)).collect(),
                 )
             }
             _ => (),
@@ -1368,6 +1834,39 @@ pub fn abstract_function<B: BV>(defs: &mut [Def<Name, B>], target_function: Name
     }
 }
 
+/// Leave each of the given functions uninterpreted. Calls to Sail
+/// functions become abstract calls as in [abstract_function], while
+/// externs are marked abstract so no primop is needed for them. In
+/// both cases a call returns an arbitrary value of the declared
+/// return type and is recorded as an abstract event.
+pub fn uninterpret_functions<B: BV>(defs: &mut [Def<Name, B>], functions: &HashSet<Name>) {
+    let externs: HashSet<Name> = defs
+        .iter()
+        .filter_map(|def| match def {
+            Def::Extern(f, _, _, _, _) => Some(*f),
+            _ => None,
+        })
+        .collect();
+
+    for def in defs.iter_mut() {
+        match def {
+            Def::Extern(f, is_abstract, _, _, _) if functions.contains(f) => *is_abstract = true,
+            Def::Let(_, instrs) | Def::Fn(_, _, instrs) => {
+                for instr in instrs.iter_mut() {
+                    match instr {
+                        Instr::Call(_, _, f, args, _) if functions.contains(f) && !externs.contains(f) => {
+                            args.push(Exp::Ref(*f));
+                            *f = ABSTRACT_CALL
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
 fn has_call<B: BV>(instrs: &[Instr<Name, B>], target_function: Name) -> bool {
     for instr in instrs {
         match instr {
@@ -1460,3 +1959,89 @@ pub fn abstract_function_with_property<B: BV>(
 
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut symtab = Symtab::new();
+        let field1 = symtab.intern("zfield1");
+        let field2 = symtab.intern("zfield2");
+        let ctor = symtab.intern("zSome_mangled");
+        symtab.mangled_names.insert(ctor, "zSome");
+        let e = symtab.intern("zE");
+        let members = vec![symtab.intern("zA"), symtab.intern("zB"), symtab.intern("zC")];
+        let defs: Vec<Def<Name, B64>> = vec![Def::Enum(e, members)];
+        let shared_state =
+            SharedState::new(symtab, &defs, HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let mut fields = HashMap::default();
+        fields.insert(field1, Val::Bits(B64::new(0xABC, 12)));
+        fields.insert(
+            field2,
+            Val::MixedBits(vec![BitsSegment::Symbolic(Sym::from_u32(3)), BitsSegment::Concrete(B64::new(0b1, 1))]),
+        );
+        let v: Val<B64> = Val::Vector(vec![
+            Val::Struct(fields),
+            Val::Ctor(ctor, Box::new(Val::I128(i128::MAX))),
+            Val::Enum(EnumMember { enum_id: EnumId::from_usize(3), member: 1 }),
+            Val::Enum(EnumMember { enum_id: EnumId::from_usize(2), member: 1 }),
+            Val::List(vec![Val::Unit, Val::Bool(true), Val::I64(-1), Val::String("str".to_string())]),
+        ]);
+
+        let json = v.to_json(&shared_state);
+        assert_eq!(json["vector"][0]["struct"]["field1"]["bits"], "#xabc");
+        assert_eq!(json["vector"][1]["ctor"], "Some");
+        assert_eq!(json["vector"][2]["enum"], "B");
+        // No enumeration with two members is defined, so there is no
+        // name for the member
+        assert_eq!(json["vector"][3]["member"], 1);
+        assert_eq!(Val::from_json(&json, &shared_state), Ok(v));
+
+        assert!(Val::<B64>::from_json(&serde_json::json!({ "enum": "E" }), &shared_state).is_err())
+    }
+
+    #[test]
+    fn uninterpret_calls() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("zf");
+        let g = symtab.intern("zg");
+        let prim = symtab.intern("zprim");
+        let x = symtab.intern("zx");
+        let info = SourceLoc::unknown();
+
+        let call = |f| Instr::Call(Loc::Id(x), false, f, vec![Exp::Bool(true)], info);
+        let mut defs: Vec<Def<Name, B64>> = vec![
+            Def::Extern(prim, false, "prim".to_string(), vec![Ty::Bool], Ty::Bits(8)),
+            Def::Fn(g, vec![], vec![call(f), call(g), call(prim)]),
+        ];
+        uninterpret_functions(&mut defs, &[f, prim].into_iter().collect());
+
+        assert!(matches!(defs[0], Def::Extern(_, true, _, _, _)));
+        let Def::Fn(_, _, instrs) = &defs[1] else {
+            panic!("expected a function")
+        };
+        let Instr::Call(_, _, h, args, _) = &instrs[0] else {
+            panic!("expected a call")
+        };
+        assert_eq!(*h, ABSTRACT_CALL);
+        assert!(matches!(args.as_slice(), [Exp::Bool(true), Exp::Ref(r)] if *r == f));
+        assert!(matches!(&instrs[1], Instr::Call(_, _, h, args, _) if *h == g && args.len() == 1));
+        assert!(matches!(&instrs[2], Instr::Call(_, _, h, _, _) if *h == prim))
+    }
+
+    #[test]
+    fn deterministic_hashing() {
+        let fields = || -> HashMap<Name, u32, HashState> {
+            let mut fields = HashMap::with_hasher(HashState::with_seeds(0, 0, 0, 0));
+            fields.extend((0..64).map(|i| (Name::from_u32(i), i)));
+            fields
+        };
+        let order: Vec<u32> = fields().into_values().collect();
+        for _ in 0..8 {
+            assert_eq!(fields().into_values().collect::<Vec<_>>(), order)
+        }
+    }
+}
@@ -0,0 +1,465 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2025 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Sail generates a `num_of_<enum>` function for every enum, which
+//! models often use to index fixed vectors (such as register banks)
+//! by an enum value. This module propagates enum values that are
+//! known within a function into calls to these conversion functions,
+//! replacing each call with the integer it would return. The
+//! resulting constant is then propagated to its uses, so vector
+//! accesses and updates get a concrete index rather than going
+//! through a function call that may produce a symbolic one.
+//!
+//! Where the indexed vector is a top-level let binding of register
+//! references (a register bank), accesses with a constant index are
+//! then lowered to direct reads and writes of the register itself,
+//! avoiding the `read_register_from_vector` and
+//! `write_register_from_vector` primitives entirely.
+
+use std::collections::{HashMap, HashSet};
+
+use super::specialize::{collect_refs, instr_exps_mut, substitute};
+use super::*;
+use crate::bitvector::BV;
+use crate::zencode;
+
+/// Statistics about the changes made by [fold_enum_indices]
+#[derive(Clone, Debug, Default)]
+pub struct EnumIndexStats {
+    /// The number of calls to enum conversion functions replaced by
+    /// a constant
+    pub calls_folded: usize,
+    /// The number of local variables replaced by the constant they
+    /// are assigned
+    pub variables_propagated: usize,
+    /// The number of register bank accesses lowered to a direct
+    /// register read or write
+    pub accesses_lowered: usize,
+}
+
+struct Conversion {
+    positions: HashMap<Name, usize>,
+    ret_ty: Ty<Name>,
+}
+
+impl Conversion {
+    fn index(&self, member: Name) -> Option<Exp<Name>> {
+        let n = *self.positions.get(&member)?;
+        match self.ret_ty {
+            Ty::I64 => Some(Exp::I64(n as i64)),
+            Ty::I128 => Some(Exp::I128(n as i128)),
+            _ => None,
+        }
+    }
+}
+
+/// Find the `num_of_<enum>` functions generated by Sail
+fn enum_conversions<B>(defs: &[Def<Name, B>], symtab: &Symtab) -> HashMap<Name, Conversion> {
+    let enums: HashMap<Name, &[Name]> = defs
+        .iter()
+        .filter_map(|def| match def {
+            Def::Enum(id, members) => Some((*id, members.as_slice())),
+            _ => None,
+        })
+        .collect();
+
+    let mut conversions = HashMap::new();
+    for def in defs {
+        let Def::Val(f, arg_tys, ret_ty) = def else { continue };
+        let [Ty::Enum(id)] = arg_tys.as_slice() else { continue };
+        let Some(members) = enums.get(id) else { continue };
+        if zencode::decode(symtab.to_str(*f)) == format!("num_of_{}", zencode::decode(symtab.to_str(*id))) {
+            let positions = members.iter().enumerate().map(|(n, member)| (*member, n)).collect();
+            conversions.insert(*f, Conversion { positions, ret_ty: ret_ty.clone() });
+        }
+    }
+    conversions
+}
+
+fn is_constant(exp: &Exp<Name>, members: &HashSet<Name>) -> bool {
+    match exp {
+        Exp::Id(id) => members.contains(id),
+        Exp::I64(_) | Exp::I128(_) => true,
+        _ => false,
+    }
+}
+
+/// Find the local variables in a function body which are assigned
+/// exactly once, with either an enum member or an integer constant.
+fn constant_locals<B>(args: &[Name], body: &mut [Instr<Name, B>], members: &HashSet<Name>) -> HashMap<Name, Exp<Name>> {
+    let mut assignments: HashMap<Name, usize> = HashMap::new();
+    let mut refs = HashSet::new();
+    let mut candidates = HashMap::new();
+
+    for instr in body.iter_mut() {
+        match instr {
+            Instr::Init(id, _, exp, _) | Instr::Copy(Loc::Id(id), exp, _) => {
+                *assignments.entry(*id).or_insert(0) += 1;
+                if is_constant(exp, members) {
+                    candidates.insert(*id, exp.clone());
+                }
+            }
+            Instr::Copy(loc, _, _)
+            | Instr::Call(loc, _, _, _, _)
+            | Instr::PrimopUnary(loc, _, _, _)
+            | Instr::PrimopBinary(loc, _, _, _, _)
+            | Instr::PrimopVariadic(loc, _, _, _) => *assignments.entry(loc.id()).or_insert(0) += 1,
+            _ => (),
+        }
+        for exp in instr_exps_mut(instr) {
+            collect_refs(exp, &mut refs)
+        }
+    }
+
+    candidates.retain(|id, _| assignments.get(id) == Some(&1) && !refs.contains(id) && !args.contains(id));
+    candidates
+}
+
+/// Find the top-level let bindings which are vectors of register
+/// references, returning the register at each index. Only straight
+/// line initialisation code built from `internal_vector_init` and
+/// `internal_vector_update` is understood.
+fn register_banks<B>(defs: &[Def<Name, B>]) -> HashMap<Name, Vec<Name>> {
+    let mut banks = HashMap::new();
+
+    for def in defs {
+        let Def::Let(bindings, setup) = def else { continue };
+        if setup.iter().any(|instr| matches!(instr, Instr::Jump(..) | Instr::Goto(_))) {
+            continue;
+        }
+
+        let mut vectors: HashMap<Name, Vec<Option<Name>>> = HashMap::new();
+        for instr in setup {
+            let (id, value) = match instr {
+                Instr::Call(Loc::Id(id), _, f, args, _) if *f == INTERNAL_VECTOR_INIT => match args.as_slice() {
+                    [Exp::I64(len)] => (*id, usize::try_from(*len).ok().map(|len| vec![None; len])),
+                    _ => (*id, None),
+                },
+                Instr::Call(Loc::Id(id), _, f, args, _) if *f == INTERNAL_VECTOR_UPDATE => match args.as_slice() {
+                    [Exp::Id(vec), Exp::I64(n), Exp::Ref(reg)] => {
+                        let updated = vectors.get(vec).cloned().and_then(|mut elems| {
+                            *elems.get_mut(usize::try_from(*n).ok()?)? = Some(*reg);
+                            Some(elems)
+                        });
+                        (*id, updated)
+                    }
+                    _ => (*id, None),
+                },
+                Instr::Init(id, _, Exp::Id(vec), _) | Instr::Copy(Loc::Id(id), Exp::Id(vec), _) => {
+                    (*id, vectors.get(vec).cloned())
+                }
+                Instr::Init(id, _, _, _) | Instr::Decl(id, _, _) => (*id, None),
+                Instr::Copy(loc, _, _)
+                | Instr::Call(loc, _, _, _, _)
+                | Instr::PrimopUnary(loc, _, _, _)
+                | Instr::PrimopBinary(loc, _, _, _, _)
+                | Instr::PrimopVariadic(loc, _, _, _) => (loc.id(), None),
+                _ => continue,
+            };
+            match value {
+                Some(elems) => vectors.insert(id, elems),
+                None => vectors.remove(&id),
+            };
+        }
+
+        for (id, _) in bindings {
+            if let Some(regs) = vectors.get(id).and_then(|elems| elems.iter().copied().collect::<Option<Vec<_>>>()) {
+                banks.insert(*id, regs);
+            }
+        }
+    }
+
+    banks
+}
+
+/// The functions through which Sail accesses register banks
+#[derive(Default)]
+struct BankAccessors {
+    read: HashSet<Name>,
+    write: HashSet<Name>,
+    access: HashSet<Name>,
+}
+
+impl BankAccessors {
+    fn new<B>(defs: &[Def<Name, B>]) -> Self {
+        let mut accessors = BankAccessors::default();
+        for def in defs {
+            if let Def::Extern(f, _, name, _, _) = def {
+                match name.as_str() {
+                    "read_register_from_vector" => accessors.read.insert(*f),
+                    "write_register_from_vector" => accessors.write.insert(*f),
+                    "vector_access" => accessors.access.insert(*f),
+                    _ => false,
+                };
+            }
+        }
+        accessors
+    }
+}
+
+fn register_at(banks: &HashMap<Name, Vec<Name>>, bank: &Exp<Name>, n: &Exp<Name>) -> Option<Name> {
+    let Exp::Id(bank) = bank else { return None };
+    let n = match n {
+        Exp::I64(n) => usize::try_from(*n).ok()?,
+        Exp::I128(n) => usize::try_from(*n).ok()?,
+        _ => return None,
+    };
+    banks.get(bank)?.get(n).copied()
+}
+
+/// Replace accesses to register banks with a constant index by
+/// direct accesses to the register. A write is only lowered when its
+/// (unit) result is never used, as it is replaced by an assignment to
+/// the register.
+fn lower_bank_accesses<B>(
+    body: &mut [Instr<Name, B>],
+    accessors: &BankAccessors,
+    banks: &HashMap<Name, Vec<Name>>,
+) -> usize {
+    let mut used = HashSet::new();
+    for instr in body.iter_mut() {
+        for exp in instr_exps_mut(instr) {
+            exp.collect_ids(&mut used)
+        }
+    }
+
+    let mut lowered = 0;
+    for instr in body.iter_mut() {
+        let Instr::Call(loc, _, f, args, info) = instr else { continue };
+        let replacement = match args.as_slice() {
+            [n, bank] if accessors.read.contains(f) => {
+                register_at(banks, bank, n).map(|reg| Instr::Copy(loc.clone(), Exp::Id(reg), *info))
+            }
+            [bank, n] if accessors.access.contains(f) => {
+                register_at(banks, bank, n).map(|reg| Instr::Copy(loc.clone(), Exp::Ref(reg), *info))
+            }
+            [n, value, bank] if accessors.write.contains(f) && !used.contains(&loc.id()) => {
+                register_at(banks, bank, n).map(|reg| Instr::Copy(Loc::Id(reg), value.clone(), *info))
+            }
+            _ => None,
+        };
+        if let Some(replacement) = replacement {
+            *instr = replacement;
+            lowered += 1
+        }
+    }
+    lowered
+}
+
+/// Replace calls to the `num_of_<enum>` functions with constant
+/// arguments by their result, propagating enum members and integer
+/// constants held in local variables to find such calls. Register
+/// bank accesses whose index becomes constant are then lowered to
+/// direct register accesses.
+pub fn fold_enum_indices<B: BV>(defs: &mut [Def<Name, B>], symtab: &Symtab) -> EnumIndexStats {
+    let mut stats = EnumIndexStats::default();
+
+    let conversions = enum_conversions(defs, symtab);
+    if conversions.is_empty() {
+        return stats;
+    }
+    let banks = register_banks(defs);
+    let accessors = BankAccessors::new(defs);
+
+    let members: HashSet<Name> = defs
+        .iter()
+        .filter_map(|def| match def {
+            Def::Enum(_, members) => Some(members.iter().copied()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+
+    for def in defs.iter_mut() {
+        let Def::Fn(_, args, body) = def else { continue };
+
+        // Each folded call produces a new constant, which may in turn
+        // allow more calls to be folded, so repeat until nothing changes.
+        let mut propagated = HashSet::new();
+        loop {
+            let constants = constant_locals(args, body, &members);
+            for instr in body.iter_mut() {
+                for exp in instr_exps_mut(instr) {
+                    substitute(exp, &constants)
+                }
+            }
+            propagated.extend(constants.into_keys());
+
+            let mut folded = 0;
+            for instr in body.iter_mut() {
+                let Instr::Call(loc, _, f, call_args, info) = instr else { continue };
+                let (Some(conversion), [Exp::Id(member)]) = (conversions.get(f), call_args.as_slice()) else {
+                    continue;
+                };
+                if let Some(index) = conversion.index(*member) {
+                    *instr = Instr::Copy(loc.clone(), index, *info);
+                    folded += 1
+                }
+            }
+
+            stats.calls_folded += folded;
+            if folded == 0 {
+                break;
+            }
+        }
+        stats.variables_propagated += propagated.len();
+        stats.accesses_lowered += lower_bank_accesses(body, &accessors, &banks)
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::source_loc::SourceLoc;
+
+    #[test]
+    fn fold_enum_indexed_vector_access() {
+        let mut symtab = Symtab::new();
+        let e = symtab.intern("zE");
+        let a = symtab.intern("zA");
+        let b = symtab.intern("zB");
+        let num_of_e = symtab.intern("znum_of_E");
+        let vector_access = symtab.intern("zvector_access");
+        let f = symtab.intern("zf");
+        let bank = symtab.intern("zbank");
+        let x = symtab.intern("zx");
+        let i = symtab.intern("zi");
+        let y = symtab.intern("zy");
+        let info = SourceLoc::unknown();
+
+        let body: Vec<Instr<Name, B64>> = vec![
+            Instr::Init(x, Ty::Enum(e), Exp::Id(b), info),
+            Instr::Decl(i, Ty::I64, info),
+            Instr::Call(Loc::Id(i), false, num_of_e, vec![Exp::Id(x)], info),
+            Instr::Decl(y, Ty::Bits(64), info),
+            Instr::Call(Loc::Id(y), false, vector_access, vec![Exp::Id(bank), Exp::Id(i)], info),
+            Instr::End,
+        ];
+        let mut defs = vec![
+            Def::Enum(e, vec![a, b]),
+            Def::Val(num_of_e, vec![Ty::Enum(e)], Ty::I64),
+            Def::Fn(f, vec![bank], body),
+        ];
+
+        let stats = fold_enum_indices(&mut defs, &symtab);
+
+        assert_eq!(stats.calls_folded, 1);
+        assert_eq!(stats.variables_propagated, 2);
+        let Def::Fn(_, _, body) = &defs[2] else { panic!("expected function") };
+        assert!(matches!(body[2], Instr::Copy(Loc::Id(id), Exp::I64(1), _) if id == i));
+        assert!(matches!(&body[4], Instr::Call(_, _, _, args, _) if matches!(args[1], Exp::I64(1))));
+    }
+
+    #[test]
+    fn lower_register_bank_accesses() {
+        let mut symtab = Symtab::new();
+        let e = symtab.intern("zE");
+        let a = symtab.intern("zA");
+        let b = symtab.intern("zB");
+        let num_of_e = symtab.intern("znum_of_E");
+        let read = symtab.intern("zread_register_from_vector");
+        let write = symtab.intern("zwrite_register_from_vector");
+        let r0 = symtab.intern("zR0");
+        let r1 = symtab.intern("zR1");
+        let regs = symtab.intern("zregs");
+        let tmp = symtab.intern("ztmp");
+        let f = symtab.intern("zf");
+        let x = symtab.intern("zx");
+        let i = symtab.intern("zi");
+        let y = symtab.intern("zy");
+        let u = symtab.intern("zu");
+        let info = SourceLoc::unknown();
+
+        let reg_ty = Ty::Bits(64);
+        let bank_ty = Ty::FixedVector(2, Box::new(Ty::Ref(Box::new(reg_ty.clone()))));
+        let setup: Vec<Instr<Name, B64>> = vec![
+            Instr::Decl(tmp, bank_ty.clone(), info),
+            Instr::Call(Loc::Id(tmp), false, INTERNAL_VECTOR_INIT, vec![Exp::I64(2)], info),
+            Instr::Call(
+                Loc::Id(tmp),
+                false,
+                INTERNAL_VECTOR_UPDATE,
+                vec![Exp::Id(tmp), Exp::I64(0), Exp::Ref(r0)],
+                info,
+            ),
+            Instr::Call(
+                Loc::Id(tmp),
+                false,
+                INTERNAL_VECTOR_UPDATE,
+                vec![Exp::Id(tmp), Exp::I64(1), Exp::Ref(r1)],
+                info,
+            ),
+            Instr::Copy(Loc::Id(regs), Exp::Id(tmp), info),
+            Instr::End,
+        ];
+        let body: Vec<Instr<Name, B64>> = vec![
+            Instr::Init(x, Ty::Enum(e), Exp::Id(b), info),
+            Instr::Decl(i, Ty::I64, info),
+            Instr::Call(Loc::Id(i), false, num_of_e, vec![Exp::Id(x)], info),
+            Instr::Decl(y, reg_ty.clone(), info),
+            Instr::Call(Loc::Id(y), false, read, vec![Exp::Id(i), Exp::Id(regs)], info),
+            Instr::Decl(u, Ty::Unit, info),
+            Instr::Call(Loc::Id(u), false, write, vec![Exp::Id(i), Exp::Id(y), Exp::Id(regs)], info),
+            Instr::End,
+        ];
+        let mut defs = vec![
+            Def::Enum(e, vec![a, b]),
+            Def::Val(num_of_e, vec![Ty::Enum(e)], Ty::I64),
+            Def::Extern(
+                read,
+                false,
+                "read_register_from_vector".to_string(),
+                vec![Ty::I64, bank_ty.clone()],
+                reg_ty.clone(),
+            ),
+            Def::Extern(
+                write,
+                false,
+                "write_register_from_vector".to_string(),
+                vec![Ty::I64, reg_ty.clone(), bank_ty.clone()],
+                Ty::Unit,
+            ),
+            Def::Register(r0, reg_ty.clone()),
+            Def::Register(r1, reg_ty),
+            Def::Let(vec![(regs, bank_ty)], setup),
+            Def::Fn(f, Vec::new(), body),
+        ];
+
+        let stats = fold_enum_indices(&mut defs, &symtab);
+
+        assert_eq!(stats.calls_folded, 1);
+        assert_eq!(stats.accesses_lowered, 2);
+        let Def::Fn(_, _, body) = &defs[7] else { panic!("expected function") };
+        assert!(matches!(body[4], Instr::Copy(Loc::Id(id), Exp::Id(reg), _) if id == y && reg == r1));
+        assert!(matches!(body[6], Instr::Copy(Loc::Id(reg), Exp::Id(id), _) if reg == r1 && id == y));
+    }
+}
@@ -0,0 +1,291 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2025 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module specializes an architecture to a fixed configuration.
+//! Registers which have a known initial value and are never assigned
+//! anywhere in the IR are replaced by that value. Branches whose
+//! conditions become constant are then folded, and any code which is
+//! no longer reachable is removed.
+//!
+//! Registers can also be assigned from outside the IR, e.g. by the
+//! reset_registers builtin, so the caller should only pass values for
+//! registers which are genuinely fixed.
+
+use std::collections::{HashMap, HashSet};
+
+use super::*;
+use crate::absint;
+use crate::bitvector::b64::B64;
+use crate::bitvector::BV;
+
+/// Statistics about the changes made by [specialize]
+#[derive(Clone, Debug, Default)]
+pub struct SpecializeStats {
+    /// The registers that were replaced by their fixed values
+    pub registers: HashSet<Name>,
+    /// The fixed registers which could not be specialized because
+    /// they are assigned, or have their address taken, in the IR
+    pub assigned: HashSet<Name>,
+    pub branches_folded: usize,
+    pub instrs_removed: usize,
+}
+
+fn val_to_exp<B: BV>(val: &Val<B>) -> Option<Exp<Name>> {
+    match val {
+        Val::Bool(b) => Some(Exp::Bool(*b)),
+        Val::Bits(bv) if bv.len() <= 64 => Some(Exp::Bits(B64::new(bv.lower_u64(), bv.len()))),
+        Val::I64(n) => Some(Exp::I64(*n)),
+        Val::I128(n) => Some(Exp::I128(*n)),
+        Val::String(s) => Some(Exp::String(s.clone())),
+        Val::Unit => Some(Exp::Unit),
+        _ => None,
+    }
+}
+
+pub(super) fn instr_exps_mut<B>(instr: &mut Instr<Name, B>) -> Vec<&mut Exp<Name>> {
+    use Instr::*;
+    match instr {
+        Init(_, _, exp, _) | Jump(exp, _, _) | Copy(_, exp, _) | PrimopUnary(_, _, exp, _) => vec![exp],
+        PrimopBinary(_, _, lhs, rhs, _) => vec![lhs, rhs],
+        Call(_, _, _, args, _) | PrimopVariadic(_, _, args, _) => args.iter_mut().collect(),
+        Decl(..) | Goto(_) | Monomorphize(..) | Exit(..) | Arbitrary | End => Vec::new(),
+    }
+}
+
+pub(super) fn collect_refs(exp: &Exp<Name>, refs: &mut HashSet<Name>) {
+    use Exp::*;
+    match exp {
+        Ref(id) => {
+            refs.insert(*id);
+        }
+        Id(_) | Bool(_) | Bits(_) | String(_) | Unit | I64(_) | I128(_) | Undefined(_) => (),
+        Kind(_, exp) | Unwrap(_, exp) | Field(exp, _) => collect_refs(exp, refs),
+        Call(_, exps) => exps.iter().for_each(|exp| collect_refs(exp, refs)),
+        Struct(_, fields) => fields.iter().for_each(|(_, exp)| collect_refs(exp, refs)),
+    }
+}
+
+/// Find every name which is assigned or referenced (and so could be
+/// assigned through the reference) anywhere in the given definitions.
+fn assigned_names<B>(defs: &mut [Def<Name, B>]) -> HashSet<Name> {
+    let mut assigned = HashSet::new();
+    for def in defs.iter_mut() {
+        let body = match def {
+            Def::Fn(_, _, body) | Def::Let(_, body) => body,
+            _ => continue,
+        };
+        for instr in body.iter_mut() {
+            match instr {
+                Instr::Decl(id, _, _) | Instr::Init(id, _, _, _) => {
+                    assigned.insert(*id);
+                }
+                Instr::Copy(loc, _, _)
+                | Instr::Call(loc, _, _, _, _)
+                | Instr::PrimopUnary(loc, _, _, _)
+                | Instr::PrimopBinary(loc, _, _, _, _)
+                | Instr::PrimopVariadic(loc, _, _, _) => {
+                    assigned.insert(loc.id());
+                }
+                _ => (),
+            }
+            for exp in instr_exps_mut(instr) {
+                collect_refs(exp, &mut assigned)
+            }
+        }
+    }
+    assigned
+}
+
+pub(super) fn substitute(exp: &mut Exp<Name>, fixed: &HashMap<Name, Exp<Name>>) {
+    use Exp::*;
+    match exp {
+        Id(id) => {
+            if let Some(value) = fixed.get(id) {
+                *exp = value.clone()
+            }
+        }
+        Ref(_) | Bool(_) | Bits(_) | String(_) | Unit | I64(_) | I128(_) | Undefined(_) => (),
+        Kind(_, exp) | Unwrap(_, exp) | Field(exp, _) => substitute(exp, fixed),
+        Call(_, exps) => exps.iter_mut().for_each(|exp| substitute(exp, fixed)),
+        Struct(_, fields) => fields.iter_mut().for_each(|(_, exp)| substitute(exp, fixed)),
+    }
+}
+
+/// Replace branches with constant conditions by gotos, returning the
+/// number of branches replaced.
+fn fold_branches<B: BV>(args: &[Name], body: &mut [Instr<Name, B>]) -> usize {
+    let constant = absint::constant_branches(args, body);
+    for (pc, taken) in &constant {
+        if let Instr::Jump(_, target, _) = body[*pc] {
+            body[*pc] = Instr::Goto(if *taken { target } else { pc + 1 })
+        }
+    }
+    constant.len()
+}
+
+/// Remove unreachable instructions, and gotos to the immediately
+/// following instruction, returning the number of instructions
+/// removed.
+fn remove_dead_code<B>(body: &mut Vec<Instr<Name, B>>) -> usize {
+    let mut reachable = vec![false; body.len()];
+    let mut worklist = vec![0];
+    while let Some(pc) = worklist.pop() {
+        if pc >= body.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+        match &body[pc] {
+            Instr::Jump(_, target, _) => worklist.extend([*target, pc + 1]),
+            Instr::Goto(target) => worklist.push(*target),
+            Instr::Exit(_, _) | Instr::Arbitrary | Instr::End => (),
+            _ => worklist.push(pc + 1),
+        }
+    }
+
+    let keep: Vec<bool> = body
+        .iter()
+        .enumerate()
+        .map(|(pc, instr)| reachable[pc] && !matches!(instr, Instr::Goto(target) if *target == pc + 1))
+        .collect();
+
+    // The new index for each old index. Any jump to a removed
+    // instruction will go to the next instruction that is kept.
+    let mut new_index = Vec::with_capacity(body.len() + 1);
+    let mut n = 0;
+    for kept in &keep {
+        new_index.push(n);
+        if *kept {
+            n += 1
+        }
+    }
+    new_index.push(n);
+
+    let removed = body.len() - n;
+    if removed == 0 {
+        return 0;
+    }
+
+    let old_body = std::mem::take(body);
+    for (instr, kept) in old_body.into_iter().zip(keep) {
+        if !kept {
+            continue;
+        }
+        body.push(match instr {
+            Instr::Jump(exp, target, info) => Instr::Jump(exp, new_index[target], info),
+            Instr::Goto(target) => Instr::Goto(new_index[target]),
+            instr => instr,
+        })
+    }
+    removed
+}
+
+/// Specialize the architecture to the fixed register values given in
+/// `fixed`. Registers which are assigned anywhere in the IR, or whose
+/// values cannot be represented as IR literals, are left alone.
+pub fn specialize<B: BV>(defs: &mut [Def<Name, B>], fixed: &HashMap<Name, Val<B>>) -> SpecializeStats {
+    let mut stats = SpecializeStats::default();
+
+    let assigned = assigned_names(defs);
+    let fixed: HashMap<Name, Exp<Name>> = fixed
+        .iter()
+        .filter(|(reg, _)| {
+            if assigned.contains(reg) {
+                stats.assigned.insert(**reg);
+                false
+            } else {
+                true
+            }
+        })
+        .filter_map(|(reg, value)| Some((*reg, val_to_exp(value)?)))
+        .collect();
+    stats.registers = fixed.keys().copied().collect();
+
+    if fixed.is_empty() {
+        return stats;
+    }
+
+    for def in defs.iter_mut() {
+        if let Def::Fn(_, args, body) = def {
+            for instr in body.iter_mut() {
+                for exp in instr_exps_mut(instr) {
+                    substitute(exp, &fixed)
+                }
+            }
+
+            // Removing dead code can make more facts available at
+            // join points, so repeat until nothing changes.
+            loop {
+                let folded = fold_branches(args, body);
+                stats.branches_folded += folded;
+                stats.instrs_removed += remove_dead_code(body);
+                if folded == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_loc::SourceLoc;
+
+    #[test]
+    fn fold_fixed_register_branch() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("f");
+        let reg = symtab.intern("R");
+        let x = symtab.intern("x");
+        let cond = symtab.intern("c");
+        let info = SourceLoc::unknown();
+
+        let body: Vec<Instr<Name, B64>> = vec![
+            Instr::Init(cond, Ty::Bool, Exp::Call(Op::Eq, vec![Exp::Id(reg), Exp::Bits(B64::new(1, 2))]), info),
+            Instr::Jump(Exp::Id(cond), 4, info),
+            Instr::Copy(Loc::Id(x), Exp::Bits(B64::new(0, 8)), info),
+            Instr::Goto(5),
+            Instr::Copy(Loc::Id(x), Exp::Bits(B64::new(1, 8)), info),
+            Instr::End,
+        ];
+        let mut defs = vec![Def::Register(reg, Ty::Bits(2)), Def::Fn(f, vec![x], body)];
+
+        let mut fixed = HashMap::new();
+        fixed.insert(reg, Val::Bits(B64::new(1, 2)));
+        let stats = specialize(&mut defs, &fixed);
+
+        assert_eq!(stats.branches_folded, 1);
+        let Def::Fn(_, _, body) = &defs[1] else { panic!("expected function") };
+        assert_eq!(body.len(), 3);
+        assert!(matches!(body[1], Instr::Copy(_, Exp::Bits(bv), _) if bv == B64::new(1, 8)));
+        assert!(matches!(body[2], Instr::End));
+    }
+}
@@ -0,0 +1,308 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a simple coordinator/worker protocol over
+//! TCP, which allows a large batch of independent jobs (such as a
+//! litmus test campaign) to be spread across many machines. Workers
+//! connect to the coordinator and repeatedly ask for a job, sending
+//! back the result of the previous one with each request. Jobs held
+//! by a worker which disconnects are handed out again, up to a fixed
+//! number of attempts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: usize,
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct JobResult {
+    pub id: usize,
+    pub output: String,
+    pub failed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Job(Job),
+    Shutdown,
+}
+
+struct Queue {
+    pending: VecDeque<(Job, usize)>,
+    shutdown: bool,
+}
+
+enum Outcome {
+    Done(Job, JobResult),
+    GaveUp(Job),
+}
+
+/// The coordinator side of the protocol, which owns the set of jobs.
+pub struct Coordinator {
+    jobs: Vec<Job>,
+    max_attempts: usize,
+}
+
+impl Coordinator {
+    pub fn new(jobs: Vec<Job>, max_attempts: usize) -> Self {
+        Coordinator { jobs, max_attempts: max_attempts.max(1) }
+    }
+
+    /// Serve jobs to workers connecting on `listener` until every job
+    /// has either completed or run out of attempts. The callback is
+    /// invoked on the calling thread for each completed job, and the
+    /// jobs that could not be completed are returned.
+    pub fn run<F>(self, listener: TcpListener, mut on_result: F) -> io::Result<Vec<Job>>
+    where
+        F: FnMut(&Job, JobResult),
+    {
+        let total = self.jobs.len();
+        let max_attempts = self.max_attempts;
+        let queue = Mutex::new(Queue { pending: self.jobs.into_iter().map(|job| (job, 0)).collect(), shutdown: false });
+        let ready = Condvar::new();
+        let mut given_up = Vec::new();
+
+        listener.set_nonblocking(true)?;
+
+        thread::scope(|scope| {
+            let (tx, rx) = channel();
+            let queue = &queue;
+            let ready = &ready;
+            let listener = &listener;
+
+            scope.spawn(move || loop {
+                if queue.lock().unwrap().shutdown {
+                    break;
+                }
+                match listener.accept() {
+                    Ok((stream, addr)) => {
+                        eprintln!("Worker connected from {}", addr);
+                        let tx = tx.clone();
+                        scope.spawn(move || serve_worker(stream, queue, ready, max_attempts, tx));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(100)),
+                    Err(e) => eprintln!("Failed to accept worker connection: {}", e),
+                }
+            });
+
+            let mut finished = 0;
+            while finished < total {
+                match rx.recv() {
+                    Ok(Outcome::Done(job, result)) => {
+                        finished += 1;
+                        eprintln!("[{}/{}] {}", finished, total, job.name);
+                        on_result(&job, result)
+                    }
+                    Ok(Outcome::GaveUp(job)) => {
+                        finished += 1;
+                        eprintln!("[{}/{}] {} failed after {} attempts", finished, total, job.name, max_attempts);
+                        given_up.push(job)
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            queue.lock().unwrap().shutdown = true;
+            ready.notify_all()
+        });
+
+        Ok(given_up)
+    }
+}
+
+/// Wait for a job to become available, returning `None` once the
+/// coordinator is shutting down.
+fn take_job(queue: &Mutex<Queue>, ready: &Condvar) -> Option<(Job, usize)> {
+    let mut queue = queue.lock().unwrap();
+    loop {
+        if queue.shutdown {
+            return None;
+        }
+        if let Some(job) = queue.pending.pop_front() {
+            return Some(job);
+        }
+        queue = ready.wait(queue).unwrap()
+    }
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    queue: &Mutex<Queue>,
+    ready: &Condvar,
+    max_attempts: usize,
+    outcomes: Sender<Outcome>,
+) {
+    let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| "unknown".to_string());
+    let mut current: Option<(Job, usize)> = None;
+
+    let result = stream.set_nonblocking(false).and_then(|()| stream.try_clone()).and_then(|output| {
+        let mut input = BufReader::new(stream);
+        let mut output = BufWriter::new(output);
+        loop {
+            let previous: Option<JobResult> = bincode::deserialize_from(&mut input).map_err(to_io_error)?;
+            if let Some(result) = previous {
+                if let Some((job, _)) = current.take() {
+                    let _ = outcomes.send(Outcome::Done(job, result));
+                }
+            }
+            match take_job(queue, ready) {
+                Some((job, attempts)) => {
+                    bincode::serialize_into(&mut output, &Response::Job(job.clone())).map_err(to_io_error)?;
+                    current = Some((job, attempts + 1))
+                }
+                None => {
+                    bincode::serialize_into(&mut output, &Response::Shutdown).map_err(to_io_error)?;
+                    output.flush()?;
+                    return Ok(());
+                }
+            }
+            output.flush()?
+        }
+    });
+
+    if let Err(e) = result {
+        eprintln!("Lost connection to worker {}: {}", peer, e)
+    }
+
+    // Hand out any job we were holding again
+    if let Some((job, attempts)) = current {
+        if attempts < max_attempts {
+            queue.lock().unwrap().pending.push_back((job, attempts));
+            ready.notify_one()
+        } else {
+            let _ = outcomes.send(Outcome::GaveUp(job));
+        }
+    }
+}
+
+fn to_io_error(err: bincode::Error) -> io::Error {
+    match *err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+/// The worker side of the protocol.
+pub struct WorkerConnection {
+    input: BufReader<TcpStream>,
+    output: BufWriter<TcpStream>,
+}
+
+impl WorkerConnection {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let output = BufWriter::new(stream.try_clone()?);
+        Ok(WorkerConnection { input: BufReader::new(stream), output })
+    }
+
+    /// Send the result of the previous job (if any), and get the next
+    /// job. Returns `None` when there are no more jobs.
+    pub fn next_job(&mut self, previous: Option<JobResult>) -> io::Result<Option<Job>> {
+        bincode::serialize_into(&mut self.output, &previous).map_err(to_io_error)?;
+        self.output.flush()?;
+        match bincode::deserialize_from(&mut self.input).map_err(to_io_error)? {
+            Response::Job(job) => Ok(Some(job)),
+            Response::Shutdown => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jobs(n: usize) -> Vec<Job> {
+        (0..n).map(|id| Job { id, name: format!("job{}", id), payload: vec![id as u8] }).collect()
+    }
+
+    #[test]
+    fn all_jobs_complete() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let workers: Vec<_> = (0..3)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut connection = WorkerConnection::connect(addr).unwrap();
+                    let mut previous = None;
+                    while let Some(job) = connection.next_job(previous.take()).unwrap() {
+                        previous = Some(JobResult { id: job.id, output: format!("{}", job.payload[0]), failed: false })
+                    }
+                })
+            })
+            .collect();
+
+        let mut outputs = Vec::new();
+        let given_up = Coordinator::new(jobs(10), 2).run(listener, |_, result| outputs.push(result.output)).unwrap();
+        for worker in workers {
+            worker.join().unwrap()
+        }
+
+        assert!(given_up.is_empty());
+        outputs.sort_by_key(|output| output.parse::<usize>().unwrap());
+        assert_eq!(outputs, (0..10).map(|i| i.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn jobs_are_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let worker = thread::spawn(move || {
+            // Take a job and disconnect without finishing it
+            let mut connection = WorkerConnection::connect(addr).unwrap();
+            assert!(connection.next_job(None).unwrap().is_some());
+            drop(connection);
+
+            let mut connection = WorkerConnection::connect(addr).unwrap();
+            let mut previous = None;
+            while let Some(job) = connection.next_job(previous.take()).unwrap() {
+                previous = Some(JobResult { id: job.id, ..JobResult::default() })
+            }
+        });
+
+        let mut completed = 0;
+        let given_up = Coordinator::new(jobs(2), 2).run(listener, |_, _| completed += 1).unwrap();
+        worker.join().unwrap();
+
+        assert!(given_up.is_empty());
+        assert_eq!(completed, 2)
+    }
+}
@@ -41,16 +41,29 @@ lalrpop_mod!(#[allow(clippy::all)] pub ir_parser);
 lalrpop_mod!(#[allow(clippy::all)] pub value_parser);
 lalrpop_mod!(#[allow(clippy::all)] pub smt_parser);
 
+pub mod absint;
+pub mod affinity;
+pub mod binary_trace;
 pub mod bitvector;
 pub mod cache;
+pub mod checkpoint;
 pub mod config;
+pub mod coverage;
+pub mod encoding_space;
 pub mod error;
 pub mod executor;
+pub mod gdb;
 pub mod init;
+pub mod instruction_names;
 pub mod ir;
 pub mod ir_lexer;
+pub mod jobserver;
 pub mod lexer;
+pub mod matrix;
 pub mod memory;
+#[cfg(unix)]
+pub mod multiprocess;
+pub mod precondition;
 pub mod primop;
 pub mod primop_util;
 mod probe;
@@ -58,4 +71,9 @@ pub mod register;
 pub mod simplify;
 pub mod smt;
 pub mod source_loc;
+pub mod summary;
+#[cfg(feature = "trace-file")]
+pub mod trace_file;
+pub mod trace_stats;
+pub mod validate;
 pub mod zencode;
@@ -0,0 +1,210 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module expands a matrix of configuration axes into the
+//! separate cells which should each be run, so a tool can explore
+//! every combination of e.g. a feature flag, the endianness, and the
+//! exception level with a single loaded architecture. Each value of
+//! an [Axis] is a list of register assignments (in the same
+//! `<register>=<value>` syntax as the `--register` option), which are
+//! left uninterpreted here.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A single value an axis can take, with the register assignments
+/// which select it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AxisValue {
+    pub label: String,
+    pub assignments: Vec<String>,
+}
+
+/// A named configuration axis and the values it ranges over
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Axis {
+    pub name: String,
+    pub values: Vec<AxisValue>,
+}
+
+/// A matrix is the cartesian product of its axes. An empty matrix
+/// has exactly one (unlabeled) cell.
+#[derive(Clone, Debug, Default)]
+pub struct Matrix {
+    axes: Vec<Axis>,
+}
+
+/// One combination of values from each axis of a [Matrix]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    /// The axis name and value label for each axis
+    pub coordinates: Vec<(String, String)>,
+    /// The register assignments for all the chosen values, in axis order
+    pub assignments: Vec<String>,
+}
+
+impl Cell {
+    /// A label for the cell, e.g. `el=EL1,endian=big`
+    pub fn label(&self) -> String {
+        let coordinates: Vec<String> =
+            self.coordinates.iter().map(|(axis, value)| format!("{}={}", axis, value)).collect();
+        coordinates.join(",")
+    }
+}
+
+impl Matrix {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_axis(&mut self, axis: Axis) -> Result<(), ParseAxisError> {
+        if self.axes.iter().any(|existing| existing.name == axis.name) {
+            return Err(ParseAxisError(format!("Axis {} given more than once", axis.name)));
+        }
+        self.axes.push(axis);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.axes.is_empty()
+    }
+
+    /// All the cells of the matrix, with the last axis varying fastest
+    pub fn cells(&self) -> Vec<Cell> {
+        let mut cells = vec![Cell { coordinates: Vec::new(), assignments: Vec::new() }];
+        for axis in &self.axes {
+            let mut extended = Vec::with_capacity(cells.len() * axis.values.len());
+            for cell in &cells {
+                for value in &axis.values {
+                    let mut cell = cell.clone();
+                    cell.coordinates.push((axis.name.clone(), value.label.clone()));
+                    cell.assignments.extend(value.assignments.iter().cloned());
+                    extended.push(cell)
+                }
+            }
+            cells = extended
+        }
+        cells
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseAxisError(String);
+
+impl fmt::Display for ParseAxisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseAxisError {}
+
+/// Parses an axis of the form
+/// `<name>=<label>:<assignment>;...|<label>:<assignment>;...`, for
+/// example `el=EL0:PSTATE.EL=0b00|EL1:PSTATE.EL=0b01`. A value may
+/// have no assignments, in which case the architecture's default is
+/// used for that cell.
+impl FromStr for Axis {
+    type Err = ParseAxisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, values) =
+            s.split_once('=').ok_or_else(|| ParseAxisError(format!("Expected <name>=<values>, got {}", s)))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ParseAxisError(format!("Axis {} has no name", s)));
+        }
+
+        let mut axis = Axis { name: name.to_string(), values: Vec::new() };
+        for value in values.split('|') {
+            let (label, assignments) = value.split_once(':').unwrap_or((value, ""));
+            let label = label.trim();
+            if label.is_empty() {
+                return Err(ParseAxisError(format!("Value with no label in axis {}", name)));
+            }
+            if axis.values.iter().any(|existing| existing.label == label) {
+                return Err(ParseAxisError(format!("Value {} given more than once in axis {}", label, name)));
+            }
+            let assignments = assignments
+                .split(';')
+                .map(str::trim)
+                .filter(|assignment| !assignment.is_empty())
+                .map(str::to_string)
+                .collect();
+            axis.values.push(AxisValue { label: label.to_string(), assignments })
+        }
+        Ok(axis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_axis() {
+        let axis: Axis = "el=EL0:PSTATE.EL=0b00|EL1:PSTATE.EL=0b01; PSTATE.SP=0b1".parse().unwrap();
+        assert_eq!(axis.name, "el");
+        assert_eq!(axis.values.len(), 2);
+        assert_eq!(axis.values[0].assignments, vec!["PSTATE.EL=0b00".to_string()]);
+        assert_eq!(axis.values[1].label, "EL1");
+        assert_eq!(axis.values[1].assignments.len(), 2);
+
+        let axis: Axis = "feature=off|on:FEAT_X=true".parse().unwrap();
+        assert!(axis.values[0].assignments.is_empty());
+
+        assert!("EL0:PSTATE.EL=0b00".parse::<Axis>().is_err());
+        assert!("el=a:X=0b0|a:X=0b1".parse::<Axis>().is_err());
+        assert!("el=:X=0b0".parse::<Axis>().is_err());
+    }
+
+    #[test]
+    fn expand_cells() {
+        let mut matrix = Matrix::new();
+        assert_eq!(matrix.cells().len(), 1);
+        assert_eq!(matrix.cells()[0].label(), "");
+
+        matrix.add_axis("feature=off:F=false|on:F=true".parse().unwrap()).unwrap();
+        matrix.add_axis("endian=little:E=0b0|big:E=0b1".parse().unwrap()).unwrap();
+        assert!(matrix.add_axis("endian=big".parse().unwrap()).is_err());
+
+        let cells = matrix.cells();
+        let labels: Vec<String> = cells.iter().map(Cell::label).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "feature=off,endian=little",
+                "feature=off,endian=big",
+                "feature=on,endian=little",
+                "feature=on,endian=big"
+            ]
+        );
+        assert_eq!(cells[3].assignments, vec!["F=true".to_string(), "E=0b1".to_string()]);
+    }
+}
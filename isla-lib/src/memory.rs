@@ -48,7 +48,7 @@ use crate::ir;
 use crate::ir::Val;
 use crate::log;
 use crate::probe;
-use crate::smt::smtlib::{bits64, Def, Exp};
+use crate::smt::smtlib::{bits64, Def, Exp, Ty};
 use crate::smt::{Event, Model, ReadOpts, SmtResult, Solver, Sym, WriteOpts};
 use crate::source_loc::SourceLoc;
 
@@ -118,6 +118,104 @@ impl<B> Clone for Region<B> {
     }
 }
 
+/// A memory-mapped device. Reads return fresh symbolic values,
+/// optionally constrained to a set of values, and both reads and
+/// writes are recorded as [ir::DEVICE_ACCESS] abstract events rather
+/// than memory events, so they do not appear in the memory footprint.
+/// The event arguments are the device name, the access kind, the
+/// address, and either the number of bytes read or the data written.
+#[derive(Clone, Debug)]
+pub struct DeviceRegion {
+    name: String,
+    values: Vec<u64>,
+}
+
+impl DeviceRegion {
+    pub fn new(name: &str, values: &[u64]) -> Self {
+        DeviceRegion { name: name.to_string(), values: values.to_vec() }
+    }
+}
+
+impl<B: BV> CustomRegion<B> for DeviceRegion {
+    fn read(
+        &self,
+        read_kind: Val<B>,
+        address: Address,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        use Exp::*;
+
+        if tag {
+            return Err(ExecError::BadRead("tagged read from device region"));
+        }
+
+        let value = solver.declare_const(Ty::BitVec(8 * bytes), SourceLoc::unknown());
+
+        if !self.values.is_empty() && bytes <= 8 {
+            let mut choices = self
+                .values
+                .iter()
+                .filter(|v| bytes == 8 || **v >> (8 * bytes) == 0)
+                .map(|v| Eq(Box::new(Var(value)), Box::new(bits64(*v, 8 * bytes))));
+            let Some(first) = choices.next() else {
+                return Err(ExecError::BadRead("no device value fits in read"))
+            };
+            let constraint = choices.fold(first, |c1, c2| Or(Box::new(c1), Box::new(c2)));
+            solver.add(Def::Assert(constraint))
+        }
+
+        solver.add_event(Event::Abstract {
+            name: ir::DEVICE_ACCESS,
+            primitive: true,
+            args: vec![
+                Val::String(self.name.clone()),
+                read_kind,
+                Val::Bits(B::from_u64(address)),
+                Val::I128(i128::from(bytes)),
+            ],
+            return_value: Val::Symbolic(value),
+        });
+
+        Ok(Val::Symbolic(value))
+    }
+
+    fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Address,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        if tag.is_some() {
+            return Err(ExecError::BadWrite("tagged write to device region"));
+        }
+
+        solver.add_event(Event::Abstract {
+            name: ir::DEVICE_ACCESS,
+            primitive: true,
+            args: vec![Val::String(self.name.clone()), write_kind, Val::Bits(B::from_u64(address)), data],
+            return_value: Val::Unit,
+        });
+
+        Ok(Val::Bool(true))
+    }
+
+    fn initial_value(&self, _address: Address, _bytes: u32) -> Option<B> {
+        None
+    }
+
+    fn region_name(&self) -> &'static str {
+        "device"
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Send + Sync + CustomRegion<B>> {
+        Box::new(self.clone())
+    }
+}
+
 pub enum SmtKind {
     ReadData,
     ReadInstr,
@@ -138,7 +236,7 @@ impl<B> fmt::Debug for Region<B> {
 }
 
 impl<B> Region<B> {
-    fn region_name(&self) -> &'static str {
+    pub(crate) fn region_name(&self) -> &'static str {
         match self {
             Region::Constrained(_, _) => "constrained",
             Region::Symbolic(_) => "symbolic",
@@ -221,6 +319,72 @@ impl<B> Clone for Box<dyn MemoryCallbacks<B>> {
     }
 }
 
+/// What happens when a memory access hits a [Watchpoint]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Log the access, pass it to the [WatchpointHandler] (if any),
+    /// and carry on with the access
+    Report,
+    /// Report the access, then suspend the path just before it, in
+    /// the same way as [crate::executor::StopAction::Suspend]
+    Suspend,
+}
+
+/// A watched range of memory. An access hits the watchpoint if any
+/// of its bytes may lie within `range`, so a symbolic address hits
+/// whenever the solver can place it there.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub range: Range<Address>,
+    pub reads: bool,
+    pub writes: bool,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    /// Watch both reads and writes to a range
+    pub fn new(range: Range<Address>, action: WatchAction) -> Self {
+        Watchpoint { range, reads: true, writes: true, action }
+    }
+
+    /// Watch only writes to a range
+    pub fn writes(range: Range<Address>, action: WatchAction) -> Self {
+        Watchpoint { range, reads: false, writes: true, action }
+    }
+
+    /// The constraint under which an access of `bytes` bytes at
+    /// `address` overlaps the watched range. The lower bound is
+    /// adjusted rather than adding to the address, so accesses near
+    /// the top of the address space cannot wrap around.
+    fn overlap_constraint(&self, address: Sym, bytes: u32) -> Exp<Sym> {
+        use Exp::*;
+        let lower = self.range.start.saturating_sub(u64::from(bytes.max(1) - 1));
+        And(
+            Box::new(Bvule(Box::new(bits64(lower, 64)), Box::new(Var(address)))),
+            Box::new(Bvult(Box::new(Var(address)), Box::new(bits64(self.range.end, 64)))),
+        )
+    }
+}
+
+/// A memory access that hit a [Watchpoint]
+#[derive(Debug)]
+pub struct WatchpointHit<'a, B> {
+    pub watchpoint: &'a Watchpoint,
+    pub address: &'a Val<B>,
+    pub bytes: u32,
+    /// The value being written, or `None` for a read
+    pub data: Option<&'a Val<B>>,
+    /// The call stack at the access, innermost function last
+    pub backtrace: &'a [(ir::Name, usize)],
+}
+
+/// Receives watchpoint hits. The solver is positioned just before
+/// the access, so a model at this point gives a concrete address for
+/// a symbolic one.
+pub trait WatchpointHandler<B>: fmt::Debug + Send + Sync {
+    fn hit(&self, hit: &WatchpointHit<'_, B>, solver: &mut Solver<B>);
+}
+
 fn make_bv_bit_pair<B>(left: Val<B>, right: Val<B>) -> Val<B> {
     let mut fields = HashMap::default();
     fields.insert(ir::BV_BIT_LEFT, left);
@@ -232,13 +396,22 @@ fn make_bv_bit_pair<B>(left: Val<B>, right: Val<B>) -> Val<B> {
 pub struct Memory<B> {
     regions: Vec<Region<B>>,
     client_info: Option<Box<dyn MemoryCallbacks<B>>>,
+    ifetch_sees_writes: bool,
+    watchpoints: Vec<Watchpoint>,
+    watchpoint_handler: Option<Arc<dyn WatchpointHandler<B>>>,
 }
 
 static DEFAULT_REGION_NAME: &str = "default";
 
 impl<B: BV> Memory<B> {
     pub fn new() -> Self {
-        Memory { regions: Vec::new(), client_info: None }
+        Memory {
+            regions: Vec::new(),
+            client_info: None,
+            ifetch_sees_writes: false,
+            watchpoints: Vec::new(),
+            watchpoint_handler: None,
+        }
     }
 
     pub fn region_name_at(&self, addr: Address) -> &'static str {
@@ -288,6 +461,21 @@ impl<B: BV> Memory<B> {
         None
     }
 
+    pub(crate) fn regions(&self) -> &[Region<B>] {
+        &self.regions
+    }
+
+    pub(crate) fn ifetch_sees_writes(&self) -> bool {
+        self.ifetch_sees_writes
+    }
+
+    /// True if any callbacks are installed, which are not preserved
+    /// by snapshots of the memory. Watchpoints are preserved, but
+    /// their handler is not.
+    pub(crate) fn has_callbacks(&self) -> bool {
+        self.client_info.is_some() || self.watchpoint_handler.is_some()
+    }
+
     pub fn add_region(&mut self, region: Region<B>) {
         self.regions.push(region)
     }
@@ -308,10 +496,105 @@ impl<B: BV> Memory<B> {
         self.regions.push(Region::Concrete(range, HashMap::new()))
     }
 
+    pub fn add_device_region(&mut self, range: Range<Address>, device: DeviceRegion) {
+        self.regions.push(Region::Custom(range, Box::new(device)))
+    }
+
     pub fn set_client_info(&mut self, info: Box<dyn MemoryCallbacks<B>>) {
         self.client_info = Some(info);
     }
 
+    /// If set, instruction fetches from concrete memory will observe
+    /// any earlier writes to the same address in the trace, allowing
+    /// self-modifying code to be executed sequentially.
+    pub fn set_ifetch_sees_writes(&mut self, ifetch_sees_writes: bool) {
+        self.ifetch_sees_writes = ifetch_sees_writes
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn set_watchpoint_handler(&mut self, handler: Arc<dyn WatchpointHandler<B>>) {
+        self.watchpoint_handler = Some(handler)
+    }
+
+    /// Find the first watchpoint hit by an access of `bytes` bytes at
+    /// `address`. Symbolic addresses are checked with the solver.
+    /// Addresses which are neither concrete nor a single symbolic
+    /// variable are not checked.
+    pub fn watchpoint_hit(
+        &self,
+        address: &Val<B>,
+        bytes: u32,
+        is_write: bool,
+        solver: &mut Solver<B>,
+    ) -> Result<Option<&Watchpoint>, ExecError> {
+        for watchpoint in &self.watchpoints {
+            if !(if is_write { watchpoint.writes } else { watchpoint.reads }) {
+                continue;
+            }
+            match address {
+                Val::Bits(bv) => {
+                    let start = bv.lower_u64();
+                    let end = start.saturating_add(u64::from(bytes.max(1)));
+                    if start < watchpoint.range.end && watchpoint.range.start < end {
+                        return Ok(Some(watchpoint));
+                    }
+                }
+                Val::Symbolic(v) => match solver.check_sat_with(&watchpoint.overlap_constraint(*v, bytes)) {
+                    SmtResult::Sat => return Ok(Some(watchpoint)),
+                    SmtResult::Unknown => return Err(ExecError::Z3Unknown),
+                    SmtResult::Unsat => (),
+                },
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check an access against the watchpoints, logging and passing
+    /// any hit to the handler. Returns true if the path should be
+    /// suspended before the access.
+    pub(crate) fn check_watchpoints(
+        &self,
+        address: &Val<B>,
+        bytes: u32,
+        data: Option<&Val<B>>,
+        backtrace: &[(ir::Name, usize)],
+        solver: &mut Solver<B>,
+    ) -> Result<bool, ExecError> {
+        if self.watchpoints.is_empty() {
+            return Ok(false);
+        }
+
+        let Some(watchpoint) = self.watchpoint_hit(address, bytes, data.is_some(), solver)? else {
+            return Ok(false)
+        };
+
+        log!(
+            log::MEMORY,
+            &format!(
+                "Watchpoint [0x{:x}, 0x{:x}) hit by {} of {} bytes at {:?}",
+                watchpoint.range.start,
+                watchpoint.range.end,
+                if data.is_some() { "write" } else { "read" },
+                bytes,
+                address
+            )
+        );
+
+        if let Some(handler) = &self.watchpoint_handler {
+            handler.hit(&WatchpointHit { watchpoint, address, bytes, data, backtrace }, solver)
+        }
+
+        Ok(watchpoint.action == WatchAction::Suspend)
+    }
+
     pub fn write_byte(&mut self, address: Address, byte: u8) {
         for region in &mut self.regions {
             match region {
@@ -460,6 +743,35 @@ impl<B: BV> Memory<B> {
                                 )
                             }
 
+                            Region::Concrete(range, contents)
+                                if opts.is_ifetch
+                                    && self.ifetch_sees_writes
+                                    && range.contains(&concrete_addr.lower_u64()) =>
+                            {
+                                if let Some(value) = latest_write(concrete_addr.lower_u64(), bytes, solver)? {
+                                    solver.add_event(Event::ReadMem {
+                                        value: value.clone(),
+                                        read_kind,
+                                        address,
+                                        bytes,
+                                        tag_value: None,
+                                        opts,
+                                        region: region.region_name(),
+                                    });
+                                    return Ok(value);
+                                }
+                                return read_concrete(
+                                    contents,
+                                    read_kind,
+                                    concrete_addr.lower_u64(),
+                                    bytes,
+                                    solver,
+                                    tag,
+                                    opts,
+                                    region.region_name(),
+                                );
+                            }
+
                             Region::Concrete(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
                                 return read_concrete(
                                     contents,
@@ -746,6 +1058,21 @@ pub fn smt_address_constraint<B: BV>(
         })
 }
 
+/// Find the data written by the most recent write event in the trace
+/// to exactly `bytes` bytes at `address`. Writes that only partially
+/// overlap the range are reported as an error, as we cannot reconstruct
+/// the combined value.
+fn latest_write<B: BV>(address: Address, bytes: u32, solver: &Solver<B>) -> Result<Option<Val<B>>, ExecError> {
+    let mut writes = solver.writes_overlapping(address, bytes);
+    match (writes.next(), writes.next()) {
+        (None, _) => Ok(None),
+        (Some((write_addr, write_bytes, data)), None) if write_addr == address && write_bytes == bytes => {
+            Ok(Some(data.clone()))
+        }
+        _ => Err(ExecError::BadRead("instruction fetch from partially overwritten memory")),
+    }
+}
+
 fn reverse_endianness(bytes: &mut [u8]) {
     if bytes.len() <= 2 {
         bytes.reverse()
@@ -830,3 +1157,71 @@ fn read_concrete<B: BV>(
         Err(ExecError::BadRead("concrete read more than 8 bytes"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::{checkpoint, Config, Context};
+
+    fn fetch(memory: &Memory<B64>, address: u64, solver: &mut Solver<B64>) -> Val<B64> {
+        let address = Val::Bits(B64::from_u64(address));
+        memory.read(Val::Unit, address, Val::I128(4), solver, false, ReadOpts::ifetch()).unwrap()
+    }
+
+    fn code_memory() -> Memory<B64> {
+        let mut memory = Memory::new();
+        let contents = [0x1f, 0x20, 0x03, 0xd5].iter().enumerate().map(|(i, byte)| (0x1000 + i as u64, *byte));
+        memory.add_concrete_region(0x1000..0x1004, contents.collect());
+        memory.set_ifetch_sees_writes(true);
+        memory
+    }
+
+    #[test]
+    fn ifetch_without_write() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let memory = code_memory();
+        assert_eq!(fetch(&memory, 0x1000, &mut solver), Val::Bits(B64::new(0xd503201f, 32)))
+    }
+
+    #[test]
+    fn ifetch_after_write() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut memory = code_memory();
+        let data = Val::Bits(B64::new(0xd65f03c0, 32));
+        memory
+            .write(Val::Unit, Val::Bits(B64::from_u64(0x1000)), data.clone(), &mut solver, None, WriteOpts::default())
+            .unwrap();
+        assert_eq!(fetch(&memory, 0x1000, &mut solver), data);
+
+        // Without ifetch_sees_writes the original contents are fetched
+        memory.set_ifetch_sees_writes(false);
+        assert_eq!(fetch(&memory, 0x1000, &mut solver), Val::Bits(B64::new(0xd503201f, 32)))
+    }
+
+    #[test]
+    fn ifetch_after_checkpoint() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut memory = code_memory();
+        let data = Val::Bits(B64::new(0xd65f03c0, 32));
+        memory
+            .write(Val::Unit, Val::Bits(B64::from_u64(0x1000)), data.clone(), &mut solver, None, WriteOpts::default())
+            .unwrap();
+
+        // The write is still seen when the path is restored
+        let point = checkpoint(&mut solver);
+        let mut solver = Solver::from_checkpoint(&ctx, point);
+        assert_eq!(fetch(&memory, 0x1000, &mut solver), data);
+
+        // Overwriting part of the instruction leaves nothing to fetch
+        let half = Val::Bits(B64::new(0, 16));
+        memory
+            .write(Val::Unit, Val::Bits(B64::from_u64(0x1002)), half, &mut solver, None, WriteOpts::default())
+            .unwrap();
+        let address = Val::Bits(B64::from_u64(0x1000));
+        assert!(memory.read(Val::Unit, address, Val::I128(4), &mut solver, false, ReadOpts::ifetch()).is_err())
+    }
+}
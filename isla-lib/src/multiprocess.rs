@@ -0,0 +1,390 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a simple multi-process execution mode. The
+//! parent process forks a number of workers after the architecture
+//! has been initialized, so each worker has a copy-on-write mapping
+//! of the same read-only [crate::executor::SharedState].
+//!
+//! The parent hands out tasks to the workers over Unix sockets. A
+//! task is identified by the id of the initial task it was forked
+//! from, and, as for a [crate::executor::ForkLog], the branches taken
+//! at every fork between the initial task and the start of the task,
+//! so a worker recreates it by replaying the initial task. The parent
+//! starts by handing out the initial tasks.
+//! Whenever a worker is idle and there are no tasks left to hand
+//! out, the parent asks the busy workers for more, and each of them
+//! gives away the next task it creates at a fork (see
+//! [crate::executor::ForkDonor]). The forks leading to a task are
+//! therefore only replayed for tasks which are given away to a
+//! worker that would otherwise be idle.
+//!
+//! Once every worker is idle and there are no tasks left, the parent
+//! tells the workers to finish, and each sends its results back to
+//! the parent using the binary trace encoding.
+
+use crossbeam::queue::SegQueue;
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::binary_trace::{
+    read_result, read_u32, read_u8, read_usize, write_result, write_u32, write_u8, write_usize, BinaryTraceError,
+    TracedResult,
+};
+use crate::bitvector::BV;
+use crate::executor::ForkDonor;
+
+/// A worker has finished its task and wants another
+const MSG_IDLE: u8 = 0;
+/// A task, sent by the parent to hand it out or by a worker to give
+/// it away
+const MSG_TASK: u8 = 1;
+/// A result sent by a worker once it has finished
+const MSG_RESULT: u8 = 2;
+/// The parent asks a worker to give away a task
+const MSG_WANTED: u8 = 3;
+/// There are no tasks left, so a worker should send its results
+const MSG_FINISH: u8 = 4;
+
+fn write_task(out: &mut dyn Write, task_id: usize, path: &[u32]) -> io::Result<()> {
+    write_u8(out, MSG_TASK)?;
+    write_usize(out, task_id)?;
+    write_usize(out, path.len())?;
+    for branch in path {
+        write_u32(out, *branch)?
+    }
+    out.flush()
+}
+
+fn read_task<R: Read>(input: &mut R) -> Result<(usize, Vec<u32>), BinaryTraceError> {
+    let task_id = read_usize(input)?;
+    let mut path = Vec::new();
+    for _ in 0..read_usize(input)? {
+        path.push(read_u32(input)?)
+    }
+    Ok((task_id, path))
+}
+
+fn write_message(out: &mut dyn Write, msg: u8) -> io::Result<()> {
+    write_u8(out, msg)?;
+    out.flush()
+}
+
+pub struct WorkerProcess {
+    pid: libc::pid_t,
+    stream: UnixStream,
+}
+
+/// The connection from a worker process to the parent.
+pub struct Worker {
+    output: Arc<Mutex<BufWriter<UnixStream>>>,
+    /// Set when the parent has asked for a task, and cleared when one
+    /// is given away or the parent hands out a new task
+    wanted: Arc<AtomicBool>,
+    /// The tasks handed out by the parent, or `None` when there are
+    /// no tasks left
+    tasks: Receiver<Option<(usize, Vec<u32>)>>,
+}
+
+pub enum Role {
+    /// The parent process, with a handle for each worker
+    Parent(Vec<WorkerProcess>),
+    /// A worker process, with its index and its connection to the
+    /// parent
+    Worker(usize, Worker),
+}
+
+/// Fork `n` worker processes. This should be called before any other
+/// threads are started, as only the calling thread exists in each
+/// worker.
+pub fn fork_workers(n: usize) -> io::Result<Role> {
+    let mut workers = Vec::new();
+    for i in 0..n {
+        let (parent_end, worker_end) = UnixStream::pair()?;
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {
+                drop(parent_end);
+                drop(workers);
+                return Ok(Role::Worker(i, Worker::new(worker_end)?));
+            }
+            pid => workers.push(WorkerProcess { pid, stream: parent_end }),
+        }
+    }
+    Ok(Role::Parent(workers))
+}
+
+impl Worker {
+    fn new(stream: UnixStream) -> io::Result<Self> {
+        let mut input = BufReader::new(stream.try_clone()?);
+        let wanted = Arc::new(AtomicBool::new(false));
+        let (sender, tasks) = mpsc::channel();
+
+        // Requests for tasks can arrive at any time, so the messages
+        // from the parent are read by a separate thread. As messages
+        // are read in order, a request which was sent before a new
+        // task was handed out is discarded along with the old task.
+        let reader_wanted = wanted.clone();
+        thread::spawn(move || -> Result<(), BinaryTraceError> {
+            loop {
+                match read_u8(&mut input)? {
+                    MSG_WANTED => reader_wanted.store(true, Ordering::SeqCst),
+                    MSG_TASK => {
+                        let task = read_task(&mut input)?;
+                        reader_wanted.store(false, Ordering::SeqCst);
+                        if sender.send(Some(task)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    MSG_FINISH => {
+                        let _ = sender.send(None);
+                        return Ok(());
+                    }
+                    tag => return Err(BinaryTraceError::BadTag("message", tag)),
+                }
+            }
+        });
+
+        Ok(Worker { output: Arc::new(Mutex::new(BufWriter::new(stream))), wanted, tasks })
+    }
+
+    /// Ask the parent for a task, returning the id of its initial
+    /// task and the path to replay from it, or `None` if there are no
+    /// tasks left.
+    pub fn next_task(&mut self) -> io::Result<Option<(usize, Vec<u32>)>> {
+        write_message(&mut *self.output.lock().unwrap(), MSG_IDLE)?;
+        self.tasks.recv().map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "connection to parent closed"))
+    }
+
+    /// A donor which gives away tasks forked from the initial task
+    /// `task_id` to the parent when it asks for them.
+    pub fn donor(&self, task_id: usize) -> Arc<dyn ForkDonor> {
+        Arc::new(WorkerDonor { task_id, output: self.output.clone(), wanted: self.wanted.clone() })
+    }
+
+    /// Send all the results collected by the worker to the parent,
+    /// closing the connection when done.
+    pub fn send_results<B: BV>(self, results: &SegQueue<TracedResult<B>>) -> Result<(), BinaryTraceError> {
+        let mut out = self.output.lock().unwrap();
+        while let Some(result) = results.pop() {
+            write_u8(&mut *out, MSG_RESULT)?;
+            write_result(&mut *out, &result)?
+        }
+        out.flush()?;
+        out.get_ref().shutdown(Shutdown::Write)?;
+        Ok(())
+    }
+}
+
+struct WorkerDonor {
+    task_id: usize,
+    output: Arc<Mutex<BufWriter<UnixStream>>>,
+    wanted: Arc<AtomicBool>,
+}
+
+impl ForkDonor for WorkerDonor {
+    fn donate(&self, path: &[u32]) -> bool {
+        if !self.wanted.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        // If the parent can't be reached we explore the task ourselves
+        write_task(&mut *self.output.lock().unwrap(), self.task_id, path).is_ok()
+    }
+}
+
+fn check_send(i: usize, states: &mut [WorkerState], failed: &mut Vec<String>, result: io::Result<()>) {
+    if let Err(err) = result {
+        failed.push(format!("worker {}: {}", i, err));
+        states[i] = WorkerState::Failed
+    }
+}
+
+enum Message {
+    Idle,
+    Task(usize, Vec<u32>),
+    /// The connection was closed, with an error if it was closed
+    /// before the worker sent all its results
+    Closed(Option<String>),
+}
+
+/// The state of each worker, as seen by the parent
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum WorkerState {
+    Idle,
+    Busy,
+    /// Busy, and asked to give away a task
+    Asked,
+    Failed,
+}
+
+/// Hand out the initial tasks `0..num_tasks`, and the tasks forked
+/// from them, to the workers until there are none left. The results
+/// from every worker are received into a single queue, and then we
+/// wait for all the workers to exit.
+pub fn distribute_tasks<B: BV>(
+    workers: Vec<WorkerProcess>,
+    num_tasks: usize,
+    results: &SegQueue<TracedResult<B>>,
+) -> Result<(), String> {
+    let pids: Vec<libc::pid_t> = workers.iter().map(|worker| worker.pid).collect();
+    let mut failed = Vec::new();
+
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for (i, worker) in workers.into_iter().enumerate() {
+        match worker.stream.try_clone() {
+            Ok(input) => inputs.push(input),
+            Err(err) => return Err(format!("worker {}: {}", i, err)),
+        }
+        outputs.push(BufWriter::new(worker.stream))
+    }
+
+    thread::scope(|scope| {
+        let (sender, messages) = mpsc::channel();
+        for (i, input) in inputs.into_iter().enumerate() {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                let mut input = BufReader::new(input);
+                let closed = loop {
+                    let msg = match read_u8(&mut input) {
+                        Ok(msg) => msg,
+                        Err(BinaryTraceError::UnexpectedEnd) => break None,
+                        Err(err) => break Some(err.to_string()),
+                    };
+                    let message = match msg {
+                        MSG_IDLE => Ok(Message::Idle),
+                        MSG_TASK => read_task(&mut input).map(|(task_id, path)| Message::Task(task_id, path)),
+                        MSG_RESULT => match read_result(&mut input) {
+                            Ok(Some(result)) => {
+                                results.push(result);
+                                continue;
+                            }
+                            Ok(None) => Err(BinaryTraceError::UnexpectedEnd),
+                            Err(err) => Err(err),
+                        },
+                        tag => Err(BinaryTraceError::BadTag("message", tag)),
+                    };
+                    match message {
+                        Ok(message) => {
+                            let _ = sender.send((i, message));
+                        }
+                        Err(err) => break Some(err.to_string()),
+                    }
+                };
+                let _ = sender.send((i, Message::Closed(closed)));
+            });
+        }
+        drop(sender);
+
+        let mut states = vec![WorkerState::Busy; outputs.len()];
+        let mut tasks: VecDeque<(usize, Vec<u32>)> = (0..num_tasks).map(|task_id| (task_id, Vec::new())).collect();
+
+        loop {
+            for i in 0..states.len() {
+                if states[i] == WorkerState::Idle {
+                    if let Some((task_id, path)) = tasks.pop_front() {
+                        states[i] = WorkerState::Busy;
+                        check_send(i, &mut states, &mut failed, write_task(&mut outputs[i], task_id, &path))
+                    }
+                }
+            }
+            if states.iter().all(|state| matches!(state, WorkerState::Idle | WorkerState::Failed)) {
+                break;
+            }
+            if tasks.is_empty() && states.contains(&WorkerState::Idle) {
+                for i in 0..states.len() {
+                    if states[i] == WorkerState::Busy {
+                        states[i] = WorkerState::Asked;
+                        check_send(i, &mut states, &mut failed, write_message(&mut outputs[i], MSG_WANTED))
+                    }
+                }
+            }
+
+            // Each worker gives away any tasks before it becomes
+            // idle, so once every worker is idle all the tasks have
+            // been received.
+            match messages.recv() {
+                Ok((i, Message::Idle)) if states[i] != WorkerState::Failed => states[i] = WorkerState::Idle,
+                Ok((i, Message::Task(task_id, path))) if states[i] != WorkerState::Failed => {
+                    if states[i] == WorkerState::Asked {
+                        states[i] = WorkerState::Busy
+                    }
+                    tasks.push_back((task_id, path))
+                }
+                Ok((i, Message::Closed(err))) if states[i] != WorkerState::Failed => {
+                    let err = err.unwrap_or_else(|| "exited before finishing".to_string());
+                    failed.push(format!("worker {}: {}", i, err));
+                    states[i] = WorkerState::Failed
+                }
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+
+        if !tasks.is_empty() {
+            failed.push(format!("{} tasks were not explored", tasks.len()))
+        }
+        for i in 0..states.len() {
+            if states[i] != WorkerState::Failed {
+                check_send(i, &mut states, &mut failed, write_message(&mut outputs[i], MSG_FINISH))
+            }
+        }
+
+        // Now wait for each worker to send its results and close the
+        // connection
+        for (i, message) in messages {
+            if let Message::Closed(Some(err)) = message {
+                if states[i] != WorkerState::Failed {
+                    failed.push(format!("worker {}: {}", i, err))
+                }
+            }
+        }
+    });
+
+    for (i, pid) in pids.iter().enumerate() {
+        let mut status = 0;
+        if unsafe { libc::waitpid(*pid, &mut status, 0) } == -1 {
+            failed.push(format!("worker {}: {}", i, io::Error::last_os_error()))
+        } else if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+            failed.push(format!("worker {} exited abnormally (status {})", i, status))
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join("\n"))
+    }
+}
@@ -0,0 +1,397 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module infers the weakest known precondition for an
+//! instruction (or function) to complete without error. The
+//! [precondition_collector] records the path condition of each path
+//! which completes, written in terms of the initial values of the
+//! registers it read, and a [Precondition] combines them into a
+//! single disjunction, e.g.
+//!
+//! ```text
+//! 4 of 6 paths complete without error when
+//!   (= (bvand X1 #x0000000000000007) #x0000000000000000)
+//!   (not (= PSTATE.EL #b00))
+//! ```
+//!
+//! To keep the precondition readable it is simplified by merging
+//! paths which differ only in the polarity of a single condition,
+//! dropping paths subsumed by weaker ones, and factoring out the
+//! conditions common to every path. Assumptions, such as user
+//! supplied register constraints, are not included as they hold on
+//! every path.
+
+use crossbeam::queue::SegQueue;
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::bitvector::BV;
+use crate::error::ExecError;
+use crate::executor::{Backtrace, LocalFrame};
+use crate::ir::{SharedState, Symtab, Val};
+use crate::simplify;
+use crate::smt::smtlib::{Def, Exp};
+use crate::smt::{register_name_string, Event, Solver};
+use crate::summary::Namer;
+
+/// A single condition on a path, written in terms of the initial
+/// register values. Negations are kept separate from the condition
+/// itself so that paths which differ only in the polarity of one
+/// condition can be merged.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Literal {
+    positive: bool,
+    atom: String,
+}
+
+impl Literal {
+    fn negate(&self) -> Self {
+        Literal { positive: !self.positive, atom: self.atom.clone() }
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.positive {
+            write!(f, "{}", self.atom)
+        } else {
+            write!(f, "(not {})", self.atom)
+        }
+    }
+}
+
+/// The conjunction of conditions which hold on a path
+pub type PathCondition = BTreeSet<Literal>;
+
+fn render(exp: &Exp<String>) -> String {
+    let mut buf = Vec::new();
+    simplify::write_named_exp(&mut buf, exp).unwrap();
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Split an expression into the literals of a conjunction, pushing
+/// negations inwards through conjunctions and disjunctions
+fn add_literals(exp: Exp<String>, positive: bool, condition: &mut PathCondition) {
+    match exp {
+        Exp::Bool(b) if b == positive => (),
+        Exp::Not(exp) => add_literals(*exp, !positive, condition),
+        Exp::Neq(lhs, rhs) => add_literals(Exp::Eq(lhs, rhs), !positive, condition),
+        Exp::And(lhs, rhs) if positive => {
+            add_literals(*lhs, positive, condition);
+            add_literals(*rhs, positive, condition)
+        }
+        Exp::Or(lhs, rhs) if !positive => {
+            add_literals(*lhs, positive, condition);
+            add_literals(*rhs, positive, condition)
+        }
+        exp => {
+            condition.insert(Literal { positive, atom: render(&exp) });
+        }
+    }
+}
+
+/// The path condition of a path from its events, which should be in
+/// the order they occurred. Only the first read of each register
+/// before it is written is named after it, so the condition is in
+/// terms of the initial state.
+pub fn path_condition<B: BV, E: Borrow<Event<B>>>(events: &[E], symtab: &Symtab) -> PathCondition {
+    let mut namer = Namer::new(events);
+    let mut written = HashSet::new();
+    let mut assertions = Vec::new();
+
+    for event in events {
+        let event = event.borrow();
+        match event {
+            Event::Smt(Def::DefineConst(v, exp), _, _) => {
+                namer.defs.insert(*v, exp);
+            }
+            Event::Smt(def, _, _) if !def.is_assumption() => {
+                if let Some(exp) = def.assertion() {
+                    assertions.push(exp)
+                }
+            }
+            Event::ReadReg(reg, _, val) if !written.contains(reg) => {
+                if let Some(name) = register_name_string(event, symtab) {
+                    namer.name_val(&name, val, symtab)
+                }
+            }
+            Event::WriteReg(reg, _, _) => {
+                written.insert(*reg);
+            }
+            _ => (),
+        }
+    }
+
+    let mut condition = PathCondition::new();
+    for exp in assertions {
+        if let Ok(exp) = exp.map_var(&mut |v| Ok::<_, String>(namer.expand(*v, 0))) {
+            add_literals(exp.eval(), true, &mut condition)
+        }
+    }
+    condition
+}
+
+/// The path condition of each path which completed (either by
+/// returning or exiting), or `None` for paths which ended with an
+/// error
+pub type PreconditionQueue = SegQueue<Option<PathCondition>>;
+
+/// A collector which records the path condition of each path, which
+/// can then be combined using [Precondition::from_queue].
+pub fn precondition_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
+    collected: &PreconditionQueue,
+) {
+    match result {
+        Ok(_) | Err((ExecError::Exit, _)) => {
+            let mut events = solver.trace().to_vec();
+            events.reverse();
+            collected.push(Some(path_condition(&events, &shared_state.symtab)))
+        }
+        Err((ExecError::Dead, _)) => (),
+        Err(_) => collected.push(None),
+    }
+}
+
+/// If two path conditions can be replaced by a single one, return it.
+/// A condition subsumes any stronger one, and two conditions which
+/// differ only in the polarity of a single literal are combined.
+fn merge_conditions(p: &PathCondition, q: &PathCondition) -> Option<PathCondition> {
+    if p.is_subset(q) {
+        return Some(p.clone());
+    }
+    if q.is_subset(p) {
+        return Some(q.clone());
+    }
+    let mut only_p = p.difference(q);
+    let mut only_q = q.difference(p);
+    match (only_p.next(), only_p.next(), only_q.next(), only_q.next()) {
+        (Some(l), None, Some(m), None) if l.negate() == *m => Some(p.intersection(q).cloned().collect()),
+        _ => None,
+    }
+}
+
+/// The disjunction of the path conditions of every path which
+/// completed without error
+pub struct Precondition {
+    /// Literals which hold on every completed path
+    common: PathCondition,
+    /// The remaining literals for each completed path, empty if the
+    /// common literals are sufficient
+    cases: Vec<PathCondition>,
+    completed: usize,
+    total: usize,
+}
+
+impl Precondition {
+    pub fn from_conditions<I: IntoIterator<Item = Option<PathCondition>>>(conditions: I) -> Self {
+        let mut total = 0;
+        let mut completed = 0;
+        let mut paths: BTreeSet<PathCondition> = BTreeSet::new();
+        for condition in conditions {
+            total += 1;
+            if let Some(condition) = condition {
+                completed += 1;
+                paths.insert(condition);
+            }
+        }
+
+        // Repeatedly merge pairs of paths until no more can be merged
+        loop {
+            let mut merged = None;
+            'search: for (i, p) in paths.iter().enumerate() {
+                for q in paths.iter().skip(i + 1) {
+                    if let Some(pq) = merge_conditions(p, q) {
+                        merged = Some((p.clone(), q.clone(), pq));
+                        break 'search;
+                    }
+                }
+            }
+            match merged {
+                Some((p, q, pq)) => {
+                    paths.remove(&p);
+                    paths.remove(&q);
+                    paths.insert(pq);
+                }
+                None => break,
+            }
+        }
+
+        let common = paths.iter().fold(None, |common: Option<PathCondition>, path| match common {
+            None => Some(path.clone()),
+            Some(common) => Some(common.intersection(path).cloned().collect()),
+        });
+        let common = common.unwrap_or_default();
+        let cases = if paths.len() > 1 {
+            paths.iter().map(|path| path.difference(&common).cloned().collect()).collect()
+        } else {
+            Vec::new()
+        };
+
+        Precondition { common, cases, completed, total }
+    }
+
+    pub fn from_queue(queue: &PreconditionQueue) -> Self {
+        let mut conditions = Vec::new();
+        while let Some(condition) = queue.pop() {
+            conditions.push(condition)
+        }
+        Self::from_conditions(conditions)
+    }
+
+    /// Literals which must hold for any path to complete
+    pub fn common(&self) -> &PathCondition {
+        &self.common
+    }
+
+    /// The alternative conditions, one of which must hold in addition
+    /// to the common literals. Empty if the common literals alone are
+    /// sufficient.
+    pub fn cases(&self) -> &[PathCondition] {
+        &self.cases
+    }
+
+    /// The number of paths which completed without error
+    pub fn num_completed(&self) -> usize {
+        self.completed
+    }
+
+    pub fn num_paths(&self) -> usize {
+        self.total
+    }
+}
+
+impl fmt::Display for Precondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.completed == 0 {
+            return writeln!(f, "none of {} paths complete without error", self.total);
+        }
+        writeln!(f, "{} of {} paths complete without error when", self.completed, self.total)?;
+        if self.common.is_empty() && self.cases.is_empty() {
+            writeln!(f, "  true")?
+        }
+        for literal in &self.common {
+            writeln!(f, "  {}", literal)?
+        }
+        if !self.cases.is_empty() {
+            writeln!(f, "and one of")?;
+            for case in &self.cases {
+                let literals: Vec<String> = case.iter().map(Literal::to_string).collect();
+                if literals.len() == 1 {
+                    writeln!(f, "  {}", literals[0])?
+                } else {
+                    writeln!(f, "  (and {})", literals.join(" "))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::{AssertionKind, Ty};
+    use crate::smt::{DefAttrs, Sym};
+    use crate::source_loc::SourceLoc;
+
+    fn lit(positive: bool, atom: &str) -> Literal {
+        Literal { positive, atom: atom.to_string() }
+    }
+
+    fn cond(literals: &[Literal]) -> Option<PathCondition> {
+        Some(literals.iter().cloned().collect())
+    }
+
+    #[test]
+    fn initial_register_conditions() {
+        let mut symtab = Symtab::new();
+        let (r1, r2) = (symtab.intern("R1"), symtab.intern("R2"));
+        let (x, y, z, w) = (Sym::from_u32(0), Sym::from_u32(1), Sym::from_u32(2), Sym::from_u32(3));
+        let smt = |def| Event::Smt(def, DefAttrs::default(), SourceLoc::unknown());
+        let bits = |n| Box::new(Exp::Bits64(B64::new(n, 64)));
+        let aligned = Exp::Eq(Box::new(Exp::Var(y)), bits(0));
+        let events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(x, Ty::BitVec(64))),
+            Event::ReadReg(r1, vec![], Val::Symbolic(x)),
+            smt(Def::NamedAssert(
+                "thread0_reset_constraint_0".to_string(),
+                AssertionKind::Assumption,
+                Exp::Bool(false),
+            )),
+            smt(Def::DefineConst(y, Exp::Bvand(Box::new(Exp::Var(x)), bits(7)))),
+            smt(Def::Assert(Exp::And(Box::new(aligned), Box::new(Exp::Bool(true))))),
+            smt(Def::DeclareConst(z, Ty::BitVec(64))),
+            Event::ReadReg(r2, vec![], Val::Symbolic(z)),
+            Event::WriteReg(r2, vec![], Val::Bits(B64::new(1, 64))),
+            smt(Def::DeclareConst(w, Ty::BitVec(64))),
+            Event::ReadReg(r2, vec![], Val::Symbolic(w)),
+            smt(Def::Assert(Exp::Not(Box::new(Exp::Neq(Box::new(Exp::Var(z)), Box::new(Exp::Var(w))))))),
+        ];
+        let condition = path_condition(&events, &symtab);
+        let expected: PathCondition =
+            vec![lit(true, "(= (bvand R1 #x0000000000000007) #x0000000000000000)"), lit(true, "(= R2 v3)")]
+                .into_iter()
+                .collect();
+        assert_eq!(condition, expected);
+    }
+
+    #[test]
+    fn simplify_preconditions() {
+        let (a, b, c) = (lit(true, "a"), lit(true, "b"), lit(true, "c"));
+        let precondition = Precondition::from_conditions(vec![
+            cond(&[a.clone(), b.clone()]),
+            cond(&[a.clone(), b.negate(), c.clone()]),
+            None,
+            cond(&[a.clone(), b.negate(), c.negate()]),
+        ]);
+        assert_eq!(precondition.num_completed(), 3);
+        assert!(precondition.cases().is_empty());
+        assert_eq!(precondition.to_string(), "3 of 4 paths complete without error when\n  a\n");
+
+        let precondition =
+            Precondition::from_conditions(vec![cond(&[a.clone(), b.clone()]), cond(&[a.clone(), c.negate()]), None]);
+        assert_eq!(
+            precondition.to_string(),
+            "2 of 3 paths complete without error when\n  a\nand one of\n  (not c)\n  b\n"
+        );
+
+        let precondition = Precondition::from_conditions(vec![cond(&[a.clone(), b]), cond(&[]), cond(&[a, c])]);
+        assert_eq!(precondition.to_string(), "3 of 3 paths complete without error when\n  true\n");
+
+        let precondition = Precondition::from_conditions(vec![None, None]);
+        assert_eq!(precondition.to_string(), "none of 2 paths complete without error\n");
+    }
+}
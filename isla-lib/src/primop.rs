@@ -43,7 +43,7 @@
 #![allow(clippy::cognitive_complexity)]
 
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::ops::{Not, Shl, Shr};
 use std::str::FromStr;
@@ -55,11 +55,13 @@ use crate::executor::LocalFrame;
 use crate::ir::{BitsSegment, UVal, Val, ELF_ENTRY};
 use crate::primop_util::*;
 use crate::smt::smtlib::*;
+use crate::smt::unknown::QuerySite;
 use crate::smt::*;
 use crate::source_loc::SourceLoc;
 
 pub mod float;
 pub mod memory;
+pub mod unimplemented;
 
 pub type Unary<B> = fn(Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError>;
 pub type Binary<B> = fn(Val<B>, Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError>;
@@ -179,7 +181,7 @@ fn optimistic_assert<B: BV>(
     match x {
         Val::Symbolic(v) => {
             let test_true = Box::new(Exp::Var(v));
-            let can_be_true = solver.check_sat_with(&test_true).is_sat()?;
+            let can_be_true = solver.is_possible(QuerySite::Assertion, Some(&test_true))?;
             if can_be_true {
                 solver.add(Def::Assert(Exp::Var(v)));
                 Ok(Val::Unit)
@@ -212,7 +214,7 @@ fn pessimistic_assert<B: BV>(
     match x {
         Val::Symbolic(v) => {
             let test_false = Exp::Not(Box::new(Exp::Var(v)));
-            let can_be_false = solver.check_sat_with(&test_false).is_sat()?;
+            let can_be_false = solver.is_possible(QuerySite::Assertion, Some(&test_false))?;
             if can_be_false {
                 Err(ExecError::AssertionFailure(message, info))
             } else {
@@ -230,6 +232,14 @@ fn pessimistic_assert<B: BV>(
     }
 }
 
+// The assertion is neither assumed nor checked
+fn ignore_assert<B: BV>(x: Val<B>, _: Val<B>, _: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
+    match x {
+        Val::Symbolic(_) | Val::Bool(_) => Ok(Val::Unit),
+        _ => Err(ExecError::Type(format!("ignore_assert {:?}", &x), info)),
+    }
+}
+
 // Conversion functions
 
 fn i64_to_i128<B: BV>(x: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
@@ -1244,14 +1254,20 @@ pub fn shift_bits_right<B: BV>(
     let shift_len = length_bits(&shift, solver, info)?;
     match (&bits, &shift) {
         (Val::Symbolic(_), Val::Symbolic(_)) | (Val::Bits(_), Val::Symbolic(_)) | (Val::Symbolic(_), Val::Bits(_)) => {
-            let shift = if bits_len < shift_len {
-                Exp::Extract(bits_len - 1, 0, Box::new(smt_value(&shift, info)?))
-            } else if bits_len > shift_len {
-                Exp::ZeroExtend(bits_len - shift_len, Box::new(smt_value(&shift, info)?))
+            if bits_len < shift_len {
+                // Shift at the width of the shift amount, as truncating
+                // it could turn a large shift into a small one
+                let bits = Exp::ZeroExtend(shift_len - bits_len, Box::new(smt_value(&bits, info)?));
+                let shifted = Exp::Bvlshr(Box::new(bits), Box::new(smt_value(&shift, info)?));
+                solver.define_const(Exp::Extract(bits_len - 1, 0, Box::new(shifted)), info).into()
             } else {
-                smt_value(&shift, info)?
-            };
-            solver.define_const(Exp::Bvlshr(Box::new(smt_value(&bits, info)?), Box::new(shift)), info).into()
+                let shift = if bits_len > shift_len {
+                    Exp::ZeroExtend(bits_len - shift_len, Box::new(smt_value(&shift, info)?))
+                } else {
+                    smt_value(&shift, info)?
+                };
+                solver.define_const(Exp::Bvlshr(Box::new(smt_value(&bits, info)?), Box::new(shift)), info).into()
+            }
         }
         (Val::Bits(x), Val::Bits(y)) => {
             let shift: u64 = (*y).try_into()?;
@@ -1274,14 +1290,20 @@ pub fn shift_bits_left<B: BV>(
     let shift_len = length_bits(&shift, solver, info)?;
     match (&bits, &shift) {
         (Val::Symbolic(_), Val::Symbolic(_)) | (Val::Bits(_), Val::Symbolic(_)) | (Val::Symbolic(_), Val::Bits(_)) => {
-            let shift = if bits_len < shift_len {
-                Exp::Extract(bits_len - 1, 0, Box::new(smt_value(&shift, info)?))
-            } else if bits_len > shift_len {
-                Exp::ZeroExtend(bits_len - shift_len, Box::new(smt_value(&shift, info)?))
+            if bits_len < shift_len {
+                // Shift at the width of the shift amount, as truncating
+                // it could turn a large shift into a small one
+                let bits = Exp::ZeroExtend(shift_len - bits_len, Box::new(smt_value(&bits, info)?));
+                let shifted = Exp::Bvshl(Box::new(bits), Box::new(smt_value(&shift, info)?));
+                solver.define_const(Exp::Extract(bits_len - 1, 0, Box::new(shifted)), info).into()
             } else {
-                smt_value(&shift, info)?
-            };
-            solver.define_const(Exp::Bvshl(Box::new(smt_value(&bits, info)?), Box::new(shift)), info).into()
+                let shift = if bits_len > shift_len {
+                    Exp::ZeroExtend(bits_len - shift_len, Box::new(smt_value(&shift, info)?))
+                } else {
+                    smt_value(&shift, info)?
+                };
+                solver.define_const(Exp::Bvshl(Box::new(smt_value(&bits, info)?), Box::new(shift)), info).into()
+            }
         }
         (Val::Bits(x), Val::Bits(y)) => {
             let shift: u64 = (*y).try_into()?;
@@ -1816,15 +1838,6 @@ fn unit_noop<B: BV>(
     Ok(Val::Unit)
 }
 
-fn unimplemented<B: BV>(
-    _: Vec<Val<B>>,
-    _: &mut Solver<B>,
-    _: &mut LocalFrame<B>,
-    _: SourceLoc,
-) -> Result<Val<B>, ExecError> {
-    Err(ExecError::Unimplemented)
-}
-
 fn eq_string<B: BV>(lhs: Val<B>, rhs: Val<B>, _: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
     match (lhs, rhs) {
         (Val::String(lhs), Val::String(rhs)) => Ok(Val::Bool(lhs == rhs)),
@@ -1923,6 +1936,12 @@ pub fn eq_anything<B: BV>(lhs: Val<B>, rhs: Val<B>, solver: &mut Solver<B>, info
             }
         }
         (Val::Unit, Val::Unit) => Ok(Val::Bool(true)),
+        (lhs @ Val::SymbolicCtor(_, _), rhs) | (lhs, rhs @ Val::SymbolicCtor(_, _)) => {
+            match lhs.eq_smt(&rhs, solver, info)? {
+                Exp::Bool(b) => Ok(Val::Bool(b)),
+                exp => solver.define_const(exp, info).into(),
+            }
+        }
 
         (lhs, rhs) => Err(ExecError::Type(format!("eq_anything {:?} {:?}", &lhs, &rhs), info)),
     }
@@ -2093,26 +2112,26 @@ fn prerr_bits<B: BV>(_message: Val<B>, _bits: Val<B>, _: &mut Solver<B>, _: Sour
 
 fn undefined_bitvector<B: BV>(sz: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
     if let Val::I128(sz) = sz {
-        solver.declare_const(Ty::BitVec(sz as u32), info).into()
+        solver.declare_undefined(Ty::BitVec(sz as u32), info).into()
     } else {
         Err(ExecError::Type(format!("undefined_bitvector {:?}", &sz), info))
     }
 }
 
 fn undefined_bit<B: BV>(_: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
-    solver.declare_const(Ty::BitVec(1), info).into()
+    solver.declare_undefined(Ty::BitVec(1), info).into()
 }
 
 fn undefined_bool<B: BV>(_: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
-    solver.declare_const(Ty::Bool, info).into()
+    solver.declare_undefined(Ty::Bool, info).into()
 }
 
 fn undefined_int<B: BV>(_: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
-    solver.declare_const(Ty::BitVec(128), info).into()
+    solver.declare_undefined(Ty::BitVec(128), info).into()
 }
 
 fn undefined_nat<B: BV>(_: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
-    let sym = solver.declare_const(Ty::BitVec(128), info);
+    let sym = solver.declare_undefined(Ty::BitVec(128), info);
     solver.add(Def::Assert(Exp::Bvsge(Box::new(Exp::Var(sym)), Box::new(smt_i128(0)))));
     Ok(Val::Symbolic(sym))
 }
@@ -2123,7 +2142,7 @@ fn undefined_range<B: BV>(
     solver: &mut Solver<B>,
     info: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
-    let sym = solver.declare_const(Ty::BitVec(128), info);
+    let sym = solver.declare_undefined(Ty::BitVec(128), info);
     solver.add(Def::Assert(Exp::Bvsle(Box::new(smt_value(&lo, info)?), Box::new(Exp::Var(sym)))));
     solver.add(Def::Assert(Exp::Bvsle(Box::new(Exp::Var(sym)), Box::new(smt_value(&hi, info)?))));
     Ok(Val::Symbolic(sym))
@@ -2183,12 +2202,53 @@ fn choice<B: BV>(xs: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<
     }
 }
 
+/// Check a memory access against the watchpoints, suspending the
+/// frame if a watchpoint asks for it. Accesses with a symbolic length
+/// are left for the memory to reject. When a frame suspended here is
+/// resumed, the access it was suspended before is not checked again,
+/// so it does not immediately suspend a second time.
+pub(crate) fn watch_access<B: BV>(
+    address: &Val<B>,
+    bytes: &Val<B>,
+    data: Option<&Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<bool, ExecError> {
+    let Val::I128(bytes) = bytes else {
+        return Ok(false)
+    };
+    if frame.memory().watchpoints().is_empty() || frame.take_resuming_watchpoint() {
+        return Ok(false);
+    }
+
+    let bytes = u32::try_from(*bytes).map_err(|_| ExecError::Overflow)?;
+    let mut backtrace = frame.backtrace().clone();
+    backtrace.push((frame.function_name(), frame.pc()));
+    let suspend = frame.memory().check_watchpoints(address, bytes, data, &backtrace, solver)?;
+    if suspend {
+        frame.suspend()
+    }
+    Ok(suspend)
+}
+
+/// Record an [Event::AddressAnnounce] for a data memory access,
+/// which is called once the executor has evaluated the address
+/// argument of a read or write primop, before the access itself.
+/// Instruction fetches are not announced.
+pub(crate) fn announce_address<B: BV>(address: &Val<B>, solver: &mut Solver<B>) {
+    solver.add_event(Event::AddressAnnounce { address: address.clone() })
+}
+
 fn read_mem<B: BV>(
     args: Vec<Val<B>>,
     solver: &mut Solver<B>,
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::default())
 }
 
@@ -2198,6 +2258,9 @@ fn read_mem_ifetch<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::ifetch())
 }
 
@@ -2207,6 +2270,10 @@ fn read_mem_exclusive<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::exclusive())
 }
 
@@ -2216,6 +2283,10 @@ fn read_memt<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[1], &args[2], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[1], solver);
     frame.memory().read(args[0].clone(), args[1].clone(), args[2].clone(), solver, true, ReadOpts::default())
 }
 
@@ -2229,6 +2300,10 @@ fn write_mem<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], Some(&args[4]), solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory_mut().write(args[0].clone(), args[2].clone(), args[4].clone(), solver, None, WriteOpts::default())
 }
 
@@ -2238,6 +2313,10 @@ fn write_mem_exclusive<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], Some(&args[4]), solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory_mut().write(args[0].clone(), args[2].clone(), args[4].clone(), solver, None, WriteOpts::exclusive())
 }
 
@@ -2247,6 +2326,10 @@ fn write_memt<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[1], &args[2], Some(&args[3]), solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[1], solver);
     frame.memory_mut().write(
         args[0].clone(),
         args[1].clone(),
@@ -2283,19 +2366,27 @@ fn get_verbosity<B: BV>(_: Val<B>, _: &mut Solver<B>, _: SourceLoc) -> Result<Va
     Ok(Val::Bits(B::zeros(64)))
 }
 
-fn sleeping<B: BV>(_: Val<B>, _solver: &mut Solver<B>, _: SourceLoc) -> Result<Val<B>, ExecError> {
-    // let sym = solver.fresh();
-    // solver.add(Def::DeclareConst(sym, Ty::Bool));
-    // solver.add_event(Event::Sleeping(sym));
-    // Ok(Val::Symbolic(sym))
-    Ok(Val::Bool(false))
+/// A hart is only ever sleeping after it has requested to sleep, in
+/// which case whether it actually sleeps is left symbolic for the
+/// scheduler to decide if [Solver::symbolic_sleep] is enabled, see
+/// [Event::Sleeping].
+fn sleeping<B: BV>(_: Val<B>, solver: &mut Solver<B>, info: SourceLoc) -> Result<Val<B>, ExecError> {
+    if solver.is_sleep_symbolic() && solver.sleep_requested() {
+        let sym = solver.declare_const(Ty::Bool, info);
+        solver.add_event(Event::Sleeping(sym));
+        Ok(Val::Symbolic(sym))
+    } else {
+        Ok(Val::Bool(false))
+    }
 }
 
-fn wakeup_request<B: BV>(_: Val<B>, _: &mut Solver<B>, _: SourceLoc) -> Result<Val<B>, ExecError> {
+fn wakeup_request<B: BV>(_: Val<B>, solver: &mut Solver<B>, _: SourceLoc) -> Result<Val<B>, ExecError> {
+    solver.add_event(Event::WakeupRequest);
     Ok(Val::Unit)
 }
 
-fn sleep_request<B: BV>(_: Val<B>, _: &mut Solver<B>, _: SourceLoc) -> Result<Val<B>, ExecError> {
+fn sleep_request<B: BV>(_: Val<B>, solver: &mut Solver<B>, _: SourceLoc) -> Result<Val<B>, ExecError> {
+    solver.add_event(Event::SleepRequest);
     Ok(Val::Unit)
 }
 
@@ -2314,6 +2405,20 @@ fn branch_announce<B: BV>(
     Ok(Val::Unit)
 }
 
+/// Implements the `platform_address_announce` builtin, which records
+/// an additional [Event::AddressAnnounce] at a point chosen by the
+/// model, such as before address translation (see
+/// [announce_address] for the events recorded at each access).
+fn address_announce<B: BV>(
+    _: Val<B>,
+    address: Val<B>,
+    solver: &mut Solver<B>,
+    _: SourceLoc,
+) -> Result<Val<B>, ExecError> {
+    solver.add_event(Event::AddressAnnounce { address });
+    Ok(Val::Unit)
+}
+
 fn synchronize_registers<B: BV>(
     _: Vec<Val<B>>,
     _: &mut Solver<B>,
@@ -2519,6 +2624,7 @@ pub fn binary_primops<B: BV>() -> HashMap<String, Binary<B>> {
     let mut primops = HashMap::new();
     primops.insert("optimistic_assert".to_string(), optimistic_assert as Binary<B>);
     primops.insert("pessimistic_assert".to_string(), pessimistic_assert as Binary<B>);
+    primops.insert("ignore_assert".to_string(), ignore_assert as Binary<B>);
     primops.insert("and_bool".to_string(), and_bool as Binary<B>);
     primops.insert("strict_and_bool".to_string(), and_bool as Binary<B>);
     primops.insert("or_bool".to_string(), or_bool as Binary<B>);
@@ -2584,6 +2690,7 @@ pub fn binary_primops<B: BV>() -> HashMap<String, Binary<B>> {
     primops.insert("print_bits".to_string(), print_bits as Binary<B>);
     primops.insert("prerr_bits".to_string(), prerr_bits as Binary<B>);
     primops.insert("platform_branch_announce".to_string(), branch_announce as Binary<B>);
+    primops.insert("platform_address_announce".to_string(), address_announce as Binary<B>);
     primops.insert("mark_register".to_string(), mark_register as Binary<B>);
     primops.extend(float::binary_primops());
     primops
@@ -2612,41 +2719,67 @@ pub fn variadic_primops<B: BV>() -> HashMap<String, Variadic<B>> {
     primops.insert("elf_entry".to_string(), elf_entry as Variadic<B>);
     primops.insert("ite".to_string(), primop_ite as Variadic<B>);
     primops.insert("mark_register_pair".to_string(), mark_register_pair as Variadic<B>);
-    // We explicitly don't handle anything real number related right now
-    primops.insert("%string->%real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("neg_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("mult_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("sub_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("add_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("div_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("sqrt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("abs_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("round_down".to_string(), unimplemented as Variadic<B>);
-    primops.insert("round_up".to_string(), unimplemented as Variadic<B>);
-    primops.insert("to_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("eq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("lt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("gt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("lteq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("gteq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("real_power".to_string(), unimplemented as Variadic<B>);
-    primops.insert("print_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("prerr_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("undefined_real".to_string(), unimplemented as Variadic<B>);
     primops.extend(float::variadic_primops());
     primops.extend(memory::variadic_primops());
     primops
 }
 
+/// A registry of primops, keyed by the name used in Sail `extern`
+/// declarations. The default registry contains all the builtin
+/// primops, and client crates can register additional (or override
+/// existing) primops before initializing an architecture with
+/// [crate::init::initialize_architecture_with_primops].
 pub struct Primops<B> {
     pub unary: HashMap<String, Unary<B>>,
     pub binary: HashMap<String, Binary<B>>,
     pub variadic: HashMap<String, Variadic<B>>,
+    /// Primops which are known to be unimplemented. Calls to them
+    /// fail with [ExecError::UnimplementedPrimop] without the warning
+    /// given for unknown primops.
+    pub unimplemented: HashSet<String>,
+}
+
+impl<B: BV> Primops<B> {
+    /// Register a unary primop, returning any previous primop with the same name
+    pub fn register_unary<S: Into<String>>(&mut self, name: S, f: Unary<B>) -> Option<Unary<B>> {
+        let name = name.into();
+        self.binary.remove(&name);
+        self.variadic.remove(&name);
+        self.unimplemented.remove(&name);
+        self.unary.insert(name, f)
+    }
+
+    /// Register a binary primop, returning any previous primop with the same name
+    pub fn register_binary<S: Into<String>>(&mut self, name: S, f: Binary<B>) -> Option<Binary<B>> {
+        let name = name.into();
+        self.unary.remove(&name);
+        self.variadic.remove(&name);
+        self.unimplemented.remove(&name);
+        self.binary.insert(name, f)
+    }
+
+    /// Register a variadic primop, returning any previous primop with the same name
+    pub fn register_variadic<S: Into<String>>(&mut self, name: S, f: Variadic<B>) -> Option<Variadic<B>> {
+        let name = name.into();
+        self.unary.remove(&name);
+        self.binary.remove(&name);
+        self.unimplemented.remove(&name);
+        self.variadic.insert(name, f)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.unary.contains_key(name) || self.binary.contains_key(name) || self.variadic.contains_key(name)
+    }
 }
 
 impl<B: BV> Default for Primops<B> {
     fn default() -> Self {
-        Primops { unary: unary_primops(), binary: binary_primops(), variadic: variadic_primops() }
+        Primops {
+            unary: unary_primops(),
+            binary: binary_primops(),
+            variadic: variadic_primops(),
+            unimplemented: unimplemented::unimplemented_primops(),
+        }
     }
 }
 
@@ -2655,11 +2788,194 @@ mod tests {
     use super::*;
     use crate::bitvector::b64::B64;
     use crate::error::ExecError;
-    use crate::ir::{BitsSegment, Val};
+    use crate::ir::{BitsSegment, Name, Val};
     use crate::smt::smtlib::Ty;
     use crate::smt::{Config, Context, SmtResult, Solver};
     use crate::source_loc::SourceLoc;
 
+    use crate::bitvector::strategy;
+    use proptest::prelude::*;
+
+    fn symbolic_bits(bv: B64, solver: &mut Solver<B64>) -> Val<B64> {
+        let v = solver.declare_const(Ty::BitVec(bv.len()), SourceLoc::unknown());
+        solver.add(Def::Assert(Exp::Eq(Box::new(Exp::Var(v)), Box::new(smt_sbits(bv)))));
+        Val::Symbolic(v)
+    }
+
+    // Check that the result of a primop on symbolic arguments must
+    // equal the result of the same primop on concrete arguments.
+    // Cases where either side is unsupported are not compared.
+    fn agrees(
+        concrete: Result<Val<B64>, ExecError>,
+        symbolic: Result<Val<B64>, ExecError>,
+        solver: &mut Solver<B64>,
+    ) -> bool {
+        let (Ok(concrete), Ok(symbolic)) = (concrete, symbolic) else { return true };
+        match symbolic {
+            Val::Symbolic(v) => {
+                let Ok(exp) = smt_value(&concrete, SourceLoc::unknown()) else { return true };
+                solver.add(Def::Assert(Exp::Not(Box::new(Exp::Eq(Box::new(Exp::Var(v)), Box::new(exp))))));
+                solver.check_sat() == SmtResult::Unsat
+            }
+            symbolic => symbolic == concrete,
+        }
+    }
+
+    const UNARY_BITS_PRIMOPS: [&str; 5] = ["not_bits", "length", "sail_unsigned", "sail_signed", "count_leading_zeros"];
+
+    const BINARY_BITS_PRIMOPS: [&str; 10] = [
+        "eq_bits",
+        "neq_bits",
+        "xor_bits",
+        "or_bits",
+        "and_bits",
+        "add_bits",
+        "sub_bits",
+        "shift_bits_right",
+        "shift_bits_left",
+        "append",
+    ];
+
+    // Primops whose operands do not need to have the same length
+    const MIXED_WIDTH_BITS_PRIMOPS: [&str; 3] = ["shift_bits_right", "shift_bits_left", "append"];
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn unary_bits_primops_agree_with_smt(x in strategy::bits::<B64>(1..=64)) {
+            let primops = unary_primops::<B64>();
+            for name in UNARY_BITS_PRIMOPS {
+                let cfg = Config::new();
+                let ctx = Context::new(cfg);
+                let mut solver = Solver::<B64>::new(&ctx);
+                let f = primops[name];
+                let concrete = f(Val::Bits(x), &mut solver, SourceLoc::unknown());
+                let sym_x = symbolic_bits(x, &mut solver);
+                let symbolic = f(sym_x, &mut solver, SourceLoc::unknown());
+                prop_assert!(agrees(concrete, symbolic, &mut solver), "{} disagrees with SMT on {:?}", name, x)
+            }
+        }
+
+        #[test]
+        fn mixed_width_bits_primops_agree_with_smt((x, y) in strategy::bits_pair_of_lens::<B64>(1..=32, 1..=32)) {
+            let primops = binary_primops::<B64>();
+            for name in MIXED_WIDTH_BITS_PRIMOPS {
+                let f = primops[name];
+                for mixed in [false, true] {
+                    let cfg = Config::new();
+                    let ctx = Context::new(cfg);
+                    let mut solver = Solver::<B64>::new(&ctx);
+                    let concrete = f(Val::Bits(x), Val::Bits(y), &mut solver, SourceLoc::unknown());
+                    let sym_x = if mixed { Val::Bits(x) } else { symbolic_bits(x, &mut solver) };
+                    let sym_y = symbolic_bits(y, &mut solver);
+                    let symbolic = f(sym_x, sym_y, &mut solver, SourceLoc::unknown());
+                    prop_assert!(
+                        agrees(concrete, symbolic, &mut solver),
+                        "{} disagrees with SMT on {:?} and {:?}",
+                        name,
+                        x,
+                        y
+                    )
+                }
+            }
+        }
+
+        #[test]
+        fn binary_bits_primops_agree_with_smt((x, y) in strategy::bits_pair::<B64>(1..=64)) {
+            let primops = binary_primops::<B64>();
+            for name in BINARY_BITS_PRIMOPS {
+                let f = primops[name];
+                // Compare both fully symbolic arguments, and mixed
+                // concrete and symbolic arguments
+                for mixed in [false, true] {
+                    let cfg = Config::new();
+                    let ctx = Context::new(cfg);
+                    let mut solver = Solver::<B64>::new(&ctx);
+                    let concrete = f(Val::Bits(x), Val::Bits(y), &mut solver, SourceLoc::unknown());
+                    let sym_x = if mixed { Val::Bits(x) } else { symbolic_bits(x, &mut solver) };
+                    let sym_y = symbolic_bits(y, &mut solver);
+                    let symbolic = f(sym_x, sym_y, &mut solver, SourceLoc::unknown());
+                    prop_assert!(
+                        agrees(concrete, symbolic, &mut solver),
+                        "{} disagrees with SMT on {:?} and {:?}",
+                        name,
+                        x,
+                        y
+                    )
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn eq_smt_structural() -> Result<(), ExecError> {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let info = SourceLoc::unknown();
+        let x = solver.declare_const(Ty::BitVec(4), info);
+
+        let lhs = Val::Vector(vec![Val::Bits(B64::new(0b1010, 4)), Val::Symbolic(x)]);
+        let rhs = Val::Vector(vec![Val::Bits(B64::new(0b1010, 4)), Val::Bits(B64::new(0b0011, 4))]);
+        let eq = lhs.eq_smt(&rhs, &mut solver, info)?;
+        assert!(!matches!(eq, Exp::Bool(_)));
+        solver.assert(eq);
+        solver.assert(Exp::Neq(Box::new(Exp::Var(x)), Box::new(bits64(0b0011, 4))));
+        assert!(solver.check_sat() == SmtResult::Unsat);
+
+        let rhs = Val::Vector(vec![Val::Bits(B64::new(0b1011, 4)), Val::Symbolic(x)]);
+        assert!(matches!(lhs.eq_smt(&rhs, &mut solver, info)?, Exp::Bool(false)));
+
+        let short = Val::Bits(B64::new(0b1010, 3));
+        assert!(Val::Bits(B64::new(0b1010, 4)).eq_smt(&short, &mut solver, info).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn symbolic_ctor_follows_branch() -> Result<(), ExecError> {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let info = SourceLoc::unknown();
+        let ctor_a = Name::from_u32(1);
+        let ctor_b = Name::from_u32(2);
+        let b = solver.declare_const(Ty::Bool, info);
+
+        let lhs = Val::Ctor(ctor_a, Box::new(Val::Bits(B64::new(1, 8))));
+        let rhs = Val::Ctor(ctor_b, Box::new(Val::Unit));
+        let merged = build_ite(b, &lhs, &rhs, &mut solver, info)?;
+        assert!(matches!(merged, Val::SymbolicCtor(_, _)));
+
+        // If the branch was taken, the merged value must be equal to the left hand side
+        let eq = match eq_anything(merged, lhs, &mut solver, info)? {
+            Val::Symbolic(eq) => eq,
+            v => panic!("expected symbolic equality, got {:?}", v),
+        };
+        solver.assert(Exp::Var(b));
+        solver.assert(Exp::Not(Box::new(Exp::Var(eq))));
+        assert!(solver.check_sat() == SmtResult::Unsat);
+        Ok(())
+    }
+
+    #[test]
+    fn shift_by_wider_amount() {
+        // Shifting a 4-bit value by 16 clears it, rather than
+        // shifting by the 0 in the low bits of the shift amount
+        let x = B64::new(0b1011, 4);
+        let y = B64::new(16, 8);
+        for f in [shift_bits_right::<B64>, shift_bits_left::<B64>] {
+            let cfg = Config::new();
+            let ctx = Context::new(cfg);
+            let mut solver = Solver::<B64>::new(&ctx);
+            let sym_y = symbolic_bits(y, &mut solver);
+            let concrete = f(Val::Bits(x), Val::Bits(y), &mut solver, SourceLoc::unknown());
+            assert_eq!(concrete.as_ref().unwrap(), &Val::Bits(B64::new(0, 4)));
+            let symbolic = f(Val::Bits(x), sym_y, &mut solver, SourceLoc::unknown());
+            assert!(agrees(concrete, symbolic, &mut solver))
+        }
+    }
+
     #[test]
     fn mixed_bits() -> Result<(), ExecError> {
         let cfg = Config::new();
@@ -2729,4 +3045,117 @@ mod tests {
         assert!(solver.check_sat() == SmtResult::Sat);
         Ok(())
     }
+
+    #[test]
+    fn assertion_modes() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let info = SourceLoc::unknown();
+        let v = solver.declare_const(Ty::Bool, info);
+        let msg = Val::String("assertion_modes".to_string());
+
+        assert!(pessimistic_assert(Val::Symbolic(v), msg.clone(), &mut solver, info).is_err());
+        assert!(ignore_assert(Val::Symbolic(v), msg.clone(), &mut solver, info).is_ok());
+        assert!(solver.check_sat_with(&Exp::Not(Box::new(Exp::Var(v)))) == SmtResult::Sat);
+        assert!(optimistic_assert(Val::Symbolic(v), msg, &mut solver, info).is_ok());
+        assert!(solver.check_sat_with(&Exp::Not(Box::new(Exp::Var(v)))) == SmtResult::Unsat)
+    }
+
+    fn custom_intrinsic(_: Val<B64>, _: &mut Solver<B64>, _: SourceLoc) -> Result<Val<B64>, ExecError> {
+        Ok(Val::Unit)
+    }
+
+    #[test]
+    fn register_custom_primop() {
+        let mut primops = Primops::<B64>::default();
+        assert!(!primops.contains("custom_intrinsic"));
+        assert!(primops.register_unary("custom_intrinsic", custom_intrinsic).is_none());
+        assert!(primops.unary.contains_key("custom_intrinsic"));
+
+        // Overriding an existing primop with a different arity removes the old one
+        assert!(primops.binary.contains_key("eq_bits"));
+        primops.register_unary("eq_bits", custom_intrinsic);
+        assert!(!primops.binary.contains_key("eq_bits"));
+        assert!(primops.unary.contains_key("eq_bits"));
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordHits(std::sync::Mutex<Vec<(Val<B64>, bool)>>);
+
+    impl crate::memory::WatchpointHandler<B64> for RecordHits {
+        fn hit(&self, hit: &crate::memory::WatchpointHit<'_, B64>, _: &mut Solver<B64>) {
+            self.0.lock().unwrap().push((hit.address.clone(), hit.data.is_some()))
+        }
+    }
+
+    fn watched_frame(watchpoint: crate::memory::Watchpoint) -> (LocalFrame<'static, B64>, std::sync::Arc<RecordHits>) {
+        use crate::ir::Name;
+        let hits = std::sync::Arc::new(RecordHits::default());
+        let mut frame = LocalFrame::new(Name::from_u32(0), &[], &crate::ir::Ty::Unit, None, &[]);
+        frame.memory_mut().add_watchpoint(watchpoint);
+        frame.memory_mut().set_watchpoint_handler(hits.clone());
+        (frame, hits)
+    }
+
+    #[test]
+    fn watchpoint_concrete_hits() {
+        use crate::memory::{WatchAction, Watchpoint};
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let (mut frame, hits) = watched_frame(Watchpoint::writes(0x1000..0x1010, WatchAction::Report));
+        let bytes = Val::I128(4);
+        let data = Val::Bits(B64::new(0, 32));
+
+        // Reads are not watched, and accesses ending before the range do not hit
+        let inside = Val::Bits(B64::new(0x100c, 64));
+        assert!(!watch_access(&inside, &bytes, None, &mut solver, &mut frame).unwrap());
+        let before = Val::Bits(B64::new(0xffc, 64));
+        assert!(!watch_access(&before, &bytes, Some(&data), &mut solver, &mut frame).unwrap());
+        assert!(hits.0.lock().unwrap().is_empty());
+
+        // An access straddling the start of the range hits
+        let straddling = Val::Bits(B64::new(0xffe, 64));
+        assert!(!watch_access(&straddling, &bytes, Some(&data), &mut solver, &mut frame).unwrap());
+        assert_eq!(*hits.0.lock().unwrap(), vec![(straddling, true)]);
+        assert!(!frame.is_suspended());
+
+        let too_large = Val::I128(i128::from(u32::MAX) + 1);
+        assert!(matches!(
+            watch_access(&inside, &too_large, Some(&data), &mut solver, &mut frame),
+            Err(ExecError::Overflow)
+        ))
+    }
+
+    #[test]
+    fn watchpoint_symbolic_hits() {
+        use crate::memory::{WatchAction, Watchpoint};
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let (mut frame, hits) = watched_frame(Watchpoint::new(0x1000..0x1010, WatchAction::Report));
+        let bytes = Val::I128(8);
+
+        let v = solver.declare_const(Ty::BitVec(64), SourceLoc::unknown());
+        let limit = Exp::Bits64(B64::new(0x2000, 64));
+        solver.add(Def::Assert(Exp::Bvuge(Box::new(Exp::Var(v)), Box::new(limit))));
+        assert!(!watch_access(&Val::Symbolic(v), &bytes, None, &mut solver, &mut frame).unwrap());
+        assert!(hits.0.lock().unwrap().is_empty());
+
+        let w = solver.declare_const(Ty::BitVec(64), SourceLoc::unknown());
+        assert!(!watch_access(&Val::Symbolic(w), &bytes, None, &mut solver, &mut frame).unwrap());
+        assert_eq!(*hits.0.lock().unwrap(), vec![(Val::Symbolic(w), false)])
+    }
+
+    #[test]
+    fn watchpoint_suspend() {
+        use crate::memory::{WatchAction, Watchpoint};
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let (mut frame, hits) = watched_frame(Watchpoint::new(0x1000..0x1010, WatchAction::Suspend));
+        let address = Val::Bits(B64::new(0x1008, 64));
+
+        assert!(watch_access(&address, &Val::I128(1), None, &mut solver, &mut frame).unwrap());
+        assert!(frame.is_suspended());
+        assert_eq!(hits.0.lock().unwrap().len(), 1)
+    }
 }
@@ -39,7 +39,7 @@ use crate::ir::Val;
 use crate::smt::*;
 use crate::source_loc::SourceLoc;
 
-use super::Variadic;
+use super::{announce_address, watch_access, Variadic};
 
 fn read_mem<B: BV>(
     args: Vec<Val<B>>,
@@ -47,6 +47,10 @@ fn read_mem<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::default())
 }
 
@@ -56,6 +60,9 @@ fn read_mem_ifetch<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::ifetch())
 }
 
@@ -65,6 +72,10 @@ fn read_mem_exclusive<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], None, solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, ReadOpts::exclusive())
 }
 
@@ -74,6 +85,10 @@ fn write_mem<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], Some(&args[4]), solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory_mut().write(args[0].clone(), args[2].clone(), args[4].clone(), solver, None, WriteOpts::default())
 }
 
@@ -83,6 +98,10 @@ fn write_mem_exclusive<B: BV>(
     frame: &mut LocalFrame<B>,
     _: SourceLoc,
 ) -> Result<Val<B>, ExecError> {
+    if watch_access(&args[2], &args[3], Some(&args[4]), solver, frame)? {
+        return Ok(Val::Poison);
+    }
+    announce_address(&args[2], solver);
     frame.memory_mut().write(args[0].clone(), args[2].clone(), args[4].clone(), solver, None, WriteOpts::exclusive())
 }
 
@@ -0,0 +1,125 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module lists the Sail primitives which are known but not
+//! implemented, and counts the paths which fail because they reach
+//! an unimplemented primitive, so a run can report every missing
+//! primitive at once rather than one failing path at a time.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Primitives which we deliberately do not implement. We don't
+/// handle anything real number related right now.
+pub(crate) fn unimplemented_primops() -> HashSet<String> {
+    [
+        "%string->%real",
+        "neg_real",
+        "mult_real",
+        "sub_real",
+        "add_real",
+        "div_real",
+        "sqrt_real",
+        "abs_real",
+        "round_down",
+        "round_up",
+        "to_real",
+        "eq_real",
+        "lt_real",
+        "gt_real",
+        "lteq_real",
+        "gteq_real",
+        "real_power",
+        "print_real",
+        "prerr_real",
+        "undefined_real",
+    ]
+    .iter()
+    .map(|name| name.to_string())
+    .collect()
+}
+
+/// Counts of the paths killed by each unimplemented primitive, which
+/// can be shared between threads
+#[derive(Debug, Default)]
+pub struct UnimplementedStats {
+    paths: Mutex<HashMap<String, usize>>,
+}
+
+impl UnimplementedStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&self, primop: &str) {
+        *self.paths.lock().unwrap().entry(primop.to_string()).or_insert(0) += 1
+    }
+
+    /// Returns true if no path has reached an unimplemented primitive
+    pub fn is_empty(&self) -> bool {
+        self.paths.lock().unwrap().is_empty()
+    }
+
+    /// Returns each unimplemented primitive that was reached with the
+    /// number of paths it killed, most frequent first.
+    pub fn summary(&self) -> Vec<(String, usize)> {
+        let mut summary: Vec<(String, usize)> =
+            self.paths.lock().unwrap().iter().map(|(primop, paths)| (primop.clone(), *paths)).collect();
+        summary.sort_by(|(p1, n1), (p2, n2)| n2.cmp(n1).then_with(|| p1.cmp(p2)));
+        summary
+    }
+
+    /// A table of the unimplemented primitives that were reached, and
+    /// how many paths each killed
+    pub fn report(&self) -> String {
+        let summary = self.summary();
+        let width = summary.iter().map(|(primop, _)| primop.len()).max().unwrap_or(0).max("primop".len());
+        let mut report = format!("{:<width$} paths\n", "primop", width = width);
+        for (primop, paths) in summary {
+            report.push_str(&format!("{:<width$} {}\n", primop, paths, width = width))
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_table() {
+        let stats = UnimplementedStats::new();
+        assert!(stats.is_empty());
+        stats.record("sqrt_real");
+        stats.record("undefined_real");
+        stats.record("sqrt_real");
+        assert_eq!(stats.summary(), vec![("sqrt_real".to_string(), 2), ("undefined_real".to_string(), 1)]);
+        assert_eq!(stats.report(), "primop         paths\nsqrt_real      2\nundefined_real 1\n")
+    }
+}
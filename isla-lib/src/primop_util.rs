@@ -193,6 +193,20 @@ pub fn smt_value<B: BV>(v: &Val<B>, info: SourceLoc) -> Result<Exp<Sym>, ExecErr
     })
 }
 
+/// Returns the SMT expression for the constructor tag of a union
+/// value, along with the payload for each constructor it could be.
+fn ctor_possibilities<B: BV>(v: &Val<B>) -> (Exp<Sym>, HashMap<Name, Val<B>>) {
+    match v {
+        Val::Ctor(ctor, payload) => {
+            let mut possibilities = HashMap::new();
+            possibilities.insert(*ctor, payload.as_ref().clone());
+            (ctor.to_smt(), possibilities)
+        }
+        Val::SymbolicCtor(tag, possibilities) => (Exp::Var(*tag), possibilities.clone()),
+        _ => unreachable!("ctor_possibilities called on non-constructor value"),
+    }
+}
+
 pub fn build_ite<B: BV>(
     b: Sym,
     lhs: &Val<B>,
@@ -212,63 +226,26 @@ pub fn build_ite<B: BV>(
             Ok(Val::Struct(fields?))
         }
 
-        (Val::Ctor(l_id, lhs), Val::Ctor(r_id, rhs)) => {
-            if l_id == r_id {
-                let v = solver.define_const(
-                    Exp::Ite(Box::new(Exp::Var(b)), Box::new(smt_value(lhs, info)?), Box::new(smt_value(rhs, info)?)),
-                    info,
-                );
-                Ok(Val::Ctor(*l_id, Box::new(Val::Symbolic(v))))
-            } else {
-                use smtlib::Exp::*;
-                let sym_id = solver.declare_const(Name::smt_ty(), info);
-                solver.assert(Or(
-                    Box::new(Eq(Box::new(Var(sym_id)), Box::new(l_id.to_smt()))),
-                    Box::new(Eq(Box::new(Var(sym_id)), Box::new(r_id.to_smt()))),
-                ));
-
-                let mut possibilities = HashMap::new();
-                possibilities.insert(*l_id, lhs.as_ref().clone());
-                possibilities.insert(*r_id, rhs.as_ref().clone());
-                Ok(Val::SymbolicCtor(sym_id, possibilities))
-            }
+        (Val::Ctor(l_id, lhs), Val::Ctor(r_id, rhs)) if l_id == r_id => {
+            Ok(Val::Ctor(*l_id, Box::new(build_ite(b, lhs, rhs, solver, info)?)))
         }
 
-        (Val::Ctor(l_id, lhs), Val::SymbolicCtor(r_id, rhs)) => {
-            use smtlib::Exp::*;
-            let sym_id = solver.declare_const(Name::smt_ty(), info);
-            solver.assert(Or(
-                Box::new(Eq(Box::new(Var(sym_id)), Box::new(l_id.to_smt()))),
-                Box::new(Eq(Box::new(Var(sym_id)), Box::new(Var(*r_id)))),
-            ));
-
-            let mut possibilities = rhs.clone();
-            match possibilities.entry(*l_id) {
-                Entry::Occupied(o) => *o.into_mut() = build_ite(b, lhs, o.get(), solver, info)?,
-                Entry::Vacant(v) => {
-                    v.insert(lhs.as_ref().clone());
-                }
-            }
-
-            Ok(Val::SymbolicCtor(sym_id, possibilities))
-        }
-
-        (Val::SymbolicCtor(_, _), Val::Ctor(_, _)) => build_ite(b, rhs, lhs, solver, info),
-
-        (Val::SymbolicCtor(l_id, lhs), Val::SymbolicCtor(r_id, rhs)) => {
+        // When the constructors can differ, the result is a symbolic
+        // constructor whose tag is chosen by the same boolean as the
+        // payloads, and any constructor possible on both sides has
+        // its payloads merged.
+        (Val::Ctor(_, _) | Val::SymbolicCtor(_, _), Val::Ctor(_, _) | Val::SymbolicCtor(_, _)) => {
             use smtlib::Exp::*;
-            let sym_id = solver.declare_const(Name::smt_ty(), info);
-            solver.assert(Or(
-                Box::new(Eq(Box::new(Var(sym_id)), Box::new(Var(*l_id)))),
-                Box::new(Eq(Box::new(Var(sym_id)), Box::new(Var(*r_id)))),
-            ));
-
-            let mut possibilities = lhs.clone();
-            for (ctor, rhs_val) in rhs.iter() {
-                match possibilities.entry(*ctor) {
-                    Entry::Occupied(o) => *o.into_mut() = build_ite(b, o.get(), rhs_val, solver, info)?,
+            let (l_tag, l_possibilities) = ctor_possibilities(lhs);
+            let (r_tag, r_possibilities) = ctor_possibilities(rhs);
+            let sym_id = solver.define_const(Ite(Box::new(Var(b)), Box::new(l_tag), Box::new(r_tag)), info);
+
+            let mut possibilities = l_possibilities;
+            for (ctor, r_val) in r_possibilities {
+                match possibilities.entry(ctor) {
+                    Entry::Occupied(o) => *o.into_mut() = build_ite(b, o.get(), &r_val, solver, info)?,
                     Entry::Vacant(v) => {
-                        v.insert(rhs_val.clone());
+                        v.insert(r_val);
                     }
                 }
             }
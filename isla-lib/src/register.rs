@@ -43,6 +43,9 @@
 //! 3. The set of all written values
 //!
 //! Additionally, all registers may be in an unitialised state.
+//! Registers can be marked as requiring a write before they are
+//! first read, in which case reading them while uninitialised is an
+//! error rather than producing a symbolic value.
 //!
 //! The semantics is as follows:
 //!
@@ -74,7 +77,6 @@
 //! clears the last read value, forcing subsequent reads to see the
 //! last written value.
 
-use ahash;
 use std::collections::{hash_map, HashMap};
 
 use crate::bitvector::BV;
@@ -86,15 +88,16 @@ use crate::source_loc::SourceLoc;
 use crate::zencode;
 
 #[derive(Clone)]
-enum RelaxedVal<'ir, B> {
+pub(crate) enum RelaxedVal<'ir, B> {
     Uninit(&'ir Ty<Name>),
     Init { last_write: Val<B>, last_read: Option<Val<B>>, old_writes: Vec<Val<B>> },
 }
 
 #[derive(Clone)]
 pub struct Register<'ir, B> {
-    relaxed: bool,
-    value: RelaxedVal<'ir, B>,
+    pub(crate) relaxed: bool,
+    pub(crate) write_before_read: bool,
+    pub(crate) value: RelaxedVal<'ir, B>,
 }
 
 impl<'ir, B: BV> RelaxedVal<'ir, B> {
@@ -238,7 +241,7 @@ impl<'ir, B: BV> Register<'ir, B> {
 
 #[derive(Clone)]
 pub struct RegisterBindings<'ir, B> {
-    map: HashMap<Name, Register<'ir, B>, ahash::RandomState>,
+    map: HashMap<Name, Register<'ir, B>, HashState>,
 }
 
 /// An iterator over registers in a [RegisterBindings]
@@ -254,13 +257,14 @@ impl<'ir, B: BV> RegisterBindings<'ir, B> {
     pub fn insert(&mut self, id: Name, relaxed: bool, v: UVal<'ir, B>) {
         match v {
             UVal::Uninit(ty) => {
-                self.map.insert(id, Register { relaxed, value: RelaxedVal::Uninit(ty) });
+                self.map.insert(id, Register { relaxed, write_before_read: false, value: RelaxedVal::Uninit(ty) });
             }
             UVal::Init(value) => {
                 self.map.insert(
                     id,
                     Register {
                         relaxed,
+                        write_before_read: false,
                         value: RelaxedVal::Init { last_write: value, last_read: None, old_writes: Vec::new() },
                     },
                 );
@@ -280,6 +284,9 @@ impl<'ir, B: BV> RegisterBindings<'ir, B> {
         info: SourceLoc,
     ) -> Result<Option<&'a Val<B>>, ExecError> {
         if let Some(reg) = self.map.get_mut(&id) {
+            if reg.write_before_read && reg.read_last_if_initialized().is_none() {
+                return Err(ExecError::ReadBeforeWrite(zencode::decode(shared_state.symtab.to_str(id))));
+            }
             let val = reg.read(shared_state, solver, info)?;
             Ok(Some(val))
         } else {
@@ -318,6 +325,15 @@ impl<'ir, B: BV> RegisterBindings<'ir, B> {
         }
     }
 
+    /// Require that the register is written before it is read,
+    /// rather than being initialised with a symbolic value on its
+    /// first read.
+    pub fn require_write_before_read(&mut self, id: Name) {
+        if let Some(reg) = self.map.get_mut(&id) {
+            reg.write_before_read = true
+        }
+    }
+
     pub fn contains_key(&self, id: Name) -> bool {
         self.map.contains_key(&id)
     }
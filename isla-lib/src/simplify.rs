@@ -33,14 +33,19 @@
 
 use std::borrow::{Borrow, BorrowMut, Cow};
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::io::Write;
+use std::sync::Arc;
 
 use crate::bitvector::{write_bits64, BV};
-use crate::ir::{BitsSegment, Loc, Name, Symtab, Val, HAVE_EXCEPTION};
+use crate::config::RegisterField;
+use crate::instruction_names::InstructionNames;
+use crate::ir::{BitsSegment, Loc, Name, Symtab, Val, ValBitsFormat, HAVE_EXCEPTION};
 use crate::smt::smtlib::{self, *};
 use crate::smt::Event::*;
-use crate::smt::{Accessor, Event, Sym};
+use crate::smt::{Accessor, Event, MemKind, Sym};
 use crate::source_loc::SourceLoc;
 use crate::zencode;
 
@@ -55,7 +60,7 @@ pub fn renumber_event<B, F>(event: &mut Event<B>, f: &mut F)
     use Event::*;
     match event {
         Smt(def, _, _) => renumber_def(def, f),
-        Fork(_, v, _, _) => *v = Sym { id: f(v.id) },
+        Fork(_, v, _, _) | Sleeping(v) | Interrupt { index: _, arrival: v } => *v = Sym { id: f(v.id) },
         Abstract { name: _, primitive: _, args, return_value } => {
             for arg in args.iter_mut() {
                 renumber_val(arg, f)
@@ -71,7 +76,7 @@ pub fn renumber_event<B, F>(event: &mut Event<B>, f: &mut F)
         ReadReg(_, _, value) | WriteReg(_, _, value) | Instr(value) | AssumeReg(_, _, value) => {
             renumber_val(value, f)
         }
-        Branch { address } => renumber_val(address, f),
+        Branch { address } | AddressAnnounce { address } => renumber_val(address, f),
         ReadMem { value, read_kind, address, bytes: _, tag_value, opts: _, region: _ } => {
             renumber_val(value, f);
             renumber_val(read_kind, f);
@@ -89,7 +94,7 @@ pub fn renumber_event<B, F>(event: &mut Event<B>, f: &mut F)
                 renumber_val(v, f);
             }
         }
-        Cycle | MarkReg { .. } | Function { .. } | Assume(_) => (),
+        Cycle(_) | SleepRequest | WakeupRequest | MarkReg { .. } | Function { .. } | Assume(_) | Provenance { .. } => (),
     }
 }
 
@@ -97,10 +102,19 @@ fn renumber_exp<F>(exp: &mut Exp<Sym>, f: &mut F)
     where F: FnMut(u32) -> u32
 {
     exp.modify(
-        &mut (|exp| {
-            if let Exp::Var(v) = exp {
-                *v = Sym { id: f(v.id) }
+        &mut (|exp| match exp {
+            Exp::Var(v) => *v = Sym { id: f(v.id) },
+            Exp::Forall(vars, _) | Exp::Exists(vars, _) => {
+                for (v, _) in vars {
+                    *v = Sym { id: f(v.id) }
+                }
+            }
+            Exp::Let(bindings, _) => {
+                for (v, _) in bindings {
+                    *v = Sym { id: f(v.id) }
+                }
             }
+            _ => (),
         }),
     )
 }
@@ -136,7 +150,7 @@ fn renumber_def<F>(def: &mut Def, f: &mut F)
             *v = Sym { id: f(v.id) };
             renumber_exp(exp, f)
         }
-        Assert(exp) => renumber_exp(exp, f),
+        Assert(exp) | NamedAssert(_, _, exp) => renumber_exp(exp, f),
         DefineEnum(_) => (),
     }
 }
@@ -230,6 +244,28 @@ fn uses_in_exp(uses: &mut HashMap<Sym, u32>, exp: &Exp<Sym>) {
             uses_in_exp(uses, y);
             uses_in_exp(uses, z)
         }
+        // Occurrences of the bound variables are not uses
+        Forall(vars, body) | Exists(vars, body) => {
+            let mut body_uses = HashMap::new();
+            uses_in_exp(&mut body_uses, body);
+            for (v, _) in vars {
+                body_uses.remove(v);
+            }
+            for (v, n) in body_uses {
+                uses.insert(v, uses.get(&v).unwrap_or(&0) + n);
+            }
+        }
+        Let(bindings, body) => {
+            let mut body_uses = HashMap::new();
+            uses_in_exp(&mut body_uses, body);
+            for (v, exp) in bindings {
+                body_uses.remove(v);
+                uses_in_exp(uses, exp)
+            }
+            for (v, n) in body_uses {
+                uses.insert(v, uses.get(&v).unwrap_or(&0) + n);
+            }
+        }
     }
 }
 
@@ -317,6 +353,28 @@ impl EventReferences {
         deps
     }
 
+    /// Returns the architecturally UNKNOWN variables in `undefined`
+    /// (see [undefined_variables]) which a symbolic variable depends
+    /// on.
+    pub fn undefined_dependencies(&self, symbol: Sym, undefined: &HashSet<Sym>) -> HashSet<Sym> {
+        self.dependencies(symbol).intersection(undefined).copied().collect()
+    }
+
+    /// Returns true if a value is entirely determined by
+    /// architecturally UNKNOWN variables, i.e. it is symbolic, depends
+    /// on at least one declared variable, and every declared variable
+    /// it depends on is in `undefined`.
+    pub fn is_dont_care<B: BV>(&self, val: &Val<B>, undefined: &HashSet<Sym>) -> bool {
+        match val {
+            Val::Symbolic(v) => {
+                let deps = self.dependencies(*v);
+                let mut declared = deps.iter().filter(|dep| !self.references.contains_key(*dep)).peekable();
+                declared.peek().is_some() && declared.all(|dep| undefined.contains(dep))
+            }
+            _ => false,
+        }
+    }
+
     /// Returns the set of registers a symbolic variable is tainted
     /// by, i.e. any symbolic registers upon which the variable
     /// depends upon. Also returns whether the value depends upon a
@@ -388,7 +446,7 @@ fn calculate_more_uses<B, E: Borrow<Event<B>>>(events: &[E], uses: &mut HashMap<
             Smt(Def::DeclareFun(_, _, _), _, _) => (),
             Smt(Def::DefineConst(_, exp), _, _) => uses_in_exp(uses, exp),
             Smt(Def::DefineEnum(_), _, _) => (),
-            Smt(Def::Assert(exp), _, _) => uses_in_exp(uses, exp),
+            Smt(Def::Assert(exp), _, _) | Smt(Def::NamedAssert(_, _, exp), _, _) => uses_in_exp(uses, exp),
             Abstract { name: _, primitive: _, args, return_value } => {
                 for arg in args {
                     uses_in_value(uses, arg)
@@ -421,21 +479,22 @@ fn calculate_more_uses<B, E: Borrow<Event<B>>>(events: &[E], uses: &mut HashMap<
                     uses_in_value(uses, v);
                 }
             }
-            Branch { address } => uses_in_value(uses, address),
-            Fork(_, sym, _, _) => {
+            Branch { address } | AddressAnnounce { address } => uses_in_value(uses, address),
+            Fork(_, sym, _, _) | Sleeping(sym) | Interrupt { arrival: sym, .. } => {
                 uses.insert(*sym, uses.get(sym).unwrap_or(&0) + 1);
             }
-            Cycle => (),
+            Cycle(_) | SleepRequest | WakeupRequest => (),
             Instr(val) => uses_in_value(uses, val),
             MarkReg { .. } => (),
             Function { .. } => (),
             Assume(_) => (),
+            Provenance { .. } => (),
             AssumeReg(_, _, val) => uses_in_value(uses, val),
         }
     }
 }
 
-fn calculate_uses<B, E: Borrow<Event<B>>>(events: &[E]) -> HashMap<Sym, u32> {
+pub(crate) fn calculate_uses<B, E: Borrow<Event<B>>>(events: &[E]) -> HashMap<Sym, u32> {
     let mut uses: HashMap<Sym, u32> = HashMap::new();
     calculate_more_uses(events, &mut uses);
     uses
@@ -501,15 +560,16 @@ fn calculate_required_uses<B, E: Borrow<Event<B>>>(events: &[E]) -> HashMap<Sym,
                     uses_in_value(&mut uses, v);
                 }
             }
-            Branch { address } => uses_in_value(&mut uses, address),
-            Fork(_, sym, _, _) => {
+            Branch { address } | AddressAnnounce { address } => uses_in_value(&mut uses, address),
+            Fork(_, sym, _, _) | Sleeping(sym) | Interrupt { arrival: sym, .. } => {
                 uses.insert(*sym, uses.get(sym).unwrap_or(&0) + 1);
             }
-            Cycle => (),
+            Cycle(_) | SleepRequest | WakeupRequest => (),
             Instr(val) => uses_in_value(&mut uses, val),
             MarkReg { .. } => (),
             Function { .. } => (),
             Assume(_) => (),
+            Provenance { .. } => (),
             AssumeReg(_, _, val) => uses_in_value(&mut uses, val),
         }
     }
@@ -574,6 +634,32 @@ fn remove_unused_pass_tree<B>(event_tree: &mut EventTree<B>, uses: &HashMap<Sym,
     removed
 }
 
+/// Returns the variables declared in the trace for architecturally
+/// UNKNOWN values, rather than genuine inputs.
+pub fn undefined_variables<B, E: Borrow<Event<B>>>(events: &[E]) -> HashSet<Sym> {
+    events
+        .iter()
+        .filter_map(|event| match event.borrow() {
+            Smt(Def::DeclareConst(v, _), attrs, _) if attrs.is_undefined() => Some(*v),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Remove register writes whose values are entirely determined by
+/// architecturally UNKNOWN bits, treating them as don't-cares.
+pub fn remove_undefined_writes<B: BV>(events: &mut Vec<Event<B>>) {
+    let undefined = undefined_variables(events);
+    if undefined.is_empty() {
+        return;
+    }
+    let evrefs = EventReferences::from_events(events);
+    events.retain(|event| match event {
+        WriteReg(_, _, value) => !evrefs.is_dont_care(value, &undefined),
+        _ => true,
+    })
+}
+
 /// Removes register effects from before the first `(cycle)`
 /// event. When combined with `remove_unused` this will reduce the
 /// amount of initialization that appears in the trace.
@@ -584,7 +670,7 @@ pub fn hide_initialization<B: BV, E: Borrow<Event<B>>>(events: &mut Vec<E>) {
         match event.borrow() {
             WriteReg { .. } if init_cycle => keep[i] = false,
             ReadReg { .. } if init_cycle => keep[i] = false,
-            Cycle => init_cycle = false,
+            Cycle(_) => init_cycle = false,
             _ => (),
         }
     }
@@ -600,7 +686,7 @@ pub fn hide_initialization_tree<B: BV>(event_tree: &mut EventTree<B>) {
     event_tree.prefix.retain(|event| match event {
         WriteReg { .. } if init_cycle => false,
         ReadReg { .. } if init_cycle => false,
-        Cycle => {
+        Cycle(_) => {
             init_cycle = false;
             true
         }
@@ -671,7 +757,7 @@ fn record_affected_register_parts<V: Eq + std::hash::Hash + Copy>(
     }
 }
 
-pub fn remove_repeated_register_reads<B: BV>(events: &mut Vec<Event<B>>) {
+fn remove_repeated_register_reads_scoped<B: BV>(events: &mut Vec<Event<B>>, per_instruction: bool) {
     let mut recent_reads: HashMap<Name, HashMap<Vec<Accessor>, Val<B>>> = HashMap::new();
     // Some contortions because the trace is in reverse order when simplifications are performed.
     let mut keep = vec![true; events.len()];
@@ -693,6 +779,7 @@ pub fn remove_repeated_register_reads<B: BV>(events: &mut Vec<Event<B>>) {
             WriteReg(name, acc, _v) => {
                 remove_affected_register_parts(&mut recent_reads, *name, acc);
             }
+            Cycle(_) | Instr(_) if per_instruction => recent_reads.clear(),
             _ => (),
         }
     }
@@ -703,8 +790,22 @@ pub fn remove_repeated_register_reads<B: BV>(events: &mut Vec<Event<B>>) {
     })
 }
 
+pub fn remove_repeated_register_reads<B: BV>(events: &mut Vec<Event<B>>) {
+    remove_repeated_register_reads_scoped(events, false)
+}
+
+/// Coalesces repeated reads of the same register (and field) with
+/// identical values within a single instruction into the first such
+/// read. Unlike [remove_repeated_register_reads], reads are never
+/// folded across an instruction boundary (a `Cycle` or `Instr`
+/// event), so each instruction still shows every register it reads.
+pub fn fold_register_reads<B: BV>(events: &mut Vec<Event<B>>) {
+    remove_repeated_register_reads_scoped(events, true)
+}
+
 fn remove_repeated_register_reads_core<B: BV>(
     recent_reads: &mut HashMap<Name, HashMap<Vec<Accessor>, Val<B>>>,
+    per_instruction: bool,
     events: &mut EventTree<B>,
 ) {
     events.prefix.retain(|event| match event {
@@ -725,15 +826,23 @@ fn remove_repeated_register_reads_core<B: BV>(
             remove_affected_register_parts(recent_reads, *name, acc);
             true
         }
+        Cycle(_) | Instr(_) if per_instruction => {
+            recent_reads.clear();
+            true
+        }
         _ => true,
     });
     for fork in &mut events.forks {
-        remove_repeated_register_reads_core(&mut recent_reads.clone(), fork);
+        remove_repeated_register_reads_core(&mut recent_reads.clone(), per_instruction, fork);
     }
 }
 
 pub fn remove_repeated_register_reads_tree<B: BV>(event_tree: &mut EventTree<B>) {
-    remove_repeated_register_reads_core(&mut HashMap::new(), event_tree);
+    remove_repeated_register_reads_core(&mut HashMap::new(), false, event_tree);
+}
+
+pub fn fold_register_reads_tree<B: BV>(event_tree: &mut EventTree<B>) {
+    remove_repeated_register_reads_core(&mut HashMap::new(), true, event_tree);
 }
 
 pub fn remove_unused_register_assumptions<B: BV>(events: &mut Vec<Event<B>>) {
@@ -859,7 +968,9 @@ fn propagate_forwards_used_once_core<B: BV, E: BorrowMut<Event<B>>>(
                     substs.insert(*sym, Some(exp));
                 }
             }
-            Event::Smt(Def::Assert(exp), _, _) => exp.subst_once_in_place(&mut substs),
+            Event::Smt(Def::Assert(exp), _, _) | Event::Smt(Def::NamedAssert(_, _, exp), _, _) => {
+                exp.subst_once_in_place(&mut substs)
+            }
             _ => (),
         }
     }
@@ -928,7 +1039,9 @@ pub fn propagate_forwards_used_once_tree<B: BV>(event_tree: &mut EventTree<B>) {
 pub fn eval<B: BV, E: BorrowMut<Event<B>>>(events: &mut Vec<E>) {
     for event in events.iter_mut() {
         match event.borrow_mut() {
-            Event::Smt(Def::DefineConst(_, exp), _, _) | Event::Smt(Def::Assert(exp), _, _) => {
+            Event::Smt(Def::DefineConst(_, exp), _, _)
+            | Event::Smt(Def::Assert(exp), _, _)
+            | Event::Smt(Def::NamedAssert(_, _, exp), _, _) => {
                 let e = std::mem::replace(exp, Exp::Bool(false));
                 *exp = e.eval();
             }
@@ -941,6 +1054,140 @@ pub fn eval_tree<B: BV>(event_tree: &mut EventTree<B>) {
     event_tree.map(&eval);
 }
 
+fn is_constant<V>(exp: &Exp<V>) -> bool {
+    matches!(exp, Exp::Bits(_) | Exp::Bits64(_) | Exp::Bool(_) | Exp::Enum(_))
+}
+
+/// Convert a constant SMT expression into a value that can appear
+/// directly in an event. Bitvectors are only converted if they fit in
+/// a single 64-bit chunk.
+fn constant_to_val<B: BV>(exp: &Exp<Sym>) -> Option<Val<B>> {
+    match exp {
+        Exp::Bits64(bv) if bv.len() <= B::MAX_WIDTH => Some(Val::Bits(B::new(bv.lower_u64(), bv.len()))),
+        Exp::Bool(b) => Some(Val::Bool(*b)),
+        Exp::Enum(member) => Some(Val::Enum(*member)),
+        _ => None,
+    }
+}
+
+fn propagate_constants_val<B: BV>(consts: &HashMap<Sym, Exp<Sym>>, val: &mut Val<B>) {
+    match val {
+        Val::Symbolic(v) => {
+            if let Some(c) = consts.get(v).and_then(constant_to_val) {
+                *val = c
+            }
+        }
+        Val::MixedBits(segments) => {
+            for segment in segments.iter_mut() {
+                if let BitsSegment::Symbolic(v) = segment {
+                    if let Some(Val::Bits(bv)) = consts.get(v).and_then(constant_to_val) {
+                        *segment = BitsSegment::Concrete(bv)
+                    }
+                }
+            }
+        }
+        Val::Vector(vals) | Val::List(vals) => vals.iter_mut().for_each(|val| propagate_constants_val(consts, val)),
+        Val::Struct(fields) => fields.values_mut().for_each(|val| propagate_constants_val(consts, val)),
+        Val::Ctor(_, val) => propagate_constants_val(consts, val),
+        Val::SymbolicCtor(_, possibilities) => {
+            possibilities.values_mut().for_each(|val| propagate_constants_val(consts, val))
+        }
+        Val::I64(_)
+        | Val::I128(_)
+        | Val::Bool(_)
+        | Val::Bits(_)
+        | Val::String(_)
+        | Val::Unit
+        | Val::Enum(_)
+        | Val::Ref(_)
+        | Val::Poison => (),
+    }
+}
+
+fn propagate_constants_event<B: BV>(consts: &mut HashMap<Sym, Exp<Sym>>, event: &mut Event<B>) {
+    let subst = |exp: &mut Exp<Sym>, consts: &HashMap<Sym, Exp<Sym>>| {
+        exp.modify(&mut |e| {
+            if let Exp::Var(v) = e {
+                if let Some(c) = consts.get(v) {
+                    *e = c.clone()
+                }
+            }
+        });
+        let e = std::mem::replace(exp, Exp::Bool(false));
+        *exp = e.eval()
+    };
+
+    match event {
+        Event::Smt(Def::DefineConst(v, exp), _, _) => {
+            subst(exp, consts);
+            if is_constant(exp) {
+                consts.insert(*v, exp.clone());
+            }
+        }
+        Event::Smt(Def::Assert(exp), _, _) | Event::Smt(Def::NamedAssert(_, _, exp), _, _) => subst(exp, consts),
+        Event::ReadReg(_, _, val)
+        | Event::WriteReg(_, _, val)
+        | Event::AssumeReg(_, _, val)
+        | Event::Branch { address: val }
+        | Event::AddressAnnounce { address: val }
+        | Event::Instr(val) => propagate_constants_val(consts, val),
+        Event::ReadMem { value, read_kind, address, tag_value, .. } => {
+            propagate_constants_val(consts, value);
+            propagate_constants_val(consts, read_kind);
+            propagate_constants_val(consts, address);
+            if let Some(tag_value) = tag_value {
+                propagate_constants_val(consts, tag_value)
+            }
+        }
+        Event::WriteMem { write_kind, address, data, tag_value, .. } => {
+            propagate_constants_val(consts, write_kind);
+            propagate_constants_val(consts, address);
+            propagate_constants_val(consts, data);
+            if let Some(tag_value) = tag_value {
+                propagate_constants_val(consts, tag_value)
+            }
+        }
+        Event::Abstract { args, return_value, .. }
+        | Event::AssumeFun { args, return_value, .. }
+        | Event::UseFunAssumption { args, return_value, .. } => {
+            args.iter_mut().for_each(|arg| propagate_constants_val(consts, arg));
+            propagate_constants_val(consts, return_value)
+        }
+        _ => (),
+    }
+}
+
+/// Constant propagation. For every `(define-const v c)` where `c` is
+/// a literal (possibly after evaluating constant subterms), this
+/// substitutes `c` for `v` in all later SMT expressions and in the
+/// values of subsequent events, so e.g. register writes show their
+/// concrete value directly. The now-redundant definitions are left
+/// in place to be cleaned up by [remove_unused].
+///
+/// Like [propagate_forwards_used_once], this expects the events to be
+/// in reverse order, as they are when collected from the executor.
+pub fn propagate_constants<B: BV, E: BorrowMut<Event<B>>>(events: &mut Vec<E>) {
+    let mut consts = HashMap::new();
+    for event in events.iter_mut().rev() {
+        propagate_constants_event(&mut consts, event.borrow_mut())
+    }
+}
+
+fn propagate_constants_descent<B: BV>(mut consts: HashMap<Sym, Exp<Sym>>, event_tree: &mut EventTree<B>) {
+    for event in event_tree.prefix.iter_mut() {
+        propagate_constants_event(&mut consts, event)
+    }
+    for fork in &mut event_tree.forks {
+        propagate_constants_descent(consts.clone(), fork)
+    }
+}
+
+/// This is an event tree version of [propagate_constants]. Constants
+/// defined in a segment are propagated into all its forks.
+pub fn propagate_constants_tree<B: BV>(event_tree: &mut EventTree<B>) {
+    propagate_constants_descent(HashMap::new(), event_tree)
+}
+
 /// This rewrite pushes extract expressions inwards where possible, so
 /// ```text
 /// (extract (f a b))
@@ -957,9 +1204,9 @@ pub fn eval_tree<B: BV>(event_tree: &mut EventTree<B>) {
 pub fn commute_extract<B: BV, E: BorrowMut<Event<B>>>(events: &mut Vec<E>) {
     for event in events.iter_mut() {
         match event.borrow_mut() {
-            Event::Smt(Def::DefineConst(_, exp), _, _) | Event::Smt(Def::Assert(exp), _, _) => {
-                exp.modify_top_down(&mut Exp::commute_extract)
-            }
+            Event::Smt(Def::DefineConst(_, exp), _, _)
+            | Event::Smt(Def::Assert(exp), _, _)
+            | Event::Smt(Def::NamedAssert(_, _, exp), _, _) => exp.modify_top_down(&mut Exp::commute_extract),
             _ => (),
         }
     }
@@ -969,6 +1216,187 @@ pub fn commute_extract_tree<B: BV>(event_tree: &mut EventTree<B>) {
     event_tree.map(&commute_extract);
 }
 
+fn is_let_leaf<V>(exp: &Exp<V>) -> bool {
+    use Exp::*;
+    matches!(exp, Var(_) | Bits(_) | Bits64(_) | Enum(_) | Bool(_) | FPConstant(..) | FPRoundingMode(_))
+}
+
+fn has_binders<V>(exp: &mut Exp<V>) -> bool {
+    let mut binders = false;
+    exp.modify(&mut |exp| binders |= matches!(exp, Exp::Forall(..) | Exp::Exists(..) | Exp::Let(..)));
+    binders
+}
+
+/// Rebuild a shared subterm from its node, where each child
+/// placeholder is either kept as a let-bound variable, or inlined if
+/// it was only used once. Returns the expression along with its let
+/// depth, i.e. one more than the deepest let-bound variable it
+/// refers to.
+fn expand_shared(id: usize, base: u32, nodes: &[Exp<Sym>], keep: &[bool], depth: &[usize]) -> (Exp<Sym>, usize) {
+    let mut exp = nodes[id].clone();
+    let mut max_depth = 0;
+    exp.modify(&mut |child| {
+        if let Exp::Var(v) = child {
+            if v.id >= base {
+                let child_id = (v.id - base) as usize;
+                if keep[child_id] {
+                    max_depth = std::cmp::max(max_depth, depth[child_id] + 1)
+                } else {
+                    let (inlined, d) = expand_shared(child_id, base, nodes, keep, depth);
+                    max_depth = std::cmp::max(max_depth, d);
+                    *child = inlined
+                }
+            }
+        }
+    });
+    (exp, max_depth)
+}
+
+/// Introduce let bindings for each non-trivial subterm that occurs
+/// more than once in an expression. The executor builds expressions
+/// by copying subterms, so when written out as a tree some traces are
+/// far larger than the underlying DAG. Let-bound variables are
+/// numbered from `next_var` (or above any variable occuring in the
+/// expression if that is larger), and `next_var` is updated past
+/// them. Expressions that already contain binders are left unchanged.
+pub fn introduce_lets(exp: &mut Exp<Sym>, next_var: &mut u32) {
+    if is_let_leaf(exp) || has_binders(exp) {
+        return;
+    }
+
+    let mut uses = HashMap::new();
+    uses_in_exp(&mut uses, exp);
+    let base = uses.keys().map(|v| v.id + 1).fold(*next_var, std::cmp::max);
+
+    // Hash-cons the expression bottom up, replacing each non-trivial
+    // subterm by a placeholder variable base + id, where nodes[id] is
+    // that subterm with its own children replaced by placeholders.
+    let mut table: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut nodes: Vec<Exp<Sym>> = Vec::new();
+    let key_opts = WriteOpts::smtlib();
+    let mut hash_consed = exp.clone();
+    hash_consed.modify(&mut |subexp| {
+        if is_let_leaf(subexp) {
+            return;
+        }
+        let mut key = Vec::new();
+        write_exp(&mut key, subexp, &key_opts).unwrap();
+        let id = *table.entry(key).or_insert_with(|| {
+            nodes.push(subexp.clone());
+            nodes.len() - 1
+        });
+        *subexp = Exp::Var(Sym::from_u32(base + id as u32))
+    });
+
+    // Count how many times each node is referenced once its parents
+    // have been shared. Children always have smaller ids than their
+    // parents, so we can propagate counts downwards in a single pass.
+    let root = nodes.len() - 1;
+    let mut refs = vec![0; nodes.len()];
+    refs[root] = 1;
+    for id in (0..nodes.len()).rev() {
+        if refs[id] > 0 {
+            let mut children = HashMap::new();
+            uses_in_exp(&mut children, &nodes[id]);
+            for (v, n) in children {
+                if v.id >= base {
+                    refs[(v.id - base) as usize] += n
+                }
+            }
+        }
+    }
+
+    let keep: Vec<bool> = refs.iter().enumerate().map(|(id, n)| id != root && *n > 1).collect();
+    if !keep.contains(&true) {
+        return;
+    }
+
+    let mut depth = vec![0; nodes.len()];
+    let mut levels: Vec<Vec<(Sym, Exp<Sym>)>> = Vec::new();
+    for id in (0..root).filter(|id| keep[*id]) {
+        let (binding, d) = expand_shared(id, base, &nodes, &keep, &depth);
+        depth[id] = d;
+        if levels.len() <= d {
+            levels.resize_with(d + 1, Vec::new)
+        }
+        levels[d].push((Sym::from_u32(base + id as u32), binding))
+    }
+
+    let (mut body, _) = expand_shared(root, base, &nodes, &keep, &depth);
+    for bindings in levels.into_iter().rev().filter(|bindings| !bindings.is_empty()) {
+        body = Exp::Let(bindings, Box::new(body))
+    }
+    *exp = body;
+    *next_var = base + nodes.len() as u32
+}
+
+/// A user-defined trace postprocessing pass. Passes take ownership
+/// of a trace (in chronological order) and return the transformed
+/// trace.
+pub type TracePass<B> = Box<dyn Fn(Vec<Event<B>>) -> Vec<Event<B>> + Send + Sync>;
+
+/// A registry of trace postprocessing passes, so project-specific
+/// cleanups can be installed without modifying this module. Linear
+/// traces are passed through the registry after the built-in
+/// simplifications and immediately before they are written (see
+/// [write_events_with_passes]). As passes operate on linear traces,
+/// for event trees they are instead run over each path as it is
+/// added to the tree (see [TracePasses::add_to_tree]), so passes
+/// should preserve `Fork` events.
+pub struct TracePasses<B> {
+    passes: Vec<(String, TracePass<B>)>,
+}
+
+impl<B: BV> Default for TracePasses<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BV> TracePasses<B> {
+    pub fn new() -> Self {
+        TracePasses { passes: Vec::new() }
+    }
+
+    /// Install a pass. Passes are run in the order they are registered.
+    pub fn register<F>(&mut self, name: &str, pass: F)
+    where
+        F: Fn(Vec<Event<B>>) -> Vec<Event<B>> + Send + Sync + 'static,
+    {
+        self.passes.push((name.to_string(), Box::new(pass)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Run every registered pass over a trace, in order. In debug
+    /// builds each pass is followed by [debug_typecheck_events], so a
+    /// pass that produces an ill-typed trace is identified by name.
+    pub fn run(&self, events: Vec<Event<B>>) -> Vec<Event<B>> {
+        self.passes.iter().fold(events, |events, (name, pass)| {
+            let events = pass(events);
+            debug_typecheck_events(&events, name);
+            events
+        })
+    }
+
+    /// Run every registered pass over a trace and then merge it into
+    /// an event tree, creating the tree if it does not exist yet.
+    pub fn add_to_tree(&self, evtree: &mut Option<EventTree<B>>, events: Vec<Event<B>>) {
+        let events = self.run(events);
+        if let Some(evtree) = evtree {
+            evtree.add_events(&events)
+        } else {
+            *evtree = Some(EventTree::from_events(&events))
+        }
+    }
+}
+
 fn accessor_to_string(acc: &[Accessor], symtab: &Symtab) -> String {
     acc.iter()
         .map(|elem| elem.to_string(symtab))
@@ -1150,6 +1578,162 @@ impl<B: BV> EventTree<B> {
     }
 }
 
+/// A type error found by [typecheck_events]. The index is the
+/// position of the offending event in the (chronological) trace.
+#[derive(Debug)]
+pub struct TraceTypeError {
+    pub event: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TraceTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Type error at event {}: {}", self.event, self.message)
+    }
+}
+
+impl Error for TraceTypeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+fn typecheck_val<B: BV>(what: &str, val: &Val<B>, tcx: &HashMap<Sym, Ty>) -> Result<(), String> {
+    for v in val.symbolic_variables() {
+        if !tcx.contains_key(&v) {
+            return Err(format!("{} refers to undeclared variable v{}", what, v));
+        }
+    }
+    if let Val::MixedBits(segments) = val {
+        for segment in segments {
+            if let BitsSegment::Symbolic(v) = segment {
+                if !matches!(tcx.get(v), Some(Ty::BitVec(_))) {
+                    return Err(format!("{} uses v{} as a bitvector segment, but it has type {:?}", what, v, tcx[v]));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn typecheck_declaration(v: Sym, tcx: &HashMap<Sym, Ty>, ftcx: &HashMap<Sym, (Vec<Ty>, Ty)>) -> Result<(), String> {
+    if tcx.contains_key(&v) || ftcx.contains_key(&v) {
+        Err(format!("v{} is declared more than once", v))
+    } else {
+        Ok(())
+    }
+}
+
+fn typecheck_event<B: BV>(
+    event: &Event<B>,
+    tcx: &mut HashMap<Sym, Ty>,
+    ftcx: &mut HashMap<Sym, (Vec<Ty>, Ty)>,
+) -> Result<(), String> {
+    match event {
+        Smt(Def::DeclareConst(v, ty), _, _) => {
+            typecheck_declaration(*v, tcx, ftcx)?;
+            tcx.insert(*v, ty.clone());
+        }
+        Smt(Def::DeclareFun(v, arg_tys, result_ty), _, _) => {
+            typecheck_declaration(*v, tcx, ftcx)?;
+            ftcx.insert(*v, (arg_tys.clone(), result_ty.clone()));
+        }
+        Smt(Def::DefineConst(v, exp), _, _) => {
+            typecheck_declaration(*v, tcx, ftcx)?;
+            let ty = exp.typecheck(tcx, ftcx).map_err(|msg| format!("in definition of v{}: {}", v, msg))?;
+            tcx.insert(*v, ty);
+        }
+        Smt(Def::DefineEnum(_), _, _) => (),
+        Smt(Def::Assert(exp), _, _) | Smt(Def::NamedAssert(_, _, exp), _, _) => {
+            match exp.typecheck(tcx, ftcx).map_err(|msg| format!("in assertion: {}", msg))? {
+                Ty::Bool => (),
+                ty => return Err(format!("assertion has type {:?}, expected Bool", ty)),
+            }
+        }
+        Fork(_, v, _, _) => match tcx.get(v) {
+            Some(Ty::Bool) => (),
+            Some(ty) => return Err(format!("fork condition v{} has type {:?}, expected Bool", v, ty)),
+            None => return Err(format!("fork condition v{} is undeclared", v)),
+        },
+        Sleeping(v) => match tcx.get(v) {
+            Some(Ty::Bool) => (),
+            Some(ty) => return Err(format!("sleeping check v{} has type {:?}, expected Bool", v, ty)),
+            None => return Err(format!("sleeping check v{} is undeclared", v)),
+        },
+        Interrupt { arrival, .. } => match tcx.get(arrival) {
+            Some(Ty::BitVec(64)) => (),
+            Some(ty) => return Err(format!("interrupt arrival v{} has type {:?}, expected a bitvector", arrival, ty)),
+            None => return Err(format!("interrupt arrival v{} is undeclared", arrival)),
+        },
+        Abstract { args, return_value, .. }
+        | AssumeFun { args, return_value, .. }
+        | UseFunAssumption { args, return_value, .. } => {
+            for arg in args {
+                typecheck_val("function argument", arg, tcx)?
+            }
+            typecheck_val("function return value", return_value, tcx)?
+        }
+        ReadReg(_, _, val) => typecheck_val("register read", val, tcx)?,
+        WriteReg(_, _, val) => typecheck_val("register write", val, tcx)?,
+        AssumeReg(_, _, val) => typecheck_val("register assumption", val, tcx)?,
+        ReadMem { value, read_kind, address, tag_value, .. } => {
+            typecheck_val("memory read value", value, tcx)?;
+            typecheck_val("memory read kind", read_kind, tcx)?;
+            typecheck_val("memory read address", address, tcx)?;
+            if let Some(v) = tag_value {
+                typecheck_val("memory read tag", v, tcx)?
+            }
+        }
+        // The value of a write is the symbol for its success, which
+        // is declared by whoever consumes the trace.
+        WriteMem { value: _, write_kind, address, data, tag_value, .. } => {
+            typecheck_val("memory write kind", write_kind, tcx)?;
+            typecheck_val("memory write address", address, tcx)?;
+            typecheck_val("memory write data", data, tcx)?;
+            if let Some(v) = tag_value {
+                typecheck_val("memory write tag", v, tcx)?
+            }
+        }
+        Branch { address } | AddressAnnounce { address } => typecheck_val("address", address, tcx)?,
+        Instr(val) => typecheck_val("instruction opcode", val, tcx)?,
+        Function { .. } | Cycle(_) | SleepRequest | WakeupRequest | MarkReg { .. } | Assume(_) | Provenance { .. } => (),
+    }
+    Ok(())
+}
+
+/// Check that a trace (in chronological order) is well-typed given
+/// the declarations already in `tcx` and `ftcx`, which are extended
+/// with the declarations made by the trace. Every SMT definition and
+/// assertion is typechecked, and every symbolic variable mentioned in
+/// an event must have been declared before it is used.
+pub fn typecheck_events_in_context<B: BV, E: Borrow<Event<B>>>(
+    events: &[E],
+    tcx: &mut HashMap<Sym, Ty>,
+    ftcx: &mut HashMap<Sym, (Vec<Ty>, Ty)>,
+) -> Result<(), TraceTypeError> {
+    for (i, event) in events.iter().enumerate() {
+        typecheck_event(event.borrow(), tcx, ftcx).map_err(|message| TraceTypeError { event: i, message })?
+    }
+    Ok(())
+}
+
+/// Check that a complete trace (in chronological order) is
+/// well-typed. See [typecheck_events_in_context].
+pub fn typecheck_events<B: BV, E: Borrow<Event<B>>>(events: &[E]) -> Result<(), TraceTypeError> {
+    typecheck_events_in_context(events, &mut HashMap::new(), &mut HashMap::new())
+}
+
+/// In debug builds, panic if a trace is ill-typed. Intended to be
+/// called after simplification passes, with `after` naming the pass
+/// that produced the trace.
+pub fn debug_typecheck_events<B: BV, E: Borrow<Event<B>>>(events: &[E], after: &str) {
+    if cfg!(debug_assertions) {
+        if let Err(err) = typecheck_events(events) {
+            panic!("Trace is ill-typed after {}: {}", after, err)
+        }
+    }
+}
+
 /// Options for writing event traces
 #[derive(Clone)]
 pub struct WriteOpts {
@@ -1171,6 +1755,28 @@ pub struct WriteOpts {
     pub prefix: bool,
     /// Hide uninteresting parts of the trace
     pub hide_uninteresting: bool,
+    /// Share repeated subterms in SMT expressions using let bindings
+    /// (see [introduce_lets])
+    pub lets: bool,
+    /// The format for concrete bitvectors in register and memory events
+    pub bits_format: ValBitsFormat,
+    /// Add a comment after register and memory events with concrete
+    /// values giving their decimal value, along with any fields from
+    /// `register_fields` for registers
+    pub bits_comments: bool,
+    /// Field layouts for registers, usually taken from the
+    /// architecture configuration
+    pub register_fields: HashMap<Name, Vec<RegisterField>>,
+    /// Make [write_events_with_opts] produce a complete SMTLIB script
+    /// ending with `(check-sat)`, with all non-SMT events written as
+    /// comments
+    pub smt_script: bool,
+    /// Write the provenance events recorded when the executor was
+    /// run with provenance tracking enabled
+    pub provenance: bool,
+    /// Add a comment after concrete instruction opcodes with their
+    /// mnemonic and fields
+    pub instruction_names: Option<Arc<InstructionNames>>,
 }
 
 impl WriteOpts {
@@ -1184,6 +1790,13 @@ impl WriteOpts {
             indent: 0,
             prefix: false,
             hide_uninteresting: false,
+            lets: false,
+            bits_format: ValBitsFormat::Default,
+            bits_comments: false,
+            register_fields: HashMap::new(),
+            smt_script: false,
+            provenance: false,
+            instruction_names: None,
         }
     }
 }
@@ -1199,6 +1812,13 @@ impl Default for WriteOpts {
             indent: 0,
             prefix: false,
             hide_uninteresting: false,
+            lets: false,
+            bits_format: ValBitsFormat::Default,
+            bits_comments: false,
+            register_fields: HashMap::new(),
+            smt_script: false,
+            provenance: false,
+            instruction_names: None,
         }
     }
 }
@@ -1255,7 +1875,7 @@ fn write_ty(buf: &mut dyn Write, ty: &Ty) -> std::io::Result<()> {
     }
 }
 
-trait WriteVar {
+pub(crate) trait WriteVar {
     fn write_var(&self, buf: &mut dyn Write, opts: &WriteOpts) -> std::io::Result<()>;
 }
 
@@ -1288,7 +1908,19 @@ impl WriteVar for Loc<String> {
     }
 }
 
-fn write_exp<V: WriteVar>(buf: &mut dyn Write, exp: &Exp<V>, opts: &WriteOpts) -> std::io::Result<()> {
+impl WriteVar for String {
+    fn write_var(&self, buf: &mut dyn Write, _opts: &WriteOpts) -> std::io::Result<()> {
+        write!(buf, "{}", self)
+    }
+}
+
+/// Write an expression whose variables have been replaced by
+/// human-readable names, such as the registers they were read from.
+pub fn write_named_exp(buf: &mut dyn Write, exp: &Exp<String>) -> std::io::Result<()> {
+    write_exp(buf, exp, &WriteOpts::default())
+}
+
+pub(crate) fn write_exp<V: WriteVar>(buf: &mut dyn Write, exp: &Exp<V>, opts: &WriteOpts) -> std::io::Result<()> {
     use Exp::*;
     match exp {
         Var(v) => v.write_var(buf, opts),
@@ -1485,6 +2117,57 @@ fn write_exp<V: WriteVar>(buf: &mut dyn Write, exp: &Exp<V>, opts: &WriteOpts) -
             write_exp(buf, z, opts)?;
             write!(buf, ")")
         }
+        Forall(vars, body) => write_quantifier(buf, "forall", vars, body, opts),
+        Exists(vars, body) => write_quantifier(buf, "exists", vars, body, opts),
+        Let(bindings, body) => {
+            write!(buf, "(let (")?;
+            for (i, (v, exp)) in bindings.iter().enumerate() {
+                if i != 0 {
+                    write!(buf, " ")?
+                }
+                write!(buf, "(")?;
+                v.write_var(buf, opts)?;
+                write!(buf, " ")?;
+                write_exp(buf, exp, opts)?;
+                write!(buf, ")")?
+            }
+            write!(buf, ") ")?;
+            write_exp(buf, body, opts)?;
+            write!(buf, ")")
+        }
+    }
+}
+
+fn write_quantifier<V: WriteVar>(
+    buf: &mut dyn Write,
+    quantifier: &str,
+    vars: &[(V, Ty)],
+    body: &Exp<V>,
+    opts: &WriteOpts,
+) -> std::io::Result<()> {
+    write!(buf, "({} (", quantifier)?;
+    for (i, (v, ty)) in vars.iter().enumerate() {
+        if i != 0 {
+            write!(buf, " ")?
+        }
+        write!(buf, "(")?;
+        v.write_var(buf, opts)?;
+        write!(buf, " ")?;
+        write_ty(buf, ty)?;
+        write!(buf, ")")?
+    }
+    write!(buf, ") ")?;
+    write_exp(buf, body, opts)?;
+    write!(buf, ")")
+}
+
+fn write_shared_exp(buf: &mut dyn Write, exp: &Exp<Sym>, opts: &WriteOpts, next_let: &mut u32) -> std::io::Result<()> {
+    if opts.lets {
+        let mut exp = exp.clone();
+        introduce_lets(&mut exp, next_let);
+        write_exp(buf, &exp, opts)
+    } else {
+        write_exp(buf, exp, opts)
     }
 }
 
@@ -1508,8 +2191,76 @@ fn write_binop<V: WriteVar>(
     write!(buf, ")")
 }
 
+fn bits_to_u128(bits: &[bool]) -> u128 {
+    bits.iter().rev().fold(0, |acc, bit| (acc << 1) | (*bit as u128))
+}
+
+/// Write the structured attributes of a memory access as a `:kind`
+/// annotation, e.g. `:kind (acquire exclusive (size-class 4))`. Plain
+/// accesses are written without an annotation.
+fn write_mem_kind(buf: &mut dyn Write, kind: &MemKind) -> std::io::Result<()> {
+    if kind.is_plain() {
+        return Ok(());
+    }
+    let mut attrs = Vec::new();
+    if kind.acquire {
+        attrs.push("acquire".to_string())
+    }
+    if kind.release {
+        attrs.push("release".to_string())
+    }
+    if kind.exclusive {
+        attrs.push("exclusive".to_string())
+    }
+    if let Some(size_class) = kind.size_class {
+        attrs.push(format!("(size-class {})", size_class))
+    }
+    write!(buf, " :kind ({})", attrs.join(" "))
+}
+
+/// Write a comment giving the decimal interpretation of a concrete
+/// value, and its fields if a layout is supplied. Returns true if a
+/// comment was written, in which case a newline is needed before any
+/// closing parenthesis.
+fn write_bits_comment<B: BV>(buf: &mut dyn Write, val: &Val<B>, fields: &[&RegisterField]) -> std::io::Result<bool> {
+    let bits = match val {
+        Val::Bits(bv) if bv.len() <= 128 => bv.to_vec(),
+        Val::I64(n) => (0..64).map(|i| (n >> i) & 1 == 1).collect(),
+        Val::I128(n) => (0..128).map(|i| (n >> i) & 1 == 1).collect(),
+        _ => return Ok(false),
+    };
+    write!(buf, " ; {}", bits_to_u128(&bits))?;
+    for field in fields {
+        if let Some(field_bits) = bits.get(field.lo as usize..=field.hi as usize) {
+            write!(buf, " {}={}", field.name, bits_to_u128(field_bits))?
+        }
+    }
+    Ok(true)
+}
+
+/// The fields of a register to describe in a comment. An access to
+/// the whole register describes every field, while an access to a
+/// single field of a packed register describes just that field.
+fn comment_fields<'a>(
+    fields: Option<&'a Vec<RegisterField>>,
+    acc: &[Accessor],
+    symtab: &Symtab,
+) -> Vec<&'a RegisterField> {
+    let Some(fields) = fields else {
+        return Vec::new()
+    };
+    match acc {
+        [] => fields.iter().collect(),
+        [Accessor::Field(field)] => {
+            let field = zencode::decode(symtab.to_str(*field));
+            fields.iter().filter(|f| f.name == field).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
 pub fn write_events_in_context<B: BV>(
-    buf: &mut dyn Write,
+    out: &mut dyn Write,
     events: &[Event<B>],
     symtab: &Symtab,
     opts: &WriteOpts,
@@ -1518,12 +2269,23 @@ pub fn write_events_in_context<B: BV>(
 ) -> std::io::Result<()> {
     let indent = " ".repeat(opts.indent);
     let mut require_newline = false;
+    let mut pending_provenance = None;
+    let mut next_let = if opts.lets { calculate_uses(events).keys().map(|v| v.id + 1).max().unwrap_or(0) } else { 0 };
 
     if !opts.just_smt {
-        write!(buf, "{}(trace", indent).unwrap();
+        write!(out, "{}(trace", indent).unwrap();
     }
-    for event in events.iter().filter(|ev| !opts.just_smt || ev.is_smt()) {
+    for event in events.iter().filter(|ev| !opts.just_smt || opts.smt_script || ev.is_smt()) {
         require_newline = false;
+        // In an SMT script, non-SMT events are written as comments
+        let as_comment = opts.smt_script && !event.is_smt();
+        let mut comment: Vec<u8> = Vec::new();
+        let buf: &mut dyn Write = if as_comment { &mut comment } else { &mut *out };
+        if !event.is_smt() && !matches!(event, Provenance { .. }) {
+            if let Some((function, pc)) = pending_provenance.take() {
+                write!(buf, "\n{}  (provenance |{}| {})", indent, zencode::decode(symtab.to_str(function)), pc)?
+            }
+        }
         (match event {
             Fork(n, _, _, loc) => {
                 write!(buf, "\n{}  (branch {} \"{}\")", indent, n, loc.location_string(symtab.files()))
@@ -1605,11 +2367,15 @@ pub fn write_events_in_context<B: BV>(
                         write!(buf, "(declare-const {}{} ", opts.variable_prefix, v)?;
                         write_ty(buf, ty)?;
                         require_newline = true;
-                        write!(buf, ") ; {:?}", loc)?
+                        if attrs.is_undefined() {
+                            write!(buf, ") ; undefined {:?}", loc)?
+                        } else {
+                            write!(buf, ") ; {:?}", loc)?
+                        }
                     }
                     Def::DeclareFun(v, arg_tys, result_ty) => {
                         ftcx.to_mut().insert(*v, (arg_tys.clone(), result_ty.clone()));
-                        write!(buf, "(declare_fun {}{} (", opts.variable_prefix, v)?;
+                        write!(buf, "(declare-fun {}{} (", opts.variable_prefix, v)?;
                         for ty in arg_tys {
                             write_ty(buf, ty)?;
                             write!(buf, " ")?
@@ -1620,38 +2386,55 @@ pub fn write_events_in_context<B: BV>(
                     }
                     Def::DefineConst(v, exp) => {
                         if opts.types {
-                            let ty = exp.infer(tcx, ftcx).expect("SMT expression was badly-typed");
+                            let ty = match exp.typecheck(tcx, ftcx) {
+                                Ok(ty) => ty,
+                                Err(msg) => panic!("SMT expression for v{} was badly-typed: {}", v, msg),
+                            };
                             tcx.to_mut().insert(*v, ty.clone());
                             write!(buf, "(define-const v{} ", v)?;
                             write_ty(buf, &ty)?;
                             write!(buf, " ")?;
-                            write_exp(buf, exp, opts)?;
+                            write_shared_exp(buf, exp, opts, &mut next_let)?;
                             write!(buf, ")")?
                         } else {
                             write!(buf, "(define-const v{} ", v)?;
-                            write_exp(buf, exp, opts)?;
+                            write_shared_exp(buf, exp, opts, &mut next_let)?;
                             write!(buf, ")")?;
                         }
                     }
                     Def::DefineEnum(size) => {
-                        if !opts.just_smt {
+                        if opts.smt_script {
+                            write_enum_datatype(buf, *size, opts)?
+                        } else if !opts.just_smt {
                             write!(buf, "(define-enum {})", size)?
                         }
                     }
                     Def::Assert(exp) => {
                         write!(buf, "(assert ")?;
-                        write_exp(buf, exp, opts)?;
+                        write_shared_exp(buf, exp, opts, &mut next_let)?;
+                        write!(buf, ")")?;
+                    }
+                    // Assumptions are only distinguished from other
+                    // assertions in the trace, not when generating SMT.
+                    Def::NamedAssert(name, AssertionKind::Assumption, exp) if !opts.just_smt => {
+                        write!(buf, "(assume-smt |{}| ", name)?;
+                        write_shared_exp(buf, exp, opts, &mut next_let)?;
                         write!(buf, ")")?;
                     }
+                    Def::NamedAssert(name, _, exp) => {
+                        write!(buf, "(assert (! ")?;
+                        write_shared_exp(buf, exp, opts, &mut next_let)?;
+                        write!(buf, " :named |{}|))", name)?;
+                    }
                 }
                 Ok(())
             }
 
             Smt(..) => Ok(()),
 
-            ReadMem { value, read_kind, address, bytes, tag_value, opts: _, region: _ } => {
+            ReadMem { value, read_kind, address, bytes, tag_value, opts: read_opts, region: _ } => {
                 write!(buf, "\n{}  (read-mem ", indent)?;
-                value.write(buf, symtab)?;
+                value.write_as(buf, symtab, opts.bits_format)?;
                 write!(buf, " ")?;
                 read_kind.write(buf, symtab)?;
                 write!(buf, " ")?;
@@ -1664,10 +2447,15 @@ pub fn write_events_in_context<B: BV>(
                         v.write(buf, symtab)?
                     }
                 }
-                write!(buf, ")")
+                write_mem_kind(buf, &read_opts.kind)?;
+                write!(buf, ")")?;
+                if opts.bits_comments {
+                    require_newline = write_bits_comment(buf, value, &[])?
+                }
+                Ok(())
             }
 
-            WriteMem { value, write_kind, address, data, bytes, tag_value, opts: _, region: _ } => {
+            WriteMem { value, write_kind, address, data, bytes, tag_value, opts: write_opts, region: _ } => {
                 if *bytes == 0 && tag_value.is_some() {
                     write!(
                         buf,
@@ -1681,14 +2469,14 @@ pub fn write_events_in_context<B: BV>(
                 } else {
                     write!(
                         buf,
-                        "\n{}  (write-mem v{} {} {} {} {}",
+                        "\n{}  (write-mem v{} {} {} ",
                         indent,
                         value,
                         write_kind.to_string(symtab),
                         address.to_string(symtab),
-                        data.to_string(symtab),
-                        bytes
                     )?;
+                    data.write_as(buf, symtab, opts.bits_format)?;
+                    write!(buf, " {}", bytes)?;
                     match tag_value {
                         None => (),
                         Some(v) => {
@@ -1696,12 +2484,21 @@ pub fn write_events_in_context<B: BV>(
                             v.write(buf, symtab)?
                         }
                     }
-                    write!(buf, ")")
+                    write_mem_kind(buf, &write_opts.kind)?;
+                    write!(buf, ")")?;
+                    if opts.bits_comments {
+                        require_newline = write_bits_comment(buf, data, &[])?
+                    }
+                    Ok(())
                 }
             }
 
             Branch { address } => write!(buf, "\n{}  (branch-address {})", indent, address.to_string(symtab)),
 
+            AddressAnnounce { address } => {
+                write!(buf, "\n{}  (address-announce {})", indent, address.to_string(symtab))
+            }
+
             WriteReg(n, acc, v) => {
                 write!(
                     buf,
@@ -1710,8 +2507,13 @@ pub fn write_events_in_context<B: BV>(
                     zencode::decode(symtab.to_str(*n)),
                     accessor_to_string(acc, symtab)
                 )?;
-                v.write(buf, symtab)?;
-                write!(buf, ")")
+                v.write_as(buf, symtab, opts.bits_format)?;
+                write!(buf, ")")?;
+                if opts.bits_comments {
+                    let fields = comment_fields(opts.register_fields.get(n), acc, symtab);
+                    require_newline = write_bits_comment(buf, v, &fields)?
+                }
+                Ok(())
             }
 
             ReadReg(n, acc, v) => {
@@ -1725,8 +2527,13 @@ pub fn write_events_in_context<B: BV>(
                         zencode::decode(symtab.to_str(*n)),
                         accessor_to_string(acc, symtab)
                     )?;
-                    v.write(buf, symtab)?;
-                    write!(buf, ")")
+                    v.write_as(buf, symtab, opts.bits_format)?;
+                    write!(buf, ")")?;
+                    if opts.bits_comments {
+                        let fields = comment_fields(opts.register_fields.get(n), acc, symtab);
+                        require_newline = write_bits_comment(buf, v, &fields)?
+                    }
+                    Ok(())
                 }
             }
 
@@ -1737,9 +2544,36 @@ pub fn write_events_in_context<B: BV>(
                 Ok(())
             }
 
-            Cycle => write!(buf, "\n{}  (cycle)", indent),
+            Cycle(n) => write!(buf, "\n{}  (cycle {})", indent, n),
+
+            SleepRequest => write!(buf, "\n{}  (sleep-request)", indent),
+
+            WakeupRequest => write!(buf, "\n{}  (wakeup-request)", indent),
 
-            Instr(value) => write!(buf, "\n{}  (instr {})", indent, value.to_string(symtab)),
+            Sleeping(v) => write!(buf, "\n{}  (sleeping v{})", indent, v),
+
+            Interrupt { index, arrival } => write!(buf, "\n{}  (interrupt {} v{})", indent, index, arrival),
+
+            // Written before the next event it applies to, so
+            // provenance for events removed by simplification is
+            // never shown
+            Provenance { function, pc } => {
+                if opts.provenance {
+                    pending_provenance = Some((*function, *pc))
+                }
+                Ok(())
+            }
+
+            Instr(value) => {
+                write!(buf, "\n{}  (instr {})", indent, value.to_string(symtab))?;
+                if let (Some(names), Val::Bits(bv)) = (&opts.instruction_names, value) {
+                    if let Some(decoded) = names.decode(*bv) {
+                        write!(buf, " ; {}", decoded)?;
+                        require_newline = true
+                    }
+                }
+                Ok(())
+            }
 
             Assume(constraint) => {
                 write!(buf, "\n{}  (assume ", indent)?;
@@ -1758,29 +2592,108 @@ pub fn write_events_in_context<B: BV>(
                     v.to_string(symtab)
                 )
             }
-        })?
+        })?;
+        if as_comment {
+            for line in String::from_utf8_lossy(&comment).lines().filter(|line| !line.trim().is_empty()) {
+                write!(out, "\n{}; {}", indent, line.trim())?
+            }
+        }
     }
     if require_newline {
-        write!(buf, "\n{}", indent)?;
+        write!(out, "\n{}", indent)?;
     }
     if !(opts.just_smt || opts.prefix) {
-        writeln!(buf, ")")?;
+        writeln!(out, ")")?;
     }
     Ok(())
 }
 
+fn write_enum_datatype(buf: &mut dyn Write, size: usize, opts: &WriteOpts) -> std::io::Result<()> {
+    write!(buf, "(declare-datatypes ((Enum{} 0)) ((", size)?;
+    for member in 0..size {
+        if member != 0 {
+            write!(buf, " ")?
+        }
+        write!(buf, "({}{}_{})", opts.enum_prefix, size, member)?
+    }
+    write!(buf, ")))")
+}
+
+fn collect_enum_sizes(ty: &Ty, sizes: &mut BTreeSet<usize>) {
+    match ty {
+        Ty::Enum(e) => {
+            sizes.insert(e.to_usize());
+        }
+        Ty::Array(dom, codom) => {
+            collect_enum_sizes(dom, sizes);
+            collect_enum_sizes(codom, sizes)
+        }
+        _ => (),
+    }
+}
+
+/// Write events as a complete SMTLIB script which can be given
+/// directly to a solver. Any enumeration types used by declarations
+/// but not defined in the trace (e.g. because they were removed by
+/// simplification) are declared up-front.
+fn write_smt_script<B: BV>(buf: &mut dyn Write, events: &[Event<B>], symtab: &Symtab, opts: &WriteOpts) -> std::io::Result<()> {
+    let mut used = BTreeSet::new();
+    let mut defined = BTreeSet::new();
+    for event in events {
+        match event {
+            Smt(Def::DeclareConst(_, ty), _, _) => collect_enum_sizes(ty, &mut used),
+            Smt(Def::DeclareFun(_, arg_tys, result_ty), _, _) => {
+                arg_tys.iter().for_each(|ty| collect_enum_sizes(ty, &mut used));
+                collect_enum_sizes(result_ty, &mut used)
+            }
+            Smt(Def::DefineEnum(size), _, _) => {
+                defined.insert(*size);
+            }
+            _ => (),
+        }
+    }
+
+    let opts = WriteOpts { just_smt: true, types: true, prefix: false, ..opts.clone() };
+    write!(buf, "(set-logic ALL)")?;
+    for size in used.difference(&defined) {
+        writeln!(buf)?;
+        write_enum_datatype(buf, *size, &opts)?
+    }
+    let tcx: HashMap<Sym, Ty> = HashMap::new();
+    let ftcx: HashMap<Sym, (Vec<Ty>, Ty)> = HashMap::new();
+    write_events_in_context(buf, events, symtab, &opts, &mut Cow::Owned(tcx), &mut Cow::Owned(ftcx))?;
+    writeln!(buf, "\n(check-sat)")
+}
+
 pub fn write_events_with_opts<B: BV>(
     buf: &mut dyn Write,
     events: &[Event<B>],
     symtab: &Symtab,
     opts: &WriteOpts,
 ) -> std::io::Result<()> {
+    if opts.smt_script {
+        return write_smt_script(buf, events, symtab, opts);
+    }
+
     let tcx: HashMap<Sym, Ty> = HashMap::new();
     let ftcx: HashMap<Sym, (Vec<Ty>, Ty)> = HashMap::new();
 
     write_events_in_context(buf, events, symtab, opts, &mut Cow::Owned(tcx), &mut Cow::Owned(ftcx))
 }
 
+/// Run a registry of user passes over a trace (in chronological
+/// order), then write it as [write_events_with_opts] does.
+pub fn write_events_with_passes<B: BV>(
+    buf: &mut dyn Write,
+    events: Vec<Event<B>>,
+    symtab: &Symtab,
+    opts: &WriteOpts,
+    passes: &TracePasses<B>,
+) -> std::io::Result<()> {
+    let events = passes.run(events);
+    write_events_with_opts(buf, &events, symtab, opts)
+}
+
 pub fn write_events<B: BV>(buf: &mut dyn Write, events: &[Event<B>], symtab: &Symtab) {
     write_events_with_opts(buf, events, symtab, &WriteOpts::default()).unwrap()
 }
@@ -1828,7 +2741,7 @@ mod tests {
     #[test]
     fn break_forks_simple() {
         let events: Vec<Event<B64>> = vec![
-            Event::Cycle,
+            Event::Cycle(0),
             Event::Fork(0, Sym::from_u32(0), 0, SourceLoc::unknown()),
             Event::MarkReg { regs: vec![], mark: "foo".to_string() },
         ];
@@ -1837,7 +2750,7 @@ mod tests {
 
         assert_eq!(broken.len(), 2);
         assert_eq!(broken[0].0, None);
-        assert!(matches!(broken[0].2[0], Event::Cycle));
+        assert!(matches!(broken[0].2[0], Event::Cycle(_)));
         assert_eq!(broken[0].2.len(), 1);
         assert_eq!(broken[1].0, Some(0));
         assert!(matches!(broken[1].2[0], Event::MarkReg { .. }));
@@ -1857,15 +2770,355 @@ mod tests {
         assert_eq!(broken[1].2.len(), 0);
     }
 
+    #[test]
+    fn propagate_constants_simple() {
+        use crate::smt::DefAttrs;
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        // Events are in reverse order, as collected by the executor
+        let mut events: Vec<Event<B64>> = vec![
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(y)),
+            Event::Smt(
+                Def::DefineConst(y, Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(1, 64))))),
+                DefAttrs::default(),
+                SourceLoc::unknown(),
+            ),
+            Event::Smt(Def::DefineConst(x, Exp::Bits64(B64::new(2, 64))), DefAttrs::default(), SourceLoc::unknown()),
+        ];
+
+        propagate_constants(&mut events);
+        assert!(matches!(&events[1], Event::Smt(Def::DefineConst(_, Exp::Bits64(bv)), _, _) if bv.lower_u64() == 3));
+        assert!(matches!(&events[0], Event::WriteReg(_, _, Val::Bits(bv)) if bv.lower_u64() == 3));
+
+        remove_unused(&mut events);
+        assert_eq!(events.len(), 1)
+    }
+
+    #[test]
+    fn fold_register_reads_per_instruction() {
+        let r = Name::from_u32(0);
+        let read = || Event::ReadReg(r, vec![], Val::Bits(B64::new(1, 64)));
+        // Events are in reverse order, as collected by the executor
+        let mut events: Vec<Event<B64>> = vec![read(), Event::Cycle(0), read(), read(), read()];
+        fold_register_reads(&mut events);
+        assert_eq!(events.len(), 3);
+
+        let mut events: Vec<Event<B64>> = vec![read(), Event::Cycle(0), read(), read(), read()];
+        remove_repeated_register_reads(&mut events);
+        assert_eq!(events.len(), 2)
+    }
+
+    #[test]
+    fn trace_passes_in_order() {
+        let mut passes: TracePasses<B64> = TracePasses::new();
+        assert!(passes.is_empty());
+        passes.register("drop-cycles", |events| events.into_iter().filter(|ev| !ev.is_cycle()).collect());
+        passes.register("mark", |mut events| {
+            events.push(Event::MarkReg { regs: vec![], mark: "done".to_string() });
+            events
+        });
+        assert_eq!(passes.names().collect::<Vec<_>>(), vec!["drop-cycles", "mark"]);
+
+        let events = passes.run(vec![Event::Cycle(0), Event::Cycle(1)]);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::MarkReg { mark, .. } if mark == "done"));
+    }
+
+    #[test]
+    fn trace_passes_applied_to_tree() {
+        let mut passes: TracePasses<B64> = TracePasses::new();
+        passes.register("drop-cycles", |events| events.into_iter().filter(|ev| !ev.is_cycle()).collect());
+
+        let mut evtree = None;
+        passes.add_to_tree(&mut evtree, vec![Event::Cycle(0), Event::MarkReg { regs: vec![], mark: "a".to_string() }]);
+        let evtree = evtree.unwrap();
+        assert_eq!(evtree.prefix.len(), 1);
+        assert!(matches!(&evtree.prefix[0], Event::MarkReg { mark, .. } if mark == "a"));
+    }
+
+    #[test]
+    fn typecheck_events_locates_errors() {
+        use crate::smt::DefAttrs;
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let smt = |def| Event::Smt(def, DefAttrs::default(), SourceLoc::unknown());
+        let add = Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(1, 64))));
+        let mut events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(x, Ty::BitVec(64))),
+            smt(Def::DefineConst(y, add)),
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(y)),
+        ];
+        assert!(typecheck_events(&events).is_ok());
+
+        events.push(smt(Def::Assert(Exp::Var(y))));
+        assert_eq!(typecheck_events(&events).unwrap_err().event, 3);
+
+        events.pop();
+        events.push(smt(Def::Assert(Exp::Eq(Box::new(Exp::Var(y)), Box::new(Exp::Bits64(B64::new(0, 32)))))));
+        assert_eq!(typecheck_events(&events).unwrap_err().event, 3);
+
+        events.swap(0, 2);
+        assert_eq!(typecheck_events(&events).unwrap_err().event, 0)
+    }
+
+    #[test]
+    fn remove_undefined_register_writes() {
+        use crate::smt::DefAttrs;
+        let (x, u, y) = (Sym::from_u32(0), Sym::from_u32(1), Sym::from_u32(2));
+        let smt = |def, attrs| Event::Smt(def, attrs, SourceLoc::unknown());
+        let not = Exp::Bvnot(Box::new(Exp::Var(u)));
+        let mut events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(x, Ty::BitVec(64)), DefAttrs::default()),
+            smt(Def::DeclareConst(u, Ty::BitVec(64)), DefAttrs::undefined()),
+            smt(Def::DefineConst(y, not), DefAttrs::default()),
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(x)),
+            Event::WriteReg(Name::from_u32(1), vec![], Val::Symbolic(y)),
+        ];
+        assert_eq!(undefined_variables(&events), HashSet::from([u]));
+
+        let evrefs = EventReferences::from_events(&events);
+        assert_eq!(evrefs.undefined_dependencies(y, &HashSet::from([u])), HashSet::from([u]));
+
+        remove_undefined_writes(&mut events);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[3], Event::WriteReg(_, _, Val::Symbolic(v)) if *v == x))
+    }
+
+    #[test]
+    fn constant_writes_are_not_dont_care() {
+        use crate::smt::DefAttrs;
+        let (u, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let smt = |def, attrs| Event::Smt(def, attrs, SourceLoc::unknown());
+        let events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(u, Ty::BitVec(64)), DefAttrs::undefined()),
+            smt(Def::DefineConst(y, Exp::Bits64(B64::new(0, 64))), DefAttrs::default()),
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(y)),
+        ];
+
+        // A value that depends on no declared variables is not
+        // determined by the UNKNOWN ones
+        let evrefs = EventReferences::from_events(&events);
+        assert!(!evrefs.is_dont_care(&Val::Symbolic(y), &undefined_variables(&events)));
+
+        let mut events = events;
+        remove_undefined_writes(&mut events);
+        assert_eq!(events.len(), 3)
+    }
+
+    #[test]
+    fn write_named_assertions() {
+        use crate::smt::DefAttrs;
+        let x = Sym::from_u32(0);
+        let events: Vec<Event<B64>> = vec![
+            Event::Smt(Def::DeclareConst(x, Ty::Bool), DefAttrs::default(), SourceLoc::unknown()),
+            Event::Smt(
+                Def::NamedAssert("pre".to_string(), AssertionKind::Assumption, Exp::Var(x)),
+                DefAttrs::default(),
+                SourceLoc::unknown(),
+            ),
+        ];
+        let symtab = Symtab::new();
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts::default()).unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("(assume-smt |pre| v0)"));
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts::smtlib()).unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("(assert (! v0 :named |pre|))"))
+    }
+
+    #[test]
+    fn write_register_fields() {
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zSCTLR");
+        let events: Vec<Event<B64>> = vec![Event::WriteReg(reg, vec![], Val::Bits(B64::new(0b1101, 4)))];
+
+        let mut register_fields = HashMap::new();
+        register_fields.insert(
+            reg,
+            vec![RegisterField { name: "EE".to_string(), hi: 3, lo: 2 }, RegisterField { name: "M".to_string(), hi: 0, lo: 0 }],
+        );
+        let opts = WriteOpts {
+            bits_format: ValBitsFormat::Bin,
+            bits_comments: true,
+            register_fields,
+            ..WriteOpts::default()
+        };
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &opts).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(trace\n  (write-reg |SCTLR| nil #b1101) ; 13 EE=3 M=1\n)\n")
+    }
+
+    #[test]
+    fn write_packed_register_field() {
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zSCTLR");
+        let field = symtab.intern("zEE");
+        let events: Vec<Event<B64>> =
+            vec![Event::ReadReg(reg, vec![Accessor::Field(field)], Val::Bits(B64::new(0b1101, 4)))];
+
+        let mut register_fields = HashMap::new();
+        register_fields.insert(
+            reg,
+            vec![
+                RegisterField { name: "EE".to_string(), hi: 3, lo: 2 },
+                RegisterField { name: "M".to_string(), hi: 0, lo: 0 },
+            ],
+        );
+        let opts = WriteOpts {
+            bits_format: ValBitsFormat::Bin,
+            bits_comments: true,
+            register_fields,
+            ..WriteOpts::default()
+        };
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &opts).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "(trace\n  (read-reg |SCTLR| ((_ field |EE|)) #b1101) ; 13 EE=3\n)\n"
+        )
+    }
+
+    #[test]
+    fn write_instruction_names() {
+        let symtab = Symtab::new();
+        let events: Vec<Event<B64>> = vec![Event::Instr(Val::Bits(B64::new(0x003100b3, 32)))];
+
+        let mut names = InstructionNames::new();
+        names.load_riscv_opcodes("add rd rs1 rs2 31..25=0 14..12=0 6..2=0x0C 1..0=3").unwrap();
+        let opts = WriteOpts { instruction_names: Some(Arc::new(names)), ..WriteOpts::default() };
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &opts).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(trace\n  (instr #x003100b3) ; add rd=1 rs1=2 rs2=3\n)\n")
+    }
+
+    #[test]
+    fn write_smt_script_comments() {
+        use crate::smt::{DefAttrs, EnumId, EnumMember};
+        let mut symtab = Symtab::new();
+        let reg = symtab.intern("zR0");
+        let x = Sym::from_u32(0);
+        let e = EnumId::from_usize(2);
+        let events: Vec<Event<B64>> = vec![
+            Event::Smt(Def::DeclareConst(x, Ty::Enum(e)), DefAttrs::default(), SourceLoc::unknown()),
+            Event::WriteReg(reg, vec![], Val::Symbolic(x)),
+            Event::Smt(
+                Def::Assert(Exp::Eq(Box::new(Exp::Var(x)), Box::new(Exp::Enum(EnumMember { enum_id: e, member: 1 })))),
+                DefAttrs::default(),
+                SourceLoc::unknown(),
+            ),
+        ];
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts { smt_script: true, ..WriteOpts::default() })
+            .unwrap();
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.starts_with("(set-logic ALL)\n(declare-datatypes ((Enum2 0)) (((e2_0) (e2_1))))\n"));
+        assert!(script.contains("\n; (write-reg |R0| nil v0)\n(assert (= v0 e2_1))"));
+        assert!(script.ends_with("\n(check-sat)\n"))
+    }
+
+    #[test]
+    fn write_provenance_before_next_event() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("zf");
+        let g = symtab.intern("zg");
+        let events: Vec<Event<B64>> = vec![
+            Event::Provenance { function: f, pc: 3 },
+            Event::Provenance { function: g, pc: 5 },
+            Event::Cycle(0),
+        ];
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts { provenance: true, ..WriteOpts::default() })
+            .unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(trace\n  (provenance |g| 5)\n  (cycle 0))\n");
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts::default()).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(trace\n  (cycle 0))\n")
+    }
+
+    #[test]
+    fn write_mem_kind_annotation() {
+        use crate::smt::{EnumId, EnumMember, ReadOpts};
+        let symtab = Symtab::new();
+        let kind = MemKind { acquire: true, size_class: Some(4), ..MemKind::default() };
+        let events: Vec<Event<B64>> = vec![Event::ReadMem {
+            value: Val::Bits(B64::new(1, 8)),
+            read_kind: Val::Enum(EnumMember { enum_id: EnumId::from_usize(3), member: 1 }),
+            address: Val::Bits(B64::new(0x1000, 16)),
+            bytes: 1,
+            tag_value: None,
+            opts: ReadOpts { kind, ..ReadOpts::default() },
+            region: "default",
+        }];
+
+        let mut buf = Vec::new();
+        write_events_with_opts(&mut buf, &events, &symtab, &WriteOpts::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "(trace\n  (read-mem #x01 e3_1 #x1000 1 :kind (acquire (size-class 4))))\n"
+        )
+    }
+
+    #[test]
+    fn quantifier_bound_variables() {
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let exp =
+            Exp::Forall(vec![(x, Ty::BitVec(4))], Box::new(Exp::Bvule(Box::new(Exp::Var(x)), Box::new(Exp::Var(y)))));
+
+        let mut uses = HashMap::new();
+        uses_in_exp(&mut uses, &exp);
+        assert_eq!(uses.get(&x), None);
+        assert_eq!(uses.get(&y), Some(&1));
+
+        let mut tcx = HashMap::new();
+        assert!(exp.typecheck(&tcx, &HashMap::new()).is_err());
+        tcx.insert(y, Ty::BitVec(4));
+        assert_eq!(exp.typecheck(&tcx, &HashMap::new()), Ok(Ty::Bool));
+
+        let mut buf = Vec::new();
+        write_exp(&mut buf, &exp, &WriteOpts::smtlib()).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(forall ((v0 (_ BitVec 4))) (bvule v0 v1))")
+    }
+
+    #[test]
+    fn introduce_lets_shares_repeated_subterms() {
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let mul = || Box::new(Exp::Bvmul(Box::new(Exp::Var(x)), Box::new(Exp::Var(y))));
+        let mut exp = Exp::Bvadd(mul(), mul());
+
+        let mut next_var = 0;
+        introduce_lets(&mut exp, &mut next_var);
+        assert!(next_var > 2);
+
+        let mut tcx = HashMap::new();
+        tcx.insert(x, Ty::BitVec(8));
+        tcx.insert(y, Ty::BitVec(8));
+        assert_eq!(exp.typecheck(&tcx, &HashMap::new()), Ok(Ty::BitVec(8)));
+
+        let mut uses = HashMap::new();
+        uses_in_exp(&mut uses, &exp);
+        assert_eq!(uses.get(&x), Some(&1));
+        assert_eq!(uses.get(&Sym::from_u32(2)), None);
+
+        let mut buf = Vec::new();
+        write_exp(&mut buf, &exp, &WriteOpts::smtlib()).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "(let ((v2 (bvmul v0 v1))) (bvadd v2 v2))")
+    }
+
     #[test]
     fn evtree_add_events() {
         let events1: Vec<Event<B64>> = vec![
-            Event::Cycle,
+            Event::Cycle(0),
             Event::Fork(0, Sym::from_u32(0), 0, SourceLoc::unknown()),
             Event::MarkReg { regs: vec![], mark: "foo".to_string() },
         ];
         let events2: Vec<Event<B64>> =
-            vec![Event::Cycle, Event::Fork(0, Sym::from_u32(0), 0, SourceLoc::unknown()), Event::Cycle];
+            vec![Event::Cycle(0), Event::Fork(0, Sym::from_u32(0), 0, SourceLoc::unknown()), Event::Cycle(1)];
 
         let mut evtree = EventTree::from_events(&events1);
         evtree.add_events(&events2);
@@ -1886,7 +3139,7 @@ mod tests {
         let events2: Vec<Event<B64>> = vec![
             Event::Smt(Def::DefineEnum(2), DefAttrs::default(), SourceLoc::unknown()),
             Event::Fork(0, Sym::from_u32(1), 0, SourceLoc::unknown()),
-            Event::Cycle,
+            Event::Cycle(0),
         ];
 
         let mut evtree = EventTree::from_events(&events1);
@@ -1911,7 +3164,7 @@ mod tests {
                 DefAttrs::default(),
                 SourceLoc::unknown(),
         ),
-            Event::Cycle,
+            Event::Cycle(0),
         ];
         let events2: Vec<Event<B64>> = vec![
             Event::Smt(
@@ -1947,26 +3200,26 @@ mod tests {
     #[test]
     fn remove_repeated_regs() {
         let event = Event::ReadReg(Name::from_u32(0), vec![], Val::Bits(B64::from_u64(0x123)));
-        let mut events: Vec<Event<B64>> = vec![event.clone(), Event::Cycle, event];
+        let mut events: Vec<Event<B64>> = vec![event.clone(), Event::Cycle(0), event];
         remove_repeated_register_reads(&mut events);
         assert_eq!(events.len(), 2);
-        assert!(matches!(events[0], Event::Cycle));
+        assert!(matches!(events[0], Event::Cycle(_)));
 
         // We shouldn't see consecutive reads with different values,
         // but we want to keep them if we do.
         let event_1 = Event::ReadReg(Name::from_u32(0), vec![], Val::Bits(B64::from_u64(0x123)));
         let event_2 = Event::ReadReg(Name::from_u32(0), vec![], Val::Bits(B64::from_u64(0x456)));
-        let mut events: Vec<Event<B64>> = vec![event_1, Event::Cycle, event_2];
+        let mut events: Vec<Event<B64>> = vec![event_1, Event::Cycle(0), event_2];
         remove_repeated_register_reads(&mut events);
         assert_eq!(events.len(), 3);
-        assert!(matches!(events[1], Event::Cycle));
+        assert!(matches!(events[1], Event::Cycle(_)));
 
         let event_r = Event::ReadReg(Name::from_u32(0), vec![], Val::Bits(B64::from_u64(0x123)));
         let event_w = Event::WriteReg(Name::from_u32(0), vec![], Val::Bits(B64::from_u64(0x123)));
-        let mut events: Vec<Event<B64>> = vec![event_r.clone(), Event::Cycle, event_w, event_r];
+        let mut events: Vec<Event<B64>> = vec![event_r.clone(), Event::Cycle(0), event_w, event_r];
         remove_repeated_register_reads(&mut events);
         assert_eq!(events.len(), 4);
-        assert!(matches!(events[1], Event::Cycle));
+        assert!(matches!(events[1], Event::Cycle(_)));
 
         let field_1 = Accessor::Field(Name::from_u32(1));
         let field_2 = Accessor::Field(Name::from_u32(2));
@@ -1975,10 +3228,10 @@ mod tests {
         let val_1 = Val::Struct([(Name::from_u32(1), val_2.clone())].iter().cloned().collect());
         let event_r = Event::ReadReg(Name::from_u32(0), vec![field_1.clone(), field_2], val_1);
         let event_w = Event::WriteReg(Name::from_u32(0), vec![field_1], val_2);
-        let mut events: Vec<Event<B64>> = vec![event_r.clone(), Event::Cycle, event_w, event_r];
+        let mut events: Vec<Event<B64>> = vec![event_r.clone(), Event::Cycle(0), event_w, event_r];
         remove_repeated_register_reads(&mut events);
         assert_eq!(events.len(), 4);
-        assert!(matches!(events[1], Event::Cycle));
+        assert!(matches!(events[1], Event::Cycle(_)));
     }
 
     #[test]
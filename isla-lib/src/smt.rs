@@ -29,39 +29,56 @@
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 //! This module defines an interface with the SMT solver, primarily
-//! via the [Solver] type. It provides a safe abstraction over the
-//! [z3_sys] crate. In addition, all the interaction with the SMT
+//! via the [Solver] type. With the `z3` feature (the default) it
+//! provides a safe abstraction over the `z3_sys` crate. Without it,
+//! the solver is given SMTLIB scripts through a
+//! [SolverBackend](backend::SolverBackend), so the crate can be
+//! built for targets where Z3 cannot be linked, such as `wasm32`.
+//! In addition, all the interaction with the SMT
 //! solver is logged as a [Trace] in an SMTLIB-like format, expanded
 //! with additional events marking e.g. memory events, the start and
 //! end of processor cycles, etc (see the [Event] type). Points in
 //! these traces can be snapshotted and shared between threads via the
 //! [Checkpoint] type.
 
-use ahash;
+#[cfg(feature = "z3")]
 use libc::{c_int, c_uint};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "z3")]
 use z3_sys::*;
 
-use std::collections::HashMap;
+#[cfg(feature = "z3")]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "z3")]
 use std::convert::TryInto;
 use std::error::Error;
+#[cfg(feature = "z3")]
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::io::Write;
 use std::mem;
+#[cfg(feature = "z3")]
 use std::ptr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "z3")]
 use crate::bitvector::b64::B64;
 use crate::bitvector::BV;
 use crate::error::ExecError;
+#[cfg(feature = "z3")]
+use crate::ir::HashState;
 use crate::ir::{Loc, Name, Symtab, Val};
 use crate::source_loc::SourceLoc;
 use crate::zencode;
+use observer::{EventObserver, Observation};
+use spill::TraceSink;
+use unknown::{QuerySite, UnknownPolicies, UnknownPolicy, UnknownStats};
 
 /// A newtype wrapper for symbolic variables, which are `u32` under
 /// the hood.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Sym {
     pub(crate) id: u32,
 }
@@ -70,6 +87,10 @@ impl Sym {
     pub fn from_u32(id: u32) -> Self {
         Sym { id }
     }
+
+    pub fn to_u32(self) -> u32 {
+        self.id
+    }
 }
 
 impl<B> From<Sym> for Result<Val<B>, ExecError> {
@@ -84,7 +105,7 @@ impl fmt::Display for Sym {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnumId {
     id: usize,
 }
@@ -99,13 +120,21 @@ impl EnumId {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnumMember {
     pub enum_id: EnumId,
     pub member: usize,
 }
 
+pub mod backend;
+pub mod observer;
+pub mod query_log;
 pub mod smtlib;
+pub mod spill;
+pub mod unknown;
+#[cfg(not(feature = "z3"))]
+pub use backend::{global_set_param_value, Config, Context, Model};
+use query_log::{QueryLog, QueryLogEntry};
 use smtlib::*;
 
 /// Snapshot of interaction with underlying solver that can be
@@ -114,17 +143,84 @@ use smtlib::*;
 pub struct Checkpoint<B> {
     num: usize,
     next_var: u32,
+    solver_time: Duration,
     trace: Arc<Option<Trace<B>>>,
 }
 
 impl<B> Checkpoint<B> {
     pub fn new() -> Self {
-        Checkpoint { num: 0, next_var: 0, trace: Arc::new(None) }
+        Checkpoint { num: 0, next_var: 0, solver_time: Duration::ZERO, trace: Arc::new(None) }
     }
 
     pub fn trace(&self) -> &Option<Trace<B>> {
         &self.trace
     }
+
+    /// The index of the next fresh variable the solver will create
+    /// when restored from this checkpoint.
+    pub fn next_var(&self) -> u32 {
+        self.next_var
+    }
+
+    /// The number of events at the start of this checkpoint which are
+    /// shared with `other`, i.e. which were recorded before the paths
+    /// the checkpoints were taken from forked from each other.
+    pub fn shared_events(&self, other: &Checkpoint<B>) -> usize {
+        let mut ours = Vec::new();
+        let mut next = &*self.trace;
+        while let Some(trace) = next {
+            ours.push(trace as *const Trace<B>);
+            next = &*trace.tail
+        }
+        let mut next = &*other.trace;
+        while let Some(trace) = next {
+            if ours.contains(&(trace as *const Trace<B>)) {
+                return trace.len();
+            }
+            next = &*trace.tail
+        }
+        0
+    }
+}
+
+impl<B: Clone> Checkpoint<B> {
+    /// The events recorded between each earlier checkpoint and this
+    /// one, oldest first.
+    pub(crate) fn segments(&self) -> Vec<Vec<Event<B>>> {
+        let mut segments = Vec::with_capacity(self.num);
+        let mut next = &*self.trace;
+        while let Some(trace) = next {
+            segments.push(trace.head.clone());
+            next = &*trace.tail
+        }
+        segments.reverse();
+        segments
+    }
+
+    /// Rebuild a checkpoint from the segments returned by
+    /// [Checkpoint::segments].
+    pub(crate) fn from_segments(next_var: u32, segments: Vec<Vec<Event<B>>>) -> Self {
+        let num = segments.len();
+        let mut tail = Arc::new(None);
+        for (checkpoints, head) in segments.into_iter().enumerate() {
+            let tail_events = Trace::events_in(&tail);
+            tail = Arc::new(Some(Trace { checkpoints, head, tail_events, spill: Spilled::default(), tail }))
+        }
+        Checkpoint { num, next_var, solver_time: Duration::ZERO, trace: tail }
+    }
+
+    /// A new checkpoint with the given events added after this one.
+    /// The events before this checkpoint are shared, not copied.
+    pub(crate) fn extend(&self, events: Vec<Event<B>>) -> Self {
+        let trace = Arc::new(Some(Trace {
+            checkpoints: self.num,
+            head: events,
+            tail_events: Trace::events_in(&self.trace),
+            spill: Trace::spilled_in(&self.trace),
+            tail: self.trace.clone(),
+        }));
+        Checkpoint { num: self.num + 1, next_var: self.next_var, solver_time: self.solver_time, trace }
+    }
 }
 
 /// For the concurrency models, register accesses must be logged at a
@@ -150,34 +246,76 @@ impl Accessor {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// Structured attributes for a memory access. The read and write
+/// kinds passed to memory events by the model are opaque values, so
+/// these are derived from them using the `[memory_kinds]` table in
+/// the architecture configuration (see
+/// [crate::config::MemoryKinds]).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemKind {
+    pub acquire: bool,
+    pub release: bool,
+    pub exclusive: bool,
+    /// The access is one half of an atomic read-modify-write
+    pub rmw: bool,
+    /// An architecture-defined size class for the access, e.g. the
+    /// single-copy-atomic size in bytes
+    pub size_class: Option<u32>,
+}
+
+impl MemKind {
+    pub fn is_plain(&self) -> bool {
+        *self == MemKind::default()
+    }
+
+    /// Combine the attributes of two kinds, preferring the size
+    /// class of `self` if both have one
+    pub fn union(self, other: MemKind) -> MemKind {
+        MemKind {
+            acquire: self.acquire || other.acquire,
+            release: self.release || other.release,
+            exclusive: self.exclusive || other.exclusive,
+            rmw: self.rmw || other.rmw,
+            size_class: self.size_class.or(other.size_class),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ReadOpts {
     pub is_exclusive: bool,
     pub is_ifetch: bool,
+    pub kind: MemKind,
+    /// Shared with the write half of an atomic read-modify-write, see
+    /// [crate::config::MemoryKinds::annotate]
+    pub rmw_id: Option<u32>,
 }
 
 impl ReadOpts {
     pub fn ifetch() -> Self {
-        ReadOpts { is_exclusive: false, is_ifetch: true }
+        ReadOpts { is_exclusive: false, is_ifetch: true, kind: MemKind::default(), rmw_id: None }
     }
 
     pub fn exclusive() -> Self {
-        ReadOpts { is_exclusive: true, is_ifetch: false }
+        ReadOpts { is_exclusive: true, is_ifetch: false, kind: MemKind::default(), rmw_id: None }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct WriteOpts {
     is_exclusive: bool,
+    pub kind: MemKind,
+    /// Shared with the read half of an atomic read-modify-write
+    pub rmw_id: Option<u32>,
 }
 
 impl WriteOpts {
     pub fn exclusive() -> Self {
-        WriteOpts { is_exclusive: true }
+        WriteOpts { is_exclusive: true, kind: MemKind::default(), rmw_id: None }
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
 pub struct DefAttrs {
     attrs: u8,
 }
@@ -197,6 +335,25 @@ impl DefAttrs {
     pub fn is_uninteresting(self) -> bool {
         self.attrs & 1 > 0
     }
+
+    /// An 'undefined' definition declares a variable for an
+    /// architecturally UNKNOWN value created by one of Sail's
+    /// `undefined` builtins, rather than a genuine input such as an
+    /// uninitialised register or a symbolic argument. Such a variable
+    /// is only constrained by any bounds asserted alongside it, so
+    /// analyses may treat values determined entirely by these
+    /// variables as don't-cares.
+    pub fn undefined() -> Self {
+        DefAttrs { attrs: 2 }
+    }
+
+    pub fn is_undefined(self) -> bool {
+        self.attrs & 2 > 0
+    }
+
+    fn union(self, other: DefAttrs) -> Self {
+        DefAttrs { attrs: self.attrs | other.attrs }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -245,8 +402,39 @@ pub enum Event<B> {
     Branch {
         address: Val<B>,
     },
-    Cycle,
+    /// Emitted when the address of a memory access becomes
+    /// architecturally determined, which can be before the access
+    /// itself (and any translation it requires) takes place.
+    AddressAnnounce {
+        address: Val<B>,
+    },
+    /// The end of an instruction cycle, numbered from zero along each
+    /// path. The executor keeps the count across forks, so the same
+    /// instruction has the same index in every trace that contains it.
+    Cycle(u64),
     Instr(Val<B>),
+    /// The hart asked to sleep until woken, e.g. by executing a WFE
+    /// or WFI instruction.
+    SleepRequest,
+    /// The hart asked for any sleeping harts to be woken, e.g. by
+    /// executing a SEV instruction.
+    WakeupRequest,
+    /// The hart checked whether it is sleeping after a
+    /// `SleepRequest`. The symbolic boolean is true if the hart slept
+    /// (and was later woken), and false if it continued without
+    /// sleeping. Which of these is possible is decided by a scheduler
+    /// that can see the events of every hart.
+    Sleeping(Sym),
+    /// A symbolically arriving interrupt entered its window of
+    /// instruction boundaries, see [crate::executor::interrupt]. The
+    /// 64-bit bitvector `arrival` is the boundary it arrives at,
+    /// which is the end of the window if it does not arrive within
+    /// it. `index` is the position of the interrupt in the task's
+    /// list of interrupts.
+    Interrupt {
+        index: u32,
+        arrival: Sym,
+    },
     Assume(Exp<Loc<String>>),
     AssumeFun {
         name: Name,
@@ -258,6 +446,14 @@ pub enum Event<B> {
         args: Vec<Val<B>>,
         return_value: Val<B>,
     },
+    /// Records the Sail function and instruction index within it that
+    /// generated the subsequent events, up until the next
+    /// `Provenance` event. Only emitted when enabled with
+    /// [Solver::track_provenance].
+    Provenance {
+        function: Name,
+        pc: u32,
+    },
 }
 
 impl<B: BV> Event<B> {
@@ -315,16 +511,28 @@ impl<B: BV> Event<B> {
     }
 
     pub fn is_cycle(&self) -> bool {
-        matches!(self, Event::Cycle)
+        matches!(self, Event::Cycle(_))
     }
 
     pub fn is_instr(&self) -> bool {
         matches!(self, Event::Instr(_))
     }
 
+    pub fn is_sleep_request(&self) -> bool {
+        matches!(self, Event::SleepRequest)
+    }
+
+    pub fn is_wakeup_request(&self) -> bool {
+        matches!(self, Event::WakeupRequest)
+    }
+
     pub fn is_branch(&self) -> bool {
         matches!(self, Event::Branch { .. })
     }
+    pub fn is_address_announce(&self) -> bool {
+        matches!(self, Event::AddressAnnounce { .. })
+    }
+
     pub fn is_fork(&self) -> bool {
         matches!(self, Event::Fork(_, _, _, _))
     }
@@ -356,6 +564,16 @@ impl<B: BV> Event<B> {
         }
     }
 
+    /// The id linking the read and write halves of an atomic
+    /// read-modify-write, if this event is one of them
+    pub fn rmw_id(&self) -> Option<u32> {
+        match self {
+            Event::ReadMem { opts, .. } => opts.rmw_id,
+            Event::WriteMem { opts, .. } => opts.rmw_id,
+            _ => None,
+        }
+    }
+
     pub fn is_abstract(&self) -> bool {
         matches!(self, Event::Abstract { .. })
     }
@@ -404,22 +622,68 @@ pub type EvPath<B> = Vec<Event<B>>;
 pub struct Trace<B> {
     checkpoints: usize,
     head: Vec<Event<B>>,
+    /// The number of events in the tail, so the length of a trace
+    /// can be found without walking it
+    tail_events: usize,
+    /// Which events have been passed to a [spill::TraceSink]. Events
+    /// which were spilled and dropped are counted in `tail_events`.
+    spill: Spilled,
     tail: Arc<Option<Trace<B>>>,
 }
 
+/// Records how much of a trace has been written to a
+/// [spill::TraceSink]. The spilled events are always the oldest
+/// events of the path, so the full trace is the chain of chunks
+/// ending at `last_chunk` followed by the unspilled events.
+#[derive(Copy, Clone, Debug, Default)]
+struct Spilled {
+    /// The number of oldest events still in memory which have been
+    /// spilled, as they are needed for replaying
+    in_memory: usize,
+    /// The total number of events spilled, including those dropped
+    events: usize,
+    last_chunk: Option<u64>,
+}
+
+impl<B> Trace<B> {
+    fn events_in(trace: &Option<Trace<B>>) -> usize {
+        trace.as_ref().map_or(0, Trace::len)
+    }
+
+    fn spilled_in(trace: &Option<Trace<B>>) -> Spilled {
+        trace.as_ref().map_or(Spilled::default(), |trace| trace.spill)
+    }
+
+    /// The total number of events in the trace
+    pub fn len(&self) -> usize {
+        self.tail_events + self.head.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 impl<B: BV> Trace<B> {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        Trace { checkpoints: 0, head: Vec::new(), tail: Arc::new(None) }
+        Trace { checkpoints: 0, head: Vec::new(), tail_events: 0, spill: Spilled::default(), tail: Arc::new(None) }
     }
 
     pub fn checkpoint(&mut self, next_var: u32) -> Checkpoint<B> {
         let mut head = Vec::new();
         mem::swap(&mut self.head, &mut head);
-        let tail = Arc::new(Some(Trace { checkpoints: self.checkpoints, head, tail: self.tail.clone() }));
+        let tail = Arc::new(Some(Trace {
+            checkpoints: self.checkpoints,
+            head,
+            tail_events: self.tail_events,
+            spill: self.spill,
+            tail: self.tail.clone(),
+        }));
         self.checkpoints += 1;
+        self.tail_events = Trace::events_in(&tail);
         self.tail = tail.clone();
-        Checkpoint { num: self.checkpoints, trace: tail, next_var }
+        Checkpoint { num: self.checkpoints, trace: tail, solver_time: Duration::ZERO, next_var }
     }
 
     pub fn to_vec<'a>(&'a self) -> Vec<&'a Event<B>> {
@@ -440,32 +704,52 @@ impl<B: BV> Trace<B> {
             }
         }
     }
+
+    /// Like [Trace::to_vec], but without the events which have
+    /// already been passed to a [spill::TraceSink]. Those events are
+    /// the chunk returned by [Trace::last_chunk] and its ancestors.
+    pub fn to_vec_unspilled<'a>(&'a self) -> Vec<&'a Event<B>> {
+        let mut vec = self.to_vec();
+        vec.truncate(vec.len() - self.spill.in_memory);
+        vec
+    }
+
+    /// The id of the last chunk of this trace passed to a
+    /// [spill::TraceSink], if any.
+    pub fn last_chunk(&self) -> Option<u64> {
+        self.spill.last_chunk
+    }
 }
 
+#[cfg(feature = "z3")]
 /// Config is a wrapper around the `Z3_config` type from the C
 /// API. `Z3_del_config` is called when it is dropped.
 pub struct Config {
     z3_cfg: Z3_config,
 }
 
+#[cfg(feature = "z3")]
 impl Config {
     pub fn new() -> Self {
         unsafe { Config { z3_cfg: Z3_mk_config() } }
     }
 }
 
+#[cfg(feature = "z3")]
 impl Drop for Config {
     fn drop(&mut self) {
         unsafe { Z3_del_config(self.z3_cfg) }
     }
 }
 
+#[cfg(feature = "z3")]
 impl Default for Config {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "z3")]
 impl Config {
     pub fn set_param_value(&mut self, id: &str, value: &str) {
         let id = CString::new(id).unwrap();
@@ -474,17 +758,20 @@ impl Config {
     }
 }
 
+#[cfg(feature = "z3")]
 pub fn global_set_param_value(id: &str, value: &str) {
     let id = CString::new(id).unwrap();
     let value = CString::new(value).unwrap();
     unsafe { Z3_global_param_set(id.as_ptr(), value.as_ptr()) }
 }
 
+#[cfg(feature = "z3")]
 /// Context is a wrapper around `Z3_context`.
 pub struct Context {
     z3_ctx: Z3_context,
 }
 
+#[cfg(feature = "z3")]
 impl Context {
     pub fn new(cfg: Config) -> Self {
         unsafe { Context { z3_ctx: Z3_mk_context_rc(cfg.z3_cfg) } }
@@ -500,23 +787,27 @@ impl Context {
     }
 }
 
+#[cfg(feature = "z3")]
 impl Drop for Context {
     fn drop(&mut self) {
         unsafe { Z3_del_context(self.z3_ctx) }
     }
 }
 
+#[cfg(feature = "z3")]
 struct Enum {
     sort: Z3_sort,
     consts: Vec<Z3_func_decl>,
     testers: Vec<Z3_func_decl>,
 }
 
+#[cfg(feature = "z3")]
 struct Enums<'ctx> {
-    enums: HashMap<usize, Enum, ahash::RandomState>,
+    enums: HashMap<usize, Enum, HashState>,
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Enums<'ctx> {
     fn new(ctx: &'ctx Context) -> Self {
         Enums { enums: HashMap::default(), ctx }
@@ -556,6 +847,7 @@ impl<'ctx> Enums<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Drop for Enums<'ctx> {
     fn drop(&mut self) {
         unsafe {
@@ -571,11 +863,13 @@ impl<'ctx> Drop for Enums<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 struct Sort<'ctx> {
     z3_sort: Z3_sort,
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Sort<'ctx> {
     fn float(ctx: &'ctx Context, ebits: u32, sbits: u32) -> Self {
         assert!(ebits > 1 && sbits > 2);
@@ -627,6 +921,7 @@ impl<'ctx> Sort<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Drop for Sort<'ctx> {
     fn drop(&mut self) {
         unsafe {
@@ -636,11 +931,13 @@ impl<'ctx> Drop for Sort<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 struct FuncDecl<'ctx> {
     z3_func_decl: Z3_func_decl,
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> FuncDecl<'ctx> {
     fn new(ctx: &'ctx Context, v: Sym, enums: &Enums<'ctx>, arg_tys: &[Ty], ty: &Ty) -> Self {
         unsafe {
@@ -656,6 +953,7 @@ impl<'ctx> FuncDecl<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Drop for FuncDecl<'ctx> {
     fn drop(&mut self) {
         unsafe {
@@ -665,11 +963,13 @@ impl<'ctx> Drop for FuncDecl<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 struct Ast<'ctx> {
     z3_ast: Z3_ast,
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Clone for Ast<'ctx> {
     fn clone(&self) -> Self {
         unsafe {
@@ -680,6 +980,7 @@ impl<'ctx> Clone for Ast<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 macro_rules! z3_nullary_op {
     ($i:ident, $ctx:ident) => {
         unsafe {
@@ -690,6 +991,7 @@ macro_rules! z3_nullary_op {
     };
 }
 
+#[cfg(feature = "z3")]
 macro_rules! z3_unary_op {
     ($i:ident, $arg:ident) => {
         unsafe {
@@ -700,6 +1002,7 @@ macro_rules! z3_unary_op {
     };
 }
 
+#[cfg(feature = "z3")]
 macro_rules! z3_binary_op {
     ($i:ident, $lhs:ident, $rhs:ident) => {
         unsafe {
@@ -710,6 +1013,7 @@ macro_rules! z3_binary_op {
     };
 }
 
+#[cfg(feature = "z3")]
 macro_rules! z3_float_binary_op {
     ($i:ident, $rm:ident, $lhs:ident, $rhs:ident) => {
         unsafe {
@@ -720,6 +1024,7 @@ macro_rules! z3_float_binary_op {
     };
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Ast<'ctx> {
     fn mk_constant(fd: &FuncDecl<'ctx>) -> Self {
         unsafe {
@@ -1152,6 +1457,21 @@ impl<'ctx> Ast<'ctx> {
         }
     }
 
+    fn mk_quantifier(forall: bool, bound: &[Ast<'ctx>], body: &Ast<'ctx>) -> Self {
+        unsafe {
+            let z3_ctx = body.ctx.z3_ctx;
+            let z3_bound: Vec<Z3_app> = bound.iter().map(|ast| Z3_to_app(z3_ctx, ast.z3_ast)).collect();
+            let len = z3_bound.len() as u32;
+            let z3_ast = if forall {
+                Z3_mk_forall_const(z3_ctx, 0, len, z3_bound.as_ptr(), 0, ptr::null(), body.z3_ast)
+            } else {
+                Z3_mk_exists_const(z3_ctx, 0, len, z3_bound.as_ptr(), 0, ptr::null(), body.z3_ast)
+            };
+            Z3_inc_ref(z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: body.ctx }
+        }
+    }
+
     fn mk_distinct(ctx: &'ctx Context, args: &[Ast<'ctx>]) -> Self {
         unsafe {
             let z3_args: Vec<Z3_ast> = args.iter().map(|ast| ast.z3_ast).collect();
@@ -1184,6 +1504,7 @@ impl<'ctx> Ast<'ctx> {
     }
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx> Drop for Ast<'ctx> {
     fn drop(&mut self) {
         unsafe { Z3_dec_ref(self.ctx.z3_ctx, self.z3_ast) }
@@ -1244,13 +1565,66 @@ pub struct Solver<'ctx, B> {
     next_var: u32,
     def_attrs: DefAttrs,
     cycles: i128,
+    /// True if a `SleepRequest` event has not yet been followed by a
+    /// `Sleeping` check. As this is derived from the trace it
+    /// survives restoring from a checkpoint.
+    sleep_requested: bool,
+    /// If true, checking whether the hart is sleeping after a sleep
+    /// request gives a symbolic result, see [Solver::symbolic_sleep].
+    symbolic_sleep: bool,
+    /// The arrival points of symbolic interrupts recorded by
+    /// `Interrupt` events, also derived from the trace.
+    interrupt_arrivals: HashMap<u32, Sym>,
+    /// The data of the most recent write to each range of concrete
+    /// addresses, keyed by the start of the range, see
+    /// [Solver::writes_overlapping]. The ranges never overlap, as a
+    /// write replaces any earlier writes it overlaps.
+    latest_writes: BTreeMap<u64, (u32, Val<B>)>,
+    /// The total time spent in satisfiability checks on this path,
+    /// including before any checkpoint it was restored from
+    solver_time: Duration,
+    #[cfg(feature = "z3")]
     decls: HashMap<Sym, Ast<'ctx>>,
+    /// Variables bound by the quantifiers and lets currently being translated
+    #[cfg(feature = "z3")]
+    bound: RefCell<HashMap<Sym, Ast<'ctx>>>,
+    #[cfg(feature = "z3")]
     func_decls: HashMap<Sym, FuncDecl<'ctx>>,
+    #[cfg(feature = "z3")]
     enums: Enums<'ctx>,
+    /// Without native Z3, every definition is kept so the whole
+    /// script can be given to the backend for each check
+    #[cfg(not(feature = "z3"))]
+    script: backend::SmtScript<B>,
+    /// The assumption used by the last check, which is needed to
+    /// get values from the corresponding model
+    #[cfg(not(feature = "z3"))]
+    assumption: Option<Exp<Sym>>,
+    /// A failure of the backend during a check, see
+    /// [Solver::check_script]
+    #[cfg(not(feature = "z3"))]
+    backend_failure: Option<String>,
+    query_log: Option<(Arc<QueryLog>, u64)>,
+    /// When tracking provenance, the current position in the model
+    /// and the last position recorded in the trace
+    track_provenance: bool,
+    position: Option<(Name, u32)>,
+    last_provenance: Option<(Name, u32)>,
+    unknown_policies: UnknownPolicies,
+    unknown_stats: Option<Arc<UnknownStats>>,
+    /// An observer for events as they are added, with the id of the
+    /// task the solver belongs to
+    observer: Option<(Arc<dyn EventObserver<B>>, usize)>,
+    aborted: Option<String>,
+    /// A sink for events, with the number of unwritten events to
+    /// collect before spilling them and the id of the task
+    trace_sink: Option<(Arc<dyn TraceSink<B>>, usize, usize)>,
+    #[cfg(feature = "z3")]
     z3_solver: Z3_solver,
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx, B> Drop for Solver<'ctx, B> {
     fn drop(&mut self) {
         unsafe {
@@ -1259,6 +1633,7 @@ impl<'ctx, B> Drop for Solver<'ctx, B> {
     }
 }
 
+#[cfg(feature = "z3")]
 /// Interface for extracting information from Z3 models.
 ///
 /// Model generation should be turned on in advance.  This is
@@ -1287,6 +1662,7 @@ pub struct Model<'ctx, B> {
     ctx: &'ctx Context,
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx, B> Drop for Model<'ctx, B> {
     fn drop(&mut self) {
         unsafe {
@@ -1295,6 +1671,7 @@ impl<'ctx, B> Drop for Model<'ctx, B> {
     }
 }
 
+#[cfg(feature = "z3")]
 // This implements Debug rather than Display because it displays the internal
 // variable names (albeit with the same numbers that appear in the trace).
 impl<'ctx, B> fmt::Debug for Model<'ctx, B> {
@@ -1306,6 +1683,7 @@ impl<'ctx, B> fmt::Debug for Model<'ctx, B> {
     }
 }
 
+#[cfg(feature = "z3")]
 impl<'ctx, B: BV> Model<'ctx, B> {
     pub fn new(solver: &'ctx Solver<'ctx, B>) -> Self {
         unsafe {
@@ -1415,7 +1793,15 @@ impl<'ctx, B: BV> Model<'ctx, B> {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The values found by [Solver::enumerate_values]. If `complete` is
+/// false there may be further values beyond those listed.
+#[derive(Clone, Debug)]
+pub struct EnumeratedValues {
+    pub values: Vec<Exp<Sym>>,
+    pub complete: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SmtResult {
     Sat,
     Unsat,
@@ -1446,37 +1832,93 @@ impl SmtResult {
     }
 }
 
+#[cfg(feature = "z3")]
 static QFAUFBV_STR: &[u8] = b"qfaufbv\0";
 
+#[cfg(feature = "z3")]
+fn mk_qfaufbv_solver(ctx: &Context) -> Z3_solver {
+    unsafe {
+        let mut major: c_uint = 0;
+        let mut minor: c_uint = 0;
+        let mut build: c_uint = 0;
+        let mut revision: c_uint = 0;
+        Z3_get_version(&mut major, &mut minor, &mut build, &mut revision);
+
+        // The QF_AUFBV solver has good performance on our problems, but we need to initialise it
+        // using a tactic rather than the logic name to ensure that the enumerations are supported,
+        // otherwise Z3 may crash.
+        let qfaufbv_tactic = Z3_mk_tactic(ctx.z3_ctx, CStr::from_bytes_with_nul_unchecked(QFAUFBV_STR).as_ptr());
+        Z3_tactic_inc_ref(ctx.z3_ctx, qfaufbv_tactic);
+        let z3_solver = Z3_mk_solver_from_tactic(ctx.z3_ctx, qfaufbv_tactic);
+        Z3_solver_inc_ref(ctx.z3_ctx, z3_solver);
+        z3_solver
+    }
+}
+
 impl<'ctx, B: BV> Solver<'ctx, B> {
     pub fn new(ctx: &'ctx Context) -> Self {
-        unsafe {
-            let mut major: c_uint = 0;
-            let mut minor: c_uint = 0;
-            let mut build: c_uint = 0;
-            let mut revision: c_uint = 0;
-            Z3_get_version(&mut major, &mut minor, &mut build, &mut revision);
-
-            // The QF_AUFBV solver has good performance on our problems, but we need to initialise it
-            // using a tactic rather than the logic name to ensure that the enumerations are supported,
-            // otherwise Z3 may crash.
-            let qfaufbv_tactic = Z3_mk_tactic(ctx.z3_ctx, CStr::from_bytes_with_nul_unchecked(QFAUFBV_STR).as_ptr());
-            Z3_tactic_inc_ref(ctx.z3_ctx, qfaufbv_tactic);
-            let z3_solver = Z3_mk_solver_from_tactic(ctx.z3_ctx, qfaufbv_tactic);
-            Z3_solver_inc_ref(ctx.z3_ctx, z3_solver);
-
-            Solver {
-                ctx,
-                z3_solver,
-                next_var: 0,
-                def_attrs: DefAttrs::default(),
-                cycles: 0,
-                trace: Trace::new(),
-                decls: HashMap::new(),
-                func_decls: HashMap::new(),
-                enums: Enums::new(ctx),
+        Solver {
+            ctx,
+            #[cfg(feature = "z3")]
+            z3_solver: mk_qfaufbv_solver(ctx),
+            next_var: 0,
+            def_attrs: DefAttrs::default(),
+            cycles: 0,
+            sleep_requested: false,
+            symbolic_sleep: false,
+            interrupt_arrivals: HashMap::new(),
+            latest_writes: BTreeMap::new(),
+            solver_time: Duration::ZERO,
+            trace: Trace::new(),
+            #[cfg(feature = "z3")]
+            decls: HashMap::new(),
+            #[cfg(feature = "z3")]
+            bound: RefCell::new(HashMap::new()),
+            #[cfg(feature = "z3")]
+            func_decls: HashMap::new(),
+            #[cfg(feature = "z3")]
+            enums: Enums::new(ctx),
+            #[cfg(not(feature = "z3"))]
+            script: backend::SmtScript::new(),
+            #[cfg(not(feature = "z3"))]
+            assumption: None,
+            #[cfg(not(feature = "z3"))]
+            backend_failure: None,
+            query_log: None,
+            track_provenance: false,
+            position: None,
+            last_provenance: None,
+            unknown_policies: UnknownPolicies::default(),
+            unknown_stats: None,
+            observer: None,
+            aborted: None,
+            trace_sink: None,
+        }
+    }
+
+    /// Log every definition and satisfiability check made by this
+    /// solver from now on to a new session in `log`. Definitions
+    /// already in the solver (e.g. from a checkpoint) are written
+    /// first, so each session can be replayed on its own.
+    pub fn log_queries(&mut self, log: Arc<QueryLog>) {
+        let session = log.start_session();
+        for event in self.trace.to_vec().iter().rev() {
+            if let Event::Smt(def, _, _) = event {
+                log.write(session, &QueryLogEntry::Def(def.clone()))
             }
         }
+        self.query_log = Some((log, session))
+    }
+
+    /// Record the time taken by a satisfiability check, and log it
+    /// if a query log is attached
+    fn finish_check_sat(&mut self, assumption: Option<&Exp<Sym>>, result: SmtResult, start: Instant) {
+        let elapsed = start.elapsed();
+        self.solver_time += elapsed;
+        if let Some((log, session)) = &self.query_log {
+            let micros = elapsed.as_micros() as u64;
+            log.write(*session, &QueryLogEntry::CheckSat { assumption: assumption.cloned(), result, micros })
+        }
     }
 
     pub fn fresh(&mut self) -> Sym {
@@ -1485,11 +1927,16 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         Sym { id: n }
     }
 
+    #[cfg(feature = "z3")]
     fn translate_exp(&self, exp: &Exp<Sym>) -> Ast<'ctx> {
         use Exp::*;
         match exp {
-            Var(v) => match self.decls.get(v) {
-                None => panic!("Could not get Z3 func_decl {}", *v),
+            // Bound variables shadow any declaration with the same name
+            Var(v) => match self.bound.borrow().get(v) {
+                None => match self.decls.get(v) {
+                    None => panic!("Could not get Z3 func_decl {}", *v),
+                    Some(ast) => ast.clone(),
+                },
                 Some(ast) => ast.clone(),
             },
             Bits(bv) => Ast::mk_bv(self.ctx, bv.len().try_into().unwrap(), bv),
@@ -1627,9 +2074,51 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
                 &self.translate_exp(y),
                 &self.translate_exp(z),
             ),
+            Forall(vars, body) => self.translate_quantifier(true, vars, body),
+            Exists(vars, body) => self.translate_quantifier(false, vars, body),
+            Let(bindings, body) => self.translate_let(bindings, body),
         }
     }
 
+    #[cfg(feature = "z3")]
+    /// Z3 shares identical terms internally, so a let can be
+    /// translated by binding each variable to its translated
+    /// expression.
+    fn translate_let(&self, bindings: &[(Sym, Exp<Sym>)], body: &Exp<Sym>) -> Ast<'ctx> {
+        let bound: Vec<(Sym, Ast<'ctx>)> = bindings.iter().map(|(v, exp)| (*v, self.translate_exp(exp))).collect();
+        let shadowed: Vec<(Sym, Option<Ast<'ctx>>)> = {
+            let mut scope = self.bound.borrow_mut();
+            bound.into_iter().map(|(v, ast)| (v, scope.insert(v, ast))).collect()
+        };
+        let body = self.translate_exp(body);
+        let mut scope = self.bound.borrow_mut();
+        for (v, prev) in shadowed {
+            match prev {
+                Some(ast) => scope.insert(v, ast),
+                None => scope.remove(&v),
+            };
+        }
+        body
+    }
+
+    #[cfg(feature = "z3")]
+    fn translate_quantifier(&self, forall: bool, vars: &[(Sym, Ty)], body: &Exp<Sym>) -> Ast<'ctx> {
+        let bound: Vec<Ast<'ctx>> = vars
+            .iter()
+            .map(|(v, ty)| {
+                let ast = Ast::mk_constant(&FuncDecl::new(self.ctx, *v, &self.enums, &[], ty));
+                self.bound.borrow_mut().insert(*v, ast.clone());
+                ast
+            })
+            .collect();
+        let body = self.translate_exp(body);
+        for (v, _) in vars {
+            self.bound.borrow_mut().remove(v);
+        }
+        Ast::mk_quantifier(forall, &bound, &body)
+    }
+
+    #[cfg(feature = "z3")]
     fn z3_assert(&mut self, exp: &Exp<Sym>) {
         let ast = self.translate_exp(exp);
         unsafe {
@@ -1638,15 +2127,27 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
     }
 
     pub fn get_enum(&mut self, size: usize) -> EnumId {
-        if !self.enums.enums.contains_key(&size) {
+        #[cfg(feature = "z3")]
+        let defined = self.enums.enums.contains_key(&size);
+        #[cfg(not(feature = "z3"))]
+        let defined = self.script.has_enum(size);
+        if !defined {
             self.add(Def::DefineEnum(size))
         };
         EnumId { id: size }
     }
 
     fn add_internal(&mut self, def: &Def) {
+        if let Some((log, session)) = &self.query_log {
+            log.write(*session, &QueryLogEntry::Def(def.clone()))
+        }
+        self.add_to_solver(def)
+    }
+
+    #[cfg(feature = "z3")]
+    fn add_to_solver(&mut self, def: &Def) {
         match &def {
-            Def::Assert(exp) => self.z3_assert(exp),
+            Def::Assert(exp) | Def::NamedAssert(_, _, exp) => self.z3_assert(exp),
             Def::DeclareConst(v, ty) => {
                 let fd = FuncDecl::new(self.ctx, *v, &self.enums, &[], ty);
                 self.decls.insert(*v, Ast::mk_constant(&fd));
@@ -1669,6 +2170,21 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    #[cfg(not(feature = "z3"))]
+    fn add_to_solver(&mut self, def: &Def) {
+        if let Def::DefineEnum(size) = def {
+            // Allocate the same names as native Z3 would, so
+            // variable numbering does not depend on the solver
+            if !self.script.has_enum(*size) {
+                for _ in 0..=*size {
+                    self.fresh();
+                }
+            }
+        }
+        self.script.add(def.clone())
+    }
+
+    #[cfg(feature = "z3")]
     pub fn length(&mut self, v: Sym) -> Option<u32> {
         match self.decls.get(&v) {
             Some(ast) => unsafe {
@@ -1688,6 +2204,12 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    #[cfg(not(feature = "z3"))]
+    pub fn length(&mut self, v: Sym) -> Option<u32> {
+        self.script.length(v)
+    }
+
+    #[cfg(feature = "z3")]
     pub fn is_bitvector(&mut self, v: Sym) -> bool {
         match self.decls.get(&v) {
             Some(ast) => unsafe {
@@ -1702,6 +2224,11 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    #[cfg(not(feature = "z3"))]
+    pub fn is_bitvector(&mut self, v: Sym) -> bool {
+        self.script.length(v).is_some()
+    }
+
     pub fn with_def_attrs<F, A>(&mut self, attrs: DefAttrs, f: F) -> A where F: Fn(&mut Self) -> A {
         let old_attrs = self.def_attrs;
         self.def_attrs = attrs;
@@ -1720,12 +2247,74 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
 
     pub fn add(&mut self, def: Def) {
         self.add_internal(&def);
-        self.trace.head.push(Event::Smt(def, self.def_attrs, SourceLoc::unknown()))
+        self.trace.head.push(Event::Smt(def, self.def_attrs, SourceLoc::unknown()));
+        self.observe_last();
+        self.spill_if_full()
     }
 
     pub fn add_with_location(&mut self, def: Def, info: SourceLoc) {
         self.add_internal(&def);
-        self.trace.head.push(Event::Smt(def, self.def_attrs, info))
+        self.trace.head.push(Event::Smt(def, self.def_attrs, info));
+        self.observe_last();
+        self.spill_if_full()
+    }
+
+    /// Pass every event added to this solver from now on to
+    /// `observer`, see [crate::smt::observer].
+    pub fn set_observer(&mut self, observer: Arc<dyn EventObserver<B>>, task_id: usize) {
+        self.observer = Some((observer, task_id))
+    }
+
+    /// Returns the reason the observer asked for the path to be
+    /// aborted, if it has done so since the last call. Without native
+    /// Z3, a failure of the solver backend also aborts the path.
+    pub fn take_abort(&mut self) -> Option<String> {
+        #[cfg(not(feature = "z3"))]
+        if let Some(msg) = self.backend_failure.take() {
+            return Some(format!("Solver backend failed: {}", msg));
+        }
+        self.aborted.take()
+    }
+
+    /// Write the events of this path to `sink` in chunks of
+    /// `threshold` events while it executes, see [crate::smt::spill].
+    pub fn set_trace_sink(&mut self, sink: Arc<dyn TraceSink<B>>, threshold: usize, task_id: usize) {
+        self.trace_sink = Some((sink, threshold, task_id))
+    }
+
+    fn spill_if_full(&mut self) {
+        let Some((sink, threshold, task_id)) = &self.trace_sink else {
+            return;
+        };
+        if self.trace.len() - self.trace.spill.events < *threshold {
+            return;
+        }
+        // Unspilled events before the last checkpoint are shared with
+        // other paths, so they are written but can't be dropped
+        let events: Vec<&Event<B>> = self.trace.to_vec_unspilled().into_iter().rev().collect();
+        let unspilled = events.len();
+        match sink.spill(*task_id, self.trace.spill.last_chunk, &events) {
+            Ok(chunk) => self.trace.spill.last_chunk = Some(chunk),
+            Err(e) => {
+                self.aborted.get_or_insert(format!("Failed to write trace: {}", e));
+                self.trace_sink = None;
+                return;
+            }
+        }
+        let len = self.trace.head.len();
+        self.trace.head.retain(spill::needed_for_replay);
+        let dropped = len - self.trace.head.len();
+        self.trace.tail_events += dropped;
+        self.trace.spill.in_memory += unspilled - dropped;
+        self.trace.spill.events = self.trace.len()
+    }
+
+    fn observe_last(&mut self) {
+        if let (Some((observer, task_id)), Some(event)) = (&self.observer, self.trace.head.last()) {
+            if let Observation::Abort(reason) = observer.observe(*task_id, event, &self.trace) {
+                self.aborted.get_or_insert(reason);
+            }
+        }
     }
 
     pub fn declare_const(&mut self, ty: Ty, info: SourceLoc) -> Sym {
@@ -1734,6 +2323,25 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         sym
     }
 
+    /// Declare a variable for an architecturally UNKNOWN value (see
+    /// [DefAttrs::undefined])
+    pub fn declare_undefined(&mut self, ty: Ty, info: SourceLoc) -> Sym {
+        self.with_undefined(|solver| solver.declare_const(ty, info))
+    }
+
+    /// Run `f`, marking every definition it adds as UNKNOWN in
+    /// addition to any current attributes
+    pub fn with_undefined<F, A>(&mut self, f: F) -> A
+    where
+        F: FnOnce(&mut Self) -> A,
+    {
+        let old_attrs = self.def_attrs;
+        self.def_attrs = old_attrs.union(DefAttrs::undefined());
+        let x = f(self);
+        self.def_attrs = old_attrs;
+        x
+    }
+
     pub fn define_const(&mut self, exp: Exp<Sym>, info: SourceLoc) -> Sym {
         let sym = self.fresh();
         self.add_with_location(Def::DefineConst(sym, exp), info);
@@ -1754,24 +2362,106 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         self.add(Def::Assert(exp))
     }
 
+    /// Assert a precondition supplied by the user, which is recorded
+    /// in the trace separately from the assertions generated during
+    /// execution.
+    pub fn assume(&mut self, name: String, exp: Exp<Sym>) {
+        self.add(Def::NamedAssert(name, AssertionKind::Assumption, exp))
+    }
+
     pub fn cycle_count(&mut self) {
-        self.cycles += 1;
-        self.add_event(Event::Cycle)
+        self.add_event(Event::Cycle(self.cycles as u64))
     }
 
     pub fn get_cycle_count(&self) -> i128 {
         self.cycles
     }
 
+    /// Returns true if the hart has asked to sleep, and has not yet
+    /// checked whether it is sleeping since.
+    pub fn sleep_requested(&self) -> bool {
+        self.sleep_requested
+    }
+
+    /// Leave whether the hart sleeps after a sleep request symbolic,
+    /// recording an [Event::Sleeping] event for a scheduler that can
+    /// see every hart to decide. Otherwise the hart never sleeps.
+    pub fn symbolic_sleep(&mut self) {
+        self.symbolic_sleep = true
+    }
+
+    pub fn is_sleep_symbolic(&self) -> bool {
+        self.symbolic_sleep
+    }
+
+    /// The arrival point of the symbolic interrupt with the given
+    /// index, if an [Event::Interrupt] has been recorded for it
+    pub fn interrupt_arrival(&self, index: u32) -> Option<Sym> {
+        self.interrupt_arrivals.get(&index).copied()
+    }
+
+    /// The most recent writes in the trace to concrete addresses
+    /// overlapping `bytes` bytes at `address`, as the address,
+    /// length, and data of each write, in address order.
+    pub fn writes_overlapping(&self, address: u64, bytes: u32) -> impl Iterator<Item = (u64, u32, &Val<B>)> {
+        overlapping_writes(&self.latest_writes, address, bytes).map(|(start, (len, data))| (*start, *len, data))
+    }
+
+    /// The total time spent checking satisfiability on the current
+    /// path, see [Solver::check_sat]
+    pub fn solver_time(&self) -> Duration {
+        self.solver_time
+    }
+
     fn add_event_internal(&mut self, event: &Event<B>) {
-        if let Event::Smt(def, _, _) = event {
-            self.add_internal(def)
-        };
+        match event {
+            Event::Smt(def, _, _) => self.add_internal(def),
+            // Also restores the count when replaying a checkpoint
+            Event::Cycle(n) => self.cycles = *n as i128 + 1,
+            Event::SleepRequest => self.sleep_requested = true,
+            Event::Sleeping(_) => self.sleep_requested = false,
+            Event::Interrupt { index, arrival } => {
+                self.interrupt_arrivals.insert(*index, *arrival);
+            }
+            Event::WriteMem { address: Val::Bits(address), data, bytes, .. } => {
+                let address = address.lower_u64();
+                let overwritten: Vec<u64> =
+                    overlapping_writes(&self.latest_writes, address, *bytes).map(|(start, _)| *start).collect();
+                for start in overwritten {
+                    self.latest_writes.remove(&start);
+                }
+                self.latest_writes.insert(address, (*bytes, data.clone()));
+            }
+            _ => (),
+        }
     }
 
     pub fn add_event(&mut self, event: Event<B>) {
+        if let Some(position) = self.position {
+            if !event.is_smt() && self.last_provenance != Some(position) {
+                self.last_provenance = Some(position);
+                self.trace.head.push(Event::Provenance { function: position.0, pc: position.1 })
+            }
+        }
         self.add_event_internal(&event);
-        self.trace.head.push(event)
+        self.trace.head.push(event);
+        self.observe_last();
+        self.spill_if_full()
+    }
+
+    /// Add an [Event::Provenance] event before any non-SMT event
+    /// generated at a different position in the model than the
+    /// previous one. The position is updated by [Solver::set_position].
+    pub fn track_provenance(&mut self) {
+        self.track_provenance = true
+    }
+
+    /// Update the current position in the model, if provenance is
+    /// being tracked.
+    pub fn set_position(&mut self, function: Name, pc: usize) {
+        if self.track_provenance {
+            self.position = Some((function, pc as u32))
+        }
     }
 
     pub fn trace_call(&mut self, name: Name) {
@@ -1805,19 +2495,30 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
             }
         }
         self.trace.checkpoints = num;
+        self.trace.tail_events = Trace::events_in(&trace);
+        self.trace.spill = Trace::spilled_in(&trace);
         self.trace.tail = trace
     }
 
-    pub fn from_checkpoint(ctx: &'ctx Context, Checkpoint { num, next_var, trace }: Checkpoint<B>) -> Self {
+    pub fn from_checkpoint(
+        ctx: &'ctx Context,
+        Checkpoint { num, next_var, solver_time, trace }: Checkpoint<B>,
+    ) -> Self {
         let mut solver = Solver::new(ctx);
         solver.replay(num, trace);
         solver.next_var = next_var;
+        solver.solver_time = solver_time;
         solver
     }
 
     pub fn check_sat_with(&mut self, exp: &Exp<Sym>) -> SmtResult {
+        let start = Instant::now();
+        #[cfg(not(feature = "z3"))]
+        let result = self.check_script(Some(exp));
+        #[cfg(feature = "z3")]
         let ast = self.translate_exp(exp);
-        unsafe {
+        #[cfg(feature = "z3")]
+        let result = unsafe {
             let result = Z3_solver_check_assumptions(self.ctx.z3_ctx, self.z3_solver, 1, &ast.z3_ast);
             if result == Z3_L_TRUE {
                 Sat
@@ -1826,6 +2527,38 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
             } else {
                 Unknown
             }
+        };
+        self.finish_check_sat(Some(exp), result, start);
+        result
+    }
+
+    /// Enumerate the concrete values a variable can take under the
+    /// current assertions, by repeatedly solving with clauses that
+    /// block each value found so far. At most `bound` values are
+    /// returned. The blocking clauses are only passed as assumptions,
+    /// so neither the solver nor the trace are modified.
+    pub fn enumerate_values(&mut self, sym: Sym, bound: usize) -> Result<EnumeratedValues, ExecError> {
+        let mut values = Vec::new();
+        let mut blocking = Exp::Bool(true);
+        loop {
+            match self.check_sat_with(&blocking) {
+                Sat => (),
+                Unsat => return Ok(EnumeratedValues { values, complete: true }),
+                Unknown => return Err(ExecError::Z3Unknown),
+            }
+            if values.len() == bound {
+                return Ok(EnumeratedValues { values, complete: false });
+            }
+            let value = {
+                let mut model = Model::new(self);
+                model.get_var(sym)?
+            };
+            // If the model does not need to assign the variable a
+            // value it is unconstrained, so we cannot enumerate it
+            let Some(value) = value else { return Ok(EnumeratedValues { values, complete: false }) };
+            let block = Exp::Neq(Box::new(Exp::Var(sym)), Box::new(value.clone()));
+            blocking = Exp::And(Box::new(blocking), Box::new(block));
+            values.push(value)
         }
     }
 
@@ -1833,8 +2566,50 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         &self.trace
     }
 
+    /// An approximation of the memory used by the trace and
+    /// declarations of this solver, not including Z3's own
+    /// allocations (see [z3_allocated]).
+    #[cfg(feature = "z3")]
+    pub fn approximate_size(&self) -> usize {
+        self.trace.len() * mem::size_of::<Event<B>>()
+            + (self.decls.len() + self.func_decls.len()) * mem::size_of::<(Sym, Ast)>()
+    }
+
+    /// An approximation of the memory used by the trace and the
+    /// script kept for the backend.
+    #[cfg(not(feature = "z3"))]
+    pub fn approximate_size(&self) -> usize {
+        (self.trace.len() + self.script.len()) * mem::size_of::<Event<B>>()
+    }
+
+    /// Check the script kept for the backend, which is used instead
+    /// of a native Z3 solver. The whole script is sent for every
+    /// check, so the cost of checking a path grows quadratically with
+    /// the number of definitions on it.
+    ///
+    /// If the backend fails, the result is `Unknown` but the failure
+    /// is also kept, so it is reported as an error by
+    /// [Solver::is_possible] or [Solver::take_abort] rather than
+    /// being mistaken for an incomplete answer from the solver.
+    #[cfg(not(feature = "z3"))]
+    fn check_script(&mut self, assumption: Option<&Exp<Sym>>) -> SmtResult {
+        self.assumption = assumption.cloned();
+        let script = self.script.to_smtlib_with(assumption);
+        match backend::block_on(self.ctx.run(&script)).and_then(|output| backend::parse_result(&output)) {
+            Ok(result) => result,
+            Err(msg) => {
+                self.backend_failure.get_or_insert(msg);
+                Unknown
+            }
+        }
+    }
+
     pub fn check_sat(&mut self) -> SmtResult {
-        unsafe {
+        let start = Instant::now();
+        #[cfg(not(feature = "z3"))]
+        let result = self.check_script(None);
+        #[cfg(feature = "z3")]
+        let result = unsafe {
             let result = Z3_solver_check(self.ctx.z3_ctx, self.z3_solver);
             if result == Z3_L_TRUE {
                 Sat
@@ -1843,9 +2618,88 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
             } else {
                 Unknown
             }
+        };
+        self.finish_check_sat(None, result, start);
+        result
+    }
+
+    /// Set what happens when [Solver::is_possible] gets an unknown
+    /// result, and where to count unknown results.
+    pub fn set_unknown_policies(&mut self, policies: UnknownPolicies, stats: Option<Arc<UnknownStats>>) {
+        self.unknown_policies = policies;
+        self.unknown_stats = stats
+    }
+
+    /// Check the current assertions (and `assumption`, if any) using
+    /// Z3's default solver, rather than the QF_AUFBV tactic used for
+    /// every other check.
+    #[cfg(feature = "z3")]
+    fn check_sat_fallback(&mut self, assumption: Option<&Exp<Sym>>) -> SmtResult {
+        let start = Instant::now();
+        let assumption_ast = assumption.map(|exp| self.translate_exp(exp));
+        let result = unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let fallback = Z3_mk_solver(z3_ctx);
+            Z3_solver_inc_ref(z3_ctx, fallback);
+            let assertions = Z3_solver_get_assertions(z3_ctx, self.z3_solver);
+            Z3_ast_vector_inc_ref(z3_ctx, assertions);
+            for i in 0..Z3_ast_vector_size(z3_ctx, assertions) {
+                Z3_solver_assert(z3_ctx, fallback, Z3_ast_vector_get(z3_ctx, assertions, i))
+            }
+            Z3_ast_vector_dec_ref(z3_ctx, assertions);
+            let result = match &assumption_ast {
+                Some(ast) => Z3_solver_check_assumptions(z3_ctx, fallback, 1, &ast.z3_ast),
+                None => Z3_solver_check(z3_ctx, fallback),
+            };
+            Z3_solver_dec_ref(z3_ctx, fallback);
+            if result == Z3_L_TRUE {
+                Sat
+            } else if result == Z3_L_FALSE {
+                Unsat
+            } else {
+                Unknown
+            }
+        };
+        self.finish_check_sat(assumption, result, start);
+        result
+    }
+
+    /// A backend has no other solver to fall back to, and would be
+    /// given the same script again, so an unknown result stands.
+    #[cfg(not(feature = "z3"))]
+    fn check_sat_fallback(&mut self, _assumption: Option<&Exp<Sym>>) -> SmtResult {
+        Unknown
+    }
+
+    /// Returns true if the current assertions (and `assumption`, if
+    /// any) may be satisfiable. An unknown result is handled
+    /// according to the policy for `site` (see
+    /// [Solver::set_unknown_policies]).
+    pub fn is_possible(&mut self, site: QuerySite, assumption: Option<&Exp<Sym>>) -> Result<bool, ExecError> {
+        let result = match assumption {
+            Some(exp) => self.check_sat_with(exp),
+            None => self.check_sat(),
+        };
+        #[cfg(not(feature = "z3"))]
+        if let Some(msg) = self.backend_failure.take() {
+            return Err(ExecError::Z3Error(msg));
+        }
+        if result != Unknown {
+            return result.is_sat();
+        }
+
+        let decided = match self.unknown_policies.get(site) {
+            UnknownPolicy::Abort => Err(ExecError::Z3Unknown),
+            UnknownPolicy::Fork => Ok(true),
+            UnknownPolicy::Retry => self.check_sat_fallback(assumption).is_sat(),
+        };
+        if let Some(stats) = &self.unknown_stats {
+            stats.record(self.unknown_policies.get(site) == UnknownPolicy::Retry && decided.is_ok())
         }
+        decided
     }
 
+    #[cfg(feature = "z3")]
     pub fn dump_solver(&mut self, filename: &str) {
         let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
         unsafe {
@@ -1855,6 +2709,7 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    #[cfg(feature = "z3")]
     pub fn dump_solver_with(&mut self, filename: &str, exp: &Exp<Sym>) {
         let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
         unsafe {
@@ -1865,6 +2720,7 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    #[cfg(feature = "z3")]
     pub fn exp_to_str(&mut self, exp: &Exp<Sym>) -> String {
         let ast = self.translate_exp(exp);
         let cs;
@@ -1874,10 +2730,45 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
         cs.to_string_lossy().to_string()
     }
+
+    #[cfg(not(feature = "z3"))]
+    pub fn dump_solver(&mut self, filename: &str) {
+        let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
+        file.write_all(self.script.to_smtlib().as_bytes()).expect("Failed to write solver dump");
+    }
+
+    #[cfg(not(feature = "z3"))]
+    pub fn dump_solver_with(&mut self, filename: &str, exp: &Exp<Sym>) {
+        let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
+        file.write_all(self.script.to_smtlib().as_bytes()).expect("Failed to write solver dump");
+        writeln!(file, "{}", self.exp_to_str(exp)).expect("Failed to write exp");
+    }
+
+    #[cfg(not(feature = "z3"))]
+    pub fn exp_to_str(&mut self, exp: &Exp<Sym>) -> String {
+        let mut buf = Vec::new();
+        crate::simplify::write_exp(&mut buf, exp, &crate::simplify::WriteOpts::default()).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+/// The writes in `writes` (see [Solver::writes_overlapping]) which
+/// overlap `bytes` bytes at `address`. As the writes are disjoint,
+/// only the last one starting before `address` can overlap it.
+fn overlapping_writes<B>(
+    writes: &BTreeMap<u64, (u32, Val<B>)>,
+    address: u64,
+    bytes: u32,
+) -> impl Iterator<Item = (&u64, &(u32, Val<B>))> {
+    let end = address + bytes as u64;
+    let before = writes.range(..address).next_back().filter(|(start, (len, _))| **start + *len as u64 > address);
+    before.into_iter().chain(writes.range(address..end))
 }
 
 pub fn checkpoint<B: BV>(solver: &mut Solver<B>) -> Checkpoint<B> {
-    solver.trace.checkpoint(solver.next_var)
+    let mut point = solver.trace.checkpoint(solver.next_var);
+    point.solver_time = solver.solver_time;
+    point
 }
 
 /// This function just calls Z3_finalize_memory(). It's useful because
@@ -1888,7 +2779,27 @@ pub fn checkpoint<B: BV>(solver: &mut Solver<B>) -> Checkpoint<B> {
 ///
 /// Shoud only be called just before exiting.
 pub unsafe fn finalize_solver() {
-    Z3_finalize_memory()
+    #[cfg(feature = "z3")]
+    Z3_finalize_memory();
+}
+
+#[cfg(feature = "z3")]
+extern "C" {
+    fn Z3_get_estimated_alloc_size() -> u64;
+}
+
+/// An estimate of the memory currently allocated by Z3, across all
+/// contexts in the process.
+#[cfg(feature = "z3")]
+pub fn z3_allocated() -> u64 {
+    unsafe { Z3_get_estimated_alloc_size() }
+}
+
+/// Without native Z3 nothing is allocated by the solver in this
+/// process.
+#[cfg(not(feature = "z3"))]
+pub fn z3_allocated() -> u64 {
+    0
 }
 
 #[cfg(test)]
@@ -1988,6 +2899,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn enumerate_values() {
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(Ty::BitVec(4), SourceLoc::unknown());
+        solver.assert(Bvult(Box::new(Var(v)), Box::new(bv!("0011"))));
+
+        let enumerated = solver.enumerate_values(v, 8).unwrap();
+        assert!(enumerated.complete);
+        let mut values: Vec<u64> = enumerated
+            .values
+            .iter()
+            .map(|value| match value {
+                Bits64(bv) => bv.lower_u64(),
+                _ => panic!("Unexpected value {:?}", value),
+            })
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![0, 1, 2]);
+
+        let enumerated = solver.enumerate_values(v, 2).unwrap();
+        assert!(!enumerated.complete);
+        assert_eq!(enumerated.values.len(), 2);
+        assert!(solver.check_sat() == Sat)
+    }
+
+    #[test]
+    fn path_resources_survive_checkpoints() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(Ty::BitVec(4), SourceLoc::unknown());
+        solver.assert_eq(Var(v), bv!("0011"));
+        assert!(solver.check_sat() == Sat);
+        let point = checkpoint(&mut solver);
+        solver.add_event(Event::Cycle(0));
+        assert_eq!(solver.trace().len(), 3);
+
+        let time = solver.solver_time();
+        let mut restored = Solver::from_checkpoint(&ctx, point.clone());
+        assert_eq!(restored.trace().len(), 2);
+        assert_eq!(restored.solver_time(), time);
+        let _ = checkpoint(&mut restored);
+        restored.add_event(Event::Cycle(0));
+        assert_eq!(restored.trace().len(), 3);
+
+        let extended = Checkpoint::from_segments(point.next_var(), point.segments()).extend(vec![Event::Cycle(0)]);
+        assert_eq!(Solver::from_checkpoint(&ctx, extended).trace().len(), 3)
+    }
+
     #[test]
     fn smt_func() {
         let mut cfg = Config::new();
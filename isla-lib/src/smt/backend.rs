@@ -0,0 +1,524 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module provides an interface for solvers which are not linked
+//! into the process, such as Z3 compiled to WebAssembly or a solver
+//! running on a remote server. Queries are sent to a
+//! [SolverBackend] as complete SMTLIB scripts and the output is
+//! returned as a future, so backends which can only answer
+//! asynchronously (as is the case in a browser) can be used.
+//!
+//! An [SmtScript] accumulates definitions in the same way as a
+//! [Solver](super::Solver), but without creating any Z3 objects, and
+//! can be checked using any backend.
+//!
+//! When isla-lib is built without the `z3` feature, the
+//! [Solver](super::Solver) used for symbolic execution is
+//! implemented using a backend, so the crate does not depend on the
+//! native Z3 library and can be built for `wasm32`. The executor is
+//! synchronous, so the solver waits for each query using
+//! [block_on], which parks the thread. A future that is completed by
+//! the browser event loop (such as one wrapping a JavaScript promise)
+//! can therefore never complete, so the backend must answer without
+//! it, e.g. by running a solver compiled to WebAssembly synchronously
+//! in the same web worker. Only code which awaits
+//! [SmtScript::check_sat] itself can use truly asynchronous backends.
+//!
+//! Backends answer each script from scratch, so the solver sends its
+//! whole script for every check rather than using `push` and `pop`.
+//! The cost of the checks made along a path is therefore quadratic
+//! in the number of definitions on it, which is acceptable for the
+//! short paths of single instructions but not for long executions.
+//! If the backend fails, symbolic execution stops with an error.
+//!
+//! The backend for each `Context` is set using `Config::set_backend`,
+//! or otherwise is created by the function given to
+//! `set_default_backend`. If there is no such function, `z3` is run
+//! as an external process (which is not possible on `wasm32`).
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+#[cfg(feature = "z3")]
+use std::ffi::{CStr, CString};
+#[cfg(not(feature = "z3"))]
+use std::fmt;
+#[cfg(not(feature = "z3"))]
+use std::sync::RwLock;
+
+#[cfg(feature = "z3")]
+use z3_sys::*;
+
+use super::smtlib::{smt_bits_from_str, Def, Exp, Ty};
+#[cfg(not(feature = "z3"))]
+use super::Solver;
+#[cfg(feature = "z3")]
+use super::{Config, Context};
+use super::{DefAttrs, EnumId, EnumMember, Event, SmtResult, Sym};
+use crate::bitvector::BV;
+#[cfg(not(feature = "z3"))]
+use crate::error::ExecError;
+use crate::ir::Symtab;
+use crate::simplify::{self, WriteOpts};
+use crate::source_loc::SourceLoc;
+
+pub type SolverFuture<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + 'a>>;
+
+/// A solver which runs SMTLIB scripts
+pub trait SolverBackend {
+    /// Run a script, returning the solver's responses to its
+    /// commands
+    fn run<'a>(&'a self, script: &'a str) -> SolverFuture<'a>;
+}
+
+/// Parse the response of a solver to `(check-sat)`, which should be
+/// the first response to a script
+pub fn parse_result(output: &str) -> Result<SmtResult, String> {
+    match output.lines().map(str::trim).find(|line| !line.is_empty()) {
+        Some("sat") => Ok(SmtResult::Sat),
+        Some("unsat") => Ok(SmtResult::Unsat),
+        Some("unknown") => Ok(SmtResult::Unknown),
+        _ => Err(format!("Unexpected solver response: {}", output.trim())),
+    }
+}
+
+/// Parse the response of a solver to `(check-sat)` followed by
+/// `(get-value (exp))`, giving the value of `exp`. Only booleans,
+/// bitvectors, and enumeration members written using the default
+/// [WriteOpts] are supported.
+pub fn parse_value(output: &str) -> Result<Exp<Sym>, String> {
+    let unexpected = || format!("Unexpected solver response: {}", output.trim());
+    if parse_result(output)? != SmtResult::Sat {
+        return Err(unexpected());
+    }
+    let response: Vec<&str> = output.lines().map(str::trim).filter(|line| !line.is_empty()).skip(1).collect();
+    let value = response.join(" ");
+    let value = match value.strip_suffix("))").and_then(|value| value.rsplit(char::is_whitespace).next()) {
+        Some(value) => value,
+        None => return Err(unexpected()),
+    };
+    match value {
+        "true" => Ok(Exp::Bool(true)),
+        "false" => Ok(Exp::Bool(false)),
+        _ if value.starts_with('#') => smt_bits_from_str(value).ok_or_else(unexpected),
+        _ => {
+            let member = value.strip_prefix('e').and_then(|member| member.split_once('_'));
+            match member.map(|(size, member)| (size.parse::<usize>(), member.parse::<usize>())) {
+                Some((Ok(size), Ok(member))) => Ok(Exp::Enum(EnumMember { enum_id: EnumId::from_usize(size), member })),
+                _ => Err(unexpected()),
+            }
+        }
+    }
+}
+
+/// A sequence of SMT definitions, which can be checked using any
+/// [SolverBackend]
+pub struct SmtScript<B> {
+    events: Vec<Event<B>>,
+    tcx: HashMap<Sym, Ty>,
+    ftcx: HashMap<Sym, (Vec<Ty>, Ty)>,
+    enums: HashSet<usize>,
+}
+
+impl<B: BV> Default for SmtScript<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BV> SmtScript<B> {
+    pub fn new() -> Self {
+        SmtScript { events: Vec::new(), tcx: HashMap::new(), ftcx: HashMap::new(), enums: HashSet::new() }
+    }
+
+    /// Create a script from the SMT events of a trace, which should be
+    /// in the order they occurred
+    pub fn from_events(events: &[Event<B>]) -> Self {
+        let mut script = Self::new();
+        for event in events {
+            if let Event::Smt(def, _, _) = event {
+                script.add(def.clone())
+            }
+        }
+        script
+    }
+
+    pub fn add(&mut self, def: Def) {
+        match &def {
+            Def::DeclareConst(v, ty) => {
+                self.tcx.insert(*v, ty.clone());
+            }
+            Def::DeclareFun(v, arg_tys, result_ty) => {
+                self.ftcx.insert(*v, (arg_tys.clone(), result_ty.clone()));
+            }
+            Def::DefineConst(v, exp) => {
+                if let Some(ty) = exp.infer(&self.tcx, &self.ftcx) {
+                    self.tcx.insert(*v, ty);
+                }
+            }
+            Def::DefineEnum(size) => {
+                self.enums.insert(*size);
+            }
+            Def::Assert(_) | Def::NamedAssert(..) => (),
+        }
+        self.events.push(Event::Smt(def, DefAttrs::default(), SourceLoc::unknown()))
+    }
+
+    /// The number of definitions in the script
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn has_enum(&self, size: usize) -> bool {
+        self.enums.contains(&size)
+    }
+
+    pub fn is_declared(&self, v: Sym) -> bool {
+        self.tcx.contains_key(&v)
+    }
+
+    /// The length of a bitvector variable, or `None` if the variable
+    /// is not a bitvector
+    pub fn length(&self, v: Sym) -> Option<u32> {
+        match self.tcx.get(&v) {
+            Some(Ty::BitVec(sz)) => Some(*sz),
+            _ => None,
+        }
+    }
+
+    fn write_script(events: &[Event<B>]) -> String {
+        let opts = WriteOpts { smt_script: true, ..WriteOpts::default() };
+        let mut buf = Vec::new();
+        simplify::write_events_with_opts(&mut buf, events, &Symtab::new(), &opts).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    pub fn to_smtlib(&self) -> String {
+        Self::write_script(&self.events)
+    }
+
+    /// As [SmtScript::to_smtlib], but with an additional assertion
+    /// before the final `(check-sat)`
+    pub fn to_smtlib_with(&self, assumption: Option<&Exp<Sym>>) -> String {
+        match assumption {
+            None => self.to_smtlib(),
+            Some(exp) => {
+                let mut events = self.events.clone();
+                events.push(Event::Smt(Def::Assert(exp.clone()), DefAttrs::default(), SourceLoc::unknown()));
+                Self::write_script(&events)
+            }
+        }
+    }
+
+    pub async fn check_sat<S: SolverBackend + ?Sized>(&self, backend: &S) -> Result<SmtResult, String> {
+        let script = self.to_smtlib();
+        parse_result(&backend.run(&script).await?)
+    }
+}
+
+/// Uses the native Z3 library, with a fresh context for each query.
+/// Queries complete synchronously.
+#[cfg(feature = "z3")]
+pub struct Z3Backend;
+
+#[cfg(feature = "z3")]
+impl SolverBackend for Z3Backend {
+    fn run<'a>(&'a self, script: &'a str) -> SolverFuture<'a> {
+        let output = CString::new(script).map_err(|e| e.to_string()).and_then(|script| {
+            let ctx = Context::new(Config::new());
+            unsafe {
+                let output = Z3_eval_smtlib2_string(ctx.z3_ctx, script.as_ptr());
+                if output.is_null() {
+                    return Err(format!("{}", ctx.error()));
+                }
+                Ok(CStr::from_ptr(output).to_string_lossy().into_owned())
+            }
+        });
+        Box::pin(std::future::ready(output))
+    }
+}
+
+/// Runs a solver process for each query, giving it the script on
+/// standard input, e.g. `z3 -in`. Queries complete synchronously.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct CommandBackend {
+    program: String,
+    args: Vec<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CommandBackend {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        CommandBackend { program: program.to_string(), args: args.iter().map(|arg| arg.to_string()).collect() }
+    }
+
+    pub fn z3() -> Self {
+        Self::new("z3", &["-in"])
+    }
+
+    fn run_command(&self, script: &str) -> Result<String, String> {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not run {}: {}", self.program, e))?;
+        child.stdin.take().unwrap().write_all(script.as_bytes()).map_err(|e| e.to_string())?;
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SolverBackend for CommandBackend {
+    fn run<'a>(&'a self, script: &'a str) -> SolverFuture<'a> {
+        Box::pin(std::future::ready(self.run_command(script)))
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// Run a future to completion on the current thread, for using
+/// backends from synchronous code. The thread is parked while the
+/// future is pending, so it must be woken by another thread; a future
+/// waiting on the event loop of the current thread will never
+/// complete.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = TaskContext::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(not(feature = "z3"))]
+type BackendFactory = Box<dyn Fn() -> Box<dyn SolverBackend> + Send + Sync>;
+
+#[cfg(not(feature = "z3"))]
+lazy_static! {
+    static ref DEFAULT_BACKEND: RwLock<Option<BackendFactory>> = RwLock::new(None);
+}
+
+/// Set the function used to create a backend for each [Context]
+/// which is not given one by [Config::set_backend]. Contexts are
+/// created by each thread of the executor, so this is the usual way
+/// to choose a backend.
+#[cfg(not(feature = "z3"))]
+pub fn set_default_backend<F>(factory: F)
+where
+    F: Fn() -> Box<dyn SolverBackend> + Send + Sync + 'static,
+{
+    *DEFAULT_BACKEND.write().unwrap() = Some(Box::new(factory))
+}
+
+#[cfg(all(not(feature = "z3"), not(target_arch = "wasm32")))]
+fn fallback_backend() -> Box<dyn SolverBackend> {
+    Box::new(CommandBackend::z3())
+}
+
+#[cfg(all(not(feature = "z3"), target_arch = "wasm32"))]
+fn fallback_backend() -> Box<dyn SolverBackend> {
+    panic!("No solver backend has been set, see isla_lib::smt::backend::set_default_backend")
+}
+
+/// Without native Z3, the configuration of a [Context] is the
+/// backend it uses.
+#[cfg(not(feature = "z3"))]
+pub struct Config {
+    backend: Option<Box<dyn SolverBackend>>,
+}
+
+#[cfg(not(feature = "z3"))]
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(feature = "z3"))]
+impl Config {
+    pub fn new() -> Self {
+        Config { backend: None }
+    }
+
+    /// Z3 parameters only apply to the native library, so they are
+    /// ignored.
+    pub fn set_param_value(&mut self, _id: &str, _value: &str) {}
+
+    pub fn set_backend(&mut self, backend: Box<dyn SolverBackend>) {
+        self.backend = Some(backend)
+    }
+}
+
+#[cfg(not(feature = "z3"))]
+pub fn global_set_param_value(_id: &str, _value: &str) {}
+
+#[cfg(not(feature = "z3"))]
+pub struct Context {
+    backend: Box<dyn SolverBackend>,
+}
+
+#[cfg(not(feature = "z3"))]
+impl Context {
+    pub fn new(cfg: Config) -> Self {
+        let backend = match cfg.backend {
+            Some(backend) => backend,
+            None => match &*DEFAULT_BACKEND.read().unwrap() {
+                Some(factory) => factory(),
+                None => fallback_backend(),
+            },
+        };
+        Context { backend }
+    }
+
+    pub(super) fn run<'a>(&'a self, script: &'a str) -> SolverFuture<'a> {
+        self.backend.run(script)
+    }
+}
+
+/// Interface for getting values from a model of the solver's
+/// assertions after a satisfiable check. Backends answer each script
+/// from scratch, so every value is found by running the script from
+/// the last check again and asking for the value. This relies on the
+/// backend finding the same model for the same script, as Z3 does.
+#[cfg(not(feature = "z3"))]
+pub struct Model<'ctx, B> {
+    solver: &'ctx Solver<'ctx, B>,
+}
+
+#[cfg(not(feature = "z3"))]
+impl<'ctx, B: BV> fmt::Debug for Model<'ctx, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut script = self.solver.script.to_smtlib_with(self.solver.assumption.as_ref());
+        script.push_str("(get-model)\n");
+        match block_on(self.solver.ctx.run(&script)) {
+            Ok(output) => write!(f, "{}", output),
+            Err(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+#[cfg(not(feature = "z3"))]
+impl<'ctx, B: BV> Model<'ctx, B> {
+    pub fn new(solver: &'ctx Solver<'ctx, B>) -> Self {
+        Model { solver }
+    }
+
+    pub fn get_var(&mut self, var: Sym) -> Result<Option<Exp<Sym>>, ExecError> {
+        if !self.solver.script.is_declared(var) {
+            return Err(ExecError::Type(format!("Unbound variable {:?}", &var), SourceLoc::unknown()));
+        }
+        self.get_exp(&Exp::Var(var))
+    }
+
+    pub fn get_exp(&mut self, exp: &Exp<Sym>) -> Result<Option<Exp<Sym>>, ExecError> {
+        let mut buf = Vec::new();
+        simplify::write_exp(&mut buf, exp, &WriteOpts::default()).unwrap();
+        let mut script = self.solver.script.to_smtlib_with(self.solver.assumption.as_ref());
+        script.push_str(&format!("(get-value ({}))\n", String::from_utf8_lossy(&buf)));
+        let output = block_on(self.solver.ctx.run(&script)).map_err(ExecError::Z3Error)?;
+        parse_value(&output).map(Some).map_err(ExecError::Z3Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+
+    #[test]
+    fn responses() {
+        assert_eq!(parse_result("sat\n"), Ok(SmtResult::Sat));
+        assert_eq!(parse_result("unsat"), Ok(SmtResult::Unsat));
+        assert!(parse_result("(error \"line 1\")").is_err())
+    }
+
+    #[test]
+    fn values() {
+        assert!(matches!(parse_value("sat\n((v3 #x5))\n"), Ok(Exp::Bits64(bv)) if bv == B64::new(5, 4)));
+        assert!(matches!(parse_value("sat\n((v3 true))\n"), Ok(Exp::Bool(true))));
+        assert!(matches!(
+            parse_value("sat\n((v3 e2_1))\n"),
+            Ok(Exp::Enum(member)) if member == EnumMember { enum_id: EnumId::from_usize(2), member: 1 }
+        ));
+        assert!(matches!(parse_value("sat\n(((ite v1 v2 v3) #b1))"), Ok(Exp::Bits64(bv)) if bv == B64::new(1, 1)));
+        assert!(parse_value("unsat\n(error \"model is not available\")").is_err())
+    }
+
+    #[test]
+    fn declared() {
+        let x = Sym::from_u32(0);
+        let y = Sym::from_u32(1);
+        let mut script: SmtScript<B64> = SmtScript::new();
+        script.add(Def::DeclareConst(x, Ty::BitVec(4)));
+        script.add(Def::DefineConst(y, Exp::Eq(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(1, 4))))));
+        assert_eq!(script.length(x), Some(4));
+        assert_eq!(script.length(y), None);
+        assert!(script.is_declared(y))
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn z3_backend() {
+        let x = Sym::from_u32(0);
+        let mut script: SmtScript<B64> = SmtScript::new();
+        script.add(Def::DeclareConst(x, Ty::BitVec(4)));
+        script.add(Def::Assert(Exp::Bvult(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(1, 4))))));
+        assert_eq!(block_on(script.check_sat(&Z3Backend)), Ok(SmtResult::Sat));
+
+        let mut script_value = script.to_smtlib();
+        script_value.push_str("(get-value (v0))\n");
+        let output = block_on(Z3Backend.run(&script_value)).unwrap();
+        assert!(matches!(parse_value(&output), Ok(Exp::Bits64(bv)) if bv == B64::new(0, 4)));
+
+        script.add(Def::Assert(Exp::Neq(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(0, 4))))));
+        assert_eq!(block_on(script.check_sat(&Z3Backend)), Ok(SmtResult::Unsat))
+    }
+}
@@ -0,0 +1,129 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module lets client code observe the events of each path as
+//! they are generated, rather than only seeing the trace once the
+//! path has finished. An [EventObserver] attached to a solver (see
+//! [super::Solver::set_observer]) is called for every event added to the
+//! trace, and can ask for the path to be aborted, e.g. to stop a path
+//! as soon as it performs a second memory write. Observers are
+//! shared between all the paths in an execution, so they are given
+//! the trace of the path so far rather than keeping per-path state,
+//! which would have to be split whenever the path forks.
+
+use super::{Event, Trace};
+use crate::bitvector::BV;
+
+/// What should happen to a path after an observer has seen an event
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Observation {
+    Continue,
+    /// Stop the path, failing it with the given reason
+    Abort(String),
+}
+
+pub trait EventObserver<B>: Send + Sync {
+    /// Called with each event as it is added to the trace of a path
+    /// belonging to the task with id `task_id`. `trace` is the trace
+    /// of the path so far, ending with `event`.
+    fn observe(&self, task_id: usize, event: &Event<B>, trace: &Trace<B>) -> Observation;
+}
+
+impl<B, F> EventObserver<B> for F
+where
+    F: Send + Sync + Fn(usize, &Event<B>, &Trace<B>) -> Observation,
+{
+    fn observe(&self, task_id: usize, event: &Event<B>, trace: &Trace<B>) -> Observation {
+        self(task_id, event, trace)
+    }
+}
+
+/// An observer which aborts any path that performs more than a given
+/// number of memory writes
+pub struct WriteLimit {
+    limit: usize,
+}
+
+impl WriteLimit {
+    pub fn new(limit: usize) -> Self {
+        WriteLimit { limit }
+    }
+}
+
+impl<B: BV> EventObserver<B> for WriteLimit {
+    fn observe(&self, _: usize, event: &Event<B>, trace: &Trace<B>) -> Observation {
+        if !event.is_memory_write() {
+            return Observation::Continue;
+        }
+        let writes = trace.to_vec().iter().filter(|event| event.is_memory_write()).count();
+        if writes > self.limit {
+            Observation::Abort(format!("path performed more than {} memory writes", self.limit))
+        } else {
+            Observation::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Val;
+
+    fn write_event(solver: &mut Solver<B64>) -> Event<B64> {
+        Event::WriteMem {
+            value: solver.fresh(),
+            write_kind: Val::Unit,
+            address: Val::Bits(B64::new(0x1000, 64)),
+            data: Val::Bits(B64::new(0, 8)),
+            bytes: 1,
+            tag_value: None,
+            opts: WriteOpts::default(),
+            region: "test",
+        }
+    }
+
+    #[test]
+    fn write_limit() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.set_observer(Arc::new(WriteLimit::new(1)), 0);
+
+        let event = write_event(&mut solver);
+        solver.add_event(event);
+        solver.add_event(Event::Cycle(0));
+        assert_eq!(solver.take_abort(), None);
+
+        let event = write_event(&mut solver);
+        solver.add_event(event);
+        assert!(solver.take_abort().is_some());
+        assert_eq!(solver.take_abort(), None)
+    }
+}
@@ -0,0 +1,212 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module logs every definition and satisfiability check made
+//! by a [Solver], along with the result and how long each check
+//! took, so that a slow or failing query can be reproduced without
+//! re-running symbolic execution. Each solver writes its own
+//! session to the log (solvers are created per task, so a log from a
+//! multi-threaded run contains many interleaved sessions), and a
+//! session can be replayed against a fresh solver with
+//! [replay_session].
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::smtlib::{Def, Exp};
+use super::{SmtResult, Solver, Sym};
+use crate::binary_trace::BinaryTraceError;
+use crate::bitvector::BV;
+
+const QUERY_LOG_MAGIC: &[u8; 8] = b"ISLAQLG\x01";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum QueryLogEntry {
+    /// A definition added to the solver
+    Def(Def),
+    /// A call to [Solver::check_sat], or [Solver::check_sat_with] if
+    /// there is an assumption.
+    CheckSat { assumption: Option<Exp<Sym>>, result: SmtResult, micros: u64 },
+}
+
+/// A log file shared between all the solvers in a run.
+pub struct QueryLog {
+    next_session: AtomicU64,
+    out: Mutex<BufWriter<File>>,
+}
+
+impl QueryLog {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(QUERY_LOG_MAGIC)?;
+        Ok(QueryLog { next_session: AtomicU64::new(0), out: Mutex::new(out) })
+    }
+
+    pub(crate) fn start_session(&self) -> u64 {
+        self.next_session.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub(crate) fn write(&self, session: u64, entry: &QueryLogEntry) {
+        let mut out = self.out.lock().unwrap();
+        bincode::serialize_into(&mut *out, &(session, entry)).expect("Failed to write SMT query log")
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+}
+
+impl Drop for QueryLog {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Read a query log, returning each session's entries in the order
+/// the sessions were started.
+pub fn read_sessions<R: Read>(input: R) -> Result<Vec<Vec<QueryLogEntry>>, BinaryTraceError> {
+    let mut input = BufReader::new(input);
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic).map_err(|_| BinaryTraceError::BadMagic)?;
+    if &magic != QUERY_LOG_MAGIC {
+        return Err(BinaryTraceError::BadMagic);
+    }
+
+    let mut sessions: Vec<Vec<QueryLogEntry>> = Vec::new();
+    loop {
+        // A log from a run that was interrupted may end part way
+        // through a record, so treat that like the end of the file.
+        let (session, entry): (u64, QueryLogEntry) = match bincode::deserialize_from(&mut input) {
+            Ok(record) => record,
+            Err(err) => {
+                if let bincode::ErrorKind::Io(io_err) = &*err {
+                    if io_err.kind() == ErrorKind::UnexpectedEof {
+                        break;
+                    }
+                }
+                return Err(err.into());
+            }
+        };
+        let session = session as usize;
+        if sessions.len() <= session {
+            sessions.resize_with(session + 1, Vec::new)
+        }
+        sessions[session].push(entry)
+    }
+    Ok(sessions)
+}
+
+/// The outcome of replaying a single satisfiability check.
+#[derive(Clone, Debug)]
+pub struct ReplayedQuery {
+    /// The position of the check within its session
+    pub index: usize,
+    pub logged_result: SmtResult,
+    pub logged_time: Duration,
+    pub result: SmtResult,
+    pub time: Duration,
+}
+
+impl ReplayedQuery {
+    /// A query diverges if the replayed solver gives a different
+    /// answer. Note that `Unknown` results are expected to vary with
+    /// timeouts and solver versions.
+    pub fn diverges(&self) -> bool {
+        self.result != self.logged_result
+    }
+}
+
+/// Replay a session against a fresh solver, which should not have
+/// query logging enabled.
+pub fn replay_session<B: BV>(solver: &mut Solver<B>, entries: &[QueryLogEntry]) -> Vec<ReplayedQuery> {
+    let mut queries = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match entry {
+            QueryLogEntry::Def(def) => solver.add(def.clone()),
+            QueryLogEntry::CheckSat { assumption, result: logged_result, micros } => {
+                let now = Instant::now();
+                let result = match assumption {
+                    Some(exp) => solver.check_sat_with(exp),
+                    None => solver.check_sat(),
+                };
+                queries.push(ReplayedQuery {
+                    index,
+                    logged_result: *logged_result,
+                    logged_time: Duration::from_micros(*micros),
+                    result,
+                    time: now.elapsed(),
+                })
+            }
+        }
+    }
+    queries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::Ty;
+    use crate::smt::{Config, Context};
+    use std::sync::Arc;
+
+    #[test]
+    fn log_and_replay() {
+        let path = std::env::temp_dir().join(format!("isla_query_log_test_{}", std::process::id()));
+        let log = Arc::new(QueryLog::create(&path).unwrap());
+        {
+            let ctx = Context::new(Config::new());
+            let mut solver = Solver::<B64>::new(&ctx);
+            solver.log_queries(log.clone());
+            let x = solver.declare_const(Ty::Bool, crate::source_loc::SourceLoc::unknown());
+            assert_eq!(solver.check_sat_with(&Exp::Not(Box::new(Exp::Var(x)))), SmtResult::Sat);
+            solver.assert(Exp::Var(x));
+            assert_eq!(solver.check_sat_with(&Exp::Not(Box::new(Exp::Var(x)))), SmtResult::Unsat);
+        }
+        drop(log);
+
+        let sessions = read_sessions(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].len(), 4);
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let queries = replay_session(&mut solver, &sessions[0]);
+        assert_eq!(queries.len(), 2);
+        assert!(queries.iter().all(|query| !query.diverges()));
+        assert_eq!(queries[1].result, SmtResult::Unsat)
+    }
+}
@@ -32,6 +32,8 @@
 //! interact with the SMT solver, which mostly corresponds to the
 //! theory of quantifier-free bitvectors and arrays.
 
+use serde::{Deserialize, Serialize};
+
 use std::collections::HashMap;
 use std::ops::{Add, BitAnd, BitOr, BitXor, Shl, Shr, Sub};
 
@@ -39,7 +41,7 @@ use super::{EnumId, EnumMember, Sym};
 use crate::bitvector::b64::B64;
 use crate::bitvector::{ParsedBits, BV};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Ty {
     Bool,
     BitVec(u32),
@@ -49,7 +51,7 @@ pub enum Ty {
     RoundingMode,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPRoundingMode {
     RoundNearestTiesToEven,
     RoundNearestTiesToAway,
@@ -58,7 +60,7 @@ pub enum FPRoundingMode {
     RoundTowardZero,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPConstant {
     NaN,
     /// If negative is true, then -∞ rather than +∞, and similarly for the Zero constructor
@@ -70,7 +72,7 @@ pub enum FPConstant {
     },
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPUnary {
     Abs,
     Neg,
@@ -96,7 +98,7 @@ impl FPUnary {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPRoundingUnary {
     Sqrt,
     RoundToIntegral,
@@ -123,7 +125,7 @@ impl FPRoundingUnary {
 /// Note that SMTLIB is slightly inconsistent w.r.t. whether it uses
 /// le or leq as a suffix for less than or equal to between bitvectors
 /// and floating point. We follow SMTLIB exactly here.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPBinary {
     Rem,
     Min,
@@ -143,7 +145,7 @@ impl FPBinary {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum FPRoundingBinary {
     Add,
     Sub,
@@ -151,7 +153,7 @@ pub enum FPRoundingBinary {
     Div,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Exp<V> {
     Var(V),
     Bits(Vec<bool>),
@@ -206,6 +208,17 @@ pub enum Exp<V> {
     FPBinary(FPBinary, Box<Exp<V>>, Box<Exp<V>>),
     FPRoundingBinary(FPRoundingBinary, Box<Exp<V>>, Box<Exp<V>>, Box<Exp<V>>),
     FPfma(Box<Exp<V>>, Box<Exp<V>>, Box<Exp<V>>, Box<Exp<V>>),
+    /// Quantifiers bind a list of sorted variables. These are never
+    /// generated by the executor, but are needed for memory model
+    /// axioms and some specification idioms. Note that the solver
+    /// created by [crate::smt::Solver::new] uses a quantifier-free
+    /// tactic, so will usually return unknown for quantified queries.
+    Forall(Vec<(V, Ty)>, Box<Exp<V>>),
+    Exists(Vec<(V, Ty)>, Box<Exp<V>>),
+    /// A parallel let binding, as in SMT-LIB, so the bindings cannot
+    /// refer to each other. These are introduced by
+    /// [crate::simplify::introduce_lets] to share repeated subterms.
+    Let(Vec<(V, Exp<V>)>, Box<Exp<V>>),
 }
 
 #[allow(clippy::needless_range_loop)]
@@ -412,6 +425,19 @@ impl<V> Exp<V> {
                     None => Ite(guard, true_exp, false_exp),
                 }
             }
+            Forall(vars, mut body) => {
+                *body = body.eval();
+                Forall(vars, body)
+            }
+            Exists(vars, mut body) => {
+                *body = body.eval();
+                Exists(vars, body)
+            }
+            Let(bindings, mut body) => {
+                let bindings = bindings.into_iter().map(|(v, exp)| (v, exp.eval())).collect();
+                *body = body.eval();
+                Let(bindings, body)
+            }
             _ => self,
         }
     }
@@ -430,7 +456,9 @@ impl<V> Exp<V> {
             | Extract(_, _, exp)
             | ZeroExtend(_, exp)
             | SignExtend(_, exp)
-            | FPUnary(_, exp) => exp.modify(f),
+            | FPUnary(_, exp)
+            | Forall(_, exp)
+            | Exists(_, exp) => exp.modify(f),
             Eq(lhs, rhs)
             | Neq(lhs, rhs)
             | And(lhs, rhs)
@@ -504,6 +532,12 @@ impl<V> Exp<V> {
                 y.modify(f);
                 z.modify(f);
             }
+            Let(bindings, body) => {
+                for (_, exp) in bindings {
+                    exp.modify(f)
+                }
+                body.modify(f)
+            }
         };
         f(self)
     }
@@ -523,7 +557,9 @@ impl<V> Exp<V> {
             | Extract(_, _, exp)
             | ZeroExtend(_, exp)
             | SignExtend(_, exp)
-            | FPUnary(_, exp) => exp.modify(f),
+            | FPUnary(_, exp)
+            | Forall(_, exp)
+            | Exists(_, exp) => exp.modify(f),
             Eq(lhs, rhs)
             | Neq(lhs, rhs)
             | And(lhs, rhs)
@@ -597,6 +633,12 @@ impl<V> Exp<V> {
                 y.modify(f);
                 z.modify(f);
             }
+            Let(bindings, body) => {
+                for (_, exp) in bindings {
+                    exp.modify(f)
+                }
+                body.modify(f)
+            }
         }
     }
 
@@ -630,9 +672,13 @@ impl<V> Exp<V> {
 }
 
 impl<'a, V: 'a> Exp<V> {
+    /// Map each variable in the expression to a new expression. The
+    /// variables bound by a quantifier or let must be mapped to
+    /// variables.
     pub fn map_var<F, Err, V2>(&'a self, f: &mut F) -> Result<Exp<V2>, Err>
     where
         F: FnMut(&'a V) -> Result<Exp<V2>, Err>,
+        Err: From<String>,
     {
         use Exp::*;
         match self {
@@ -705,8 +751,34 @@ impl<'a, V: 'a> Exp<V> {
                 Box::new(y.map_var(f)?),
                 Box::new(z.map_var(f)?),
             )),
+            Forall(vars, body) => Ok(Forall(Self::map_bound_vars(vars, f)?, Box::new(body.map_var(f)?))),
+            Exists(vars, body) => Ok(Exists(Self::map_bound_vars(vars, f)?, Box::new(body.map_var(f)?))),
+            Let(bindings, body) => {
+                let mut new_bindings = Vec::with_capacity(bindings.len());
+                for (v, exp) in bindings {
+                    let exp = exp.map_var(f)?;
+                    match f(v)? {
+                        Var(v2) => new_bindings.push((v2, exp)),
+                        _ => return Err(Err::from("Let-bound variable was not mapped to a variable".to_string())),
+                    }
+                }
+                Ok(Let(new_bindings, Box::new(body.map_var(f)?)))
+            }
         }
     }
+
+    fn map_bound_vars<F, Err, V2>(vars: &'a [(V, Ty)], f: &mut F) -> Result<Vec<(V2, Ty)>, Err>
+    where
+        F: FnMut(&'a V) -> Result<Exp<V2>, Err>,
+        Err: From<String>,
+    {
+        vars.iter()
+            .map(|(v, ty)| match f(v)? {
+                Exp::Var(v2) => Ok((v2, ty.clone())),
+                _ => Err(Err::from("Quantified variable was not mapped to a variable".to_string())),
+            })
+            .collect()
+    }
 }
 
 impl Exp<Sym> {
@@ -729,7 +801,9 @@ impl Exp<Sym> {
             | Extract(_, _, exp)
             | ZeroExtend(_, exp)
             | SignExtend(_, exp)
-            | FPUnary(_, exp) => exp.subst_once_in_place(substs),
+            | FPUnary(_, exp)
+            | Forall(_, exp)
+            | Exists(_, exp) => exp.subst_once_in_place(substs),
             Eq(lhs, rhs)
             | Neq(lhs, rhs)
             | And(lhs, rhs)
@@ -803,6 +877,12 @@ impl Exp<Sym> {
                 y.subst_once_in_place(substs);
                 z.subst_once_in_place(substs);
             }
+            Let(bindings, body) => {
+                for (_, exp) in bindings {
+                    exp.subst_once_in_place(substs)
+                }
+                body.subst_once_in_place(substs)
+            }
         }
     }
 
@@ -828,7 +908,9 @@ impl Exp<Sym> {
             | Bvsge(_, _)
             | Bvugt(_, _)
             | Bvsgt(_, _)
-            | Distinct(_) => Some(Ty::Bool),
+            | Distinct(_)
+            | Forall(_, _)
+            | Exists(_, _) => Some(Ty::Bool),
             Bvnot(exp) | Bvneg(exp) => exp.infer(tcx, ftcx),
             Extract(i, j, _) => Some(Ty::BitVec((i - j) + 1)),
             ZeroExtend(ext, exp) | SignExtend(ext, exp) => match exp.infer(tcx, ftcx) {
@@ -888,15 +970,266 @@ impl Exp<Sym> {
             }
             FPRoundingBinary(_, _, lhs, _) => lhs.infer(tcx, ftcx),
             FPfma(_, x, _, _) => x.infer(tcx, ftcx),
+            Let(bindings, body) => {
+                let mut body_tcx = tcx.clone();
+                for (v, exp) in bindings {
+                    body_tcx.insert(*v, exp.infer(tcx, ftcx)?);
+                }
+                body.infer(&body_tcx, ftcx)
+            }
+        }
+    }
+
+    /// Check that an SMTLIB expression is well-typed, returning its
+    /// type. Unlike [Exp::infer] this checks every subexpression, and
+    /// on failure describes the innermost operation that was
+    /// ill-typed.
+    pub fn typecheck(&self, tcx: &HashMap<Sym, Ty>, ftcx: &HashMap<Sym, (Vec<Ty>, Ty)>) -> Result<Ty, String> {
+        use Exp::*;
+
+        fn expect_bool(op: &str, ty: Ty) -> Result<(), String> {
+            match ty {
+                Ty::Bool => Ok(()),
+                ty => Err(format!("{}: expected Bool, found {:?}", op, ty)),
+            }
+        }
+
+        fn expect_bitvec(op: &str, ty: Ty) -> Result<u32, String> {
+            match ty {
+                Ty::BitVec(sz) => Ok(sz),
+                ty => Err(format!("{}: expected a bitvector, found {:?}", op, ty)),
+            }
+        }
+
+        fn expect_float(op: &str, ty: Ty) -> Result<Ty, String> {
+            match ty {
+                Ty::Float(..) => Ok(ty),
+                ty => Err(format!("{}: expected a floating point value, found {:?}", op, ty)),
+            }
+        }
+
+        fn expect_same(op: &str, lhs: Ty, rhs: Ty) -> Result<Ty, String> {
+            if lhs == rhs {
+                Ok(lhs)
+            } else {
+                Err(format!("{}: operand types {:?} and {:?} differ", op, lhs, rhs))
+            }
+        }
+
+        let bitvec_binop = |op: &str, lhs: &Exp<Sym>, rhs: &Exp<Sym>| -> Result<Ty, String> {
+            let lsz = expect_bitvec(op, lhs.typecheck(tcx, ftcx)?)?;
+            let rsz = expect_bitvec(op, rhs.typecheck(tcx, ftcx)?)?;
+            expect_same(op, Ty::BitVec(lsz), Ty::BitVec(rsz))
+        };
+
+        let rounding_mode = |op: &str, rm: &Exp<Sym>| -> Result<(), String> {
+            match rm.typecheck(tcx, ftcx)? {
+                Ty::RoundingMode => Ok(()),
+                ty => Err(format!("{}: expected RoundingMode, found {:?}", op, ty)),
+            }
+        };
+
+        match self {
+            Var(v) => tcx.get(v).cloned().ok_or_else(|| format!("undeclared variable v{}", v)),
+            Bits(bv) => Ok(Ty::BitVec(bv.len() as u32)),
+            Bits64(bv) => Ok(Ty::BitVec(bv.len())),
+            Enum(e) => Ok(Ty::Enum(e.enum_id)),
+            Bool(_) => Ok(Ty::Bool),
+            Not(exp) => {
+                expect_bool("not", exp.typecheck(tcx, ftcx)?)?;
+                Ok(Ty::Bool)
+            }
+            And(lhs, rhs) | Or(lhs, rhs) => {
+                expect_bool("and/or", lhs.typecheck(tcx, ftcx)?)?;
+                expect_bool("and/or", rhs.typecheck(tcx, ftcx)?)?;
+                Ok(Ty::Bool)
+            }
+            Eq(lhs, rhs) | Neq(lhs, rhs) => {
+                expect_same("=", lhs.typecheck(tcx, ftcx)?, rhs.typecheck(tcx, ftcx)?)?;
+                Ok(Ty::Bool)
+            }
+            Distinct(exps) => {
+                let mut tys = exps.iter().map(|exp| exp.typecheck(tcx, ftcx));
+                if let Some(first) = tys.next() {
+                    let first = first?;
+                    for ty in tys {
+                        expect_same("distinct", first.clone(), ty?)?;
+                    }
+                }
+                Ok(Ty::Bool)
+            }
+            Bvnot(exp) | Bvneg(exp) => Ok(Ty::BitVec(expect_bitvec("bvnot/bvneg", exp.typecheck(tcx, ftcx)?)?)),
+            Bvand(lhs, rhs)
+            | Bvor(lhs, rhs)
+            | Bvxor(lhs, rhs)
+            | Bvnand(lhs, rhs)
+            | Bvnor(lhs, rhs)
+            | Bvxnor(lhs, rhs)
+            | Bvadd(lhs, rhs)
+            | Bvsub(lhs, rhs)
+            | Bvmul(lhs, rhs)
+            | Bvudiv(lhs, rhs)
+            | Bvsdiv(lhs, rhs)
+            | Bvurem(lhs, rhs)
+            | Bvsrem(lhs, rhs)
+            | Bvsmod(lhs, rhs)
+            | Bvshl(lhs, rhs)
+            | Bvlshr(lhs, rhs)
+            | Bvashr(lhs, rhs) => bitvec_binop("bitvector operation", lhs, rhs),
+            Bvult(lhs, rhs)
+            | Bvslt(lhs, rhs)
+            | Bvule(lhs, rhs)
+            | Bvsle(lhs, rhs)
+            | Bvuge(lhs, rhs)
+            | Bvsge(lhs, rhs)
+            | Bvugt(lhs, rhs)
+            | Bvsgt(lhs, rhs) => {
+                bitvec_binop("bitvector comparison", lhs, rhs)?;
+                Ok(Ty::Bool)
+            }
+            Extract(i, j, exp) => {
+                let sz = expect_bitvec("extract", exp.typecheck(tcx, ftcx)?)?;
+                if j > i || *i >= sz {
+                    Err(format!("extract: indices {} {} out of range for a bitvector of length {}", i, j, sz))
+                } else {
+                    Ok(Ty::BitVec((i - j) + 1))
+                }
+            }
+            ZeroExtend(ext, exp) | SignExtend(ext, exp) => {
+                Ok(Ty::BitVec(expect_bitvec("extend", exp.typecheck(tcx, ftcx)?)? + ext))
+            }
+            Concat(lhs, rhs) => {
+                let lsz = expect_bitvec("concat", lhs.typecheck(tcx, ftcx)?)?;
+                let rsz = expect_bitvec("concat", rhs.typecheck(tcx, ftcx)?)?;
+                Ok(Ty::BitVec(lsz + rsz))
+            }
+            Ite(cond, then_exp, else_exp) => {
+                expect_bool("ite", cond.typecheck(tcx, ftcx)?)?;
+                expect_same("ite", then_exp.typecheck(tcx, ftcx)?, else_exp.typecheck(tcx, ftcx)?)
+            }
+            App(f, args) => {
+                let (arg_tys, ret_ty) = ftcx.get(f).ok_or_else(|| format!("undeclared function f{}", f))?;
+                if arg_tys.len() != args.len() {
+                    return Err(format!("f{}: expected {} arguments, found {}", f, arg_tys.len(), args.len()));
+                }
+                for (arg, ty) in args.iter().zip(arg_tys) {
+                    expect_same(&format!("f{}", f), ty.clone(), arg.typecheck(tcx, ftcx)?)?;
+                }
+                Ok(ret_ty.clone())
+            }
+            Select(array, index) => match array.typecheck(tcx, ftcx)? {
+                Ty::Array(dom_ty, codom_ty) => {
+                    expect_same("select", *dom_ty, index.typecheck(tcx, ftcx)?)?;
+                    Ok(*codom_ty)
+                }
+                ty => Err(format!("select: expected an array, found {:?}", ty)),
+            },
+            Store(array, index, value) => match array.typecheck(tcx, ftcx)? {
+                Ty::Array(dom_ty, codom_ty) => {
+                    expect_same("store", *dom_ty.clone(), index.typecheck(tcx, ftcx)?)?;
+                    expect_same("store", *codom_ty.clone(), value.typecheck(tcx, ftcx)?)?;
+                    Ok(Ty::Array(dom_ty, codom_ty))
+                }
+                ty => Err(format!("store: expected an array, found {:?}", ty)),
+            },
+            FPConstant(_, ebits, sbits) => Ok(Ty::Float(*ebits, *sbits)),
+            FPRoundingMode(_) => Ok(Ty::RoundingMode),
+            FPUnary(op, exp) => {
+                let ty = exp.typecheck(tcx, ftcx)?;
+                match op {
+                    self::FPUnary::FromIEEE(..) => {
+                        expect_bitvec("fp.from_ieee", ty)?;
+                    }
+                    _ => {
+                        expect_float("floating point operation", ty.clone())?;
+                    }
+                }
+                Ok(op.result_ty().unwrap_or(ty))
+            }
+            FPRoundingUnary(op, rm, exp) => {
+                rounding_mode("floating point operation", rm)?;
+                let ty = exp.typecheck(tcx, ftcx)?;
+                match op {
+                    self::FPRoundingUnary::FromSigned(..) | self::FPRoundingUnary::FromUnsigned(..) => {
+                        expect_bitvec("to_fp", ty.clone())?;
+                    }
+                    _ => {
+                        expect_float("floating point operation", ty.clone())?;
+                    }
+                }
+                Ok(op.result_ty().unwrap_or(ty))
+            }
+            FPBinary(op, lhs, rhs) => {
+                let lhs_ty = expect_float("floating point operation", lhs.typecheck(tcx, ftcx)?)?;
+                let ty = expect_same("floating point operation", lhs_ty, rhs.typecheck(tcx, ftcx)?)?;
+                if op.is_predicate() {
+                    Ok(Ty::Bool)
+                } else {
+                    Ok(ty)
+                }
+            }
+            FPRoundingBinary(_, rm, lhs, rhs) => {
+                rounding_mode("floating point operation", rm)?;
+                let lhs_ty = expect_float("floating point operation", lhs.typecheck(tcx, ftcx)?)?;
+                expect_same("floating point operation", lhs_ty, rhs.typecheck(tcx, ftcx)?)
+            }
+            FPfma(rm, x, y, z) => {
+                rounding_mode("fp.fma", rm)?;
+                let ty = expect_float("fp.fma", x.typecheck(tcx, ftcx)?)?;
+                let ty = expect_same("fp.fma", ty, y.typecheck(tcx, ftcx)?)?;
+                expect_same("fp.fma", ty, z.typecheck(tcx, ftcx)?)
+            }
+            Forall(vars, body) | Exists(vars, body) => {
+                let mut tcx = tcx.clone();
+                for (v, ty) in vars {
+                    tcx.insert(*v, ty.clone());
+                }
+                expect_bool("quantifier", body.typecheck(&tcx, ftcx)?)?;
+                Ok(Ty::Bool)
+            }
+            Let(bindings, body) => {
+                let mut body_tcx = tcx.clone();
+                for (v, exp) in bindings {
+                    body_tcx.insert(*v, exp.typecheck(tcx, ftcx)?);
+                }
+                body.typecheck(&body_tcx, ftcx)
+            }
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// Distinguishes constraints supplied by the user from those
+/// generated during symbolic execution.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssertionKind {
+    /// Generated by the model, e.g. branch conditions
+    Derived,
+    /// A precondition provided by the user, e.g. register or
+    /// instruction constraints
+    Assumption,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Def {
     DeclareConst(Sym, Ty),
     DeclareFun(Sym, Vec<Ty>, Ty),
     DefineConst(Sym, Exp<Sym>),
     DefineEnum(usize),
     Assert(Exp<Sym>),
+    NamedAssert(String, AssertionKind, Exp<Sym>),
+}
+
+impl Def {
+    /// Returns the asserted expression if the definition is an
+    /// assertion (named or otherwise).
+    pub fn assertion(&self) -> Option<&Exp<Sym>> {
+        match self {
+            Def::Assert(exp) | Def::NamedAssert(_, _, exp) => Some(exp),
+            _ => None,
+        }
+    }
+
+    pub fn is_assumption(&self) -> bool {
+        matches!(self, Def::NamedAssert(_, AssertionKind::Assumption, _))
+    }
 }
@@ -0,0 +1,217 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module lets the events of a long running path be written
+//! out while it executes, rather than accumulating them all in memory
+//! until it finishes. When a [TraceSink] is attached to a solver (see
+//! [super::Solver::set_trace_sink]), each time the path has generated
+//! a given number of unwritten events they are passed to the sink,
+//! and dropped from the in-memory trace. Events the solver needs to
+//! replay the path from a checkpoint are kept, but are not passed to
+//! the sink again (see [super::Trace::to_vec_unspilled]).
+//!
+//! Each chunk continues the previous chunk of the same path. When a
+//! path forks, the chunks written so far are shared by every path
+//! created by the fork, each of which continues them with chunks of
+//! its own. Once a path finishes, [super::Trace::last_chunk] gives
+//! the chunk its remaining events follow.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use super::Event;
+use crate::binary_trace::{BinaryTraceError, BinaryTraceWriter};
+use crate::bitvector::BV;
+use crate::ir::Val;
+
+pub trait TraceSink<B>: Send + Sync {
+    /// Called with the next chunk of events from the path belonging
+    /// to the task with id `task_id`, oldest first, which follow the
+    /// events of chunk `parent`. Returns an id for the new chunk.
+    fn spill(&self, task_id: usize, parent: Option<u64>, events: &[&Event<B>]) -> Result<u64, BinaryTraceError>;
+}
+
+/// A sink which writes each chunk of events to a binary trace as a
+/// separate record, so the chunks written so far can be recovered if
+/// the process is killed (see
+/// [crate::binary_trace::BinaryTraceReader::recovering]). The rest
+/// of each trace should be written to the same writer once its path
+/// finishes, starting with a reference to its last chunk (see
+/// [crate::binary_trace::BinaryTraceWriter::write_chunk_ref]).
+pub struct BinaryTraceSink<W> {
+    writer: Mutex<Option<BinaryTraceWriter<W>>>,
+    next_chunk: AtomicU64,
+}
+
+impl<W: Write> BinaryTraceSink<W> {
+    pub fn new(writer: BinaryTraceWriter<W>) -> Self {
+        BinaryTraceSink { writer: Mutex::new(Some(writer)), next_chunk: AtomicU64::new(0) }
+    }
+
+    /// Lock the writer, which is `None` once it has been taken.
+    pub fn lock(&self) -> MutexGuard<'_, Option<BinaryTraceWriter<W>>> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Take the writer so it can be finished. Any chunks spilled
+    /// afterwards are rejected.
+    pub fn take(&self) -> Option<BinaryTraceWriter<W>> {
+        self.lock().take()
+    }
+}
+
+impl<B: BV, W: Write + Send> TraceSink<B> for BinaryTraceSink<W> {
+    fn spill(&self, _: usize, parent: Option<u64>, events: &[&Event<B>]) -> Result<u64, BinaryTraceError> {
+        match &mut *self.lock() {
+            Some(writer) => {
+                let id = self.next_chunk.fetch_add(1, Ordering::Relaxed);
+                writer.write_chunk(id, parent, events)?;
+                Ok(id)
+            }
+            None => Err(BinaryTraceError::Io(io::Error::new(io::ErrorKind::Other, "trace file already closed"))),
+        }
+    }
+}
+
+/// True for the events which [super::Solver::replay] uses to rebuild
+/// the state of the solver, which must be kept in memory.
+pub(super) fn needed_for_replay<B>(event: &Event<B>) -> bool {
+    matches!(
+        event,
+        Event::Smt(..)
+            | Event::Cycle(_)
+            | Event::SleepRequest
+            | Event::Sleeping(_)
+            | Event::Interrupt { .. }
+            | Event::WriteMem { address: Val::Bits(_), .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::binary_trace::BinaryTraceReader;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Val;
+
+    fn write_rest(sink: &BinaryTraceSink<Vec<u8>>, solver: &Solver<B64>) {
+        let rest: Vec<Event<B64>> = solver.trace().to_vec_unspilled().into_iter().rev().cloned().collect();
+        let mut writer = sink.lock();
+        let writer = writer.as_mut().unwrap();
+        if let Some(chunk) = solver.trace().last_chunk() {
+            writer.write_chunk_ref(chunk).unwrap()
+        }
+        writer.write_trace(&rest).unwrap()
+    }
+
+    fn read_traces(sink: &BinaryTraceSink<Vec<u8>>) -> Vec<Vec<Event<B64>>> {
+        let bytes = sink.take().unwrap().into_inner();
+        BinaryTraceReader::<_, B64>::new(&bytes[..]).unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn spill_chunks() {
+        let sink = Arc::new(BinaryTraceSink::new(BinaryTraceWriter::new(Vec::new()).unwrap()));
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.set_trace_sink(sink.clone(), 4, 0);
+
+        let v = solver.declare_const(smtlib::Ty::BitVec(64), SourceLoc::unknown());
+        for i in 0..5 {
+            solver.add_event(Event::Instr(Val::Bits(B64::new(i, 32))));
+        }
+        assert_eq!(solver.trace().len(), 6);
+        assert_eq!(solver.trace().to_vec().len(), 3);
+
+        // The declaration is still needed, but has already been written
+        assert_eq!(solver.trace().to_vec_unspilled().len(), 2);
+        write_rest(&sink, &solver);
+
+        let solver_from_checkpoint = Solver::from_checkpoint(&ctx, checkpoint(&mut solver));
+        assert_eq!(solver_from_checkpoint.trace().to_vec_unspilled().len(), 2);
+        assert_eq!(solver_from_checkpoint.trace().last_chunk(), solver.trace().last_chunk());
+        assert!(
+            matches!(solver.trace().to_vec().last(), Some(Event::Smt(smtlib::Def::DeclareConst(w, _), _, _)) if *w == v)
+        );
+
+        let traces = read_traces(&sink);
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].len(), 6)
+    }
+
+    #[test]
+    fn spill_forked_paths() {
+        let sink = Arc::new(BinaryTraceSink::new(BinaryTraceWriter::new(Vec::new()).unwrap()));
+        let ctx = Context::new(Config::new());
+
+        // Events before the sink is attached, as when a task starts
+        // from a checkpoint after initialisation
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.add_event(Event::Cycle(0));
+        let start = checkpoint(&mut solver);
+
+        let mut solver = Solver::from_checkpoint(&ctx, start);
+        solver.set_trace_sink(sink.clone(), 3, 0);
+        for i in 0..3 {
+            solver.add_event(Event::Instr(Val::Bits(B64::new(i, 32))));
+        }
+        assert!(solver.trace().last_chunk().is_some());
+        solver.add_event(Event::Instr(Val::Bits(B64::new(3, 32))));
+
+        // Fork, with each path adding different events
+        let fork = checkpoint(&mut solver);
+        let mut other = Solver::from_checkpoint(&ctx, fork);
+        other.set_trace_sink(sink.clone(), 3, 0);
+        for i in 0..3 {
+            solver.add_event(Event::Instr(Val::Bits(B64::new(10 + i, 32))));
+            other.add_event(Event::Instr(Val::Bits(B64::new(20 + i, 32))))
+        }
+        other.add_event(Event::Instr(Val::Bits(B64::new(23, 32))));
+        write_rest(&sink, &solver);
+        write_rest(&sink, &other);
+
+        let instrs = |trace: &[Event<B64>]| -> Vec<u64> {
+            trace
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Instr(Val::Bits(bv)) => Some(bv.lower_u64()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let traces = read_traces(&sink);
+        assert_eq!(traces.len(), 2);
+        assert!(matches!(traces[0].first(), Some(Event::Cycle(0))));
+        assert_eq!(instrs(&traces[0]), vec![0, 1, 2, 3, 10, 11, 12]);
+        assert!(matches!(traces[1].first(), Some(Event::Cycle(0))));
+        assert_eq!(instrs(&traces[1]), vec![0, 1, 2, 3, 20, 21, 22, 23])
+    }
+}
@@ -0,0 +1,207 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module configures what happens when the SMT solver cannot
+//! decide a query, for example due to a timeout or running out of
+//! resources. By default an unknown result fails the path with
+//! [crate::error::ExecError::Z3Unknown], but a different
+//! [UnknownPolicy] can be chosen for each kind of query (see
+//! [QuerySite]). The number of unknown results can be recorded in an
+//! [UnknownStats].
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What to do when the solver returns unknown
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnknownPolicy {
+    /// Fail the path with [crate::error::ExecError::Z3Unknown]
+    #[default]
+    Abort,
+    /// Treat the query as satisfiable, so both sides of a branch are
+    /// explored and a path is never pruned as dead
+    Fork,
+    /// Check the query again with Z3's default solver rather than
+    /// the QF_AUFBV tactic, aborting if that is also unknown
+    Retry,
+}
+
+/// The kinds of query the executor makes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuerySite {
+    /// Deciding which way a symbolic branch can go
+    Branch,
+    /// Checking whether a Sail assertion can fail
+    Assertion,
+    /// Checking whether the current path is still feasible
+    Feasibility,
+}
+
+/// The policy used at each [QuerySite]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnknownPolicies {
+    branch: UnknownPolicy,
+    assertion: UnknownPolicy,
+    feasibility: UnknownPolicy,
+}
+
+impl UnknownPolicies {
+    /// Use the same policy everywhere
+    pub fn all(policy: UnknownPolicy) -> Self {
+        UnknownPolicies { branch: policy, assertion: policy, feasibility: policy }
+    }
+
+    pub fn get(&self, site: QuerySite) -> UnknownPolicy {
+        match site {
+            QuerySite::Branch => self.branch,
+            QuerySite::Assertion => self.assertion,
+            QuerySite::Feasibility => self.feasibility,
+        }
+    }
+
+    pub fn set(&mut self, site: QuerySite, policy: UnknownPolicy) -> &mut Self {
+        match site {
+            QuerySite::Branch => self.branch = policy,
+            QuerySite::Assertion => self.assertion = policy,
+            QuerySite::Feasibility => self.feasibility = policy,
+        }
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsePolicyError(String);
+
+impl fmt::Display for ParsePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParsePolicyError {}
+
+impl FromStr for UnknownPolicy {
+    type Err = ParsePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(UnknownPolicy::Abort),
+            "fork" => Ok(UnknownPolicy::Fork),
+            "retry" => Ok(UnknownPolicy::Retry),
+            _ => Err(ParsePolicyError(format!("Unknown policy {} (expected abort, fork, or retry)", s))),
+        }
+    }
+}
+
+impl FromStr for QuerySite {
+    type Err = ParsePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "branch" => Ok(QuerySite::Branch),
+            "assertion" => Ok(QuerySite::Assertion),
+            "feasibility" => Ok(QuerySite::Feasibility),
+            _ => {
+                Err(ParsePolicyError(format!("Unknown query site {} (expected branch, assertion, or feasibility)", s)))
+            }
+        }
+    }
+}
+
+/// Parses either a single policy used everywhere, e.g. `fork`, or a
+/// comma-separated list of per-site policies such as
+/// `branch=fork,assertion=retry`. Sites which are not listed use
+/// [UnknownPolicy::Abort].
+impl FromStr for UnknownPolicies {
+    type Err = ParsePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains('=') {
+            return Ok(UnknownPolicies::all(s.trim().parse()?));
+        }
+        let mut policies = UnknownPolicies::default();
+        for entry in s.split(',') {
+            let (site, policy) = entry
+                .split_once('=')
+                .ok_or_else(|| ParsePolicyError(format!("Expected <site>=<policy>, got {}", entry)))?;
+            policies.set(site.trim().parse()?, policy.trim().parse()?);
+        }
+        Ok(policies)
+    }
+}
+
+/// Counts of unknown solver results, which can be shared between
+/// threads
+#[derive(Debug, Default)]
+pub struct UnknownStats {
+    unknown: AtomicU64,
+    resolved: AtomicU64,
+}
+
+impl UnknownStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&self, resolved_by_retry: bool) {
+        self.unknown.fetch_add(1, Ordering::Relaxed);
+        if resolved_by_retry {
+            self.resolved.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of queries for which the solver returned unknown
+    pub fn unknown(&self) -> u64 {
+        self.unknown.load(Ordering::Relaxed)
+    }
+
+    /// The number of unknown queries which were decided by retrying
+    pub fn resolved_by_retry(&self) -> u64 {
+        self.resolved.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("fork".parse::<UnknownPolicies>().unwrap(), UnknownPolicies::all(UnknownPolicy::Fork));
+
+        let policies: UnknownPolicies = "branch=fork, assertion=retry".parse().unwrap();
+        assert_eq!(policies.get(QuerySite::Branch), UnknownPolicy::Fork);
+        assert_eq!(policies.get(QuerySite::Assertion), UnknownPolicy::Retry);
+        assert_eq!(policies.get(QuerySite::Feasibility), UnknownPolicy::Abort);
+
+        assert!("branch=maybe".parse::<UnknownPolicies>().is_err());
+        assert!("loop=fork".parse::<UnknownPolicies>().is_err());
+    }
+}
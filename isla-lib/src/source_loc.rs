@@ -235,7 +235,8 @@ impl SourceLoc {
 
         let file_info = format!("{}-->{} {}:{}:{}", blue, no_color, buf_name, self.line1, self.char1);
 
-        self.message_str(buf, &format!("{}error{}: {}\n", red, no_color, message), &file_info, red, blue, no_color)
+        let label = if is_error { "error" } else { "note" };
+        self.message_str(buf, &format!("{}{}{}: {}\n", red, label, no_color, message), &file_info, red, blue, no_color)
     }
 
     /// Print a message associated with an original source code
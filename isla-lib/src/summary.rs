@@ -0,0 +1,438 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module provides a collector which summarises the results of
+//! symbolic execution. Rather than producing a trace for every path,
+//! paths are grouped by the final values they write to each register
+//! (or the error they end with), with those values written in terms
+//! of the registers that were read, e.g.
+//!
+//! ```text
+//! paths 1-12: R1 := (bvadd R2 R3)
+//! paths 13-14: error: ...
+//! ```
+//!
+//! When the opcode being executed has symbolic bits, a
+//! [witness_collector] can also record a concrete opcode for each
+//! group, showing which encodings reach each outcome.
+
+use crossbeam::queue::SegQueue;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::bitvector::BV;
+use crate::error::ExecError;
+use crate::executor::{Backtrace, LocalFrame};
+use crate::ir::{BitsSegment, SharedState, Symtab, Val};
+use crate::simplify;
+use crate::smt::smtlib::{Def, Exp};
+use crate::smt::{register_name_string, Event, Model, SmtResult, Solver, Sym};
+use crate::source_loc::SourceLoc;
+use crate::zencode;
+
+/// Definitions are only inlined up to this depth, after which the
+/// variable itself is shown, so pathological traces cannot produce
+/// enormous summaries
+const MAX_INLINE_DEPTH: usize = 16;
+
+/// The outcome of a single path. The ordering is used to sort groups
+/// so summaries are deterministic regardless of which threads
+/// finished first.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathOutcome {
+    /// The final value written to each register, ordered by register
+    Writes(Vec<(String, String)>),
+    Error(String),
+}
+
+impl fmt::Display for PathOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathOutcome::Writes(writes) if writes.is_empty() => write!(f, "no register writes"),
+            PathOutcome::Writes(writes) => {
+                let writes: Vec<String> = writes.iter().map(|(reg, value)| format!("{} := {}", reg, value)).collect();
+                write!(f, "{}", writes.join(", "))
+            }
+            PathOutcome::Error(msg) => write!(f, "error: {}", msg),
+        }
+    }
+}
+
+/// Names for the variables in a trace, so the values written to
+/// registers can be shown in terms of the registers that were read
+pub(crate) struct Namer<'a> {
+    pub(crate) defs: HashMap<Sym, &'a Exp<Sym>>,
+    names: HashMap<Sym, String>,
+    undefined: HashSet<Sym>,
+}
+
+impl<'a> Namer<'a> {
+    pub(crate) fn new<B, E: Borrow<Event<B>>>(events: &[E]) -> Self {
+        Namer { defs: HashMap::new(), names: HashMap::new(), undefined: simplify::undefined_variables(events) }
+    }
+
+    pub(crate) fn name_val<B: BV>(&mut self, prefix: &str, val: &Val<B>, symtab: &Symtab) {
+        match val {
+            Val::Symbolic(v) => {
+                self.names.entry(*v).or_insert_with(|| prefix.to_string());
+            }
+            Val::Struct(fields) => {
+                for (field, val) in fields {
+                    self.name_val(&format!("{}.{}", prefix, zencode::decode(symtab.to_str(*field))), val, symtab)
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn expand(&self, v: Sym, depth: usize) -> Exp<String> {
+        if let Some(name) = self.names.get(&v) {
+            return Exp::Var(name.clone());
+        }
+        if self.undefined.contains(&v) {
+            return Exp::Var("UNKNOWN".to_string());
+        }
+        match self.defs.get(&v) {
+            Some(exp) if depth < MAX_INLINE_DEPTH => exp
+                .map_var(&mut |w| Ok::<_, String>(self.expand(*w, depth + 1)))
+                .unwrap_or_else(|_| Exp::Var(format!("v{}", v))),
+            _ => Exp::Var(format!("v{}", v)),
+        }
+    }
+
+    fn render<B: BV>(&self, val: &Val<B>, symtab: &Symtab) -> String {
+        match val {
+            Val::Symbolic(v) => {
+                let exp = self.expand(*v, 0).eval();
+                let mut buf = Vec::new();
+                simplify::write_named_exp(&mut buf, &exp).unwrap();
+                String::from_utf8_lossy(&buf).into_owned()
+            }
+            Val::Struct(fields) => {
+                let mut fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, val)| {
+                        format!("{} = {}", zencode::decode(symtab.to_str(*field)), self.render(val, symtab))
+                    })
+                    .collect();
+                fields.sort();
+                format!("{{ {} }}", fields.join(", "))
+            }
+            _ => val.to_string(symtab),
+        }
+    }
+}
+
+impl PathOutcome {
+    /// Summarise a path from its events, which should be in the order
+    /// they occurred.
+    pub fn from_events<B: BV, E: Borrow<Event<B>>>(events: &[E], symtab: &Symtab) -> Self {
+        let mut namer = Namer::new(events);
+        let mut writes: BTreeMap<String, &Val<B>> = BTreeMap::new();
+
+        for event in events {
+            let event = event.borrow();
+            match event {
+                Event::Smt(Def::DefineConst(v, exp), _, _) => {
+                    namer.defs.insert(*v, exp);
+                }
+                Event::ReadReg(_, _, val) => {
+                    if let Some(name) = register_name_string(event, symtab) {
+                        namer.name_val(&name, val, symtab)
+                    }
+                }
+                Event::WriteReg(_, _, val) => {
+                    if let Some(name) = register_name_string(event, symtab) {
+                        writes.insert(name, val);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        PathOutcome::Writes(writes.into_iter().map(|(reg, val)| (reg, namer.render(val, symtab))).collect())
+    }
+}
+
+/// The outcome of a path as passed to a collector, or `None` for dead
+/// paths which should not be counted
+fn collected_outcome<'ir, B: BV>(
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    solver: &Solver<B>,
+) -> Option<PathOutcome> {
+    match result {
+        Ok(_) | Err((ExecError::Exit, _)) => {
+            let mut events = solver.trace().to_vec();
+            events.reverse();
+            Some(PathOutcome::from_events(&events, &shared_state.symtab))
+        }
+        Err((ExecError::Dead, _)) => None,
+        Err((err, _)) => Some(PathOutcome::Error(format!("{}", err))),
+    }
+}
+
+pub type SummaryQueue = SegQueue<PathOutcome>;
+
+/// A collector which records the [PathOutcome] of each path, which
+/// can then be grouped using [Summary::from_queue].
+pub fn summary_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    solver: Solver<B>,
+    collected: &SummaryQueue,
+) {
+    if let Some(outcome) = collected_outcome(result, shared_state, &solver) {
+        collected.push(outcome)
+    }
+}
+
+pub(crate) fn model_bits<B: BV>(model: &mut Model<B>, v: Sym, len: u32) -> Result<B, ExecError> {
+    match model.get_var(v)? {
+        Some(Exp::Bits64(bv)) => Ok(B::new(bv.lower_u64(), len)),
+        Some(Exp::Bits(bits)) => Ok(bits.iter().enumerate().fold(B::zeros(len), |bv, (i, bit)| {
+            if *bit {
+                bv.set_slice(i as u32, B::new(1, 1))
+            } else {
+                bv
+            }
+        })),
+        // The model leaves bits which do not affect the path unassigned
+        None => Ok(B::zeros(len)),
+        Some(exp) => {
+            Err(ExecError::Type(format!("Expected bits in model for v{}, got {:?}", v, exp), SourceLoc::unknown()))
+        }
+    }
+}
+
+/// Find a concrete value for an opcode, which may contain symbolic
+/// bits, that is consistent with the current path. Returns `None` if
+/// the path is unsatisfiable or the opcode is not a bitvector.
+pub fn opcode_witness<B: BV>(solver: &mut Solver<B>, opcode: &Val<B>) -> Result<Option<B>, ExecError> {
+    let segments: Vec<BitsSegment<B>> = match opcode {
+        Val::Bits(bv) => return Ok(Some(*bv)),
+        Val::Symbolic(v) => vec![BitsSegment::Symbolic(*v)],
+        Val::MixedBits(segments) => segments.clone(),
+        _ => return Ok(None),
+    };
+
+    let mut lengths = HashMap::new();
+    for segment in &segments {
+        if let BitsSegment::Symbolic(v) = segment {
+            match solver.length(*v) {
+                Some(len) => lengths.insert(*v, len),
+                None => return Ok(None),
+            };
+        }
+    }
+
+    if solver.check_sat() != SmtResult::Sat {
+        return Ok(None);
+    }
+    let mut model = Model::new(solver);
+    let mut witness = B::zero_width();
+    for segment in &segments {
+        let bits = match segment {
+            BitsSegment::Concrete(bv) => *bv,
+            BitsSegment::Symbolic(v) => model_bits(&mut model, *v, lengths[v])?,
+        };
+        witness = witness.append(bits).ok_or(ExecError::Overflow)?
+    }
+    Ok(Some(witness))
+}
+
+/// Path outcomes paired with a witness value for the opcode that was
+/// executed
+pub struct WitnessQueue<B> {
+    opcode: Val<B>,
+    outcomes: SegQueue<(PathOutcome, Option<B>)>,
+}
+
+impl<B: BV> WitnessQueue<B> {
+    pub fn new(opcode: Val<B>) -> Self {
+        WitnessQueue { opcode, outcomes: SegQueue::new() }
+    }
+}
+
+/// Like [summary_collector], but also records a witness for the
+/// opcode given to [WitnessQueue::new]. Results can be grouped using
+/// [Summary::from_witness_queue].
+pub fn witness_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &WitnessQueue<B>,
+) {
+    if let Some(outcome) = collected_outcome(result, shared_state, &solver) {
+        let witness = opcode_witness(&mut solver, &collected.opcode).unwrap_or(None);
+        collected.outcomes.push((outcome, witness))
+    }
+}
+
+/// Paths grouped by their outcome, along with the number of paths in
+/// each group, and optionally a witness opcode for each group
+pub struct Summary {
+    groups: Vec<(PathOutcome, usize)>,
+    witnesses: Vec<Option<String>>,
+}
+
+impl Summary {
+    pub fn from_outcomes<I: IntoIterator<Item = PathOutcome>>(outcomes: I) -> Self {
+        let mut groups: BTreeMap<PathOutcome, usize> = BTreeMap::new();
+        for outcome in outcomes {
+            *groups.entry(outcome).or_insert(0) += 1
+        }
+        let witnesses = vec![None; groups.len()];
+        Summary { groups: groups.into_iter().collect(), witnesses }
+    }
+
+    pub fn from_queue(queue: &SummaryQueue) -> Self {
+        Summary::from_outcomes(std::iter::from_fn(|| queue.pop()))
+    }
+
+    /// Group outcomes as [Summary::from_outcomes]. The numerically
+    /// smallest witness is kept for each group, so the summary does
+    /// not depend on the order paths were explored.
+    pub fn from_witnessed<B: BV, I: IntoIterator<Item = (PathOutcome, Option<B>)>>(outcomes: I) -> Self {
+        let mut groups: BTreeMap<PathOutcome, (usize, Option<B>)> = BTreeMap::new();
+        for (outcome, witness) in outcomes {
+            let (n, smallest) = groups.entry(outcome).or_insert((0, None));
+            *n += 1;
+            *smallest = match (*smallest, witness) {
+                (Some(bv1), Some(bv2)) => Some(if bv2.unsigned() < bv1.unsigned() { bv2 } else { bv1 }),
+                (bv1, bv2) => bv1.or(bv2),
+            }
+        }
+        let (groups, witnesses) = groups
+            .into_iter()
+            .map(|(outcome, (n, witness))| ((outcome, n), witness.map(|bv| format!("{}", bv))))
+            .unzip();
+        Summary { groups, witnesses }
+    }
+
+    pub fn from_witness_queue<B: BV>(queue: &WitnessQueue<B>) -> Self {
+        Summary::from_witnessed(std::iter::from_fn(|| queue.outcomes.pop()))
+    }
+
+    pub fn groups(&self) -> &[(PathOutcome, usize)] {
+        &self.groups
+    }
+
+    pub fn num_paths(&self) -> usize {
+        self.groups.iter().map(|(_, n)| n).sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    /// Paths are numbered consecutively within each group
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = 1;
+        for ((outcome, n), witness) in self.groups.iter().zip(self.witnesses.iter()) {
+            if *n == 1 {
+                write!(f, "path {}: {}", first, outcome)?
+            } else {
+                write!(f, "paths {}-{}: {}", first, first + n - 1, outcome)?
+            }
+            if let Some(witness) = witness {
+                write!(f, " (e.g. {})", witness)?
+            }
+            writeln!(f)?;
+            first += n
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::smt::smtlib::Ty;
+    use crate::smt::{Config, Context, DefAttrs};
+
+    #[test]
+    fn summarise_register_writes() {
+        let mut symtab = Symtab::new();
+        let (r1, r2) = (symtab.intern("R1"), symtab.intern("R2"));
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let smt = |def| Event::Smt(def, DefAttrs::default(), SourceLoc::unknown());
+        let add = Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(1, 64))));
+        let events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(x, Ty::BitVec(64))),
+            Event::ReadReg(r2, vec![], Val::Symbolic(x)),
+            smt(Def::DefineConst(y, add)),
+            Event::WriteReg(r1, vec![], Val::Symbolic(y)),
+        ];
+        let outcome = PathOutcome::from_events(&events, &symtab);
+        assert_eq!(outcome, PathOutcome::Writes(vec![("R1".to_string(), "(bvadd R2 #x0000000000000001)".to_string())]));
+
+        let summary = Summary::from_outcomes(vec![
+            outcome.clone(),
+            PathOutcome::Error("oops".to_string()),
+            outcome,
+            PathOutcome::Writes(vec![]),
+        ]);
+        assert_eq!(summary.num_paths(), 4);
+        assert_eq!(
+            summary.to_string(),
+            "path 1: no register writes\npaths 2-3: R1 := (bvadd R2 #x0000000000000001)\npath 4: error: oops\n"
+        );
+    }
+
+    #[test]
+    fn witness_opcodes() {
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let v = solver.declare_const(Ty::BitVec(8), SourceLoc::unknown());
+        solver.assert(Exp::Eq(Box::new(Exp::Var(v)), Box::new(Exp::Bits64(B64::new(0x2a, 8)))));
+        let w = solver.declare_const(Ty::BitVec(4), SourceLoc::unknown());
+
+        let opcode = Val::MixedBits(vec![
+            BitsSegment::Concrete(B64::new(0xb, 4)),
+            BitsSegment::Symbolic(v),
+            BitsSegment::Symbolic(w),
+        ]);
+        assert_eq!(opcode_witness(&mut solver, &opcode).unwrap(), Some(B64::new(0xb2a0, 16)));
+
+        let outcome = PathOutcome::Writes(vec![]);
+        let summary = Summary::from_witnessed(vec![
+            (outcome.clone(), Some(B64::new(0xb2a0, 16))),
+            (PathOutcome::Error("oops".to_string()), None),
+            (outcome, Some(B64::new(0x0b2a, 16))),
+        ]);
+        assert_eq!(summary.to_string(), "paths 1-2: no register writes (e.g. #x0b2a)\npath 3: error: oops\n");
+    }
+}
@@ -0,0 +1,361 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module handles opening trace files for reading and writing,
+//! transparently applying zstd compression when the file name ends
+//! with `.zst`.
+//!
+//! When writing, each path can be placed in its own zstd frame by
+//! calling [TraceFileWriter::end_path]. Frames are independently
+//! decompressible, and the offset of each frame is recorded in an
+//! index at the end of the file, so a [TraceFileReader] can seek
+//! directly to any path. The index is stored in a zstd skippable
+//! frame, so a standard zstd decoder will still read the whole file
+//! as one stream.
+
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+
+/// Default zstd compression level, which favours speed as traces
+/// are typically written far more often than they are read.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Magic number of the zstd skippable frame holding the frame index
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5E;
+
+/// Magic number at the very end of an indexed trace file, following
+/// the number of entries in the index
+const INDEX_MAGIC: u32 = 0x49534C41;
+
+/// Returns true if a trace file should be zstd compressed, based on
+/// its extension.
+pub fn is_compressed<P: AsRef<Path>>(path: P) -> bool {
+    matches!(path.as_ref().extension(), Some(ext) if ext == "zst")
+}
+
+/// Counts the bytes written to the file, so we know where each zstd
+/// frame starts.
+struct CountingWriter {
+    fd: BufWriter<File>,
+    written: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.fd.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.fd.flush()
+    }
+}
+
+/// The state of compressed output, which is between frames until
+/// something is written
+enum ZstdState {
+    Idle(CountingWriter),
+    Frame(Encoder<'static, CountingWriter>),
+    Finished,
+}
+
+struct ZstdOutput {
+    state: ZstdState,
+    level: i32,
+    /// The offset of each frame written so far
+    frames: Vec<u64>,
+}
+
+impl ZstdOutput {
+    /// The encoder for the current frame, starting a new frame if
+    /// there is none
+    fn encoder(&mut self) -> io::Result<&mut Encoder<'static, CountingWriter>> {
+        if let ZstdState::Idle(_) = self.state {
+            if let ZstdState::Idle(fd) = mem::replace(&mut self.state, ZstdState::Finished) {
+                self.frames.push(fd.written);
+                self.state = ZstdState::Frame(Encoder::new(fd, self.level)?)
+            }
+        }
+        match &mut self.state {
+            ZstdState::Frame(encoder) => Ok(encoder),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "Trace file has already been finished")),
+        }
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        if let ZstdState::Frame(_) = self.state {
+            if let ZstdState::Frame(encoder) = mem::replace(&mut self.state, ZstdState::Finished) {
+                self.state = ZstdState::Idle(encoder.finish()?)
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the last frame and write the index as a skippable
+    /// frame. The final 8 bytes are the number of entries followed by
+    /// [INDEX_MAGIC], so the index can be found from the end of the
+    /// file.
+    fn finish(&mut self) -> io::Result<()> {
+        self.end_frame()?;
+        let ZstdState::Idle(mut fd) = mem::replace(&mut self.state, ZstdState::Finished) else { return Ok(()) };
+        let size = 8 * self.frames.len() + 8;
+        fd.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+        fd.write_all(&(size as u32).to_le_bytes())?;
+        for offset in &self.frames {
+            fd.write_all(&offset.to_le_bytes())?
+        }
+        fd.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        fd.write_all(&INDEX_MAGIC.to_le_bytes())?;
+        fd.flush()
+    }
+}
+
+enum Output {
+    Plain(BufWriter<File>),
+    Zstd(ZstdOutput),
+}
+
+/// A writer for trace files, which compresses its output if the file
+/// was created with a `.zst` extension. Dropping the writer finishes
+/// the file as [TraceFileWriter::finish] does, but ignores any
+/// errors.
+pub struct TraceFileWriter {
+    output: Output,
+}
+
+impl TraceFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::create_with_level(path, DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    pub fn create_with_level<P: AsRef<Path>>(path: P, level: i32) -> io::Result<Self> {
+        // Traces can be large, so use a 5MB buffer
+        let fd = BufWriter::with_capacity(5 * usize::pow(2, 20), File::create(&path)?);
+        let output = if is_compressed(&path) {
+            let fd = CountingWriter { fd, written: 0 };
+            Output::Zstd(ZstdOutput { state: ZstdState::Idle(fd), level, frames: Vec::new() })
+        } else {
+            Output::Plain(fd)
+        };
+        Ok(TraceFileWriter { output })
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.output, Output::Zstd(_))
+    }
+
+    /// Mark the end of a path in the trace file. For compressed
+    /// output this finishes the current zstd frame, so the next path
+    /// starts in a fresh frame. Empty paths do not create a frame.
+    pub fn end_path(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            Output::Plain(_) => Ok(()),
+            Output::Zstd(output) => output.end_frame(),
+        }
+    }
+
+    fn finish_output(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            Output::Plain(fd) => fd.flush(),
+            Output::Zstd(output) => output.finish(),
+        }
+    }
+
+    /// Finish writing the trace file, ending the final path and
+    /// writing the frame index for compressed output.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_output()
+    }
+}
+
+impl Drop for TraceFileWriter {
+    fn drop(&mut self) {
+        let _ = self.finish_output();
+    }
+}
+
+impl Write for TraceFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.output {
+            Output::Plain(fd) => fd.write(buf),
+            Output::Zstd(_) if buf.is_empty() => Ok(0),
+            Output::Zstd(output) => output.encoder()?.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            Output::Plain(fd) => fd.flush(),
+            Output::Zstd(ZstdOutput { state: ZstdState::Idle(fd), .. }) => fd.flush(),
+            Output::Zstd(ZstdOutput { state: ZstdState::Frame(encoder), .. }) => encoder.flush(),
+            Output::Zstd(ZstdOutput { state: ZstdState::Finished, .. }) => Ok(()),
+        }
+    }
+}
+
+/// Open a trace file for reading, decompressing it if it has a
+/// `.zst` extension. All frames in a compressed file are read in
+/// sequence.
+pub fn open_trace_file<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    let fd = File::open(&path)?;
+    if is_compressed(&path) {
+        Ok(Box::new(BufReader::new(Decoder::new(fd)?)))
+    } else {
+        Ok(Box::new(BufReader::new(fd)))
+    }
+}
+
+fn invalid_index(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid trace file index: {}", msg))
+}
+
+/// A reader for compressed trace files which uses the frame index
+/// to read individual paths. Each call to
+/// [TraceFileWriter::end_path] ends a frame, so frame `n` holds the
+/// `n`th path, unless something (such as a header) was written
+/// before the first path.
+pub struct TraceFileReader {
+    fd: File,
+    frames: Vec<u64>,
+}
+
+impl TraceFileReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        if !is_compressed(&path) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Only compressed trace files have a frame index"));
+        }
+        let mut fd = File::open(&path)?;
+
+        let len = fd.seek(SeekFrom::End(0))?;
+        if len < 16 {
+            return Err(invalid_index("file is too short"));
+        }
+        let mut footer = [0; 8];
+        fd.seek(SeekFrom::End(-8))?;
+        fd.read_exact(&mut footer)?;
+        if u32::from_le_bytes(footer[4..].try_into().unwrap()) != INDEX_MAGIC {
+            return Err(invalid_index("no index found"));
+        }
+        let count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+        if 8 * count + 16 > len {
+            return Err(invalid_index("index is longer than the file"));
+        }
+
+        let index_start = len - 8 * count - 16;
+        let mut header = [0; 8];
+        fd.seek(SeekFrom::Start(index_start))?;
+        fd.read_exact(&mut header)?;
+        if u32::from_le_bytes(header[..4].try_into().unwrap()) != SKIPPABLE_FRAME_MAGIC
+            || u32::from_le_bytes(header[4..].try_into().unwrap()) as u64 != 8 * count + 8
+        {
+            return Err(invalid_index("bad skippable frame header"));
+        }
+
+        let mut entries = vec![0; 8 * count as usize];
+        fd.read_exact(&mut entries)?;
+        let frames: Vec<u64> =
+            entries.chunks_exact(8).map(|entry| u64::from_le_bytes(entry.try_into().unwrap())).collect();
+        if frames.iter().any(|offset| *offset >= index_start) {
+            return Err(invalid_index("frame offset is out of bounds"));
+        }
+
+        Ok(TraceFileReader { fd, frames })
+    }
+
+    /// The number of frames in the file
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Seek to frame `n` and return a reader for its contents
+    pub fn read_frame(&mut self, n: usize) -> io::Result<impl BufRead + '_> {
+        let offset = *self
+            .frames
+            .get(n)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Frame {} is out of range", n)))?;
+        self.fd.seek(SeekFrom::Start(offset))?;
+        Ok(BufReader::new(Decoder::new(&mut self.fd)?.single_frame()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_roundtrip() {
+        let path = std::env::temp_dir().join(format!("isla_trace_file_test_{}.zst", std::process::id()));
+        let mut writer = TraceFileWriter::create(&path).unwrap();
+        assert!(writer.is_compressed());
+        writer.write_all(b"(trace\n  (cycle))\n").unwrap();
+        writer.end_path().unwrap();
+        writer.write_all(b"(trace\n  (cycle))\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut contents = String::new();
+        open_trace_file(&path).unwrap().read_to_string(&mut contents).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "(trace\n  (cycle))\n(trace\n  (cycle))\n")
+    }
+
+    #[test]
+    fn read_frame() {
+        let path = std::env::temp_dir().join(format!("isla_trace_file_frames_test_{}.zst", std::process::id()));
+        {
+            let mut writer = TraceFileWriter::create(&path).unwrap();
+            for i in 0..3 {
+                writeln!(writer, "(trace {})", i).unwrap();
+                writer.end_path().unwrap()
+            }
+            writer.end_path().unwrap();
+            writeln!(writer, "(trace 3)").unwrap();
+            // Dropped without calling finish
+        }
+
+        let mut reader = TraceFileReader::open(&path).unwrap();
+        assert_eq!(reader.frame_count(), 4);
+        for i in [2, 0, 3, 1] {
+            let mut contents = String::new();
+            reader.read_frame(i).unwrap().read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, format!("(trace {})\n", i))
+        }
+        assert!(reader.read_frame(4).is_err());
+
+        let mut contents = String::new();
+        open_trace_file(&path).unwrap().read_to_string(&mut contents).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "(trace 0)\n(trace 1)\n(trace 2)\n(trace 3)\n")
+    }
+}
@@ -0,0 +1,231 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module computes statistics about traces, for finding which
+//! traces are responsible for a slow or memory hungry run. For each
+//! trace a [TraceStats] records the number of events of each kind,
+//! the number of symbolic variables, the number of assertions and how
+//! deeply nested the definitions they depend on are, the size of the
+//! largest expression, and the registers read and written, e.g.
+//!
+//! ```text
+//! 1532 events
+//!   define-const 1204, assert 41, read-reg 57, write-reg 12, ...
+//!   variables: 96 declared, 1204 defined
+//!   assertions: 41, depth 23
+//!   largest expression: 312 nodes
+//!   registers read: PSTATE, R1, R2
+//!   registers written: PC, R1
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::ir::{Name, Symtab};
+use crate::smt::smtlib::{Def, Exp};
+use crate::smt::{Event, Sym};
+use crate::zencode;
+
+/// Statistics for a single trace
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceStats {
+    pub events: usize,
+    /// The number of events of each kind, using the names from the
+    /// textual trace format
+    pub event_kinds: BTreeMap<&'static str, usize>,
+    /// Variables introduced by `declare-const` or `declare-fun`
+    pub declared: usize,
+    /// Variables introduced by `define-const`
+    pub defined: usize,
+    pub assertions: usize,
+    /// The length of the longest chain of definitions an assertion
+    /// depends on, counting the assertion itself
+    pub assertion_depth: usize,
+    /// The number of nodes in the largest defined or asserted
+    /// expression, without inlining any definitions
+    pub largest_exp: usize,
+    pub registers_read: BTreeSet<Name>,
+    pub registers_written: BTreeSet<Name>,
+}
+
+fn event_kind<B>(event: &Event<B>) -> &'static str {
+    match event {
+        Event::Smt(Def::DeclareConst(..), _, _) => "declare-const",
+        Event::Smt(Def::DeclareFun(..), _, _) => "declare-fun",
+        Event::Smt(Def::DefineConst(..), _, _) => "define-const",
+        Event::Smt(Def::DefineEnum(..), _, _) => "define-enum",
+        Event::Smt(Def::Assert(..) | Def::NamedAssert(..), _, _) => "assert",
+        Event::Fork(..) => "branch",
+        Event::Function { call: true, .. } => "call",
+        Event::Function { call: false, .. } => "return",
+        Event::Abstract { primitive: false, .. } => "abstract-call",
+        Event::Abstract { primitive: true, .. } => "abstract-primop",
+        Event::ReadReg(..) => "read-reg",
+        Event::WriteReg(..) => "write-reg",
+        Event::AssumeReg(..) => "assume-reg",
+        Event::ReadMem { .. } => "read-mem",
+        Event::WriteMem { .. } => "write-mem",
+        Event::MarkReg { .. } => "mark-reg",
+        Event::Branch { .. } => "branch-address",
+        Event::AddressAnnounce { .. } => "address-announce",
+        Event::Cycle(_) => "cycle",
+        Event::Instr(_) => "instr",
+        Event::SleepRequest => "sleep-request",
+        Event::WakeupRequest => "wakeup-request",
+        Event::Sleeping(_) => "sleeping",
+        Event::Interrupt { .. } => "interrupt",
+        Event::Assume(_) => "assume",
+        Event::AssumeFun { .. } => "function-assumption",
+        Event::UseFunAssumption { .. } => "use-function-assumption",
+        Event::Provenance { .. } => "provenance",
+    }
+}
+
+/// The number of nodes in an expression, and the greatest depth of
+/// any variable it uses
+fn exp_stats(exp: &Exp<Sym>, depths: &HashMap<Sym, usize>) -> (usize, usize) {
+    let mut size = 0;
+    let mut depth = 0;
+    exp.clone().modify(&mut |exp| {
+        size += 1;
+        if let Exp::Var(v) = &*exp {
+            depth = depth.max(depths.get(v).copied().unwrap_or(0))
+        }
+    });
+    (size, depth)
+}
+
+impl TraceStats {
+    /// Compute the statistics for a trace, whose events should be in
+    /// the order they occurred.
+    pub fn from_events<B>(events: &[Event<B>]) -> Self {
+        let mut stats = TraceStats { events: events.len(), ..TraceStats::default() };
+        // The length of the chain of definitions each variable depends on
+        let mut depths: HashMap<Sym, usize> = HashMap::new();
+
+        for event in events {
+            *stats.event_kinds.entry(event_kind(event)).or_insert(0) += 1;
+            match event {
+                Event::Smt(Def::DeclareConst(v, _) | Def::DeclareFun(v, _, _), _, _) => {
+                    stats.declared += 1;
+                    depths.insert(*v, 1);
+                }
+                Event::Smt(Def::DefineConst(v, exp), _, _) => {
+                    stats.defined += 1;
+                    let (size, depth) = exp_stats(exp, &depths);
+                    stats.largest_exp = stats.largest_exp.max(size);
+                    depths.insert(*v, depth + 1);
+                }
+                Event::Smt(Def::Assert(exp) | Def::NamedAssert(_, _, exp), _, _) => {
+                    stats.assertions += 1;
+                    let (size, depth) = exp_stats(exp, &depths);
+                    stats.largest_exp = stats.largest_exp.max(size);
+                    stats.assertion_depth = stats.assertion_depth.max(depth + 1);
+                }
+                Event::ReadReg(reg, _, _) | Event::AssumeReg(reg, _, _) => {
+                    stats.registers_read.insert(*reg);
+                }
+                Event::WriteReg(reg, _, _) => {
+                    stats.registers_written.insert(*reg);
+                }
+                _ => (),
+            }
+        }
+
+        stats
+    }
+
+    pub fn to_string(&self, symtab: &Symtab) -> String {
+        let registers = |regs: &BTreeSet<Name>| {
+            let mut names: Vec<String> = regs.iter().map(|reg| zencode::decode(symtab.to_str(*reg))).collect();
+            names.sort();
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        };
+
+        let mut kinds: Vec<(&str, usize)> = self.event_kinds.iter().map(|(kind, n)| (*kind, *n)).collect();
+        kinds.sort_by(|(k1, n1), (k2, n2)| n2.cmp(n1).then(k1.cmp(k2)));
+        let kinds: Vec<String> = kinds.iter().map(|(kind, n)| format!("{} {}", kind, n)).collect();
+
+        format!(
+            "{} events\n  {}\n  variables: {} declared, {} defined\n  assertions: {}, depth {}\n  largest expression: {} nodes\n  registers read: {}\n  registers written: {}",
+            self.events,
+            kinds.join(", "),
+            self.declared,
+            self.defined,
+            self.assertions,
+            self.assertion_depth,
+            self.largest_exp,
+            registers(&self.registers_read),
+            registers(&self.registers_written),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Val;
+    use crate::smt::smtlib::{bits64, Ty};
+    use crate::smt::DefAttrs;
+    use crate::source_loc::SourceLoc;
+
+    #[test]
+    fn trace_statistics() {
+        let mut symtab = Symtab::new();
+        let (r1, r2) = (symtab.intern("zR1"), symtab.intern("zR2"));
+        let (x, y, z) = (Sym::from_u32(0), Sym::from_u32(1), Sym::from_u32(2));
+        let smt = |def| Event::Smt(def, DefAttrs::default(), SourceLoc::unknown());
+        let events: Vec<Event<B64>> = vec![
+            smt(Def::DeclareConst(x, Ty::BitVec(8))),
+            Event::ReadReg(r1, vec![], Val::Symbolic(x)),
+            smt(Def::DefineConst(y, Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(bits64(1, 8))))),
+            smt(Def::DefineConst(z, Exp::Bvnot(Box::new(Exp::Var(y))))),
+            smt(Def::Assert(Exp::Eq(Box::new(Exp::Var(z)), Box::new(bits64(0, 8))))),
+            Event::WriteReg(r2, vec![], Val::Symbolic(z)),
+            Event::Cycle(0),
+        ];
+
+        let stats = TraceStats::from_events(&events);
+        assert_eq!(stats.events, 7);
+        assert_eq!(stats.event_kinds["define-const"], 2);
+        assert_eq!((stats.declared, stats.defined, stats.assertions), (1, 2, 1));
+        assert_eq!(stats.assertion_depth, 4);
+        assert_eq!(stats.largest_exp, 3);
+        assert_eq!(
+            stats.to_string(&symtab),
+            "7 events\n  define-const 2, assert 1, cycle 1, declare-const 1, read-reg 1, write-reg 1\n  \
+             variables: 1 declared, 2 defined\n  assertions: 1, depth 4\n  largest expression: 3 nodes\n  \
+             registers read: R1\n  registers written: R2"
+        )
+    }
+}
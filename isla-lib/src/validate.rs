@@ -0,0 +1,261 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module checks that a sequence of events is well-formed. The
+//! executor should only ever produce well-formed traces, so this is
+//! mostly useful for catching bugs in the executor itself, and in
+//! trace files that have been produced or edited by other tools.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::bitvector::BV;
+use crate::ir::{BitsSegment, Val};
+use crate::simplify::calculate_uses;
+use crate::smt::smtlib::{Def, Ty};
+use crate::smt::{Event, Sym};
+
+/// A violation of one of the trace invariants. The `usize` in each
+/// case is the index of the offending event.
+#[derive(Clone, Debug)]
+pub enum EventError {
+    /// A symbol is used before (or without) being declared
+    Undeclared(usize, Sym),
+    /// A symbol is declared or defined more than once
+    Redeclared(usize, Sym),
+    /// The width of a memory read or write value does not match the
+    /// number of bytes accessed
+    WidthMismatch { index: usize, bytes: u32, width: u32 },
+    /// A fork or assertion is over a non-boolean expression
+    NotBoolean(usize, Ty),
+    /// A fork identifier is repeated, or fork identifiers go
+    /// backwards within a single trace
+    BadFork { index: usize, fork_id: u32, previous: u32 },
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EventError::*;
+        match self {
+            Undeclared(i, v) => write!(f, "Event {}: variable v{} is used before it is declared", i, v),
+            Redeclared(i, v) => write!(f, "Event {}: variable v{} is declared more than once", i, v),
+            WidthMismatch { index, bytes, width } => {
+                write!(f, "Event {}: {} byte memory access has a {} bit value", index, bytes, width)
+            }
+            NotBoolean(i, ty) => write!(f, "Event {}: expected a boolean, but found {:?}", i, ty),
+            BadFork { index, fork_id, previous } => {
+                write!(f, "Event {}: fork {} follows fork {} in the same trace", index, fork_id, previous)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EventError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+fn val_width<B: BV>(val: &Val<B>, tcx: &HashMap<Sym, Ty>) -> Option<u32> {
+    match val {
+        Val::Bits(bv) => Some(bv.len()),
+        Val::Symbolic(v) => match tcx.get(v) {
+            Some(Ty::BitVec(width)) => Some(*width),
+            _ => None,
+        },
+        Val::MixedBits(segments) => segments.iter().try_fold(0, |width, segment| match segment {
+            BitsSegment::Concrete(bv) => Some(width + bv.len()),
+            BitsSegment::Symbolic(v) => match tcx.get(v) {
+                Some(Ty::BitVec(w)) => Some(width + w),
+                _ => None,
+            },
+        }),
+        _ => None,
+    }
+}
+
+fn declare(declared: &mut HashSet<Sym>, errors: &mut Vec<EventError>, i: usize, v: Sym) {
+    if !declared.insert(v) {
+        errors.push(EventError::Redeclared(i, v))
+    }
+}
+
+/// Check a trace for well-formedness, returning every violation
+/// found. The events should be in the order they occurred (i.e. the
+/// order in which they are written, not the reverse order in which
+/// they are collected from the executor). The checks are:
+///
+/// * Every symbol is declared exactly once, before it is used.
+///
+/// * The value of every memory read and write has a width equal to
+///   the number of bytes accessed (when the width is known).
+///
+/// * Fork conditions and assertions are boolean.
+///
+/// * Fork identifiers strictly increase along the trace.
+pub fn validate_events<B: BV>(events: &[Event<B>]) -> Vec<EventError> {
+    let mut errors = Vec::new();
+
+    let mut declared: HashSet<Sym> = HashSet::new();
+    let mut tcx: HashMap<Sym, Ty> = HashMap::new();
+    let mut ftcx: HashMap<Sym, (Vec<Ty>, Ty)> = HashMap::new();
+    let mut last_fork: Option<u32> = None;
+
+    for (i, event) in events.iter().enumerate() {
+        let mut uses: Vec<Sym> = calculate_uses::<B, _>(std::slice::from_ref(event)).into_keys().collect();
+        uses.sort_by_key(|v| v.id);
+        // Memory writes introduce their own success variable
+        if let Event::WriteMem { value, .. } = event {
+            uses.retain(|v| v != value)
+        }
+        for v in uses {
+            if !declared.contains(&v) {
+                errors.push(EventError::Undeclared(i, v))
+            }
+        }
+
+        match event {
+            Event::Smt(Def::DeclareConst(v, ty), _, _) => {
+                declare(&mut declared, &mut errors, i, *v);
+                tcx.insert(*v, ty.clone());
+            }
+            Event::Smt(Def::DeclareFun(v, arg_tys, ret_ty), _, _) => {
+                declare(&mut declared, &mut errors, i, *v);
+                ftcx.insert(*v, (arg_tys.clone(), ret_ty.clone()));
+            }
+            Event::Smt(Def::DefineConst(v, exp), _, _) => {
+                declare(&mut declared, &mut errors, i, *v);
+                if let Some(ty) = exp.infer(&tcx, &ftcx) {
+                    tcx.insert(*v, ty);
+                }
+            }
+            Event::Smt(Def::Assert(exp), _, _) | Event::Smt(Def::NamedAssert(_, _, exp), _, _) => {
+                match exp.infer(&tcx, &ftcx) {
+                    Some(Ty::Bool) | None => (),
+                    Some(ty) => errors.push(EventError::NotBoolean(i, ty)),
+                }
+            }
+            Event::WriteMem { value, data, bytes, .. } => {
+                declare(&mut declared, &mut errors, i, *value);
+                tcx.insert(*value, Ty::Bool);
+                match val_width(data, &tcx) {
+                    Some(width) if width != bytes * 8 => {
+                        errors.push(EventError::WidthMismatch { index: i, bytes: *bytes, width })
+                    }
+                    _ => (),
+                }
+            }
+            Event::ReadMem { value, bytes, .. } => match val_width(value, &tcx) {
+                Some(width) if width != bytes * 8 => {
+                    errors.push(EventError::WidthMismatch { index: i, bytes: *bytes, width })
+                }
+                _ => (),
+            },
+            Event::Fork(fork_id, v, _, _) => {
+                match tcx.get(v) {
+                    Some(Ty::Bool) | None => (),
+                    Some(ty) => errors.push(EventError::NotBoolean(i, ty.clone())),
+                }
+                if let Some(previous) = last_fork {
+                    if *fork_id <= previous {
+                        errors.push(EventError::BadFork { index: i, fork_id: *fork_id, previous })
+                    }
+                }
+                last_fork = Some(*fork_id)
+            }
+            _ => (),
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitvector::b64::B64;
+    use crate::ir::Name;
+    use crate::smt::smtlib::Exp;
+    use crate::smt::{DefAttrs, ReadOpts};
+    use crate::source_loc::SourceLoc;
+
+    #[test]
+    fn well_formed() {
+        let (x, b) = (Sym::from_u32(0), Sym::from_u32(1));
+        let events: Vec<Event<B64>> = vec![
+            Event::Smt(Def::DeclareConst(x, Ty::BitVec(32)), DefAttrs::default(), SourceLoc::unknown()),
+            Event::Smt(
+                Def::DefineConst(b, Exp::Eq(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(B64::new(0, 32))))),
+                DefAttrs::default(),
+                SourceLoc::unknown(),
+            ),
+            Event::Fork(0, b, 0, SourceLoc::unknown()),
+            Event::ReadMem {
+                value: Val::Symbolic(x),
+                read_kind: Val::Unit,
+                address: Val::Bits(B64::new(0x1000, 64)),
+                bytes: 4,
+                tag_value: None,
+                opts: ReadOpts::default(),
+                region: "default",
+            },
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(x)),
+        ];
+        assert!(validate_events(&events).is_empty())
+    }
+
+    #[test]
+    fn ill_formed() {
+        let (x, y) = (Sym::from_u32(0), Sym::from_u32(1));
+        let events: Vec<Event<B64>> = vec![
+            Event::WriteReg(Name::from_u32(0), vec![], Val::Symbolic(y)),
+            Event::Smt(Def::DeclareConst(x, Ty::BitVec(32)), DefAttrs::default(), SourceLoc::unknown()),
+            Event::Smt(Def::DeclareConst(x, Ty::BitVec(32)), DefAttrs::default(), SourceLoc::unknown()),
+            Event::Fork(1, x, 0, SourceLoc::unknown()),
+            Event::Fork(0, x, 0, SourceLoc::unknown()),
+            Event::ReadMem {
+                value: Val::Symbolic(x),
+                read_kind: Val::Unit,
+                address: Val::Bits(B64::new(0x1000, 64)),
+                bytes: 8,
+                tag_value: None,
+                opts: ReadOpts::default(),
+                region: "default",
+            },
+        ];
+        let errors = validate_events(&events);
+        assert!(matches!(errors[0], EventError::Undeclared(0, v) if v == y));
+        assert!(matches!(errors[1], EventError::Redeclared(2, v) if v == x));
+        assert!(matches!(errors[2], EventError::NotBoolean(3, Ty::BitVec(32))));
+        assert!(matches!(errors[3], EventError::NotBoolean(4, _)));
+        assert!(matches!(errors[4], EventError::BadFork { index: 4, fork_id: 0, previous: 1 }));
+        assert!(matches!(errors[5], EventError::WidthMismatch { index: 5, bytes: 8, width: 32 }));
+        assert_eq!(errors.len(), 6)
+    }
+}
@@ -33,17 +33,19 @@
 //! memory model.
 
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
 
 use isla_lib::bitvector::{required_index_bits, BV};
 use isla_lib::ir::{SharedState, Val};
 use isla_lib::smt::smtlib::Ty;
-use isla_lib::smt::{Event, Sym};
+use isla_lib::smt::{Event, MemKind, Sym};
 use isla_lib::zencode;
+use isla_lib::{if_logging, log};
 
 use crate::memory_model::constants::*;
-use crate::memory_model::{Accessor, AccessorInfo, Name, Symtab};
-use crate::smt::{Sexp, SexpArena, SexpId};
+use crate::memory_model::{format_location, Accessor, AccessorInfo, Error, Name, Symtab};
+use crate::smt::{BitWidth, Sexp, SexpArena, SexpId};
 
 /// Because isla-axiomatic imports isla-mml, we don't know the
 /// concrete (axiomatic) event type yet. Therefore, we define a trait
@@ -67,6 +69,32 @@ pub enum AccessorTree<'a> {
 
 static ACCESSORTREE_LEAF: AccessorTree<'static> = AccessorTree::Leaf;
 
+/// The arm of a match accessor taken by an event, recorded for the
+/// accessor coverage debug output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchArm {
+    Ctor(Name),
+    Wildcard,
+    NoArm,
+}
+
+impl MatchArm {
+    fn from_key(key: Option<Name>) -> Self {
+        match key {
+            Some(ctor) => MatchArm::Ctor(ctor),
+            None => MatchArm::Wildcard,
+        }
+    }
+
+    fn pretty<'a>(&self, symtab: &'a Symtab) -> &'a str {
+        match self {
+            MatchArm::Ctor(ctor) => &symtab[*ctor],
+            MatchArm::Wildcard => "_",
+            MatchArm::NoArm => "no arm",
+        }
+    }
+}
+
 impl<'a> AccessorTree<'a> {
     pub fn from_accessors(accessors: &'a [Accessor]) -> Self {
         let mut constructor_stack = Vec::new();
@@ -100,6 +128,7 @@ impl<'a> AccessorTree<'a> {
 enum AccessorVal<'ev, B> {
     Val(&'ev Val<B>),
     Bits(B),
+    Bool(bool),
     Sexp(SexpId),
 }
 
@@ -108,6 +137,8 @@ impl<'ev, B: BV> AccessorVal<'ev, B> {
         Some(match self {
             AccessorVal::Sexp(id) => id,
             AccessorVal::Bits(bv) => sexps.alloc(Sexp::Bits(bv.to_vec())),
+            AccessorVal::Bool(true) => sexps.bool_true,
+            AccessorVal::Bool(false) => sexps.bool_false,
             AccessorVal::Val(v) => match v {
                 Val::Bool(true) => sexps.bool_true,
                 Val::Bool(false) => sexps.bool_false,
@@ -118,8 +149,87 @@ impl<'ev, B: BV> AccessorVal<'ev, B> {
             },
         })
     }
+
+    /// Infer the SMT type of the value, if possible
+    fn infer_type(self, types: &HashMap<Sym, Ty>, sexps: &mut SexpArena) -> Option<SexpId> {
+        match self {
+            AccessorVal::Bits(bv) => Some(sexps.alloc_bitvec(bv.len())),
+            AccessorVal::Val(Val::Bits(bv)) => Some(sexps.alloc_bitvec(bv.len())),
+            AccessorVal::Bool(_) | AccessorVal::Val(Val::Bool(_)) => Some(sexps.bool_ty),
+            AccessorVal::Val(Val::Symbolic(v)) => symbolic_type(*v, types, sexps),
+            AccessorVal::Sexp(sexp) => {
+                if sexp == sexps.bool_true || sexp == sexps.bool_false {
+                    Some(sexps.bool_ty)
+                } else {
+                    sexp_bitvec_width(sexp, types, sexps).map(|width| sexps.alloc_bitvec(width))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn symbolic_type(v: Sym, types: &HashMap<Sym, Ty>, sexps: &mut SexpArena) -> Option<SexpId> {
+    match types.get(&v) {
+        Some(Ty::BitVec(width)) => Some(sexps.alloc_bitvec(*width)),
+        Some(Ty::Bool) => Some(sexps.bool_ty),
+        _ => None,
+    }
 }
 
+/// Compute the width of the bitvector S-expressions generated by the
+/// accessor functions, i.e. symbolic variables and the results of
+/// applying extract, zero_extend, and sign_extend to them.
+fn sexp_bitvec_width(sexp: SexpId, types: &HashMap<Sym, Ty>, sexps: &SexpArena) -> Option<u32> {
+    match &sexps[sexp] {
+        Sexp::Bits(bv) => Some(bv.len() as u32),
+        Sexp::Symbolic(v) => match types.get(v) {
+            Some(Ty::BitVec(width)) => Some(*width),
+            _ => None,
+        },
+        Sexp::List(xs) => match xs.as_slice() {
+            [op, arg] => match &sexps[*op] {
+                Sexp::List(op) => match op.as_slice() {
+                    [_, extract, hi, lo] if *extract == sexps.extract => match (&sexps[*hi], &sexps[*lo]) {
+                        (Sexp::Int(hi), Sexp::Int(lo)) => Some(hi - lo + 1),
+                        _ => None,
+                    },
+                    [_, extend, n] if *extend == sexps.zero_extend || *extend == sexps.sign_extend => {
+                        match &sexps[*n] {
+                            Sexp::Int(n) => sexp_bitvec_width(*arg, types, sexps).map(|width| width + n),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns false if two SMT types are known to differ
+fn compatible_types(ty1: SexpId, ty2: SexpId, sexps: &SexpArena) -> bool {
+    match (&sexps[ty1], &sexps[ty2]) {
+        (Sexp::Atom(a), Sexp::Atom(b)) => a == b,
+        (Sexp::BitVec(BitWidth::Fixed(n)), Sexp::BitVec(BitWidth::Fixed(m))) => n == m,
+        // The width of an index bitvector is not known until the
+        // index sets for the execution have been computed
+        (Sexp::BitVec(BitWidth::Index(_)), Sexp::BitVec(_)) | (Sexp::BitVec(_), Sexp::BitVec(BitWidth::Index(_))) => {
+            true
+        }
+        (Sexp::EnumTy(n), Sexp::EnumTy(m)) => n == m,
+        _ => false,
+    }
+}
+
+// The attributes that can be accessed using `.attr()`. Not every
+// event has every attribute, e.g. only memory events have `.acquire()`.
+static SPECIAL_ATTRIBUTES: [&str; 10] =
+    ["opcode", "data", "address", "return", "acquire", "release", "exclusive", "rmw", "size_class", "rmw_id"];
+
 // This type represents the view into an event's data as we follow the
 // accessor sequence through it.
 struct View<'ev, B> {
@@ -132,11 +242,13 @@ struct View<'ev, B> {
     values: Option<&'ev [Val<B>]>,
     // The contains the value we get as we apply accessors to the event data
     value: Option<AccessorVal<'ev, B>>,
+    // For register events, the register whose (possibly packed) value we are viewing
+    register: Option<Name>,
 }
 
 impl<'ev, B: BV> Default for View<'ev, B> {
     fn default() -> Self {
-        View { name: None, special: HashMap::new(), values: None, value: None }
+        View { name: None, special: HashMap::new(), values: None, value: None, register: None }
     }
 }
 
@@ -193,11 +305,39 @@ impl<'ev, B: BV> View<'ev, B> {
         self
     }
 
+    // Memory events have attributes derived from their read or write
+    // kind, accessed using `.acquire()`, `.release()`, `.exclusive()`,
+    // `.rmw()`, and `.size_class()`.
+    fn with_mem_kind(mut self, kind: &MemKind) -> Self {
+        self.special.insert("acquire".to_string(), AccessorVal::Bool(kind.acquire));
+        self.special.insert("release".to_string(), AccessorVal::Bool(kind.release));
+        self.special.insert("exclusive".to_string(), AccessorVal::Bool(kind.exclusive));
+        self.special.insert("rmw".to_string(), AccessorVal::Bool(kind.rmw));
+        if let Some(size_class) = kind.size_class {
+            self.special.insert("size_class".to_string(), AccessorVal::Bits(B::from_u32(size_class)));
+        }
+        self
+    }
+
+    // The read and write halves of an atomic read-modify-write share
+    // an id, accessed using `.rmw_id()`.
+    fn with_rmw_id(mut self, rmw_id: Option<u32>) -> Self {
+        if let Some(id) = rmw_id {
+            self.special.insert("rmw_id".to_string(), AccessorVal::Bits(B::from_u32(id)));
+        }
+        self
+    }
+
     fn with_value(mut self, value: &'ev Val<B>) -> Self {
         self.value = Some(AccessorVal::Val(value));
         self
     }
 
+    fn with_register(mut self, register: Name) -> Self {
+        self.register = Some(register);
+        self
+    }
+
     fn with_values(mut self, values: &'ev [Val<B>]) -> Self {
         match values {
             [value] => self.value = Some(AccessorVal::Val(value)),
@@ -240,12 +380,16 @@ impl<'ev, B: BV> View<'ev, B> {
         *self = Self::default()
     }
 
-    fn access_special<S: Into<String>>(&mut self, key: S) {
-        if let Some(value) = self.special.get(&key.into()) {
+    fn access_special(&mut self, key: &str) -> Result<(), String> {
+        if let Some(value) = self.special.get(key) {
             self.set_accessor_value(*value)
-        } else {
+        } else if SPECIAL_ATTRIBUTES.contains(&key) {
+            // The attribute exists, but not for this kind of event
             *self = Self::default()
+        } else {
+            return Err(format!("unknown attribute .{}()", key));
         }
+        Ok(())
     }
 
     fn access_match<'a, 'b, 'c>(
@@ -253,20 +397,25 @@ impl<'ev, B: BV> View<'ev, B> {
         arms: &'b HashMap<Option<Name>, AccessorTree<'c>>,
         symtab: &Symtab,
         shared_state: &SharedState<B>,
-    ) -> &'b AccessorTree<'c> {
+    ) -> Result<(&'b AccessorTree<'c>, MatchArm), String> {
         if let Some(AccessorVal::Val(Val::Ctor(ctor_name, value))) = self.value {
-            let ctor_name = shared_state.symtab.to_str_demangled(*ctor_name);
+            let ctor_name = zencode::decode(shared_state.symtab.to_str_demangled(*ctor_name));
             self.set_value(value);
-            let n = &symtab.lookup(&zencode::decode(ctor_name));
-            return match arms.get(n) {
-                Some(accessor_tree) => accessor_tree,
-                // If the constructor isn't in the match arms, return the wildcard using None
-                None => &arms[&None],
+            let n = symtab.lookup(&ctor_name);
+            let arm = n.and_then(|ctor| arms.get(&Some(ctor)).map(|tree| (tree, MatchArm::Ctor(ctor))));
+            // If the constructor isn't in the match arms, use the
+            // wildcard arm (represented using None)
+            return match arm.or_else(|| arms.get(&None).map(|tree| (tree, MatchArm::Wildcard))) {
+                Some(arm) => Ok(arm),
+                None => Err(format!("{} has no arm for constructor {}", match_label(arms, symtab), ctor_name)),
             };
         }
 
+        // Accessors are applied to every event, so events without a
+        // constructor to match on (such as register reads when
+        // matching on a memory read kind) just have no value
         *self = Self::default();
-        &ACCESSORTREE_LEAF
+        Ok((&ACCESSORTREE_LEAF, MatchArm::NoArm))
     }
 
     fn access_is_name(&mut self, expected_name: &str) {
@@ -286,7 +435,14 @@ impl<'ev, B: BV> View<'ev, B> {
         }
     }
 
-    fn access_field(&mut self, field: Name, symtab: &Symtab, shared_state: &SharedState<B>) {
+    fn access_field(
+        &mut self,
+        field: Name,
+        symtab: &Symtab,
+        shared_state: &SharedState<B>,
+        types: &HashMap<Sym, Ty>,
+        sexps: &mut SexpArena,
+    ) {
         if let Some(sym) = symtab.get(field) {
             if let Some(AccessorVal::Val(Val::Struct(fields))) = self.value {
                 for (field_name, field_value) in fields {
@@ -296,6 +452,14 @@ impl<'ev, B: BV> View<'ev, B> {
                     }
                 }
             }
+
+            // Fields of packed registers are slices of the register's bitvector
+            let packed = self.register.and_then(|reg| shared_state.register_fields.get(&reg));
+            if let Some(packed_field) = packed.and_then(|fields| fields.iter().find(|f| f.name == sym)) {
+                self.register = None;
+                self.access_subvec(packed_field.hi, packed_field.lo, types, sexps);
+                return;
+            }
         }
         *self = Self::default()
     }
@@ -380,13 +544,21 @@ fn generate_ite_chain<'ev, B: BV>(
     chain
 }
 
-pub fn infer_accessor_type(accessors: &[Accessor], sexps: &mut SexpArena) -> SexpId {
+pub fn infer_accessor_type(accessors: &[Accessor], sexps: &mut SexpArena, symtab: &Symtab) -> SexpId {
     use Accessor::*;
 
     if let Some(accessor) = accessors.iter().next() {
         match accessor {
             Subvec(hi, lo) => sexps.alloc_bitvec((hi - lo) + 1),
             Extz(n) | Exts(n) => sexps.alloc_bitvec(*n),
+            Is(_) => sexps.bool_ty,
+            // These are the attributes added by View::with_mem_kind
+            // and View::with_rmw_id
+            Attr(attr) => match &symtab[*attr] {
+                "acquire" | "release" | "exclusive" | "rmw" => sexps.bool_ty,
+                "size_class" | "rmw_id" => sexps.alloc_bitvec(32),
+                _ => sexps.alloc_bitvec(64),
+            },
             _ => sexps.alloc_bitvec(64),
         }
     } else {
@@ -396,30 +568,102 @@ pub fn infer_accessor_type(accessors: &[Accessor], sexps: &mut SexpArena) -> Sex
 
 fn event_view<'ev, B: BV>(ev: &'ev Event<B>, opcode: B, shared_state: &SharedState<B>) -> Option<View<'ev, B>> {
     match ev {
-        Event::ReadMem { address, value, read_kind, .. } => Some(
+        Event::ReadMem { address, value, read_kind, opts, .. } => Some(
             View::new(opcode)
                 .with_name("sail_mem_read")
                 .with_special("data", value)
                 .with_special("address", address)
+                .with_mem_kind(&opts.kind)
+                .with_rmw_id(opts.rmw_id)
                 .with_value(read_kind),
         ),
-        Event::WriteMem { address, data, write_kind, .. } => Some(
+        Event::WriteMem { address, data, write_kind, opts, .. } => Some(
             View::new(opcode)
                 .with_name("sail_mem_write")
                 .with_special("data", data)
                 .with_special("address", address)
+                .with_mem_kind(&opts.kind)
+                .with_rmw_id(opts.rmw_id)
                 .with_value(write_kind),
         ),
+        Event::AddressAnnounce { address } => Some(
+            View::new(opcode).with_name("sail_address_announce").with_special("address", address).with_value(address),
+        ),
         Event::Abstract { name: outcome_name, primitive, args, return_value } if *primitive => {
             // This will be the original name of the outcome in the Sail source
             let outcome_name = zencode::decode(shared_state.symtab.to_str_demangled(*outcome_name));
             Some(View::new(opcode).with_name(outcome_name).with_values(args).with_special("return", return_value))
         }
-        Event::ReadReg(_, _, value) | Event::WriteReg(_, _, value) => Some(View::new(opcode).with_value(value)),
+        Event::ReadReg(reg, _, value) | Event::WriteReg(reg, _, value) => {
+            Some(View::new(opcode).with_register(*reg).with_value(value))
+        }
         _ => None,
     }
 }
 
+lazy_static! {
+    static ref WARNED_ACCESSORS: Mutex<HashSet<(usize, (usize, usize))>> = Mutex::new(HashSet::new());
+}
+
+/// Warn (once per accessor) if an accessor has the default value for
+/// every event, which usually means a field or constructor name in
+/// the accessor is misspelled.
+fn warn_default_accessor(fn_name: Name, acc_info: &AccessorInfo, failures: &[(Name, String)], symtab: &Symtab) {
+    if !WARNED_ACCESSORS.lock().unwrap().insert((acc_info.file, acc_info.span)) {
+        return;
+    }
+
+    let accessor_name = if acc_info.ty_annot.is_some() { &symtab[fn_name] } else { "inline accessor" };
+    let mut message = format!(
+        "Warning: {} at {} has the default value for every event",
+        accessor_name,
+        format_location(acc_info.file, acc_info.span)
+    );
+
+    let mut attempted: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (ev, step) in failures {
+        attempted.entry(step).or_default().push(&symtab[*ev])
+    }
+    for (step, evs) in attempted {
+        message.push_str(&format!("\n  {} failed for {}", step, evs.join(", ")))
+    }
+    eprintln!("{}", message)
+}
+
+/// A label identifying a match accessor by its arms, e.g. `match { A, B, _ }`.
+fn match_label<'a>(arms: &HashMap<Option<Name>, AccessorTree<'a>>, symtab: &Symtab) -> String {
+    let mut keys: Vec<MatchArm> = arms.keys().map(|key| MatchArm::from_key(*key)).collect();
+    keys.sort();
+    let keys: Vec<&str> = keys.iter().map(|arm| arm.pretty(symtab)).collect();
+    format!("match {{ {} }}", keys.join(", "))
+}
+
+/// Print, for each match in an accessor, how many events took each
+/// arm, and how many events the accessor produced a value for.
+fn log_accessor_coverage<B>(
+    fn_name: Name,
+    acc_info: &AccessorInfo,
+    event_values: &HashMap<Name, Vec<(View<'_, B>, &AccessorTree)>>,
+    match_coverage: &BTreeMap<String, BTreeMap<MatchArm, usize>>,
+    symtab: &Symtab,
+) {
+    let accessor_name = if acc_info.ty_annot.is_some() { &symtab[fn_name] } else { "inline accessor" };
+    let views: Vec<_> = event_values.values().flatten().collect();
+    let with_value = views.iter().filter(|(view, _)| view.value.is_some()).count();
+    let mut message = format!(
+        "accessor coverage for {} at {}: {} of {} events have a non-default value",
+        accessor_name,
+        format_location(acc_info.file, acc_info.span),
+        with_value,
+        views.len()
+    );
+    for (label, counts) in match_coverage {
+        let counts: Vec<String> = counts.iter().map(|(arm, n)| format!("{} {}", arm.pretty(symtab), n)).collect();
+        message.push_str(&format!("\n  {}: {}", label, counts.join(", ")))
+    }
+    log!(log::ACCESSOR, message)
+}
+
 pub fn index_bitwidths<'ev, B: BV, E: ModelEvent<'ev, B>, V: Borrow<E>>(events: &[V]) -> HashMap<Name, u32> {
     let mut max_events = HashMap::new();
 
@@ -442,7 +686,7 @@ pub fn generate_function<'ev, B: BV, E: ModelEvent<'ev, B>, V: Borrow<E>>(
     shared_state: &SharedState<B>,
     symtab: &Symtab,
     sexps: &mut SexpArena,
-) -> SexpId {
+) -> Result<SexpId, Error> {
     use Accessor::*;
 
     let acctree = &AccessorTree::from_accessors(acc_info.accessors);
@@ -466,10 +710,22 @@ pub fn generate_function<'ev, B: BV, E: ModelEvent<'ev, B>, V: Borrow<E>>(
         }
     }
 
-    for views in event_values.values_mut() {
+    // For each event where the accessor failed to apply, the step in
+    // the accessor sequence at which it failed
+    let mut failures: Vec<(Name, String)> = Vec::new();
+
+    // How many events took each arm of each match in the accessor,
+    // only collected when accessor debugging is enabled
+    let mut match_coverage: BTreeMap<String, BTreeMap<MatchArm, usize>> = BTreeMap::new();
+
+    let accessor_error = |message: String| Error::new(message, acc_info.file, acc_info.span);
+
+    for (ev, views) in event_values.iter_mut() {
         for (view, acctree) in views.iter_mut() {
+            let mut failed = false;
             loop {
-                match acctree {
+                let had_value = view.value.is_some() || view.values.is_some();
+                let step = match acctree {
                     AccessorTree::Node { elem, child } => {
                         match *elem {
                             Extz(n) => view.access_extz(*n, types, sexps),
@@ -478,29 +734,55 @@ pub fn generate_function<'ev, B: BV, E: ModelEvent<'ev, B>, V: Borrow<E>>(
                             Tuple(n) => view.access_tuple(*n, shared_state),
                             Bits(_bitvec) => (),
                             Id(id) => view.access_literal_id(*id, sexps),
-                            Field(name) => view.access_field(*name, symtab, shared_state),
+                            Field(name) => view.access_field(*name, symtab, shared_state, types, sexps),
                             Length(_n) => (),
-                            Address => view.access_special("address"),
-                            Data => view.access_special("data"),
-                            Opcode => view.access_special("opcode"),
-                            Return => view.access_special("return"),
+                            Address => view.access_special("address").map_err(&accessor_error)?,
+                            Attr(attr) => view.access_special(&symtab[*attr]).map_err(&accessor_error)?,
+                            Data => view.access_special("data").map_err(&accessor_error)?,
+                            Opcode => view.access_special("opcode").map_err(&accessor_error)?,
+                            Return => view.access_special("return").map_err(&accessor_error)?,
                             Is(expected) => view.access_is_name(&symtab[*expected]),
 
                             // Should not occur as an accessortree node
                             Ctor(_) | Wildcard | Match(_) => unreachable!(),
                         }
-                        *acctree = child
+                        let step = *elem;
+                        *acctree = child;
+                        Some(step)
                     }
                     AccessorTree::Match { arms } => {
-                        let child = view.access_match(arms, symtab, shared_state);
-                        *acctree = child
+                        let (child, arm) = view.access_match(arms, symtab, shared_state).map_err(&accessor_error)?;
+                        if_logging!(log::ACCESSOR, {
+                            let counts = match_coverage.entry(match_label(arms, symtab)).or_insert_with(|| {
+                                arms.keys()
+                                    .map(|key| (MatchArm::from_key(*key), 0))
+                                    .chain([(MatchArm::NoArm, 0)])
+                                    .collect()
+                            });
+                            *counts.entry(arm).or_insert(0) += 1
+                        });
+                        *acctree = child;
+                        None
                     }
                     AccessorTree::Leaf => break,
+                };
+                // An explicit default is not a failure
+                let explicit_default = matches!(step, Some(Id(id)) if *id == DEFAULT.name());
+                if !failed && had_value && !explicit_default && view.value.is_none() && view.values.is_none() {
+                    let step = step.map(|acc| acc.pretty(symtab)).unwrap_or_else(|| ".match".to_string());
+                    failures.push((*ev, step));
+                    failed = true
                 }
             }
         }
     }
 
+    if !event_values.is_empty() && event_values.values().flatten().all(|(view, _)| view.value.is_none()) {
+        warn_default_accessor(fn_name, &acc_info, &failures, symtab)
+    }
+
+    if_logging!(log::ACCESSOR, { log_accessor_coverage(fn_name, &acc_info, &event_values, &match_coverage, symtab) });
+
     let index_bits = acc_info.index_set.and_then(|ix| index_bitwidths(events).get(&ix).copied());
 
     let mut accessor_params = vec![sexps.alloc(Sexp::List(vec![sexps.ev1, sexps.event]))];
@@ -511,10 +793,112 @@ pub fn generate_function<'ev, B: BV, E: ModelEvent<'ev, B>, V: Borrow<E>>(
     let accessor_params = sexps.alloc(Sexp::List(accessor_params));
     let accessor_ty = match acc_info.ty_annot {
         Some(ty) => ty,
-        None => infer_accessor_type(acc_info.accessors, sexps),
+        None => infer_accessor_type(acc_info.accessors, sexps, symtab),
     };
+
+    // Every value in the ite chain must have the type of the
+    // accessor, otherwise the generated SMT is ill-sorted
+    for (ev, views) in &event_values {
+        for (view, _) in views {
+            let Some(value_ty) = view.value.and_then(|v| v.infer_type(types, sexps)) else {
+                continue
+            };
+            if !compatible_types(value_ty, accessor_ty, sexps) {
+                let accessor_name = if acc_info.ty_annot.is_some() { &symtab[fn_name] } else { "inline accessor" };
+                return Err(Error::new(
+                    format!(
+                        "{} has type {}, but produces a value of type {} for event {}",
+                        accessor_name,
+                        sexps.format_sexp(accessor_ty, symtab),
+                        sexps.format_sexp(value_ty, symtab),
+                        &symtab[*ev]
+                    ),
+                    acc_info.file,
+                    acc_info.span,
+                ));
+            }
+        }
+    }
+
     let accessor_ite = generate_ite_chain(&event_values, accessor_ty, index_bits, sexps);
 
     let accessor_fn = sexps.alloc(Sexp::Atom(fn_name));
-    sexps.alloc(Sexp::List(vec![sexps.define_fun, accessor_fn, accessor_params, accessor_ty, accessor_ite]))
+    Ok(sexps.alloc(Sexp::List(vec![sexps.define_fun, accessor_fn, accessor_params, accessor_ty, accessor_ite])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use isla_lib::bitvector::b64::B64;
+
+    #[test]
+    fn attribute_types() {
+        let mut symtab = Symtab::new();
+        let mut sexps = SexpArena::new();
+        let kind = MemKind { acquire: true, release: true, exclusive: true, rmw: true, size_class: Some(4) };
+        let view: View<B64> = View::new(B64::zeros(32)).with_mem_kind(&kind).with_rmw_id(Some(1));
+
+        for (attr, width) in [
+            ("acquire", None),
+            ("release", None),
+            ("exclusive", None),
+            ("rmw", None),
+            ("size_class", Some(32)),
+            ("rmw_id", Some(32)),
+        ] {
+            let accessors = [Accessor::Attr(symtab.intern(attr))];
+            let ty = infer_accessor_type(&accessors, &mut sexps, &symtab);
+            match width {
+                Some(n) => assert!(matches!(sexps[ty], Sexp::BitVec(BitWidth::Fixed(m)) if m == n), ".{}()", attr),
+                None => assert_eq!(ty, sexps.bool_ty, ".{}()", attr),
+            }
+
+            // The inferred type must match the value the view produces
+            let value = view.special[attr].infer_type(&HashMap::new(), &mut sexps).unwrap();
+            assert!(compatible_types(ty, value, &sexps), "value of .{}()", attr)
+        }
+    }
+
+    #[test]
+    fn unknown_attribute() {
+        let mut view: View<B64> = View::new(B64::zeros(32));
+        assert!(view.access_special("aquire").is_err());
+
+        // A known attribute that the event does not have just has no value
+        let mut view: View<B64> = View::new(B64::zeros(32));
+        assert!(view.access_special("acquire").is_ok());
+        assert!(view.value.is_none())
+    }
+
+    #[test]
+    fn match_without_wildcard() {
+        let mut ir_symtab = isla_lib::ir::Symtab::new();
+        let plain = ir_symtab.intern("zPlain");
+        let acquire = ir_symtab.intern("zAcquire");
+        let shared_state: SharedState<B64> =
+            SharedState::new(ir_symtab, &[], HashSet::new(), HashSet::new(), Vec::new(), Vec::new(), Vec::new());
+
+        let mut symtab = Symtab::new();
+        let mut arms = HashMap::new();
+        arms.insert(Some(symtab.intern("Plain")), AccessorTree::Leaf);
+
+        let plain = Val::Ctor(plain, Box::new(Val::Unit));
+        let acquire = Val::Ctor(acquire, Box::new(Val::Unit));
+        let bits = Val::Bits(B64::zeros(8));
+
+        let mut view: View<B64> = View::default().with_value(&plain);
+        assert!(matches!(view.access_match(&arms, &symtab, &shared_state), Ok((_, MatchArm::Ctor(_)))));
+
+        let mut view: View<B64> = View::default().with_value(&acquire);
+        assert!(view.access_match(&arms, &symtab, &shared_state).is_err());
+
+        arms.insert(None, AccessorTree::Leaf);
+        let mut view: View<B64> = View::default().with_value(&acquire);
+        assert!(matches!(view.access_match(&arms, &symtab, &shared_state), Ok((_, MatchArm::Wildcard))));
+
+        // Values that are not constructors have no value, rather than being an error
+        let mut view: View<B64> = View::default().with_value(&bits);
+        assert!(matches!(view.access_match(&arms, &symtab, &shared_state), Ok((_, MatchArm::NoArm))));
+        assert!(view.value.is_none())
+    }
 }
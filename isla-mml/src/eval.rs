@@ -0,0 +1,696 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2022 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements direct evaluation of a memory model over a
+//! fully-concrete candidate execution, i.e. one where the set of
+//! events is fixed and every base set, relation, and accessor has a
+//! known value for each event. The relation algebra is interpreted
+//! over the finite event set, without invoking the SMT solver.
+//!
+//! The evaluation follows the same structure as the SMT compilation
+//! in [crate::smt]: an expression is evaluated pointwise for a tuple
+//! of events, so `x` evaluated at `[ev1, ev2]` is true if and only if
+//! `(ev1, ev2)` is in the relation `x`. If the model refers to
+//! anything that has no concrete interpretation in the execution,
+//! evaluation fails with [EvalError::Unknown], and the caller should
+//! fall back to the SMT encoding.
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+
+use crate::memory_model::constants::*;
+use crate::memory_model::{
+    Binary, Check, Def, Error, Exp, ExpArena, ExpId, MemoryModel, MemoryModelEnums, Name, Spanned, Symtab, TyAnnot,
+    Unary,
+};
+
+/// A concrete value in a candidate execution
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Value {
+    Bool(bool),
+    /// An event, identified by its index in the execution
+    Event(usize),
+    /// A bitvector, least significant bit first
+    Bits(Vec<bool>),
+    /// A member of an enumeration, e.g. `Enum(1, 3)` for the second
+    /// element of a 3-element enumeration
+    Enum(usize, usize),
+}
+
+/// A fully-concrete candidate execution, providing the values of
+/// the base sets, relations, and accessors used by a memory model.
+pub struct ConcreteExecution {
+    num_events: usize,
+    sets: HashMap<Name, HashSet<usize>>,
+    relations: HashMap<Name, HashSet<(usize, usize)>>,
+    accessors: HashMap<Name, Vec<Value>>,
+}
+
+impl ConcreteExecution {
+    pub fn new(num_events: usize) -> Self {
+        ConcreteExecution { num_events, sets: HashMap::new(), relations: HashMap::new(), accessors: HashMap::new() }
+    }
+
+    pub fn num_events(&self) -> usize {
+        self.num_events
+    }
+
+    pub fn add_set<I: IntoIterator<Item = usize>>(&mut self, name: Name, events: I) {
+        self.sets.insert(name, events.into_iter().collect());
+    }
+
+    pub fn add_relation<I: IntoIterator<Item = (usize, usize)>>(&mut self, name: Name, pairs: I) {
+        self.relations.insert(name, pairs.into_iter().collect());
+    }
+
+    /// Add the values of an accessor (named or inline, see
+    /// [Symtab::encode_accessors]) for each event in the execution
+    pub fn add_accessor(&mut self, name: Name, values: Vec<Value>) {
+        assert!(values.len() == self.num_events);
+        self.accessors.insert(name, values);
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    /// The model refers to a name with no concrete interpretation
+    Unknown(String),
+    /// The model uses a construct the evaluator does not support
+    Unsupported(Error),
+    /// The model is ill-typed
+    Type(Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EvalError::*;
+        match self {
+            Unknown(name) => write!(f, "No concrete interpretation for {}", name),
+            Unsupported(err) => write!(f, "Unsupported in concrete evaluation: {}", err.message),
+            Type(err) => write!(f, "Type error in concrete evaluation: {}", err.message),
+        }
+    }
+}
+
+impl error::Error for EvalError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+fn type_error<T>(exp: &Spanned<Exp>, message: &str) -> Result<T, EvalError> {
+    Err(EvalError::Type(Error::new(message, exp.file, exp.span)))
+}
+
+fn unsupported<T>(exp: &Spanned<Exp>, message: &str) -> Result<T, EvalError> {
+    Err(EvalError::Unsupported(Error::new(message, exp.file, exp.span)))
+}
+
+/// The result of evaluating a memory model over a candidate execution
+#[derive(Debug, Default)]
+pub struct EvalResult {
+    /// The names of any checks that do not hold
+    pub failed_checks: Vec<Name>,
+    /// The number of top-level assertions that do not hold
+    pub failed_assertions: usize,
+    /// The value of each flag in the model
+    pub flags: Vec<(Name, bool)>,
+}
+
+impl EvalResult {
+    /// Returns true if the candidate execution is allowed by the model
+    pub fn is_allowed(&self) -> bool {
+        self.failed_checks.is_empty() && self.failed_assertions == 0
+    }
+}
+
+type Env = Vec<(Name, Value)>;
+
+struct Definition {
+    params: Vec<Name>,
+    // The number of additional event arguments, i.e. 2 for a
+    // relation defined by an un-annotated let
+    extra_events: usize,
+    body: ExpId,
+}
+
+struct Evaluator<'a> {
+    exps: &'a ExpArena,
+    symtab: &'a mut Symtab,
+    exec: &'a ConcreteExecution,
+    enums: MemoryModelEnums,
+    definitions: HashMap<Name, Definition>,
+    memo: HashMap<(Name, Vec<Value>), Value>,
+    closures: HashMap<(ExpId, bool), Vec<bool>>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn events(&self) -> impl Iterator<Item = Value> {
+        (0..self.exec.num_events).map(Value::Event)
+    }
+
+    fn define(&mut self, f: Name, definition: Definition) {
+        self.memo.retain(|(g, _), _| *g != f);
+        self.definitions.insert(f, definition);
+    }
+
+    fn domain(&self, ty: &TyAnnot) -> Result<Vec<Value>, EvalError> {
+        let Some(ty) = ty else { return Ok(self.events().collect()) };
+        let ty = &self.exps[*ty];
+        match &ty.node {
+            Exp::Id(id) if *id == EVENT.name() => Ok(self.events().collect()),
+            Exp::Id(id) if *id == BOOL.name() => Ok(vec![Value::Bool(false), Value::Bool(true)]),
+            Exp::Id(id) => {
+                if let Some(members) = self.enums.enum_ids.get(id).copied() {
+                    Ok((0..members).map(|n| Value::Enum(n, members)).collect())
+                } else {
+                    type_error(ty, "No enum with this name")
+                }
+            }
+            _ => unsupported(ty, "can only quantify over events, booleans, and enumerations"),
+        }
+    }
+
+    fn eval_bool(&mut self, exp: ExpId, evs: &[Value], env: &Env) -> Result<bool, EvalError> {
+        match self.eval(exp, evs, env)? {
+            Value::Bool(b) => Ok(b),
+            _ => type_error(&self.exps[exp], "Expected a boolean"),
+        }
+    }
+
+    fn eval_quantifier(
+        &mut self,
+        universal: bool,
+        args: &[(Name, TyAnnot)],
+        env: &Env,
+        body: &mut dyn FnMut(&mut Self, &Env) -> Result<bool, EvalError>,
+    ) -> Result<bool, EvalError> {
+        let Some(((v, ty), args)) = args.split_first() else { return body(self, env) };
+        for value in self.domain(ty)? {
+            let mut env = env.clone();
+            env.push((*v, value));
+            if self.eval_quantifier(universal, args, &env, body)? != universal {
+                return Ok(!universal);
+            }
+        }
+        Ok(universal)
+    }
+
+    // Compute the (reflexive) transitive closure of a binary relation
+    // as an adjacency matrix. Like the SMT compilation, this assumes
+    // the relation does not refer to any local variables.
+    fn closure(&mut self, x: ExpId, reflexive: bool, env: &Env) -> Result<&[bool], EvalError> {
+        let n = self.exec.num_events;
+        if !self.closures.contains_key(&(x, reflexive)) {
+            let mut matrix = vec![false; n * n];
+            for i in 0..n {
+                for j in 0..n {
+                    matrix[i * n + j] =
+                        (reflexive && i == j) || self.eval_bool(x, &[Value::Event(i), Value::Event(j)], env)?
+                }
+            }
+            for k in 0..n {
+                for i in 0..n {
+                    if matrix[i * n + k] {
+                        for j in 0..n {
+                            if matrix[k * n + j] {
+                                matrix[i * n + j] = true
+                            }
+                        }
+                    }
+                }
+            }
+            self.closures.insert((x, reflexive), matrix);
+        }
+        Ok(&self.closures[&(x, reflexive)])
+    }
+
+    fn apply(&mut self, exp: &Spanned<Exp>, f: Name, args: Vec<Value>) -> Result<Value, EvalError> {
+        if let Some(definition) = self.definitions.get(&f) {
+            if args.len() != definition.params.len() + definition.extra_events {
+                return type_error(exp, "Incorrect number of arguments");
+            }
+
+            let key = (f, args);
+            if let Some(value) = self.memo.get(&key) {
+                return Ok(value.clone());
+            }
+            let (f, args) = key;
+
+            let env: Env = definition.params.iter().copied().zip(args.iter().cloned()).collect();
+            let body = definition.body;
+            let evs = args[definition.params.len()..].to_vec();
+            let value = self.eval(body, &evs, &env)?;
+            self.memo.insert((f, args), value.clone());
+            return Ok(value);
+        }
+
+        let exec = self.exec;
+        if let Some(set) = exec.sets.get(&f) {
+            match args.as_slice() {
+                [Value::Event(ev)] => Ok(Value::Bool(set.contains(ev))),
+                _ => type_error(exp, "Set used in a context where a set was not expected"),
+            }
+        } else if let Some(rel) = exec.relations.get(&f) {
+            match args.as_slice() {
+                [Value::Event(ev1), Value::Event(ev2)] => Ok(Value::Bool(rel.contains(&(*ev1, *ev2)))),
+                _ => type_error(exp, "Relation used in a context where a binary relation was not expected"),
+            }
+        } else if let Some(values) = exec.accessors.get(&f) {
+            match args.as_slice() {
+                [Value::Event(ev)] => Ok(values[*ev].clone()),
+                _ => type_error(exp, "Accessor must be applied to a single event"),
+            }
+        } else {
+            Err(EvalError::Unknown(self.symtab[f].to_string()))
+        }
+    }
+
+    fn eval(&mut self, exp_id: ExpId, evs: &[Value], env: &Env) -> Result<Value, EvalError> {
+        let exps = self.exps;
+        let exp = &exps[exp_id];
+
+        let value = match &exp.node {
+            Exp::Empty => false,
+
+            Exp::Int(_) => return type_error(exp, "unexpected integer"),
+
+            Exp::App(f, args) if *f == DOMAIN.name() => match args.as_slice() {
+                [Some(arg)] => {
+                    let mut found = false;
+                    for range_ev in self.events() {
+                        let mut evs_with_range = evs.to_vec();
+                        evs_with_range.push(range_ev);
+                        if self.eval_bool(*arg, &evs_with_range, env)? {
+                            found = true;
+                            break;
+                        }
+                    }
+                    found
+                }
+                _ => return type_error(exp, "domain expects a single argument"),
+            },
+
+            Exp::App(f, args) if *f == RANGE.name() => match args.as_slice() {
+                [Some(arg)] => {
+                    let mut found = false;
+                    for domain_ev in self.events() {
+                        let mut evs_with_domain = vec![domain_ev];
+                        evs_with_domain.extend_from_slice(evs);
+                        if self.eval_bool(*arg, &evs_with_domain, env)? {
+                            found = true;
+                            break;
+                        }
+                    }
+                    found
+                }
+                _ => return type_error(exp, "range expects a single argument"),
+            },
+
+            Exp::App(f, args) if *f == EXTRACT.name() => match args.as_slice() {
+                [Some(hi), Some(lo), Some(arg)] => match (&exps[*hi].node, &exps[*lo].node) {
+                    (Exp::Int(hi), Exp::Int(lo)) if lo <= hi => match self.eval(*arg, &[], env)? {
+                        Value::Bits(bv) if (*hi as usize) < bv.len() => {
+                            return Ok(Value::Bits(bv[*lo as usize..=*hi as usize].to_vec()))
+                        }
+                        _ => return type_error(exp, "extract applied to a bitvector that is too short"),
+                    },
+                    _ => return type_error(exp, "extract must have integer literals as the first two arguments"),
+                },
+                _ => return type_error(exp, "extract expects a three arguments"),
+            },
+
+            Exp::App(f, args) => {
+                let mut wildcards = 0;
+                let mut values = Vec::new();
+                for arg in args {
+                    if let Some(arg) = arg {
+                        values.push(self.eval(*arg, &[], env)?)
+                    } else if let Some(ev) = evs.get(wildcards) {
+                        values.push(ev.clone());
+                        wildcards += 1
+                    } else {
+                        return type_error(exp, "Incorrect number of wildcards in function call");
+                    }
+                }
+                if wildcards != 0 && wildcards != evs.len() {
+                    return type_error(exp, "Incorrect number of wildcards in function call");
+                }
+                values.extend_from_slice(&evs[wildcards..]);
+                return self.apply(exp, *f, values);
+            }
+
+            Exp::Bits(bv) => return Ok(Value::Bits(bv.clone())),
+
+            Exp::Id(f) => {
+                if let Some((x, y)) = self.enums.enum_members.get(f).copied() {
+                    return Ok(Value::Enum(x, y));
+                } else if evs.is_empty() {
+                    if let Some((_, value)) = env.iter().rev().find(|(v, _)| v == f) {
+                        return Ok(value.clone());
+                    }
+                }
+                return self.apply(exp, *f, evs.to_vec());
+            }
+
+            Exp::Cartesian(x, y) => match evs {
+                [ev1, ev2] => {
+                    let x = match x {
+                        Some(x) => self.eval_bool(*x, &[ev1.clone()], env)?,
+                        None => true,
+                    };
+                    x && match y {
+                        Some(y) => self.eval_bool(*y, &[ev2.clone()], env)?,
+                        None => true,
+                    }
+                }
+                _ => return type_error(exp, "Cartesian product in a context where a binary relation was not expected"),
+            },
+
+            Exp::Binary(Binary::Diff, x, y) => self.eval_bool(*x, evs, env)? && !self.eval_bool(*y, evs, env)?,
+
+            Exp::Binary(Binary::Union, x, y) => self.eval_bool(*x, evs, env)? || self.eval_bool(*y, evs, env)?,
+
+            Exp::Binary(Binary::Inter, x, y) => self.eval_bool(*x, evs, env)? && self.eval_bool(*y, evs, env)?,
+
+            Exp::Binary(Binary::Implies, x, y) => !self.eval_bool(*x, evs, env)? || self.eval_bool(*y, evs, env)?,
+
+            Exp::Binary(Binary::In, x, set) => {
+                if !evs.is_empty() {
+                    return type_error(exp, "Boolean set membership in a context where a set or relation was expected");
+                }
+                let mut args = Vec::new();
+                match &exps[*x].node {
+                    Exp::Tuple(xs) => {
+                        for x in xs {
+                            args.push(self.eval(*x, &[], env)?)
+                        }
+                    }
+                    _ => args.push(self.eval(*x, &[], env)?),
+                }
+                self.eval_bool(*set, &args, env)?
+            }
+
+            Exp::Binary(Binary::Seq, x, y) => match evs {
+                [ev1, ev2] => {
+                    let mut found = false;
+                    for ev3 in self.events() {
+                        if self.eval_bool(*x, &[ev1.clone(), ev3.clone()], env)?
+                            && self.eval_bool(*y, &[ev3, ev2.clone()], env)?
+                        {
+                            found = true;
+                            break;
+                        }
+                    }
+                    found
+                }
+                _ => {
+                    return type_error(
+                        exp,
+                        "Sequential composition in a context where a binary relation was not expected",
+                    )
+                }
+            },
+
+            Exp::Binary(Binary::Eq, x, y) => self.eval(*x, evs, env)? == self.eval(*y, evs, env)?,
+
+            Exp::Binary(Binary::Neq, x, y) => self.eval(*x, evs, env)? != self.eval(*y, evs, env)?,
+
+            Exp::Unary(Unary::Compl, x) => !self.eval_bool(*x, evs, env)?,
+
+            Exp::Unary(Unary::Identity, x) => match evs {
+                [ev1, ev2] => {
+                    ev1 == ev2 && self.eval_bool(*x, &[ev1.clone()], env)? && self.eval_bool(*x, &[ev2.clone()], env)?
+                }
+                _ => return type_error(exp, "Identity in a context where a binary relation was not expected"),
+            },
+
+            Exp::Unary(Unary::IdentityUnion, x) => match evs {
+                [ev1, ev2] => ev1 == ev2 || self.eval_bool(*x, evs, env)?,
+                _ => {
+                    return type_error(exp, "Union with identity in a context where a binary relation was not expected")
+                }
+            },
+
+            Exp::Unary(Unary::Inverse, x) => match evs {
+                [ev1, ev2] => self.eval_bool(*x, &[ev2.clone(), ev1.clone()], env)?,
+                _ => return type_error(exp, "Inverse in a context where a binary relation was not expected"),
+            },
+
+            Exp::Unary(closure_op @ (Unary::TClosure | Unary::RTClosure), x) => match evs {
+                [Value::Event(i), Value::Event(j)] => {
+                    let n = self.exec.num_events;
+                    let matrix = self.closure(*x, matches!(closure_op, Unary::RTClosure), env)?;
+                    matrix[i * n + j]
+                }
+                _ => return type_error(exp, "Closure operator in a context where a binary relation was not expected"),
+            },
+
+            Exp::Set(v, _, body) => match evs {
+                [ev1] => {
+                    let mut env = env.clone();
+                    env.push((*v, ev1.clone()));
+                    return self.eval(*body, &[], &env);
+                }
+                _ => return type_error(exp, "Explicit set in a context where a set was not expected"),
+            },
+
+            Exp::Relation(v1, _, v2, _, body) => match evs {
+                [ev1, ev2] => {
+                    let mut env = env.clone();
+                    env.push((*v1, ev1.clone()));
+                    env.push((*v2, ev2.clone()));
+                    return self.eval(*body, &[], &env);
+                }
+                _ => return type_error(exp, "Explicit relation in a context where a binary relation was not expected"),
+            },
+
+            Exp::Forall(args, body) | Exp::Exists(args, body) => {
+                if !evs.is_empty() {
+                    return type_error(exp, "Quantifier in a context where a set or relation was expected");
+                }
+                let universal = matches!(exp.node, Exp::Forall(..));
+                self.eval_quantifier(universal, args, env, &mut |eval, env| eval.eval_bool(*body, &[], env))?
+            }
+
+            Exp::WhereForall(rel, args, cond) | Exp::WhereExists(rel, args, cond) => {
+                let universal = matches!(exp.node, Exp::WhereForall(..));
+                self.eval_quantifier(universal, args, env, &mut |eval, env| {
+                    Ok(eval.eval_bool(*cond, &[], env)? && eval.eval_bool(*rel, evs, env)?)
+                })?
+            }
+
+            Exp::SetLiteral(elements) => {
+                let mut found = false;
+                for element in elements {
+                    let is_member = match &exps[*element].node {
+                        Exp::Tuple(xs) if xs.len() == evs.len() => {
+                            let mut is_member = true;
+                            for (x, ev) in xs.iter().zip(evs.iter()) {
+                                is_member &= self.eval(*x, &[], env)? == *ev
+                            }
+                            is_member
+                        }
+                        _ if evs.len() == 1 => self.eval(*element, &[], env)? == evs[0],
+                        _ => return type_error(&exps[*element], "Set literal element has the wrong length"),
+                    };
+                    if is_member {
+                        found = true;
+                        break;
+                    }
+                }
+                found
+            }
+
+            Exp::Accessor(x, accs) => {
+                let ev = self.eval(*x, &[], env)?;
+                let f = self.symtab.encode_accessors(accs);
+                return self.apply(exp, f, vec![ev]);
+            }
+
+            Exp::IndexedAccessor(..) => return unsupported(exp, "indexed accessors"),
+
+            Exp::Tuple(_) => return type_error(exp, "Unexpected tuple"),
+        };
+
+        Ok(Value::Bool(value))
+    }
+
+    fn eval_check(&mut self, check: Check, exp: ExpId) -> Result<bool, EvalError> {
+        let env = Vec::new();
+        let n = self.exec.num_events;
+        Ok(match check {
+            Check::Empty | Check::NonEmpty => {
+                let mut empty = true;
+                'outer: for i in 0..n {
+                    for j in 0..n {
+                        if self.eval_bool(exp, &[Value::Event(i), Value::Event(j)], &env)? {
+                            empty = false;
+                            break 'outer;
+                        }
+                    }
+                }
+                empty == matches!(check, Check::Empty)
+            }
+
+            Check::Irreflexive | Check::NonIrreflexive => {
+                let mut irreflexive = true;
+                for i in 0..n {
+                    if self.eval_bool(exp, &[Value::Event(i), Value::Event(i)], &env)? {
+                        irreflexive = false;
+                        break;
+                    }
+                }
+                irreflexive == matches!(check, Check::Irreflexive)
+            }
+
+            Check::Acyclic | Check::NonAcyclic => {
+                let matrix = self.closure(exp, false, &env)?;
+                let acyclic = (0..n).all(|i| !matrix[i * n + i]);
+                acyclic == matches!(check, Check::Acyclic)
+            }
+        })
+    }
+}
+
+/// Evaluate a memory model over a concrete candidate execution. The
+/// model should have had any includes resolved.
+pub fn evaluate_memory_model(
+    mm: &MemoryModel,
+    exps: &ExpArena,
+    symtab: &mut Symtab,
+    exec: &ConcreteExecution,
+) -> Result<EvalResult, EvalError> {
+    let mut evaluator = Evaluator {
+        exps,
+        symtab,
+        exec,
+        enums: mm.enums(),
+        definitions: HashMap::new(),
+        memo: HashMap::new(),
+        closures: HashMap::new(),
+    };
+    let mut result = EvalResult::default();
+
+    for def in &mm.defs {
+        match &def.node {
+            Def::Let(f, params, annot, body) => {
+                let definition = Definition {
+                    params: params.iter().map(|(n, _)| *n).collect(),
+                    extra_events: if annot.is_none() { 2 } else { 0 },
+                    body: *body,
+                };
+                evaluator.define(*f, definition)
+            }
+
+            Def::Define(f, params, _, body) => {
+                let definition =
+                    Definition { params: params.iter().map(|(n, _)| *n).collect(), extra_events: 0, body: *body };
+                evaluator.define(*f, definition)
+            }
+
+            Def::Assert(exp) => {
+                if !evaluator.eval_bool(*exp, &[], &Vec::new())? {
+                    result.failed_assertions += 1
+                }
+            }
+
+            Def::Check(check, exp, as_name) => {
+                if !evaluator.eval_check(*check, *exp)? {
+                    result.failed_checks.push(*as_name)
+                }
+            }
+
+            Def::Flag(check, exp, as_name) => {
+                let flag = evaluator.eval_check(*check, *exp)?;
+                result.flags.push((*as_name, flag))
+            }
+
+            Def::Include(_) => panic!("include statement should be removed before evaluation"),
+
+            // Declarations, accessors, and sets/relations are
+            // provided by the concrete execution
+            Def::Declare(..)
+            | Def::Accessor(..)
+            | Def::AccessorSet(..)
+            | Def::IndexedAccessor(..)
+            | Def::Relation(..)
+            | Def::Show(_)
+            | Def::Enum(..)
+            | Def::Index(_) => (),
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SC: &str = "relation po\nrelation rf\nrelation co\nrelation loc\n\
+                       let fr = (rf^-1; co) & loc\n\
+                       acyclic po | rf | co | fr as sc";
+
+    // Store buffering, with events W x, R y, W y, R x, and IW
+    fn store_buffering(symtab: &mut Symtab, rf: &[(usize, usize)]) -> ConcreteExecution {
+        let mut exec = ConcreteExecution::new(5);
+        exec.add_relation(symtab.intern("po"), [(0, 1), (2, 3)]);
+        exec.add_relation(symtab.intern("co"), [(4, 0), (4, 2)]);
+        exec.add_relation(symtab.intern("loc"), [(0, 3), (3, 0), (1, 2), (2, 1)]);
+        exec.add_relation(symtab.intern("rf"), rf.iter().copied());
+        exec
+    }
+
+    fn sc_allows(rf: &[(usize, usize)]) -> bool {
+        let mut arena = ExpArena::new();
+        let mut symtab = Symtab::new();
+        let mm = MemoryModel::from_string("sc.cat", 0, SC, &mut arena, &mut symtab).unwrap();
+        let exec = store_buffering(&mut symtab, rf);
+        evaluate_memory_model(&mm, &arena, &mut symtab, &exec).unwrap().is_allowed()
+    }
+
+    #[test]
+    fn store_buffering_sc() {
+        assert!(!sc_allows(&[(4, 1), (4, 3)]));
+        assert!(sc_allows(&[(2, 1), (4, 3)]));
+        assert!(sc_allows(&[(2, 1), (0, 3)]));
+    }
+
+    #[test]
+    fn unknown_name() {
+        let mut arena = ExpArena::new();
+        let mut symtab = Symtab::new();
+        let mm =
+            MemoryModel::from_string("test.cat", 0, "let a = foo\nacyclic a as x", &mut arena, &mut symtab).unwrap();
+        let exec = ConcreteExecution::new(1);
+        assert!(matches!(evaluate_memory_model(&mm, &arena, &mut symtab, &exec), Err(EvalError::Unknown(_))))
+    }
+}
@@ -38,6 +38,7 @@ lalrpop_mod!(
 );
 
 pub mod accessor;
+pub mod eval;
 mod lexer;
 pub mod memory_model;
 pub mod smt;
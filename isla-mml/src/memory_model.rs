@@ -36,7 +36,7 @@ use id_arena::{Arena, Id};
 use lalrpop_util::ParseError;
 
 use std::borrow::Cow;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -291,6 +291,10 @@ impl Symtab {
                 Accessor::Tuple(n) => write!(&mut encoding, "t{}", n).unwrap(),
                 Accessor::Length(n) => write!(&mut encoding, "n{}", n).unwrap(),
                 Accessor::Address => write!(&mut encoding, "a").unwrap(),
+                Accessor::Attr(id) => {
+                    write!(&mut encoding, "k{}", zencode::encode(&self[*id])).unwrap();
+                    need_sep = true
+                }
                 Accessor::Data => write!(&mut encoding, "d").unwrap(),
                 Accessor::Opcode => write!(&mut encoding, "o").unwrap(),
                 Accessor::Return => write!(&mut encoding, "r").unwrap(),
@@ -315,6 +319,27 @@ pub struct Error {
     pub message: String,
     pub file: usize,
     pub span: (usize, usize),
+    /// Additional locations relevant to the error, e.g. where a name
+    /// it refers to was defined
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug)]
+pub struct Note {
+    pub message: String,
+    pub file: usize,
+    pub span: (usize, usize),
+}
+
+impl Error {
+    pub fn new<S: Into<String>>(message: S, file: usize, span: (usize, usize)) -> Self {
+        Error { message: message.into(), file, span, notes: Vec::new() }
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, message: S, file: usize, span: (usize, usize)) -> Self {
+        self.notes.push(Note { message: message.into(), file, span });
+        self
+    }
 }
 
 /// Convert a span (which is a character offset pair) into a Isla source location
@@ -438,9 +463,14 @@ pub struct AccessorInfo<'a> {
     pub ty_annot: Option<SexpId>,
     /// A reference to the accessor sequence
     pub accessors: &'a [Accessor],
+    /// The file containing the accessor, for error reporting
+    pub file: usize,
+    /// The span of the accessor definition (or the expression
+    /// containing the accessor if it is inline)
+    pub span: (usize, usize),
 }
 
-impl Exp {
+impl Spanned<Exp> {
     fn add_accessors<'a>(
         &'a self,
         collection: &mut HashMap<Name, AccessorInfo<'a>>,
@@ -448,37 +478,104 @@ impl Exp {
         symtab: &mut Symtab,
     ) {
         use Exp::*;
-        match self {
+        match &self.node {
             Accessor(exp, accessors) => {
-                exps[*exp].node.add_accessors(collection, exps, symtab);
+                exps[*exp].add_accessors(collection, exps, symtab);
                 let name = symtab.encode_accessors(accessors);
-                collection.insert(name, AccessorInfo { index_set: None, ty_annot: None, accessors });
+                collection.insert(
+                    name,
+                    AccessorInfo { index_set: None, ty_annot: None, accessors, file: self.file, span: self.span },
+                );
             }
             Tuple(xs) => {
                 for x in xs {
-                    exps[*x].node.add_accessors(collection, exps, symtab)
+                    exps[*x].add_accessors(collection, exps, symtab)
                 }
             }
             App(_, args) => {
                 for arg in args.iter().flatten() {
-                    exps[*arg].node.add_accessors(collection, exps, symtab)
+                    exps[*arg].add_accessors(collection, exps, symtab)
                 }
             }
-            Unary(_, exp) => exps[*exp].node.add_accessors(collection, exps, symtab),
+            Unary(_, exp) => exps[*exp].add_accessors(collection, exps, symtab),
             Binary(_, lhs, rhs) => {
-                exps[*lhs].node.add_accessors(collection, exps, symtab);
-                exps[*rhs].node.add_accessors(collection, exps, symtab)
+                exps[*lhs].add_accessors(collection, exps, symtab);
+                exps[*rhs].add_accessors(collection, exps, symtab)
             }
             Set(_, _, exp) => {
-                exps[*exp].node.add_accessors(collection, exps, symtab);
+                exps[*exp].add_accessors(collection, exps, symtab);
             }
-            Relation(_, _, _, _, exp) => exps[*exp].node.add_accessors(collection, exps, symtab),
-            Forall(_, exp) | Exists(_, exp) => exps[*exp].node.add_accessors(collection, exps, symtab),
+            Relation(_, _, _, _, exp) => exps[*exp].add_accessors(collection, exps, symtab),
+            Forall(_, exp) | Exists(_, exp) => exps[*exp].add_accessors(collection, exps, symtab),
             _ => (),
         }
     }
 }
 
+/// Collect every use of a name in an expression which is not bound
+/// by an enclosing binder (such as a quantifier), along with the
+/// location of the use.
+fn free_names(exp: ExpId, exps: &ExpArena, bound: &mut Vec<Name>, uses: &mut Vec<(Name, usize, (usize, usize))>) {
+    use Exp::*;
+
+    let exp = &exps[exp];
+    match &exp.node {
+        Id(name) => {
+            if !bound.contains(name) {
+                uses.push((*name, exp.file, exp.span))
+            }
+        }
+        App(f, args) => {
+            if !bound.contains(f) {
+                uses.push((*f, exp.file, exp.span))
+            }
+            for arg in args.iter().flatten() {
+                free_names(*arg, exps, bound, uses)
+            }
+        }
+        Accessor(x, _) | Unary(_, x) => free_names(*x, exps, bound, uses),
+        IndexedAccessor(x, y, _) | Binary(_, x, y) => {
+            free_names(*x, exps, bound, uses);
+            free_names(*y, exps, bound, uses)
+        }
+        Cartesian(x, y) => {
+            for x in x.iter().chain(y.iter()) {
+                free_names(*x, exps, bound, uses)
+            }
+        }
+        SetLiteral(xs) | Tuple(xs) => {
+            for x in xs {
+                free_names(*x, exps, bound, uses)
+            }
+        }
+        Set(p, _, body) => {
+            bound.push(*p);
+            free_names(*body, exps, bound, uses);
+            bound.pop();
+        }
+        Relation(p, _, q, _, body) => {
+            bound.push(*p);
+            bound.push(*q);
+            free_names(*body, exps, bound, uses);
+            bound.truncate(bound.len() - 2)
+        }
+        Forall(params, body) | Exists(params, body) => {
+            let len = bound.len();
+            bound.extend(params.iter().map(|(param, _)| *param));
+            free_names(*body, exps, bound, uses);
+            bound.truncate(len)
+        }
+        WhereForall(x, params, cond) | WhereExists(x, params, cond) => {
+            let len = bound.len();
+            bound.extend(params.iter().map(|(param, _)| *param));
+            free_names(*x, exps, bound, uses);
+            free_names(*cond, exps, bound, uses);
+            bound.truncate(len)
+        }
+        Bits(_) | Empty | Int(_) => (),
+    }
+}
+
 /// Accessors represent paths into potentially complex nested Sail
 /// datatypes that are used in the concurrency interface. These Sail
 /// subexpressions may not be fully representable in SMT, so when we
@@ -487,6 +584,7 @@ impl Exp {
 #[derive(Debug)]
 pub enum Accessor {
     Address,
+    Attr(Name),
     Bits(Vec<bool>),
     Ctor(Name),
     Data,
@@ -504,6 +602,39 @@ pub enum Accessor {
     Wildcard,
 }
 
+impl Accessor {
+    /// Print an accessor in the same form as it appears in the memory
+    /// model source
+    pub fn pretty(&self, symtab: &Symtab) -> String {
+        use Accessor::*;
+        match self {
+            Address => ".address()".to_string(),
+            Attr(attr) => format!(".{}()", &symtab[*attr]),
+            Bits(bv) => {
+                let mut s = "0b".to_string();
+                for bit in bv.iter().rev() {
+                    s.push(if *bit { '1' } else { '0' })
+                }
+                s
+            }
+            Ctor(ctor) => format!("{} =>", &symtab[*ctor]),
+            Data => ".data()".to_string(),
+            Exts(n) => format!(".exts({})", n),
+            Extz(n) => format!(".extz({})", n),
+            Field(field) => format!(".{}", &symtab[*field]),
+            Id(id) => symtab[*id].to_string(),
+            Is(expected) => format!("is {}", &symtab[*expected]),
+            Length(n) => format!(".length({})", n),
+            Match(_) => ".match".to_string(),
+            Opcode => ".opcode()".to_string(),
+            Return => ".return()".to_string(),
+            Subvec(hi, lo) => format!("[{} .. {}]", hi, lo),
+            Tuple(n) => format!(".{}", n),
+            Wildcard => "_ =>".to_string(),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Check {
     Acyclic,
@@ -518,6 +649,9 @@ pub type TyAnnot = Option<ExpId>;
 
 pub enum Def {
     Accessor(Name, ExpId, Vec<Accessor>),
+    /// A set of events defined by a boolean accessor predicate,
+    /// e.g. `set release = .match { W_release => true, _ => false }`
+    AccessorSet(Name, Vec<Accessor>),
     Assert(ExpId),
     Check(Check, ExpId, Name),
     Declare(Name, Vec<ExpId>, ExpId),
@@ -627,16 +761,44 @@ lazy_static! {
     ]);
 }
 
-/// Format an error message with the included source
-pub fn format_error(error: &Error) -> String {
+fn format_message(
+    loaded_models: &[(PathBuf, Cow<'static, str>)],
+    message: &str,
+    file: usize,
+    span: (usize, usize),
+    is_error: bool,
+) -> String {
+    if let Some((path, contents)) = loaded_models.get(file) {
+        let loc = span_to_source_loc(span, 0, contents);
+        loc.message_file_contents(&path.to_string_lossy(), contents, message, is_error, true)
+    } else {
+        message.to_string()
+    }
+}
+
+/// Format the location of a span, without the source text
+pub fn format_location(file: usize, span: (usize, usize)) -> String {
     let loaded_models = LOADED_MEMORY_MODELS.read().unwrap();
 
-    if let Some((path, contents)) = loaded_models.get(error.file) {
-        let loc = span_to_source_loc(error.span, 0, contents);
-        loc.message_file_contents(&path.to_string_lossy(), contents, &error.message, true, true)
+    if let Some((path, contents)) = loaded_models.get(file) {
+        let loc = span_to_source_loc(span, 0, contents);
+        loc.location_string(&[&path.to_string_lossy()])
     } else {
-        error.message.to_string()
+        "unknown location".to_string()
+    }
+}
+
+/// Format an error message with the included source, followed by
+/// any notes attached to the error
+pub fn format_error(error: &Error) -> String {
+    let loaded_models = LOADED_MEMORY_MODELS.read().unwrap();
+
+    let mut output = format_message(&loaded_models, &error.message, error.file, error.span, true);
+    for note in &error.notes {
+        output.push('\n');
+        output.push_str(&format_message(&loaded_models, &note.message, note.file, note.span, false))
     }
+    output
 }
 
 impl MemoryModel {
@@ -653,21 +815,47 @@ impl MemoryModel {
                     let ty = crate::smt::compile_type(&exps[*ty], &self.enums(), exps, sexps)?;
                     collection.insert(
                         *name,
-                        AccessorInfo { index_set: None, ty_annot: Some(ty), accessors: accs.as_slice() },
+                        AccessorInfo {
+                            index_set: None,
+                            ty_annot: Some(ty),
+                            accessors: accs.as_slice(),
+                            file: def.file,
+                            span: def.span,
+                        },
+                    );
+                }
+                Def::AccessorSet(name, accs) => {
+                    // The generated accessor function is the
+                    // characteristic function of the set
+                    collection.insert(
+                        *name,
+                        AccessorInfo {
+                            index_set: None,
+                            ty_annot: Some(sexps.bool_ty),
+                            accessors: accs.as_slice(),
+                            file: def.file,
+                            span: def.span,
+                        },
                     );
                 }
                 Def::IndexedAccessor(name, ix, ty, accs) => {
                     let ty = crate::smt::compile_type(&exps[*ty], &self.enums(), exps, sexps)?;
                     collection.insert(
                         *name,
-                        AccessorInfo { index_set: Some(*ix), ty_annot: Some(ty), accessors: accs.as_slice() },
+                        AccessorInfo {
+                            index_set: Some(*ix),
+                            ty_annot: Some(ty),
+                            accessors: accs.as_slice(),
+                            file: def.file,
+                            span: def.span,
+                        },
                     );
                 }
                 Def::Let(_, _, _, exp) | Def::Define(_, _, _, exp) => {
-                    exps[*exp].node.add_accessors(&mut collection, exps, symtab)
+                    exps[*exp].add_accessors(&mut collection, exps, symtab)
                 }
                 Def::Check(_, exp, _) | Def::Assert(exp) | Def::Flag(_, exp, _) => {
-                    exps[*exp].node.add_accessors(&mut collection, exps, symtab)
+                    exps[*exp].add_accessors(&mut collection, exps, symtab)
                 }
                 Def::Include(_)
                 | Def::Relation(_, _)
@@ -680,6 +868,99 @@ impl MemoryModel {
         Ok(collection)
     }
 
+    /// Check that no name is given more than one top-level
+    /// definition, that no definition refers to a name that is only
+    /// defined later in the model, and that every name the model
+    /// refers to is defined somewhere. The SMT definitions are
+    /// generated in order, so any of these would otherwise only be
+    /// reported by the solver, without any location in the model.
+    ///
+    /// Names for which `is_provided` returns true are defined by the
+    /// caller for each execution, and need not be declared.
+    pub fn check_names<F>(&self, exps: &ExpArena, symtab: &Symtab, is_provided: F) -> Result<(), Error>
+    where
+        F: Fn(&str) -> bool,
+    {
+        // Accessors are compiled into functions separately from (and
+        // before) the other definitions, so they have their own namespace
+        let mut definitions: HashMap<Name, usize> = HashMap::new();
+        let mut accessors: HashMap<Name, usize> = HashMap::new();
+
+        // Names that are declared, but do not have a definition we
+        // generate, like relations and enumeration members
+        let mut declared: HashSet<Name> = {
+            use constants::*;
+            [TRUE, FALSE, DEFAULT, SELF, DOMAIN, RANGE, EXTRACT, INDEX].iter().map(|c| c.name()).collect()
+        };
+
+        for (i, def) in self.defs.iter().enumerate() {
+            let (name, defined) = match &def.node {
+                Def::Let(f, ..) | Def::Declare(f, ..) | Def::Define(f, ..) | Def::Flag(_, _, f) => {
+                    (*f, &mut definitions)
+                }
+                Def::Accessor(f, ..) | Def::AccessorSet(f, _) | Def::IndexedAccessor(f, ..) => (*f, &mut accessors),
+                Def::Relation(_, f) | Def::Index(f) => {
+                    declared.insert(*f);
+                    continue;
+                }
+                Def::Enum(f, members) => {
+                    declared.insert(*f);
+                    declared.extend(members.iter().copied());
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(previous) = defined.get(&name) {
+                let previous = &self.defs[*previous];
+                return Err(Error::new(format!("{} is defined more than once", &symtab[name]), def.file, def.span)
+                    .with_note("previously defined here", previous.file, previous.span));
+            }
+            defined.insert(name, i);
+        }
+
+        for (i, def) in self.defs.iter().enumerate() {
+            let mut bound = Vec::new();
+            let mut uses = Vec::new();
+            match &def.node {
+                Def::Let(_, params, _, body) => {
+                    bound.extend(params.iter().map(|(param, _)| *param));
+                    free_names(*body, exps, &mut bound, &mut uses)
+                }
+                Def::Define(_, params, _, body) => {
+                    bound.extend(params.iter().map(|(param, _)| *param));
+                    free_names(*body, exps, &mut bound, &mut uses)
+                }
+                Def::Assert(exp) | Def::Check(_, exp, _) | Def::Flag(_, exp, _) => {
+                    free_names(*exp, exps, &mut bound, &mut uses)
+                }
+                _ => (),
+            }
+
+            for (name, file, span) in uses {
+                match definitions.get(&name) {
+                    Some(j) if *j > i => {
+                        let definition = &self.defs[*j];
+                        let message = format!("{} is used before it is defined", &symtab[name]);
+                        return Err(Error::new(message, file, span).with_note(
+                            "defined here",
+                            definition.file,
+                            definition.span,
+                        ));
+                    }
+                    Some(_) => (),
+                    None => {
+                        let known = accessors.contains_key(&name) || declared.contains(&name);
+                        if !known && !is_provided(&symtab[name]) {
+                            return Err(Error::new(format!("{} is not defined", &symtab[name]), file, span));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn enums(&self) -> MemoryModelEnums {
         let mut enum_ids = HashMap::new();
         let mut enum_members = HashMap::new();
@@ -808,6 +1089,31 @@ pub fn load_memory_model(name: &str, arena: &mut ExpArena, symtab: &mut Symtab)
     }
 }
 
+/// Load a memory model whose source is built into the binary. The
+/// name is used for error reporting only, and any includes are
+/// resolved using `ISLA_MM_LIB` and the internal library files.
+pub fn load_builtin_memory_model(
+    name: &str,
+    contents: &'static str,
+    arena: &mut ExpArena,
+    symtab: &mut Symtab,
+) -> Result<MemoryModel, String> {
+    let mut memory_model_dirs: Vec<PathBuf> = Vec::new();
+
+    if let Ok(directory) = env::var("ISLA_MM_LIB") {
+        memory_model_dirs.push(directory.into())
+    }
+
+    let mut mm = {
+        let mut loaded_memory_models = LOADED_MEMORY_MODELS.write().unwrap();
+        let mm = MemoryModel::from_string(name, loaded_memory_models.len(), contents, arena, symtab)?;
+        loaded_memory_models.push((PathBuf::from(name), Cow::Borrowed(contents)));
+        mm
+    };
+    resolve_includes(&memory_model_dirs, &mut mm, arena, symtab)?;
+    Ok(mm)
+}
+
 /// Resolve any include statements. Note that some included model
 /// files are very special, like `cos.cat` and `stdlib.cat` which are
 /// defined internally.
@@ -830,3 +1136,37 @@ pub fn resolve_includes(
         .collect();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_names(contents: &str, provided: &[&str]) -> Result<(), String> {
+        let mut arena = ExpArena::new();
+        let mut symtab = Symtab::new();
+        let mm = MemoryModel::from_string("test.cat", 0, contents, &mut arena, &mut symtab)?;
+        mm.check_names(&arena, &symtab, |name| provided.contains(&name)).map_err(|err| err.message)
+    }
+
+    #[test]
+    fn undefined_names() {
+        assert_eq!(check_names("let a = b", &[]), Err("b is not defined".to_string()));
+        assert_eq!(check_names("let a = b", &["b"]), Ok(()));
+        assert_eq!(check_names("relation b\nlet a = b", &[]), Ok(()));
+        assert_eq!(check_names("let a = [R]; po", &["R"]), Err("po is not defined".to_string()));
+        assert_eq!(
+            check_names("enum E = {A, B}\naccessor kind: E = .kind\ndefine f(ev: Event): bool = kind(ev) == A", &[]),
+            Ok(())
+        );
+        assert_eq!(check_names("define f(ev: Event): bool = g(ev)", &[]), Err("g is not defined".to_string()));
+    }
+
+    #[test]
+    fn names_used_before_definition() {
+        assert_eq!(check_names("let a = b\nlet b = a", &[]), Err("b is used before it is defined".to_string()));
+        assert_eq!(
+            check_names("relation b\nlet a = b\nlet a = b", &[]),
+            Err("a is defined more than once".to_string())
+        );
+    }
+}
@@ -291,6 +291,14 @@ impl SexpArena {
         self.alloc(Sexp::List(vec![self.define_fun, name, params, ret_ty, body]))
     }
 
+    /// Format an S-expression on a single line, e.g. for use in an
+    /// error message
+    pub fn format_sexp(&self, sexp: SexpId, symtab: &Symtab) -> String {
+        let mut buf = Vec::new();
+        self[sexp].write(&mut buf, self, symtab, &HashMap::new()).unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
     pub fn alloc_or(&mut self, disj: Vec<SexpId>) -> SexpId {
         if disj.is_empty() {
             self.bool_false
@@ -341,6 +349,36 @@ impl Sexp {
             }
         }
     }
+
+    /// Write an S-expression, breaking any list that would not fit
+    /// within `width` columns over multiple lines with each argument
+    /// indented beneath the head of the list.
+    fn write_pretty(
+        &self,
+        buf: &mut dyn Write,
+        indent: usize,
+        width: usize,
+        sexps: &SexpArena,
+        symtab: &Symtab,
+        bitwidths: &HashMap<Name, u32>,
+    ) -> std::io::Result<()> {
+        let mut flat = Vec::new();
+        self.write(&mut flat, sexps, symtab, bitwidths)?;
+
+        match self {
+            Sexp::List(xs) if xs.len() > 1 && indent + flat.len() > width => {
+                let (head, args) = xs.split_first().unwrap();
+                write!(buf, "(")?;
+                sexps[*head].write_pretty(buf, indent + 1, width, sexps, symtab, bitwidths)?;
+                for arg in args {
+                    write!(buf, "\n{:indent$}", "", indent = indent + 2)?;
+                    sexps[*arg].write_pretty(buf, indent + 2, width, sexps, symtab, bitwidths)?
+                }
+                write!(buf, ")")
+            }
+            _ => buf.write_all(&flat),
+        }
+    }
 }
 
 pub fn write_sexps(
@@ -357,6 +395,32 @@ pub fn write_sexps(
     Ok(())
 }
 
+/// The line width used when pretty-printing S-expressions
+pub const PRETTY_WIDTH: usize = 100;
+
+/// Like [write_sexps], but with line wrapping and indentation. Each
+/// `(i, comment)` in `origins` causes `comment` to be written as an
+/// SMT comment before `xs[i]`, which can be used to name the
+/// definition in the memory model that generated the S-expressions.
+pub fn write_sexps_pretty(
+    buf: &mut dyn Write,
+    xs: &[SexpId],
+    origins: &[(usize, String)],
+    sexps: &SexpArena,
+    symtab: &Symtab,
+    bitwidths: &HashMap<Name, u32>,
+) -> std::io::Result<()> {
+    let mut origins = origins.iter().peekable();
+    for (i, x) in xs.iter().enumerate() {
+        while let Some((_, comment)) = origins.next_if(|(j, _)| *j <= i) {
+            writeln!(buf, "; {}", comment)?
+        }
+        sexps[*x].write_pretty(buf, 0, PRETTY_WIDTH, sexps, symtab, bitwidths)?;
+        write!(buf, "\n\n")?
+    }
+    Ok(())
+}
+
 fn relation_arity_name(n: usize) -> Cow<'static, str> {
     match n {
         0 => Cow::Borrowed("value"),
@@ -411,7 +475,7 @@ pub fn compile_type(
         },
         _ => Err("Could not generate SMT compatible type"),
     };
-    result.map_err(|msg| Error { message: msg.to_string(), file: ty.file, span: ty.span })
+    result.map_err(|msg| Error::new(msg, ty.file, ty.span))
 }
 
 pub fn compile_tyannot(
@@ -439,7 +503,7 @@ pub fn compile_exp(
     match &exp.node {
         Exp::Empty => Ok(sexps.bool_false),
 
-        Exp::Int(_) => Err(Error { message: "unexpected integer".to_string(), file: exp.file, span: exp.span }),
+        Exp::Int(_) => Err(Error::new("unexpected integer", exp.file, exp.span)),
 
         Exp::App(f, args) if *f == DOMAIN.name() => match args.as_slice() {
             [Some(arg)] => {
@@ -449,7 +513,7 @@ pub fn compile_exp(
                 let rel = compile_exp(&exps[*arg], &evs_with_range, enums, exps, sexps, symtab, compiled)?;
                 Ok(sexps.alloc_exists_id(range_ev, rel))
             }
-            _ => Err(Error { message: "range expects a single argument".to_string(), file: exp.file, span: exp.span }),
+            _ => Err(Error::new("range expects a single argument", exp.file, exp.span)),
         },
 
         Exp::App(f, args) if *f == RANGE.name() => match args.as_slice() {
@@ -460,7 +524,7 @@ pub fn compile_exp(
                 let rel = compile_exp(&exps[*arg], &evs_with_domain, enums, exps, sexps, symtab, compiled)?;
                 Ok(sexps.alloc_exists_id(domain_ev, rel))
             }
-            _ => Err(Error { message: "range expects a single argument".to_string(), file: exp.file, span: exp.span }),
+            _ => Err(Error::new("range expects a single argument", exp.file, exp.span)),
         },
 
         Exp::App(f, args) if *f == EXTRACT.name() => match args.as_slice() {
@@ -472,29 +536,25 @@ pub fn compile_exp(
                     let arg = compile_exp(&exps[*arg], &[], enums, exps, sexps, symtab, compiled)?;
                     Ok(sexps.alloc(Sexp::List(vec![extract, arg])))
                 }
-                _ => Err(Error {
-                    message: "extract must have integer literals as the first two arguments".to_string(),
-                    file: exp.file,
-                    span: exp.span,
-                }),
+                _ => {
+                    Err(Error::new("extract must have integer literals as the first two arguments", exp.file, exp.span))
+                }
             },
-            _ => {
-                Err(Error { message: "extract expects a three arguments".to_string(), file: exp.file, span: exp.span })
-            }
+            _ => Err(Error::new("extract expects a three arguments", exp.file, exp.span)),
         },
 
         Exp::App(f, args) => {
             let wildcards = count_wildcards(args);
             if !(wildcards == evs.len() || wildcards == 0) {
-                return Err(Error {
-                    message: format!(
+                return Err(Error::new(
+                    format!(
                         "Incorrect number of wildcards in function call. Either {} or 0 allowed, {} found",
                         evs.len(),
                         wildcards
                     ),
-                    file: exp.file,
-                    span: exp.span,
-                });
+                    exp.file,
+                    exp.span,
+                ));
             }
 
             let mut wildcard: usize = 0;
@@ -543,14 +603,14 @@ pub fn compile_exp(
                 (None, Some(y)) => compile_exp(&exps[*y], &[ev2], enums, exps, sexps, symtab, compiled),
                 (None, None) => Ok(sexps.bool_true),
             },
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Cartesian product in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len()),
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Binary(Binary::Diff, x, y) => {
@@ -595,14 +655,14 @@ pub fn compile_exp(
                 }
                 compile_exp(&exps[*set], &args, enums, exps, sexps, symtab, compiled)
             } else {
-                Err(Error {
-                    message: format!(
+                Err(Error::new(
+                    format!(
                         "Boolean set membership in a context where a {} was expected",
                         relation_arity_name(evs.len())
                     ),
-                    file: exp.file,
-                    span: exp.span,
-                })
+                    exp.file,
+                    exp.span,
+                ))
             }
         }
 
@@ -614,14 +674,14 @@ pub fn compile_exp(
                 xs.push(compile_exp(&exps[*y], &[ev3, ev2], enums, exps, sexps, symtab, compiled)?);
                 Ok(sexps.alloc_exists(ev3, Sexp::List(xs)))
             }
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Sequential composition in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len())
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Binary(Binary::Eq, x, y) => {
@@ -653,14 +713,14 @@ pub fn compile_exp(
                 xs.push(sexps.alloc(Sexp::List(vec![sexps.eq, ev1, ev2])));
                 Ok(sexps.alloc(Sexp::List(xs)))
             }
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Identity in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len())
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Unary(Unary::IdentityUnion, x) => match evs {
@@ -670,26 +730,26 @@ pub fn compile_exp(
                 xs.push(sexps.alloc(Sexp::List(vec![sexps.eq, ev1, ev2])));
                 Ok(sexps.alloc(Sexp::List(xs)))
             }
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Union with identity in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len())
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Unary(Unary::Inverse, x) => match evs {
             &[ev1, ev2] => compile_exp(&exps[*x], &[ev2, ev1], enums, exps, sexps, symtab, compiled),
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Inverse in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len())
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Unary(closure_op @ (Unary::TClosure | Unary::RTClosure), x) => match evs {
@@ -700,14 +760,14 @@ pub fn compile_exp(
                     compile_closure(true, &exps[*x], evs, enums, exps, sexps, symtab, compiled)
                 }
             }
-            _ => Err(Error {
-                message: format!(
+            _ => Err(Error::new(
+                format!(
                     "Closure operator in a context where a {} was expected, rather than a binary relation",
                     relation_arity_name(evs.len())
                 ),
-                file: exp.file,
-                span: exp.span,
-            }),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Set(v, _, body) => match evs {
@@ -715,11 +775,11 @@ pub fn compile_exp(
                 let body = compile_exp(&exps[*body], &[], enums, exps, sexps, symtab, compiled)?;
                 Ok(sexps.alloc_letbind(&[(*v, ev1)], body))
             }
-            _ => Err(Error {
-                message: format!("Explicit set in a context where a {} was expected", relation_arity_name(evs.len())),
-                file: exp.file,
-                span: exp.span,
-            }),
+            _ => Err(Error::new(
+                format!("Explicit set in a context where a {} was expected", relation_arity_name(evs.len())),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Relation(v1, _, v2, _, body) => match evs {
@@ -727,14 +787,11 @@ pub fn compile_exp(
                 let body = compile_exp(&exps[*body], &[], enums, exps, sexps, symtab, compiled)?;
                 Ok(sexps.alloc_letbind(&[(*v1, ev1), (*v2, ev2)], body))
             }
-            _ => Err(Error {
-                message: format!(
-                    "Explicit relation in a context where a {} was expected",
-                    relation_arity_name(evs.len())
-                ),
-                file: exp.file,
-                span: exp.span,
-            }),
+            _ => Err(Error::new(
+                format!("Explicit relation in a context where a {} was expected", relation_arity_name(evs.len())),
+                exp.file,
+                exp.span,
+            )),
         },
 
         Exp::Forall(args, body) => {
@@ -746,14 +803,14 @@ pub fn compile_exp(
                 }
                 Ok(sexps.alloc_multi_forall(&compiled_args, body))
             } else {
-                Err(Error {
-                    message: format!(
+                Err(Error::new(
+                    format!(
                         "Universal quantifier in a context where a {} was expected",
                         relation_arity_name(evs.len())
                     ),
-                    file: exp.file,
-                    span: exp.span,
-                })
+                    exp.file,
+                    exp.span,
+                ))
             }
         }
 
@@ -766,14 +823,14 @@ pub fn compile_exp(
                 }
                 Ok(sexps.alloc_multi_exists(&compiled_args, body))
             } else {
-                Err(Error {
-                    message: format!(
+                Err(Error::new(
+                    format!(
                         "Existential quantifier in a context where a {} was expected",
                         relation_arity_name(evs.len())
                     ),
-                    file: exp.file,
-                    span: exp.span,
-                })
+                    exp.file,
+                    exp.span,
+                ))
             }
         }
 
@@ -817,15 +874,15 @@ pub fn compile_exp(
                         disj.push(sexps.alloc(Sexp::List(vec![sexps.eq, evs[0], x])))
                     }
                     _ => {
-                        return Err(Error {
-                            message: format!(
+                        return Err(Error::new(
+                            format!(
                             "Set literal must contain only tuples with length {} in a context where a {} was expected",
                             evs.len(),
                             relation_arity_name(evs.len())
                         ),
-                            file: element.file,
-                            span: element.span,
-                        })
+                            element.file,
+                            element.span,
+                        ))
                     }
                 }
             }
@@ -845,7 +902,7 @@ pub fn compile_exp(
             Ok(sexps.alloc(Sexp::List(vec![accessor_function, exp, index])))
         }
 
-        Exp::Tuple(_) => Err(Error { message: "Unexpected tuple".to_string(), file: exp.file, span: exp.span }),
+        Exp::Tuple(_) => Err(Error::new("Unexpected tuple", exp.file, exp.span)),
     }
 }
 
@@ -1099,26 +1156,77 @@ pub fn compile_def(
 
         Def::Accessor(..) => Ok(()),
 
+        Def::AccessorSet(..) => Ok(()),
+
         Def::IndexedAccessor(..) => Ok(()),
 
         Def::Enum(..) => Ok(()),
 
         Def::Index(_) => Ok(()),
 
-        Def::Include(_) => panic!("include statement should be removed before compilation to SMT"),
+        Def::Include(name) => Err(Error::new(
+            format!("include of {} should be resolved before compilation to SMT", name),
+            def.file,
+            def.span,
+        )),
+    }
+}
+
+fn describe_def(def: &Def, symtab: &Symtab) -> Option<String> {
+    match def {
+        Def::Let(f, _, _, _) => Some(format!("let {}", &symtab[*f])),
+        Def::Assert(_) => Some("assert".to_string()),
+        Def::Flag(_, _, as_name) => Some(format!("flag {}", &symtab[*as_name])),
+        Def::Check(_, _, as_name) => Some(format!("check {}", &symtab[*as_name])),
+        Def::Declare(f, _, _) => Some(format!("declare {}", &symtab[*f])),
+        Def::Define(f, _, _, _) => Some(format!("define {}", &symtab[*f])),
+        _ => None,
     }
 }
 
-pub fn compile_memory_model(
+/// Compile a memory model. Names for which `is_provided` returns
+/// true are defined elsewhere, so the model may refer to them
+/// without declaring them (see [MemoryModel::check_names]).
+pub fn compile_memory_model<F>(
     mm: &MemoryModel,
+    is_provided: F,
     exps: &ExpArena,
     sexps: &mut SexpArena,
     symtab: &mut Symtab,
     compiled: &mut Vec<SexpId>,
-) -> Result<(), Error> {
+) -> Result<(), Error>
+where
+    F: Fn(&str) -> bool,
+{
+    compile_memory_model_with_origins(mm, is_provided, exps, sexps, symtab, compiled).map(|_| ())
+}
+
+/// Compile a memory model, additionally returning for each
+/// definition the index of the first S-expression it generated in
+/// `compiled` together with a short description of the definition,
+/// suitable for use with [write_sexps_pretty].
+pub fn compile_memory_model_with_origins<F>(
+    mm: &MemoryModel,
+    is_provided: F,
+    exps: &ExpArena,
+    sexps: &mut SexpArena,
+    symtab: &mut Symtab,
+    compiled: &mut Vec<SexpId>,
+) -> Result<Vec<(usize, String)>, Error>
+where
+    F: Fn(&str) -> bool,
+{
+    mm.check_names(exps, symtab, is_provided)?;
     let enums = mm.enums();
+    let mut origins = Vec::new();
     for def in mm.defs.iter() {
-        compile_def(def, &enums, exps, sexps, symtab, compiled)?
+        let start = compiled.len();
+        compile_def(def, &enums, exps, sexps, symtab, compiled)?;
+        if compiled.len() > start {
+            if let Some(description) = describe_def(&def.node, symtab) {
+                origins.push((start, description))
+            }
+        }
     }
-    Ok(())
+    Ok(origins)
 }
@@ -37,28 +37,35 @@ use std::ffi::OsStr;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::{prelude::*, BufReader, Lines};
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
 use std::process::{self, Command};
 use std::thread;
 use std::time::Instant;
 
+use isla_axiomatic::generate::{generate_litmus, parse_cycle, GenerateArch};
 use isla_axiomatic::graph::{graph_from_unsat, graph_from_z3_output, Graph, GraphOpts, GraphValueNames};
 use isla_axiomatic::litmus::Litmus;
 use isla_axiomatic::page_table::{name_initial_walk_bitvectors, VirtualAddress};
 use isla_axiomatic::run_litmus;
 use isla_axiomatic::run_litmus::LitmusRunOpts;
+use isla_axiomatic::smt_events;
 use isla_lib::bitvector::{b64::B64, BV};
 use isla_lib::config::ISAConfig;
 use isla_lib::error::{IslaError, VoidError};
 use isla_lib::init::initialize_architecture;
 use isla_lib::ir::*;
+use isla_lib::jobserver::{Coordinator, Job, JobResult, WorkerConnection};
 use isla_lib::log;
 use isla_mml::memory_model;
-use isla_mml::smt::{compile_memory_model, SexpArena};
+use isla_mml::smt::{compile_memory_model_with_origins, write_sexps_pretty, SexpArena};
 
 mod opts;
 use opts::CommonOpts;
 
+mod targets;
+use targets::Target;
+
 use std::sync::atomic::{AtomicBool, Ordering};
 
 static FAILURE: AtomicBool = AtomicBool::new(false);
@@ -138,17 +145,22 @@ fn isla_main() -> i32 {
     opts.optopt("", "only-group", "only perform jobs for one thread group", "<n>");
     opts.optopt("s", "timeout", "Add a timeout (in seconds)", "<n>");
     opts.optopt("", "memory", "Add a max memory consumption (in megabytes)", "<n>");
-    opts.reqopt("m", "model", "Memory model in cat format", "<path>");
+    opts.optopt("m", "model", "Memory model in cat format", "<path>");
+    opts.optopt("", "target", "Use a built-in config and memory model for a target (aarch64, riscv64)", "<name>");
     opts.optflag("", "ifetch", "Generate ifetch events");
     opts.optflag("", "armv8-page-tables", "Automatically set up ARMv8 page tables");
     opts.optflag("", "merge-translations", "Merge consecutive translate events into a single event");
     opts.optflag("", "merge-split-stages", "Split stages when merging translations");
     opts.optopt("", "remove-uninteresting", "Remove uninteresting translate events", "all/safe");
     opts.optflag("e", "exhaustive", "Attempt to exhaustively enumerate all possible rf combinations");
+    opts.optflag("", "relation-report", "Print the sizes of the relations generated for each candidate execution");
+    opts.optflag("", "symbolic-sleep", "Let threads sleep after a sleep request (e.g. WFE) if they can be woken");
     opts.optmulti("", "extra-smt", "additional SMT appended to each candidate", "<file>");
+    opts.optopt("", "dump-smt", "write the SMT generated for the memory model to a file", "<file>");
     opts.optopt("", "check-sat-using", "Use z3 tactic for checking satisfiablity", "tactic");
     opts.optopt("", "latex", "generate latex version of input files in specified directory", "<path>");
     opts.optflag("", "no-z3-model", "do not generate a graph");
+    opts.optflag("", "no-concrete-eval", "always use the SMT solver, even for candidates with concrete addresses");
     opts.optopt("", "dot", "Generate graphviz dot files in specified directory", "<path>");
     opts.optflag("", "temp-dot", "Generate graphviz dot files in TMPDIR or /tmp");
     opts.optflag("", "graph-debug", "Show everything, all trace events and full information in the nodes");
@@ -186,6 +198,24 @@ fn isla_main() -> i32 {
         "Open graphviz dot files in default image viewer. Implies --temp-dot unless --dot is set.",
     );
     opts.optopt("", "refs", "references to compare output with", "<path>");
+    opts.optopt("", "coordinator", "hand out tests to workers connecting to this address", "<address>");
+    opts.optopt("", "worker", "run tests handed out by the coordinator at this address", "<address>");
+    opts.optopt("", "job-attempts", "number of times a coordinator will hand out each test (default 3)", "<n>");
+    opts.optflag("", "herd", "Print results in the same format as herd7");
+    opts.optmulti("", "generate", "Generate and run a litmus test from a cycle of relations", "<cycle>");
+    opts.optopt(
+        "",
+        "generate-arch",
+        "Architecture to use for generated litmus tests (default: that of the loaded model)",
+        "<arch>",
+    );
+    opts.optopt(
+        "",
+        "check-expected",
+        "Compare results against a file of expected results, exiting with an error on any divergence. \
+         These override any --refs for the same test",
+        "<path>",
+    );
     opts.optopt(
         "",
         "cache",
@@ -195,12 +225,26 @@ fn isla_main() -> i32 {
 
     let mut hasher = Sha256::new();
     let (matches, orig_arch) = opts::parse::<B64>(&mut hasher, &opts);
+
+    let target = match matches.opt_str("target").map(|name| Target::find(&name)).transpose() {
+        Ok(target) => target,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            return 1;
+        }
+    };
+    if target.is_none() && !matches.opt_present("model") {
+        eprintln!("Either a memory model (--model) or a target (--target) must be given");
+        return 1;
+    }
+    let target_config = target.map(Target::config);
+
     let CommonOpts { num_threads, mut arch, symtab, isa_config, source_path } =
-        opts::parse_with_arch(&mut hasher, &opts, &matches, &orig_arch);
+        opts::parse_with_arch_and_default_config(&mut hasher, &opts, &matches, &orig_arch, target_config);
 
     // Huge hack, just load an entirely separate copy of the architecture for footprint analysis
     let CommonOpts { num_threads: _, arch: mut farch, symtab: fsymtab, isa_config: _, source_path: _ } =
-        opts::parse_with_arch(&mut hasher, &opts, &matches, &orig_arch);
+        opts::parse_with_arch_and_default_config(&mut hasher, &opts, &matches, &orig_arch, target_config);
 
     let iarch = initialize_architecture(&mut arch, symtab, &isa_config, AssertionMode::Optimistic);
     let iarch_config = InitArchWithConfig::from_initialized(&iarch, &isa_config);
@@ -296,7 +340,11 @@ fn isla_main() -> i32 {
 
     let view = matches.opt_present("view");
 
+    let herd = matches.opt_present("herd");
+
     let exhaustive = matches.opt_present("exhaustive");
+    let relation_report = matches.opt_present("relation-report");
+    let symbolic_sleep = matches.opt_present("symbolic-sleep");
 
     let timeout: Option<u64> = match matches.opt_get("timeout") {
         Ok(timeout) => timeout,
@@ -314,7 +362,7 @@ fn isla_main() -> i32 {
         }
     };
 
-    let refs = if let Some(refs_file) = matches.opt_str("refs") {
+    let mut refs = if let Some(refs_file) = matches.opt_str("refs") {
         match process_refs(&refs_file) {
             Ok(refs) => refs,
             Err(e) => {
@@ -326,6 +374,24 @@ fn isla_main() -> i32 {
         HashMap::new()
     };
 
+    // Expected results take precedence over any --refs result for
+    // the same test, as they are what --check-expected checks against
+    let check_expected = matches.opt_str("check-expected");
+    if let Some(expected_file) = &check_expected {
+        match process_expected(expected_file) {
+            Ok(expected) if expected.is_empty() => {
+                eprintln!("No expected results in {}", expected_file);
+                return 1;
+            }
+            Ok(expected) => refs.extend(expected),
+            Err(e) => {
+                eprintln!("Error when reading {}:\n{}", expected_file, e);
+                return 1;
+            }
+        }
+    }
+    let divergences = SegQueue::new();
+
     // Get all the tests from the command line
     let mut tests = Vec::new();
     for path in matches.free.iter().map(PathBuf::from) {
@@ -337,11 +403,105 @@ fn isla_main() -> i32 {
         }
     }
 
+    // Generate any tests from cycles given on the command line, which
+    // are written to the cache directory so they can be inspected
+    let model_arch = GenerateArch::from_symtab(&iarch.shared_state.symtab);
+    let generate_arch = match (matches.opt_str("generate-arch").map(|arch| arch.parse::<GenerateArch>()), model_arch) {
+        (Some(Err(e)), _) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+        (Some(Ok(arch)), Some(model_arch)) if arch != model_arch => {
+            eprintln!(
+                "--generate-arch {:?} does not match the architecture of the loaded model ({:?})",
+                arch, model_arch
+            );
+            return 1;
+        }
+        (Some(Ok(arch)), _) => Some(arch),
+        (None, model_arch) => model_arch,
+    };
+    if generate_arch.is_none() && matches.opt_present("generate") {
+        eprintln!("Could not determine the architecture to generate litmus tests for, use --generate-arch");
+        return 1;
+    }
+    for cycle in matches.opt_strs("generate") {
+        match parse_cycle(&cycle).and_then(|cycle| generate_litmus(&cycle, generate_arch.unwrap())) {
+            Ok((name, litmus)) => {
+                let path = cache.join(format!("{}.toml", name));
+                if let Err(e) = fs::write(&path, litmus) {
+                    eprintln!("Failed to write generated litmus test {}: {}", path.display(), e);
+                    return 1;
+                }
+                tests.push(path)
+            }
+            Err(e) => {
+                eprintln!("Failed to generate litmus test from {}: {}", cycle, e);
+                return 1;
+            }
+        }
+    }
+
+    // In coordinator mode the tests are run by workers, which report
+    // their results back to us
+    if let Some(addr) = matches.opt_str("coordinator") {
+        let attempts = match matches.opt_get_default("job-attempts", 3) {
+            Ok(attempts) => attempts,
+            Err(e) => {
+                eprintln!("Failed to parse --job-attempts: {}", e);
+                return 1;
+            }
+        };
+        let mut jobs = Vec::new();
+        for (id, path) in tests.iter().enumerate() {
+            let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            match fs::read(path) {
+                Ok(payload) => jobs.push(Job { id, name, payload }),
+                Err(e) => {
+                    eprintln!("Failed to read litmus file: {}\n{}", path.display(), e);
+                    return 1;
+                }
+            }
+        }
+        let listener = match TcpListener::bind(&addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", addr, e);
+                return 1;
+            }
+        };
+        eprintln!("Waiting for workers on {}", addr);
+        let result = Coordinator::new(jobs, attempts).run(listener, |job, result| {
+            print!("{}", result.output);
+            if result.failed {
+                FAILURE.store(true, Ordering::Relaxed);
+                divergences.push(job.name.clone())
+            }
+        });
+        match result {
+            Ok(given_up) => {
+                for job in given_up {
+                    FAILURE.store(true, Ordering::Relaxed);
+                    eprintln!("No worker completed {}", job.name)
+                }
+            }
+            Err(e) => {
+                eprintln!("Coordinator failed: {}", e);
+                return 1;
+            }
+        }
+        return report_divergences(check_expected.is_some(), &divergences);
+    }
+
     // Load and compile the memory model
-    let mm_file = &matches.opt_str("model").unwrap();
     let mut mm_symtab = memory_model::Symtab::new();
     let mut mm_arena = memory_model::ExpArena::new();
-    let mm = match memory_model::load_memory_model(mm_file, &mut mm_arena, &mut mm_symtab) {
+    let mm = match (matches.opt_str("model"), target) {
+        (Some(mm_file), _) => memory_model::load_memory_model(&mm_file, &mut mm_arena, &mut mm_symtab),
+        (None, Some(target)) => target.load_memory_model(&mut mm_arena, &mut mm_symtab),
+        (None, None) => unreachable!(),
+    };
+    let mm = match mm {
         Ok(mm) => mm,
         Err(message) => {
             eprintln!("{}", message);
@@ -357,9 +517,30 @@ fn isla_main() -> i32 {
         }
     };
     let mut mm_compiled = Vec::new();
-    if let Err(compile_error) = compile_memory_model(&mm, &mm_arena, &mut sexps, &mut mm_symtab, &mut mm_compiled) {
-        eprintln!("{}", memory_model::format_error(&compile_error));
-        return 1;
+    let is_provided = |name: &str| smt_events::is_provided_name(name, &isa_config);
+    let mm_origins = match compile_memory_model_with_origins(
+        &mm,
+        is_provided,
+        &mm_arena,
+        &mut sexps,
+        &mut mm_symtab,
+        &mut mm_compiled,
+    ) {
+        Ok(origins) => origins,
+        Err(compile_error) => {
+            eprintln!("{}", memory_model::format_error(&compile_error));
+            return 1;
+        }
+    };
+
+    if let Some(file) = matches.opt_str("dump-smt") {
+        let result = File::create(&file).and_then(|mut fd| {
+            write_sexps_pretty(&mut fd, &mm_compiled, &mm_origins, &sexps, &mm_symtab, &HashMap::new())
+        });
+        if let Err(err) = result {
+            eprintln!("Error writing SMT to {}: {}", file, err);
+            return 1;
+        }
     }
 
     let extra_smt = match matches
@@ -397,6 +578,10 @@ fn isla_main() -> i32 {
     let only_group: Option<usize> = matches.opt_get("only-group").unwrap();
 
     let get_z3_model = !matches.opt_present("no-z3-model");
+    // Concretely evaluated candidates have no model to draw a graph from
+    let concrete_eval = !matches.opt_present("no-concrete-eval") && dot_path.is_none();
+
+    let worker_addr = matches.opt_str("worker");
 
     thread::scope(|scope| {
         for group_id in 0..thread_groups {
@@ -407,6 +592,7 @@ fn isla_main() -> i32 {
             // These ensure that only references are captured by the closure in scope.spawn below
             let tests = &tests;
             let refs = &refs;
+            let divergences = &divergences;
             let iarch = &iarch_config;
             let shared_state = &iarch.shared_state;
             let symtab = &shared_state.symtab;
@@ -414,9 +600,12 @@ fn isla_main() -> i32 {
             let isa_config = &isa_config;
             let source_path = &source_path;
             let cache = &cache;
+            let arch_hash = &arch_hash;
             let dot_path = &dot_path;
             let latex_path = &latex_path;
             let sexps = &sexps;
+            let mm = &mm;
+            let mm_arena = &mm_arena;
             let mm_compiled = &mm_compiled;
             let mm_symtab = &mm_symtab;
             let accessors = &accessors;
@@ -426,9 +615,53 @@ fn isla_main() -> i32 {
             let graph_force_show_events = graph_force_show_events.as_ref();
             let graph_force_hide_events = graph_force_hide_events.as_ref();
             let check_sat_using = check_sat_using.as_deref();
+            let worker_addr = worker_addr.as_deref();
 
             scope.spawn(move || {
-                for (i, litmus_file) in GroupIndex::new(tests, group_id, thread_groups).enumerate() {
+                // In worker mode each thread group gets tests from the
+                // coordinator, and sends back the output for each test
+                // when it asks for the next one
+                let mut connection = match worker_addr {
+                    Some(addr) => match WorkerConnection::connect(addr) {
+                        Ok(connection) => Some(connection),
+                        Err(e) => {
+                            eprintln!("Failed to connect to coordinator at {}: {}", addr, e);
+                            FAILURE.store(true, Ordering::Relaxed);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                let mut local_tests = GroupIndex::new(tests, group_id, thread_groups);
+                let mut job_result: Option<JobResult> = None;
+
+                for i in 0.. {
+                    let job_file;
+                    let litmus_file: &Path = if let Some(connection) = &mut connection {
+                        match connection.next_job(job_result.take()) {
+                            Ok(Some(job)) => {
+                                job_file = cache.join(format!("job{}-{}", job.id, job.name));
+                                job_result = Some(JobResult { id: job.id, output: String::new(), failed: true });
+                                if let Err(e) = fs::write(&job_file, &job.payload) {
+                                    eprintln!("Failed to write {}: {}", job_file.display(), e);
+                                    continue;
+                                }
+                                &job_file
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                eprintln!("Lost connection to coordinator: {}", e);
+                                FAILURE.store(true, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+                    } else {
+                        match local_tests.next() {
+                            Some(litmus_file) => litmus_file.as_path(),
+                            None => break,
+                        }
+                    };
+
                     let litmus = if litmus_file.extension() == Some(OsStr::new("litmus")) {
                         let mut opt_args = Vec::new();
                         if armv8_page_tables {
@@ -448,6 +681,7 @@ fn isla_main() -> i32 {
                                 litmus_file.display(),
                                 String::from_utf8_lossy(&output.stderr)
                             );
+                            record_failed_test(divergences, litmus_file);
                             continue;
                         }
                     } else {
@@ -455,6 +689,7 @@ fn isla_main() -> i32 {
                             Ok(litmus) => litmus,
                             Err(msg) => {
                                 eprintln!("Failed to read litmus file: {}\n{}", litmus_file.display(), msg);
+                                record_failed_test(divergences, litmus_file);
                                 continue;
                             }
                         }
@@ -464,6 +699,7 @@ fn isla_main() -> i32 {
                         Ok(litmus) => litmus,
                         Err(msg) => {
                             eprintln!("Failed to parse litmus file: {}\n{}", litmus_file.display(), msg);
+                            record_failed_test(divergences, litmus_file);
                             continue;
                         }
                     };
@@ -502,6 +738,9 @@ fn isla_main() -> i32 {
                         armv8_page_tables,
                         merge_translations,
                         remove_uninteresting_translates,
+                        arch_hash: Some(format!("{:x}", arch_hash)),
+                        relation_report,
+                        symbolic_sleep,
                     };
 
                     let mut graph_show_regs: HashSet<String> =
@@ -552,6 +791,7 @@ fn isla_main() -> i32 {
                         mm_compiled,
                         mm_symtab,
                         accessors,
+                        if concrete_eval { Some((mm, mm_arena)) } else { None },
                         extra_smt,
                         check_sat_using,
                         get_z3_model,
@@ -664,7 +904,22 @@ fn isla_main() -> i32 {
                             "{}",
                             err.source_loc().message(source_path.as_ref(), symtab.files(), &msg, true, true)
                         );
-                        print_results(&litmus.name, now, &[Error(None, "".to_string())], ref_result);
+                        let (output, diverged) = if herd {
+                            format_herd_results(
+                                &litmus,
+                                symtab,
+                                isa_config,
+                                now,
+                                &[Error(None, "".to_string())],
+                                ref_result,
+                            )
+                        } else {
+                            format_results(&litmus.name, now, &[Error(None, "".to_string())], ref_result)
+                        };
+                        emit_results(&mut job_result, output, diverged);
+                        if diverged {
+                            divergences.push(litmus.name.clone())
+                        }
                         continue;
                     }
 
@@ -673,7 +928,15 @@ fn isla_main() -> i32 {
                         results.push(result)
                     }
 
-                    print_results(&litmus.name, now, &results, ref_result);
+                    let (output, diverged) = if herd {
+                        format_herd_results(&litmus, symtab, isa_config, now, &results, ref_result)
+                    } else {
+                        format_results(&litmus.name, now, &results, ref_result)
+                    };
+                    emit_results(&mut job_result, output, diverged);
+                    if diverged {
+                        divergences.push(litmus.name.clone())
+                    }
 
                     if let Some(dot_path) = dot_path {
                         for (i, allowed) in results.iter().enumerate() {
@@ -719,6 +982,30 @@ fn isla_main() -> i32 {
         }
     });
 
+    report_divergences(check_expected.is_some(), &divergences)
+}
+
+/// Record a test which could not be run at all, so it is reported
+/// as a divergence like a test without any results.
+fn record_failed_test(divergences: &SegQueue<String>, litmus_file: &Path) {
+    FAILURE.store(true, Ordering::Relaxed);
+    divergences.push(litmus_file.display().to_string())
+}
+
+/// Print any divergences from the expected results, and return the
+/// exit code.
+fn report_divergences(check_expected: bool, divergences: &SegQueue<String>) -> i32 {
+    if check_expected {
+        if divergences.is_empty() {
+            eprintln!("All results match the expected results")
+        } else {
+            eprintln!("{} tests diverged from the expected results:", divergences.len());
+            while let Some(name) = divergences.pop() {
+                eprintln!("  {}", name)
+            }
+        }
+    }
+
     if FAILURE.load(Ordering::Relaxed) {
         1
     } else {
@@ -726,11 +1013,29 @@ fn isla_main() -> i32 {
     }
 }
 
-fn print_results(name: &str, start_time: Instant, results: &[AxResult], expected: Option<&AxResult>) {
+/// Print the output for a test, or save it to be sent back to the
+/// coordinator when running as a worker.
+fn emit_results(job_result: &mut Option<JobResult>, output: String, diverged: bool) {
+    if let Some(result) = job_result {
+        result.output.push_str(&output);
+        result.failed = diverged
+    } else {
+        print!("{}", output)
+    }
+}
+
+/// Format the results for a test, returning true if they diverge
+/// from the expected result.
+fn format_results(
+    name: &str,
+    start_time: Instant,
+    results: &[AxResult],
+    expected: Option<&AxResult>,
+) -> (String, bool) {
     if results.is_empty() {
+        FAILURE.store(true, Ordering::Relaxed);
         let prefix = format!("{} no executions {}", name, start_time.elapsed().as_millis());
-        println!("{:.<100} \x1b[95m\x1b[1merror\x1b[0m", prefix);
-        return;
+        return (format!("{:.<100} \x1b[95m\x1b[1merror\x1b[0m\n", prefix), true);
     }
 
     let got = if let Some(err) = results.iter().find(|result| result.is_error()) {
@@ -771,21 +1076,90 @@ fn print_results(name: &str, start_time: Instant, results: &[AxResult], expected
         format!("{} {} ({}) {}ms ", name, got.short_name(), count, start_time.elapsed().as_millis())
     };
 
-    let result = if let Some(reference) = expected {
+    let (result, diverged) = if let Some(reference) = expected {
         if got.matches(reference) {
-            "\x1b[92m\x1b[1mok\x1b[0m"
+            ("\x1b[92m\x1b[1mok\x1b[0m", false)
         } else if got.is_error() {
             FAILURE.store(true, Ordering::Relaxed);
-            "\x1b[95m\x1b[1merror\x1b[0m"
+            ("\x1b[95m\x1b[1merror\x1b[0m", true)
         } else {
             FAILURE.store(true, Ordering::Relaxed);
-            "\x1b[91m\x1b[1mfail\x1b[0m"
+            ("\x1b[91m\x1b[1mfail\x1b[0m", true)
         }
     } else {
-        "\x1b[93m\x1b[1m?\x1b[0m"
+        ("\x1b[93m\x1b[1m?\x1b[0m", false)
     };
 
-    println!("{:.<100} {}", prefix, result)
+    (format!("{:.<100} {}\n", prefix, result), diverged)
+}
+
+/// Format the results for a test in the format used by herd7, so
+/// they can be compared against herd7 output using the existing
+/// tooling. Isla only determines whether the final condition can be
+/// satisfied, so the `States` section contains at most the state
+/// described by the condition itself.
+///
+/// As in herd7, the word after the test name is the kind of the
+/// condition, and whether the condition is validated is given by
+/// the following `Ok` or `No` line.
+fn format_herd_results<B: BV>(
+    litmus: &Litmus<B>,
+    symtab: &Symtab,
+    isa_config: &ISAConfig<B>,
+    start_time: Instant,
+    results: &[AxResult],
+    expected: Option<&AxResult>,
+) -> (String, bool) {
+    let allowed = results.iter().filter(|result| result.is_allowed()).count();
+    let forbidden = results.iter().filter(|result| matches!(result, AxResult::Forbidden(_))).count();
+
+    // A single allowed candidate is enough to show the test is
+    // allowed, but it is only forbidden if every candidate is
+    let got = if allowed > 0 {
+        AxResult::Allowed(None)
+    } else if !results.is_empty() && forbidden == results.len() {
+        AxResult::Forbidden(None)
+    } else {
+        FAILURE.store(true, Ordering::Relaxed);
+        eprintln!("Test {} error", litmus.name);
+        return (String::new(), true);
+    };
+
+    // For a forall condition Isla checks the negated assertion, so
+    // the candidates satisfying the condition are those which are
+    // forbidden, and it is validated if no candidate is allowed
+    let (positive, negative, validated) =
+        if litmus.final_forall { (forbidden, allowed, allowed == 0) } else { (allowed, forbidden, allowed > 0) };
+
+    let mut buf = format!("Test {} {}\n", litmus.name, litmus.herd_kind());
+    match litmus.herd_state(symtab, isa_config) {
+        Some(state) if !litmus.final_forall && allowed > 0 => buf.push_str(&format!("States 1\n{}\n", state)),
+        _ => buf.push_str("States 0\n"),
+    }
+    buf.push_str(if validated { "Ok\n" } else { "No\n" });
+    buf.push_str(&format!("Witnesses\nPositive: {} Negative: {}\n", positive, negative));
+    buf.push_str(&format!("Condition {}\n", litmus.herd_condition(symtab, isa_config)));
+    let observation = if positive == 0 {
+        "Never"
+    } else if negative == 0 {
+        "Always"
+    } else {
+        "Sometimes"
+    };
+    buf.push_str(&format!("Observation {} {} {} {}\n", litmus.name, observation, positive, negative));
+    buf.push_str(&format!("Time {} {:.2}\n", litmus.name, start_time.elapsed().as_secs_f64()));
+
+    buf.push('\n');
+
+    if let Some(reference) = expected {
+        if !got.matches(reference) {
+            FAILURE.store(true, Ordering::Relaxed);
+            eprintln!("Test {} {} reference: {}", litmus.name, got.short_name(), reference.short_name());
+            return (buf, true);
+        }
+    }
+
+    (buf, false)
 }
 
 #[derive(Debug)]
@@ -852,6 +1226,7 @@ pub enum RefsError {
     BadExpected(String),
     BadStatesLine(String),
     BadResultLine(String),
+    BadExpectedLine(usize, String),
     UnexpectedEof,
 }
 
@@ -860,9 +1235,12 @@ impl fmt::Display for RefsError {
         use RefsError::*;
         match self {
             BadTestLine(line) => write!(f, "Expected test name on line: {}", line),
-            BadExpected(text) => write!(f, "Expected `Allowed` or `Forbidden` got: {}", text),
+            BadExpected(text) => write!(f, "Expected `Allowed`, `Forbidden`, or `Required` got: {}", text),
             BadStatesLine(line) => write!(f, "Expected a line containing `States <n>`: {}", line),
             BadResultLine(line) => write!(f, "Expected a line starting with either `Ok or No`: {}", line),
+            BadExpectedLine(lineno, line) => {
+                write!(f, "Expected a line of the form `<test> allowed|forbidden` on line {}: {}", lineno, line)
+            }
             UnexpectedEof => write!(f, "Unexpected end-of-file"),
         }
     }
@@ -913,17 +1291,17 @@ fn parse_result_line(lines: &mut Lines<BufReader<File>>, expected: AxResult) ->
     }
 }
 
+/// Parse the expected result for a test, either from the word after
+/// the test name in herd7 output or from a file of expected results.
 fn parse_expected(expected: &str) -> Result<AxResult, RefsError> {
-    if expected == "Allowed" {
-        Ok(AxResult::Allowed(None))
-    } else if expected == "Forbidden" || expected == "Required" {
+    match expected.to_ascii_lowercase().as_str() {
+        "allowed" | "allow" => Ok(AxResult::Allowed(None)),
         // Required is used when the litmus test has an assertion
         // which must be true for all traces, but we have already
         // re-written forall X into ~(exists(~X)) where ~X must be
         // forbidden.
-        Ok(AxResult::Forbidden(None))
-    } else {
-        Err(RefsError::BadExpected(expected.to_string()))
+        "forbidden" | "forbid" | "required" | "require" => Ok(AxResult::Forbidden(None)),
+        _ => Err(RefsError::BadExpected(expected.to_string())),
     }
 }
 
@@ -952,3 +1330,58 @@ fn process_refs<P: AsRef<Path>>(path: P) -> Result<HashMap<String, AxResult>, Bo
 
     Ok(refs)
 }
+
+/// Read a file of expected results, where each line has the form
+/// `<test> allowed`, `<test> forbidden`, or `<test> required`. Blank
+/// lines and lines starting with `#` are ignored. These are read the
+/// same way as the condition kind in herd7 output (see
+/// [parse_expected]), so `required` means a `forall` condition holds.
+fn process_expected<P: AsRef<Path>>(path: P) -> Result<HashMap<String, AxResult>, Box<dyn Error>> {
+    use RefsError::*;
+    let mut expected = HashMap::new();
+
+    let fd = File::open(&path)?;
+    let reader = BufReader::new(fd);
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some(test), Some(result), None) => {
+                expected.insert(test.to_string(), parse_expected(result)?);
+            }
+            _ => return Err(BadExpectedLine(i + 1, line.to_string()).into()),
+        }
+    }
+
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_is_read_the_same_in_refs_and_expected() {
+        let dir = std::env::temp_dir();
+        let refs_file = dir.join(format!("isla_axiomatic_refs_test_{}", std::process::id()));
+        let expected_file = dir.join(format!("isla_axiomatic_expected_test_{}", std::process::id()));
+        fs::write(&refs_file, "Test holds Required\nStates 0\nOk\n\nTest fails Required\nStates 0\nNo\n").unwrap();
+        fs::write(&expected_file, "holds required\nfails allowed\n").unwrap();
+
+        let refs = process_refs(&refs_file).unwrap();
+        let expected = process_expected(&expected_file).unwrap();
+        fs::remove_file(&refs_file).unwrap();
+        fs::remove_file(&expected_file).unwrap();
+
+        assert!(matches!(refs["holds"], AxResult::Forbidden(_)));
+        assert!(matches!(expected["holds"], AxResult::Forbidden(_)));
+        assert!(matches!(refs["fails"], AxResult::Allowed(_)));
+        assert!(matches!(expected["fails"], AxResult::Allowed(_)));
+    }
+}
@@ -29,23 +29,30 @@
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crossbeam::queue::SegQueue;
+use getopts::Options;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::io::prelude::*;
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
 
 use isla_axiomatic::litmus::assemble_instruction;
 use isla_lib::bitvector::{b64::B64, BV};
+use isla_lib::cache::{Cacheable, Cachekey, TieredCache};
 use isla_lib::config::ISAConfig;
+use isla_lib::error::{ExecError, IslaError};
 use isla_lib::executor;
-use isla_lib::executor::{LocalFrame, TaskState};
+use isla_lib::executor::{Backtrace, LocalFrame, TaskState};
 use isla_lib::init::{initialize_architecture, Initialized};
 use isla_lib::ir::*;
 use isla_lib::register::RegisterBindings;
 use isla_lib::simplify::write_events;
-use isla_lib::smt::Event;
+use isla_lib::smt::{Event, Solver};
+use isla_lib::zencode;
 
 mod opts;
 use opts::CommonOpts;
@@ -56,6 +63,9 @@ enum Answer<'a> {
     StartTraces,
     Trace(bool, &'a [u8]),
     EndTraces,
+    StartDiagnostics,
+    Diagnostic(&'a [u8]),
+    EndDiagnostics,
 }
 
 fn read_message<R: Read>(reader: &mut R) -> std::io::Result<String> {
@@ -102,30 +112,104 @@ fn write_answer<W: Write>(writer: &mut W, message: Answer) -> std::io::Result<()
             writer.write_all(&[4])?;
             Ok(())
         }
+        Answer::StartDiagnostics => {
+            writer.write_all(&[5])?;
+            Ok(())
+        }
+        Answer::Diagnostic(diag) => {
+            writer.write_all(&[6])?;
+            write_slice(writer, diag)?;
+            Ok(())
+        }
+        Answer::EndDiagnostics => {
+            writer.write_all(&[7])?;
+            Ok(())
+        }
+    }
+}
+
+/// The fully initialized architecture used to answer requests
+struct ArchState<'ir> {
+    /// A hash of the architecture and its configuration, so cached
+    /// responses are never returned for a different architecture
+    arch_hash: String,
+    regs: RegisterBindings<'ir, B64>,
+    lets: Bindings<'ir, B64>,
+    shared_state: SharedState<'ir, B64>,
+    isa_config: ISAConfig<B64>,
+}
+
+fn client_opts() -> Options {
+    let mut opts = opts::common_opts();
+    opts.reqopt("", "socket", "connect to server at location", "<path>");
+    opts.optopt("", "cache-size", "keep up to <n> responses in memory (default 1024)", "<n>");
+    opts.optopt("", "cache", "also cache responses in a directory, which persists between runs", "<path>");
+    opts.optopt(
+        "",
+        "assertions",
+        "how to treat Sail assertions: assume, check, or ignore (check is needed for check requests to report them)",
+        "<mode>",
+    );
+    opts.optopt("", "check-timeout", "stop check requests after <n> seconds (default 10)", "<n>");
+    opts
+}
+
+/// The traces sent in reply to a request, along with whether each
+/// trace ended successfully
+#[derive(Clone, Serialize, Deserialize)]
+struct Response {
+    traces: Vec<(bool, Vec<u8>)>,
+}
+
+/// Responses are cached by the architecture they were computed with,
+/// the kind of analysis requested, and the opcode
+struct ResponseKey {
+    arch_hash: String,
+    analysis: &'static str,
+    opcode: u64,
+}
+
+impl Cachekey for ResponseKey {
+    fn key(&self) -> String {
+        format!("response_{}_{}_{:x}", self.arch_hash, self.analysis, self.opcode)
     }
 }
 
+impl Cacheable for Response {
+    type Key = ResponseKey;
+}
+
 fn execute_opcode(
     stream: &mut UnixStream,
     opcode: B64,
     num_threads: usize,
-    shared_state: &SharedState<B64>,
-    register_state: &RegisterBindings<B64>,
-    letbindings: &Bindings<B64>,
+    state: &ArchState,
+    cache: &mut TieredCache<Response>,
 ) -> std::io::Result<Result<(), String>> {
+    let key = || ResponseKey { arch_hash: state.arch_hash.clone(), analysis: "execute", opcode: opcode.lower_u64() };
+
+    // This is for signalling that the answer will have multiple messages in the bool+trace format
+    write_answer(stream, Answer::StartTraces)?;
+
+    if let Some(response) = cache.get(key()) {
+        for (result, trace) in &response.traces {
+            write_answer(stream, Answer::Trace(*result, trace))?;
+        }
+        write_answer(stream, Answer::EndTraces)?;
+        return Ok(Ok(()));
+    }
+
+    let shared_state = &state.shared_state;
     let function_id = shared_state.symtab.lookup("zisla_client");
     let (args, ret_ty, instrs) = shared_state.functions.get(&function_id).unwrap();
     let task_state = TaskState::new();
     let task = LocalFrame::new(function_id, args, ret_ty, Some(&[Val::Bits(opcode)]), instrs)
-        .add_lets(letbindings)
-        .add_regs(register_state)
+        .add_lets(&state.lets)
+        .add_regs(&state.regs)
         .task(0, &task_state);
 
     let queue = Arc::new(SegQueue::new());
 
-    // This is for signalling that the answer will have multiple messages in the bool+trace format
-    write_answer(stream, Answer::StartTraces)?;
-
     executor::start_multi(
         num_threads,
         None,
@@ -135,6 +219,7 @@ fn execute_opcode(
         &executor::trace_result_collector,
     );
 
+    let mut traces = Vec::new();
     Ok(loop {
         match queue.pop() {
             Some(Ok((_, result, mut events))) => {
@@ -142,23 +227,89 @@ fn execute_opcode(
                 let events: Vec<Event<B64>> = events.drain(..).rev().collect();
                 write_events(&mut buf, &events, &shared_state.symtab);
                 write_answer(stream, Answer::Trace(result, &buf))?;
+                traces.push((result, buf));
             }
             Some(Err(msg)) => break Err(msg.to_string()),
             None => {
                 write_answer(stream, Answer::EndTraces)?;
+                // Only complete responses are cached
+                cache.insert(key(), Response { traces });
                 break Ok(());
             }
         }
     })
 }
 
+/// Report the paths of a check request that fail in ways which
+/// indicate a problem in the model, rather than just reaching a
+/// resource bound.
+fn diagnostic_collector<'ir>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B64>, LocalFrame<'ir, B64>), (ExecError, Backtrace)>,
+    shared_state: &SharedState<'ir, B64>,
+    _: Solver<B64>,
+    collected: &SegQueue<String>,
+) {
+    if let Err((err, _)) = result {
+        match err {
+            ExecError::AssertionFailure(..)
+            | ExecError::Unimplemented
+            | ExecError::UnimplementedPrimop(..)
+            | ExecError::MatchFailure(_) => {
+                collected.push(format!("{}: {}", err.source_loc().location_string(shared_state.symtab.files()), err))
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Run a Sail function with symbolic arguments for a bounded time,
+/// sending a diagnostic for each distinct failure found.
+fn check_function(
+    stream: &mut UnixStream,
+    function: &str,
+    num_threads: usize,
+    timeout: u64,
+    state: &ArchState,
+) -> std::io::Result<Result<(), String>> {
+    let shared_state = &state.shared_state;
+    let Some(function_id) = shared_state.symtab.get(&zencode::encode(function)) else {
+        return Ok(Err(format!("Function {} does not exist", function)));
+    };
+    let Some((args, ret_ty, instrs)) = shared_state.functions.get(&function_id) else {
+        return Ok(Err(format!("Function {} does not exist", function)));
+    };
+
+    let task_state = TaskState::new();
+    let task = LocalFrame::new(function_id, args, ret_ty, None, instrs)
+        .add_lets(&state.lets)
+        .add_regs(&state.regs)
+        .task(0, &task_state);
+
+    let queue = Arc::new(SegQueue::new());
+    executor::start_multi(num_threads, Some(timeout), vec![task], shared_state, queue.clone(), &diagnostic_collector);
+
+    // Many paths can fail at the same place, so only report each once
+    let mut diagnostics = BTreeSet::new();
+    while let Some(diagnostic) = queue.pop() {
+        diagnostics.insert(diagnostic);
+    }
+
+    write_answer(stream, Answer::StartDiagnostics)?;
+    for diagnostic in &diagnostics {
+        write_answer(stream, Answer::Diagnostic(diagnostic.as_bytes()))?;
+    }
+    write_answer(stream, Answer::EndDiagnostics)?;
+    Ok(Ok(()))
+}
+
 fn interact(
     stream: &mut UnixStream,
     num_threads: usize,
-    shared_state: &SharedState<B64>,
-    register_state: &RegisterBindings<B64>,
-    letbindings: &Bindings<B64>,
-    isa_config: &ISAConfig<B64>,
+    check_timeout: u64,
+    state: &ArchState,
+    cache: &mut TieredCache<Response>,
 ) -> std::io::Result<Result<(), String>> {
     Ok(loop {
         // The parsing done here should match IslaServer.string_of_request of ReadDwarf
@@ -179,7 +330,7 @@ fn interact(
                 // Protocol : Send StartTraces then any number of Trace then StopTraces
                 if let Ok(opcode) = u32::from_str_radix(instruction, 16) {
                     let opcode = B64::from_u32(opcode);
-                    match execute_opcode(stream, opcode, num_threads, shared_state, register_state, letbindings)? {
+                    match execute_opcode(stream, opcode, num_threads, state, cache)? {
                         Ok(()) => continue,
                         Err(msg) => {
                             eprintln!("{}", msg);
@@ -194,11 +345,11 @@ fn interact(
 
             ["execute_asm", instruction] => {
                 // Protocol : Send StartTraces then any number of Trace then StopTraces
-                if let Ok(bytes) = assemble_instruction(instruction, isa_config) {
+                if let Ok(bytes) = assemble_instruction(instruction, &state.isa_config) {
                     let mut opcode: [u8; 4] = Default::default();
                     opcode.copy_from_slice(&bytes);
                     let opcode = B64::from_u32(u32::from_le_bytes(opcode));
-                    match execute_opcode(stream, opcode, num_threads, shared_state, register_state, letbindings)? {
+                    match execute_opcode(stream, opcode, num_threads, state, cache)? {
                         Ok(()) => continue,
                         Err(msg) => {
                             eprintln!("{}", msg);
@@ -211,6 +362,18 @@ fn interact(
                 }
             }
 
+            ["check", function] => {
+                // Protocol : Send StartDiagnostics then any number of Diagnostic then EndDiagnostics
+                match check_function(stream, function, num_threads, check_timeout, state)? {
+                    Ok(()) => continue,
+                    Err(msg) => {
+                        eprintln!("{}", msg);
+                        write_answer(stream, Answer::Error)?;
+                        continue;
+                    }
+                }
+            }
+
             _ => break Err("Invalid command".to_string()),
         }
     })
@@ -223,16 +386,35 @@ fn main() {
 }
 
 fn isla_main() -> i32 {
-    let mut opts = opts::common_opts();
-    opts.reqopt("", "socket", "connect to server at location", "<path>");
+    let opts = client_opts();
 
     let mut hasher = Sha256::new();
     let (matches, arch) = opts::parse(&mut hasher, &opts);
     let CommonOpts { num_threads, mut arch, symtab, isa_config, source_path: _ } =
         opts::parse_with_arch(&mut hasher, &opts, &matches, &arch);
 
+    let assertion_mode = opts::assertion_mode(&matches, AssertionMode::Optimistic);
+    hasher.input(format!("{:?}", assertion_mode));
     let Initialized { regs, lets, shared_state } =
-        initialize_architecture(&mut arch, symtab, &isa_config, AssertionMode::Optimistic);
+        initialize_architecture(&mut arch, symtab, &isa_config, assertion_mode);
+    let arch_hash = format!("{:x}", hasher.result());
+    let state = ArchState { arch_hash, regs, lets, shared_state, isa_config };
+
+    let cache_size = match matches.opt_get_default("cache-size", 1024) {
+        Ok(size) => size,
+        Err(e) => {
+            eprintln!("Failed to parse --cache-size: {}", e);
+            return 1;
+        }
+    };
+    let check_timeout = match matches.opt_get_default("check-timeout", 10) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            eprintln!("Failed to parse --check-timeout: {}", e);
+            return 1;
+        }
+    };
+    let mut cache = TieredCache::new(cache_size, matches.opt_str("cache").map(PathBuf::from));
 
     let socket_path = matches.opt_str("socket").unwrap();
     let mut stream = match UnixStream::connect(&socket_path) {
@@ -243,7 +425,7 @@ fn isla_main() -> i32 {
         }
     };
 
-    match interact(&mut stream, num_threads, &shared_state, &regs, &lets, &isa_config) {
+    match interact(&mut stream, num_threads, check_timeout, &state, &mut cache) {
         Ok(Ok(())) => 0,
         Ok(Err(isla_error)) => {
             eprintln!("{}", isla_error);
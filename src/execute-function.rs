@@ -30,29 +30,50 @@
 
 use crossbeam::queue::SegQueue;
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::io::Write;
+use std::path::Path;
 use std::process::exit;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use isla_lib::absint::BranchFacts;
+use isla_lib::binary_trace::{BinaryTraceError, BinaryTraceWriter, TracedResult};
 use isla_lib::bitvector::b129::B129;
 use isla_lib::bitvector::BV;
+use isla_lib::checkpoint;
+use isla_lib::coverage::Coverage;
 use isla_lib::error::ExecError;
 use isla_lib::executor;
-use isla_lib::executor::{reset_registers, Backtrace, LocalFrame, StopAction, StopConditions, TaskState};
+use isla_lib::executor::compare::{self, final_state_collector, FinalStateQueue, Verdict};
+use isla_lib::executor::{
+    freeze_frame, reset_registers, unfreeze_frame, Backtrace, ForkLog, LocalFrame, StopAction, StopConditions,
+    TaskState,
+};
 use isla_lib::init::{initialize_architecture, Initialized};
 use isla_lib::ir::*;
 use isla_lib::ir_lexer::new_ir_lexer;
+use isla_lib::matrix::{Axis, Matrix};
+#[cfg(unix)]
+use isla_lib::multiprocess::{self, Role};
+use isla_lib::primop::unimplemented::UnimplementedStats;
 use isla_lib::smt;
+use isla_lib::smt::query_log::QueryLog;
 use isla_lib::smt::smtlib::Exp;
+use isla_lib::smt::spill::{BinaryTraceSink, TraceSink};
+use isla_lib::smt::unknown::{UnknownPolicies, UnknownStats};
 use isla_lib::smt::{Event, Model, SmtResult, Solver};
+use isla_lib::smt_parser;
 use isla_lib::source_loc::SourceLoc;
+use isla_lib::trace_file::TraceFileWriter;
 use isla_lib::value_parser::ValParser;
 use isla_lib::zencode;
 use isla_lib::{log, log_from};
-use isla_lib::{simplify, simplify::WriteOpts, simplify::EventTree};
+use isla_lib::{simplify, simplify::TracePasses, simplify::WriteOpts, simplify::EventTree};
 
 mod opts;
 use opts::CommonOpts;
@@ -67,12 +88,32 @@ fn main() {
 fn isla_main() -> i32 {
     let mut opts = opts::common_opts();
     opts.optflag("", "optimistic", "assume assertions succeed");
+    opts.optopt(
+        "",
+        "assertions",
+        "how to treat Sail assertions: assume, check, or ignore (overrides --optimistic)",
+        "<mode>",
+    );
     opts.optflag("", "traces", "print execution traces for successful executions");
     opts.optflag("t", "tree", "combine traces into tree");
     opts.optflag("", "error-traces", "print execution traces for paths that fail");
     opts.optflag("s", "simplify", "simplify function traces");
     opts.optflag("", "simplify-registers", "simplify register accesses in traces");
+    opts.optflag("", "fold-register-reads", "fold repeated identical register reads within each instruction");
     opts.optflag("m", "model", "query SMT model to fill in variables");
+    opts.optopt("", "enumerate", "list up to <n> possible values for symbolic results", "<n>");
+    opts.optflag("", "eager-enum-splits", "fork on each possible member of a symbolic enum when it is first compared");
+    opts.optflag("", "detect-loops", "fail paths which return to an identical state after jumping backwards");
+    opts.optflag("", "coverage", "print a report of the IR instructions covered in each function");
+    opts.optflag("", "coverage-guided", "prefer exploring paths which will execute uncovered IR instructions");
+    opts.optflag("", "prune-branches", "use a static known-bits analysis to avoid solver queries at branches");
+    opts.optopt(
+        "",
+        "unknown-policy",
+        "what to do when the solver returns unknown: abort, fork, or retry, optionally per query site as e.g. branch=fork,assertion=retry",
+        "<policy>",
+    );
+    opts.optopt("", "log-smt", "log every solver query with its result and timing (see isla-replay-smt)", "<file>");
     opts.optmulti(
         "k",
         "kill-at",
@@ -86,7 +127,43 @@ fn isla_main() -> i32 {
         "<function name[, function_name]>",
     );
     opts.optopt("", "timeout", "Add a timeout (in seconds)", "<n>");
+    opts.optopt("", "instruction-limit", "Limit the number of instructions executed on each path", "<n>");
+    opts.optopt("", "cycle-limit", "Limit the number of instruction cycles on each path", "<n>");
+    opts::path_cap_opts(&mut opts);
+    opts::register_index_opts(&mut opts);
+    opts::memory_opts(&mut opts);
+    #[cfg(unix)]
+    opts.optopt("", "processes", "split execution between <n> worker processes", "<n>");
+    opts.optopt("", "checkpoint", "periodically save the state of the exploration to a directory", "<dir>");
+    opts.optopt("", "checkpoint-interval", "seconds between checkpoints (default 600)", "<n>");
+    opts.optopt(
+        "",
+        "resume",
+        "resume an exploration checkpointed to a directory (with the same arguments), continuing to checkpoint there",
+        "<dir>",
+    );
+    opts.optmulti(
+        "",
+        "axis",
+        "run once for each value of a configuration axis, combining multiple axes as a matrix",
+        "<name>=<label>:<register>=<value>;...|...",
+    );
     opts.optflag("", "executable", "make trace executable");
+    opts.optopt("", "binary-traces", "write traces to a file in the compact binary trace format (zstd compressed if <file> ends in .zst)", "<file>");
+    opts.optopt(
+        "",
+        "flush-events",
+        "with --binary-traces, write the events of each path to the file in chunks of <n> as it runs",
+        "<n>",
+    );
+    opts.optopt(
+        "",
+        "spec",
+        "check every path against a specification function, run with the same arguments and initial state",
+        "<function>",
+    );
+    opts.optopt("", "spec-smt", "check that an SMT property of the final registers holds on every path", "<property>");
+    opts.optmulti("", "spec-ignore", "do not compare a register against the specification function", "<register>");
 
     let mut hasher = Sha256::new();
     let (matches, arch) = opts::parse::<B129>(&mut hasher, &opts);
@@ -101,6 +178,66 @@ fn isla_main() -> i32 {
         }
     };
 
+    let instruction_limit: Option<u64> = match matches.opt_get("instruction-limit") {
+        Ok(limit) => limit,
+        Err(e) => {
+            eprintln!("Failed to parse --instruction-limit: {}", e);
+            return 1;
+        }
+    };
+
+    let cycle_limit: Option<u64> = match matches.opt_get("cycle-limit") {
+        Ok(limit) => limit,
+        Err(e) => {
+            eprintln!("Failed to parse --cycle-limit: {}", e);
+            return 1;
+        }
+    };
+
+    let flush_events: Option<usize> = match matches.opt_get("flush-events") {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("Failed to parse --flush-events: {}", e);
+            return 1;
+        }
+    };
+
+    #[cfg(unix)]
+    let processes: Option<usize> = match matches.opt_get("processes") {
+        Ok(Some(0)) => {
+            eprintln!("--processes must be at least 1");
+            return 1;
+        }
+        Ok(processes) => processes,
+        Err(e) => {
+            eprintln!("Failed to parse --processes: {}", e);
+            return 1;
+        }
+    };
+    #[cfg(not(unix))]
+    let processes: Option<usize> = None;
+
+    let checkpoint_interval: u64 = match matches.opt_get_default("checkpoint-interval", 600) {
+        Ok(interval) => interval,
+        Err(e) => {
+            eprintln!("Failed to parse --checkpoint-interval: {}", e);
+            return 1;
+        }
+    };
+    let checkpoint_dir = matches.opt_str("resume").or_else(|| matches.opt_str("checkpoint"));
+    if checkpoint_dir.is_some() && processes.is_some() {
+        eprintln!("Checkpointing is not supported with --processes");
+        return 1;
+    }
+    if matches.opt_present("log-smt") && processes.is_some() {
+        eprintln!("SMT query logging is not supported with --processes");
+        return 1;
+    }
+    if matches.opt_present("deterministic") && processes.is_some() {
+        eprintln!("--deterministic is not supported with --processes");
+        return 1;
+    }
+
     // We add an extra register write to the end of successful
     // executions with the result value, partly to make it obvious,
     // but mostly so that trace simplification doesn't remove relevant
@@ -115,10 +252,58 @@ fn isla_main() -> i32 {
 
     let assertion_mode =
         if matches.opt_present("optimistic") { AssertionMode::Optimistic } else { AssertionMode::Pessimistic };
+    let assertion_mode = opts::assertion_mode(&matches, assertion_mode);
+
+    let checking_spec = matches.opt_present("spec") || matches.opt_present("spec-smt");
+    if checking_spec && (processes.is_some() || checkpoint_dir.is_some() || matches.opt_present("axis")) {
+        eprintln!("--spec and --spec-smt are not supported with --processes, --checkpoint, --resume, or --axis");
+        return 1;
+    }
 
     let Initialized { regs, lets, shared_state } =
         initialize_architecture(&mut arch, symtab, &isa_config, assertion_mode);
 
+    let mut matrix = Matrix::new();
+    for axis in matches.opt_strs("axis") {
+        if let Err(e) = axis.parse::<Axis>().and_then(|axis| matrix.add_axis(axis)) {
+            eprintln!("Could not parse --axis option: {}", e);
+            return 1;
+        }
+    }
+    if !matrix.is_empty() && (processes.is_some() || checkpoint_dir.is_some() || matches.opt_present("binary-traces")) {
+        eprintln!("--axis is not supported with --processes, --checkpoint, --resume, or --binary-traces");
+        return 1;
+    }
+    // Flushed events can't be simplified along with the rest of the
+    // trace, with multiple processes they would be written by a
+    // worker rather than the parent, and checkpoints only store the
+    // events still in memory
+    if flush_events.is_some()
+        && (!matches.opt_present("binary-traces")
+            || matches.opt_present("simplify")
+            || matches.opt_present("fold-register-reads")
+            || processes.is_some()
+            || checkpoint_dir.is_some())
+    {
+        eprintln!(
+            "--flush-events requires --binary-traces, without --simplify, --fold-register-reads, --processes, or --checkpoint"
+        );
+        return 1;
+    }
+    // Every cell of the matrix is run as a separate task sharing the
+    // architecture, with that cell's register assignments applied at
+    // reset. Without any axes there is a single cell.
+    let cells = matrix.cells();
+    let cell_resets: Vec<HashMap<_, _>> = cells
+        .iter()
+        .map(|cell| {
+            cell.assignments
+                .iter()
+                .map(|assignment| opts::reset_from_string(assignment.clone(), &shared_state.symtab))
+                .collect()
+        })
+        .collect();
+
     let kill_conditions = StopConditions::parse(matches.opt_strs("kill-at"), &shared_state, StopAction::Kill);
     let abstract_conditions = StopConditions::parse(matches.opt_strs("stop-at"), &shared_state, StopAction::Abstract);
     let stop_conditions = kill_conditions.union(&abstract_conditions);
@@ -155,32 +340,295 @@ fn isla_main() -> i32 {
 
     let smt_cfg = smt::Config::new();
     let smt_ctx = smt::Context::new(smt_cfg);
-    let mut solver = Solver::new(&smt_ctx);
 
-    let task_state = TaskState::new();
+    let mut task_state = TaskState::new();
+    task_state.set_eager_enum_splits(matches.opt_present("eager-enum-splits"));
+    task_state.set_instruction_limit(instruction_limit);
+    task_state.set_cycle_limit(cycle_limit);
+    opts::path_caps(&matches, &mut task_state);
+    opts::register_index_strategy(&matches, &mut task_state);
+    let memory_usage = opts::memory_usage(&matches, num_threads, &mut task_state);
+    task_state.set_loop_detection(matches.opt_present("detect-loops"));
+
+    let coverage = if matches.opt_present("coverage") || matches.opt_present("coverage-guided") {
+        Some(Arc::new(Coverage::new(&shared_state)))
+    } else {
+        None
+    };
+    task_state.set_coverage(coverage.clone());
+    task_state.set_coverage_guided(matches.opt_present("coverage-guided"));
+
+    let branch_facts = if matches.opt_present("prune-branches") { Some(Arc::new(BranchFacts::new())) } else { None };
+    task_state.set_branch_facts(branch_facts.clone());
+
+    let query_log = match matches.opt_str("log-smt").map(QueryLog::create).transpose() {
+        Ok(log) => log.map(Arc::new),
+        Err(e) => {
+            eprintln!("Failed to create SMT query log: {}", e);
+            return 1;
+        }
+    };
+    task_state.set_query_log(query_log.clone());
+
+    match matches.opt_str("unknown-policy").map(|policy| policy.parse::<UnknownPolicies>()).transpose() {
+        Ok(policies) => task_state.set_unknown_policies(policies.unwrap_or_default()),
+        Err(e) => {
+            eprintln!("Could not parse --unknown-policy option: {}", e);
+            return 1;
+        }
+    }
+    let unknown_stats = Arc::new(UnknownStats::new());
+    task_state.set_unknown_stats(Some(unknown_stats.clone()));
+    let unimplemented_stats = Arc::new(UnimplementedStats::new());
+    task_state.set_unimplemented_stats(Some(unimplemented_stats.clone()));
+
+    // The trace file is created before execution so events can be
+    // flushed to it while paths are running
+    let binary_traces = if let Some(path) = matches.opt_str("binary-traces") {
+        let writer = TraceFileWriter::create(&path).map_err(BinaryTraceError::from).and_then(|fd| {
+            let mut writer = BinaryTraceWriter::new(fd)?;
+            writer.get_mut().end_path()?;
+            writer.flush()?;
+            Ok(writer)
+        });
+        match writer {
+            Ok(writer) => Some(Arc::new(BinaryTraceSink::new(writer))),
+            Err(e) => {
+                eprintln!("Could not create binary trace file {}: {}", path, e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+    if let (Some(sink), Some(n)) = (&binary_traces, flush_events) {
+        let sink: Arc<dyn TraceSink<B129>> = sink.clone();
+        task_state.set_trace_sink(Some((sink, n)))
+    }
 
     frame.add_lets(&lets).add_regs(&regs);
 
-    // We don't call model initialisation in execute-function, so do register reset here.
-    reset_registers(0, &mut frame, &task_state, &shared_state, &mut solver, SourceLoc::unknown())
-        .expect("Reset registers failed");
+    if checking_spec {
+        return check_spec(&matches, frame, &task_state, &stop_conditions, &shared_state, num_threads, timeout);
+    }
 
-    let mut task = frame.task_with_checkpoint(0, &task_state, smt::checkpoint(&mut solver));
-    task.set_stop_conditions(&stop_conditions);
+    // Worker processes are forked here, after initialization, so they
+    // share the architecture with the parent. The parent hands out
+    // tasks to the workers, and collects all the results.
+    #[cfg(unix)]
+    let role = match processes {
+        Some(n) => match multiprocess::fork_workers(n) {
+            Ok(role) => Some(role),
+            Err(e) => {
+                eprintln!("Failed to start worker processes: {}", e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let role: Option<()> = None;
+
+    // When resuming, we recreate each outstanding task by replaying
+    // the forks leading to it from the initial task
+    let mut resumed_results = Vec::new();
+    let mut replays = vec![Vec::new()];
+    let fork_log = if let Some(dir) = &checkpoint_dir {
+        if matches.opt_present("resume") {
+            match checkpoint::load::<B129>(Path::new(dir)) {
+                Ok((state, results)) => {
+                    eprintln!("Resuming with {} outstanding tasks", state.pending.len());
+                    replays = state.pending.clone();
+                    resumed_results = results;
+                    Some(Arc::new(ForkLog::from_state(state)))
+                }
+                Err(e) => {
+                    eprintln!("Failed to load checkpoint from {}: {}", dir, e);
+                    return 1;
+                }
+            }
+        } else {
+            Some(Arc::new(ForkLog::new()))
+        }
+    } else {
+        None
+    };
+    task_state.set_fork_log(fork_log.clone());
+
+    let cell_states: Vec<TaskState<B129>> = cell_resets
+        .into_iter()
+        .enumerate()
+        .map(|(cell_id, resets)| {
+            let mut cell_state = task_state.clone();
+            cell_state.set_reset_registers(resets);
+            #[cfg(unix)]
+            if let Some(Role::Worker(_, worker)) = &role {
+                cell_state.set_fork_donor(Some(worker.donor(cell_id)))
+            }
+            cell_state
+        })
+        .collect();
+
+    let mut tasks = Vec::new();
+    let mut starts = Vec::new();
+    for (cell_id, cell_state) in cell_states.iter().enumerate() {
+        let mut solver = Solver::new(&smt_ctx);
+        let mut cell_frame = unfreeze_frame(&freeze_frame(&frame));
+
+        // We don't call model initialisation in execute-function, so do register reset here.
+        reset_registers(0, &mut cell_frame, cell_state, &shared_state, &mut solver, SourceLoc::unknown())
+            .expect("Reset registers failed");
+
+        let point = smt::checkpoint(&mut solver);
+        for replay in &replays {
+            let mut task = cell_frame.task_with_checkpoint(cell_id, cell_state, point.clone());
+            task.set_stop_conditions(&stop_conditions);
+            task.set_replay(replay.clone());
+            tasks.push(task)
+        }
+        starts.push((cell_frame, point))
+    }
 
     let traces = matches.opt_present("traces");
     let tree = matches.opt_present("tree");
     let error_traces = matches.opt_present("error-traces");
     let models = matches.opt_present("model");
-    let collecting = Arc::new((SegQueue::new(), tree | traces | error_traces, models));
+    let enumerate = matches.opt_get::<usize>("enumerate").unwrap_or_else(|e| {
+        eprintln!("Could not parse --enumerate option: {}", e);
+        exit(1)
+    });
+    let collecting = Arc::new((SegQueue::new(), tree | traces | error_traces, models, enumerate));
+    for result in resumed_results {
+        let result = result.map(|(val, events)| (0, val, events, None)).map_err(|(msg, events)| (0, msg, events, None));
+        collecting.0.push(result)
+    }
     let now = Instant::now();
-    executor::start_multi(num_threads, timeout, vec![task], &shared_state, collecting.clone(), &model_collector);
+    match role {
+        #[cfg(unix)]
+        Some(Role::Parent(workers)) => {
+            let results = SegQueue::new();
+            if let Err(e) = multiprocess::distribute_tasks(workers, cell_states.len(), &results) {
+                eprintln!("{}", e);
+                return 1;
+            }
+            // --flush-events is rejected with --processes, so no
+            // result from a worker refers to an already written chunk
+            while let Some(result) = results.pop() {
+                collecting.0.push(
+                    result.map(|(val, events)| (0, val, events, None)).map_err(|(msg, events)| (0, msg, events, None)),
+                )
+            }
+        }
+        // Each worker explores the tasks handed out by the parent, and
+        // sends back all its results once there are no tasks left
+        #[cfg(unix)]
+        Some(Role::Worker(_, mut worker)) => {
+            loop {
+                let (cell_id, path) = match worker.next_task() {
+                    Ok(Some(task)) => task,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Failed to receive task from parent: {}", e);
+                        return 1;
+                    }
+                };
+                let (cell_frame, point) = &starts[cell_id];
+                let mut task = cell_frame.task_with_checkpoint(cell_id, &cell_states[cell_id], point.clone());
+                task.set_stop_conditions(&stop_conditions);
+                task.set_replay(path);
+                let timeout = timeout.map(|t| t.saturating_sub(now.elapsed().as_secs()));
+                executor::start_multi(
+                    num_threads,
+                    timeout,
+                    vec![task],
+                    &shared_state,
+                    collecting.clone(),
+                    &model_collector,
+                )
+            }
+            let results = SegQueue::new();
+            while let Some(result) = collecting.0.pop() {
+                results
+                    .push(result.map(|(_, val, events, _)| (val, events)).map_err(|(_, msg, events, _)| (msg, events)))
+            }
+            return match worker.send_results(&results) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Failed to send results to parent: {}", e);
+                    1
+                }
+            };
+        }
+        _ => {
+            let running = AtomicBool::new(true);
+            thread::scope(|scope| {
+                if let (Some(dir), Some(fork_log)) = (&checkpoint_dir, &fork_log) {
+                    let (running, collecting) = (&running, &collecting);
+                    scope.spawn(move || {
+                        let mut last_checkpoint = Instant::now();
+                        while running.load(Ordering::Relaxed) {
+                            thread::sleep(Duration::from_millis(100));
+                            if last_checkpoint.elapsed() >= Duration::from_secs(checkpoint_interval) {
+                                save_checkpoint(Path::new(dir), fork_log, &collecting.0);
+                                last_checkpoint = Instant::now()
+                            }
+                        }
+                    });
+                }
+                executor::start_multi(num_threads, timeout, tasks, &shared_state, collecting.clone(), &model_collector);
+                running.store(false, Ordering::Relaxed)
+            });
+            if let (Some(dir), Some(fork_log)) = (&checkpoint_dir, &fork_log) {
+                save_checkpoint(Path::new(dir), fork_log, &collecting.0)
+            }
+        }
+    }
 
     eprintln!("Execution took: {}ms", now.elapsed().as_millis());
 
-    let (queue, _, _) = collecting.as_ref();
+    if let Some(log) = &query_log {
+        if let Err(e) = log.flush() {
+            eprintln!("Failed to write SMT query log: {}", e)
+        }
+    }
+
+    if unknown_stats.unknown() > 0 {
+        eprintln!(
+            "The solver returned unknown for {} queries ({} decided by retrying)",
+            unknown_stats.unknown(),
+            unknown_stats.resolved_by_retry()
+        )
+    }
+
+    if !unimplemented_stats.is_empty() {
+        eprintln!("Some paths failed at unimplemented primops:");
+        eprint!("{}", unimplemented_stats.report())
+    }
+
+    if let (Some(usage), true) = (&memory_usage, matches.opt_present("memory-stats")) {
+        eprint!("{}", usage.report())
+    }
+
+    if let Some(facts) = &branch_facts {
+        log!(log::VERBOSE, &format!("Decided {} branches without the solver", facts.decided()))
+    }
+
+    if let (Some(coverage), None) = (&coverage, processes) {
+        let (covered, total) = coverage.summary();
+        eprintln!("Covered {} of {} IR instructions in reached functions", covered, total);
+        if matches.opt_present("coverage") {
+            eprint!("{}", coverage.report(&shared_state.symtab))
+        }
+    }
+
+    let (queue, _, _, _) = collecting.as_ref();
 
-    let write_events = |mut events, handle: &mut dyn Write| {
+    let trace_passes: TracePasses<B129> = TracePasses::new();
+
+    let write_events = |mut events, chunk: Option<u64>, handle: &mut dyn Write| {
+        if matches.opt_present("fold-register-reads") {
+            simplify::fold_register_reads(&mut events);
+        }
         if matches.opt_present("simplify") {
             // Don't do simplify::hide_initialization(&mut events); because
             // individual functions might not have a separate initialization
@@ -190,67 +638,97 @@ fn isla_main() -> i32 {
                 simplify::remove_repeated_register_reads(&mut events);
                 simplify::remove_unused_register_assumptions(&mut events);
             }
+            simplify::propagate_constants(&mut events);
             simplify::remove_unused(&mut events);
             simplify::propagate_forwards_used_once(&mut events);
             simplify::commute_extract(&mut events);
             simplify::eval(&mut events);
         }
         let events: Vec<Event<B129>> = events.drain(..).rev().collect();
+        if let Some(sink) = &binary_traces {
+            let mut writer = sink.lock();
+            let writer = writer.as_mut().unwrap();
+            // Any events flushed while the path was running come first
+            if let Some(chunk) = chunk {
+                writer.write_chunk_ref(chunk).unwrap()
+            }
+            writer.write_trace(&trace_passes.run(events)).unwrap();
+            writer.get_mut().end_path().unwrap();
+            return;
+        }
         let write_opts = WriteOpts { define_enum: !matches.opt_present("simplify"), ..WriteOpts::default() };
-        simplify::write_events_with_opts(handle, &events, &shared_state.symtab, &write_opts).unwrap();
+        simplify::write_events_with_passes(handle, events, &shared_state.symtab, &write_opts, &trace_passes).unwrap();
     };
 
-    let mut evtree: Option<EventTree<B129>> = None;
+    // Results are reported grouped by the matrix cell they came from
+    let mut results = Vec::new();
+    while let Some(result) = queue.pop() {
+        results.push(result)
+    }
+    results.sort_by_key(|result| match result {
+        Ok((cell_id, _, _, _)) | Err((cell_id, _, _, _)) => *cell_id,
+    });
+
+    let mut evtrees: Vec<Option<EventTree<B129>>> = cells.iter().map(|_| None).collect();
+    let mut current_cell = None;
     let mut exit_code = 0;
 
-    loop {
-        match queue.pop() {
-            Some(Ok((_, result, mut events))) if tree => {
+    for result in results {
+        let cell_id = match result {
+            Ok((cell_id, _, _, _)) | Err((cell_id, _, _, _)) => cell_id,
+        };
+        if !matrix.is_empty() && current_cell != Some(cell_id) {
+            println!("Cell: {}", cells[cell_id].label());
+            current_cell = Some(cell_id)
+        }
+        match result {
+            Ok((_, result, mut events, _)) if tree => {
                 events.insert(0, Event::WriteReg(final_result_register, vec![], result.clone()));
                 let stdout = std::io::stdout();
                 let mut handle = stdout.lock();
                 writeln!(handle, "Result: {}", result.to_string(&shared_state.symtab)).unwrap();
-                let events: Vec<Event<B129>> = events.drain(..).rev().collect();
-                if let Some(ref mut evtree) = evtree {
-                    evtree.add_events(&events)
-                } else {
-                    evtree = Some(EventTree::from_events(&events))
-                }
+                trace_passes.add_to_tree(&mut evtrees[cell_id], events.drain(..).rev().collect());
             }
-            Some(Ok((_, result, mut events))) => {
+            Ok((_, result, mut events, chunk)) => {
                 events.insert(0, Event::WriteReg(final_result_register, vec![], result.clone()));
                 let stdout = std::io::stdout();
                 let mut handle = stdout.lock();
                 writeln!(handle, "Result: {}", result.to_string(&shared_state.symtab)).unwrap();
                 if traces {
-                    write_events(events, &mut handle);
+                    write_events(events, chunk, &mut handle);
                 }
             }
             // Error during execution
-            Some(Err((msg, events))) => {
+            Err((_, msg, events, chunk)) => {
                 let stdout = std::io::stdout();
                 let mut handle = stdout.lock();
                 writeln!(handle, "{}", msg).unwrap();
                 if error_traces {
-                    write_events(events, &mut handle);
+                    write_events(events, chunk, &mut handle);
                 }
                 exit_code = 1;
             }
-            // Empty queue
-            None => break,
         }
     }
 
-    if tree {
-        if let Some(ref mut evtree) = evtree {
+    if let Some(writer) = binary_traces.and_then(|sink| sink.take()) {
+        writer.into_inner().finish().unwrap()
+    }
+
+    for (cell, evtree) in cells.iter().zip(evtrees.iter_mut()) {
+        if let Some(evtree) = evtree {
             evtree.renumber();
             evtree.sort();
+            if matches.opt_present("fold-register-reads") {
+                simplify::fold_register_reads_tree(evtree);
+            }
             if matches.opt_present("simplify") {
                 if matches.opt_present("simplify-registers") {
                     simplify::remove_extra_register_fields_tree(evtree);
                     simplify::remove_repeated_register_reads_tree(evtree);
                     simplify::remove_unused_register_assumptions_tree(evtree);
                 }
+                simplify::propagate_constants_tree(evtree);
                 simplify::remove_unused_tree(evtree);
                 simplify::propagate_forwards_used_once_tree(evtree);
                 simplify::commute_extract_tree(evtree);
@@ -261,6 +739,9 @@ fn isla_main() -> i32 {
             }
             let stdout = std::io::stdout();
             let mut handle = stdout.lock();
+            if !matrix.is_empty() {
+                writeln!(handle, "Cell: {}", cell.label()).unwrap();
+            }
             let write_opts = WriteOpts { define_enum: !matches.opt_present("simplify"), ..WriteOpts::default() };
             simplify::write_event_tree(&mut handle, evtree, &shared_state.symtab, &write_opts);
             writeln!(&mut handle).unwrap();
@@ -270,6 +751,145 @@ fn isla_main() -> i32 {
     exit_code
 }
 
+/// Run the function, and any specification function from the same
+/// initial state, then print a verdict for each path of the function.
+/// Returns a non-zero exit code unless every path satisfies the
+/// specification.
+fn check_spec<'ir>(
+    matches: &getopts::Matches,
+    mut frame: LocalFrame<'ir, B129>,
+    task_state: &TaskState<B129>,
+    stop_conditions: &StopConditions,
+    shared_state: &SharedState<'ir, B129>,
+    num_threads: usize,
+    timeout: Option<u64>,
+) -> i32 {
+    let symtab = &shared_state.symtab;
+
+    let property = match matches.opt_str("spec-smt") {
+        Some(property) => match smt_parser::ExpParser::new().parse(&property) {
+            Ok(exp) => Some(exp),
+            Err(e) => {
+                eprintln!("Could not parse --spec-smt property: {}", e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let mut ignore = HashSet::new();
+    for register in matches.opt_strs("spec-ignore") {
+        match symtab.get(&zencode::encode(&register)) {
+            Some(name) => ignore.insert(name),
+            None => {
+                eprintln!("Register {} not found", register);
+                return 1;
+            }
+        };
+    }
+
+    // The arguments and registers are initialized before the
+    // checkpoint, so the function and specification share them
+    let smt_ctx = smt::Context::new(smt::Config::new());
+    let mut solver = Solver::new(&smt_ctx);
+    let initialized = reset_registers(0, &mut frame, task_state, shared_state, &mut solver, SourceLoc::unknown())
+        .and_then(|()| frame.initialize_registers(shared_state, &mut solver).map(|_| ()));
+    if let Err(e) = initialized {
+        eprintln!("Failed to initialize registers: {}", e);
+        return 1;
+    }
+
+    let spec_frame = match matches.opt_str("spec") {
+        Some(spec) => {
+            let Some(spec_id) = symtab.get(&zencode::encode(&spec)) else {
+                eprintln!("Specification function {} not found", spec);
+                return 1;
+            };
+            match compare::spec_frame(&mut frame, spec_id, shared_state, &mut solver) {
+                Ok(spec_frame) => Some(spec_frame),
+                Err(e) => {
+                    eprintln!("Could not start specification function {}: {}", spec, e);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let point = smt::checkpoint(&mut solver);
+    let run = |frame: &LocalFrame<'ir, B129>| {
+        let mut task = frame.task_with_checkpoint(0, task_state, point.clone());
+        task.set_stop_conditions(stop_conditions);
+        let collected = Arc::new(FinalStateQueue::new());
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, collected.clone(), &final_state_collector);
+        let mut outcomes = Vec::new();
+        while let Some(outcome) = collected.pop() {
+            outcomes.push(outcome)
+        }
+        outcomes
+    };
+
+    let mut spec_states = Vec::new();
+    if let Some(spec_frame) = &spec_frame {
+        for outcome in run(spec_frame) {
+            match outcome {
+                Ok(state) => spec_states.push(state),
+                Err((_, error)) => eprintln!("A specification path did not return: {}", error.error),
+            }
+        }
+        if spec_states.is_empty() {
+            eprintln!("No specification path returned, so no path can be checked against it")
+        }
+    }
+
+    let mut satisfied = true;
+    for (i, outcome) in run(&frame).into_iter().enumerate() {
+        let state = match outcome {
+            Ok(state) => state,
+            Err((_, error)) => {
+                println!("path {}: did not return: {}", i, error.error);
+                satisfied = false;
+                continue;
+            }
+        };
+
+        let mut verdicts = Vec::new();
+        if spec_frame.is_some() {
+            verdicts.push(compare::check_against_spec(&state, &spec_states, &ignore))
+        }
+        if let Some(property) = &property {
+            verdicts.push(compare::check_property(&state, property, symtab))
+        }
+        for verdict in verdicts {
+            match verdict {
+                Ok(Verdict::Satisfies) => println!("path {}: satisfies specification", i),
+                Ok(Verdict::Differs(distinction)) => {
+                    println!("path {}: {}", i, distinction.to_string(symtab));
+                    satisfied = false
+                }
+                Ok(Verdict::Violates(counterexample)) => {
+                    println!("path {}: property {}", i, counterexample.to_string(symtab));
+                    satisfied = false
+                }
+                Ok(Verdict::Unknown) => {
+                    println!("path {}: unknown", i);
+                    satisfied = false
+                }
+                Err(e) => {
+                    println!("path {}: could not check specification: {}", i, e);
+                    satisfied = false
+                }
+            }
+        }
+    }
+
+    if satisfied {
+        0
+    } else {
+        1
+    }
+}
+
 fn bits_to_bv<B: BV>(bits: &[bool]) -> B {
     let mut bv = B::zeros(bits.len() as u32);
     for (n, bit) in bits.iter().enumerate() {
@@ -295,7 +915,34 @@ fn concrete_value<B: BV>(model: &mut Model<B>, val: &Val<B>) -> Val<B> {
     }
 }
 
-type AllTraceValueQueue<B> = SegQueue<Result<(usize, Val<B>, Vec<Event<B>>), (String, Vec<Event<B>>)>>;
+/// Each result has the id of its matrix cell, and the trace of the
+/// path with the last chunk of it that was already written to the
+/// binary trace file, if any.
+type AllTraceValueQueue<B> =
+    SegQueue<Result<(usize, Val<B>, Vec<Event<B>>, Option<u64>), (usize, String, Vec<Event<B>>, Option<u64>)>>;
+
+fn save_checkpoint<B: BV>(dir: &Path, fork_log: &ForkLog, queue: &AllTraceValueQueue<B>) {
+    fork_log.snapshot(|state| {
+        let mut results = Vec::new();
+        while let Some(result) = queue.pop() {
+            results.push(result)
+        }
+        let path_results: Vec<TracedResult<B>> = results
+            .iter()
+            .map(|result| match result {
+                Ok((_, val, events, _)) => Ok((val.clone(), events.clone())),
+                Err((_, msg, events, _)) => Err((msg.clone(), events.clone())),
+            })
+            .collect();
+        match checkpoint::save(dir, state, &path_results) {
+            Ok(()) => log!(log::VERBOSE, &format!("Saved checkpoint with {} outstanding tasks", state.pending.len())),
+            Err(e) => eprintln!("Failed to save checkpoint to {}: {}", dir.display(), e),
+        }
+        for result in results {
+            queue.push(result)
+        }
+    })
+}
 
 fn model_collector<'ir, B: BV>(
     tid: usize,
@@ -303,21 +950,40 @@ fn model_collector<'ir, B: BV>(
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     shared_state: &SharedState<'ir, B>,
     mut solver: Solver<B>,
-    (collected, trace, models): &(AllTraceValueQueue<B>, bool, bool),
+    (collected, trace, models, enumerate): &(AllTraceValueQueue<B>, bool, bool, Option<usize>),
 ) {
-    let events: Vec<Event<B>> = if *trace { solver.trace().to_vec().drain(..).cloned().collect() } else { vec![] };
+    // Any events already flushed to the trace file are skipped
+    let events: Vec<Event<B>> =
+        if *trace { solver.trace().to_vec_unspilled().drain(..).cloned().collect() } else { vec![] };
+    let chunk = solver.trace().last_chunk();
     match result {
         Ok((val, _)) => {
             if solver.check_sat() == SmtResult::Sat {
+                if let (Some(bound), Val::Symbolic(v)) = (enumerate, &val) {
+                    match solver.enumerate_values(*v, *bound) {
+                        Ok(enumerated) => {
+                            let values: Vec<String> =
+                                enumerated.values.iter().map(|value| format!("{:?}", value)).collect();
+                            let more = if enumerated.complete { "" } else { ", ..." };
+                            println!("Values of v{}: {}{}", v, values.join(", "), more)
+                        }
+                        Err(err) => log_from!(tid, log::VERBOSE, format!("Could not enumerate v{}: {:?}", v, err)),
+                    }
+                }
                 let val = if *models {
                     let mut model = Model::new(&solver);
                     concrete_value(&mut model, &val)
                 } else {
                     val
                 };
-                collected.push(Ok((task_id, val, events)))
+                collected.push(Ok((task_id, val, events, chunk)))
             } else {
-                collected.push(Err((format!("Got value {} but unsat?", val.to_string(&shared_state.symtab)), events)))
+                collected.push(Err((
+                    task_id,
+                    format!("Got value {} but unsat?", val.to_string(&shared_state.symtab)),
+                    events,
+                    chunk,
+                )))
             }
         }
         Err((ExecError::Dead, _)) => (),
@@ -328,9 +994,9 @@ fn model_collector<'ir, B: BV>(
             }
             if solver.check_sat() == SmtResult::Sat {
                 let model = Model::new(&solver);
-                collected.push(Err((format!("Error {:?}\n{:?}", err, model), events)))
+                collected.push(Err((task_id, format!("Error {:?}\n{:?}", err, model), events, chunk)))
             } else {
-                collected.push(Err((format!("Error {:?}\nno model", err), events)))
+                collected.push(Err((task_id, format!("Error {:?}\nno model", err), events, chunk)))
             }
         }
     }
@@ -0,0 +1,183 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Compare the footprints of a list of opcodes between two versions
+//! of an architecture, e.g. when moving to a new snapshot of a Sail
+//! model, and report the instructions whose register or memory
+//! behaviour changed.
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::exit;
+
+use isla_axiomatic::footprint_analysis::footprint_analysis;
+use isla_lib::bitvector::{b64::B64, BV};
+use isla_lib::init::{initialize_architecture, InitArchWithConfig};
+use isla_lib::ir::*;
+use isla_lib::smt::{EvPath, Event};
+
+mod opts;
+use opts::CommonOpts;
+
+fn main() {
+    let code = isla_main();
+    unsafe { isla_lib::smt::finalize_solver() };
+    exit(code)
+}
+
+/// Read a file of hexadecimal opcodes, one per line. Blank lines and
+/// anything following a `#` are ignored.
+fn read_opcodes<P: AsRef<Path>>(file: P) -> Result<Vec<B64>, String> {
+    let file = file.as_ref();
+    let reader = BufReader::new(File::open(file).map_err(|e| format!("Failed to open {}: {}", file.display(), e))?);
+    let mut opcodes = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line.strip_prefix("0x").unwrap_or(line);
+        match u32::from_str_radix(digits, 16) {
+            Ok(opcode) => opcodes.push(B64::from_u32(opcode)),
+            Err(e) => return Err(format!("{}:{}: invalid opcode '{}': {}", file.display(), i + 1, line, e)),
+        }
+    }
+    Ok(opcodes)
+}
+
+fn isla_main() -> i32 {
+    let mut opts = opts::common_opts();
+    opts.reqopt("B", "other-arch", "architecture file to compare against", "<file>");
+    opts.optopt("", "other-config", "config for the other architecture (default: same as --config)", "<file>");
+    opts.reqopt("", "opcodes", "file containing hexadecimal opcodes, one per line", "<file>");
+    opts.optflag("", "all", "also report instructions whose footprint is unchanged");
+
+    let mut hasher = Sha256::new();
+    let (matches, arch) = opts::parse::<B64>(&mut hasher, &opts);
+    if !matches.free.is_empty() {
+        eprintln!("Unexpected arguments: {}", matches.free.join(" "));
+        return 1;
+    }
+    let CommonOpts { num_threads, mut arch, symtab, isa_config, source_path: _ } =
+        opts::parse_with_arch(&mut hasher, &opts, &matches, &arch);
+
+    let other_file = matches.opt_str("other-arch").unwrap();
+    if !Path::new(&other_file).exists() {
+        eprintln!("-B/--other-arch file '{}' does not exist", other_file);
+        return 1;
+    }
+    let other_arch = match opts::load_ir::<_, B64>(&mut hasher, &other_file) {
+        Ok(other_arch) => other_arch,
+        Err(e) => {
+            eprintln!("Error when loading architecture {}: {}", other_file, e);
+            return 1;
+        }
+    };
+    // The config has to be parsed against the symbol table of the
+    // architecture it is used with, so by default the other
+    // architecture gets its own copy of the --config file.
+    let other_config = matches.opt_str("other-config").or_else(|| matches.opt_str("config"));
+    let CommonOpts {
+        num_threads: _,
+        arch: mut other_arch,
+        symtab: other_symtab,
+        isa_config: other_isa_config,
+        source_path: _,
+    } = opts::parse_with_arch_and_config(&mut hasher, &opts, &matches, &other_arch, other_config, None);
+
+    let opcodes = match read_opcodes(matches.opt_str("opcodes").unwrap()) {
+        Ok(opcodes) => opcodes,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    // footprint_analysis collects the opcodes to analyse from the
+    // instruction events of candidate executions, so present the
+    // opcodes as a single execution.
+    let path: EvPath<B64> = opcodes.iter().map(|opcode| Event::Instr(Val::Bits(*opcode))).collect();
+    let buckets = [vec![path]];
+
+    let iarch = initialize_architecture(&mut arch, symtab, &isa_config, AssertionMode::Optimistic);
+    let iarch_config = InitArchWithConfig::from_initialized(&iarch, &isa_config);
+
+    let other_iarch =
+        initialize_architecture(&mut other_arch, other_symtab, &other_isa_config, AssertionMode::Optimistic);
+    let other_iarch_config = InitArchWithConfig::from_initialized(&other_iarch, &other_isa_config);
+
+    let footprints = match footprint_analysis(num_threads, &buckets, &iarch_config, None, None) {
+        Ok(footprints) => footprints,
+        Err(e) => {
+            eprintln!("Footprint analysis failed for {}: {}", matches.opt_str("arch").unwrap(), e);
+            return 1;
+        }
+    };
+    let other_footprints = match footprint_analysis(num_threads, &buckets, &other_iarch_config, None, None) {
+        Ok(footprints) => footprints,
+        Err(e) => {
+            eprintln!("Footprint analysis failed for {}: {}", other_file, e);
+            return 1;
+        }
+    };
+
+    let mut changed = 0;
+    for opcode in &opcodes {
+        let (footprint, other_footprint) = match (footprints.get(opcode), other_footprints.get(opcode)) {
+            (Some(footprint), Some(other_footprint)) => (footprint, other_footprint),
+            (footprint, _) => {
+                changed += 1;
+                let missing = if footprint.is_none() { matches.opt_str("arch").unwrap() } else { other_file.clone() };
+                println!("{}: no footprint for {}", opcode, missing);
+                continue;
+            }
+        };
+        let summary = footprint.coalesced(&isa_config.coalesced_registers).summary(&iarch.shared_state.symtab);
+        let other_summary =
+            other_footprint.coalesced(&other_isa_config.coalesced_registers).summary(&other_iarch.shared_state.symtab);
+        let changes = summary.diff(&other_summary);
+        if changes.is_empty() {
+            if matches.opt_present("all") {
+                println!("{}: unchanged", opcode)
+            }
+        } else {
+            changed += 1;
+            println!("{}: changed", opcode);
+            for change in changes {
+                println!("  {}", change)
+            }
+        }
+    }
+
+    eprintln!("{} of {} instructions changed", changed, opcodes.len());
+    0
+}
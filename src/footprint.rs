@@ -29,7 +29,7 @@
 
 use crossbeam::queue::SegQueue;
 use sha2::{Digest, Sha256};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::{BufWriter, Read, Write};
@@ -45,21 +45,33 @@ use isla_axiomatic::page_table::setup::PageTableSetup;
 use isla_elf::arch::AArch64;
 use isla_elf::elf;
 use isla_elf::relocation_types::SymbolicRelocation;
+use isla_lib::absint::BranchFacts;
+use isla_lib::binary_trace::{BinaryTraceError, BinaryTraceWriter};
 use isla_lib::bitvector::{b129::B129, BV};
+use isla_lib::coverage::Coverage;
+use isla_lib::encoding_space::{partition_collector, Partition, PartitionQueue, Pattern};
 use isla_lib::error::IslaError;
 use isla_lib::executor;
+use isla_lib::executor::reach::{reach_collector, ReachQueue};
+use isla_lib::executor::simplify_pool::{simplifying_trace_collector, SimplifyPool, TracePipeline};
 use isla_lib::executor::{LocalFrame, StopAction, StopConditions, TaskState};
 use isla_lib::init::{initialize_architecture, InitArchWithConfig};
+use isla_lib::instruction_names::InstructionNames;
 use isla_lib::ir::*;
 use isla_lib::log;
-use isla_lib::memory::Memory;
+use isla_lib::memory::{DeviceRegion, Memory};
+use isla_lib::precondition::{precondition_collector, Precondition};
+use isla_lib::primop::unimplemented::UnimplementedStats;
 use isla_lib::register::Register;
 use isla_lib::simplify;
-use isla_lib::simplify::{EventTree, WriteOpts};
+use isla_lib::simplify::{EventTree, TracePasses, WriteOpts};
 use isla_lib::smt;
+use isla_lib::smt::query_log::QueryLog;
 use isla_lib::smt::{smtlib, Checkpoint, EvPath, Event, Solver};
 use isla_lib::smt_parser;
 use isla_lib::source_loc::SourceLoc;
+use isla_lib::summary::{summary_collector, witness_collector, Summary, WitnessQueue};
+use isla_lib::trace_file::TraceFileWriter;
 use isla_lib::zencode;
 
 mod opts;
@@ -99,6 +111,7 @@ fn instruction_to_string<B: BV>(opcode: &[InstructionSegment<B>]) -> String {
 }
 
 fn instruction_to_val<B: BV>(
+    tid: usize,
     opcode: &[InstructionSegment<B>],
     constraints: &[String],
     solver: &mut Solver<B>,
@@ -134,7 +147,7 @@ fn instruction_to_val<B: BV>(
                     .collect(),
             );
             println!(")");
-            for constraint in constraints {
+            for (i, constraint) in constraints.iter().enumerate() {
                 let mut lookup = |loc: &Loc<String>| match loc {
                     Loc::Id(name) => match var_map.get(&zencode::decode(name)) {
                         Some((_size, v)) => Ok(smtlib::Exp::Var(*v)),
@@ -145,13 +158,62 @@ fn instruction_to_val<B: BV>(
                 let assertion = smt_parser::ExpParser::new().parse(&constraint).expect("Bad instruction constraint");
                 solver.add_event(Event::Assume(assertion.clone()));
                 let assertion_exp = assertion.map_var(&mut lookup).expect("Bad instruction constraint");
-                solver.add(smtlib::Def::Assert(assertion_exp));
+                solver.assume(format!("thread{}_instruction_constraint_{}", tid, i), assertion_exp);
             }
             val
         }
     }
 }
 
+/// Parse an encoding pattern such as `0x8B0_____` or `0b1x01...`,
+/// where `_` or `x` marks a don't-care hex digit or bit respectively.
+/// Each run of don't-care bits becomes a symbolic segment named after
+/// the bits it covers, e.g. `bits19_0`.
+fn mask_segments<B: BV>(pattern: &str) -> Result<Vec<InstructionSegment<B>>, String> {
+    let (digits, radix, digit_bits) = if let Some(hex) = pattern.strip_prefix("0x") {
+        (hex, 16, 4)
+    } else {
+        (pattern.strip_prefix("0b").unwrap_or(pattern), 2, 1)
+    };
+
+    // Bits of the pattern from most to least significant, with None
+    // for don't-care bits
+    let mut bits: Vec<Option<bool>> = Vec::new();
+    for c in digits.chars() {
+        if c == '_' || c == 'x' || c == 'X' {
+            bits.extend(std::iter::repeat(None).take(digit_bits))
+        } else if let Some(d) = c.to_digit(radix) {
+            bits.extend((0..digit_bits).rev().map(|i| Some((d >> i) & 1 == 1)))
+        } else {
+            return Err(format!("Invalid character '{}' in encoding pattern {}", c, pattern));
+        }
+    }
+    if bits.is_empty() {
+        return Err(format!("Empty encoding pattern {}", pattern));
+    }
+
+    let mut segments = Vec::new();
+    let mut i = 0;
+    while i < bits.len() {
+        let symbolic = bits[i].is_none();
+        let mut j = i;
+        while j < bits.len() && bits[j].is_none() == symbolic {
+            j += 1
+        }
+        let len = (j - i) as u32;
+        if symbolic {
+            segments.push(InstructionSegment::Symbolic(format!("bits{}_{}", bits.len() - 1 - i, bits.len() - j), len))
+        } else {
+            let bv = bits[i..j]
+                .iter()
+                .fold(B::zeros(0), |bv, bit| bv.append(B::new(u64::from(*bit == Some(true)), 1)).unwrap());
+            segments.push(InstructionSegment::Concrete(bv))
+        }
+        i = j
+    }
+    Ok(segments)
+}
+
 fn opcode_bytes<B: BV>(opcode: Vec<u8>, little_endian: bool) -> B {
     if opcode.len() > 8 {
         eprintln!("Currently instructions greater than 8 bytes in length are not supported");
@@ -301,13 +363,60 @@ fn isla_main() -> i32 {
     opts.optflag("x", "hex", "parse instruction as hexadecimal opcode, rather than assembly");
     opts.optflag("s", "simplify", "simplify instruction footprint");
     opts.optflag("", "simplify-registers", "simplify register accesses in traces");
+    opts.optflag("", "summarize", "group paths by their final register writes rather than printing traces");
+    opts.optflag(
+        "",
+        "precondition",
+        "print the weakest known condition on the initial registers for the instruction to complete without error",
+    );
+    opts.optflag("", "undefined-dont-care", "drop register writes determined only by UNKNOWN bits");
+    opts.optflag("", "fold-register-reads", "fold repeated identical register reads within each instruction");
     opts.optflag("", "hide", "hide uninteresting trace elements");
+    opts.optflag("", "lets", "use let bindings to share repeated subterms in traces");
+    opts.optopt("", "bits", "format for concrete bitvectors in register and memory events", "hex/bin");
+    opts.optflag("", "provenance", "show the Sail function and instruction that generated each event");
+    opts.optflag("", "smt-script", "write each trace as a self-contained SMTLIB script for a solver");
+    opts.optflag("", "bits-comments", "annotate concrete register and memory values with decimal and field values");
+    opts.optflag("", "coverage", "print a report of the IR instructions covered in each function");
+    opts.optflag("", "coverage-guided", "prefer exploring paths which will execute uncovered IR instructions");
+    opts.optflag("", "prune-branches", "use a static known-bits analysis to avoid solver queries at branches");
+    opts.optopt(
+        "",
+        "instruction-names",
+        "label opcodes using ARM XML or riscv-opcodes instruction metadata",
+        "<file or directory>",
+    );
+    opts.optopt("", "log-smt", "log every solver query with its result and timing (see isla-replay-smt)", "<file>");
     opts.optflag("t", "tree", "combine traces into tree");
     opts.optopt("f", "function", "use a custom footprint function", "<identifer>");
     opts.optflag("c", "continue-on-error", "continue generating traces upon encountering an error");
     opts.optopt("", "armv8-page-tables", "set up page tables with provided constraints", "<constraints>");
     opts.optflag("", "zero-memory", "treat all memory as being zero");
     opts.optflag("", "partial", "parse instruction as binary with unknown bits");
+    opts.optflag(
+        "",
+        "mask",
+        "parse instruction as an encoding pattern like 0x8B0_____, and report each reachable behavior with a witness opcode",
+    );
+    opts.optflag(
+        "",
+        "partition",
+        "parse instruction as an encoding pattern like 0x________, and report the encodings reaching each behavior",
+    );
+    opts.optmulti(
+        "",
+        "behavior-prefix",
+        "with --partition, stop at functions with this prefix, e.g. execute_, grouping encodings by which is reached",
+        "<prefix>",
+    );
+    opts.optopt(
+        "",
+        "reach",
+        "search for paths which call a function, have an abstract event, or throw an exception with this constructor, \
+         printing a witness opcode and the registers it reads. Exits with status 1 if it is not reached, but some \
+         paths failed before it could be",
+        "<name>",
+    );
     opts.optopt("", "from-file", "parse instruction from opcodes file", "<file>");
     opts.optmulti("", "instruction-constraint", "add constraint on variables in a partial instruction", "<constraint>");
     opts.optmulti(
@@ -323,8 +432,17 @@ fn isla_main() -> i32 {
         "<function name[, function_name]>",
     );
     opts.optflag("", "pessimistic", "fail on any assertion that is not necessarily true");
+    opts.optopt(
+        "",
+        "assertions",
+        "how to treat Sail assertions: assume, check, or ignore (overrides --pessimistic)",
+        "<mode>",
+    );
     opts.optopt("", "timeout", "Add a timeout (in seconds)", "<n>");
+    opts::path_cap_opts(&mut opts);
+    opts::register_index_opts(&mut opts);
     opts.optflag("", "executable", "make trace executable");
+    opts.optopt("", "binary-traces", "write traces to a file in the compact binary trace format (zstd compressed if <file> ends in .zst)", "<file>");
 
     let mut hasher = Sha256::new();
     let (matches, arch) = opts::parse(&mut hasher, &opts);
@@ -338,6 +456,7 @@ fn isla_main() -> i32 {
     // Note this is the opposite default to other tools
     let assertion_mode =
         if matches.opt_present("pessimistic") { AssertionMode::Pessimistic } else { AssertionMode::Optimistic };
+    let assertion_mode = opts::assertion_mode(&matches, assertion_mode);
 
     let iarch = initialize_architecture(&mut arch, symtab, &isa_config, assertion_mode);
     let iarch_config = InitArchWithConfig::from_initialized(&iarch, &isa_config);
@@ -356,6 +475,16 @@ fn isla_main() -> i32 {
         }
     };
 
+    let bits_format = match matches.opt_str("bits").as_deref() {
+        None => ValBitsFormat::Default,
+        Some("hex") => ValBitsFormat::Hex,
+        Some("bin") => ValBitsFormat::Bin,
+        Some(_) => {
+            eprintln!("--bits argument must be one of either `hex` or `bin`");
+            exit(1)
+        }
+    };
+
     let timeout: Option<u64> = match matches.opt_get("timeout") {
         Ok(timeout) => timeout,
         Err(e) => {
@@ -387,6 +516,14 @@ fn isla_main() -> i32 {
                     })
             })
             .collect()
+    } else if matches.opt_present("mask") || matches.opt_present("partition") {
+        match mask_segments(&instruction) {
+            Ok(segments) => segments,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                return 1;
+            }
+        }
     } else if let Some(opcode_file) = matches.opt_str("from-file").as_deref() {
         let mut contents = String::new();
         match File::open(opcode_file) {
@@ -483,11 +620,51 @@ fn isla_main() -> i32 {
         log!(log::VERBOSE, &format!("opcode: {}", instruction_to_string(&opcode)));
     }
 
+    let instruction_names = match matches.opt_str("instruction-names") {
+        Some(path) => {
+            let mut names = InstructionNames::new();
+            if let Err(msg) = names.load(&path) {
+                eprintln!("{}", msg);
+                return 1;
+            }
+            log!(log::VERBOSE, &format!("Loaded {} instruction encodings from {}", names.len(), path));
+            Some(Arc::new(names))
+        }
+        None => None,
+    };
+
     let kill_conditions = StopConditions::parse(matches.opt_strs("kill-at"), &shared_state, StopAction::Kill);
     let abstract_conditions = StopConditions::parse(matches.opt_strs("stop-at"), &shared_state, StopAction::Abstract);
-    let stop_conditions = kill_conditions.union(&abstract_conditions);
+    let mut stop_conditions = kill_conditions.union(&abstract_conditions);
+    for prefix in matches.opt_strs("behavior-prefix") {
+        for function in shared_state.functions.keys() {
+            if zencode::decode(shared_state.symtab.to_str(*function)).starts_with(&prefix) {
+                stop_conditions.add(*function, None, StopAction::Kill)
+            }
+        }
+    }
+    // Paths which reach a target function end there
+    let reach_target = match matches.opt_str("reach") {
+        Some(name) => match shared_state.symtab.get(&zencode::encode(&name)) {
+            Some(target) => {
+                if shared_state.functions.contains_key(&target) {
+                    stop_conditions.add(target, None, StopAction::Kill)
+                }
+                Some((name, target))
+            }
+            None => {
+                eprintln!("No function, event, or constructor named {}", name);
+                return 1;
+            }
+        },
+        None => None,
+    };
 
     let mut memory = Memory::new();
+    memory.set_ifetch_sees_writes(isa_config.self_modifying_code);
+    for mmio in &isa_config.mmio_regions {
+        memory.add_device_region(mmio.range.clone(), DeviceRegion::new(&mmio.name, &mmio.values))
+    }
 
     let PageTableSetup { memory_checkpoint, .. } = if let Some(setup) = matches.opt_str("armv8-page-tables") {
         let lexer = page_table::setup_lexer::SetupLexer::new(&setup);
@@ -569,12 +746,12 @@ fn isla_main() -> i32 {
         None => "zisla_footprint".to_string(),
     };
 
-    let (initial_checkpoint, opcode_val) = {
+    let (initial_checkpoint, opcode_val, encodings) = {
         let solver_cfg = smt::Config::new();
         let solver_ctx = smt::Context::new(solver_cfg);
         let mut solver = Solver::from_checkpoint(&solver_ctx, elf_checkpoint);
         let opcode_val =
-            if have_elf { elf_opcode_val.unwrap() } else { instruction_to_val(&opcode, &constraints, &mut solver) };
+            if have_elf { elf_opcode_val.unwrap() } else { instruction_to_val(0, &opcode, &constraints, &mut solver) };
         // Record register assumptions from defaults; others are recorded at reset-registers
         let mut sorted_regs: Vec<(&Name, &Register<_>)> = regs.iter().collect();
         sorted_regs.sort_by_key(|(name, _)| *name);
@@ -583,12 +760,38 @@ fn isla_main() -> i32 {
                 solver.add_event(Event::AssumeReg(*name, vec![], value.clone()))
             }
         }
-        (smt::checkpoint(&mut solver), opcode_val)
+        let encodings = Pattern::from_opcode(&opcode_val, &mut solver);
+        (smt::checkpoint(&mut solver), opcode_val, encodings)
     };
 
     let function_id = shared_state.symtab.lookup(&footprint_function);
     let (args, ret_ty, instrs) = shared_state.functions.get(&function_id).unwrap();
-    let task_state = TaskState::with_reset_registers(reset_registers);
+    let mut task_state = TaskState::with_reset_registers(reset_registers);
+    let coverage = if matches.opt_present("coverage") || matches.opt_present("coverage-guided") {
+        Some(Arc::new(Coverage::new(shared_state)))
+    } else {
+        None
+    };
+    task_state.set_coverage(coverage.clone());
+    task_state.set_coverage_guided(matches.opt_present("coverage-guided"));
+
+    let branch_facts = if matches.opt_present("prune-branches") { Some(Arc::new(BranchFacts::new())) } else { None };
+    task_state.set_branch_facts(branch_facts.clone());
+
+    let query_log = match matches.opt_str("log-smt").map(QueryLog::create).transpose() {
+        Ok(log) => log.map(Arc::new),
+        Err(e) => {
+            eprintln!("Failed to create SMT query log: {}", e);
+            return 1;
+        }
+    };
+    task_state.set_query_log(query_log.clone());
+    task_state.set_provenance(matches.opt_present("provenance"));
+    opts::path_caps(&matches, &mut task_state);
+    opts::register_index_strategy(&matches, &mut task_state);
+    let unimplemented_stats = Arc::new(UnimplementedStats::new());
+    task_state.set_unimplemented_stats(Some(unimplemented_stats.clone()));
+
     let mut task = LocalFrame::new(function_id, args, ret_ty, Some(&[opcode_val.clone()]), instrs)
         .add_lets(lets)
         .add_regs(regs)
@@ -596,16 +799,169 @@ fn isla_main() -> i32 {
         .task_with_checkpoint(0, &task_state, initial_checkpoint);
     task.set_stop_conditions(&stop_conditions);
 
+    if let Some((name, target)) = reach_target {
+        let queue = Arc::new(ReachQueue::new(target, opcode_val.clone()));
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &reach_collector);
+        let witnesses = queue.witnesses();
+        let errors = queue.errors();
+        match witnesses.first() {
+            Some(witness) => {
+                println!("{} reachable on {} paths", name, witnesses.len());
+                println!("{}", witness.to_string(&shared_state.symtab))
+            }
+            None if errors.is_empty() => println!("{} unreachable", name),
+            None => {
+                // Paths which failed might have reached the target
+                println!("{} not reached, but {} paths failed, so it may be reachable", name, errors.len());
+                let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+                for error in &errors {
+                    *counts.entry(error.as_str()).or_insert(0) += 1
+                }
+                for (error, count) in counts {
+                    println!("  {} x {}", count, error)
+                }
+                return 1;
+            }
+        }
+        return 0;
+    }
+
+    if matches.opt_present("partition") {
+        let Some(encodings) = encodings else {
+            eprintln!("--partition requires an encoding pattern of at most 64 bits");
+            return 1;
+        };
+        let queue = Arc::new(PartitionQueue::new(opcode_val.clone(), encodings));
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &partition_collector);
+        print!("{}", Partition::from_queue(&queue));
+        return 0;
+    }
+
+    if matches.opt_present("mask") {
+        let queue = Arc::new(WitnessQueue::new(opcode_val.clone()));
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &witness_collector);
+        print!("{}", Summary::from_witness_queue(&queue));
+        return 0;
+    }
+
+    if matches.opt_present("precondition") {
+        let queue = Arc::new(SegQueue::new());
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &precondition_collector);
+        print!("{}", Precondition::from_queue(&queue));
+        return 0;
+    }
+
+    if matches.opt_present("summarize") {
+        let queue = Arc::new(SegQueue::new());
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &summary_collector);
+        print!("{}", Summary::from_queue(&queue));
+        return 0;
+    }
+
     let queue = Arc::new(SegQueue::new());
 
     let now = Instant::now();
-    executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &executor::trace_collector);
+    if matches.opt_present("dependency") || matches.opt_present("tree") {
+        executor::start_multi(num_threads, timeout, vec![task], shared_state, queue.clone(), &executor::trace_collector);
+    } else {
+        // Simplify each trace in the background as it is collected,
+        // rather than holding every unsimplified trace until the end
+        let fold_register_reads = matches.opt_present("fold-register-reads");
+        let simplify_traces = matches.opt_present("simplify");
+        let simplify_registers = matches.opt_present("simplify-registers");
+        let undefined_dont_care = matches.opt_present("undefined-dont-care");
+        let pipeline: Arc<TracePipeline<B129>> = Arc::new(move |events: &mut EvPath<B129>| {
+            if fold_register_reads {
+                simplify::fold_register_reads(events);
+            }
+            if simplify_traces {
+                simplify::hide_initialization(events);
+                if simplify_registers {
+                    simplify::remove_extra_register_fields(events);
+                    simplify::remove_repeated_register_reads(events);
+                    simplify::remove_unused_register_assumptions(events);
+                }
+                simplify::propagate_constants(events);
+                simplify::remove_unused(events);
+                simplify::propagate_forwards_used_once(events);
+                simplify::commute_extract(events);
+                simplify::eval(events);
+            }
+            if undefined_dont_care {
+                simplify::remove_undefined_writes(events);
+                simplify::remove_unused(events);
+            }
+        });
+        let pool = Arc::new(SimplifyPool::new(num_threads, pipeline, queue.clone()));
+        executor::start_multi(
+            num_threads,
+            timeout,
+            vec![task],
+            shared_state,
+            pool.clone(),
+            &simplifying_trace_collector,
+        );
+        pool.finish()
+    }
     log!(log::VERBOSE, &format!("Execution took: {}ms", now.elapsed().as_millis()));
 
+    if let Some(log) = &query_log {
+        if let Err(e) = log.flush() {
+            eprintln!("Failed to write SMT query log: {}", e)
+        }
+    }
+
+    if let Some(facts) = &branch_facts {
+        log!(log::VERBOSE, &format!("Decided {} branches without the solver", facts.decided()))
+    }
+
+    if !unimplemented_stats.is_empty() {
+        eprintln!("Some paths failed at unimplemented primops:");
+        eprint!("{}", unimplemented_stats.report())
+    }
+
+    if let Some(coverage) = &coverage {
+        let (covered, total) = coverage.summary();
+        eprintln!("Covered {} of {} IR instructions in reached functions", covered, total);
+        if matches.opt_present("coverage") {
+            eprint!("{}", coverage.report(&shared_state.symtab))
+        }
+    }
+
     let mut paths = Vec::new();
     let mut evtree: Option<EventTree<B129>> = None;
 
-    let write_opts = WriteOpts { define_enum: !matches.opt_present("simplify"), hide_uninteresting: matches.opt_present("hide"), ..WriteOpts::default() };
+    let write_opts = WriteOpts {
+        define_enum: !matches.opt_present("simplify"),
+        hide_uninteresting: matches.opt_present("hide"),
+        lets: matches.opt_present("lets"),
+        bits_format,
+        bits_comments: matches.opt_present("bits-comments"),
+        register_fields: isa_config.register_fields.clone(),
+        smt_script: matches.opt_present("smt-script"),
+        provenance: matches.opt_present("provenance"),
+        instruction_names: instruction_names.clone(),
+        ..WriteOpts::default()
+    };
+
+    let trace_passes: TracePasses<B129> = TracePasses::new();
+
+    let mut binary_traces = if let Some(path) = matches.opt_str("binary-traces") {
+        let writer = TraceFileWriter::create(&path).map_err(BinaryTraceError::from).and_then(|fd| {
+            let mut writer = BinaryTraceWriter::new(fd)?;
+            writer.get_mut().end_path()?;
+            Ok(writer)
+        });
+        match writer {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Could not create binary trace file {}: {}", path, e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
     
     loop {
         match queue.pop() {
@@ -626,27 +982,17 @@ fn isla_main() -> i32 {
                 paths.push(events)
             }
             Some(Ok((_, mut events))) if matches.opt_present("tree") => {
-                let events: Vec<Event<B129>> = events.drain(..).rev().collect();
-                if let Some(ref mut evtree) = evtree {
-                    evtree.add_events(&events)
-                } else {
-                    evtree = Some(EventTree::from_events(&events))
-                }
+                trace_passes.add_to_tree(&mut evtree, events.drain(..).rev().collect());
             }
+            // Already simplified by the pool
             Some(Ok((_, mut events))) => {
-                if matches.opt_present("simplify") {
-                    simplify::hide_initialization(&mut events);
-                    if matches.opt_present("simplify-registers") {
-                        simplify::remove_extra_register_fields(&mut events);
-                        simplify::remove_repeated_register_reads(&mut events);
-                        simplify::remove_unused_register_assumptions(&mut events);
-                    }
-                    simplify::remove_unused(&mut events);
-                    simplify::propagate_forwards_used_once(&mut events);
-                    simplify::commute_extract(&mut events);
-                    simplify::eval(&mut events);
+                let mut events: Vec<Event<B129>> = trace_passes.run(events.drain(..).rev().collect());
+                isa_config.memory_kinds.annotate(&mut events, shared_state);
+                if let Some(ref mut writer) = binary_traces {
+                    writer.write_trace(&events).unwrap();
+                    writer.get_mut().end_path().unwrap();
+                    continue;
                 }
-                let events: Vec<Event<B129>> = events.drain(..).rev().collect();
                 let stdout = std::io::stdout();
                 // Traces can be large, so use a 5MB buffer
                 let mut handle = BufWriter::with_capacity(5 * usize::pow(2, 20), stdout.lock());
@@ -669,10 +1015,17 @@ fn isla_main() -> i32 {
         }
     }
 
+    if let Some(writer) = binary_traces {
+        writer.into_inner().finish().unwrap()
+    }
+
     if matches.opt_present("tree") {
         if let Some(ref mut evtree) = evtree {
             evtree.renumber();
             evtree.sort();
+            if matches.opt_present("fold-register-reads") {
+                simplify::fold_register_reads_tree(evtree);
+            }
             if matches.opt_present("simplify") {
                 simplify::hide_initialization_tree(evtree);
                 if matches.opt_present("simplify-registers") {
@@ -680,6 +1033,7 @@ fn isla_main() -> i32 {
                     simplify::remove_repeated_register_reads_tree(evtree);
                     simplify::remove_unused_register_assumptions_tree(evtree);
                 }
+                simplify::propagate_constants_tree(evtree);
                 simplify::remove_unused_tree(evtree);
                 simplify::propagate_forwards_used_once_tree(evtree);
                 simplify::commute_extract_tree(evtree);
@@ -696,12 +1050,16 @@ fn isla_main() -> i32 {
     }
 
     if matches.opt_present("dependency") {
-        match footprint_analysis(num_threads, &[paths], &iarch_config, None) {
+        match footprint_analysis(num_threads, &[paths], &iarch_config, None, None) {
             Ok(footprints) => {
-                for (_, footprint) in footprints {
+                for (opcode, footprint) in footprints {
                     {
                         let stdout = std::io::stdout();
                         let mut handle = stdout.lock();
+                        if let Some(decoded) = instruction_names.as_ref().and_then(|names| names.decode(opcode)) {
+                            let _ = writeln!(handle, "{}: {}", opcode, decoded);
+                        }
+                        let footprint = footprint.coalesced(&isa_config.coalesced_registers);
                         let _ = footprint.pretty(&mut handle, &shared_state.symtab);
                     }
                 }
@@ -0,0 +1,451 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use sha2::{Digest, Sha256};
+use std::net::TcpListener;
+use std::process::exit;
+use std::sync::Mutex;
+
+use isla_elf::elf;
+use isla_lib::bitvector::{b129::B129, BV};
+use isla_lib::error::ExecError;
+use isla_lib::executor;
+use isla_lib::executor::{Backtrace, LocalFrame, TaskState};
+use isla_lib::gdb::{GdbStub, RegisterInfo, Target};
+use isla_lib::init::initialize_architecture;
+use isla_lib::ir::*;
+use isla_lib::log;
+use isla_lib::memory::{DeviceRegion, Memory};
+use isla_lib::register::RegisterBindings;
+use isla_lib::smt;
+use isla_lib::smt::smtlib::{Def, Exp};
+use isla_lib::smt::{Checkpoint, Event, Model, SmtResult, Solver};
+use isla_lib::zencode;
+
+mod opts;
+use opts::CommonOpts;
+
+fn main() {
+    let code = isla_main();
+    unsafe { isla_lib::smt::finalize_solver() };
+    exit(code)
+}
+
+/// The ELF program header type for loadable segments
+const PT_LOAD: u32 = 1;
+
+/// Where the value of a gdb register comes from
+enum Source {
+    Register(Name),
+    /// A hardwired zero register, such as x0 on RISC-V
+    Zero,
+    /// A register gdb requires but the model does not provide
+    Unavailable,
+}
+
+/// The gdb architecture, feature, and register layout for each
+/// supported architecture. `None` in the register list means the
+/// program counter from the ISA configuration.
+#[allow(clippy::type_complexity)]
+fn gdb_layout(arch: &str) -> Option<(&'static str, &'static str, Vec<(String, u32, Option<String>)>)> {
+    match arch {
+        "aarch64" => {
+            let mut regs: Vec<_> = (0..31).map(|n| (format!("x{}", n), 64, Some(format!("R{}", n)))).collect();
+            regs.push(("sp".to_string(), 64, Some("SP_EL0".to_string())));
+            regs.push(("pc".to_string(), 64, None));
+            regs.push(("cpsr".to_string(), 32, Some("".to_string())));
+            Some(("aarch64", "org.gnu.gdb.aarch64.core", regs))
+        }
+        "riscv64" | "riscv32" => {
+            let (name, bitsize) = if arch == "riscv64" { ("riscv:rv64", 64) } else { ("riscv:rv32", 32) };
+            let mut regs: Vec<_> = (0..32).map(|n| (format!("x{}", n), bitsize, Some(format!("x{}", n)))).collect();
+            regs.push(("pc".to_string(), bitsize, None));
+            Some((name, "org.gnu.gdb.riscv.cpu", regs))
+        }
+        _ => None,
+    }
+}
+
+/// The state carried between steps, produced by [step_collector]
+struct Step<'ir, B> {
+    regs: RegisterBindings<'ir, B>,
+    checkpoint: Checkpoint<B>,
+    /// Memory writes made by the step, in order
+    writes: Vec<(u64, Vec<u8>)>,
+}
+
+type StepResult<'ir, B> = Mutex<Option<Result<Step<'ir, B>, String>>>;
+
+/// Concrete bytes for a value written to memory. Any symbolic value
+/// (for example an UNKNOWN value) is given a concrete value from the
+/// model, which is then asserted so later steps stay consistent.
+fn concrete_bytes<B: BV>(solver: &mut Solver<B>, val: &Val<B>, bytes: u32) -> Result<Vec<u8>, String> {
+    let value = match val {
+        Val::Bits(bv) => *bv,
+        Val::Symbolic(v) => {
+            if solver.check_sat() != SmtResult::Sat {
+                return Err("Path became unsatisfiable".to_string());
+            }
+            let exp = {
+                let mut model = Model::new(solver);
+                model.get_var(*v).map_err(|e| format!("{:?}", e))?
+            };
+            let bv = match exp {
+                Some(Exp::Bits64(bv)) => B::new(bv.lower_u64(), bytes * 8),
+                Some(Exp::Bits(bits)) => bits.iter().enumerate().fold(B::zeros(bytes * 8), |bv, (i, bit)| {
+                    if *bit {
+                        bv.set_slice(i as u32, B::new(1, 1))
+                    } else {
+                        bv
+                    }
+                }),
+                None => B::zeros(bytes * 8),
+                Some(exp) => return Err(format!("Could not make value {:?} concrete", exp)),
+            };
+            solver.add(Def::Assert(Exp::Eq(Box::new(Exp::Var(*v)), Box::new(smt_bits(bv)))));
+            bv
+        }
+        _ => return Err(format!("Unexpected value {:?} written to memory", val)),
+    };
+    let mut value = value.to_le_bytes();
+    value.resize(bytes as usize, 0);
+    Ok(value)
+}
+
+fn smt_bits<B: BV>(bv: B) -> Exp<smt::Sym> {
+    Exp::Bits(bv.to_vec())
+}
+
+fn step_collector<'ir, B: BV>(
+    _: usize,
+    _: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    _: &SharedState<'ir, B>,
+    mut solver: Solver<B>,
+    collected: &StepResult<'ir, B>,
+) {
+    let step = match result {
+        Ok((_, frame)) => {
+            // The trace is in reverse order, and each step starts
+            // with an Instr event
+            let writes: Vec<(Val<B>, Val<B>, u32)> = solver
+                .trace()
+                .to_vec()
+                .into_iter()
+                .take_while(|event| !matches!(event, Event::Instr(_)))
+                .filter_map(|event| match event {
+                    Event::WriteMem { address, data, bytes, .. } => Some((address.clone(), data.clone(), *bytes)),
+                    _ => None,
+                })
+                .collect();
+            writes
+                .iter()
+                .rev()
+                .map(|(address, data, bytes)| {
+                    let address = concrete_bytes(&mut solver, address, 8)?;
+                    let address = u64::from_le_bytes(address.try_into().unwrap());
+                    Ok((address, concrete_bytes(&mut solver, data, *bytes)?))
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|writes| Step { regs: frame.regs().clone(), checkpoint: smt::checkpoint(&mut solver), writes })
+        }
+        Err((ExecError::Dead, _)) => return,
+        Err((err, _)) => Err(format!("{}", err)),
+    };
+    let mut collected = collected.lock().unwrap();
+    if collected.is_none() {
+        *collected = Some(step)
+    } else {
+        log!(log::VERBOSE, "Ignoring additional path in concrete step")
+    }
+}
+
+struct ConcreteTarget<'ir, B> {
+    shared_state: &'ir SharedState<'ir, B>,
+    function_id: Name,
+    lets: Bindings<'ir, B>,
+    regs: RegisterBindings<'ir, B>,
+    memory: Memory<B>,
+    checkpoint: Checkpoint<B>,
+    task_state: TaskState<B>,
+    pc: Name,
+    architecture: &'static str,
+    feature: &'static str,
+    registers: Vec<RegisterInfo>,
+    sources: Vec<Source>,
+}
+
+impl<'ir, B: BV> ConcreteTarget<'ir, B> {
+    fn source_name(&self, n: usize) -> Option<Name> {
+        match self.sources.get(n)? {
+            Source::Register(name) => Some(*name),
+            Source::Zero | Source::Unavailable => None,
+        }
+    }
+}
+
+impl<'ir, B: BV> Target for ConcreteTarget<'ir, B> {
+    fn architecture(&self) -> &str {
+        self.architecture
+    }
+
+    fn feature(&self) -> &str {
+        self.feature
+    }
+
+    fn registers(&self) -> &[RegisterInfo] {
+        &self.registers
+    }
+
+    fn read_register(&mut self, n: usize) -> Option<u64> {
+        if let Some(Source::Zero) = self.sources.get(n) {
+            return Some(0);
+        }
+        match self.regs.get_last_if_initialized(self.source_name(n)?)? {
+            Val::Bits(bv) if bv.len() <= 64 => Some(bv.lower_u64()),
+            Val::I64(i) => Some(*i as u64),
+            Val::I128(i) => Some(*i as u64),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, n: usize, value: u64) -> Result<(), String> {
+        let Some(name) = self.source_name(n) else {
+            return Ok(());
+        };
+        let len = match self.regs.get_last_if_initialized(name) {
+            Some(Val::Bits(bv)) => bv.len(),
+            _ => self.registers[n].bitsize,
+        };
+        self.regs.assign(name, Val::Bits(B::new(value, len)), self.shared_state);
+        Ok(())
+    }
+
+    fn read_memory(&mut self, address: u64, len: usize) -> Result<Vec<u8>, String> {
+        (address..address + len as u64)
+            .map(|addr| match self.memory.read_initial(addr, 1) {
+                Ok(Val::Bits(bv)) => Ok(bv.lower_u8()),
+                _ => Err(format!("Could not read memory at {:#x}", addr)),
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), String> {
+        for (addr, byte) in (address..).zip(data) {
+            self.memory.write_byte(addr, *byte)
+        }
+        Ok(())
+    }
+
+    fn pc(&mut self) -> Option<u64> {
+        match self.regs.get_last_if_initialized(self.pc)? {
+            Val::Bits(bv) => Some(bv.lower_u64()),
+            _ => None,
+        }
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        let pc = self.pc().ok_or_else(|| "The program counter is not concrete".to_string())?;
+        let opcode = self
+            .memory
+            .read_initial(pc, 4)
+            .map_err(|e| format!("Could not fetch instruction at {:#x}: {:?}", pc, e))?;
+
+        let checkpoint = {
+            let ctx = smt::Context::new(smt::Config::new());
+            let mut solver = Solver::from_checkpoint(&ctx, self.checkpoint.clone());
+            solver.add_event(Event::Instr(opcode.clone()));
+            smt::checkpoint(&mut solver)
+        };
+
+        let (args, ret_ty, instrs) = self.shared_state.functions.get(&self.function_id).unwrap();
+        let task = LocalFrame::new(self.function_id, args, ret_ty, Some(&[opcode]), instrs)
+            .add_lets(&self.lets)
+            .add_regs(&self.regs)
+            .set_memory(self.memory.clone())
+            .task_with_checkpoint(0, &self.task_state, checkpoint);
+
+        let result: StepResult<'ir, B> = Mutex::new(None);
+        executor::start_single(task, self.shared_state, &result, &step_collector);
+
+        let step =
+            result.into_inner().unwrap().unwrap_or_else(|| Err(format!("No paths for instruction at {:#x}", pc)))?;
+        for (address, bytes) in step.writes {
+            self.write_memory(address, &bytes)?
+        }
+        self.regs = step.regs;
+        // Once every register is concrete, the solver state is no
+        // longer needed, so avoid replaying it on each step
+        if self.regs.iter().all(|(_, reg)| reg.read_last_if_initialized().map_or(true, |val| !val.is_symbolic())) {
+            self.checkpoint = Checkpoint::new()
+        } else {
+            self.checkpoint = step.checkpoint
+        }
+        Ok(())
+    }
+}
+
+fn isla_main() -> i32 {
+    let mut opts = opts::common_opts();
+    opts.optopt("", "elf", "the ELF file to load and run", "<file>");
+    opts.optopt("", "gdb-arch", "register layout presented to gdb (default: aarch64)", "aarch64/riscv64/riscv32");
+    opts.optmulti("", "gdb-register", "take the value of a gdb register from a Sail register", "<gdb name>=<register>");
+    opts.optopt("", "port", "port to listen on for gdb (default: 1234)", "<n>");
+    opts.optopt("f", "function", "use a custom footprint function to execute each instruction", "<identifer>");
+
+    let mut hasher = Sha256::new();
+    let (matches, arch) = opts::parse::<B129>(&mut hasher, &opts);
+    let CommonOpts { mut arch, symtab, isa_config, .. } = opts::parse_with_arch(&mut hasher, &opts, &matches, &arch);
+
+    let assertion_mode = opts::assertion_mode(&matches, AssertionMode::Optimistic);
+    let iarch = initialize_architecture(&mut arch, symtab, &isa_config, assertion_mode);
+    let shared_state = &iarch.shared_state;
+
+    let Some((architecture, feature, layout)) = gdb_layout(matches.opt_str("gdb-arch").as_deref().unwrap_or("aarch64"))
+    else {
+        eprintln!("--gdb-arch must be one of aarch64, riscv64, or riscv32");
+        return 1;
+    };
+
+    let mut overrides = std::collections::HashMap::new();
+    for arg in matches.opt_strs("gdb-register") {
+        let Some((gdb_name, register)) = arg.split_once('=') else {
+            eprintln!("Could not parse --gdb-register {}, expected <gdb name>=<register>", arg);
+            return 1;
+        };
+        overrides.insert(gdb_name.to_string(), register.to_string());
+    }
+
+    let mut registers = Vec::new();
+    let mut sources = Vec::new();
+    for (gdb_name, bitsize, register) in layout {
+        let source = match overrides.get(&gdb_name).or(register.as_ref()).map(String::as_str) {
+            None => Source::Register(isa_config.pc),
+            Some("") => Source::Unavailable,
+            Some(register) => match shared_state.symtab.get(&zencode::encode(register)) {
+                Some(name) if iarch.regs.contains_key(name) => Source::Register(name),
+                // RISC-V models have no register for x0
+                _ if gdb_name == "x0" && architecture.starts_with("riscv") => Source::Zero,
+                _ => {
+                    eprintln!("Register {} for gdb register {} not found, it will be unavailable", register, gdb_name);
+                    Source::Unavailable
+                }
+            },
+        };
+        registers.push(RegisterInfo { name: gdb_name, bitsize });
+        sources.push(source)
+    }
+
+    let Some(file) = matches.opt_str("elf") else {
+        eprintln!("No ELF file given, use --elf <file>");
+        return 1;
+    };
+    let buf = match std::fs::read(&file) {
+        Ok(buf) => buf,
+        Err(e) => {
+            eprintln!("Could not read ELF file {}: {}", file, e);
+            return 1;
+        }
+    };
+    let Some((_, elf, _)) = elf::parse_elf_with_debug_info(&buf) else {
+        eprintln!("Failed to parse ELF file {}", file);
+        return 1;
+    };
+    if !elf.little_endian {
+        eprintln!("Only little-endian ELF files are supported");
+        return 1;
+    }
+
+    let mut memory = Memory::new();
+    for mmio in &isa_config.mmio_regions {
+        memory.add_device_region(mmio.range.clone(), DeviceRegion::new(&mmio.name, &mmio.values))
+    }
+    for header in elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD) {
+        let offset = header.p_offset as usize;
+        let Some(data) = buf.get(offset..offset + header.p_filesz as usize) else {
+            eprintln!("Segment at {:#x} extends past the end of {}", header.p_vaddr, file);
+            return 1;
+        };
+        let contents = (header.p_vaddr..).zip(data.iter().copied()).collect();
+        memory.add_concrete_region(header.p_vaddr..header.p_vaddr + header.p_memsz, contents)
+    }
+    // Everything outside the ELF segments (such as the stack) starts
+    // out as zero
+    memory.add_zero_region(0..u64::MAX);
+
+    let mut regs = iarch.regs.clone();
+    let pc_len = match regs.get_last_if_initialized(isa_config.pc) {
+        Some(Val::Bits(bv)) => bv.len(),
+        _ => 64,
+    };
+    regs.assign(isa_config.pc, Val::Bits(B129::new(elf.entry, pc_len)), shared_state);
+
+    let function_id = match matches.opt_str("function") {
+        Some(id) => shared_state.symtab.lookup(&zencode::encode(&id)),
+        None => shared_state.symtab.lookup("zisla_footprint"),
+    };
+
+    let target = ConcreteTarget {
+        shared_state,
+        function_id,
+        lets: iarch.lets.clone(),
+        regs,
+        memory,
+        checkpoint: Checkpoint::new(),
+        task_state: TaskState::new(),
+        pc: isa_config.pc,
+        architecture,
+        feature,
+        registers,
+        sources,
+    };
+
+    let port = matches.opt_str("port").unwrap_or_else(|| "1234".to_string());
+    let listener = match TcpListener::bind(format!("127.0.0.1:{}", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            return 1;
+        }
+    };
+    eprintln!("Waiting for gdb on port {} (entry point {:#x})", port, elf.entry);
+
+    let mut stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(e) => {
+            eprintln!("Failed to accept connection: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = GdbStub::new(target).serve(&mut stream) {
+        eprintln!("Connection to gdb failed: {}", e);
+        return 1;
+    }
+    0
+}
@@ -40,12 +40,18 @@ use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 
+use isla_lib::affinity;
 use isla_lib::bitvector::BV;
 use isla_lib::config::ISAConfig;
+use isla_lib::executor::shed::MemoryUsage;
+use isla_lib::executor::TaskState;
 use isla_lib::ir;
+use isla_lib::ir::enum_index;
 use isla_lib::ir::linearize;
 use isla_lib::ir::partial_linearize;
+use isla_lib::ir::specialize;
 use isla_lib::ir::*;
 use isla_lib::ir_parser;
 use isla_lib::log;
@@ -75,6 +81,8 @@ pub fn print_usage(opts: &Options, free: &str, code: i32) -> ! {
 pub fn common_opts() -> Options {
     let mut opts = Options::new();
     opts.optopt("T", "threads", "use this many worker threads", "<n>");
+    opts.optflag("", "pin-threads", "pin worker threads to cores, grouped by NUMA node");
+    opts.optflag("", "deterministic", "use a single thread and fixed hashing, so runs are reproducible");
     opts.reqopt("A", "arch", "load architecture file", "<file>");
     opts.optopt("C", "config", "load custom config for architecture", "<file>");
     opts.optopt("", "toolchain", "use specified toolchain from config", "<name>");
@@ -91,7 +99,10 @@ pub fn common_opts() -> Options {
     opts.optmulti("", "abstract", "make function abstract", "<id>");
     opts.optmulti("", "debug-id", "print the name of an interned identifier (for debugging)", "<name id>");
     opts.optmulti("", "reset-constraint", "property to enforce at the reset_registers builtin", "<constraint>");
+    opts.optmulti("", "preset", "enforce a named set of reset constraints from the config", "<name>");
     opts.optflag("", "fork-assertions", "change assertions into explicit control flow");
+    opts.optflag("", "specialize", "specialize the architecture to registers with fixed initial values");
+    opts.optflag("", "fold-enum-indices", "fold known enum to integer conversions and lower register bank accesses");
     opts.optmulti("", "fun-assumption", "add an assumption about the behaviour of a Sail function", "<assumption>");
     opts
 }
@@ -219,7 +230,7 @@ fn parse_ir<'a, 'input, B: BV>(contents: &'input str, symtab: &'a mut Symtab<'in
     }
 }
 
-fn load_ir<P, B>(hasher: &mut Sha256, file: P) -> Result<Architecture<B>, SerializationError>
+pub fn load_ir<P, B>(hasher: &mut Sha256, file: P) -> Result<Architecture<B>, SerializationError>
 where
     P: AsRef<Path>,
     B: BV,
@@ -273,9 +284,16 @@ pub fn parse<B: BV>(hasher: &mut Sha256, opts: &Options) -> (Matches, Architectu
         | (if debug_opts.contains('m') { log::MEMORY } else { 0u32 })
         | (if debug_opts.contains('l') { log::LITMUS } else { 0u32 })
         | (if debug_opts.contains('g') { log::GRAPH } else { 0u32 })
+        | (if debug_opts.contains('a') { log::ACCESSOR } else { 0u32 })
         | (if debug_opts.contains('p') { log::PROBE } else { 0u32 });
     log::set_flags(logging_flags);
 
+    // This must happen before the architecture is loaded, so every
+    // map it creates uses the fixed keys
+    if matches.opt_present("deterministic") {
+        ir::set_deterministic_hashing()
+    }
+
     let arch = {
         let file = matches.opt_str("arch").unwrap();
         match load_ir(hasher, &file) {
@@ -290,6 +308,119 @@ pub fn parse<B: BV>(hasher: &mut Sha256, opts: &Options) -> (Matches, Architectu
     (matches, arch)
 }
 
+/// Determine how Sail assertions should be treated from the
+/// `--assertions` option, which tools that support it should add to
+/// their options.
+pub fn assertion_mode(matches: &Matches, default: AssertionMode) -> AssertionMode {
+    match matches.opt_str("assertions") {
+        Some(mode) => mode.parse().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1)
+        }),
+        None => default,
+    }
+}
+
+/// Add options capping the resources used by each path, which tools
+/// that support them should apply with [path_caps].
+#[allow(dead_code)]
+pub fn path_cap_opts(opts: &mut Options) {
+    opts.optopt("", "max-forks", "fail any path which forks more than <n> times", "<n>");
+    opts.optopt("", "max-solver-ms-per-path", "fail any path which spends more than <ms> in the SMT solver", "<ms>");
+    opts.optopt("", "max-events-per-path", "fail any path whose trace has more than <n> events", "<n>");
+}
+
+/// Set the per-path resource caps given by the options from
+/// [path_cap_opts] on a task.
+#[allow(dead_code)]
+pub fn path_caps<B>(matches: &Matches, task_state: &mut TaskState<B>) {
+    fn cap<T: std::str::FromStr>(matches: &Matches, name: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        matches.opt_get(name).unwrap_or_else(|e| {
+            eprintln!("Failed to parse --{}: {}", name, e);
+            exit(1)
+        })
+    }
+    task_state.set_fork_limit(cap(matches, "max-forks"));
+    task_state.set_solver_time_limit(cap(matches, "max-solver-ms-per-path").map(Duration::from_millis));
+    task_state.set_event_limit(cap(matches, "max-events-per-path"));
+}
+
+/// Add the `--register-index` option, which tools that support it
+/// should apply with [register_index_strategy].
+#[allow(dead_code)]
+pub fn register_index_opts(opts: &mut Options) {
+    opts.optopt(
+        "",
+        "register-index",
+        "how to access register files at a symbolic index, e.g. from a symbolic opcode: \
+         ite (default) selects the register in SMT, split forks once for each register",
+        "ite/split",
+    );
+}
+
+/// Set the strategy for register file accesses at a symbolic index
+/// from the option added by [register_index_opts].
+#[allow(dead_code)]
+pub fn register_index_strategy<B>(matches: &Matches, task_state: &mut TaskState<B>) {
+    if let Some(strategy) = matches.opt_str("register-index") {
+        match strategy.parse() {
+            Ok(strategy) => task_state.set_register_index_strategy(strategy),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1)
+            }
+        }
+    }
+}
+
+/// Add options for reporting and capping the memory used by each
+/// worker, which tools that support them should apply with
+/// [memory_usage].
+#[allow(dead_code)]
+pub fn memory_opts(opts: &mut Options) {
+    opts.optopt("", "memory-cap", "write new tasks to disk while workers use more than <MiB> (approximately)", "<MiB>");
+    opts.optopt("", "shed-dir", "directory for tasks written to disk by --memory-cap", "<dir>");
+    opts.optflag("", "memory-stats", "report the approximate memory used by each worker");
+}
+
+/// Track the memory used by each worker in a task if any of the
+/// options from [memory_opts] were given, returning the usage so it
+/// can be reported.
+#[allow(dead_code)]
+pub fn memory_usage<B>(
+    matches: &Matches,
+    num_threads: usize,
+    task_state: &mut TaskState<B>,
+) -> Option<Arc<MemoryUsage>> {
+    let cap: Option<usize> = matches.opt_get("memory-cap").unwrap_or_else(|e| {
+        eprintln!("Failed to parse --memory-cap: {}", e);
+        exit(1)
+    });
+    let usage = match cap {
+        Some(cap) => {
+            let dir = matches
+                .opt_str("shed-dir")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join(format!("isla-shed-{}", std::process::id())));
+            match MemoryUsage::with_cap(num_threads, cap * 1024 * 1024, dir.clone()) {
+                Ok(usage) => usage,
+                Err(e) => {
+                    eprintln!("Could not create --shed-dir {}: {}", dir.display(), e);
+                    exit(1)
+                }
+            }
+        }
+        None if matches.opt_present("memory-stats") => MemoryUsage::new(num_threads),
+        None => return None,
+    };
+    let usage = Arc::new(usage);
+    task_state.set_memory_usage(Some(usage.clone()));
+    Some(usage)
+}
+
 pub fn reset_from_string<B: BV>(arg: String, symtab: &Symtab) -> (Loc<Name>, Reset<B>) {
     let (loc, value) = match value_parser::UAssignParser::new().parse::<B, _, _>(symtab, new_ir_lexer(&arg)) {
         Ok((loc, value)) => {
@@ -324,14 +455,42 @@ pub fn parse_with_arch<'ir, B: BV>(
     opts: &Options,
     matches: &Matches,
     arch: &'ir Architecture<B>,
+) -> CommonOpts<'ir, B> {
+    parse_with_arch_and_default_config(hasher, opts, matches, arch, None)
+}
+
+/// As [parse_with_arch], but with a named configuration to use when
+/// `--config` is not given, rather than the default configuration.
+pub fn parse_with_arch_and_default_config<'ir, B: BV>(
+    hasher: &mut Sha256,
+    opts: &Options,
+    matches: &Matches,
+    arch: &'ir Architecture<B>,
+    default_config: Option<(&str, &str)>,
+) -> CommonOpts<'ir, B> {
+    parse_with_arch_and_config(hasher, opts, matches, arch, matches.opt_str("config"), default_config)
+}
+
+/// As [parse_with_arch_and_default_config], but reading the
+/// configuration from `config` rather than the `--config` option,
+/// e.g. for a second architecture which has its own configuration.
+pub fn parse_with_arch_and_config<'ir, B: BV>(
+    hasher: &mut Sha256,
+    opts: &Options,
+    matches: &Matches,
+    arch: &'ir Architecture<B>,
+    config: Option<String>,
+    default_config: Option<(&str, &str)>,
 ) -> CommonOpts<'ir, B> {
     let num_threads = match matches.opt_get_default("threads", default_parallelism()) {
+        Ok(_) if matches.opt_present("deterministic") => 1,
         Ok(t) => t,
         Err(f) => {
             eprintln!("Could not parse --threads option: {}", f);
             print_usage(opts, "", 1)
         }
     };
+    affinity::set_pin_workers(matches.opt_present("pin-threads"));
 
     let (mut symtab, mut arch) = match arch {
         Architecture::Unparsed(arch) => {
@@ -345,7 +504,7 @@ pub fn parse_with_arch<'ir, B: BV>(
         }
     };
 
-    let mut isa_config = if let Some(file) = matches.opt_str("config") {
+    let mut isa_config = if let Some(file) = config {
         match ISAConfig::from_file(hasher, file, matches.opt_str("toolchain").as_deref(), &symtab) {
             Ok(isa_config) => isa_config,
             Err(e) => {
@@ -353,6 +512,16 @@ pub fn parse_with_arch<'ir, B: BV>(
                 exit(1)
             }
         }
+    } else if let Some((name, contents)) = default_config {
+        hasher.input(contents);
+        hasher.input(matches.opt_str("toolchain").as_deref().unwrap_or("default"));
+        match ISAConfig::parse(contents, matches.opt_str("toolchain").as_deref(), &symtab) {
+            Ok(isa_config) => isa_config,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                exit(1)
+            }
+        }
     } else {
         match ISAConfig::new(&symtab) {
             Ok(isa_config) => isa_config,
@@ -413,6 +582,44 @@ pub fn parse_with_arch<'ir, B: BV>(
         }
     });
 
+    if matches.opt_present("specialize") {
+        // Registers with reset values are assigned outside the IR, so
+        // they cannot be treated as fixed
+        let fixed: HashMap<Name, Val<B>> = isa_config
+            .default_registers
+            .iter()
+            .filter(|(reg, _)| !isa_config.reset_registers.iter().any(|(loc, _)| loc.id() == **reg))
+            .map(|(reg, value)| (*reg, value.clone()))
+            .collect();
+        let stats = specialize::specialize(&mut arch, &fixed);
+        log!(
+            log::VERBOSE,
+            &format!(
+                "Specialized {} registers: folded {} branches, removed {} instructions",
+                stats.registers.len(),
+                stats.branches_folded,
+                stats.instrs_removed
+            )
+        );
+        for reg in &stats.assigned {
+            log!(
+                log::VERBOSE,
+                &format!("Register {} is assigned, so was not specialized", zencode::decode(symtab.to_str(*reg)))
+            )
+        }
+    }
+
+    if matches.opt_present("fold-enum-indices") {
+        let stats = enum_index::fold_enum_indices(&mut arch, &symtab);
+        log!(
+            log::VERBOSE,
+            &format!(
+                "Folded {} enum index conversions, propagated {} constant variables, lowered {} register bank accesses",
+                stats.calls_folded, stats.variables_propagated, stats.accesses_lowered
+            )
+        )
+    }
+
     matches.opt_strs("abstract").iter().for_each(|arg| {
         if let Some((id, property_id)) = arg.split_once(|c| c == ' ' || c == ':') {
             let target = symtab.get(&zencode::encode(id.trim()));
@@ -562,6 +769,13 @@ pub fn parse_with_arch<'ir, B: BV>(
         }
     });
 
+    for preset in matches.opt_strs("preset") {
+        if let Err(e) = isa_config.use_constraint_preset(&preset) {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    }
+
     for constraint in matches.opt_strs("reset-constraint") {
         // NB: this doesn't have enough information to check if the locations exist
         match smt_parser::ExpParser::new().parse(&constraint) {
@@ -29,11 +29,11 @@
 
 use sha2::{Digest, Sha256};
 use std::process::exit;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use isla_lib::bitvector::b129::B129;
 use isla_lib::executor;
+use isla_lib::executor::counterexample::{counterexample_collector, CounterexampleQueue};
 use isla_lib::executor::{LocalFrame, TaskState};
 use isla_lib::init::{initialize_architecture, Initialized};
 use isla_lib::ir::*;
@@ -53,6 +53,12 @@ fn isla_main() -> i32 {
     let mut opts = opts::common_opts();
     opts.reqopt("p", "property", "check property in architecture", "<id>");
     opts.optflag("", "optimistic", "assume assertions succeed");
+    opts.optopt(
+        "",
+        "assertions",
+        "how to treat Sail assertions: assume, check, or ignore (overrides --optimistic)",
+        "<mode>",
+    );
 
     let mut hasher = Sha256::new();
     let (matches, arch) = opts::parse::<B129>(&mut hasher, &opts);
@@ -61,6 +67,7 @@ fn isla_main() -> i32 {
 
     let assertion_mode =
         if matches.opt_present("optimistic") { AssertionMode::Optimistic } else { AssertionMode::Pessimistic };
+    let assertion_mode = opts::assertion_mode(&matches, assertion_mode);
 
     let Initialized { regs, lets, shared_state } =
         initialize_architecture(&mut arch, symtab, &isa_config, assertion_mode);
@@ -72,15 +79,18 @@ fn isla_main() -> i32 {
     let task_state = TaskState::new();
     let task =
         LocalFrame::new(function_id, args, ret_ty, None, instrs).add_lets(&lets).add_regs(&regs).task(0, &task_state);
-    let result = Arc::new(AtomicBool::new(true));
+    let result = Arc::new(CounterexampleQueue::new());
 
-    executor::start_multi(num_threads, None, vec![task], &shared_state, result.clone(), &executor::all_unsat_collector);
+    executor::start_multi(num_threads, None, vec![task], &shared_state, result.clone(), &counterexample_collector);
 
-    if result.load(Ordering::Acquire) {
+    if result.is_empty() {
         println!("ok");
         0
     } else {
         println!("fail");
+        while let Some(counterexample) = result.pop() {
+            println!("{}", counterexample.to_string(&shared_state.symtab))
+        }
         1
     }
 }
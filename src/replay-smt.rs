@@ -0,0 +1,283 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Replays a log of solver queries written with `--log-smt`, either
+//! against Z3 via the C API (as used by isla itself) or against any
+//! SMTLIB solver that reads commands from stdin, such as
+//! `z3 -in` or `cvc5 --incremental`.
+
+use getopts::Options;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{exit, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use isla_lib::bitvector::b129::B129;
+use isla_lib::ir::Symtab;
+use isla_lib::simplify::{write_events_in_context, WriteOpts};
+use isla_lib::smt::query_log::{read_sessions, replay_session, QueryLogEntry, ReplayedQuery};
+use isla_lib::smt::smtlib::{Def, Exp, Ty};
+use isla_lib::smt::{Config, Context, Event, SmtResult, Solver, Sym};
+use isla_lib::source_loc::SourceLoc;
+
+fn main() {
+    let code = isla_main();
+    unsafe { isla_lib::smt::finalize_solver() };
+    exit(code)
+}
+
+fn print_usage(opts: &Options, code: i32) -> ! {
+    let brief = "Usage: isla-replay-smt [options] <log>";
+    eprint!("{}", opts.usage(brief));
+    exit(code)
+}
+
+/// An external SMTLIB solver process, which we keep a single session
+/// open with, using push and pop for each query.
+struct External {
+    stdin: std::process::ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    tcx: Cow<'static, HashMap<Sym, Ty>>,
+    ftcx: Cow<'static, HashMap<Sym, (Vec<Ty>, Ty)>>,
+}
+
+impl External {
+    fn spawn(command: &str) -> io::Result<(std::process::Child, Self)> {
+        let mut words = command.split_whitespace();
+        let program =
+            words.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty solver command"))?;
+        let mut child = Command::new(program).args(words).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok((child, External { stdin, stdout, tcx: Cow::Owned(HashMap::new()), ftcx: Cow::Owned(HashMap::new()) }))
+    }
+
+    fn write_def(&mut self, def: &Def, symtab: &Symtab) -> io::Result<()> {
+        if let Def::DefineEnum(size) = def {
+            write!(self.stdin, "(declare-datatypes ((Enum{} 0)) ((", size)?;
+            for i in 0..*size {
+                write!(self.stdin, "(e{}_{})", size, i)?
+            }
+            writeln!(self.stdin, ")))")
+        } else {
+            let event: Event<B129> = Event::Smt(def.clone(), Default::default(), SourceLoc::unknown());
+            write_events_in_context(
+                &mut self.stdin,
+                &[event],
+                symtab,
+                &WriteOpts::smtlib(),
+                &mut self.tcx,
+                &mut self.ftcx,
+            )?;
+            writeln!(self.stdin)
+        }
+    }
+
+    fn check_sat(&mut self, assumption: Option<&Exp<Sym>>, symtab: &Symtab) -> io::Result<(SmtResult, Duration)> {
+        if let Some(exp) = assumption {
+            writeln!(self.stdin, "(push 1)")?;
+            self.write_def(&Def::Assert(exp.clone()), symtab)?
+        }
+        writeln!(self.stdin, "(check-sat)")?;
+        self.stdin.flush()?;
+        let now = Instant::now();
+        let mut line = String::new();
+        self.stdout.read_line(&mut line)?;
+        let time = now.elapsed();
+        if assumption.is_some() {
+            writeln!(self.stdin, "(pop 1)")?
+        }
+        match line.trim() {
+            "sat" => Ok((SmtResult::Sat, time)),
+            "unsat" => Ok((SmtResult::Unsat, time)),
+            "unknown" => Ok((SmtResult::Unknown, time)),
+            other => Err(io::Error::new(io::ErrorKind::Other, format!("unexpected solver response: {}", other))),
+        }
+    }
+}
+
+fn replay_external(command: &str, entries: &[QueryLogEntry], symtab: &Symtab) -> io::Result<Vec<ReplayedQuery>> {
+    let (mut child, mut solver) = External::spawn(command)?;
+    let mut queries = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match entry {
+            QueryLogEntry::Def(def) => solver.write_def(def, symtab)?,
+            QueryLogEntry::CheckSat { assumption, result: logged_result, micros } => {
+                let (result, time) = solver.check_sat(assumption.as_ref(), symtab)?;
+                queries.push(ReplayedQuery {
+                    index,
+                    logged_result: *logged_result,
+                    logged_time: Duration::from_micros(*micros),
+                    result,
+                    time,
+                })
+            }
+        }
+    }
+    writeln!(solver.stdin, "(exit)")?;
+    drop(solver);
+    child.wait()?;
+    Ok(queries)
+}
+
+fn isla_main() -> i32 {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message");
+    opts.optopt("", "solver", "replay using an external SMTLIB solver rather than Z3 via its C API", "<command>");
+    opts.optmulti("", "param", "set a Z3 parameter (when not using --solver)", "<key=value>");
+    opts.optmulti("", "session", "only replay the given session", "<n>");
+    opts.optopt("", "slowest", "list the n queries that took longest to replay", "<n>");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", f);
+            print_usage(&opts, 1)
+        }
+    };
+    if matches.opt_present("help") || matches.free.len() != 1 {
+        print_usage(&opts, 0)
+    }
+
+    let mut params = Vec::new();
+    for param in matches.opt_strs("param") {
+        match param.split_once('=') {
+            Some((key, value)) => params.push((key.to_string(), value.to_string())),
+            None => {
+                eprintln!("Z3 parameter {} should be of the form key=value", param);
+                return 1;
+            }
+        }
+    }
+
+    let slowest: usize = match matches.opt_get_default("slowest", 0) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("Failed to parse --slowest: {}", e);
+            return 1;
+        }
+    };
+
+    let sessions = match File::open(&matches.free[0]).map_err(Into::into).and_then(read_sessions) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Failed to read query log {}: {}", matches.free[0], e);
+            return 1;
+        }
+    };
+
+    let selected: Vec<usize> = if matches.opt_present("session") {
+        match matches.opt_strs("session").iter().map(|n| n.parse::<usize>()).collect() {
+            Ok(selected) => selected,
+            Err(e) => {
+                eprintln!("Failed to parse --session: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        (0..sessions.len()).collect()
+    };
+
+    let symtab = Symtab::new();
+    let mut all_queries = Vec::new();
+    let mut exit_code = 0;
+
+    for session in selected {
+        let entries = match sessions.get(session) {
+            Some(entries) => entries,
+            None => {
+                eprintln!("Session {} does not exist, the log has {} sessions", session, sessions.len());
+                return 1;
+            }
+        };
+
+        let queries = if let Some(command) = matches.opt_str("solver") {
+            match replay_external(&command, entries, &symtab) {
+                Ok(queries) => queries,
+                Err(e) => {
+                    eprintln!("Session {}: external solver failed: {}", session, e);
+                    exit_code = 1;
+                    continue;
+                }
+            }
+        } else {
+            let mut cfg = Config::new();
+            for (key, value) in &params {
+                cfg.set_param_value(key, value)
+            }
+            let ctx = Context::new(cfg);
+            let mut solver = Solver::<B129>::new(&ctx);
+            replay_session(&mut solver, entries)
+        };
+
+        let logged: Duration = queries.iter().map(|query| query.logged_time).sum();
+        let replayed: Duration = queries.iter().map(|query| query.time).sum();
+        println!(
+            "Session {}: {} queries, logged {}ms, replayed {}ms",
+            session,
+            queries.len(),
+            logged.as_millis(),
+            replayed.as_millis()
+        );
+        for query in queries.iter().filter(|query| query.diverges()) {
+            println!(
+                "  Query at entry {} diverged: logged {:?}, replayed {:?}",
+                query.index, query.logged_result, query.result
+            );
+            if query.logged_result != SmtResult::Unknown && query.result != SmtResult::Unknown {
+                exit_code = 1
+            }
+        }
+
+        all_queries.extend(queries.into_iter().map(|query| (session, query)))
+    }
+
+    if slowest > 0 {
+        all_queries.sort_by(|(_, q1), (_, q2)| q2.time.cmp(&q1.time));
+        println!("Slowest queries:");
+        for (session, query) in all_queries.iter().take(slowest) {
+            println!(
+                "  session {} entry {}: {:?} in {}ms (logged {:?} in {}ms)",
+                session,
+                query.index,
+                query.result,
+                query.time.as_millis(),
+                query.logged_result,
+                query.logged_time.as_millis()
+            )
+        }
+    }
+
+    exit_code
+}
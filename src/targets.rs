@@ -0,0 +1,84 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Built-in targets, which bundle an architecture configuration
+//! (including the event kinds and barrier classifications) with a
+//! memory model, so a litmus test can be run with just an
+//! architecture snapshot.
+
+use isla_mml::memory_model::{self, ExpArena, MemoryModel, Symtab};
+
+pub struct Target {
+    pub name: &'static str,
+    pub description: &'static str,
+    config_name: &'static str,
+    config: &'static str,
+    model_name: &'static str,
+    model: &'static str,
+}
+
+pub static TARGETS: &[Target] = &[
+    Target {
+        name: "aarch64",
+        description: "Armv8-A with the Arm user-mode memory model",
+        config_name: "aarch64.toml",
+        config: include_str!("../configs/aarch64.toml"),
+        model_name: "aarch64.cat",
+        model: include_str!("../web/client/dist/aarch64.cat"),
+    },
+    Target {
+        name: "riscv64",
+        description: "64-bit RISC-V with the RVWMO memory model",
+        config_name: "riscv64.toml",
+        config: include_str!("../configs/riscv64.toml"),
+        model_name: "riscv.cat",
+        model: include_str!("../web/client/dist/riscv.cat"),
+    },
+];
+
+impl Target {
+    pub fn find(name: &str) -> Result<&'static Target, String> {
+        TARGETS.iter().find(|target| target.name == name).ok_or_else(|| {
+            let mut message = format!("Unknown target {}, available targets are:", name);
+            for target in TARGETS {
+                message.push_str(&format!("\n  {:<10} {}", target.name, target.description))
+            }
+            message
+        })
+    }
+
+    /// The name and contents of the configuration for the target
+    pub fn config(&self) -> (&'static str, &'static str) {
+        (self.config_name, self.config)
+    }
+
+    pub fn load_memory_model(&self, arena: &mut ExpArena, symtab: &mut Symtab) -> Result<MemoryModel, String> {
+        memory_model::load_builtin_memory_model(self.model_name, self.model, arena, symtab)
+    }
+}
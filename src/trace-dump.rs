@@ -0,0 +1,144 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, BufWriter, Read, Write};
+use std::process::exit;
+use std::sync::Arc;
+
+use isla_lib::binary_trace::{is_binary_trace, BinaryTraceReader, MAGIC};
+use isla_lib::bitvector::b129::B129;
+use isla_lib::instruction_names::InstructionNames;
+use isla_lib::simplify;
+use isla_lib::simplify::WriteOpts;
+use isla_lib::trace_file::open_trace_file;
+use isla_lib::validate::validate_events;
+
+mod opts;
+use opts::CommonOpts;
+
+fn main() {
+    let code = isla_main();
+    unsafe { isla_lib::smt::finalize_solver() };
+    exit(code)
+}
+
+fn isla_main() -> i32 {
+    let mut opts = opts::common_opts();
+    opts.optflag("", "hide", "hide uninteresting trace elements");
+    opts.optflag("", "validate", "check traces are well-formed rather than printing them");
+    opts.optflag("", "recover", "also print the flushed events of paths which never finished in a binary trace file");
+    opts.optopt(
+        "",
+        "instruction-names",
+        "label opcodes using ARM XML or riscv-opcodes instruction metadata",
+        "<file or directory>",
+    );
+
+    let mut hasher = Sha256::new();
+    let (matches, arch) = opts::parse::<B129>(&mut hasher, &opts);
+    let CommonOpts { symtab, .. } = opts::parse_with_arch(&mut hasher, &opts, &matches, &arch);
+
+    if matches.free.is_empty() {
+        eprintln!("No trace file given");
+        return 1;
+    }
+
+    let validate = matches.opt_present("validate");
+    let mut exit_code = 0;
+
+    let instruction_names = match matches.opt_str("instruction-names") {
+        Some(path) => {
+            let mut names = InstructionNames::new();
+            if let Err(msg) = names.load(&path) {
+                eprintln!("{}", msg);
+                return 1;
+            }
+            Some(Arc::new(names))
+        }
+        None => None,
+    };
+
+    let write_opts =
+        WriteOpts { hide_uninteresting: matches.opt_present("hide"), instruction_names, ..WriteOpts::default() };
+    let stdout = io::stdout();
+    let mut handle = BufWriter::with_capacity(5 * usize::pow(2, 20), stdout.lock());
+
+    for path in &matches.free {
+        let mut fd = match open_trace_file(path) {
+            Ok(fd) => fd,
+            Err(e) => {
+                eprintln!("Could not open trace file {}: {}", path, e);
+                return 1;
+            }
+        };
+
+        let mut prefix = Vec::with_capacity(MAGIC.len());
+        if let Err(e) = (&mut fd).take(MAGIC.len() as u64).read_to_end(&mut prefix) {
+            eprintln!("Could not read trace file {}: {}", path, e);
+            return 1;
+        }
+
+        if is_binary_trace(&prefix) {
+            let traces = match BinaryTraceReader::<_, B129>::new((&prefix[..]).chain(fd)) {
+                Ok(traces) if matches.opt_present("recover") => traces.recovering(),
+                Ok(traces) => traces,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return 1;
+                }
+            };
+            for (i, trace) in traces.enumerate() {
+                match trace {
+                    Ok(events) if validate => {
+                        for err in validate_events(&events) {
+                            eprintln!("{}: trace {}: {}", path, i, err);
+                            exit_code = 1
+                        }
+                    }
+                    Ok(events) => simplify::write_events_with_opts(&mut handle, &events, &symtab, &write_opts).unwrap(),
+                    Err(e) => {
+                        eprintln!("{}: {}", path, e);
+                        return 1;
+                    }
+                }
+            }
+        } else if validate {
+            eprintln!("{}: only binary traces can be validated", path);
+            exit_code = 1
+        } else {
+            // Textual traces are already in the output format
+            handle.write_all(&prefix).unwrap();
+            io::copy(&mut fd, &mut handle).unwrap();
+        }
+    }
+
+    handle.flush().unwrap();
+    exit_code
+}
@@ -0,0 +1,121 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::process::exit;
+
+use isla_lib::binary_trace::{is_binary_trace, BinaryTraceReader, MAGIC};
+use isla_lib::bitvector::b129::B129;
+use isla_lib::trace_file::open_trace_file;
+use isla_lib::trace_stats::TraceStats;
+
+mod opts;
+use opts::CommonOpts;
+
+fn main() {
+    let code = isla_main();
+    unsafe { isla_lib::smt::finalize_solver() };
+    exit(code)
+}
+
+fn isla_main() -> i32 {
+    let mut opts = opts::common_opts();
+    opts.optopt("", "top", "only show the <n> traces with the most events", "<n>");
+    opts.optflag("", "recover", "read a binary trace file that ends part way through a trace, up to its last flush");
+
+    let mut hasher = Sha256::new();
+    let (matches, arch) = opts::parse::<B129>(&mut hasher, &opts);
+    let CommonOpts { symtab, .. } = opts::parse_with_arch(&mut hasher, &opts, &matches, &arch);
+
+    if matches.free.is_empty() {
+        eprintln!("No trace file given");
+        return 1;
+    }
+
+    let top: Option<usize> = match matches.opt_get("top") {
+        Ok(top) => top,
+        Err(e) => {
+            eprintln!("Failed to parse --top: {}", e);
+            return 1;
+        }
+    };
+
+    let mut stats = Vec::new();
+
+    for path in &matches.free {
+        let mut fd = match open_trace_file(path) {
+            Ok(fd) => fd,
+            Err(e) => {
+                eprintln!("Could not open trace file {}: {}", path, e);
+                return 1;
+            }
+        };
+
+        let mut prefix = Vec::with_capacity(MAGIC.len());
+        if let Err(e) = (&mut fd).take(MAGIC.len() as u64).read_to_end(&mut prefix) {
+            eprintln!("Could not read trace file {}: {}", path, e);
+            return 1;
+        }
+
+        if !is_binary_trace(&prefix) {
+            eprintln!("{}: only binary traces are supported (see the --binary-traces option of isla-footprint)", path);
+            return 1;
+        }
+
+        let traces = match BinaryTraceReader::<_, B129>::new((&prefix[..]).chain(fd)) {
+            Ok(traces) if matches.opt_present("recover") => traces.recovering(),
+            Ok(traces) => traces,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return 1;
+            }
+        };
+        for (i, trace) in traces.enumerate() {
+            match trace {
+                Ok(events) => stats.push((path, i, TraceStats::from_events(&events))),
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return 1;
+                }
+            }
+        }
+    }
+
+    if let Some(top) = top {
+        stats.sort_by(|(_, _, s1), (_, _, s2)| s2.events.cmp(&s1.events));
+        stats.truncate(top)
+    }
+
+    for (path, i, stats) in &stats {
+        println!("{}: trace {}: {}", path, i, stats.to_string(&symtab))
+    }
+
+    0
+}
@@ -29,6 +29,8 @@
 
 use serde::{Deserialize, Serialize};
 
+pub static ARCH_WHITELIST: [&str; 4] = ["aarch64", "aarch64-vmsa", "riscv32", "riscv64"];
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 pub struct Request {
     pub arch: String,
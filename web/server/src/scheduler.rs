@@ -0,0 +1,285 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Worker processes are a limited resource, so requests wait here for
+//! a free slot. There are two levels of queue: interactive requests
+//! are always started before batch requests, and one slot is kept
+//! free of batch requests so an interactive request never waits
+//! behind long exhaustive runs. Within each level, the waiting
+//! request whose client currently has the fewest running workers is
+//! started first, so a single client cannot monopolise the server.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Batch,
+}
+
+struct Waiter {
+    client: Option<IpAddr>,
+    wake: oneshot::Sender<()>,
+}
+
+struct State {
+    running: usize,
+    running_batch: usize,
+    interactive: VecDeque<Waiter>,
+    batch: VecDeque<Waiter>,
+    /// The number of running workers for each client
+    per_client: HashMap<Option<IpAddr>, usize>,
+}
+
+pub struct Scheduler {
+    max_workers: usize,
+    state: Mutex<State>,
+}
+
+/// A running worker slot, which is released when dropped
+pub struct Slot<'a> {
+    scheduler: &'a Scheduler,
+    client: Option<IpAddr>,
+    priority: Priority,
+}
+
+impl<'a> Drop for Slot<'a> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        self.scheduler.release(&mut state, self.client, self.priority)
+    }
+}
+
+/// A request waiting for a slot. If it is dropped while still
+/// waiting, because the request was cancelled, any slot it was
+/// granted in the meantime is released.
+struct Waiting<'a> {
+    scheduler: &'a Scheduler,
+    client: Option<IpAddr>,
+    priority: Priority,
+    woken: Option<oneshot::Receiver<()>>,
+}
+
+impl<'a> Drop for Waiting<'a> {
+    fn drop(&mut self) {
+        if let Some(mut woken) = self.woken.take() {
+            // Holding the lock means dispatch cannot wake us between
+            // closing the channel and checking it
+            let mut state = self.scheduler.state.lock().unwrap();
+            woken.close();
+            if woken.try_recv().is_ok() {
+                self.scheduler.release(&mut state, self.client, self.priority)
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn new(max_workers: usize) -> Self {
+        Scheduler {
+            max_workers,
+            state: Mutex::new(State {
+                running: 0,
+                running_batch: 0,
+                interactive: VecDeque::new(),
+                batch: VecDeque::new(),
+                per_client: HashMap::new(),
+            }),
+        }
+    }
+
+    fn batch_limit(&self) -> usize {
+        if self.max_workers > 1 {
+            self.max_workers - 1
+        } else {
+            1
+        }
+    }
+
+    /// Remove the waiter whose client has the fewest running workers,
+    /// preferring those that have waited longest
+    fn take_fairest(queue: &mut VecDeque<Waiter>, per_client: &HashMap<Option<IpAddr>, usize>) -> Option<Waiter> {
+        let (i, _) = queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, waiter)| (per_client.get(&waiter.client).copied().unwrap_or(0), *i))?;
+        queue.remove(i)
+    }
+
+    /// Give up a running slot and start any request waiting for it
+    fn release(&self, state: &mut State, client: Option<IpAddr>, priority: Priority) {
+        state.running -= 1;
+        if priority == Priority::Batch {
+            state.running_batch -= 1
+        }
+        if let Some(n) = state.per_client.get_mut(&client) {
+            *n -= 1;
+            if *n == 0 {
+                state.per_client.remove(&client);
+            }
+        }
+        self.dispatch(state)
+    }
+
+    /// Start as many waiting requests as there are free slots
+    fn dispatch(&self, state: &mut State) {
+        while state.running < self.max_workers {
+            let (waiter, priority) =
+                if let Some(waiter) = Scheduler::take_fairest(&mut state.interactive, &state.per_client) {
+                    (waiter, Priority::Interactive)
+                } else if state.running_batch < self.batch_limit() {
+                    match Scheduler::take_fairest(&mut state.batch, &state.per_client) {
+                        Some(waiter) => (waiter, Priority::Batch),
+                        None => return,
+                    }
+                } else {
+                    return;
+                };
+            // The request may have been abandoned while waiting
+            if waiter.wake.send(()).is_ok() {
+                state.running += 1;
+                if priority == Priority::Batch {
+                    state.running_batch += 1
+                }
+                *state.per_client.entry(waiter.client).or_insert(0) += 1
+            }
+        }
+    }
+
+    /// Wait for a free worker slot
+    pub async fn acquire(&self, client: Option<IpAddr>, priority: Priority) -> Slot<'_> {
+        let (wake, woken) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let waiter = Waiter { client, wake };
+            match priority {
+                Priority::Interactive => state.interactive.push_back(waiter),
+                Priority::Batch => state.batch.push_back(waiter),
+            }
+            self.dispatch(&mut state)
+        }
+        let mut waiting = Waiting { scheduler: self, client, priority, woken: Some(woken) };
+        if let Some(woken) = &mut waiting.woken {
+            // The sender is only dropped after sending
+            let _ = woken.await;
+        }
+        // The slot is now ours, so the guard no longer needs to release it
+        waiting.woken = None;
+        Slot { scheduler: self, client, priority }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::future::Future;
+    use std::net::Ipv4Addr;
+    use std::pin::Pin;
+    use std::ptr;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) }
+    }
+
+    fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(&noop_waker()))
+    }
+
+    fn client(n: u8) -> Option<IpAddr> {
+        Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)))
+    }
+
+    fn start(scheduler: &Scheduler, n: u8, priority: Priority) -> Slot<'_> {
+        match poll_once(Box::pin(scheduler.acquire(client(n), priority)).as_mut()) {
+            Poll::Ready(slot) => slot,
+            Poll::Pending => panic!("request should start immediately"),
+        }
+    }
+
+    #[test]
+    fn batch_leaves_a_slot_for_interactive() {
+        let scheduler = Scheduler::new(2);
+
+        let _batch = start(&scheduler, 1, Priority::Batch);
+        let mut waiting = Box::pin(scheduler.acquire(client(2), Priority::Batch));
+        assert!(poll_once(waiting.as_mut()).is_pending());
+        let _interactive = start(&scheduler, 2, Priority::Interactive);
+    }
+
+    #[test]
+    fn fewest_running_client_first() {
+        let scheduler = Scheduler::new(2);
+
+        let _slot1 = start(&scheduler, 1, Priority::Interactive);
+        let slot2 = start(&scheduler, 3, Priority::Interactive);
+        let mut waiting1 = Box::pin(scheduler.acquire(client(1), Priority::Interactive));
+        assert!(poll_once(waiting1.as_mut()).is_pending());
+        let mut waiting2 = Box::pin(scheduler.acquire(client(2), Priority::Interactive));
+        assert!(poll_once(waiting2.as_mut()).is_pending());
+
+        // Client 1 already has a running worker, so client 2 is
+        // started first even though it has waited for less time
+        drop(slot2);
+        let started = poll_once(waiting2.as_mut());
+        assert!(started.is_ready());
+        assert!(poll_once(waiting1.as_mut()).is_pending())
+    }
+
+    #[test]
+    fn cancelled_request_releases_slot() {
+        let scheduler = Scheduler::new(1);
+
+        let slot = start(&scheduler, 1, Priority::Interactive);
+        let mut waiting = Box::pin(scheduler.acquire(client(2), Priority::Interactive));
+        assert!(poll_once(waiting.as_mut()).is_pending());
+
+        // The slot is handed to the waiting request, which is
+        // cancelled before it is polled again
+        drop(slot);
+        drop(waiting);
+        {
+            let state = scheduler.state.lock().unwrap();
+            assert_eq!(state.running, 0);
+            assert!(state.per_client.is_empty())
+        }
+
+        let _next = start(&scheduler, 3, Priority::Interactive);
+    }
+}
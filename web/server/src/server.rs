@@ -28,36 +28,43 @@
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use getopts::Options;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
 use tokio::sync::RwLock;
-use tokio::task;
 use warp::reject::Rejection;
 use warp::Filter;
 
 mod request;
-use request::{Request, Response};
+use request::{Request, Response, ARCH_WHITELIST};
+
+mod scheduler;
+use scheduler::{Priority, Scheduler};
+
+mod versions;
+use versions::{ArchVersion, ArchVersions};
 
-static WORKERS: AtomicUsize = AtomicUsize::new(0);
 static MAX_WORKERS: usize = 10;
 
-async fn spawn_worker_err(config: &Config, req: &Request) -> Option<String> {
-    loop {
-        let num = WORKERS.load(Ordering::SeqCst);
-        if num < MAX_WORKERS && WORKERS.compare_exchange(num, num + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
-            break;
-        }
-        task::yield_now().await;
-    }
+async fn spawn_worker_err(
+    config: &Config,
+    scheduler: &Scheduler,
+    client: Option<IpAddr>,
+    version: &ArchVersion,
+    req: &Request,
+) -> Option<String> {
+    // Exhaustive runs can take a long time, so they should not hold
+    // up interactive requests
+    let priority = if req.exhaustive { Priority::Batch } else { Priority::Interactive };
+    let _slot = scheduler.acquire(client, priority).await;
 
     let mut command = Command::new(&config.worker);
-    command.arg("--resources").arg(&config.resources).arg("--cache").arg(&config.cache);
+    command.arg("--resources").arg(&version.resources).arg("--cache").arg(&config.cache);
 
     if let Some(path) = &config.litmus_convert {
         command.arg("--litmus-convert").arg(path);
@@ -90,29 +97,79 @@ async fn spawn_worker_err(config: &Config, req: &Request) -> Option<String> {
         serde_json::to_string(&Response::InternalError).ok()?
     };
 
-    let num = WORKERS.fetch_sub(1, Ordering::SeqCst);
-    assert!(num != 0);
-
     Some(response)
 }
 
-async fn spawn_worker((config, req_cache, req): (&Config, &ReqCache, Request)) -> Result<String, Rejection> {
+/// Run the worker's `--check` mode on a candidate version of the
+/// resource files for an architecture.
+fn check_version(config: &Config, arch: &str, resources: &Path) -> Result<(), String> {
+    let mut command = std::process::Command::new(&config.worker);
+    command.arg("--resources").arg(resources).arg("--check").arg(arch);
+
+    if let Some(value) = &config.ld_library_path {
+        command.env("LD_LIBRARY_PATH", value);
+    }
+
+    let output = command.output().map_err(|e| format!("Failed to run worker: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// If the resource files for an architecture have changed, load and
+/// check them in the background. Requests keep using the current
+/// version until the new one has been checked.
+fn reload_in_background(config: &'static Config, versions: &'static ArchVersions, arch: &str) {
+    if versions.start_reload(arch) {
+        let arch = arch.to_string();
+        std::thread::spawn(move || {
+            if let Err(e) = versions.reload(&arch, |resources| check_version(config, &arch, resources)) {
+                eprintln!("Failed to reload {}: {}", arch, e)
+            }
+        });
+    }
+}
+
+async fn spawn_worker(
+    (config, req_cache, scheduler, versions, client, req): (
+        &'static Config,
+        &ReqCache,
+        &Scheduler,
+        &'static ArchVersions,
+        Option<SocketAddr>,
+        Request,
+    ),
+) -> Result<Box<dyn warp::Reply>, Rejection> {
+    if !ARCH_WHITELIST.contains(&req.arch.as_str()) {
+        let response = serde_json::to_string(&Response::InternalError).map_err(|_| warp::reject::reject())?;
+        return Ok(Box::new(response));
+    }
+
+    reload_in_background(config, versions, &req.arch);
+
+    let version: Arc<ArchVersion> = versions.current(&req.arch).ok_or_else(warp::reject::reject)?;
+    let key = (version.version, req);
+
     let cached = {
         let cache = req_cache.read().await;
-        cache.get(&req).map(String::to_owned)
+        cache.get(&key).map(String::to_owned)
     };
 
-    match cached {
-        Some(response) => Ok(response),
-        None => match spawn_worker_err(config, &req).await {
+    let response = match cached {
+        Some(response) => response,
+        None => match spawn_worker_err(config, scheduler, client.map(|addr| addr.ip()), &version, &key.1).await {
             Some(response) => {
                 let mut cache = req_cache.write().await;
-                cache.insert(req, response.clone());
-                Ok(response)
+                cache.insert(key, response.clone());
+                response
             }
-            None => Err(warp::reject::reject()),
+            None => return Err(warp::reject::reject()),
         },
-    }
+    };
+
+    Ok(Box::new(warp::reply::with_header(response, "x-isla-arch-version", version.version.to_string())))
 }
 
 #[derive(Clone)]
@@ -165,19 +222,47 @@ fn get_config() -> &'static Config {
     }))
 }
 
-type ReqCache = RwLock<HashMap<Request, String>>;
+/// Responses are cached per version of the architecture they were
+/// computed with
+type ReqCache = RwLock<HashMap<(u64, Request), String>>;
 
 fn create_cache() -> &'static ReqCache {
     Box::leak(Box::new(RwLock::new(HashMap::new())))
 }
 
+fn create_scheduler() -> &'static Scheduler {
+    Box::leak(Box::new(Scheduler::new(MAX_WORKERS)))
+}
+
+/// Load the initial version of every architecture before we start
+/// serving requests.
+fn create_versions(config: &'static Config) -> &'static ArchVersions {
+    let versions = Box::leak(Box::new(ArchVersions::new(config.resources.clone(), config.cache.join("versions"))));
+
+    for arch in ARCH_WHITELIST.iter() {
+        if !config.resources.join(format!("{}.irx", arch)).is_file() {
+            continue;
+        }
+        if versions.start_reload(arch) {
+            if let Err(e) = versions.reload(arch, |resources| check_version(config, arch, resources)) {
+                eprintln!("Failed to load {}: {}", arch, e)
+            }
+        }
+    }
+
+    versions
+}
+
 #[tokio::main]
 async fn main() {
     let config = get_config();
     let req_cache = create_cache();
+    let scheduler = create_scheduler();
+    let versions = create_versions(config);
 
     let dist = warp::filters::query::query::<Request>()
-        .map(move |req| (config, req_cache, req))
+        .and(warp::addr::remote())
+        .map(move |req, client| (config, req_cache, scheduler, versions, client, req))
         .and_then(spawn_worker)
         .or(warp::fs::dir(&config.dist));
 
@@ -0,0 +1,275 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The server can be left running while the pre-processed
+//! architectures in its resources directory are rebuilt. Requests are
+//! answered using a snapshot of each architecture's resource files,
+//! and when those files change a new snapshot is taken in the
+//! background. The new snapshot is only swapped in once a worker has
+//! successfully parsed and initialized the architecture from it, so
+//! requests never see a partially written or broken architecture, and
+//! requests that are already running keep the snapshot they started
+//! with. Each response is tagged with the version of the
+//! architecture it was computed with.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// The resource files read by the worker for an architecture
+fn resource_files(arch: &str) -> [String; 4] {
+    [format!("{}.toml", arch), format!("{}-footprint.toml", arch), format!("{}.symtab", arch), format!("{}.irx", arch)]
+}
+
+/// The size and modification time of each resource file for an
+/// architecture, used to notice when they change
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Fingerprint(Vec<Option<(u64, SystemTime)>>);
+
+impl Fingerprint {
+    fn of(resources: &Path, arch: &str) -> Self {
+        Fingerprint(
+            resource_files(arch)
+                .iter()
+                .map(|file| {
+                    let metadata = fs::metadata(resources.join(file)).ok()?;
+                    Some((metadata.len(), metadata.modified().ok()?))
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    IOError(io::Error),
+    CheckFailed(String),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ReloadError::*;
+        match self {
+            IOError(err) => write!(f, "IO error when taking snapshot: {}", err),
+            CheckFailed(msg) => write!(f, "Architecture failed to load: {}", msg),
+        }
+    }
+}
+
+impl Error for ReloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl From<io::Error> for ReloadError {
+    fn from(err: io::Error) -> Self {
+        ReloadError::IOError(err)
+    }
+}
+
+/// A snapshot of the resource files for an architecture. The
+/// snapshot is removed once no request is using it.
+pub struct ArchVersion {
+    pub version: u64,
+    /// A directory containing the snapshot, which can be given to
+    /// the worker as its resources directory
+    pub resources: PathBuf,
+    fingerprint: Fingerprint,
+}
+
+impl Drop for ArchVersion {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.resources);
+    }
+}
+
+pub struct ArchVersions {
+    resources: PathBuf,
+    snapshots: PathBuf,
+    current: RwLock<HashMap<String, Arc<ArchVersion>>>,
+    reloading: Mutex<HashSet<String>>,
+    /// The resource files for an architecture which last failed to
+    /// load, so we do not try them again until they change
+    rejected: Mutex<HashMap<String, Fingerprint>>,
+}
+
+impl ArchVersions {
+    /// Snapshots of the architectures in `resources` are taken in
+    /// the `snapshots` directory.
+    pub fn new(resources: PathBuf, snapshots: PathBuf) -> Self {
+        ArchVersions {
+            resources,
+            snapshots,
+            current: RwLock::new(HashMap::new()),
+            reloading: Mutex::new(HashSet::new()),
+            rejected: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The version of an architecture currently used to answer
+    /// requests, if it has been loaded
+    pub fn current(&self, arch: &str) -> Option<Arc<ArchVersion>> {
+        self.current.read().unwrap().get(arch).cloned()
+    }
+
+    /// Returns true if the resource files for an architecture have
+    /// changed since the current version was taken (or since they
+    /// last failed to load), and no reload is already in progress.
+    /// The caller must then call [ArchVersions::reload].
+    pub fn start_reload(&self, arch: &str) -> bool {
+        let fingerprint = Fingerprint::of(&self.resources, arch);
+        let stale = match self.current(arch) {
+            Some(current) => current.fingerprint != fingerprint,
+            None => true,
+        };
+        let rejected = self.rejected.lock().unwrap().get(arch) == Some(&fingerprint);
+        stale && !rejected && self.reloading.lock().unwrap().insert(arch.to_string())
+    }
+
+    /// Take a new snapshot of the resource files for an
+    /// architecture, and make it the current version if `check`
+    /// accepts it. Otherwise the current version is kept.
+    pub fn reload<F>(&self, arch: &str, check: F) -> Result<Arc<ArchVersion>, ReloadError>
+    where
+        F: FnOnce(&Path) -> Result<(), String>,
+    {
+        // The fingerprint is taken first, so if a file changes while
+        // we are copying it the architecture will be reloaded again
+        let fingerprint = Fingerprint::of(&self.resources, arch);
+        let result = self.snapshot(arch, fingerprint.clone()).and_then(|snapshot| {
+            check(&snapshot.resources).map_err(ReloadError::CheckFailed)?;
+            let snapshot = Arc::new(snapshot);
+            self.current.write().unwrap().insert(arch.to_string(), snapshot.clone());
+            Ok(snapshot)
+        });
+        if result.is_err() {
+            self.rejected.lock().unwrap().insert(arch.to_string(), fingerprint);
+        }
+        self.reloading.lock().unwrap().remove(arch);
+        result
+    }
+
+    fn snapshot(&self, arch: &str, fingerprint: Fingerprint) -> Result<ArchVersion, ReloadError> {
+        let version = self.current(arch).map(|current| current.version + 1).unwrap_or(0);
+        let dir = self.snapshots.join(arch).join(version.to_string());
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?
+        }
+        fs::create_dir_all(&dir)?;
+
+        let snapshot = ArchVersion { version, resources: dir, fingerprint };
+        for file in &resource_files(arch) {
+            fs::copy(self.resources.join(file), snapshot.resources.join(file))?;
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("isla-server-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_resources(dir: &Path, contents: &str) {
+        for file in &resource_files("riscv64") {
+            fs::write(dir.join(file), contents).unwrap()
+        }
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let resources = temp_dir("reload-resources");
+        let snapshots = temp_dir("reload-snapshots");
+        write_resources(&resources, "a");
+        let versions = ArchVersions::new(resources.clone(), snapshots);
+
+        assert!(versions.current("riscv64").is_none());
+        assert!(versions.start_reload("riscv64"));
+        let v0 = versions.reload("riscv64", |_| Ok(())).unwrap();
+        assert_eq!(v0.version, 0);
+        assert_eq!(fs::read_to_string(v0.resources.join("riscv64.irx")).unwrap(), "a");
+        assert!(!versions.start_reload("riscv64"));
+
+        // Running requests keep the snapshot they started with
+        write_resources(&resources, "bb");
+        assert!(versions.start_reload("riscv64"));
+        assert!(!versions.start_reload("riscv64"), "only one reload runs at a time");
+        let v1 = versions.reload("riscv64", |_| Ok(())).unwrap();
+        assert_eq!(v1.version, 1);
+        assert_eq!(versions.current("riscv64").unwrap().version, 1);
+        assert_eq!(fs::read_to_string(v1.resources.join("riscv64.irx")).unwrap(), "bb");
+        assert_eq!(fs::read_to_string(v0.resources.join("riscv64.irx")).unwrap(), "a");
+
+        // Once nothing is using the old snapshot it is removed
+        let old = v0.resources.clone();
+        drop(v0);
+        assert!(!old.exists());
+        assert!(!versions.start_reload("riscv64"));
+    }
+
+    #[test]
+    fn failed_reload_keeps_current() {
+        let resources = temp_dir("failed-resources");
+        let snapshots = temp_dir("failed-snapshots");
+        write_resources(&resources, "a");
+        let versions = ArchVersions::new(resources.clone(), snapshots);
+        versions.reload("riscv64", |_| Ok(())).unwrap();
+
+        write_resources(&resources, "bad");
+        assert!(versions.start_reload("riscv64"));
+        let err = versions.reload("riscv64", |_| Err("parse error".to_string()));
+        assert!(matches!(err, Err(ReloadError::CheckFailed(_))));
+        let current = versions.current("riscv64").unwrap();
+        assert_eq!(current.version, 0);
+        assert_eq!(fs::read_to_string(current.resources.join("riscv64.irx")).unwrap(), "a");
+        assert!(!versions.start_reload("riscv64"), "files which failed to load are not retried");
+
+        // A missing file is an error rather than a partial snapshot
+        fs::remove_file(resources.join("riscv64.symtab")).unwrap();
+        assert!(versions.start_reload("riscv64"));
+        assert!(matches!(versions.reload("riscv64", |_| Ok(())), Err(ReloadError::IOError(_))));
+        assert_eq!(versions.current("riscv64").unwrap().version, 0);
+
+        write_resources(&resources, "good");
+        assert!(versions.start_reload("riscv64"));
+        assert_eq!(versions.reload("riscv64", |_| Ok(())).unwrap().version, 1);
+    }
+}
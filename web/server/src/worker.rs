@@ -37,7 +37,7 @@ use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Instant;
 
@@ -56,9 +56,9 @@ use isla_lib::init::{initialize_architecture, Initialized};
 use isla_lib::ir::serialize as ir_serialize;
 use isla_lib::ir::*;
 
-use getopts::Options;
+use getopts::{Matches, Options};
 mod request;
-use request::{JsGraph, JsRelation, Request, Response};
+use request::{JsGraph, JsRelation, Request, Response, ARCH_WHITELIST};
 
 static THREADS: usize = 2;
 static LIMIT_MEM_BYTES: u64 = 2048 * 1024 * 1024;
@@ -112,7 +112,37 @@ fn main() {
         std::process::exit(1)
     }
 
-    let response = match handle_request() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut opts = Options::new();
+    opts.reqopt("", "resources", "path to resource files", "<path>");
+    opts.optopt("", "cache", "path to a cache directory", "<path>");
+    opts.optopt("", "litmus-convert", "path of .litmus to .toml file converter", "<path>");
+    opts.optopt("", "check", "only check that an architecture in the resource files loads", "<arch>");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+
+    // The server uses this to check a new version of the resource
+    // files before using it to answer requests
+    if let Some(arch) = matches.opt_str("check") {
+        let resources = PathBuf::from(matches.opt_str("resources").unwrap());
+        let code = match check_architecture(&resources, &arch) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        };
+        unsafe { isla_lib::smt::finalize_solver() };
+        std::process::exit(code)
+    }
+
+    let response = match handle_request(&matches) {
         Ok(resp) => match serde_json::to_vec(&resp) {
             Ok(resp) => resp,
             Err(e) => {
@@ -139,22 +169,54 @@ fn deserialize_from_stdin<T: DeserializeOwned>() -> Option<T> {
     bincode::deserialize_from(handle).ok()
 }
 
-static ARCH_WHITELIST: [&str; 4] = ["aarch64", "aarch64-vmsa", "riscv32", "riscv64"];
+/// The pre-processed architecture and its configurations, as stored
+/// in the resources directory
+struct ArchFiles {
+    strings: Vec<String>,
+    files: Vec<String>,
+    ir: Vec<Def<Name, B64>>,
+    config: String,
+    footprint_config: String,
+}
+
+fn read_arch_files(resources: &Path, arch: &str) -> Result<ArchFiles, Box<dyn Error>> {
+    let config_file = resources.join(format!("{}.toml", arch));
+    let footprint_config_file = resources.join(format!("{}-footprint.toml", arch));
+    let symtab_file = resources.join(format!("{}.symtab", arch));
+    let ir_file = resources.join(format!("{}.irx", arch));
+
+    let (strings, files): (Vec<String>, Vec<String>) = bincode::deserialize(&fs::read(&symtab_file)?)?;
+    let ir = ir_serialize::deserialize(&fs::read(&ir_file)?).ok_or("Failed to deserialize IR")?;
+
+    Ok(ArchFiles {
+        strings,
+        files,
+        ir,
+        config: fs::read_to_string(&config_file)?,
+        footprint_config: fs::read_to_string(&footprint_config_file)?,
+    })
+}
+
+/// Parse and initialize an architecture, as handle_request would
+fn check_architecture(resources: &Path, arch: &str) -> Result<(), Box<dyn Error>> {
+    let ArchFiles { strings, files, ir, config, footprint_config } = read_arch_files(resources, arch)?;
+    let symtab = Symtab::from_raw_table(&strings, &files);
+    let isa_config: ISAConfig<B64> = ISAConfig::parse(&config, &symtab)?;
+    let footprint_config: ISAConfig<B64> = ISAConfig::parse(&footprint_config, &symtab)?;
+
+    let mut footprint_ir = ir.clone();
+    initialize_architecture(&mut footprint_ir, symtab.clone(), &footprint_config, AssertionMode::Optimistic);
+    let mut ir = ir;
+    initialize_architecture(&mut ir, symtab, &isa_config, AssertionMode::Optimistic);
+    Ok(())
+}
 
 /// The error handling scheme is as follows. If we have an expected
 /// error condition (i.e. a flaw in the user input), then that is
 /// returned normally as part of the response using
 /// Response::Error. This function either panics or returns Err on
 /// unexpected errors.
-fn handle_request() -> Result<Response, Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let mut opts = Options::new();
-    opts.reqopt("", "resources", "path to resource files", "<path>");
-    opts.reqopt("", "cache", "path to a cache directory", "<path>");
-    opts.optopt("", "litmus-convert", "path of .litmus to .toml file converter", "<path>");
-
-    let matches = opts.parse(&args[1..])?;
-
+fn handle_request(matches: &Matches) -> Result<Response, Box<dyn Error>> {
     // Log absolutely everything
     isla_lib::log::set_flags(0xffff_ffff);
 
@@ -172,7 +234,7 @@ fn handle_request() -> Result<Response, Box<dyn Error>> {
         panic!("Invalid resources directory");
     }
 
-    let mut cache = PathBuf::from(matches.opt_str("cache").unwrap());
+    let mut cache = PathBuf::from(matches.opt_str("cache").ok_or("No cache directory given")?);
     cache.push(&req.arch);
     fs::create_dir_all(&cache).expect("Failed to create cache directory if missing");
     if !cache.is_dir() {
@@ -181,19 +243,11 @@ fn handle_request() -> Result<Response, Box<dyn Error>> {
 
     let now = Instant::now();
 
-    let config_file = resources.join(format!("{}.toml", req.arch));
-    let footprint_config_file = resources.join(format!("{}-footprint.toml", req.arch));
-    let symtab_file = resources.join(format!("{}.symtab", req.arch));
-    let ir_file = resources.join(format!("{}.irx", req.arch));
-
-    let (strings, files): (Vec<String>, Vec<String>) = bincode::deserialize(&fs::read(&symtab_file)?)?;
+    let ArchFiles { strings, files, mut ir, config, footprint_config } = read_arch_files(&resources, &req.arch)?;
     let symtab = Symtab::from_raw_table(&strings, &files);
 
-    let mut ir: Vec<Def<Name, B64>> =
-        ir_serialize::deserialize(&fs::read(&ir_file)?).expect("Failed to deserialize IR");
-
-    let isa_config: ISAConfig<B64> = ISAConfig::parse(&fs::read_to_string(&config_file)?, &symtab)?;
-    let footprint_config: ISAConfig<B64> = ISAConfig::parse(&fs::read_to_string(&footprint_config_file)?, &symtab)?;
+    let isa_config: ISAConfig<B64> = ISAConfig::parse(&config, &symtab)?;
+    let footprint_config: ISAConfig<B64> = ISAConfig::parse(&footprint_config, &symtab)?;
 
     eprintln!("Loaded architecture in: {}ms", now.elapsed().as_millis());
 
@@ -273,6 +327,10 @@ fn handle_request() -> Result<Response, Box<dyn Error>> {
         armv8_page_tables: req.armv8_page_tables,
         merge_translations: if req.merge_translations { Some(req.merge_split_stages) } else { None },
         remove_uninteresting_translates: if req.remove_uninteresting { Some(true) } else { None },
+        // The cache directory is already specific to the architecture
+        arch_hash: None,
+        relation_report: false,
+        symbolic_sleep: false,
     };
 
     let graph_opts = GraphOpts {